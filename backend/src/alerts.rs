@@ -0,0 +1,122 @@
+use chrono::{Duration, Utc};
+use std::collections::HashSet;
+use crate::db::DbClient;
+use crate::models::{AlertCondition, AlertScope, AlertTrigger, CryptoToken, MoveWindow, StoredAlert};
+use crate::webhook::{self, WebhookNotifier};
+
+// The move that makes `alert` fire for `token`, if it does. `favorites` is the favorite
+// set as of this evaluation, so a token favorited after the alert was made is covered.
+pub fn triggered(alert: &StoredAlert, token: &CryptoToken, favorites: &HashSet<String>) -> Option<f64> {
+    let in_scope = match &alert.scope {
+        AlertScope::Token(token_id) => *token_id == token.token_id,
+        AlertScope::Favorites => favorites.contains(&token.token_id),
+    };
+    if !in_scope {
+        return None;
+    }
+    match alert.condition {
+        AlertCondition::PercentMove { percent, window: MoveWindow::Day } => {
+            let change = token.price_change_percentage_24h;
+            (change.is_finite() && change.abs() >= percent).then_some(change)
+        }
+    }
+}
+
+// Evaluates every alert against freshly refreshed tokens, storing and announcing the
+// triggers. An alert that fired for a token within `cooldown` stays quiet about it, so a
+// sustained move is reported once.
+pub async fn check(db: &DbClient, tokens: &[CryptoToken], cooldown: Duration, notifier: &WebhookNotifier) -> Vec<AlertTrigger> {
+    let alerts = match db.load_alerts().await {
+        Ok(alerts) => alerts,
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to load alerts");
+            return Vec::new();
+        }
+    };
+    if alerts.is_empty() {
+        return Vec::new();
+    }
+    let favorites = if alerts.iter().any(|a| a.scope == AlertScope::Favorites) {
+        db.favorite_token_ids().await.unwrap_or_else(|e| {
+            tracing::warn!(error = %e, "Failed to load favorites for alerts");
+            HashSet::new()
+        })
+    } else {
+        HashSet::new()
+    };
+
+    let mut fired = Vec::new();
+    for alert in &alerts {
+        let Some(alert_id) = alert.id.map(|id| id.to_hex()) else { continue };
+        for token in tokens {
+            let Some(change_percentage) = triggered(alert, token, &favorites) else { continue };
+            let trigger = AlertTrigger {
+                alert_id: alert_id.clone(),
+                token_id: token.token_id.clone(),
+                change_percentage,
+                at: Utc::now(),
+            };
+            match db.record_alert_trigger(&trigger, cooldown).await {
+                Ok(true) => {
+                    notifier.notify(webhook::alert_event(&trigger, &alert.condition));
+                    fired.push(trigger);
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    tracing::warn!(alert_id = %alert_id, token_id = %token.token_id, error = %e, "Failed to record alert trigger");
+                }
+            }
+        }
+    }
+    fired
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::oid::ObjectId;
+
+    fn token(token_id: &str, change: f64) -> CryptoToken {
+        serde_json::from_value(serde_json::json!({
+            "token_id": token_id,
+            "symbol": token_id,
+            "name": token_id,
+            "current_price": 1.0,
+            "market_cap": 1.0,
+            "volume_24h": 1.0,
+            "price_change_24h": 0.0,
+            "price_change_percentage_24h": change,
+            "last_updated": Utc::now(),
+            "is_favorite": false,
+        }))
+        .unwrap()
+    }
+
+    fn alert(scope: AlertScope, percent: f64) -> StoredAlert {
+        StoredAlert {
+            id: Some(ObjectId::new()),
+            condition: AlertCondition::PercentMove { percent, window: MoveWindow::Day },
+            scope,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn moves_past_the_threshold_either_way_trigger() {
+        let alert = alert(AlertScope::Token("bitcoin".to_string()), 10.0);
+        let none = HashSet::new();
+        assert_eq!(triggered(&alert, &token("bitcoin", 12.0), &none), Some(12.0));
+        assert_eq!(triggered(&alert, &token("bitcoin", -10.0), &none), Some(-10.0));
+        assert_eq!(triggered(&alert, &token("bitcoin", 9.9), &none), None);
+        assert_eq!(triggered(&alert, &token("ethereum", 50.0), &none), None);
+    }
+
+    #[test]
+    fn favorites_scope_covers_whatever_is_favorited_now() {
+        let alert = alert(AlertScope::Favorites, 5.0);
+        let moving = token("solana", 8.0);
+        assert_eq!(triggered(&alert, &moving, &HashSet::new()), None);
+        let favorites = HashSet::from(["solana".to_string()]);
+        assert_eq!(triggered(&alert, &moving, &favorites), Some(8.0));
+    }
+}
@@ -0,0 +1,703 @@
+use crate::models::CoinGeckoHistoricalData;
+use crate::ordering::cmp_f64;
+
+pub const MIN_POINTS: usize = 2;
+pub const MAX_POINTS: usize = 2000;
+
+// Largest-Triangle-Three-Buckets over [timestamp, value] pairs. Returns at most
+// `max_points` points, always keeping the first and last so the chart spans the
+// same range; series already short enough come back unchanged.
+pub fn downsample(series: &[Vec<f64>], max_points: usize) -> Vec<Vec<f64>> {
+    let len = series.len();
+    if max_points >= len || max_points < MIN_POINTS {
+        return series.to_vec();
+    }
+
+    let x = |i: usize| series[i].first().copied().unwrap_or(0.0);
+    let y = |i: usize| series[i].get(1).copied().unwrap_or(0.0);
+
+    let mut sampled = Vec::with_capacity(max_points);
+    sampled.push(series[0].clone());
+
+    // Everything between the endpoints is split into max_points - 2 buckets
+    let bucket_size = (len - 2) as f64 / (max_points - 2) as f64;
+    let mut selected = 0;
+
+    for bucket in 0..max_points - 2 {
+        let start = (bucket as f64 * bucket_size) as usize + 1;
+        let end = (((bucket + 1) as f64 * bucket_size) as usize + 1).min(len - 1);
+
+        // Average of the next bucket (or the last point) is the third triangle vertex
+        let next_start = end;
+        let next_end = (((bucket + 2) as f64 * bucket_size) as usize + 1).min(len);
+        let next_count = (next_end - next_start).max(1) as f64;
+        let (avg_x, avg_y) = (next_start..next_end.max(next_start + 1))
+            .fold((0.0, 0.0), |(sx, sy), i| (sx + x(i), sy + y(i)));
+        let (avg_x, avg_y) = (avg_x / next_count, avg_y / next_count);
+
+        let (ax, ay) = (x(selected), y(selected));
+        let best = (start..end.max(start + 1))
+            .max_by(|&i, &j| {
+                let area = |k: usize| ((ax - avg_x) * (y(k) - ay) - (ax - x(k)) * (avg_y - ay)).abs();
+                cmp_f64(area(i), area(j))
+            })
+            .unwrap_or(start);
+
+        sampled.push(series[best].clone());
+        selected = best;
+    }
+
+    sampled.push(series[len - 1].clone());
+    sampled
+}
+
+// Downsamples each series independently so every one stays within `max_points`
+pub fn downsample_history(data: CoinGeckoHistoricalData, max_points: usize) -> CoinGeckoHistoricalData {
+    CoinGeckoHistoricalData {
+        prices: downsample(&data.prices, max_points),
+        market_caps: downsample(&data.market_caps, max_points),
+        total_volumes: downsample(&data.total_volumes, max_points),
+    }
+}
+
+// Rolls hourly (or finer) history up to one point per UTC day, the last one each day
+// has, so the result has the shape of CoinGecko's own daily series
+pub fn daily_history(data: CoinGeckoHistoricalData) -> CoinGeckoHistoricalData {
+    CoinGeckoHistoricalData {
+        prices: last_per(&data.prices, MS_PER_DAY),
+        market_caps: last_per(&data.market_caps, MS_PER_DAY),
+        total_volumes: last_per(&data.total_volumes, MS_PER_DAY),
+    }
+}
+
+// Same as daily_history, one point per UTC hour; thins the 5-minutely points of
+// single-day charts merged into a longer hourly series
+pub fn hourly_history(data: CoinGeckoHistoricalData) -> CoinGeckoHistoricalData {
+    CoinGeckoHistoricalData {
+        prices: last_per(&data.prices, MS_PER_HOUR),
+        market_caps: last_per(&data.market_caps, MS_PER_HOUR),
+        total_volumes: last_per(&data.total_volumes, MS_PER_HOUR),
+    }
+}
+
+// A merged daily series back in the shape of one CoinGecko daily chart: the first point
+// of each UTC day and the newest one, dropping the current prices earlier fetches ended on
+pub fn compact_daily_history(data: CoinGeckoHistoricalData) -> CoinGeckoHistoricalData {
+    CoinGeckoHistoricalData {
+        prices: first_per_day_and_last(data.prices),
+        market_caps: first_per_day_and_last(data.market_caps),
+        total_volumes: first_per_day_and_last(data.total_volumes),
+    }
+}
+
+fn first_per_day_and_last(series: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+    let newest = series.last().cloned();
+    let mut compact: Vec<Vec<f64>> = Vec::new();
+    for point in series {
+        let Some(t) = point.first() else {
+            continue;
+        };
+        let day = (*t as i64).div_euclid(MS_PER_DAY);
+        if compact.last().is_none_or(|last| (last[0] as i64).div_euclid(MS_PER_DAY) != day) {
+            compact.push(point);
+        }
+    }
+    if let Some(newest) = newest {
+        if compact.last() != Some(&newest) {
+            compact.push(newest);
+        }
+    }
+    compact
+}
+
+fn last_per(series: &[Vec<f64>], bucket_ms: i64) -> Vec<Vec<f64>> {
+    let mut thinned: Vec<Vec<f64>> = Vec::new();
+    for point in series {
+        let Some(t) = point.first() else {
+            continue;
+        };
+        let bucket = (*t as i64).div_euclid(bucket_ms);
+        match thinned.last_mut() {
+            Some(last) if (last[0] as i64).div_euclid(bucket_ms) == bucket => *last = point.clone(),
+            _ => thinned.push(point.clone()),
+        }
+    }
+    thinned
+}
+
+// Union of two [timestamp, value] series, sorted by timestamp with one point per
+// timestamp. Either side may be unsorted or overlap the other; on a shared timestamp
+// the `fetched` point wins. Points without a value or a finite timestamp are dropped.
+pub fn merge_series(existing: &[Vec<f64>], fetched: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    let mut merged: std::collections::BTreeMap<i64, Vec<f64>> = std::collections::BTreeMap::new();
+    for point in existing.iter().chain(fetched) {
+        if point.len() >= 2 && point[0].is_finite() {
+            merged.insert(point[0] as i64, point.clone());
+        }
+    }
+    merged.into_values().collect()
+}
+
+// merge_series over each of the three series
+pub fn merge_history(existing: &CoinGeckoHistoricalData, fetched: &CoinGeckoHistoricalData) -> CoinGeckoHistoricalData {
+    CoinGeckoHistoricalData {
+        prices: merge_series(&existing.prices, &fetched.prices),
+        market_caps: merge_series(&existing.market_caps, &fetched.market_caps),
+        total_volumes: merge_series(&existing.total_volumes, &fetched.total_volumes),
+    }
+}
+
+// The last `days` of sorted history, counted back from its newest price, the way
+// CoinGecko cuts a range; None keeps everything (`max`)
+pub fn slice_history(data: CoinGeckoHistoricalData, days: Option<u32>) -> CoinGeckoHistoricalData {
+    let (Some(days), Some(newest)) = (days, data.prices.last().and_then(|point| point.first())) else {
+        return data;
+    };
+    let since = *newest as i64 - i64::from(days) * MS_PER_DAY;
+    let window = |series: Vec<Vec<f64>>| series.into_iter().filter(|point| point.first().is_some_and(|t| *t as i64 >= since)).collect();
+    CoinGeckoHistoricalData {
+        prices: window(data.prices),
+        market_caps: window(data.market_caps),
+        total_volumes: window(data.total_volumes),
+    }
+}
+
+// `amount` of a token priced `from_price` expressed in a token priced `to_price`
+// (same quote currency), as (converted amount, rate). None when the target price
+// is zero, negative or not finite, or the result would overflow.
+pub fn convert(amount: f64, from_price: f64, to_price: f64) -> Option<(f64, f64)> {
+    if !(to_price.is_finite() && to_price > 0.0 && from_price.is_finite() && from_price >= 0.0) {
+        return None;
+    }
+    let converted = amount * from_price / to_price;
+    let rate = from_price / to_price;
+    (converted.is_finite() && rate.is_finite()).then_some((converted, rate))
+}
+
+const MS_PER_DAY: i64 = 86_400_000;
+const MS_PER_HOUR: i64 = 3_600_000;
+
+// The last usable price of each UTC day, as (day number, price) in day order. Input may
+// be unsorted and have gaps; zero, negative and non-finite prices are dropped.
+pub fn daily_closes(prices: &[(i64, f64)]) -> Vec<(i64, f64)> {
+    let mut points: Vec<(i64, f64)> = prices.iter().copied().filter(|(_, p)| p.is_finite() && *p > 0.0).collect();
+    points.sort_by_key(|(t, _)| *t);
+
+    let mut closes: Vec<(i64, f64)> = Vec::new();
+    for (t, price) in points {
+        let day = t.div_euclid(MS_PER_DAY);
+        match closes.last_mut() {
+            Some((last_day, last_price)) if *last_day == day => *last_price = price,
+            _ => closes.push((day, price)),
+        }
+    }
+    closes
+}
+
+// Sums daily series (as daily_closes returns them) per day, counting the series that
+// have that day: (day number, total, series included), in day order
+pub fn daily_totals(series: &[Vec<(i64, f64)>]) -> Vec<(i64, f64, usize)> {
+    let mut totals: std::collections::BTreeMap<i64, (f64, usize)> = std::collections::BTreeMap::new();
+    for (day, value) in series.iter().flatten() {
+        let entry = totals.entry(*day).or_default();
+        entry.0 += value;
+        entry.1 += 1;
+    }
+    totals.into_iter().map(|(day, (total, count))| (day, total, count)).collect()
+}
+
+// Start of a day number daily_closes returned, in Unix milliseconds
+pub fn day_start_ms(day: i64) -> i64 {
+    day * MS_PER_DAY
+}
+
+// Restricts daily series to the days all of them have, returning those days and each
+// series' prices on them. Series of any length line up; an empty input aligns to nothing.
+pub fn align(series: &[Vec<(i64, f64)>]) -> (Vec<i64>, Vec<Vec<f64>>) {
+    let Some(first) = series.first() else {
+        return (Vec::new(), Vec::new());
+    };
+    let days: Vec<i64> = first
+        .iter()
+        .map(|(day, _)| *day)
+        .filter(|day| series[1..].iter().all(|s| s.binary_search_by_key(day, |(d, _)| *d).is_ok()))
+        .collect();
+
+    let aligned = series
+        .iter()
+        .map(|s| {
+            days.iter()
+                .filter_map(|day| s.binary_search_by_key(day, |(d, _)| *d).ok().map(|i| s[i].1))
+                .collect()
+        })
+        .collect();
+    (days, aligned)
+}
+
+// Simple returns between consecutive prices
+pub fn returns(prices: &[f64]) -> Vec<f64> {
+    prices.windows(2).map(|w| w[1] / w[0] - 1.0).collect()
+}
+
+// The `p`th percentile (0-100) of an ascending slice, interpolating linearly between
+// the two nearest values; the median is the 50th. None for an empty slice.
+pub fn percentile(sorted: &[f64], p: f64) -> Option<f64> {
+    let last = sorted.len().checked_sub(1)?;
+    let rank = p.clamp(0.0, 100.0) / 100.0 * last as f64;
+    let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+    let weight = rank - lower as f64;
+    Some(sorted[lower] + (sorted[upper] - sorted[lower]) * weight)
+}
+
+// Pearson correlation of two equally long samples. None with fewer than two points,
+// mismatched lengths, or a constant sample (a pegged stablecoin, say).
+pub fn pearson(a: &[f64], b: &[f64]) -> Option<f64> {
+    if a.len() != b.len() || a.len() < 2 {
+        return None;
+    }
+    let n = a.len() as f64;
+    let mean_a = a.iter().sum::<f64>() / n;
+    let mean_b = b.iter().sum::<f64>() / n;
+
+    let (mut covariance, mut variance_a, mut variance_b) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b) {
+        let (dx, dy) = (x - mean_a, y - mean_b);
+        covariance += dx * dy;
+        variance_a += dx * dx;
+        variance_b += dy * dy;
+    }
+    if variance_a <= f64::EPSILON || variance_b <= f64::EPSILON {
+        return None;
+    }
+    let r = covariance / (variance_a.sqrt() * variance_b.sqrt());
+    r.is_finite().then_some(r.clamp(-1.0, 1.0))
+}
+
+// The (timestamp, price) points a change over `days` runs between: the latest one, and
+// the earlier one closest to `days` before it. A series that doesn't reach back that
+// far starts at its earliest point. Input may be unsorted; zero, negative and
+// non-finite prices are dropped. None with fewer than two usable points.
+pub fn change_window(prices: &[(i64, f64)], days: u32) -> Option<((i64, f64), (i64, f64))> {
+    let mut points: Vec<(i64, f64)> = prices.iter().copied().filter(|(_, p)| p.is_finite() && *p > 0.0).collect();
+    points.sort_by_key(|(t, _)| *t);
+    let (&end, earlier) = points.split_last()?;
+    let target = end.0 - days as i64 * MS_PER_DAY;
+    let start = earlier.iter().min_by_key(|(t, _)| (t - target).abs())?;
+    Some((*start, end))
+}
+
+// The point of a time-ordered series closest to `at`, by binary search; the earlier
+// one on a tie. None for an empty series.
+pub fn nearest_point(points: &[(i64, f64)], at: i64) -> Option<(i64, f64)> {
+    let after = points.partition_point(|(t, _)| *t < at);
+    let before = after.checked_sub(1).map(|i| points[i]);
+    match (before, points.get(after).copied()) {
+        (Some(b), Some(a)) => Some(if at - b.0 <= a.0 - at { b } else { a }),
+        (b, a) => b.or(a),
+    }
+}
+
+// Bitcoin's share of the summed market caps at each of its points, in percent. `others`
+// holds every other top token as (current market cap, time-ordered market caps); a
+// token counts at a timestamp when it has a point within `tolerance_ms` of it. Points
+// where the tokens counted (bitcoin included) make up less than `min_coverage` of the
+// current total are left out, as their sum would understate the market.
+pub fn dominance_series(
+    btc: &[(i64, f64)],
+    btc_weight: f64,
+    others: &[(f64, Vec<(i64, f64)>)],
+    tolerance_ms: i64,
+    min_coverage: f64,
+) -> Vec<(i64, f64)> {
+    let total_weight = btc_weight + others.iter().map(|(weight, _)| weight).sum::<f64>();
+    if total_weight <= 0.0 {
+        return Vec::new();
+    }
+
+    btc.iter()
+        .filter(|(_, cap)| cap.is_finite() && *cap > 0.0)
+        .filter_map(|&(t, btc_cap)| {
+            let mut covered = btc_weight;
+            let mut total = btc_cap;
+            for (weight, caps) in others {
+                if let Some((at, cap)) = nearest_point(caps, t) {
+                    if (at - t).abs() <= tolerance_ms && cap.is_finite() && cap > 0.0 {
+                        covered += weight;
+                        total += cap;
+                    }
+                }
+            }
+            (covered / total_weight >= min_coverage).then(|| (t, btc_cap / total * 100.0))
+        })
+        .collect()
+}
+
+// Span between two timestamps in fractional days
+pub fn span_days(from: i64, to: i64) -> f64 {
+    (to - from) as f64 / MS_PER_DAY as f64
+}
+
+// Symmetric matrix of pairwise correlations between return series
+pub fn correlation_matrix(series: &[Vec<f64>]) -> Vec<Vec<Option<f64>>> {
+    let mut matrix = vec![vec![None; series.len()]; series.len()];
+    for i in 0..series.len() {
+        for j in i..series.len() {
+            let r = pearson(&series[i], &series[j]);
+            matrix[i][j] = r;
+            matrix[j][i] = r;
+        }
+    }
+    matrix
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile_odd_count() {
+        let sorted = [1.0, 2.0, 3.0, 4.0, 5.0];
+        assert_eq!(percentile(&sorted, 50.0), Some(3.0));
+        assert_eq!(percentile(&sorted, 0.0), Some(1.0));
+        assert_eq!(percentile(&sorted, 100.0), Some(5.0));
+        assert!((percentile(&sorted, 10.0).unwrap() - 1.4).abs() < 1e-12);
+        assert!((percentile(&sorted, 90.0).unwrap() - 4.6).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_percentile_even_count_interpolates() {
+        let sorted = [-4.0, -1.0, 2.0, 10.0];
+        assert_eq!(percentile(&sorted, 50.0), Some(0.5));
+        assert!((percentile(&sorted, 10.0).unwrap() - -3.1).abs() < 1e-12);
+        assert!((percentile(&sorted, 90.0).unwrap() - 7.6).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_percentile_all_equal_and_edge_cases() {
+        let sorted = [2.5; 7];
+        for p in [0.0, 10.0, 50.0, 90.0, 100.0] {
+            assert_eq!(percentile(&sorted, p), Some(2.5));
+        }
+        assert_eq!(percentile(&[7.0], 90.0), Some(7.0));
+        assert_eq!(percentile(&[], 50.0), None);
+    }
+
+    #[test]
+    fn test_daily_totals_count_only_series_with_the_day() {
+        let day = MS_PER_DAY;
+        // Hourly points roll up to the last one of each day
+        let bitcoin = daily_closes(&[(0, 90.0), (3_600_000, 100.0), (day, 110.0), (2 * day, 120.0)]);
+        // Listed a day later
+        let solana = daily_closes(&[(day + 5, 10.0), (2 * day + 5, 20.0)]);
+
+        assert_eq!(
+            daily_totals(&[bitcoin, solana]),
+            vec![(0, 100.0, 1), (1, 120.0, 2), (2, 140.0, 2)]
+        );
+        assert!(daily_totals(&[]).is_empty());
+        assert_eq!(day_start_ms(2), 2 * day);
+    }
+
+    #[test]
+    fn test_dominance_sums_nearest_caps_of_covered_tokens() {
+        let day = MS_PER_DAY;
+        let btc = vec![(0, 60.0), (day, 50.0), (2 * day, 40.0)];
+        let others = vec![
+            // Daily points an hour off bitcoin's
+            (30.0, vec![(3_600_000, 40.0), (day + 3_600_000, 50.0), (2 * day + 3_600_000, 60.0)]),
+            // Only the first day cached
+            (10.0, vec![(0, 20.0)]),
+        ];
+
+        let series = dominance_series(&btc, 60.0, &others, day / 2, 0.8);
+        // Day 0 counts all three; later days leave out the third token, still 90% covered
+        assert_eq!(series, vec![(0, 50.0), (day, 50.0), (2 * day, 40.0)]);
+
+        // Requiring everything keeps only the first day
+        assert_eq!(dominance_series(&btc, 60.0, &others, day / 2, 1.0), vec![(0, 50.0)]);
+    }
+
+    #[test]
+    fn test_dominance_without_coverage_is_empty() {
+        let btc = vec![(0, 60.0)];
+        let others = vec![(60.0, Vec::new())];
+        assert!(dominance_series(&btc, 40.0, &others, MS_PER_DAY, 0.8).is_empty());
+        assert!(dominance_series(&[], 40.0, &others, MS_PER_DAY, 0.8).is_empty());
+    }
+
+    fn series(len: usize) -> Vec<Vec<f64>> {
+        (0..len)
+            .map(|i| vec![1_600_000_000_000.0 + i as f64 * 3_600_000.0, (i as f64 / 10.0).sin() * 100.0 + 1000.0])
+            .collect()
+    }
+
+    #[test]
+    fn test_downsample_returns_exact_count_and_keeps_endpoints() {
+        let input = series(1000);
+        let output = downsample(&input, 100);
+
+        assert_eq!(output.len(), 100);
+        assert_eq!(output.first(), input.first());
+        assert_eq!(output.last(), input.last());
+    }
+
+    #[test]
+    fn test_downsample_preserves_time_order() {
+        let output = downsample(&series(1000), 100);
+        assert!(output.windows(2).all(|w| w[0][0] < w[1][0]));
+    }
+
+    #[test]
+    fn test_downsample_keeps_peaks() {
+        let mut input = series(1000);
+        input[500][1] = 1_000_000.0;
+
+        let output = downsample(&input, 50);
+        assert!(output.iter().any(|p| p[1] == 1_000_000.0));
+    }
+
+    #[test]
+    fn test_short_series_unchanged() {
+        let input = series(50);
+        assert_eq!(downsample(&input, 100), input);
+        assert_eq!(downsample(&input, 50), input);
+        assert!(downsample(&[], 10).is_empty());
+    }
+
+    #[test]
+    fn test_two_points_is_just_the_endpoints() {
+        let input = series(1000);
+        let output = downsample(&input, 2);
+        assert_eq!(output, vec![input[0].clone(), input[999].clone()]);
+    }
+
+    #[test]
+    fn test_downsample_history_applies_to_every_series() {
+        let data = CoinGeckoHistoricalData {
+            prices: series(1000),
+            market_caps: series(1000),
+            total_volumes: series(30),
+        };
+
+        let output = downsample_history(data, 100);
+        assert_eq!(output.prices.len(), 100);
+        assert_eq!(output.market_caps.len(), 100);
+        assert_eq!(output.total_volumes.len(), 30);
+    }
+
+    #[test]
+    fn test_daily_history_keeps_the_last_point_of_each_utc_day() {
+        // Hourly from 12:26 UTC: 12 points on the first day, 24 on each full day, 12 on the last
+        let hourly = series(72);
+        let data = CoinGeckoHistoricalData {
+            prices: hourly.clone(),
+            market_caps: hourly.clone(),
+            total_volumes: hourly[..30].to_vec(),
+        };
+
+        let daily = daily_history(data);
+        assert_eq!(daily.prices, vec![hourly[11].clone(), hourly[35].clone(), hourly[59].clone(), hourly[71].clone()]);
+        assert_eq!(daily.market_caps, daily.prices);
+        assert_eq!(daily.total_volumes, vec![hourly[11].clone(), hourly[29].clone()]);
+        assert!(daily_history(CoinGeckoHistoricalData { prices: vec![], market_caps: vec![], total_volumes: vec![] }).prices.is_empty());
+    }
+
+    fn points(pairs: &[(i64, f64)]) -> Vec<Vec<f64>> {
+        pairs.iter().map(|&(t, v)| vec![t as f64, v]).collect()
+    }
+
+    #[test]
+    fn test_merge_series_unions_overlapping_ranges() {
+        // A long daily series and a short recent one overlapping its end
+        let existing = points(&[(0, 1.0), (10, 2.0), (20, 3.0)]);
+        let fetched = points(&[(15, 2.5), (25, 3.5)]);
+        assert_eq!(
+            merge_series(&existing, &fetched),
+            points(&[(0, 1.0), (10, 2.0), (15, 2.5), (20, 3.0), (25, 3.5)])
+        );
+    }
+
+    #[test]
+    fn test_merge_series_keeps_the_fetched_point_on_duplicate_timestamps() {
+        let existing = points(&[(0, 1.0), (10, 2.0)]);
+        let fetched = points(&[(10, 9.0), (10, 9.5), (20, 3.0)]);
+        assert_eq!(merge_series(&existing, &fetched), points(&[(0, 1.0), (10, 9.5), (20, 3.0)]));
+    }
+
+    #[test]
+    fn test_merge_series_sorts_out_of_order_input() {
+        let existing = points(&[(30, 3.0), (10, 1.0)]);
+        let fetched = points(&[(20, 2.0), (0, 0.0)]);
+        let mut malformed = fetched.clone();
+        malformed.push(vec![40.0]);
+        malformed.push(vec![f64::NAN, 5.0]);
+        assert_eq!(merge_series(&existing, &malformed), points(&[(0, 0.0), (10, 1.0), (20, 2.0), (30, 3.0)]));
+        assert!(merge_series(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn test_slice_history_counts_back_from_the_newest_price() {
+        let day = MS_PER_DAY;
+        let series = points(&[(0, 0.0), (day, 1.0), (2 * day, 2.0), (3 * day, 3.0)]);
+        let data = CoinGeckoHistoricalData { prices: series.clone(), market_caps: series.clone(), total_volumes: series.clone() };
+
+        let sliced = slice_history(data, Some(2));
+        assert_eq!(sliced.prices, points(&[(day, 1.0), (2 * day, 2.0), (3 * day, 3.0)]));
+        assert_eq!(sliced.total_volumes, sliced.prices);
+
+        let data = CoinGeckoHistoricalData { prices: series.clone(), market_caps: vec![], total_volumes: vec![] };
+        assert_eq!(slice_history(data, None).prices, series);
+    }
+
+    #[test]
+    fn test_compact_daily_history_drops_earlier_current_prices() {
+        let day = MS_PER_DAY;
+        // Two daily fetches merged: each ended on that moment's price
+        let merged = points(&[(0, 1.0), (day, 2.0), (day + 15 * 3_600_000, 2.5), (2 * day, 3.0), (2 * day + 3_600_000, 3.1)]);
+        let data = CoinGeckoHistoricalData { prices: merged, market_caps: vec![], total_volumes: vec![] };
+        assert_eq!(
+            compact_daily_history(data).prices,
+            points(&[(0, 1.0), (day, 2.0), (2 * day, 3.0), (2 * day + 3_600_000, 3.1)])
+        );
+    }
+
+    #[test]
+    fn test_hourly_history_thins_finer_points() {
+        let five_minutely: Vec<Vec<f64>> = (0..24).map(|i| vec![(i * 300_000) as f64, i as f64]).collect();
+        let data = CoinGeckoHistoricalData { prices: five_minutely.clone(), market_caps: vec![], total_volumes: vec![] };
+        assert_eq!(hourly_history(data).prices, vec![five_minutely[11].clone(), five_minutely[23].clone()]);
+    }
+
+    #[test]
+    fn test_convert_math() {
+        assert_eq!(convert(1.5, 50000.0, 2500.0), Some((30.0, 20.0)));
+        assert_eq!(convert(2.0, 3.0, 1.0), Some((6.0, 3.0)));
+    }
+
+    #[test]
+    fn test_convert_very_small_prices() {
+        // Sub-cent memecoins on either side
+        let (converted, rate) = convert(1_000_000.0, 0.000_000_012, 60000.0).unwrap();
+        assert!((converted - 2.0e-7).abs() < 1e-18);
+        assert!((rate - 2.0e-13).abs() < 1e-24);
+
+        let (converted, rate) = convert(0.001, 60000.0, 0.000_000_012).unwrap();
+        assert!((converted / 5.0e9 - 1.0).abs() < 1e-9);
+        assert!((rate / 5.0e12 - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_convert_rejects_unusable_prices() {
+        assert_eq!(convert(1.0, 100.0, 0.0), None);
+        assert_eq!(convert(1.0, 100.0, -1.0), None);
+        assert_eq!(convert(1.0, 100.0, f64::NAN), None);
+        assert_eq!(convert(1.0, f64::INFINITY, 1.0), None);
+        assert_eq!(convert(f64::MAX, f64::MAX, 1e-300), None);
+    }
+
+    fn day(n: i64) -> i64 {
+        1_600_000_000_000 + n * MS_PER_DAY
+    }
+
+    #[test]
+    fn test_daily_closes_keep_last_price_of_each_day() {
+        let prices = [(day(1) + 5, 11.0), (day(0), 1.0), (day(0) + 1000, 2.0), (day(1), 10.0), (day(3), f64::NAN)];
+        let closes = daily_closes(&prices);
+        assert_eq!(closes.iter().map(|(_, p)| *p).collect::<Vec<_>>(), vec![2.0, 11.0]);
+        assert_eq!(closes[1].0 - closes[0].0, 1);
+        assert!(daily_closes(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_change_window_picks_the_point_closest_to_n_days_back() {
+        let hourly: Vec<(i64, f64)> = (0..=10 * 24).rev().map(|h| (day(0) + h * 3_600_000, 100.0 + h as f64)).collect();
+        let (start, end) = change_window(&hourly, 7).unwrap();
+        assert_eq!(end, (day(10), 340.0));
+        assert_eq!(start, (day(3), 172.0));
+        assert_eq!(span_days(start.0, end.0), 7.0);
+
+        // Only three days cached: the window shrinks to what's there
+        let short = [(day(0), 1.0), (day(1), f64::NAN), (day(2), 2.0), (day(3), 4.0)];
+        let (start, end) = change_window(&short, 30).unwrap();
+        assert_eq!((start, end), ((day(0), 1.0), (day(3), 4.0)));
+
+        assert!(change_window(&[(day(0), 1.0)], 7).is_none());
+        assert!(change_window(&[(day(0), 0.0), (day(1), 1.0)], 7).is_none());
+    }
+
+    #[test]
+    fn test_nearest_point_searches_both_neighbours() {
+        let points = [(day(0), 1.0), (day(1), 2.0), (day(3), 4.0)];
+        assert_eq!(nearest_point(&points, day(1)), Some((day(1), 2.0)));
+        assert_eq!(nearest_point(&points, day(2) - 1), Some((day(1), 2.0)));
+        assert_eq!(nearest_point(&points, day(2)), Some((day(1), 2.0)));
+        assert_eq!(nearest_point(&points, day(2) + 1), Some((day(3), 4.0)));
+        assert_eq!(nearest_point(&points, day(-5)), Some((day(0), 1.0)));
+        assert_eq!(nearest_point(&points, day(9)), Some((day(3), 4.0)));
+        assert_eq!(nearest_point(&[], day(0)), None);
+    }
+
+    #[test]
+    fn test_align_handles_different_lengths_and_gaps() {
+        let a = daily_closes(&(0..10).map(|d| (day(d), 1.0 + d as f64)).collect::<Vec<_>>());
+        // Starts later and skips day 5
+        let b = daily_closes(&(3..12).filter(|d| *d != 5).map(|d| (day(d), 100.0 + d as f64)).collect::<Vec<_>>());
+
+        let (days, aligned) = align(&[a, b]);
+        assert_eq!(days.len(), 6);
+        assert_eq!(aligned[0], vec![4.0, 5.0, 7.0, 8.0, 9.0, 10.0]);
+        assert_eq!(aligned[1], vec![103.0, 104.0, 106.0, 107.0, 108.0, 109.0]);
+
+        let (days, aligned) = align(&[vec![(1, 1.0)], Vec::new()]);
+        assert!(days.is_empty() && aligned.iter().all(Vec::is_empty));
+        assert_eq!(align(&[]), (Vec::new(), Vec::new()));
+    }
+
+    #[test]
+    fn test_pearson_perfect_and_anti_correlation() {
+        let a = [0.01, -0.02, 0.03, 0.0, -0.01];
+        let doubled: Vec<f64> = a.iter().map(|x| x * 2.0 + 0.001).collect();
+        let negated: Vec<f64> = a.iter().map(|x| -x).collect();
+
+        assert!((pearson(&a, &doubled).unwrap() - 1.0).abs() < 1e-12);
+        assert!((pearson(&a, &negated).unwrap() + 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_pearson_independent_noise_is_near_zero() {
+        // Two deterministic pseudo-random walks from different seeds
+        let noise = |mut seed: u64| -> Vec<f64> {
+            (0..5000)
+                .map(|_| {
+                    seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                    (seed >> 11) as f64 / (1u64 << 53) as f64 - 0.5
+                })
+                .collect()
+        };
+        let r = pearson(&noise(1), &noise(2)).unwrap();
+        assert!(r.abs() < 0.05, "r = {}", r);
+    }
+
+    #[test]
+    fn test_pearson_degenerate_inputs() {
+        assert_eq!(pearson(&[1.0], &[1.0]), None);
+        assert_eq!(pearson(&[1.0, 2.0], &[1.0]), None);
+        assert_eq!(pearson(&[0.0, 0.0, 0.0], &[1.0, 2.0, 3.0]), None);
+    }
+
+    #[test]
+    fn test_correlation_matrix_is_symmetric() {
+        let prices = [vec![1.0, 2.0, 1.5, 3.0], vec![2.0, 4.0, 3.0, 6.0], vec![3.0, 2.0, 2.5, 1.0]];
+        let series: Vec<Vec<f64>> = prices.iter().map(|p| returns(p)).collect();
+        let matrix = correlation_matrix(&series);
+
+        for (i, row) in matrix.iter().enumerate() {
+            assert!((row[i].unwrap() - 1.0).abs() < 1e-12);
+            for (j, value) in row.iter().enumerate() {
+                assert_eq!(*value, matrix[j][i]);
+            }
+        }
+        assert!((matrix[0][1].unwrap() - 1.0).abs() < 1e-12);
+        assert!(matrix[0][2].unwrap() < 0.0);
+    }
+}
@@ -0,0 +1,253 @@
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::header::{self, HeaderMap};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, FromRequest, HttpRequest, HttpResponse};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use mongodb::bson::oid::ObjectId;
+use sha2::{Digest, Sha256};
+use crate::config::Config;
+use crate::db::DbClient;
+use crate::models::ErrorResponse;
+
+pub const ADMIN_TOKEN_HEADER: &str = "x-admin-token";
+
+// Prefix on generated keys so they're recognizable in config files and leak scanners
+const API_KEY_PREFIX: &str = "ctk_";
+
+// A fresh random API key. Only its hash is stored, so it can be shown exactly once.
+pub fn generate_api_key() -> String {
+    format!(
+        "{}{}{}",
+        API_KEY_PREFIX,
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    )
+}
+
+// Keys are long and random, so a plain SHA-256 is enough to keep them out of the database
+pub fn hash_api_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+// The caller behind a valid `Authorization: Bearer <key>` header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiUser {
+    pub id: ObjectId,
+}
+
+// Optional authentication for endpoints that are per user when a key is sent and global
+// otherwise. A missing header is `None`; a header with an unknown key is a 401, so a
+// typo doesn't silently fall back to the shared favorites.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaybeUser(pub Option<ApiUser>);
+
+fn reject(response: HttpResponse, message: &'static str) -> actix_web::Error {
+    InternalError::from_response(message, response).into()
+}
+
+fn unauthorized_response(message: &'static str) -> HttpResponse {
+    HttpResponse::Unauthorized()
+        .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+        .json(ErrorResponse::new(message))
+}
+
+fn unauthorized(message: &'static str) -> actix_web::Error {
+    reject(unauthorized_response(message), message)
+}
+
+// The token of an `Authorization: Bearer <token>` header, if that is what was sent
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    value.strip_prefix("Bearer ").map(str::trim).filter(|token| !token.is_empty())
+}
+
+fn is_api_token(req: &HttpRequest, token: &str) -> bool {
+    req.app_data::<web::Data<Config>>()
+        .and_then(|config| config.api_token.as_deref())
+        .is_some_and(|expected| constant_time_eq(token.as_bytes(), expected.as_bytes()))
+}
+
+fn is_admin_token(req: &HttpRequest, token: &str) -> bool {
+    req.app_data::<web::Data<Config>>()
+        .and_then(|config| config.admin_token.as_deref())
+        .is_some_and(|expected| constant_time_eq(token.as_bytes(), expected.as_bytes()))
+}
+
+impl FromRequest for MaybeUser {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let authorization = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .map(|value| value.to_str().map(str::to_string));
+        // The shared API_TOKEN and the admin token authorize a request but aren't anybody's key
+        if bearer_token(req.headers()).is_some_and(|token| is_api_token(req, token) || is_admin_token(req, token)) {
+            return Box::pin(async { Ok(MaybeUser(None)) });
+        }
+        let db = req.app_data::<web::Data<DbClient>>().cloned();
+
+        Box::pin(async move {
+            let key = match authorization {
+                None => return Ok(MaybeUser(None)),
+                Some(Ok(value)) => match value.strip_prefix("Bearer ").map(str::trim) {
+                    Some(key) if !key.is_empty() && !key.contains(char::is_whitespace) => key.to_string(),
+                    _ => return Err(unauthorized("Authorization must be `Bearer <api key>`")),
+                },
+                Some(Err(_)) => return Err(unauthorized("Authorization must be `Bearer <api key>`")),
+            };
+
+            let Some(db) = db else {
+                return Err(reject(
+                    HttpResponse::InternalServerError().json(ErrorResponse::new("Database not configured")),
+                    "Database not configured",
+                ));
+            };
+
+            match db.find_user_by_key_hash(&hash_api_key(&key)).await {
+                Ok(Some(user)) => match user.id {
+                    Some(id) => Ok(MaybeUser(Some(ApiUser { id }))),
+                    None => Err(unauthorized("Invalid API key")),
+                },
+                Ok(None) => Err(unauthorized("Invalid API key")),
+                Err(e) => {
+                    tracing::error!(error = %e, "Failed to look up API key");
+                    Err(reject(
+                        HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")),
+                        "Database error",
+                    ))
+                }
+            }
+        })
+    }
+}
+
+// Required authentication, for endpoints that only exist per user: no header is a 401
+// just like an unknown key
+impl FromRequest for ApiUser {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let user = MaybeUser::from_request(req, payload);
+        Box::pin(async move {
+            match user.await? {
+                MaybeUser(Some(user)) => Ok(user),
+                MaybeUser(None) => Err(unauthorized("An API key is required: `Authorization: Bearer <api key>`")),
+            }
+        })
+    }
+}
+
+// Guard for admin endpoints: the request must carry ADMIN_TOKEN in X-Admin-Token or as
+// `Authorization: Bearer`. Without ADMIN_TOKEN configured the endpoints are off entirely.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Admin;
+
+// Compares without bailing at the first differing byte, so timing doesn't leak the token
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+// Whether the request carries `expected` in X-Admin-Token or as a bearer token
+fn presents_admin_token(req: &HttpRequest, expected: &str) -> bool {
+    let header = req.headers().get(ADMIN_TOKEN_HEADER).map(|value| value.as_bytes());
+    let bearer = bearer_token(req.headers()).map(str::as_bytes);
+    [header, bearer].into_iter().flatten().any(|token| constant_time_eq(token, expected.as_bytes()))
+}
+
+impl FromRequest for Admin {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let expected = req.app_data::<web::Data<Config>>().and_then(|config| config.admin_token.clone());
+        let Some(expected) = expected else {
+            return ready(Err(reject(
+                HttpResponse::Forbidden().json(ErrorResponse::new("Admin endpoints are disabled (ADMIN_TOKEN is not set)")),
+                "Admin endpoints are disabled",
+            )));
+        };
+
+        if presents_admin_token(req, &expected) {
+            return ready(Ok(Admin));
+        }
+        ready(Err(reject(
+            HttpResponse::Unauthorized().json(ErrorResponse::new("Missing or invalid X-Admin-Token")),
+            "Missing or invalid X-Admin-Token",
+        )))
+    }
+}
+
+// With API_TOKEN set, a request that changes anything (any method but GET, HEAD, OPTIONS
+// and TRACE) must carry it as `Authorization: Bearer`. Reads stay public, and so does
+// everything while API_TOKEN is unset. A user's API key or the admin token are accepted
+// in its place, since they already identify the caller; the handler checks them as before.
+pub async fn require_api_token(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let required = !req.method().is_safe()
+        && req.app_data::<web::Data<Config>>().is_some_and(|config| config.api_token.is_some());
+    if !required || is_authorized(req.request()).await {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    }
+
+    tracing::debug!(method = %req.method(), path = req.path(), "Rejected write without API_TOKEN");
+    let response = unauthorized_response("A valid `Authorization: Bearer <token>` header is required to change data");
+    Ok(req.into_response(response))
+}
+
+async fn is_authorized(req: &HttpRequest) -> bool {
+    let admin = req.app_data::<web::Data<Config>>().and_then(|config| config.admin_token.as_deref());
+    if admin.is_some_and(|expected| presents_admin_token(req, expected)) {
+        return true;
+    }
+
+    let Some(token) = bearer_token(req.headers()) else {
+        return false;
+    };
+    if is_api_token(req, token) {
+        return true;
+    }
+    let Some(db) = req.app_data::<web::Data<DbClient>>() else {
+        return false;
+    };
+    match db.find_user_by_key_hash(&hash_api_key(token)).await {
+        Ok(user) => user.is_some(),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to look up API key");
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_keys_are_unique_and_hash_stably() {
+        let a = generate_api_key();
+        let b = generate_api_key();
+        assert_ne!(a, b);
+        assert!(a.starts_with(API_KEY_PREFIX));
+        assert_eq!(a.len(), API_KEY_PREFIX.len() + 64);
+
+        assert_eq!(hash_api_key(&a), hash_api_key(&a));
+        assert_ne!(hash_api_key(&a), hash_api_key(&b));
+        assert_eq!(hash_api_key(&a).len(), 64);
+        assert!(!hash_api_key(&a).contains(&a));
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret-longer"));
+        assert!(!constant_time_eq(b"", b"secret"));
+    }
+}
@@ -0,0 +1,284 @@
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use crate::crypto_service::{CryptoService, CryptoServiceError};
+use crate::currency::DEFAULT_CURRENCY;
+use crate::db::{history_coverage, DbClient};
+use crate::models::{HistoryDays, HistoryFetch, HistoryInterval};
+use crate::rate_limiter::RateLimiter;
+use crate::shutdown::BackgroundTasks;
+
+// The ranges kept cached for every favorite, as the chart's range buttons ask for them
+pub const BACKFILL_RANGES: [u32; 3] = [1, 30, 365];
+
+// One chart to fetch, at the granularity a plain request for its range is cached under
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BackfillJob {
+    pub token_id: String,
+    pub days: HistoryDays,
+}
+
+impl BackfillJob {
+    pub fn interval(&self) -> HistoryInterval {
+        HistoryInterval::auto(self.days)
+    }
+}
+
+// Favorite charts that aren't fresh in the cache, by token then range. `fetches` holds
+// what each token's stored history was fetched from; a chart counts as fresh when
+// history_coverage would serve it from there at `at`.
+pub fn stale_jobs(
+    favorites: &HashSet<String>,
+    fetches: &HashMap<String, Vec<HistoryFetch>>,
+    at: DateTime<Utc>,
+) -> Vec<BackfillJob> {
+    let is_fresh = |job: &BackfillJob| {
+        let fetched = fetches.get(&job.token_id).map(Vec::as_slice).unwrap_or_default();
+        history_coverage(fetched, job.days, job.interval(), Some(at)).is_some()
+    };
+
+    let mut jobs: Vec<BackfillJob> = favorites
+        .iter()
+        .flat_map(|token_id| {
+            BACKFILL_RANGES.iter().map(move |&days| BackfillJob { token_id: token_id.clone(), days: days.into() })
+        })
+        .filter(|job| !is_fresh(job))
+        .collect();
+    jobs.sort();
+    jobs
+}
+
+// Round-robin over tokens: each pick is the first stale chart of the token after the
+// one picked last, so a token with three stale ranges can't hold up the others
+#[derive(Debug, Default)]
+pub struct Rotation {
+    last_token: Option<String>,
+}
+
+impl Rotation {
+    // The next job from `pending` (sorted as stale_jobs returns it) and whether the
+    // rotation wrapped around to the start to find it
+    pub fn next<'a>(&mut self, pending: &'a [BackfillJob]) -> Option<(&'a BackfillJob, bool)> {
+        let after_last = pending
+            .iter()
+            .find(|job| self.last_token.as_deref().is_none_or(|last| job.token_id.as_str() > last));
+        let (job, wrapped) = match after_last {
+            Some(job) => (job, false),
+            None => (pending.first()?, true),
+        };
+        self.last_token = Some(job.token_id.clone());
+        Some((job, wrapped))
+    }
+}
+
+// Favorite charts still waiting to be fetched, as of the last tick; shared with /metrics
+#[derive(Debug, Clone, Default)]
+pub struct BackfillStatus {
+    pending: Arc<AtomicUsize>,
+}
+
+impl BackfillStatus {
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    fn set_pending(&self, pending: usize) {
+        self.pending.store(pending, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TickOutcome {
+    // Every favorite chart is fresh
+    Idle,
+    // Something is stale but the rate limiter had no slot this tick
+    Waiting,
+    Fetched(BackfillJob),
+    Failed(BackfillJob),
+}
+
+// Keeps favorites' charts cached ahead of the first request for them
+pub struct HistoryBackfill {
+    db: DbClient,
+    crypto_service: CryptoService,
+    rate_limiter: Arc<RateLimiter>,
+    status: BackfillStatus,
+    rotation: Rotation,
+    fetched: usize,
+    failed: usize,
+}
+
+impl HistoryBackfill {
+    pub fn new(db: DbClient, crypto_service: CryptoService, rate_limiter: Arc<RateLimiter>, status: BackfillStatus) -> Self {
+        Self {
+            db,
+            crypto_service,
+            rate_limiter,
+            status,
+            rotation: Rotation::default(),
+            fetched: 0,
+            failed: 0,
+        }
+    }
+
+    // At most one upstream call, for the next stale chart in the rotation, and only
+    // when the shared rate limiter grants a slot
+    pub async fn tick(&mut self) -> mongodb::error::Result<TickOutcome> {
+        let favorites = self.db.favorite_token_ids().await?;
+        let token_ids: Vec<String> = favorites.iter().cloned().collect();
+        let fetches = self.db.history_fetches(&token_ids).await?;
+        let pending = stale_jobs(&favorites, &fetches, Utc::now());
+        self.status.set_pending(pending.len());
+
+        if pending.is_empty() {
+            self.finish_cycle(0);
+            return Ok(TickOutcome::Idle);
+        }
+        // The rotation only moves on a real fetch, so a denied slot skips nobody
+        if !self.rate_limiter.try_acquire().await {
+            return Ok(TickOutcome::Waiting);
+        }
+        let Some((job, wrapped)) = self.rotation.next(&pending) else {
+            return Ok(TickOutcome::Idle);
+        };
+        if wrapped {
+            self.finish_cycle(pending.len());
+        }
+
+        let job = job.clone();
+        let interval = job.interval();
+        match self.crypto_service.fetch_historical_data_in(&job.token_id, job.days, DEFAULT_CURRENCY, None).await {
+            Ok(data) => {
+                self.db.save_history(&job.token_id, job.days, interval, &data).await?;
+                tracing::debug!(token_id = %job.token_id, days = %job.days, %interval, "Backfilled historical data");
+                self.fetched += 1;
+                self.status.set_pending(pending.len() - 1);
+                Ok(TickOutcome::Fetched(job))
+            }
+            Err(e) => {
+                if e == CryptoServiceError::RateLimited {
+                    self.rate_limiter.record_rate_limit().await;
+                }
+                tracing::warn!(token_id = %job.token_id, days = %job.days, error = %e, "Failed to backfill historical data");
+                self.failed += 1;
+                Ok(TickOutcome::Failed(job))
+            }
+        }
+    }
+
+    // Logs what the pass over the pending charts did, once it did anything
+    fn finish_cycle(&mut self, pending: usize) {
+        if self.fetched + self.failed > 0 {
+            tracing::info!(fetched = self.fetched, failed = self.failed, pending, "History backfill cycle finished");
+        }
+        self.fetched = 0;
+        self.failed = 0;
+    }
+}
+
+// Runs a backfill tick every `interval` until shutdown is signalled
+pub fn spawn_history_backfill(tasks: &BackgroundTasks, mut backfill: HistoryBackfill, interval: Duration) {
+    tasks.spawn(move |token| async move {
+        loop {
+            if let Err(e) = backfill.tick().await {
+                tracing::warn!("History backfill tick failed: {}", e);
+            }
+
+            tokio::select! {
+                _ = token.cancelled() => break,
+                _ = tokio::time::sleep(interval) => {}
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn favorites(ids: &[&str]) -> HashSet<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    fn job(token_id: &str, days: u32) -> BackfillJob {
+        BackfillJob { token_id: token_id.to_string(), days: days.into() }
+    }
+
+    fn fetched(charts: &[(u32, HistoryInterval, DateTime<Utc>)]) -> HashMap<String, Vec<HistoryFetch>> {
+        let fetches = charts.iter().map(|&(days, interval, fetched_at)| HistoryFetch { days: days.into(), interval, fetched_at });
+        HashMap::from([("bitcoin".to_string(), fetches.collect())])
+    }
+
+    #[test]
+    fn test_every_range_of_every_favorite_is_stale_with_nothing_cached() {
+        let jobs = stale_jobs(&favorites(&["ethereum", "bitcoin"]), &HashMap::new(), Utc::now());
+        assert_eq!(
+            jobs,
+            vec![job("bitcoin", 1), job("bitcoin", 30), job("bitcoin", 365), job("ethereum", 1), job("ethereum", 30), job("ethereum", 365)]
+        );
+    }
+
+    #[test]
+    fn test_fresh_charts_are_skipped() {
+        let now = Utc::now();
+        let cached = fetched(&[
+            (1, HistoryInterval::Hourly, now),
+            (365, HistoryInterval::Daily, now),
+            // Not the granularity a plain 30-day request is cached under
+            (30, HistoryInterval::Daily, now),
+        ]);
+        assert_eq!(stale_jobs(&favorites(&["bitcoin"]), &cached, now), vec![job("bitcoin", 30)]);
+    }
+
+    #[test]
+    fn test_fresh_hourly_chart_covers_daily_and_shorter_ranges() {
+        let now = Utc::now();
+        let cached = fetched(&[(365, HistoryInterval::Hourly, now)]);
+        assert_eq!(stale_jobs(&favorites(&["bitcoin"]), &cached, now), vec![job("bitcoin", 1)]);
+
+        // Fetched long enough ago that the 30-day range has expired but not the year
+        let cached = fetched(&[(365, HistoryInterval::Hourly, now - chrono::Duration::hours(12))]);
+        assert_eq!(stale_jobs(&favorites(&["bitcoin"]), &cached, now), vec![job("bitcoin", 1), job("bitcoin", 30)]);
+    }
+
+    #[test]
+    fn test_rotation_takes_one_chart_per_token_in_turn() {
+        let mut pending = stale_jobs(&favorites(&["bitcoin", "ethereum", "solana"]), &HashMap::new(), Utc::now());
+        let mut rotation = Rotation::default();
+        let mut order = Vec::new();
+        while let Some((next, _)) = rotation.next(&pending) {
+            let next = next.clone();
+            pending.retain(|j| *j != next);
+            order.push(next);
+        }
+
+        assert_eq!(
+            order,
+            vec![
+                job("bitcoin", 1), job("ethereum", 1), job("solana", 1),
+                job("bitcoin", 30), job("ethereum", 30), job("solana", 30),
+                job("bitcoin", 365), job("ethereum", 365), job("solana", 365),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rotation_moves_past_a_token_that_stays_stale() {
+        // A fetch that keeps failing leaves the pending set unchanged
+        let pending = vec![job("bitcoin", 1), job("ethereum", 1)];
+        let mut rotation = Rotation::default();
+
+        let picks: Vec<(BackfillJob, bool)> = (0..3).map(|_| {
+            let (job, wrapped) = rotation.next(&pending).unwrap();
+            (job.clone(), wrapped)
+        }).collect();
+        assert_eq!(picks, vec![(job("bitcoin", 1), false), (job("ethereum", 1), false), (job("bitcoin", 1), true)]);
+    }
+
+    #[test]
+    fn test_rotation_with_nothing_pending() {
+        assert!(Rotation::default().next(&[]).is_none());
+    }
+}
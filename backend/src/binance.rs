@@ -0,0 +1,56 @@
+use reqwest::Client;
+use std::time::Duration;
+use crate::config::Config;
+use crate::models::BinanceTicker;
+
+// Binance's public market data API, used only as a fallback price source.
+// No key is needed; the ticker endpoint is cheap for a few dozen symbols.
+#[derive(Clone)]
+pub struct BinanceService {
+    client: Client,
+    base_url: String,
+}
+
+impl BinanceService {
+    pub fn new(base_url: String) -> Self {
+        let client = Client::builder()
+            .user_agent("CryptoTracker/1.0 (Educational Project)")
+            .timeout(Duration::from_secs(10))
+            .build()
+            .unwrap_or_else(|_| Client::new());
+
+        Self { client, base_url }
+    }
+
+    // None when BINANCE_FALLBACK is off
+    pub fn from_config(config: &Config) -> Option<Self> {
+        config
+            .binance_fallback
+            .then(|| Self::new(config.binance_api_url.clone()))
+    }
+
+    // 24h tickers for the given symbols (e.g. BTCUSDT), in one request
+    pub async fn fetch_tickers(&self, symbols: &[String]) -> Result<Vec<BinanceTicker>, Box<dyn std::error::Error + Send + Sync>> {
+        if symbols.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let url = format!("{}/api/v3/ticker/24hr", self.base_url);
+        let symbols = serde_json::to_string(symbols)?;
+
+        let response = self.client
+            .get(&url)
+            .query(&[("symbols", symbols)])
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            tracing::error!("Binance error: status={}, body={}", status, body);
+            return Err(format!("Binance returned error: {}", status).into());
+        }
+
+        Ok(response.json().await?)
+    }
+}
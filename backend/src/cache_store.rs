@@ -0,0 +1,224 @@
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Where shared state (rate-limit timestamps, the cached token list) lives, picked with CACHE_BACKEND
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CacheBackend {
+    #[default]
+    Memory,
+    Redis,
+}
+
+impl FromStr for CacheBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "memory" => Ok(CacheBackend::Memory),
+            "redis" => Ok(CacheBackend::Redis),
+            other => Err(format!("expected 'memory' or 'redis', got '{}'", other)),
+        }
+    }
+}
+
+impl fmt::Display for CacheBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CacheBackend::Memory => "memory",
+            CacheBackend::Redis => "redis",
+        })
+    }
+}
+
+// Small key-value store with expiry that the rate limiter and token cache are built on.
+// The memory store is per process; a shared one (Redis) coordinates every replica.
+// Backend failures are logged by the implementation and look like a missing key,
+// a failed swap or a `None` counter, so a cache outage never fails a request.
+#[async_trait]
+pub trait CacheStore: Send + Sync {
+    async fn get(&self, key: &str) -> Option<String>;
+    async fn set(&self, key: &str, value: String, ttl: Duration);
+    async fn delete(&self, key: &str);
+    // Adds `by` and returns the new value. A missing key counts from 0 and, when
+    // created, expires after `ttl`; `None` keeps it until deleted.
+    async fn incr(&self, key: &str, by: i64, ttl: Option<Duration>) -> Option<i64>;
+    // Writes `value` only if the key currently holds `expected` (`None`: is absent).
+    // Returns whether the write happened.
+    async fn compare_and_set(&self, key: &str, expected: Option<&str>, value: String, ttl: Duration) -> bool;
+}
+
+struct Entry {
+    value: String,
+    expires_at: Option<Instant>,
+}
+
+impl Entry {
+    fn is_live(&self) -> bool {
+        self.expires_at.is_none_or(|at| Instant::now() < at)
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryCacheStore {
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl MemoryCacheStore {
+    fn entries(&self) -> std::sync::MutexGuard<'_, HashMap<String, Entry>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Current value of a key, dropping it first if it has expired
+    fn live_value(entries: &mut HashMap<String, Entry>, key: &str) -> Option<String> {
+        match entries.get(key) {
+            Some(entry) if entry.is_live() => Some(entry.value.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+}
+
+#[async_trait]
+impl CacheStore for MemoryCacheStore {
+    async fn get(&self, key: &str) -> Option<String> {
+        Self::live_value(&mut self.entries(), key)
+    }
+
+    async fn set(&self, key: &str, value: String, ttl: Duration) {
+        self.entries().insert(key.to_string(), Entry { value, expires_at: Some(Instant::now() + ttl) });
+    }
+
+    async fn delete(&self, key: &str) {
+        self.entries().remove(key);
+    }
+
+    async fn incr(&self, key: &str, by: i64, ttl: Option<Duration>) -> Option<i64> {
+        let mut entries = self.entries();
+        let current = match Self::live_value(&mut entries, key) {
+            Some(value) => match value.parse::<i64>() {
+                Ok(n) => n,
+                Err(_) => {
+                    tracing::warn!("Cannot increment non-numeric cache key {}", key);
+                    return None;
+                }
+            },
+            None => {
+                let expires_at = ttl.map(|ttl| Instant::now() + ttl);
+                entries.insert(key.to_string(), Entry { value: "0".to_string(), expires_at });
+                0
+            }
+        };
+
+        let next = current + by;
+        if let Some(entry) = entries.get_mut(key) {
+            entry.value = next.to_string();
+        }
+        Some(next)
+    }
+
+    async fn compare_and_set(&self, key: &str, expected: Option<&str>, value: String, ttl: Duration) -> bool {
+        let mut entries = self.entries();
+        if Self::live_value(&mut entries, key).as_deref() != expected {
+            return false;
+        }
+        entries.insert(key.to_string(), Entry { value, expires_at: Some(Instant::now() + ttl) });
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[tokio::test]
+    async fn test_get_set_delete_and_expiry() {
+        let store = MemoryCacheStore::default();
+        assert!(store.get("key").await.is_none());
+
+        store.set("key", "value".to_string(), MINUTE).await;
+        assert_eq!(store.get("key").await.as_deref(), Some("value"));
+
+        store.delete("key").await;
+        assert!(store.get("key").await.is_none());
+
+        store.set("short", "value".to_string(), Duration::from_millis(20)).await;
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        assert!(store.get("short").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_incr_counts_from_zero_and_keeps_first_expiry() {
+        let store = MemoryCacheStore::default();
+        assert_eq!(store.incr("hits", 1, None).await, Some(1));
+        assert_eq!(store.incr("hits", 5, None).await, Some(6));
+        assert_eq!(store.get("hits").await.as_deref(), Some("6"));
+
+        store.incr("window", 1, Some(Duration::from_millis(20))).await;
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        assert_eq!(store.incr("window", 1, Some(MINUTE)).await, Some(1));
+
+        store.set("text", "abc".to_string(), MINUTE).await;
+        assert_eq!(store.incr("text", 1, None).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_compare_and_set_requires_expected_value() {
+        let store = MemoryCacheStore::default();
+
+        // None means "only if absent"
+        assert!(store.compare_and_set("lock", None, "a".to_string(), MINUTE).await);
+        assert!(!store.compare_and_set("lock", None, "b".to_string(), MINUTE).await);
+        assert_eq!(store.get("lock").await.as_deref(), Some("a"));
+
+        assert!(!store.compare_and_set("lock", Some("b"), "c".to_string(), MINUTE).await);
+        assert!(store.compare_and_set("lock", Some("a"), "c".to_string(), MINUTE).await);
+        assert_eq!(store.get("lock").await.as_deref(), Some("c"));
+    }
+
+    #[tokio::test]
+    async fn test_expired_value_counts_as_absent_for_compare_and_set() {
+        let store = MemoryCacheStore::default();
+        store.set("lock", "old".to_string(), Duration::from_millis(20)).await;
+        tokio::time::sleep(Duration::from_millis(40)).await;
+
+        assert!(!store.compare_and_set("lock", Some("old"), "new".to_string(), MINUTE).await);
+        assert!(store.compare_and_set("lock", None, "new".to_string(), MINUTE).await);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_compare_and_set_has_exactly_one_winner() {
+        let store = Arc::new(MemoryCacheStore::default());
+
+        let attempts: Vec<_> = (0..32)
+            .map(|i| {
+                let store = store.clone();
+                tokio::spawn(async move { store.compare_and_set("slot", None, i.to_string(), MINUTE).await })
+            })
+            .collect();
+
+        let mut winners = 0;
+        for attempt in attempts {
+            if attempt.await.unwrap() {
+                winners += 1;
+            }
+        }
+        assert_eq!(winners, 1);
+    }
+
+    #[test]
+    fn test_backend_parses_case_insensitively() {
+        assert_eq!("memory".parse::<CacheBackend>(), Ok(CacheBackend::Memory));
+        assert_eq!("Redis".parse::<CacheBackend>(), Ok(CacheBackend::Redis));
+        assert!("memcached".parse::<CacheBackend>().is_err());
+    }
+}
@@ -0,0 +1,33 @@
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::Error;
+
+// Bodies smaller than this go out as they are: the gzip header and framing would eat
+// most of the saving
+pub const MIN_COMPRESS_BYTES: u64 = 1024;
+
+// Runs inside actix's `Compress` and marks responses it should leave alone with
+// `Content-Encoding: identity`, which `Compress` respects. That covers small bodies and
+// server-sent events, where the encoder would hold events back until its buffer fills.
+// Streaming exports are still compressed; `Compress` encodes them chunk by chunk and
+// flushes whenever the stream pauses, so they keep streaming.
+pub async fn skip_uncompressible(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let mut res = next.call(req).await?;
+
+    let small = matches!(res.response().body().size(), BodySize::Sized(len) if len < MIN_COMPRESS_BYTES);
+    let event_stream = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/event-stream"));
+
+    if (small || event_stream) && !res.headers().contains_key(header::CONTENT_ENCODING) {
+        res.headers_mut().insert(header::CONTENT_ENCODING, HeaderValue::from_static("identity"));
+    }
+    Ok(res)
+}
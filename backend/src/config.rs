@@ -0,0 +1,779 @@
+use std::env;
+use std::fmt;
+use reqwest::header::HeaderValue;
+use reqwest::Url;
+use crate::cache_store::CacheBackend;
+use crate::crypto_service::{ApiPlan, DEFAULT_USER_AGENT};
+use crate::models::MAX_HISTORY_DAYS;
+use crate::telemetry::LogFormat;
+
+// Typed application configuration, loaded once at startup from the environment
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub mongodb_uri: String,
+    pub mongodb_connect_attempts: u32,
+    pub mongo_max_pool_size: u32,
+    pub mongo_min_pool_size: u32,
+    pub mongo_connect_timeout_secs: u64,
+    pub mongo_server_selection_timeout_secs: u64,
+    pub redis_url: Option<String>,
+    pub cache_backend: CacheBackend,
+    pub database_name: String,
+    pub host: String,
+    pub port: u16,
+    pub coingecko_api_url: String,
+    pub coingecko_api_key: Option<String>,
+    pub coingecko_api_plan: ApiPlan,
+    // Sent to CoinGecko: HTTP_USER_AGENT, with HTTP_CONTACT_EMAIL appended when set
+    pub http_user_agent: String,
+    pub binance_fallback: bool,
+    pub binance_api_url: String,
+    pub token_cache_ttl_secs: u64,
+    pub history_cache_ttl_secs: u64,
+    pub max_history_days: u32,
+    pub token_detail_max_age_secs: u64,
+    pub force_fresh_interval_secs: u64,
+    pub profile_cache_ttl_secs: u64,
+    pub memory_cache_ttl_secs: u64,
+    pub history_prune_interval_secs: u64,
+    pub history_backfill_interval_secs: u64,
+    pub startup_warmup: bool,
+    pub min_request_interval_secs: f64,
+    pub rate_limit_backoff_secs: i64,
+    pub admin_token: Option<String>,
+    // Required as `Authorization: Bearer` on mutating requests when set
+    pub api_token: Option<String>,
+    pub shutdown_grace_secs: u64,
+    pub request_timeout_secs: u64,
+    pub upstream_timeout_secs: u64,
+    pub max_concurrent_upstream: usize,
+    // Tokens fetched from CoinGecko per refresh; past 250 that takes several pages
+    pub top_tokens_limit: u32,
+    // /coins/markets pages fetched at once when an API key is set; without one, paging is sequential
+    pub page_fetch_parallelism: usize,
+    pub large_move_percent: f64,
+    pub event_dedup_window_secs: u64,
+    // How long an alert stays quiet about a token after firing for it
+    pub alert_cooldown_secs: u64,
+    pub debug_endpoints: bool,
+    pub log_format: LogFormat,
+}
+
+// Every problem found while loading the config, reported together
+#[derive(Debug)]
+pub struct ConfigError {
+    pub errors: Vec<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration: {}", self.errors.join("; "))
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+const DEFAULT_HOST: &str = "127.0.0.1";
+const DEFAULT_PORT: u16 = 8080;
+const DEFAULT_COINGECKO_API_URL: &str = "https://api.coingecko.com/api/v3";
+const DEFAULT_COINGECKO_PRO_API_URL: &str = "https://pro-api.coingecko.com/api/v3";
+const DEFAULT_BINANCE_API_URL: &str = "https://api.binance.com";
+const DEFAULT_TOKEN_CACHE_TTL_SECS: u64 = 60;
+const DEFAULT_HISTORY_CACHE_TTL_SECS: u64 = 3600;
+const DEFAULT_TOKEN_DETAIL_MAX_AGE_SECS: u64 = 300;
+// Per client, for requests that skip the cache with ?fresh=true or Cache-Control: no-cache
+const DEFAULT_FORCE_FRESH_INTERVAL_SECS: u64 = 30;
+// Descriptions and links rarely change
+const DEFAULT_PROFILE_CACHE_TTL_SECS: u64 = 7 * 24 * 3600;
+const DEFAULT_MEMORY_CACHE_TTL_SECS: u64 = 10;
+const DEFAULT_HISTORY_PRUNE_INTERVAL_SECS: u64 = 3600;
+// At most one backfill fetch per tick, so this bounds its share of the upstream budget
+const DEFAULT_HISTORY_BACKFILL_INTERVAL_SECS: u64 = 120;
+const DEFAULT_MIN_REQUEST_INTERVAL_SECS: f64 = 2.0; // Minimum 2 seconds between API calls
+const DEFAULT_PRO_MIN_REQUEST_INTERVAL_SECS: f64 = 0.2; // Pro keys allow ~500 calls/minute
+const DEFAULT_RATE_LIMIT_BACKOFF_SECS: i64 = 60; // Wait 60 seconds after rate limit
+const DEFAULT_SHUTDOWN_GRACE_SECS: u64 = 10;
+const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 20;
+const DEFAULT_UPSTREAM_TIMEOUT_SECS: u64 = 8;
+const DEFAULT_MAX_CONCURRENT_UPSTREAM: usize = 4;
+const DEFAULT_PAGE_FETCH_PARALLELISM: usize = 2;
+const DEFAULT_TOP_TOKENS_LIMIT: u32 = 100;
+// One full page at CoinGecko's per_page cap, so a refresh takes a single rate limiter slot
+const MAX_TOP_TOKENS_LIMIT: u32 = 250;
+// A refresh-to-refresh price move at least this large (either way) is recorded as an event
+const DEFAULT_LARGE_MOVE_PERCENT: f64 = 10.0;
+const DEFAULT_EVENT_DEDUP_WINDOW_SECS: u64 = 3600;
+// As long as the 24h window, so one sustained move fires once
+const DEFAULT_ALERT_COOLDOWN_SECS: u64 = 86400;
+const DEFAULT_MONGODB_CONNECT_ATTEMPTS: u32 = 5;
+const DEFAULT_MONGO_MAX_POOL_SIZE: u32 = 20;
+const DEFAULT_MONGO_MIN_POOL_SIZE: u32 = 0;
+const DEFAULT_MONGO_CONNECT_TIMEOUT_SECS: u64 = 10;
+// The driver's own default is 30s, long for a request waiting on a down primary
+const DEFAULT_MONGO_SERVER_SELECTION_TIMEOUT_SECS: u64 = 10;
+
+impl Config {
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_lookup(|key| env::var(key).ok())
+    }
+
+    // Builds the config from an arbitrary key lookup so tests don't have to mutate the process env
+    pub fn from_lookup<F>(lookup: F) -> Result<Self, ConfigError>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut errors = Vec::new();
+
+        let get = |key: &str| lookup(key).map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+
+        let mongodb_uri = match get("MONGODB_URI") {
+            Some(uri) if uri.starts_with("mongodb://") || uri.starts_with("mongodb+srv://") => uri,
+            Some(uri) => {
+                errors.push(format!("MONGODB_URI must start with mongodb:// or mongodb+srv:// (got '{}')", uri));
+                String::new()
+            }
+            None => {
+                errors.push("MONGODB_URI must be set".to_string());
+                String::new()
+            }
+        };
+
+        let mongodb_connect_attempts =
+            parse_or(&get, "MONGODB_CONNECT_ATTEMPTS", DEFAULT_MONGODB_CONNECT_ATTEMPTS, &mut errors);
+        if mongodb_connect_attempts == 0 {
+            errors.push("MONGODB_CONNECT_ATTEMPTS must be at least 1".to_string());
+        }
+        let mongo_max_pool_size = parse_or(&get, "MONGO_MAX_POOL_SIZE", DEFAULT_MONGO_MAX_POOL_SIZE, &mut errors);
+        let mongo_min_pool_size = parse_or(&get, "MONGO_MIN_POOL_SIZE", DEFAULT_MONGO_MIN_POOL_SIZE, &mut errors);
+        if mongo_max_pool_size == 0 {
+            errors.push("MONGO_MAX_POOL_SIZE must be at least 1".to_string());
+        } else if mongo_min_pool_size > mongo_max_pool_size {
+            errors.push("MONGO_MIN_POOL_SIZE can't be larger than MONGO_MAX_POOL_SIZE".to_string());
+        }
+        let mongo_connect_timeout_secs =
+            parse_or(&get, "MONGO_CONNECT_TIMEOUT_SECS", DEFAULT_MONGO_CONNECT_TIMEOUT_SECS, &mut errors);
+        let mongo_server_selection_timeout_secs = parse_or(
+            &get,
+            "MONGO_SERVER_SELECTION_TIMEOUT_SECS",
+            DEFAULT_MONGO_SERVER_SELECTION_TIMEOUT_SECS,
+            &mut errors,
+        );
+        if mongo_connect_timeout_secs == 0 {
+            errors.push("MONGO_CONNECT_TIMEOUT_SECS must be at least 1".to_string());
+        }
+        if mongo_server_selection_timeout_secs == 0 {
+            errors.push("MONGO_SERVER_SELECTION_TIMEOUT_SECS must be at least 1".to_string());
+        }
+
+        let redis_url = get("REDIS_URL");
+        if let Some(url) = &redis_url {
+            if !(url.starts_with("redis://") || url.starts_with("rediss://")) {
+                errors.push(format!("REDIS_URL must start with redis:// or rediss:// (got '{}')", url));
+            }
+        }
+
+        // Setting REDIS_URL alone is enough to share state; CACHE_BACKEND=memory opts out
+        let cache_backend = match get("CACHE_BACKEND") {
+            Some(raw) => raw.parse().unwrap_or_else(|e| {
+                errors.push(format!("CACHE_BACKEND: {}", e));
+                CacheBackend::default()
+            }),
+            None if redis_url.is_some() => CacheBackend::Redis,
+            None => CacheBackend::Memory,
+        };
+        if cache_backend == CacheBackend::Redis && redis_url.is_none() {
+            errors.push("CACHE_BACKEND=redis requires REDIS_URL".to_string());
+        }
+
+        let log_format = get("LOG_FORMAT")
+            .map(|raw| {
+                raw.parse().unwrap_or_else(|e| {
+                    errors.push(format!("LOG_FORMAT: {}", e));
+                    LogFormat::default()
+                })
+            })
+            .unwrap_or_default();
+
+        let database_name = get("DATABASE_NAME").unwrap_or_else(|| {
+            errors.push("DATABASE_NAME must be set".to_string());
+            String::new()
+        });
+
+        let host = get("SERVER_HOST").unwrap_or_else(|| DEFAULT_HOST.to_string());
+        let port = parse_or(&get, "SERVER_PORT", DEFAULT_PORT, &mut errors);
+
+        let coingecko_api_key = get("COINGECKO_API_KEY");
+        let coingecko_api_plan = match get("COINGECKO_API_PLAN") {
+            Some(raw) => raw.parse().unwrap_or_else(|e| {
+                errors.push(format!("COINGECKO_API_PLAN: {}", e));
+                ApiPlan::default()
+            }),
+            None => ApiPlan::default(),
+        };
+        let has_pro_key = coingecko_api_key.is_some() && coingecko_api_plan == ApiPlan::Pro;
+
+        // Pro keys are only accepted on the pro host
+        let coingecko_api_url = get("COINGECKO_API_URL").unwrap_or_else(|| {
+            if has_pro_key { DEFAULT_COINGECKO_PRO_API_URL } else { DEFAULT_COINGECKO_API_URL }.to_string()
+        });
+        match Url::parse(&coingecko_api_url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") && url.has_host() => {}
+            _ => errors.push(format!(
+                "COINGECKO_API_URL must be an absolute http(s) URL (got '{}')",
+                coingecko_api_url
+            )),
+        }
+        let coingecko_api_url = coingecko_api_url.trim_end_matches('/').to_string();
+
+        // CoinGecko asks API users to identify themselves; a contact address lets them reach us
+        let mut http_user_agent = get("HTTP_USER_AGENT").unwrap_or_else(|| DEFAULT_USER_AGENT.to_string());
+        if let Some(email) = get("HTTP_CONTACT_EMAIL") {
+            if email.contains('@') && !email.contains(|c: char| c.is_whitespace() || c == '(' || c == ')') {
+                http_user_agent = format!("{} (+mailto:{})", http_user_agent, email);
+            } else {
+                errors.push(format!("HTTP_CONTACT_EMAIL must be an email address (got '{}')", email));
+            }
+        }
+        if HeaderValue::from_str(&http_user_agent).is_err() {
+            errors.push("HTTP_USER_AGENT must be printable ASCII".to_string());
+        }
+
+        let binance_fallback = parse_or(&get, "BINANCE_FALLBACK", true, &mut errors);
+        let binance_api_url = get("BINANCE_API_URL").unwrap_or_else(|| DEFAULT_BINANCE_API_URL.to_string());
+        match Url::parse(&binance_api_url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") && url.has_host() => {}
+            _ => errors.push(format!(
+                "BINANCE_API_URL must be an absolute http(s) URL (got '{}')",
+                binance_api_url
+            )),
+        }
+        let binance_api_url = binance_api_url.trim_end_matches('/').to_string();
+
+        let token_cache_ttl_secs =
+            parse_or(&get, "TOKEN_CACHE_TTL_SECS", DEFAULT_TOKEN_CACHE_TTL_SECS, &mut errors);
+        let history_cache_ttl_secs =
+            parse_or(&get, "HISTORY_CACHE_TTL_SECS", DEFAULT_HISTORY_CACHE_TTL_SECS, &mut errors);
+        // Can only tighten MAX_HISTORY_DAYS, which is as far back as CoinGecko's public API goes
+        let max_history_days = parse_or(&get, "MAX_HISTORY_DAYS", MAX_HISTORY_DAYS, &mut errors);
+        if !(1..=MAX_HISTORY_DAYS).contains(&max_history_days) {
+            errors.push(format!("MAX_HISTORY_DAYS must be between 1 and {}", MAX_HISTORY_DAYS));
+        }
+        let token_detail_max_age_secs =
+            parse_or(&get, "TOKEN_DETAIL_MAX_AGE_SECS", DEFAULT_TOKEN_DETAIL_MAX_AGE_SECS, &mut errors);
+        let force_fresh_interval_secs =
+            parse_or(&get, "FORCE_FRESH_INTERVAL_SECS", DEFAULT_FORCE_FRESH_INTERVAL_SECS, &mut errors);
+        let profile_cache_ttl_secs =
+            parse_or(&get, "PROFILE_CACHE_TTL_SECS", DEFAULT_PROFILE_CACHE_TTL_SECS, &mut errors);
+        let memory_cache_ttl_secs =
+            parse_or(&get, "MEMORY_CACHE_TTL_SECS", DEFAULT_MEMORY_CACHE_TTL_SECS, &mut errors);
+        let history_prune_interval_secs =
+            parse_or(&get, "HISTORY_PRUNE_INTERVAL_SECS", DEFAULT_HISTORY_PRUNE_INTERVAL_SECS, &mut errors);
+        let history_backfill_interval_secs =
+            parse_or(&get, "HISTORY_BACKFILL_INTERVAL_SECS", DEFAULT_HISTORY_BACKFILL_INTERVAL_SECS, &mut errors);
+        let startup_warmup = parse_or(&get, "STARTUP_WARMUP", true, &mut errors);
+        // A paid key raises the upstream limit, so the default interval drops with it.
+        // Fractional values are accepted so it can be tuned below one second.
+        let default_interval = if has_pro_key {
+            DEFAULT_PRO_MIN_REQUEST_INTERVAL_SECS
+        } else {
+            DEFAULT_MIN_REQUEST_INTERVAL_SECS
+        };
+        let min_request_interval_secs =
+            parse_or(&get, "MIN_REQUEST_INTERVAL_SECS", default_interval, &mut errors);
+        let rate_limit_backoff_secs =
+            parse_or(&get, "RATE_LIMIT_BACKOFF_SECS", DEFAULT_RATE_LIMIT_BACKOFF_SECS, &mut errors);
+
+        let shutdown_grace_secs =
+            parse_or(&get, "SHUTDOWN_GRACE_SECS", DEFAULT_SHUTDOWN_GRACE_SECS, &mut errors);
+        let request_timeout_secs =
+            parse_or(&get, "REQUEST_TIMEOUT_SECS", DEFAULT_REQUEST_TIMEOUT_SECS, &mut errors);
+        let upstream_timeout_secs =
+            parse_or(&get, "UPSTREAM_TIMEOUT_SECS", DEFAULT_UPSTREAM_TIMEOUT_SECS, &mut errors);
+        let max_concurrent_upstream =
+            parse_or(&get, "MAX_CONCURRENT_UPSTREAM", DEFAULT_MAX_CONCURRENT_UPSTREAM, &mut errors);
+
+        let large_move_percent =
+            parse_or(&get, "LARGE_MOVE_PERCENT", DEFAULT_LARGE_MOVE_PERCENT, &mut errors);
+        let event_dedup_window_secs =
+            parse_or(&get, "EVENT_DEDUP_WINDOW_SECS", DEFAULT_EVENT_DEDUP_WINDOW_SECS, &mut errors);
+        let top_tokens_limit = parse_or(&get, "TOP_TOKENS_LIMIT", DEFAULT_TOP_TOKENS_LIMIT, &mut errors);
+        let page_fetch_parallelism =
+            parse_or(&get, "PAGE_FETCH_PARALLELISM", DEFAULT_PAGE_FETCH_PARALLELISM, &mut errors);
+        let alert_cooldown_secs = parse_or(&get, "ALERT_COOLDOWN_SECS", DEFAULT_ALERT_COOLDOWN_SECS, &mut errors);
+
+        let debug_endpoints = parse_or(&get, "DEBUG_ENDPOINTS", false, &mut errors);
+
+        if !min_request_interval_secs.is_finite() || min_request_interval_secs < 0.0 {
+            errors.push("MIN_REQUEST_INTERVAL_SECS must be a non-negative number".to_string());
+        }
+        if rate_limit_backoff_secs < 0 {
+            errors.push("RATE_LIMIT_BACKOFF_SECS must not be negative".to_string());
+        }
+        if request_timeout_secs == 0 {
+            errors.push("REQUEST_TIMEOUT_SECS must be at least 1".to_string());
+        }
+        if upstream_timeout_secs == 0 {
+            errors.push("UPSTREAM_TIMEOUT_SECS must be at least 1".to_string());
+        }
+        if max_concurrent_upstream == 0 {
+            errors.push("MAX_CONCURRENT_UPSTREAM must be at least 1".to_string());
+        }
+        if !(1..=MAX_TOP_TOKENS_LIMIT).contains(&top_tokens_limit) {
+            errors.push(format!("TOP_TOKENS_LIMIT must be between 1 and {}", MAX_TOP_TOKENS_LIMIT));
+        }
+        if page_fetch_parallelism == 0 {
+            errors.push("PAGE_FETCH_PARALLELISM must be at least 1".to_string());
+        }
+        if !large_move_percent.is_finite() || large_move_percent <= 0.0 {
+            errors.push("LARGE_MOVE_PERCENT must be a positive number".to_string());
+        }
+        if event_dedup_window_secs == 0 {
+            errors.push("EVENT_DEDUP_WINDOW_SECS must be at least 1".to_string());
+        }
+        if alert_cooldown_secs == 0 {
+            errors.push("ALERT_COOLDOWN_SECS must be at least 1".to_string());
+        }
+
+        if !errors.is_empty() {
+            return Err(ConfigError { errors });
+        }
+
+        Ok(Self {
+            mongodb_uri,
+            mongodb_connect_attempts,
+            mongo_max_pool_size,
+            mongo_min_pool_size,
+            mongo_connect_timeout_secs,
+            mongo_server_selection_timeout_secs,
+            redis_url,
+            cache_backend,
+            database_name,
+            host,
+            port,
+            coingecko_api_url,
+            coingecko_api_key,
+            coingecko_api_plan,
+            http_user_agent,
+            binance_fallback,
+            binance_api_url,
+            token_cache_ttl_secs,
+            history_cache_ttl_secs,
+            max_history_days,
+            token_detail_max_age_secs,
+            force_fresh_interval_secs,
+            profile_cache_ttl_secs,
+            memory_cache_ttl_secs,
+            history_prune_interval_secs,
+            history_backfill_interval_secs,
+            startup_warmup,
+            min_request_interval_secs,
+            rate_limit_backoff_secs,
+            admin_token: get("ADMIN_TOKEN"),
+            api_token: get("API_TOKEN"),
+            shutdown_grace_secs,
+            request_timeout_secs,
+            upstream_timeout_secs,
+            max_concurrent_upstream,
+            top_tokens_limit,
+            page_fetch_parallelism,
+            large_move_percent,
+            event_dedup_window_secs,
+            alert_cooldown_secs,
+            debug_endpoints,
+            log_format,
+        })
+    }
+
+    // Sensible local defaults for tests and the test app factory
+    pub fn default_for_tests() -> Self {
+        Self {
+            mongodb_uri: "mongodb://localhost:27017".to_string(),
+            mongodb_connect_attempts: DEFAULT_MONGODB_CONNECT_ATTEMPTS,
+            mongo_max_pool_size: DEFAULT_MONGO_MAX_POOL_SIZE,
+            mongo_min_pool_size: DEFAULT_MONGO_MIN_POOL_SIZE,
+            mongo_connect_timeout_secs: DEFAULT_MONGO_CONNECT_TIMEOUT_SECS,
+            mongo_server_selection_timeout_secs: DEFAULT_MONGO_SERVER_SELECTION_TIMEOUT_SECS,
+            redis_url: None,
+            cache_backend: CacheBackend::Memory,
+            database_name: "crypto_tracker_test".to_string(),
+            host: DEFAULT_HOST.to_string(),
+            port: DEFAULT_PORT,
+            coingecko_api_url: DEFAULT_COINGECKO_API_URL.to_string(),
+            coingecko_api_key: None,
+            coingecko_api_plan: ApiPlan::default(),
+            http_user_agent: DEFAULT_USER_AGENT.to_string(),
+            binance_fallback: true,
+            binance_api_url: DEFAULT_BINANCE_API_URL.to_string(),
+            token_cache_ttl_secs: DEFAULT_TOKEN_CACHE_TTL_SECS,
+            history_cache_ttl_secs: DEFAULT_HISTORY_CACHE_TTL_SECS,
+            max_history_days: MAX_HISTORY_DAYS,
+            token_detail_max_age_secs: DEFAULT_TOKEN_DETAIL_MAX_AGE_SECS,
+            force_fresh_interval_secs: DEFAULT_FORCE_FRESH_INTERVAL_SECS,
+            profile_cache_ttl_secs: DEFAULT_PROFILE_CACHE_TTL_SECS,
+            memory_cache_ttl_secs: DEFAULT_MEMORY_CACHE_TTL_SECS,
+            history_prune_interval_secs: DEFAULT_HISTORY_PRUNE_INTERVAL_SECS,
+            history_backfill_interval_secs: DEFAULT_HISTORY_BACKFILL_INTERVAL_SECS,
+            startup_warmup: true,
+            min_request_interval_secs: DEFAULT_MIN_REQUEST_INTERVAL_SECS,
+            rate_limit_backoff_secs: DEFAULT_RATE_LIMIT_BACKOFF_SECS,
+            admin_token: None,
+            api_token: None,
+            shutdown_grace_secs: DEFAULT_SHUTDOWN_GRACE_SECS,
+            request_timeout_secs: DEFAULT_REQUEST_TIMEOUT_SECS,
+            upstream_timeout_secs: DEFAULT_UPSTREAM_TIMEOUT_SECS,
+            max_concurrent_upstream: DEFAULT_MAX_CONCURRENT_UPSTREAM,
+            top_tokens_limit: DEFAULT_TOP_TOKENS_LIMIT,
+            page_fetch_parallelism: DEFAULT_PAGE_FETCH_PARALLELISM,
+            large_move_percent: DEFAULT_LARGE_MOVE_PERCENT,
+            event_dedup_window_secs: DEFAULT_EVENT_DEDUP_WINDOW_SECS,
+            alert_cooldown_secs: DEFAULT_ALERT_COOLDOWN_SECS,
+            debug_endpoints: false,
+            log_format: LogFormat::Text,
+        }
+    }
+
+    pub fn bind_address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+fn parse_or<T, G>(get: &G, key: &str, default: T, errors: &mut Vec<String>) -> T
+where
+    T: std::str::FromStr,
+    G: Fn(&str) -> Option<String>,
+{
+    match get(key) {
+        Some(raw) => raw.parse().unwrap_or_else(|_| {
+            errors.push(format!("{} has an invalid value '{}'", key, raw));
+            default
+        }),
+        None => default,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn load(vars: &[(&str, &str)]) -> Result<Config, ConfigError> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Config::from_lookup(|key| vars.get(key).cloned())
+    }
+
+    #[test]
+    fn test_defaults_applied_for_optional_values() {
+        let config = load(&[
+            ("MONGODB_URI", "mongodb://localhost:27017"),
+            ("DATABASE_NAME", "crypto_tracker_db"),
+        ])
+        .expect("config should load");
+
+        assert_eq!(config.host, "127.0.0.1");
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.coingecko_api_url, "https://api.coingecko.com/api/v3");
+        assert_eq!(config.min_request_interval_secs, 2.0);
+        assert_eq!(config.coingecko_api_plan, ApiPlan::Demo);
+        assert_eq!(config.rate_limit_backoff_secs, 60);
+        assert!(config.coingecko_api_key.is_none());
+        assert!(config.admin_token.is_none());
+        assert_eq!(config.shutdown_grace_secs, 10);
+        assert_eq!(config.request_timeout_secs, 20);
+        assert_eq!(config.upstream_timeout_secs, 8);
+        assert_eq!(config.max_concurrent_upstream, 4);
+        assert_eq!(config.page_fetch_parallelism, 2);
+        assert_eq!(config.top_tokens_limit, 100);
+        assert_eq!(config.large_move_percent, 10.0);
+        assert_eq!(config.event_dedup_window_secs, 3600);
+        assert_eq!(config.alert_cooldown_secs, 86400);
+        assert_eq!(config.mongodb_connect_attempts, 5);
+        assert_eq!((config.mongo_min_pool_size, config.mongo_max_pool_size), (0, 20));
+        assert_eq!(config.mongo_connect_timeout_secs, 10);
+        assert_eq!(config.mongo_server_selection_timeout_secs, 10);
+        assert!(!config.debug_endpoints);
+        assert!(config.redis_url.is_none());
+        assert_eq!(config.cache_backend, CacheBackend::Memory);
+        assert!(config.binance_fallback);
+        assert_eq!(config.binance_api_url, "https://api.binance.com");
+        assert_eq!(config.token_detail_max_age_secs, 300);
+        assert_eq!(config.max_history_days, 365);
+        assert_eq!(config.force_fresh_interval_secs, 30);
+        assert_eq!(config.profile_cache_ttl_secs, 604800);
+        assert_eq!(config.memory_cache_ttl_secs, 10);
+        assert_eq!(config.history_prune_interval_secs, 3600);
+        assert_eq!(config.history_backfill_interval_secs, 120);
+        assert!(config.startup_warmup);
+        assert_eq!(config.bind_address(), "127.0.0.1:8080");
+    }
+
+    #[test]
+    fn test_missing_required_values_are_all_reported() {
+        let err = load(&[]).unwrap_err();
+
+        assert_eq!(err.errors.len(), 2);
+        let message = err.to_string();
+        assert!(message.contains("MONGODB_URI"));
+        assert!(message.contains("DATABASE_NAME"));
+    }
+
+    #[test]
+    fn test_invalid_port_is_rejected() {
+        let err = load(&[
+            ("MONGODB_URI", "mongodb://localhost:27017"),
+            ("DATABASE_NAME", "db"),
+            ("SERVER_PORT", "99999"),
+        ])
+        .unwrap_err();
+
+        assert_eq!(err.errors.len(), 1);
+        assert!(err.errors[0].contains("SERVER_PORT"));
+    }
+
+    #[test]
+    fn test_malformed_urls_are_rejected() {
+        let err = load(&[
+            ("MONGODB_URI", "localhost:27017"),
+            ("DATABASE_NAME", "db"),
+            ("COINGECKO_API_URL", "not a url"),
+            ("REDIS_URL", "localhost:6379"),
+        ])
+        .unwrap_err();
+
+        assert_eq!(err.errors.len(), 3);
+        assert!(err.errors.iter().any(|e| e.contains("MONGODB_URI")));
+        assert!(err.errors.iter().any(|e| e.contains("COINGECKO_API_URL")));
+        assert!(err.errors.iter().any(|e| e.contains("REDIS_URL")));
+    }
+
+    #[test]
+    fn test_optional_secrets_and_overrides_are_read() {
+        let config = load(&[
+            ("MONGODB_URI", "mongodb+srv://cluster.example.net"),
+            ("DATABASE_NAME", "db"),
+            ("SERVER_PORT", "9000"),
+            ("COINGECKO_API_URL", "https://pro-api.coingecko.com/api/v3/"),
+            ("COINGECKO_API_KEY", "secret"),
+            ("ADMIN_TOKEN", "admin"),
+            ("MIN_REQUEST_INTERVAL_SECS", "1"),
+            ("TOKEN_CACHE_TTL_SECS", "30"),
+            ("DEBUG_ENDPOINTS", "true"),
+            ("REDIS_URL", "redis://cache:6379/0"),
+        ])
+        .expect("config should load");
+
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.coingecko_api_url, "https://pro-api.coingecko.com/api/v3");
+        assert_eq!(config.coingecko_api_key.as_deref(), Some("secret"));
+        assert_eq!(config.admin_token.as_deref(), Some("admin"));
+        assert_eq!(config.min_request_interval_secs, 1.0);
+        assert_eq!(config.token_cache_ttl_secs, 30);
+        assert!(config.debug_endpoints);
+        assert_eq!(config.redis_url.as_deref(), Some("redis://cache:6379/0"));
+        assert_eq!(config.cache_backend, CacheBackend::Redis);
+    }
+
+    #[test]
+    fn test_cache_backend_selection() {
+        let base = [("MONGODB_URI", "mongodb://localhost:27017"), ("DATABASE_NAME", "db")];
+        let with = |extra: &[(&str, &str)]| load(&[&base[..], extra].concat());
+
+        let config = with(&[("REDIS_URL", "redis://cache:6379"), ("CACHE_BACKEND", "memory")]).unwrap();
+        assert_eq!(config.cache_backend, CacheBackend::Memory);
+
+        let err = with(&[("CACHE_BACKEND", "redis")]).unwrap_err();
+        assert!(err.errors.iter().any(|e| e.contains("requires REDIS_URL")));
+
+        let err = with(&[("CACHE_BACKEND", "memcached")]).unwrap_err();
+        assert!(err.errors.iter().any(|e| e.contains("CACHE_BACKEND")));
+    }
+
+    #[test]
+    fn test_log_format_selection() {
+        let base = [("MONGODB_URI", "mongodb://localhost:27017"), ("DATABASE_NAME", "db")];
+        let with = |extra: &[(&str, &str)]| load(&[&base[..], extra].concat());
+
+        assert_eq!(with(&[]).unwrap().log_format, LogFormat::Text);
+        assert_eq!(with(&[("LOG_FORMAT", "json")]).unwrap().log_format, LogFormat::Json);
+
+        let err = with(&[("LOG_FORMAT", "xml")]).unwrap_err();
+        assert!(err.errors.iter().any(|e| e.contains("LOG_FORMAT")));
+    }
+
+    #[test]
+    fn test_blank_values_are_treated_as_unset() {
+        let config = load(&[
+            ("MONGODB_URI", "mongodb://localhost:27017"),
+            ("DATABASE_NAME", "db"),
+            ("COINGECKO_API_KEY", "   "),
+            ("SERVER_PORT", ""),
+        ])
+        .expect("config should load");
+
+        assert!(config.coingecko_api_key.is_none());
+        assert_eq!(config.port, 8080);
+    }
+
+    #[test]
+    fn test_pro_key_switches_host_and_lowers_interval() {
+        let config = load(&[
+            ("MONGODB_URI", "mongodb://localhost:27017"),
+            ("DATABASE_NAME", "db"),
+            ("COINGECKO_API_KEY", "secret"),
+            ("COINGECKO_API_PLAN", "Pro"),
+        ])
+        .expect("config should load");
+
+        assert_eq!(config.coingecko_api_plan, ApiPlan::Pro);
+        assert_eq!(config.coingecko_api_url, "https://pro-api.coingecko.com/api/v3");
+        assert_eq!(config.min_request_interval_secs, 0.2);
+    }
+
+    #[test]
+    fn test_fractional_interval_and_bad_plan() {
+        let config = load(&[
+            ("MONGODB_URI", "mongodb://localhost:27017"),
+            ("DATABASE_NAME", "db"),
+            ("MIN_REQUEST_INTERVAL_SECS", "0.5"),
+        ])
+        .expect("config should load");
+        assert_eq!(config.min_request_interval_secs, 0.5);
+
+        let err = load(&[
+            ("MONGODB_URI", "mongodb://localhost:27017"),
+            ("DATABASE_NAME", "db"),
+            ("COINGECKO_API_PLAN", "enterprise"),
+            ("MIN_REQUEST_INTERVAL_SECS", "-1"),
+        ])
+        .unwrap_err();
+        assert_eq!(err.errors.len(), 2);
+    }
+
+    #[test]
+    fn test_zero_request_timeout_is_rejected() {
+        let err = load(&[
+            ("MONGODB_URI", "mongodb://localhost:27017"),
+            ("DATABASE_NAME", "db"),
+            ("REQUEST_TIMEOUT_SECS", "0"),
+        ])
+        .unwrap_err();
+
+        assert_eq!(err.errors.len(), 1);
+        assert!(err.errors[0].contains("REQUEST_TIMEOUT_SECS"));
+    }
+
+    #[test]
+    fn test_top_tokens_limit_is_bounded() {
+        let base = [("MONGODB_URI", "mongodb://localhost:27017"), ("DATABASE_NAME", "db")];
+        let config = load(&[&base[..], &[("TOP_TOKENS_LIMIT", "250")]].concat()).unwrap();
+        assert_eq!(config.top_tokens_limit, 250);
+
+        for limit in ["0", "251"] {
+            let err = load(&[&base[..], &[("TOP_TOKENS_LIMIT", limit)]].concat()).unwrap_err();
+            assert_eq!(err.errors.len(), 1, "{}", limit);
+            assert!(err.errors[0].contains("TOP_TOKENS_LIMIT"), "{}", limit);
+        }
+    }
+
+    #[test]
+    fn test_upstream_limits_must_be_positive() {
+        let err = load(&[
+            ("MONGODB_URI", "mongodb://localhost:27017"),
+            ("DATABASE_NAME", "db"),
+            ("UPSTREAM_TIMEOUT_SECS", "0"),
+            ("MAX_CONCURRENT_UPSTREAM", "0"),
+            ("PAGE_FETCH_PARALLELISM", "0"),
+        ])
+        .unwrap_err();
+
+        assert_eq!(err.errors.len(), 3);
+        assert!(err.to_string().contains("UPSTREAM_TIMEOUT_SECS"));
+        assert!(err.to_string().contains("MAX_CONCURRENT_UPSTREAM"));
+        assert!(err.to_string().contains("PAGE_FETCH_PARALLELISM"));
+    }
+
+    #[test]
+    fn test_event_settings_are_checked() {
+        let base = [("MONGODB_URI", "mongodb://localhost:27017"), ("DATABASE_NAME", "db")];
+
+        let config = load(&[&base[..], &[("LARGE_MOVE_PERCENT", "2.5"), ("EVENT_DEDUP_WINDOW_SECS", "600"), ("ALERT_COOLDOWN_SECS", "60")]].concat()).unwrap();
+        assert_eq!(config.large_move_percent, 2.5);
+        assert_eq!(config.event_dedup_window_secs, 600);
+        assert_eq!(config.alert_cooldown_secs, 60);
+
+        let err = load(&[&base[..], &[("LARGE_MOVE_PERCENT", "0"), ("EVENT_DEDUP_WINDOW_SECS", "0"), ("ALERT_COOLDOWN_SECS", "0")]].concat()).unwrap_err();
+        assert_eq!(err.errors.len(), 3);
+        assert!(err.to_string().contains("LARGE_MOVE_PERCENT"));
+        assert!(err.to_string().contains("EVENT_DEDUP_WINDOW_SECS"));
+        assert!(err.to_string().contains("ALERT_COOLDOWN_SECS"));
+    }
+
+    #[test]
+    fn test_max_history_days_can_only_tighten() {
+        let base = [("MONGODB_URI", "mongodb://localhost:27017"), ("DATABASE_NAME", "db")];
+
+        let config = load(&[&base[..], &[("MAX_HISTORY_DAYS", "90")]].concat()).unwrap();
+        assert_eq!(config.max_history_days, 90);
+
+        for days in ["0", "366"] {
+            let err = load(&[&base[..], &[("MAX_HISTORY_DAYS", days)]].concat()).unwrap_err();
+            assert!(err.errors[0].contains("MAX_HISTORY_DAYS"), "{}", days);
+        }
+    }
+
+    #[test]
+    fn test_mongodb_connect_attempts_must_be_positive() {
+        let base = [("MONGODB_URI", "mongodb://localhost:27017"), ("DATABASE_NAME", "db")];
+
+        let config = load(&[&base[..], &[("MONGODB_CONNECT_ATTEMPTS", "10")]].concat()).unwrap();
+        assert_eq!(config.mongodb_connect_attempts, 10);
+
+        let err = load(&[&base[..], &[("MONGODB_CONNECT_ATTEMPTS", "0")]].concat()).unwrap_err();
+        assert_eq!(err.errors.len(), 1);
+        assert!(err.errors[0].contains("MONGODB_CONNECT_ATTEMPTS"));
+    }
+
+    #[test]
+    fn test_mongo_pool_settings_are_checked() {
+        let base = [("MONGODB_URI", "mongodb://localhost:27017"), ("DATABASE_NAME", "db")];
+
+        let config = load(&[&base[..], &[("MONGO_MAX_POOL_SIZE", "50"), ("MONGO_MIN_POOL_SIZE", "5")]].concat()).unwrap();
+        assert_eq!((config.mongo_min_pool_size, config.mongo_max_pool_size), (5, 50));
+
+        let err = load(&[&base[..], &[("MONGO_MAX_POOL_SIZE", "4"), ("MONGO_MIN_POOL_SIZE", "8")]].concat()).unwrap_err();
+        assert!(err.errors.iter().any(|e| e.contains("MONGO_MIN_POOL_SIZE")));
+
+        let err = load(&[
+            &base[..],
+            &[
+                ("MONGO_MAX_POOL_SIZE", "0"),
+                ("MONGO_CONNECT_TIMEOUT_SECS", "0"),
+                ("MONGO_SERVER_SELECTION_TIMEOUT_SECS", "soon"),
+            ],
+        ]
+        .concat())
+        .unwrap_err();
+        assert_eq!(err.errors.len(), 3);
+    }
+
+    #[test]
+    fn test_user_agent_with_optional_contact_email() {
+        let base = [("MONGODB_URI", "mongodb://localhost:27017"), ("DATABASE_NAME", "db")];
+
+        assert_eq!(load(&base).unwrap().http_user_agent, DEFAULT_USER_AGENT);
+        let config = load(&[&base[..], &[("HTTP_USER_AGENT", "DexTracker/2.1")]].concat()).unwrap();
+        assert_eq!(config.http_user_agent, "DexTracker/2.1");
+        let config = load(&[&base[..], &[("HTTP_USER_AGENT", "DexTracker/2.1"), ("HTTP_CONTACT_EMAIL", "ops@example.com")]].concat()).unwrap();
+        assert_eq!(config.http_user_agent, "DexTracker/2.1 (+mailto:ops@example.com)");
+        let config = load(&[&base[..], &[("HTTP_CONTACT_EMAIL", "ops@example.com")]].concat()).unwrap();
+        assert_eq!(config.http_user_agent, format!("{} (+mailto:ops@example.com)", DEFAULT_USER_AGENT));
+
+        let err = load(&[&base[..], &[("HTTP_USER_AGENT", "Dex\u{7f}Tracker"), ("HTTP_CONTACT_EMAIL", "ops at example")]].concat())
+            .unwrap_err();
+        assert_eq!(err.errors.len(), 2);
+        assert!(err.to_string().contains("HTTP_USER_AGENT"));
+        assert!(err.to_string().contains("HTTP_CONTACT_EMAIL"));
+    }
+}
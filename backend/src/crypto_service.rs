@@ -1,193 +1,640 @@
-use reqwest::Client;
-use crate::models::{CoinGeckoMarket, CoinGeckoHistoricalData, CryptoToken};
-use chrono::Utc;
+use reqwest::{Client, StatusCode};
+use reqwest::header::{HeaderMap, HeaderValue};
+use futures::stream::{self, StreamExt};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+use tracing::Instrument;
+use crate::config::Config;
+use crate::models::{Category, CoinGeckoCoin, CoinGeckoExchangeRates, ExchangeRates, CoinGeckoMarket, CoinGeckoSearch, CoinProfile, CoinSearchResult, CoinGeckoHistoricalData, CryptoToken, HistoryDays, HistoryInterval, PriceSource};
+use chrono::{DateTime, Utc};
+
+// CoinGecko subscription tier; decides which header carries the key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ApiPlan {
+    #[default]
+    Demo,
+    Pro,
+}
+
+impl ApiPlan {
+    pub fn header_name(&self) -> &'static str {
+        match self {
+            ApiPlan::Demo => "x-cg-demo-api-key",
+            ApiPlan::Pro => "x-cg-pro-api-key",
+        }
+    }
+}
+
+impl FromStr for ApiPlan {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "demo" => Ok(ApiPlan::Demo),
+            "pro" => Ok(ApiPlan::Pro),
+            other => Err(format!("unknown CoinGecko plan '{}' (expected demo or pro)", other)),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ApiKey {
+    pub key: String,
+    pub plan: ApiPlan,
+}
+
+// Keep the key itself out of logs
+impl fmt::Debug for ApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ApiKey").field("key", &"***").field("plan", &self.plan).finish()
+    }
+}
+
+// CoinGecko rejects larger pages on /coins/markets
+const MAX_PER_PAGE: u32 = 250;
+const DEFAULT_PAGE_DELAY: Duration = Duration::from_secs(2);
+const DEFAULT_PAGE_PARALLELISM: usize = 2;
+// Matches from /search whose market data is fetched
+const MAX_SEARCH_RESULTS: usize = 10;
+
+// Why a CoinGecko call failed. Handlers match on this to tell rate limiting (back off
+// and serve the cache) from a token that doesn't exist (404) and everything else.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CryptoServiceError {
+    // 429 Too Many Requests
+    RateLimited,
+    // Any other non-success status
+    Http(StatusCode),
+    Timeout,
+    // The body wasn't the JSON we expected
+    Parse(String),
+    // No response at all: connection refused, DNS, TLS and the like
+    Transport(String),
+    // CoinGecko doesn't know the token
+    NotFound,
+    // Too many upstream calls already in flight; nothing was sent (see crate::upstream)
+    Busy,
+}
+
+impl CryptoServiceError {
+    fn from_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::TOO_MANY_REQUESTS => CryptoServiceError::RateLimited,
+            status => CryptoServiceError::Http(status),
+        }
+    }
+
+    // The upstream HTTP status behind the error, when there was a response
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            CryptoServiceError::RateLimited => Some(StatusCode::TOO_MANY_REQUESTS.as_u16()),
+            CryptoServiceError::Http(status) => Some(status.as_u16()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CryptoServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CryptoServiceError::RateLimited => write!(f, "API returned error: {}", StatusCode::TOO_MANY_REQUESTS),
+            CryptoServiceError::Http(status) => write!(f, "API returned error: {}", status),
+            CryptoServiceError::Timeout => write!(f, "request to CoinGecko timed out"),
+            CryptoServiceError::Parse(message) => write!(f, "Failed to parse API response: {}", message),
+            CryptoServiceError::Transport(message) => write!(f, "request to CoinGecko failed: {}", message),
+            CryptoServiceError::NotFound => write!(f, "Token not found"),
+            CryptoServiceError::Busy => write!(f, "too many CoinGecko requests in flight"),
+        }
+    }
+}
+
+impl std::error::Error for CryptoServiceError {}
+
+impl From<reqwest::Error> for CryptoServiceError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            CryptoServiceError::Timeout
+        } else if e.is_decode() {
+            CryptoServiceError::Parse(e.to_string())
+        } else if let Some(status) = e.status() {
+            CryptoServiceError::from_status(status)
+        } else {
+            CryptoServiceError::Transport(e.to_string())
+        }
+    }
+}
+
+// A page that failed after earlier pages succeeded
+#[derive(Debug, Clone)]
+pub struct PageError {
+    pub page: u32,
+    pub error: CryptoServiceError,
+}
+
+impl fmt::Display for PageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "page {} failed: {}", self.page, self.error)
+    }
+}
+
+impl std::error::Error for PageError {}
+
+// Tokens in market cap order; `partial` is set when paging stopped on an error
+#[derive(Debug)]
+pub struct TopTokens {
+    pub tokens: Vec<CryptoToken>,
+    pub partial: Option<PageError>,
+}
+
+impl TopTokens {
+    pub fn is_partial(&self) -> bool {
+        self.partial.is_some()
+    }
+}
+
+// CoinGecko's RFC3339 `last_updated`, or now when it's missing or unreadable
+fn upstream_timestamp(value: Option<&str>) -> DateTime<Utc> {
+    value
+        .and_then(|v| DateTime::parse_from_rfc3339(v).ok())
+        .map(|t| t.with_timezone(&Utc))
+        .unwrap_or_else(Utc::now)
+}
+
+// Change windows requested from /coins/markets; each beyond 24h comes back as a
+// `price_change_percentage_<window>_in_currency` field
+const PRICE_CHANGE_WINDOWS: &str = "24h,7d,30d,1y";
+
+// A /coins/markets body, with how many entries it held. Only a body that isn't a JSON
+// array is an error: an entry that doesn't read as a CoinGeckoMarket is logged and
+// skipped so the rest still get served.
+fn parse_markets(text: &str) -> Result<(Vec<CoinGeckoMarket>, usize), serde_json::Error> {
+    let entries: Vec<serde_json::Value> = serde_json::from_str(text)?;
+    let count = entries.len();
+    let markets = entries
+        .into_iter()
+        .filter_map(|entry| {
+            let id = entry.get("id").and_then(|id| id.as_str()).unwrap_or("?").to_string();
+            serde_json::from_value(entry)
+                .map_err(|e| tracing::warn!(token_id = %id, error = %e, "Skipping malformed CoinGecko market entry"))
+                .ok()
+        })
+        .collect();
+    Ok((markets, count))
+}
+
+fn market_to_token(market: CoinGeckoMarket) -> CryptoToken {
+    CryptoToken {
+        id: None,
+        token_id: market.id,
+        symbol: market.symbol,
+        name: market.name,
+        current_price: market.current_price,
+        market_cap: market.market_cap,
+        volume_24h: market.total_volume,
+        price_change_24h: market.price_change_24h.unwrap_or(0.0),
+        price_change_percentage_24h: market.price_change_percentage_24h.unwrap_or(0.0),
+        price_change_percentage_7d: market.price_change_percentage_7d_in_currency,
+        price_change_percentage_30d: market.price_change_percentage_30d_in_currency,
+        price_change_percentage_1y: market.price_change_percentage_1y_in_currency,
+        sparkline_7d: market.sparkline_in_7d.map(|sparkline| sparkline.price),
+        high_24h: market.high_24h,
+        low_24h: market.low_24h,
+        circulating_supply: market.circulating_supply,
+        total_supply: market.total_supply,
+        max_supply: market.max_supply,
+        fully_diluted_valuation: market.fully_diluted_valuation,
+        ath: market.ath,
+        ath_change_percentage: market.ath_change_percentage,
+        atl: market.atl,
+        atl_change_percentage: market.atl_change_percentage,
+        image: (!market.image.is_empty()).then_some(market.image),
+        last_updated: upstream_timestamp(market.last_updated.as_deref()),
+        fetched_at: Some(Utc::now()),
+        is_favorite: false,
+        hidden: false,
+        tags: Vec::new(),
+        note: None,
+        price_source: Some(PriceSource::CoinGecko),
+        category: None,
+    }
+}
 
 #[derive(Clone)]
 pub struct CryptoService {
     client: Client,
     base_url: String,
+    api_key: Option<ApiKey>,
+    page_delay: Duration,
+    page_parallelism: usize,
 }
 
+// Sent to CoinGecko unless HTTP_USER_AGENT says otherwise
+pub const DEFAULT_USER_AGENT: &str = "CryptoTracker/1.0 (Educational Project)";
+
 impl CryptoService {
-    pub fn new(base_url: String) -> Self {
+    pub fn new(base_url: String, api_key: Option<ApiKey>) -> Self {
+        Self::with_user_agent(base_url, api_key, DEFAULT_USER_AGENT)
+    }
+
+    fn with_user_agent(base_url: String, api_key: Option<ApiKey>, user_agent: &str) -> Self {
+        // Default headers ride along on every request built from this client
+        let mut headers = HeaderMap::new();
+        if let Some(api_key) = &api_key {
+            match HeaderValue::from_str(&api_key.key) {
+                Ok(mut value) => {
+                    value.set_sensitive(true);
+                    headers.insert(api_key.plan.header_name(), value);
+                }
+                Err(_) => tracing::error!("CoinGecko API key contains invalid characters, sending requests without it"),
+            }
+        }
+
         let client = Client::builder()
-            .user_agent("CryptoTracker/1.0 (Educational Project)")
-            .timeout(std::time::Duration::from_secs(15))
+            .user_agent(user_agent)
+            .default_headers(headers)
+            .timeout(Duration::from_secs(15))
             .build()
             .unwrap_or_else(|_| Client::new());
             
         Self {
             client,
             base_url,
+            api_key,
+            page_delay: DEFAULT_PAGE_DELAY,
+            page_parallelism: DEFAULT_PAGE_PARALLELISM,
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        let api_key = config.coingecko_api_key.clone().map(|key| ApiKey {
+            key,
+            plan: config.coingecko_api_plan,
+        });
+        Self::with_user_agent(config.coingecko_api_url.clone(), api_key, &config.http_user_agent)
+            .with_page_delay(Duration::from_secs_f64(config.min_request_interval_secs))
+            .with_page_parallelism(config.page_fetch_parallelism)
+    }
+
+    pub fn api_plan(&self) -> Option<ApiPlan> {
+        self.api_key.as_ref().map(|k| k.plan)
+    }
+
+    // Delay between consecutive page requests, keeps paging under the upstream rate limit
+    pub fn with_page_delay(mut self, page_delay: Duration) -> Self {
+        self.page_delay = page_delay;
+        self
+    }
+
+    // Pages fetched at once when an API key's higher limits allow it; at least 1
+    pub fn with_page_parallelism(mut self, page_parallelism: usize) -> Self {
+        self.page_parallelism = page_parallelism.max(1);
+        self
+    }
+
+    // Sends one upstream request inside a `coingecko` span recording the URL path,
+    // status and latency. The query string stays out of it in case it ever carries a key.
+    async fn send(&self, request: reqwest::RequestBuilder) -> reqwest::Result<reqwest::Response> {
+        let request = request.build()?;
+        let span = tracing::info_span!(
+            "coingecko",
+            path = %request.url().path(),
+            status = tracing::field::Empty,
+            latency_ms = tracing::field::Empty,
+        );
+
+        async {
+            let started = Instant::now();
+            let result = self.client.execute(request).await;
+            let latency_ms = started.elapsed().as_millis() as u64;
+            let span = tracing::Span::current();
+            span.record("latency_ms", latency_ms);
+            match &result {
+                Ok(response) => {
+                    let status = response.status().as_u16();
+                    span.record("status", status);
+                    tracing::info!(status, latency_ms, "CoinGecko responded");
+                }
+                Err(e) => tracing::warn!(latency_ms, error = %e, "CoinGecko request failed"),
+            }
+            result
         }
+        .instrument(span)
+        .await
+    }
+
+    // Pages through /coins/markets since CoinGecko caps per_page at 250, concurrently when
+    // an API key is set. A failure on the first page is an error; a later failure returns
+    // what we have with `partial` set.
+    pub async fn fetch_top_tokens(&self, limit: u32) -> Result<TopTokens, CryptoServiceError> {
+        self.fetch_top_tokens_in(limit, None, false).await
     }
 
-    pub async fn fetch_top_tokens(&self, limit: u32) -> Result<Vec<CryptoToken>, Box<dyn std::error::Error>> {
+    // Same, restricted to one CoinGecko category (e.g. `decentralized-finance-defi`).
+    // The returned tokens carry that category, and with `sparkline` their 7-day sparkline.
+    pub async fn fetch_top_tokens_in(
+        &self,
+        limit: u32,
+        category: Option<&str>,
+        sparkline: bool,
+    ) -> Result<TopTokens, CryptoServiceError> {
+        let per_page = limit.min(MAX_PER_PAGE);
+        let pages = limit.div_ceil(MAX_PER_PAGE);
+        if pages > 1 && self.api_key.is_some() && self.page_parallelism > 1 {
+            return self.fetch_pages_concurrently(limit, per_page, pages, category, sparkline).await;
+        }
+        let mut tokens = Vec::with_capacity(limit as usize);
+
+        for page in 1..=pages {
+            if page > 1 {
+                tokio::time::sleep(self.page_delay).await;
+            }
+
+            match self.fetch_markets_page(per_page, page, category, sparkline).await {
+                Ok((batch, entries)) => {
+                    // Counted before malformed entries were dropped, so those don't end paging
+                    let short_page = entries < per_page as usize;
+                    tokens.extend(batch);
+                    if short_page {
+                        break;
+                    }
+                }
+                Err(e) if page == 1 => return Err(e),
+                Err(e) => {
+                    let error = PageError { page, error: e };
+                    tracing::warn!(page, tokens = tokens.len(), "Returning partial token list: {}", error);
+                    return Ok(TopTokens { tokens, partial: Some(error) });
+                }
+            }
+        }
+
+        tokens.truncate(limit as usize);
+        Ok(TopTokens { tokens, partial: None })
+    }
+
+    // The same paging with up to `page_parallelism` requests in flight and no delay between
+    // them. Pages are put back in order before concatenating. A failed page stops pages
+    // after it, in flight or not yet sent, while the ones before it finish, so the result
+    // is what sequential paging would have returned.
+    async fn fetch_pages_concurrently(
+        &self,
+        limit: u32,
+        per_page: u32,
+        pages: u32,
+        category: Option<&str>,
+        sparkline: bool,
+    ) -> Result<TopTokens, CryptoServiceError> {
+        let failed_at = AtomicU32::new(u32::MAX);
+        let failed_at = &failed_at;
+        let mut fetches = stream::iter(1..=pages)
+            .map(|page| async move {
+                if page > failed_at.load(Ordering::Relaxed) {
+                    return (page, None);
+                }
+                (page, Some(self.fetch_markets_page(per_page, page, category, sparkline).await))
+            })
+            .buffer_unordered(self.page_parallelism);
+
+        let mut fetched = BTreeMap::new();
+        let mut failure: Option<PageError> = None;
+        while let Some((page, result)) = fetches.next().await {
+            match result {
+                Some(Ok(batch)) => {
+                    fetched.insert(page, batch);
+                }
+                Some(Err(error)) if failure.as_ref().is_none_or(|f| page < f.page) => {
+                    failed_at.store(page, Ordering::Relaxed);
+                    failure = Some(PageError { page, error });
+                }
+                Some(Err(_)) | None => {}
+            }
+            if let Some(failure) = &failure {
+                if (1..failure.page).all(|p| fetched.contains_key(&p)) {
+                    break;
+                }
+            }
+        }
+        // Drops the requests for pages past a failure that are still in flight
+        drop(fetches);
+
+        let mut tokens = Vec::with_capacity(limit as usize);
+        let mut complete = false;
+        for (expected, (page, (batch, entries))) in (1..).zip(fetched) {
+            if page != expected {
+                break;
+            }
+            tokens.extend(batch);
+            if entries < per_page as usize || page == pages {
+                complete = true;
+                break;
+            }
+        }
+
+        match failure {
+            Some(failure) if !complete => {
+                if tokens.is_empty() {
+                    return Err(failure.error);
+                }
+                tracing::warn!(page = failure.page, tokens = tokens.len(), "Returning partial token list: {}", failure);
+                Ok(TopTokens { tokens, partial: Some(failure) })
+            }
+            _ => {
+                tokens.truncate(limit as usize);
+                Ok(TopTokens { tokens, partial: None })
+            }
+        }
+    }
+
+    async fn fetch_markets_page(
+        &self,
+        per_page: u32,
+        page: u32,
+        category: Option<&str>,
+        sparkline: bool,
+    ) -> Result<(Vec<CryptoToken>, usize), CryptoServiceError> {
         let url = format!(
-            "{}/coins/markets?vs_currency=usd&order=market_cap_desc&per_page={}&page=1&sparkline=false&price_change_percentage=24h",
-            self.base_url, limit
+            "{}/coins/markets?vs_currency=usd&order=market_cap_desc&per_page={}&page={}&sparkline={}&price_change_percentage={}",
+            self.base_url, per_page, page, sparkline, PRICE_CHANGE_WINDOWS
         );
 
-        log::info!("Fetching tokens from: {}", url);
-        
-        let response = self.client
-            .get(&url)
-            .timeout(std::time::Duration::from_secs(10))
-            .send()
-            .await?;
+        tracing::info!(page, per_page, category, "Fetching tokens from CoinGecko");
+
+        let mut request = self.client.get(&url).timeout(Duration::from_secs(10));
+        if let Some(category) = category {
+            request = request.query(&[("category", category)]);
+        }
+        let response = self.send(request).await?;
 
         let status = response.status();
         let text = response.text().await?;
-        
-        log::debug!("API Response status: {}, body length: {}", status, text.len());
-        
+
+        tracing::debug!(status = status.as_u16(), body_len = text.len(), "CoinGecko markets page received");
+
         if !status.is_success() {
-            log::error!("API error: status={}, body={}", status, text);
-            return Err(format!("API returned error: {}", status).into());
+            tracing::error!(status = status.as_u16(), body = %text, "CoinGecko API error");
+            return Err(CryptoServiceError::from_status(status));
         }
         
-        let markets: Vec<CoinGeckoMarket> = match serde_json::from_str(&text) {
+        let (markets, entries) = match parse_markets(&text) {
             Ok(m) => m,
             Err(e) => {
-                log::error!("Failed to parse API response: {}. Response: {}", e, &text[..text.len().min(500)]);
-                return Err(format!("Failed to parse API response: {}", e).into());
+                tracing::error!(error = %e, body = %&text[..text.len().min(500)], "Failed to parse CoinGecko response");
+                return Err(CryptoServiceError::Parse(e.to_string()));
             }
         };
 
         let tokens = markets
             .into_iter()
             .map(|market| CryptoToken {
-                id: None,
-                token_id: market.id,
-                symbol: market.symbol,
-                name: market.name,
-                current_price: market.current_price,
-                market_cap: market.market_cap,
-                volume_24h: market.total_volume,
-                price_change_24h: market.price_change_24h.unwrap_or(0.0),
-                price_change_percentage_24h: market.price_change_percentage_24h.unwrap_or(0.0),
-                high_24h: market.high_24h,
-                low_24h: market.low_24h,
-                circulating_supply: market.circulating_supply,
-                total_supply: market.total_supply,
-                ath: market.ath,
-                ath_change_percentage: market.ath_change_percentage,
-                atl: market.atl,
-                atl_change_percentage: market.atl_change_percentage,
-                image: Some(market.image),
-                last_updated: Utc::now(),
-                is_favorite: false,
+                category: category.map(str::to_string),
+                ..market_to_token(market)
             })
             .collect();
+        Ok((tokens, entries))
+    }
 
-        Ok(tokens)
+    // Every category id CoinGecko's markets `category` filter accepts, with its display name
+    pub async fn fetch_categories(&self) -> Result<Vec<Category>, CryptoServiceError> {
+        let url = format!("{}/coins/categories/list", self.base_url);
+        let response = self.send(self.client.get(&url)).await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(CryptoServiceError::from_status(status));
+        }
+
+        Ok(response.json().await?)
+    }
+
+    // Every currency CoinGecko quotes BTC in (fiat, crypto and commodities), stamped now
+    pub async fn fetch_exchange_rates(&self) -> Result<ExchangeRates, CryptoServiceError> {
+        let url = format!("{}/exchange_rates", self.base_url);
+        let response = self.send(self.client.get(&url)).await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(CryptoServiceError::from_status(status));
+        }
+
+        let body: CoinGeckoExchangeRates = response.json().await?;
+        Ok(ExchangeRates { rates: body.rates, fetched_at: Utc::now() })
+    }
+
+    pub async fn fetch_token_details(&self, token_id: &str) -> Result<CryptoToken, CryptoServiceError> {
+        match self.fetch_tokens_by_ids(&[token_id.to_string()]).await?.pop() {
+            Some(token) => Ok(token),
+            None => Err(CryptoServiceError::NotFound),
+        }
     }
 
-    pub async fn fetch_token_details(&self, token_id: &str) -> Result<CryptoToken, Box<dyn std::error::Error>> {
+    // Description, links, genesis date and categories; nothing market related
+    pub async fn fetch_coin_profile(&self, id: &str) -> Result<CoinProfile, CryptoServiceError> {
         let url = format!(
-            "{}/coins/markets?vs_currency=usd&ids={}&order=market_cap_desc&sparkline=false&price_change_percentage=24h",
-            self.base_url, token_id
+            "{}/coins/{}?localization=false&tickers=false&market_data=false&community_data=false&developer_data=false",
+            self.base_url, id
         );
+        let response = self.send(self.client.get(&url)).await?;
 
-        let response = self.client
-            .get(&url)
-            .send()
-            .await?;
-
-        let mut markets: Vec<CoinGeckoMarket> = response.json().await?;
-
-        if let Some(market) = markets.pop() {
-            Ok(CryptoToken {
-                id: None,
-                token_id: market.id,
-                symbol: market.symbol,
-                name: market.name,
-                current_price: market.current_price,
-                market_cap: market.market_cap,
-                volume_24h: market.total_volume,
-                price_change_24h: market.price_change_24h.unwrap_or(0.0),
-                price_change_percentage_24h: market.price_change_percentage_24h.unwrap_or(0.0),
-                high_24h: market.high_24h,
-                low_24h: market.low_24h,
-                circulating_supply: market.circulating_supply,
-                total_supply: market.total_supply,
-                ath: market.ath,
-                ath_change_percentage: market.ath_change_percentage,
-                atl: market.atl,
-                atl_change_percentage: market.atl_change_percentage,
-                image: Some(market.image),
-                last_updated: Utc::now(),
-                is_favorite: false,
-            })
-        } else {
-            Err(Box::new(std::io::Error::new(std::io::ErrorKind::NotFound, "Token not found")))
+        let status = response.status();
+        if status == StatusCode::NOT_FOUND {
+            return Err(CryptoServiceError::NotFound);
         }
+        if !status.is_success() {
+            return Err(CryptoServiceError::from_status(status));
+        }
+
+        let coin: CoinGeckoCoin = response.json().await?;
+        Ok(CoinProfile::from(coin))
+    }
+
+    // Market data for several tokens in one request; ids CoinGecko doesn't know are left out
+    pub async fn fetch_tokens_by_ids(&self, token_ids: &[String]) -> Result<Vec<CryptoToken>, CryptoServiceError> {
+        let url = format!(
+            "{}/coins/markets?vs_currency=usd&ids={}&order=market_cap_desc&sparkline=false&price_change_percentage={}",
+            self.base_url, token_ids.join(","), PRICE_CHANGE_WINDOWS
+        );
+
+        let response = self.send(self.client.get(&url)).await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(CryptoServiceError::from_status(status));
+        }
+
+        let text = response.text().await?;
+        let (markets, _) = parse_markets(&text).map_err(|e| CryptoServiceError::Parse(e.to_string()))?;
+        Ok(markets.into_iter().map(market_to_token).collect())
     }
 
     pub async fn fetch_historical_data(
         &self,
         token_id: &str,
-        days: u32,
-    ) -> Result<CoinGeckoHistoricalData, Box<dyn std::error::Error>> {
-        let url = format!(
-            "{}/coins/{}/market_chart?vs_currency=usd&days={}",
-            self.base_url, token_id, days
+        days: impl Into<HistoryDays>,
+    ) -> Result<CoinGeckoHistoricalData, CryptoServiceError> {
+        self.fetch_historical_data_in(token_id, days, "usd", None).await
+    }
+
+    // Same chart quoted in another vs_currency (see crate::currency). With an interval,
+    // CoinGecko is asked for that granularity instead of picking one from the range.
+    pub async fn fetch_historical_data_in(
+        &self,
+        token_id: &str,
+        days: impl Into<HistoryDays>,
+        currency: &str,
+        interval: Option<HistoryInterval>,
+    ) -> Result<CoinGeckoHistoricalData, CryptoServiceError> {
+        let days = days.into();
+        let mut url = format!(
+            "{}/coins/{}/market_chart?vs_currency={}&days={}",
+            self.base_url, token_id, currency, days
         );
+        if let Some(interval) = interval {
+            url.push_str(&format!("&interval={}", interval));
+        }
 
-        let response = self.client
-            .get(&url)
-            .send()
-            .await?;
+        let response = self.send(self.client.get(&url)).await?;
 
-        let data = response.json().await?;
-        Ok(data)
+        // /coins/{id}/... answers 404 for ids it doesn't know
+        let status = response.status();
+        if status == StatusCode::NOT_FOUND {
+            return Err(CryptoServiceError::NotFound);
+        }
+        if !status.is_success() {
+            return Err(CryptoServiceError::from_status(status));
+        }
+
+        Ok(response.json().await?)
     }
 
-    pub async fn search_tokens(&self, query: &str) -> Result<Vec<CryptoToken>, Box<dyn std::error::Error>> {
-        let url = format!(
-            "{}/coins/markets?vs_currency=usd&order=market_cap_desc&per_page=50&page=1&sparkline=false",
-            self.base_url
-        );
+    // CoinGecko's own search over every coin it lists, best match first, at most
+    // MAX_SEARCH_RESULTS of them
+    pub async fn search_coins(&self, query: &str) -> Result<Vec<CoinSearchResult>, CryptoServiceError> {
+        let url = format!("{}/search", self.base_url);
+        let response = self.send(self.client.get(&url).query(&[("query", query)])).await?;
 
-        let response = self.client
-            .get(&url)
-            .send()
-            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(CryptoServiceError::from_status(status));
+        }
 
-        let markets: Vec<CoinGeckoMarket> = response.json().await?;
+        let mut found: CoinGeckoSearch = response.json().await?;
+        found.coins.truncate(MAX_SEARCH_RESULTS);
+        Ok(found.coins)
+    }
 
-        let query_lower = query.to_lowercase();
-        let tokens: Vec<CryptoToken> = markets
-            .into_iter()
-            .filter(|market| {
-                market.name.to_lowercase().contains(&query_lower)
-                    || market.symbol.to_lowercase().contains(&query_lower)
-                    || market.id.to_lowercase().contains(&query_lower)
-            })
-            .map(|market| CryptoToken {
-                id: None,
-                token_id: market.id,
-                symbol: market.symbol,
-                name: market.name,
-                current_price: market.current_price,
-                market_cap: market.market_cap,
-                volume_24h: market.total_volume,
-                price_change_24h: market.price_change_24h.unwrap_or(0.0),
-                price_change_percentage_24h: market.price_change_percentage_24h.unwrap_or(0.0),
-                high_24h: market.high_24h,
-                low_24h: market.low_24h,
-                circulating_supply: market.circulating_supply,
-                total_supply: market.total_supply,
-                ath: market.ath,
-                ath_change_percentage: market.ath_change_percentage,
-                atl: market.atl,
-                atl_change_percentage: market.atl_change_percentage,
-                image: Some(market.image),
-                last_updated: Utc::now(),
-                is_favorite: false,
-            })
-            .collect();
+    // search_coins with market data, for coins outside the cached list: their markets come
+    // from /coins/markets, after the usual pause between calls. Results keep CoinGecko's ranking.
+    pub async fn search_tokens(&self, query: &str) -> Result<Vec<CryptoToken>, CryptoServiceError> {
+        let ids: Vec<String> = self.search_coins(query).await?.into_iter().map(|coin| coin.id).collect();
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
 
+        tokio::time::sleep(self.page_delay).await;
+        let mut tokens = self.fetch_tokens_by_ids(&ids).await?;
+        tokens.sort_by_key(|token| ids.iter().position(|id| *id == token.token_id));
         Ok(tokens)
     }
 }
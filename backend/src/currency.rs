@@ -0,0 +1,50 @@
+// Quote currencies accepted wherever a `currency` parameter is taken, served as-is by
+// /api/currencies. A subset of CoinGecko's /simple/supported_vs_currencies, kept static
+// so validation never depends on an upstream call; extend both together.
+pub const SUPPORTED_CURRENCIES: &[&str] = &[
+    // Fiat
+    "usd", "aed", "ars", "aud", "bdt", "bhd", "bmd", "brl", "cad", "chf", "clp", "cny", "czk", "dkk", "eur",
+    "gbp", "gel", "hkd", "huf", "idr", "ils", "inr", "jpy", "krw", "kwd", "lkr", "mmk", "mxn", "myr", "ngn",
+    "nok", "nzd", "php", "pkr", "pln", "rub", "sar", "sek", "sgd", "thb", "try", "twd", "uah", "vnd", "zar",
+    // Crypto and commodities
+    "btc", "eth", "ltc", "bch", "bnb", "xrp", "xlm", "link", "dot", "sol", "bits", "sats", "xag", "xau", "xdr",
+];
+
+// What everything is priced in unless asked otherwise
+pub const DEFAULT_CURRENCY: &str = "usd";
+
+// Normalizes a `currency` parameter, falling back to usd when it's absent
+pub fn parse(currency: Option<&str>) -> Result<&'static str, String> {
+    let Some(raw) = currency.map(str::trim).filter(|c| !c.is_empty()) else {
+        return Ok(DEFAULT_CURRENCY);
+    };
+    let code = raw.to_ascii_lowercase();
+    SUPPORTED_CURRENCIES
+        .iter()
+        .find(|supported| **supported == code)
+        .copied()
+        .ok_or_else(|| format!("unsupported currency '{}', see /api/currencies", raw))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_normalizes_and_defaults() {
+        assert_eq!(parse(None), Ok("usd"));
+        assert_eq!(parse(Some("")), Ok("usd"));
+        assert_eq!(parse(Some("EUR")), Ok("eur"));
+        assert_eq!(parse(Some(" btc ")), Ok("btc"));
+        assert!(parse(Some("doge")).unwrap_err().contains("/api/currencies"));
+    }
+
+    #[test]
+    fn test_list_has_no_duplicates() {
+        let mut codes = SUPPORTED_CURRENCIES.to_vec();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), SUPPORTED_CURRENCIES.len());
+        assert!(codes.contains(&DEFAULT_CURRENCY));
+    }
+}
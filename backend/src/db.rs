@@ -1,5 +1,188 @@
-use mongodb::{Client, Collection, Database};
-use crate::models::{CryptoToken, PriceHistory};
+use chrono::{DateTime, Duration, Utc};
+use futures::stream::TryStreamExt;
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::bson::oid::ObjectId;
+use mongodb::options::{ClientOptions, IndexOptions};
+use mongodb::{Client, Collection, Database, IndexModel};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use crate::analytics;
+use crate::config::Config;
+use crate::models::{USER_OWNED_FIELDS, AlertCondition, AlertScope, AlertTrigger, StoredAlert, RepairPass, RepairReport, ScreenedToken, Screener, Category, ChangeWindow, CoinProfile, ExchangeRates, StoredTransaction, CoinGeckoHistoricalData, CollectionSummary, CryptoToken, FavoritesImportMode, FavoritesImportSummary, TokenAnnotation, TokenDocuments, HistoryCacheEntry, HistoryDays, HistoryFetch, HistoryInterval, HistoryCacheStatus, PriceHistory, SymbolMapping, TokenChange, User, UserFavorite, Event, WebhookConfig, WebhookDelivery};
+
+// Name of the tokens collection's text index, which ensure_indexes creates
+pub const TOKEN_TEXT_INDEX: &str = "token_text";
+
+// Token and event timestamps are stored as RFC3339 strings, which is what the models read
+// back. A fixed precision keeps them ordered when compared as strings.
+pub fn stored_timestamp(at: DateTime<Utc>) -> String {
+    at.to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}
+
+// What users set on any of a token's duplicate copies, newest first: either flag set on
+// any copy, every tag once, and the newest non-empty note. Covers every USER_OWNED_FIELDS
+// entry.
+fn merge_user_owned_fields(copies: &[Document]) -> Document {
+    let flagged = |field: &str| copies.iter().any(|copy| copy.get_bool(field).unwrap_or(false));
+    let mut tags: Vec<&str> = Vec::new();
+    for tag in copies.iter().filter_map(|copy| copy.get_array("tags").ok()).flatten().filter_map(Bson::as_str) {
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    let mut merged = doc! { "is_favorite": flagged("is_favorite"), "hidden": flagged("hidden") };
+    if !tags.is_empty() {
+        merged.insert("tags", tags);
+    }
+    if let Some(note) = copies.iter().filter_map(|copy| copy.get_str("note").ok()).find(|note| !note.is_empty()) {
+        merged.insert("note", note);
+    }
+    merged
+}
+
+// A stored `last_updated`, whether written as a string or, by older versions, a BSON date
+fn document_timestamp(value: Option<&Bson>) -> Option<DateTime<Utc>> {
+    match value? {
+        Bson::String(s) => DateTime::parse_from_rfc3339(s).ok().map(|t| t.with_timezone(&Utc)),
+        Bson::DateTime(t) => Some(t.to_chrono()),
+        _ => None,
+    }
+}
+
+// Fields every token document is written with but older ones may lack, and what the
+// backfill repair sets when one is missing. `is_favorite` is the one CryptoToken can't
+// deserialize without.
+fn token_field_defaults() -> Document {
+    doc! {
+        "high_24h": Bson::Null,
+        "low_24h": Bson::Null,
+        "circulating_supply": Bson::Null,
+        "total_supply": Bson::Null,
+        "ath": Bson::Null,
+        "ath_change_percentage": Bson::Null,
+        "atl": Bson::Null,
+        "atl_change_percentage": Bson::Null,
+        "image": Bson::Null,
+        "is_favorite": false,
+    }
+}
+
+// How long a cached chart stays useful: short ranges move fast, long ones barely change
+pub fn history_freshness(days: u32) -> Duration {
+    match days {
+        0..=1 => Duration::hours(1),
+        2..=30 => Duration::hours(6),
+        _ => Duration::days(1),
+    }
+}
+
+// Which stored series can answer a (days, interval) chart instead of CoinGecko: one
+// fetched at least that far back at the same or a finer granularity and, with
+// `fresh_at`, not expired by then. A daily chart prefers the daily series and settles
+// for rolling up the hourly one. A single-day hourly chart is 5-minutely, finer than
+// any longer range, so only another single-day fetch covers it.
+pub fn history_coverage(
+    fetches: &[HistoryFetch],
+    days: HistoryDays,
+    interval: HistoryInterval,
+    fresh_at: Option<DateTime<Utc>>,
+) -> Option<HistoryInterval> {
+    let covers = |stored: HistoryInterval| {
+        fetches.iter().any(|fetch| {
+            fetch.interval == stored
+                && fetch.days >= days
+                && (interval == HistoryInterval::Daily || days != HistoryDays::Days(1) || fetch.days == days)
+                && fresh_at.is_none_or(|at| fetch.fetched_at + history_freshness(days.span()) > at)
+        })
+    };
+    match interval {
+        HistoryInterval::Hourly => covers(HistoryInterval::Hourly).then_some(HistoryInterval::Hourly),
+        HistoryInterval::Daily => [HistoryInterval::Daily, HistoryInterval::Hourly].into_iter().find(|&stored| covers(stored)),
+    }
+}
+
+// A token's price_history: the union of every chart fetched for it, one series per
+// granularity, and the fetches that went into them
+#[derive(Debug, Default)]
+pub struct StoredHistory {
+    pub hourly: CoinGeckoHistoricalData,
+    pub daily: CoinGeckoHistoricalData,
+    pub fetches: Vec<HistoryFetch>,
+}
+
+impl StoredHistory {
+    pub fn series(&self, interval: HistoryInterval) -> &CoinGeckoHistoricalData {
+        match interval {
+            HistoryInterval::Hourly => &self.hourly,
+            HistoryInterval::Daily => &self.daily,
+        }
+    }
+
+    fn series_mut(&mut self, interval: HistoryInterval) -> &mut CoinGeckoHistoricalData {
+        match interval {
+            HistoryInterval::Hourly => &mut self.hourly,
+            HistoryInterval::Daily => &mut self.daily,
+        }
+    }
+
+    // Merges a fetched chart into the series of its granularity; the fetch replaces an
+    // earlier one of the same range and granularity
+    pub fn merge(&mut self, fetch: HistoryFetch, data: &CoinGeckoHistoricalData) {
+        let series = self.series_mut(fetch.interval);
+        *series = analytics::merge_history(series, data);
+        self.fetches.retain(|f| (f.days, f.interval) != (fetch.days, fetch.interval));
+        self.fetches.push(fetch);
+    }
+
+    // Folds a token's documents together: the merged document, and charts cached one
+    // document per (days, interval) before it existed
+    fn from_documents(documents: &[Document]) -> Self {
+        let mut stored = StoredHistory::default();
+        for document in documents {
+            if document.contains_key("fetches") {
+                for interval in [HistoryInterval::Hourly, HistoryInterval::Daily] {
+                    if let Ok(chart) = document.get_document(interval.as_str()) {
+                        let series = stored.series_mut(interval);
+                        *series = analytics::merge_history(series, &stored_chart(chart));
+                    }
+                }
+                stored.fetches.extend(document_fetches(document));
+                continue;
+            }
+
+            let chart = stored_chart(document);
+            match document_fetches(document).pop() {
+                Some(fetch) => stored.merge(fetch, &chart),
+                // Nothing says what range it was, so it only adds points
+                None => {
+                    let interval = document.get_str("interval").ok().and_then(|i| i.parse().ok()).unwrap_or(HistoryInterval::Daily);
+                    let series = stored.series_mut(interval);
+                    *series = analytics::merge_history(series, &chart);
+                }
+            }
+        }
+        stored
+    }
+
+    // When the pruning task may drop the document: once the last of its fetches expired
+    fn expires_at(&self) -> Option<DateTime<Utc>> {
+        self.fetches.iter().map(|fetch| fetch.fetched_at + history_freshness(fetch.days.span())).max()
+    }
+}
+
+// What one batched upsert did; `failed` holds (index in the batch, reason)
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct UpsertOutcome {
+    pub inserted: u64,
+    pub updated: u64,
+    pub failed: Vec<(usize, String)>,
+}
+
+// Which end of the 24h change ranking to read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Movers {
+    Gainers,
+    Losers,
+}
 
 #[derive(Clone)]
 pub struct DbClient {
@@ -14,14 +197,1359 @@ impl DbClient {
     pub fn get_history_collection(&self) -> Collection<PriceHistory> {
         self.db.collection::<PriceHistory>("price_history")
     }
+
+    pub fn get_symbol_map_collection(&self) -> Collection<SymbolMapping> {
+        self.db.collection::<SymbolMapping>("symbol_map")
+    }
+
+    pub fn get_categories_collection(&self) -> Collection<Category> {
+        self.db.collection::<Category>("categories")
+    }
+
+    pub fn get_users_collection(&self) -> Collection<User> {
+        self.db.collection::<User>("users")
+    }
+
+    pub fn get_user_favorites_collection(&self) -> Collection<UserFavorite> {
+        self.db.collection::<UserFavorite>("user_favorites")
+    }
+
+    // Unique indexes the user collections rely on: one user per key hash, one row per
+    // (user, token) so concurrent toggles can't duplicate a favorite, one annotation
+    // per token
+    pub async fn ensure_indexes(&self) -> mongodb::error::Result<()> {
+        let unique = || IndexOptions::builder().unique(true).build();
+        self.get_users_collection()
+            .create_index(IndexModel::builder().keys(doc! { "api_key_hash": 1 }).options(unique()).build(), None)
+            .await?;
+        self.get_user_favorites_collection()
+            .create_index(
+                IndexModel::builder().keys(doc! { "user_id": 1, "token_id": 1 }).options(unique()).build(),
+                None,
+            )
+            .await?;
+        self.get_transactions_collection()
+            .create_index(IndexModel::builder().keys(doc! { "user_id": 1 }).build(), None)
+            .await?;
+        self.get_events_collection()
+            .create_index(IndexModel::builder().keys(doc! { "token_id": 1, "kind": 1, "at": -1 }).build(), None)
+            .await?;
+        self.get_annotations_collection()
+            .create_index(IndexModel::builder().keys(doc! { "token_id": 1 }).options(unique()).build(), None)
+            .await?;
+        self.get_annotations_collection()
+            .create_index(IndexModel::builder().keys(doc! { "tags": 1 }).build(), None)
+            .await?;
+        self.get_alert_triggers_collection()
+            .create_index(IndexModel::builder().keys(doc! { "alert_id": 1, "token_id": 1, "at": -1 }).build(), None)
+            .await?;
+        // Backs /api/search; a symbol match outranks a name match, which outranks the id
+        let text = IndexOptions::builder()
+            .name(TOKEN_TEXT_INDEX.to_string())
+            .weights(doc! { "symbol": 10, "name": 5, "token_id": 1 })
+            .build();
+        self.get_tokens_collection()
+            .create_index(
+                IndexModel::builder().keys(doc! { "name": "text", "symbol": "text", "token_id": "text" }).options(text).build(),
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    // Tokens matching `filter` and the words of `query` through the text index, best
+    // score first and by market cap within a score, each with its score
+    pub async fn text_search(&self, query: &str, filter: Document, limit: usize) -> mongodb::error::Result<Vec<(CryptoToken, f64)>> {
+        let options = mongodb::options::FindOptions::builder()
+            .projection(doc! { "score": { "$meta": "textScore" } })
+            .sort(doc! { "score": { "$meta": "textScore" }, "market_cap": -1, "token_id": 1 })
+            .limit(limit as i64)
+            .build();
+        let found: Vec<Document> = self
+            .get_tokens_collection()
+            .clone_with_type::<Document>()
+            .find(doc! { "$and": [{ "$text": { "$search": query } }, filter] }, options)
+            .await?
+            .try_collect()
+            .await?;
+        let mut matches = Vec::with_capacity(found.len());
+        for mut document in found {
+            let score = document.remove("score").and_then(|s| s.as_f64()).unwrap_or_default();
+            matches.push((mongodb::bson::from_document(document)?, score));
+        }
+        Ok(matches)
+    }
+
+    // Counts everything stored under `token_id`, and with `delete` removes it. The token
+    // document goes last, so a delete that fails halfway can be retried. Portfolio
+    // transactions stay; they are the users' own records.
+    pub async fn purge_token(&self, token_id: &str, delete: bool) -> mongodb::error::Result<TokenDocuments> {
+        let filter = doc! { "token_id": token_id };
+        let mut documents = TokenDocuments::default();
+        for (name, count) in [
+            (self.get_history_collection().name().to_string(), &mut documents.price_history),
+            (self.get_user_favorites_collection().name().to_string(), &mut documents.user_favorites),
+            (self.get_coin_profiles_collection().name().to_string(), &mut documents.coin_profiles),
+            (self.get_symbol_map_collection().name().to_string(), &mut documents.symbol_map),
+            (self.get_events_collection().name().to_string(), &mut documents.events),
+            (self.get_annotations_collection().name().to_string(), &mut documents.annotations),
+            (self.get_tokens_collection().name().to_string(), &mut documents.tokens),
+        ] {
+            let collection = self.db.collection::<Document>(&name);
+            *count = if delete {
+                collection.delete_many(filter.clone(), None).await?.deleted_count
+            } else {
+                collection.count_documents(filter.clone(), None).await?
+            };
+        }
+        Ok(documents)
+    }
+
+    // Deletes token documents we haven't fetched since `older_than`, except tokens starred
+    // in the shared list or by any user and tokens an admin hid. Documents from before
+    // fetched_at existed are judged by last_updated; either may still be a BSON date,
+    // which only compares to a date. History and other documents expire on their own.
+    pub async fn purge_stale_tokens(&self, older_than: DateTime<Utc>) -> mongodb::error::Result<u64> {
+        let favorites: Vec<String> = self.favorite_token_ids().await?.into_iter().collect();
+        let cutoff = stored_timestamp(older_than);
+        let cutoff_date = mongodb::bson::DateTime::from_chrono(older_than);
+        let filter = doc! {
+            "$or": [
+                { "fetched_at": { "$lt": &cutoff } },
+                { "fetched_at": { "$lt": cutoff_date } },
+                {
+                    "fetched_at": null,
+                    "$or": [
+                        { "last_updated": { "$lt": &cutoff } },
+                        { "last_updated": { "$lt": cutoff_date } },
+                    ],
+                },
+            ],
+            "is_favorite": { "$ne": true },
+            "hidden": { "$ne": true },
+            "token_id": { "$nin": favorites },
+        };
+        let result = self.get_tokens_collection().delete_many(filter, None).await?;
+        Ok(result.deleted_count)
+    }
+
+    // Runs the given repair passes in order, reporting on each
+    pub async fn repair(&self, passes: &[RepairPass]) -> mongodb::error::Result<Vec<RepairReport>> {
+        let mut reports = Vec::with_capacity(passes.len());
+        for pass in passes {
+            reports.push(match pass {
+                RepairPass::Dedupe => self.dedupe_tokens().await?,
+                RepairPass::Backfill => self.backfill_token_fields().await?,
+                RepairPass::EmptyHistory => self.delete_empty_history().await?,
+            });
+        }
+        Ok(reports)
+    }
+
+    // Leaves one document per token_id: the one upstream updated last, or the last one
+    // inserted among equals, carrying what users set on any of the copies
+    pub async fn dedupe_tokens(&self) -> mongodb::error::Result<RepairReport> {
+        let raw = self.get_tokens_collection().clone_with_type::<Document>();
+        let examined = raw.count_documents(None, None).await?;
+        let duplicated = vec![
+            doc! { "$group": { "_id": "$token_id", "count": { "$sum": 1 } } },
+            doc! { "$match": { "count": { "$gt": 1 } } },
+        ];
+        let groups: Vec<Document> = raw.aggregate(duplicated, None).await?.try_collect().await?;
+
+        let mut report = RepairReport { pass: RepairPass::Dedupe, examined, fixed: 0, deleted: 0 };
+        for group in groups {
+            let Ok(token_id) = group.get_str("_id") else { continue };
+            let mut projection = doc! { "last_updated": 1 };
+            for field in USER_OWNED_FIELDS {
+                projection.insert(*field, 1);
+            }
+            let options = mongodb::options::FindOptions::builder().projection(projection).build();
+            let mut copies: Vec<Document> = raw.find(doc! { "token_id": token_id }, options).await?.try_collect().await?;
+            // Newest first; the first copy is the one kept
+            copies.sort_by_key(|copy| {
+                std::cmp::Reverse((document_timestamp(copy.get("last_updated")), copy.get_object_id("_id").ok()))
+            });
+            let Some(keep) = copies.first().and_then(|copy| copy.get_object_id("_id").ok()) else {
+                continue;
+            };
+            let others: Vec<ObjectId> = copies.iter().filter_map(|copy| copy.get_object_id("_id").ok()).filter(|id| *id != keep).collect();
+
+            raw.update_one(doc! { "_id": keep }, doc! { "$set": merge_user_owned_fields(&copies) }, None).await?;
+            report.deleted += raw.delete_many(doc! { "_id": { "$in": others } }, None).await?.deleted_count;
+            report.fixed += 1;
+        }
+        Ok(report)
+    }
+
+    // Sets every field CryptoToken requires but a document lacks to its default
+    pub async fn backfill_token_fields(&self) -> mongodb::error::Result<RepairReport> {
+        let raw = self.get_tokens_collection().clone_with_type::<Document>();
+        let examined = raw.count_documents(None, None).await?;
+        let defaults = token_field_defaults();
+        let missing: Vec<Document> = defaults.keys().map(|field| doc! { field: { "$exists": false } }).collect();
+        let incomplete: Vec<Document> = raw.find(doc! { "$or": missing }, None).await?.try_collect().await?;
+
+        let mut report = RepairReport { pass: RepairPass::Backfill, examined, fixed: 0, deleted: 0 };
+        for document in incomplete {
+            let Ok(id) = document.get_object_id("_id") else { continue };
+            let fill: Document = defaults
+                .iter()
+                .filter(|(field, _)| !document.contains_key(field.as_str()))
+                .map(|(field, value)| (field.clone(), value.clone()))
+                .collect();
+            report.fixed += raw.update_one(doc! { "_id": id }, doc! { "$set": fill }, None).await?.modified_count;
+        }
+        Ok(report)
+    }
+
+    // Deletes cached charts whose prices are missing or empty; the next request refetches them
+    pub async fn delete_empty_history(&self) -> mongodb::error::Result<RepairReport> {
+        let raw = self.get_history_collection().clone_with_type::<Document>();
+        let examined = raw.count_documents(None, None).await?;
+        let empty = doc! { "prices": { "$in": [Bson::Array(Vec::new()), Bson::Null] } };
+        let deleted = raw.delete_many(empty, None).await?.deleted_count;
+        Ok(RepairReport { pass: RepairPass::EmptyHistory, examined, fixed: 0, deleted })
+    }
+
+    pub async fn ping(&self) -> mongodb::error::Result<()> {
+        self.db.run_command(doc! { "ping": 1 }, None).await?;
+        Ok(())
+    }
+
+    // Whether any token has been stored yet; an empty collection means nothing to serve
+    pub async fn has_cached_tokens(&self) -> mongodb::error::Result<bool> {
+        Ok(self.get_tokens_collection().estimated_document_count(None).await? > 0)
+    }
+
+    pub fn get_transactions_collection(&self) -> Collection<StoredTransaction> {
+        self.db.collection::<StoredTransaction>("portfolio_transactions")
+    }
+
+    // A user's transactions in the order they were recorded
+    pub async fn user_transactions(&self, user_id: ObjectId) -> mongodb::error::Result<Vec<StoredTransaction>> {
+        let options = mongodb::options::FindOptions::builder().sort(doc! { "_id": 1 }).build();
+        self.get_transactions_collection()
+            .find(doc! { "user_id": user_id }, options)
+            .await?
+            .try_collect()
+            .await
+    }
+
+    pub async fn insert_transaction(&self, transaction: &StoredTransaction) -> mongodb::error::Result<()> {
+        self.get_transactions_collection().insert_one(transaction, None).await?;
+        Ok(())
+    }
+
+    // False when the user has no transaction with that id
+    pub async fn replace_transaction(&self, transaction: &StoredTransaction) -> mongodb::error::Result<bool> {
+        let Some(id) = transaction.id else {
+            return Ok(false);
+        };
+        let result = self
+            .get_transactions_collection()
+            .replace_one(doc! { "_id": id, "user_id": transaction.user_id }, transaction, None)
+            .await?;
+        Ok(result.matched_count > 0)
+    }
+
+    pub async fn delete_transaction(&self, user_id: ObjectId, id: ObjectId) -> mongodb::error::Result<bool> {
+        let result = self
+            .get_transactions_collection()
+            .delete_one(doc! { "_id": id, "user_id": user_id }, None)
+            .await?;
+        Ok(result.deleted_count > 0)
+    }
+
+    pub async fn create_user(&self, api_key_hash: String) -> mongodb::error::Result<User> {
+        let mut user = User {
+            id: None,
+            api_key_hash,
+            created_at: Utc::now(),
+        };
+        let result = self.get_users_collection().insert_one(&user, None).await?;
+        user.id = result.inserted_id.as_object_id();
+        Ok(user)
+    }
+
+    pub async fn find_user_by_key_hash(&self, api_key_hash: &str) -> mongodb::error::Result<Option<User>> {
+        self.get_users_collection().find_one(doc! { "api_key_hash": api_key_hash }, None).await
+    }
+
+    pub async fn user_favorite_ids(&self, user_id: ObjectId) -> mongodb::error::Result<HashSet<String>> {
+        let favorites: Vec<UserFavorite> = self
+            .get_user_favorites_collection()
+            .find(doc! { "user_id": user_id }, None)
+            .await?
+            .try_collect()
+            .await?;
+        Ok(favorites.into_iter().map(|f| f.token_id).collect())
+    }
+
+    // Tokens hidden through /api/tokens/{id}/hide, for lists that never touched the collection
+    pub async fn hidden_token_ids(&self) -> mongodb::error::Result<HashSet<String>> {
+        let ids = self.get_tokens_collection().distinct("token_id", doc! { "hidden": true }, None).await?;
+        Ok(ids.into_iter().filter_map(|id| id.as_str().map(String::from)).collect())
+    }
+
+    // The stored values of `fields` by token_id, for overlaying on copies fetched upstream,
+    // which know nothing of what users set
+    pub async fn user_owned_fields(&self, fields: &[&str]) -> mongodb::error::Result<HashMap<String, Document>> {
+        let mut projection = doc! { "_id": 0, "token_id": 1 };
+        for field in fields {
+            projection.insert(*field, 1);
+        }
+        let options = mongodb::options::FindOptions::builder().projection(projection).build();
+        let documents: Vec<Document> = self
+            .get_tokens_collection()
+            .clone_with_type::<Document>()
+            .find(None, options)
+            .await?
+            .try_collect()
+            .await?;
+        Ok(documents
+            .into_iter()
+            .filter_map(|mut document| {
+                let token_id = document.remove("token_id")?.as_str()?.to_string();
+                Some((token_id, document))
+            })
+            .collect())
+    }
+
+    // Every token with a chart in price_history, hidden or not
+    pub async fn history_token_ids(&self) -> mongodb::error::Result<HashSet<String>> {
+        let ids = self.get_history_collection().distinct("token_id", None, None).await?;
+        Ok(ids.into_iter().filter_map(|id| id.as_str().map(String::from)).collect())
+    }
+
+    // Tokens starred in the shared list or by any user
+    pub async fn favorite_token_ids(&self) -> mongodb::error::Result<HashSet<String>> {
+        let shared = self.get_tokens_collection().distinct("token_id", doc! { "is_favorite": true }, None).await?;
+        let per_user = self.get_user_favorites_collection().distinct("token_id", None, None).await?;
+        Ok(shared.into_iter().chain(per_user).filter_map(|id| id.as_str().map(String::from)).collect())
+    }
+
+    // Tokens starred in the shared list, in id order
+    pub async fn shared_favorite_ids(&self) -> mongodb::error::Result<Vec<String>> {
+        let ids = self.get_tokens_collection().distinct("token_id", doc! { "is_favorite": true }, None).await?;
+        let mut ids: Vec<String> = ids.into_iter().filter_map(|id| id.as_str().map(String::from)).collect();
+        ids.sort();
+        Ok(ids)
+    }
+
+    // Stars `token_ids` in the shared list and, in replace mode, unstars every other token.
+    // Each is a single update_many however many ids are listed.
+    pub async fn import_favorites(
+        &self,
+        token_ids: &[String],
+        mode: FavoritesImportMode,
+    ) -> mongodb::error::Result<FavoritesImportSummary> {
+        let collection = self.get_tokens_collection();
+        let known: HashSet<String> = collection
+            .distinct("token_id", doc! { "token_id": { "$in": token_ids } }, None)
+            .await?
+            .into_iter()
+            .filter_map(|id| id.as_str().map(String::from))
+            .collect();
+        let mut unknown: Vec<String> = Vec::new();
+        for token_id in token_ids {
+            if !known.contains(token_id) && !unknown.contains(token_id) {
+                unknown.push(token_id.clone());
+            }
+        }
+
+        let favorited = collection
+            .update_many(
+                doc! { "token_id": { "$in": token_ids }, "is_favorite": { "$ne": true } },
+                doc! { "$set": { "is_favorite": true } },
+                None,
+            )
+            .await?
+            .modified_count;
+        let unfavorited = match mode {
+            FavoritesImportMode::Merge => 0,
+            FavoritesImportMode::Replace => {
+                collection
+                    .update_many(
+                        doc! { "token_id": { "$nin": token_ids }, "is_favorite": true },
+                        doc! { "$set": { "is_favorite": false } },
+                        None,
+                    )
+                    .await?
+                    .modified_count
+            }
+        };
+        Ok(FavoritesImportSummary { mode, favorited, unfavorited, unknown })
+    }
+
+    // Flips one of the user's favorites and returns the new state
+    pub async fn toggle_user_favorite(&self, user_id: ObjectId, token_id: &str) -> mongodb::error::Result<bool> {
+        let collection = self.get_user_favorites_collection();
+        let filter = doc! { "user_id": user_id, "token_id": token_id };
+        if collection.delete_one(filter.clone(), None).await?.deleted_count > 0 {
+            return Ok(false);
+        }
+
+        // Upsert rather than insert so a concurrent toggle that got here first isn't an error
+        let update = doc! { "$setOnInsert": { "created_at": Utc::now().to_rfc3339() } };
+        let options = mongodb::options::UpdateOptions::builder().upsert(true).build();
+        match collection.update_one(filter, update, options).await {
+            Ok(_) => Ok(true),
+            Err(e) if is_duplicate_key(&e) => Ok(true),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Replaces each token's document (matched by token_id, inserted when new) in a single
+    // unordered `update` command, so one bad document doesn't stop the rest
+    pub async fn upsert_tokens(&self, tokens: &[CryptoToken]) -> mongodb::error::Result<UpsertOutcome> {
+        let mut outcome = UpsertOutcome::default();
+        let mut updates = Vec::with_capacity(tokens.len());
+        let mut positions = Vec::with_capacity(tokens.len());
+        for (index, token) in tokens.iter().enumerate() {
+            match mongodb::bson::to_document(token) {
+                Ok(replacement) => {
+                    updates.push(doc! { "q": { "token_id": &token.token_id }, "u": replacement, "upsert": true });
+                    positions.push(index);
+                }
+                Err(e) => outcome.failed.push((index, e.to_string())),
+            }
+        }
+        if updates.is_empty() {
+            return Ok(outcome);
+        }
+
+        let reply = self
+            .db
+            .run_command(
+                doc! { "update": self.get_tokens_collection().name(), "updates": updates, "ordered": false },
+                None,
+            )
+            .await?;
+
+        let matched_or_upserted = reply.get_i32("n").unwrap_or(0).max(0) as u64;
+        outcome.inserted = reply.get_array("upserted").map(|u| u.len() as u64).unwrap_or(0);
+        outcome.updated = matched_or_upserted.saturating_sub(outcome.inserted);
+        if let Ok(errors) = reply.get_array("writeErrors") {
+            for error in errors.iter().filter_map(|e| e.as_document()) {
+                let index = error.get_i32("index").unwrap_or(0).max(0) as usize;
+                let message = error.get_str("errmsg").unwrap_or("write failed").to_string();
+                outcome.failed.push((positions.get(index).copied().unwrap_or(index), message));
+            }
+        }
+        outcome.failed.sort_by_key(|(index, _)| *index);
+        Ok(outcome)
+    }
+
+    pub fn get_coin_profiles_collection(&self) -> Collection<CoinProfile> {
+        self.db.collection::<CoinProfile>("coin_profiles")
+    }
+
+    pub async fn load_coin_profile(&self, token_id: &str) -> mongodb::error::Result<Option<CoinProfile>> {
+        self.get_coin_profiles_collection().find_one(doc! { "token_id": token_id }, None).await
+    }
+
+    // One document per token, replaced on every fetch
+    pub async fn save_coin_profile(&self, profile: &CoinProfile) -> mongodb::error::Result<()> {
+        let options = mongodb::options::ReplaceOptions::builder().upsert(true).build();
+        self.get_coin_profiles_collection()
+            .replace_one(doc! { "token_id": &profile.token_id }, profile, options)
+            .await?;
+        Ok(())
+    }
+
+    pub fn get_exchange_rates_collection(&self) -> Collection<ExchangeRates> {
+        self.db.collection::<ExchangeRates>("exchange_rates")
+    }
+
+    pub async fn load_exchange_rates(&self) -> mongodb::error::Result<Option<ExchangeRates>> {
+        self.get_exchange_rates_collection().find_one(None, None).await
+    }
+
+    // A single document, replaced on every fetch
+    pub async fn save_exchange_rates(&self, rates: &ExchangeRates) -> mongodb::error::Result<()> {
+        let options = mongodb::options::ReplaceOptions::builder().upsert(true).build();
+        self.get_exchange_rates_collection().replace_one(doc! {}, rates, options).await?;
+        Ok(())
+    }
+
+    // Every stored category, by name
+    pub async fn load_categories(&self) -> mongodb::error::Result<Vec<Category>> {
+        let options = mongodb::options::FindOptions::builder().sort(doc! { "name": 1 }).build();
+        self.get_categories_collection().find(None, options).await?.try_collect().await
+    }
+
+    // Upserts by category_id; categories CoinGecko drops stay around, which is harmless
+    pub async fn save_categories(&self, categories: &[Category]) -> mongodb::error::Result<()> {
+        let collection = self.get_categories_collection();
+        let options = mongodb::options::UpdateOptions::builder().upsert(true).build();
+        for category in categories {
+            collection
+                .update_one(
+                    doc! { "category_id": &category.category_id },
+                    doc! { "$set": { "name": &category.name } },
+                    options.clone(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub fn get_webhook_deliveries_collection(&self) -> Collection<WebhookDelivery> {
+        self.db.collection("webhook_deliveries")
+    }
+
+    pub async fn load_webhook_config(&self) -> mongodb::error::Result<Option<WebhookConfig>> {
+        self.db.collection::<WebhookConfig>("webhook_config").find_one(doc! { "_id": "webhook" }, None).await
+    }
+
+    // Written field by field: `secret` is skipped when a WebhookConfig is serialized
+    pub async fn save_webhook_config(&self, config: &WebhookConfig) -> mongodb::error::Result<()> {
+        let options = mongodb::options::ReplaceOptions::builder().upsert(true).build();
+        let document = doc! { "_id": "webhook", "url": &config.url, "secret": &config.secret, "enabled": config.enabled };
+        self.db
+            .collection::<Document>("webhook_config")
+            .replace_one(doc! { "_id": "webhook" }, document, options)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn record_webhook_delivery(&self, delivery: &WebhookDelivery) -> mongodb::error::Result<()> {
+        self.get_webhook_deliveries_collection().insert_one(delivery, None).await?;
+        Ok(())
+    }
+
+    // Most recent attempts first
+    pub async fn recent_webhook_deliveries(&self, limit: i64) -> mongodb::error::Result<Vec<WebhookDelivery>> {
+        let options = mongodb::options::FindOptions::builder().sort(doc! { "_id": -1 }).limit(limit).build();
+        self.get_webhook_deliveries_collection().find(None, options).await?.try_collect().await
+    }
+
+    pub fn get_events_collection(&self) -> Collection<Event> {
+        self.db.collection("events")
+    }
+
+    pub fn get_alerts_collection(&self) -> Collection<StoredAlert> {
+        self.db.collection("alerts")
+    }
+
+    pub fn get_alert_triggers_collection(&self) -> Collection<AlertTrigger> {
+        self.db.collection("alert_triggers")
+    }
+
+    pub async fn create_alert(&self, condition: AlertCondition, scope: AlertScope) -> mongodb::error::Result<StoredAlert> {
+        let mut alert = StoredAlert { id: None, condition, scope, created_at: Utc::now() };
+        let result = self.get_alerts_collection().insert_one(&alert, None).await?;
+        alert.id = result.inserted_id.as_object_id();
+        Ok(alert)
+    }
+
+    // Oldest first
+    pub async fn load_alerts(&self) -> mongodb::error::Result<Vec<StoredAlert>> {
+        let options = mongodb::options::FindOptions::builder().sort(doc! { "_id": 1 }).build();
+        self.get_alerts_collection().find(None, options).await?.try_collect().await
+    }
+
+    // Removes the alert and the triggers it left; false when there was no such alert
+    pub async fn delete_alert(&self, id: ObjectId) -> mongodb::error::Result<bool> {
+        let result = self.get_alerts_collection().delete_one(doc! { "_id": id }, None).await?;
+        if result.deleted_count == 0 {
+            return Ok(false);
+        }
+        self.get_alert_triggers_collection().delete_many(doc! { "alert_id": id.to_hex() }, None).await?;
+        Ok(true)
+    }
+
+    // Stores `trigger` unless the alert already fired for the token within `cooldown`
+    // before it. Returns whether it was stored.
+    pub async fn record_alert_trigger(&self, trigger: &AlertTrigger, cooldown: Duration) -> mongodb::error::Result<bool> {
+        let mut document = mongodb::bson::to_document(trigger)?;
+        document.insert("at", stored_timestamp(trigger.at));
+        let recent = doc! {
+            "alert_id": &trigger.alert_id,
+            "token_id": &trigger.token_id,
+            "at": { "$gte": stored_timestamp(trigger.at - cooldown) },
+        };
+        let options = mongodb::options::UpdateOptions::builder().upsert(true).build();
+        let result = self
+            .get_alert_triggers_collection()
+            .clone_with_type::<Document>()
+            .update_one(recent, doc! { "$setOnInsert": document }, options)
+            .await?;
+        Ok(result.upserted_id.is_some())
+    }
+
+    // Newest first
+    pub async fn alert_triggers(&self, alert_id: &str, limit: i64) -> mongodb::error::Result<Vec<AlertTrigger>> {
+        let options = mongodb::options::FindOptions::builder()
+            .sort(doc! { "at": -1 })
+            .projection(doc! { "_id": 0 })
+            .limit(limit)
+            .build();
+        self.get_alert_triggers_collection().find(doc! { "alert_id": alert_id }, options).await?.try_collect().await
+    }
+
+    pub fn get_annotations_collection(&self) -> Collection<TokenAnnotation> {
+        self.db.collection("annotations")
+    }
+
+    pub async fn load_annotation(&self, token_id: &str) -> mongodb::error::Result<Option<TokenAnnotation>> {
+        self.get_annotations_collection().find_one(doc! { "token_id": token_id }, None).await
+    }
+
+    // One document per token, replaced on every save
+    pub async fn save_annotation(&self, annotation: &TokenAnnotation) -> mongodb::error::Result<()> {
+        let options = mongodb::options::ReplaceOptions::builder().upsert(true).build();
+        self.get_annotations_collection()
+            .replace_one(doc! { "token_id": &annotation.token_id }, annotation, options)
+            .await?;
+        Ok(())
+    }
+
+    // Whether there was an annotation to delete
+    pub async fn delete_annotation(&self, token_id: &str) -> mongodb::error::Result<bool> {
+        let result = self.get_annotations_collection().delete_one(doc! { "token_id": token_id }, None).await?;
+        Ok(result.deleted_count > 0)
+    }
+
+    // Tokens annotated with `tag`, which is matched as stored: trimmed and lowercased
+    pub async fn annotated_token_ids(&self, tag: &str) -> mongodb::error::Result<BTreeSet<String>> {
+        let ids = self.get_annotations_collection().distinct("token_id", doc! { "tags": tag }, None).await?;
+        Ok(ids.into_iter().filter_map(|id| id.as_str().map(String::from)).collect())
+    }
+
+    // Stores `event` unless the same kind was already recorded for the token within
+    // `window` before it, so a price flapping around a threshold yields one row per
+    // window. Returns whether it was stored.
+    pub async fn record_event(&self, event: &Event, window: Duration) -> mongodb::error::Result<bool> {
+        let mut document = mongodb::bson::to_document(event)?;
+        document.insert("at", stored_timestamp(event.at));
+        let recent = doc! {
+            "token_id": &event.token_id,
+            "kind": event.kind.name(),
+            "at": { "$gte": stored_timestamp(event.at - window) },
+        };
+        let options = mongodb::options::UpdateOptions::builder().upsert(true).build();
+        let result = self
+            .get_events_collection()
+            .clone_with_type::<Document>()
+            .update_one(recent, doc! { "$setOnInsert": document }, options)
+            .await?;
+        Ok(result.upserted_id.is_some())
+    }
+
+    // Newest first, optionally from `since` on and of one kind
+    pub async fn recent_events(
+        &self,
+        since: Option<DateTime<Utc>>,
+        kind: Option<&str>,
+        limit: i64,
+    ) -> mongodb::error::Result<Vec<Event>> {
+        let mut filter = Document::new();
+        if let Some(since) = since {
+            filter.insert("at", doc! { "$gte": stored_timestamp(since) });
+        }
+        if let Some(kind) = kind {
+            filter.insert("kind", kind);
+        }
+        let options = mongodb::options::FindOptions::builder()
+            .sort(doc! { "at": -1 })
+            .projection(doc! { "_id": 0 })
+            .limit(limit)
+            .build();
+        self.get_events_collection().find(filter, options).await?.try_collect().await
+    }
+
+    // token_id -> Binance symbol for every mapped token
+    pub async fn load_symbol_map(&self) -> mongodb::error::Result<HashMap<String, String>> {
+        let mappings: Vec<SymbolMapping> = self.get_symbol_map_collection().find(None, None).await?.try_collect().await?;
+        Ok(mappings.into_iter().map(|m| (m.token_id, m.symbol.to_uppercase())).collect())
+    }
+
+    // Top `limit` tokens by their change over `window`, sorted and cut in the database.
+    // Tokens without a change (missing, null, zero or NaN) are left out so fresh listings
+    // don't show up. `filter` narrows the tokens considered, e.g. to leave stablecoins out.
+    pub async fn top_movers(
+        &self,
+        movers: Movers,
+        window: ChangeWindow,
+        limit: i64,
+        filter: Document,
+    ) -> mongodb::error::Result<Vec<TokenChange>> {
+        let direction = if movers == Movers::Gainers { -1 } else { 1 };
+        let field = window.field();
+        let mut has_change = Document::new();
+        has_change.insert(field, doc! { "$exists": true, "$nin": [null, 0, f64::NAN] });
+        let mut sort = Document::new();
+        sort.insert(field, direction);
+        sort.insert("token_id", 1);
+        let pipeline = [
+            doc! { "$match": has_change },
+            doc! { "$match": filter },
+            doc! { "$sort": sort },
+            doc! { "$limit": limit },
+            doc! { "$project": {
+                "_id": 0,
+                "token_id": 1,
+                "name": 1,
+                "symbol": 1,
+                "current_price": 1,
+                "change_percentage": format!("${}", field),
+            } },
+        ];
+
+        let documents: Vec<mongodb::bson::Document> =
+            self.get_tokens_collection().aggregate(pipeline, None).await?.try_collect().await?;
+        documents
+            .into_iter()
+            .map(|document| mongodb::bson::from_document(document).map_err(Into::into))
+            .collect()
+    }
+
+    // Tokens matching `filter` and the screener's bounds, best first by its sort field,
+    // each with its volume/market-cap ratio. The ratio is computed in the pipeline, and
+    // left null for a zero market cap rather than divided by, so a ratio bound or sort
+    // passes over those tokens.
+    pub async fn screen_tokens(&self, screener: &Screener, filter: Document) -> mongodb::error::Result<Vec<ScreenedToken>> {
+        let mut bounds = Document::new();
+        for (field, min, max) in &screener.bounds {
+            let mut range = Document::new();
+            if let Some(min) = min {
+                range.insert("$gte", min);
+            }
+            if let Some(max) = max {
+                range.insert("$lte", max);
+            }
+            bounds.insert(*field, range);
+        }
+        let mut sort = Document::new();
+        sort.insert(screener.sort, -1);
+        sort.insert("token_id", 1);
+        let pipeline = [
+            doc! { "$match": filter },
+            doc! { "$addFields": { "volume_mcap_ratio": {
+                "$cond": [
+                    { "$gt": ["$market_cap", 0] },
+                    { "$divide": ["$volume_24h", "$market_cap"] },
+                    Bson::Null,
+                ],
+            } } },
+            doc! { "$match": bounds },
+            doc! { "$sort": sort },
+            doc! { "$limit": screener.limit as i64 },
+            doc! { "$project": { "sparkline_7d": 0 } },
+        ];
+
+        let documents: Vec<Document> = self.get_tokens_collection().aggregate(pipeline, None).await?.try_collect().await?;
+        documents
+            .into_iter()
+            .map(|mut document| {
+                let volume_mcap_ratio = document.remove("volume_mcap_ratio").and_then(|ratio| ratio.as_f64());
+                let token = mongodb::bson::from_document(document)?;
+                Ok(ScreenedToken { token, volume_mcap_ratio })
+            })
+            .collect()
+    }
+
+    // Whether any stored token has a figure for `window`. Tokens fetched before the
+    // longer windows were requested have none until their next refresh.
+    pub async fn has_change_data(&self, window: ChangeWindow) -> mongodb::error::Result<bool> {
+        let mut filter = Document::new();
+        filter.insert(window.field(), doc! { "$type": "number" });
+        let options = mongodb::options::CountOptions::builder().limit(1).build();
+        Ok(self.get_tokens_collection().count_documents(filter, options).await? > 0)
+    }
+
+    // Cached price series for the given tokens, keyed by token_id; tokens without a
+    // cached chart are absent. Reads the raw documents since prices are stored both as
+    // `[t, p]` pairs and as `{ t, p }` documents.
+    pub async fn cached_price_series(&self, token_ids: &[String]) -> mongodb::error::Result<HashMap<String, Vec<(i64, f64)>>> {
+        self.cached_series(token_ids, "prices").await
+    }
+
+    // Same as cached_price_series, for the market caps stored alongside the prices
+    pub async fn cached_market_cap_series(&self, token_ids: &[String]) -> mongodb::error::Result<HashMap<String, Vec<(i64, f64)>>> {
+        self.cached_series(token_ids, "market_caps").await
+    }
+
+    async fn cached_series(&self, token_ids: &[String], field: &str) -> mongodb::error::Result<HashMap<String, Vec<(i64, f64)>>> {
+        let collection = self.db.collection::<Document>(self.get_history_collection().name());
+        let documents: Vec<Document> = collection
+            .find(doc! { "token_id": { "$in": token_ids } }, None)
+            .await?
+            .try_collect()
+            .await?;
+
+        // A token has an hourly and a daily series (or, cached before those, a chart per
+        // range and interval); the one reaching back furthest wins, then the denser one
+        let reach = |points: &[(i64, f64)]| {
+            let span = match (points.iter().map(|(t, _)| *t).min(), points.iter().map(|(t, _)| *t).max()) {
+                (Some(first), Some(last)) => last - first,
+                _ => 0,
+            };
+            (span, points.len())
+        };
+        let mut series: HashMap<String, Vec<(i64, f64)>> = HashMap::new();
+        for document in &documents {
+            let Ok(token_id) = document.get_str("token_id") else {
+                continue;
+            };
+            let charts = [Some(document), document.get_document("hourly").ok(), document.get_document("daily").ok()];
+            for points in charts.into_iter().flatten().filter_map(|chart| chart.get_array(field).ok()) {
+                let points: Vec<(i64, f64)> = points.iter().filter_map(price_point).collect();
+                match series.get(token_id) {
+                    Some(existing) if reach(existing) >= reach(&points) => {}
+                    _ => {
+                        series.insert(token_id.to_string(), points);
+                    }
+                }
+            }
+        }
+        Ok(series)
+    }
+
+    // Everything stored for a token, None when nothing is
+    pub async fn stored_history(&self, token_id: &str) -> mongodb::error::Result<Option<StoredHistory>> {
+        let collection = self.db.collection::<Document>(self.get_history_collection().name());
+        let documents: Vec<Document> = collection.find(doc! { "token_id": token_id }, None).await?.try_collect().await?;
+        Ok((!documents.is_empty()).then(|| StoredHistory::from_documents(&documents)))
+    }
+
+    // The last `days` of the stored series that covers (days, interval), with the
+    // granularity of that series; see history_coverage. A daily request settles for
+    // hourly points, which the caller rolls up. With `fresh_at`, fetches that expired
+    // by then don't count.
+    pub async fn cached_history(
+        &self,
+        token_id: &str,
+        days: HistoryDays,
+        interval: HistoryInterval,
+        fresh_at: Option<DateTime<Utc>>,
+    ) -> mongodb::error::Result<Option<(HistoryInterval, CoinGeckoHistoricalData)>> {
+        let Some(mut stored) = self.stored_history(token_id).await? else {
+            return Ok(None);
+        };
+        let Some(covering) = history_coverage(&stored.fetches, days, interval, fresh_at) else {
+            return Ok(None);
+        };
+        let window = match days {
+            HistoryDays::Days(days) => Some(days),
+            HistoryDays::Max => None,
+        };
+        let series = std::mem::take(stored.series_mut(covering));
+        Ok(Some((covering, analytics::slice_history(series, window))))
+    }
+
+    // Merges a usd chart fetched for (days, interval) into the token's stored history.
+    // Read-modify-write: of two saves racing for one token the later wins, and the other
+    // chart is fetched again when next needed.
+    pub async fn save_history(
+        &self,
+        token_id: &str,
+        days: HistoryDays,
+        interval: HistoryInterval,
+        data: &CoinGeckoHistoricalData,
+    ) -> mongodb::error::Result<()> {
+        let mut stored = self.stored_history(token_id).await?.unwrap_or_default();
+        let fetched_at = Utc::now();
+        stored.merge(HistoryFetch { days, interval, fetched_at }, data);
+
+        let points = |series: &[Vec<f64>]| {
+            series
+                .iter()
+                .filter(|point| point.len() >= 2)
+                .map(|point| doc! { "t": point[0] as i64, "p": point[1] })
+                .collect::<Vec<_>>()
+        };
+        let chart = |data: &CoinGeckoHistoricalData| {
+            doc! {
+                "prices": points(&data.prices),
+                "market_caps": points(&data.market_caps),
+                "total_volumes": points(&data.total_volumes),
+            }
+        };
+        let fetches: Vec<Document> = stored
+            .fetches
+            .iter()
+            .map(|fetch| doc! { "days": fetch.days, "interval": fetch.interval.as_str(), "fetched_at": fetch.fetched_at })
+            .collect();
+
+        // One document per token, keyed by its id; charts cached per (days, interval)
+        // before it existed are folded in above and dropped once it's written
+        let filter = doc! { "_id": token_id };
+        let update = doc! {
+            "$set": {
+                "token_id": token_id,
+                "symbol": token_id,
+                "hourly": chart(&stored.hourly),
+                "daily": chart(&stored.daily),
+                "fetches": fetches,
+                "timestamp": fetched_at,
+                // The pruning task drops the document once this passes
+                "expires_at": stored.expires_at().unwrap_or(fetched_at),
+            }
+        };
+        let options = mongodb::options::UpdateOptions::builder().upsert(true).build();
+        self.get_history_collection().update_one(filter, update, options).await?;
+        self.get_history_collection()
+            .delete_many(doc! { "token_id": token_id, "_id": { "$ne": token_id } }, None)
+            .await?;
+        Ok(())
+    }
+
+    // What was fetched into each of these tokens' stored history, expired or not;
+    // tokens with nothing stored are absent
+    pub async fn history_fetches(&self, token_ids: &[String]) -> mongodb::error::Result<HashMap<String, Vec<HistoryFetch>>> {
+        let options = mongodb::options::FindOptions::builder()
+            .projection(doc! { "token_id": 1, "days": 1, "interval": 1, "timestamp": 1, "fetches": 1 })
+            .build();
+        let collection = self.db.collection::<Document>(self.get_history_collection().name());
+        let documents: Vec<Document> =
+            collection.find(doc! { "token_id": { "$in": token_ids } }, options).await?.try_collect().await?;
+
+        let mut fetches: HashMap<String, Vec<HistoryFetch>> = HashMap::new();
+        for document in &documents {
+            let Ok(token_id) = document.get_str("token_id") else {
+                continue;
+            };
+            fetches.entry(token_id.to_string()).or_default().extend(document_fetches(document));
+        }
+        Ok(fetches)
+    }
+
+    // Count and last_updated range of the cached tokens, in one $group
+    pub async fn token_cache_summary(&self) -> mongodb::error::Result<CollectionSummary> {
+        let pipeline = [doc! { "$group": {
+            "_id": null,
+            "count": { "$sum": 1 },
+            "oldest": { "$min": "$last_updated" },
+            "newest": { "$max": "$last_updated" },
+        } }];
+        let collection = self.db.collection::<Document>(self.get_tokens_collection().name());
+        let Some(group) = collection.aggregate(pipeline, None).await?.try_next().await? else {
+            return Ok(CollectionSummary::default());
+        };
+
+        Ok(CollectionSummary {
+            count: group.get("count").and_then(number).unwrap_or(0.0) as u64,
+            oldest: stored_time(group.get("oldest")),
+            newest: stored_time(group.get("newest")),
+        })
+    }
+
+    // Every cached chart by (token_id, days, interval), with the overall count and fetch-time range
+    pub async fn history_cache_summary(&self) -> mongodb::error::Result<HistoryCacheStatus> {
+        let options = mongodb::options::FindOptions::builder()
+            .projection(doc! { "token_id": 1, "days": 1, "interval": 1, "timestamp": 1, "expires_at": 1, "fetches": 1 })
+            .build();
+        let collection = self.db.collection::<Document>(self.get_history_collection().name());
+        let documents: Vec<Document> = collection.find(None, options).await?.try_collect().await?;
+
+        let mut entries: Vec<(Option<HistoryDays>, HistoryCacheEntry)> = Vec::new();
+        for document in &documents {
+            let token_id = document.get_str("token_id").unwrap_or_default().to_string();
+            if document.contains_key("fetches") {
+                entries.extend(document_fetches(document).into_iter().map(|fetch| {
+                    let entry = HistoryCacheEntry {
+                        token_id: token_id.clone(),
+                        days: Some(fetch.days.to_string()),
+                        interval: Some(fetch.interval.as_str().to_string()),
+                        fetched_at: Some(fetch.fetched_at),
+                        expires_at: Some(fetch.fetched_at + history_freshness(fetch.days.span())),
+                    };
+                    (Some(fetch.days), entry)
+                }));
+                continue;
+            }
+            let days = document.get("days").and_then(stored_days);
+            let entry = HistoryCacheEntry {
+                token_id,
+                days: days.map(|days| days.to_string()),
+                interval: document.get_str("interval").ok().map(String::from),
+                fetched_at: stored_time(document.get("timestamp")),
+                expires_at: stored_time(document.get("expires_at")),
+            };
+            entries.push((days, entry));
+        }
+        entries.sort_by(|(a_days, a), (b_days, b)| (&a.token_id, a_days, &a.interval).cmp(&(&b.token_id, b_days, &b.interval)));
+
+        let mut status = HistoryCacheStatus::default();
+        for (_, entry) in entries {
+            status.summary.count += 1;
+            status.summary.oldest = status.summary.oldest.into_iter().chain(entry.fetched_at).min();
+            status.summary.newest = status.summary.newest.into_iter().chain(entry.fetched_at).max();
+            status.entries.push(entry);
+        }
+        Ok(status)
+    }
+
+    // Deletes history whose freshness window ended before `older_than`. Documents
+    // written before `expires_at` existed fall back to their fetch time plus the
+    // longest window.
+    pub async fn prune_history(&self, older_than: DateTime<Utc>) -> mongodb::error::Result<u64> {
+        let legacy_cutoff = older_than - history_freshness(u32::MAX);
+        let filter = doc! {
+            "$or": [
+                { "expires_at": { "$lt": older_than } },
+                { "expires_at": { "$exists": false }, "timestamp": { "$lt": legacy_cutoff } },
+            ]
+        };
+
+        let result = self.get_history_collection().delete_many(filter, None).await?;
+        Ok(result.deleted_count)
+    }
+}
+
+fn number(value: &Bson) -> Option<f64> {
+    match value {
+        Bson::Double(v) => Some(*v),
+        Bson::Int64(v) => Some(*v as f64),
+        Bson::Int32(v) => Some(*v as f64),
+        _ => None,
+    }
+}
+
+// A stored timestamp: tokens keep RFC3339 strings, history BSON dates
+fn stored_time(value: Option<&Bson>) -> Option<DateTime<Utc>> {
+    match value? {
+        Bson::String(text) => DateTime::parse_from_rfc3339(text).ok().map(|t| t.with_timezone(&Utc)),
+        Bson::DateTime(at) => Some(at.to_chrono()),
+        _ => None,
+    }
+}
+
+// One stored price point, either shape
+fn price_point(value: &Bson) -> Option<(i64, f64)> {
+    let (t, p) = match value {
+        Bson::Array(pair) => (pair.first()?, pair.get(1)?),
+        Bson::Document(point) => (point.get("t")?, point.get("p")?),
+        _ => return None,
+    };
+    Some((number(t)? as i64, number(p)?))
+}
+
+// A stored `days`: a number, or "max"
+fn stored_days(value: &Bson) -> Option<HistoryDays> {
+    match value {
+        Bson::String(days) => days.parse().ok(),
+        days => Some(HistoryDays::Days(number(days)? as u32)),
+    }
+}
+
+// The three series of a stored chart
+fn stored_chart(document: &Document) -> CoinGeckoHistoricalData {
+    let series = |field: &str| -> Vec<Vec<f64>> {
+        document
+            .get_array(field)
+            .map(|points| points.iter().filter_map(price_point).map(|(t, p)| vec![t as f64, p]).collect())
+            .unwrap_or_default()
+    };
+    CoinGeckoHistoricalData { prices: series("prices"), market_caps: series("market_caps"), total_volumes: series("total_volumes") }
+}
+
+fn stored_fetch(fetch: &Document) -> Option<HistoryFetch> {
+    Some(HistoryFetch {
+        days: stored_days(fetch.get("days")?)?,
+        interval: fetch.get_str("interval").ok()?.parse().ok()?,
+        fetched_at: stored_time(fetch.get("fetched_at"))?,
+    })
+}
+
+// The fetches a price_history document records: its `fetches`, or for a chart cached
+// before those existed, its own range and fetch time
+fn document_fetches(document: &Document) -> Vec<HistoryFetch> {
+    if let Ok(fetches) = document.get_array("fetches") {
+        return fetches.iter().filter_map(|fetch| fetch.as_document().and_then(stored_fetch)).collect();
+    }
+    let legacy = || {
+        let days = stored_days(document.get("days")?)?;
+        let interval = match document.get_str("interval") {
+            Ok(interval) => interval.parse().ok()?,
+            Err(_) => HistoryInterval::auto(days),
+        };
+        Some(HistoryFetch { days, interval, fetched_at: stored_time(document.get("timestamp"))? })
+    };
+    legacy().into_iter().collect()
+}
+
+fn is_duplicate_key(error: &mongodb::error::Error) -> bool {
+    matches!(
+        error.kind.as_ref(),
+        mongodb::error::ErrorKind::Write(mongodb::error::WriteFailure::WriteError(e)) if e.code == 11000
+    )
+}
+
+// Wait before the second connection attempt, doubling for each one after
+const CONNECT_BACKOFF: std::time::Duration = std::time::Duration::from_secs(1);
+const MAX_CONNECT_BACKOFF: std::time::Duration = std::time::Duration::from_secs(30);
+
+// Reported to the server so our connections can be told apart in MongoDB's logs
+pub const APP_NAME: &str = "crypto-tracker-backend";
+
+// Connection pool and timeouts, applied over whatever the URI sets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolSettings {
+    pub max_pool_size: u32,
+    pub min_pool_size: u32,
+    pub connect_timeout: std::time::Duration,
+    pub server_selection_timeout: std::time::Duration,
+}
+
+impl PoolSettings {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_pool_size: config.mongo_max_pool_size,
+            min_pool_size: config.mongo_min_pool_size,
+            connect_timeout: std::time::Duration::from_secs(config.mongo_connect_timeout_secs),
+            server_selection_timeout: std::time::Duration::from_secs(config.mongo_server_selection_timeout_secs),
+        }
+    }
+}
+
+// The URI's options with the pool settings on top. An appName in the URI is kept.
+pub async fn client_options(uri: &str, pool: &PoolSettings) -> mongodb::error::Result<ClientOptions> {
+    let mut options = ClientOptions::parse(uri).await?;
+    options.max_pool_size = Some(pool.max_pool_size);
+    options.min_pool_size = Some(pool.min_pool_size);
+    options.connect_timeout = Some(pool.connect_timeout);
+    options.server_selection_timeout = Some(pool.server_selection_timeout);
+    options.app_name.get_or_insert_with(|| APP_NAME.to_string());
+    Ok(options)
+}
+
+// Connects and pings, retrying with exponential backoff up to MONGODB_CONNECT_ATTEMPTS
+// times so the server can start before MongoDB does. A malformed URI fails straight away.
+pub async fn init_db(config: &Config) -> mongodb::error::Result<DbClient> {
+    connect_with_retry(
+        &config.mongodb_uri,
+        &config.database_name,
+        config.mongodb_connect_attempts,
+        &PoolSettings::from_config(config),
+        CONNECT_BACKOFF,
+    )
+    .await
 }
 
-pub async fn init_db(uri: &str, database_name: &str) -> DbClient {
-    let client = Client::with_uri_str(uri)
-        .await
-        .expect("Failed to connect to MongoDB");
+async fn connect_with_retry(
+    uri: &str,
+    database_name: &str,
+    attempts: u32,
+    pool: &PoolSettings,
+    backoff: std::time::Duration,
+) -> mongodb::error::Result<DbClient> {
+    let mut attempt = 1;
+    loop {
+        // Building the client only parses the URI (and resolves SRV records); the ping
+        // is what proves a server is reachable
+        let result = match client_options(uri, pool).await.and_then(Client::with_options) {
+            Ok(client) => {
+                let db = DbClient { db: client.database(database_name) };
+                db.ping().await.map(|_| db)
+            }
+            Err(e) => Err(e),
+        };
+        let error = match result {
+            Ok(db) => return Ok(db),
+            Err(e) if matches!(e.kind.as_ref(), mongodb::error::ErrorKind::InvalidArgument { .. }) => return Err(e),
+            Err(e) if attempt >= attempts => return Err(e),
+            Err(e) => e,
+        };
+
+        let wait = (backoff * 2u32.saturating_pow(attempt - 1)).min(MAX_CONNECT_BACKOFF);
+        tracing::warn!(attempt, attempts, retry_in_ms = wait.as_millis() as u64, error = %error, "MongoDB not reachable, retrying");
+        tokio::time::sleep(wait).await;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quick_pool() -> PoolSettings {
+        PoolSettings {
+            server_selection_timeout: std::time::Duration::from_millis(50),
+            ..PoolSettings::from_config(&Config::default_for_tests())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connect_gives_up_after_the_configured_attempts() {
+        let started = std::time::Instant::now();
+        let result =
+            connect_with_retry("mongodb://127.0.0.1:9", "db", 3, &quick_pool(), std::time::Duration::from_millis(20)).await;
+
+        assert!(result.is_err());
+        // Three pings plus 20ms and 40ms of backoff
+        assert!(started.elapsed() >= std::time::Duration::from_millis(60), "{:?}", started.elapsed());
+    }
+
+    #[test]
+    fn test_duplicates_merge_every_user_owned_field() {
+        let copies = [
+            doc! { "is_favorite": false, "hidden": false, "tags": ["defi"], "note": "" },
+            doc! { "is_favorite": true, "tags": ["hodl", "defi"] },
+            doc! { "hidden": true, "note": "cold wallet" },
+            doc! { "note": "older note" },
+        ];
+        let merged = merge_user_owned_fields(&copies);
+        assert_eq!(
+            merged,
+            doc! { "is_favorite": true, "hidden": true, "tags": ["defi", "hodl"], "note": "cold wallet" }
+        );
+        for field in USER_OWNED_FIELDS {
+            assert!(merged.contains_key(field), "{}", field);
+        }
+
+        let plain = merge_user_owned_fields(&[doc! {}, doc! { "is_favorite": false }]);
+        assert_eq!(plain, doc! { "is_favorite": false, "hidden": false });
+    }
+
+    #[tokio::test]
+    async fn test_malformed_uri_is_not_retried() {
+        let started = std::time::Instant::now();
+        let result = connect_with_retry("mongodb://", "db", 5, &quick_pool(), std::time::Duration::from_secs(10)).await;
+
+        assert!(result.is_err());
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_pool_settings_override_the_uri() {
+        let pool = PoolSettings {
+            max_pool_size: 50,
+            min_pool_size: 5,
+            connect_timeout: std::time::Duration::from_secs(3),
+            server_selection_timeout: std::time::Duration::from_secs(4),
+        };
+        let options = client_options("mongodb://localhost:27017/?maxPoolSize=2", &pool).await.unwrap();
+        assert_eq!(options.max_pool_size, Some(50));
+        assert_eq!(options.min_pool_size, Some(5));
+        assert_eq!(options.connect_timeout, Some(std::time::Duration::from_secs(3)));
+        assert_eq!(options.server_selection_timeout, Some(std::time::Duration::from_secs(4)));
+        assert_eq!(options.app_name.as_deref(), Some(APP_NAME));
+
+        let options = client_options("mongodb://localhost:27017/?appName=ops-shell", &pool).await.unwrap();
+        assert_eq!(options.app_name.as_deref(), Some("ops-shell"));
+    }
+
+    #[test]
+    fn test_price_points_read_in_both_stored_shapes() {
+        assert_eq!(price_point(&Bson::Array(vec![Bson::Int64(1000), Bson::Double(2.5)])), Some((1000, 2.5)));
+        assert_eq!(price_point(&Bson::Array(vec![Bson::Double(1000.0), Bson::Int32(3)])), Some((1000, 3.0)));
+        assert_eq!(price_point(&Bson::Document(doc! { "t": 1000_i64, "p": 2.5 })), Some((1000, 2.5)));
+        assert_eq!(price_point(&Bson::Array(vec![Bson::Int64(1000)])), None);
+        assert_eq!(price_point(&Bson::String("x".to_string())), None);
+    }
+
+    #[test]
+    fn test_history_freshness_grows_with_range() {
+        assert_eq!(history_freshness(1), Duration::hours(1));
+        assert_eq!(history_freshness(7), Duration::hours(6));
+        assert_eq!(history_freshness(365), Duration::days(1));
+        assert!(history_freshness(1) < history_freshness(30));
+    }
+
+    fn fetch(days: HistoryDays, interval: HistoryInterval, fetched_at: DateTime<Utc>) -> HistoryFetch {
+        HistoryFetch { days, interval, fetched_at }
+    }
+
+    #[test]
+    fn test_longer_fetch_covers_shorter_window() {
+        let now = Utc::now();
+        let fetches = [fetch(365.into(), HistoryInterval::Daily, now), fetch(90.into(), HistoryInterval::Hourly, now)];
 
-    let db = client.database(database_name);
+        assert_eq!(history_coverage(&fetches, 7.into(), HistoryInterval::Hourly, Some(now)), Some(HistoryInterval::Hourly));
+        assert_eq!(history_coverage(&fetches, 30.into(), HistoryInterval::Daily, Some(now)), Some(HistoryInterval::Daily));
+        // Hourly only reaches 90 days back, daily has to do for the year
+        assert_eq!(history_coverage(&fetches, 180.into(), HistoryInterval::Hourly, Some(now)), None);
+        assert_eq!(history_coverage(&fetches, HistoryDays::Max, HistoryInterval::Daily, Some(now)), None);
+        assert_eq!(history_coverage(&[fetch(HistoryDays::Max, HistoryInterval::Daily, now)], 365.into(), HistoryInterval::Daily, Some(now)), Some(HistoryInterval::Daily));
+    }
+
+    #[test]
+    fn test_daily_window_settles_for_hourly_but_not_the_reverse() {
+        let now = Utc::now();
+        let hourly = [fetch(30.into(), HistoryInterval::Hourly, now)];
+        assert_eq!(history_coverage(&hourly, 30.into(), HistoryInterval::Daily, Some(now)), Some(HistoryInterval::Hourly));
+
+        let daily = [fetch(30.into(), HistoryInterval::Daily, now)];
+        assert_eq!(history_coverage(&daily, 30.into(), HistoryInterval::Hourly, Some(now)), None);
+    }
+
+    #[test]
+    fn test_single_day_window_needs_a_single_day_fetch() {
+        let now = Utc::now();
+        let fetches = [fetch(30.into(), HistoryInterval::Hourly, now)];
+        assert_eq!(history_coverage(&fetches, 1.into(), HistoryInterval::Hourly, Some(now)), None);
+        assert_eq!(history_coverage(&fetches, 1.into(), HistoryInterval::Daily, Some(now)), Some(HistoryInterval::Hourly));
 
-    DbClient { db }
+        let fetches = [fetch(1.into(), HistoryInterval::Hourly, now)];
+        assert_eq!(history_coverage(&fetches, 1.into(), HistoryInterval::Hourly, Some(now)), Some(HistoryInterval::Hourly));
+    }
+
+    #[test]
+    fn test_coverage_expires_with_the_window_asked_for() {
+        let now = Utc::now();
+        let fetches = [fetch(365.into(), HistoryInterval::Hourly, now - Duration::hours(12))];
+
+        // Twelve hours is stale for a week but not for a year
+        assert_eq!(history_coverage(&fetches, 7.into(), HistoryInterval::Hourly, Some(now)), None);
+        assert_eq!(history_coverage(&fetches, 365.into(), HistoryInterval::Hourly, Some(now)), Some(HistoryInterval::Hourly));
+        // Without a freshness bound anything that reaches back far enough will do
+        assert_eq!(history_coverage(&fetches, 7.into(), HistoryInterval::Hourly, None), Some(HistoryInterval::Hourly));
+        assert_eq!(history_coverage(&[], 7.into(), HistoryInterval::Hourly, None), None);
+    }
+
+    #[test]
+    fn test_stored_history_folds_legacy_charts_into_one_series_per_interval() {
+        let fetched_at = DateTime::parse_from_rfc3339("2024-03-01T12:00:00Z").unwrap().with_timezone(&Utc);
+        let documents = [
+            doc! { "token_id": "bitcoin", "days": 365_i64, "interval": "daily", "timestamp": fetched_at,
+                   "prices": [{ "t": 0_i64, "p": 1.0 }, { "t": 86_400_000_i64, "p": 2.0 }] },
+            doc! { "token_id": "bitcoin", "days": "max", "timestamp": fetched_at,
+                   "prices": [[86_400_000_i64, 2.5], [-86_400_000_i64, 0.5]] },
+            doc! { "token_id": "bitcoin", "days": 7_i64, "interval": "hourly", "timestamp": fetched_at,
+                   "prices": [{ "t": 3_600_000_i64, "p": 1.5 }] },
+        ];
+        let stored = StoredHistory::from_documents(&documents);
+
+        let prices: Vec<(f64, f64)> = stored.daily.prices.iter().map(|p| (p[0], p[1])).collect();
+        assert_eq!(prices, vec![(-86_400_000.0, 0.5), (0.0, 1.0), (86_400_000.0, 2.5)]);
+        assert_eq!(stored.hourly.prices, vec![vec![3_600_000.0, 1.5]]);
+        let mut ranges: Vec<(HistoryDays, HistoryInterval)> = stored.fetches.iter().map(|f| (f.days, f.interval)).collect();
+        ranges.sort_by_key(|(days, _)| *days);
+        assert_eq!(
+            ranges,
+            vec![(7.into(), HistoryInterval::Hourly), (365.into(), HistoryInterval::Daily), (HistoryDays::Max, HistoryInterval::Daily)]
+        );
+    }
+
+    #[test]
+    fn test_merging_a_fetch_replaces_its_range_and_keeps_the_rest() {
+        let earlier = Utc::now() - Duration::hours(2);
+        let mut stored = StoredHistory::default();
+        let chart = |points: &[(f64, f64)]| CoinGeckoHistoricalData {
+            prices: points.iter().map(|&(t, p)| vec![t, p]).collect(),
+            ..Default::default()
+        };
+        stored.merge(fetch(365.into(), HistoryInterval::Daily, earlier), &chart(&[(0.0, 1.0), (10.0, 2.0)]));
+        stored.merge(fetch(7.into(), HistoryInterval::Daily, earlier), &chart(&[(10.0, 2.0)]));
+
+        let now = Utc::now();
+        stored.merge(fetch(7.into(), HistoryInterval::Daily, now), &chart(&[(10.0, 2.2), (20.0, 3.0)]));
+        assert_eq!(stored.daily.prices, vec![vec![0.0, 1.0], vec![10.0, 2.2], vec![20.0, 3.0]]);
+        assert_eq!(stored.fetches.len(), 2);
+        assert_eq!(stored.expires_at(), Some(earlier + history_freshness(365)));
+        assert!(stored.hourly.prices.is_empty());
+    }
+
+    #[test]
+    fn test_stored_times_read_as_strings_or_dates() {
+        let at = DateTime::parse_from_rfc3339("2024-03-01T12:00:00.250Z").unwrap().with_timezone(&Utc);
+        assert_eq!(stored_time(Some(&Bson::String("2024-03-01T12:00:00.250Z".to_string()))), Some(at));
+        assert_eq!(stored_time(Some(&Bson::DateTime(at.into()))), Some(at));
+        assert_eq!(stored_time(Some(&Bson::Null)), None);
+        assert_eq!(stored_time(None), None);
+    }
 }
@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use actix_web::body::{to_bytes, BodySize, BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{header, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::{error, web, Error, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use crate::listing::DEFAULT_PER_PAGE;
+use crate::models::{
+    ApiResponse, CacheOutcome, CryptoToken, Envelope, EnvelopeError, EnvelopeMeta, EnvelopePagination, ErrorResponse,
+    ResponseMeta,
+};
+
+// Set by cursor-paginated lists; `wrap` repeats it as meta.next_cursor
+pub const NEXT_CURSOR_HEADER: &str = "X-Next-Cursor";
+
+// Opts a request into `opt_in`'s envelope, as does `?envelope=true`
+pub const ENVELOPE_HEADER: &str = "X-Response-Envelope";
+
+// How old the data behind a response is. Handlers attach it to their response's
+// extensions; `wrap` copies it into the envelope's `meta`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Freshness {
+    pub stale: bool,
+    pub cache_age_seconds: u64,
+    // Answered from stored data rather than a call upstream
+    pub from_cache: bool,
+}
+
+impl Freshness {
+    // Straight from upstream
+    pub fn live() -> Self {
+        Self::default()
+    }
+
+    // Measured from the most recently fetched token; stale past `max_age_secs`
+    pub fn of(tokens: &[CryptoToken], max_age_secs: u64) -> Self {
+        Self::newest(tokens.iter().map(|t| t.fetched_at.unwrap_or(t.last_updated)).max(), max_age_secs)
+    }
+
+    // Cached data last fetched at `newest`, if anything was cached at all
+    pub fn newest(newest: Option<DateTime<Utc>>, max_age_secs: u64) -> Self {
+        let Some(newest) = newest else {
+            return Self { from_cache: true, ..Self::default() };
+        };
+        let cache_age_seconds = (Utc::now() - newest).num_seconds().max(0) as u64;
+        Self { stale: cache_age_seconds > max_age_secs, cache_age_seconds, from_cache: true }
+    }
+}
+
+pub fn attach(mut response: HttpResponse, freshness: Freshness) -> HttpResponse {
+    response.extensions_mut().insert(freshness);
+    response
+}
+
+// Rewrites a handler's JSON response into an `ApiResponse`. Status and headers are kept;
+// a 304 has no body and passes through untouched.
+pub async fn wrap(response: HttpResponse) -> HttpResponse {
+    let status = response.status();
+    if status == StatusCode::NOT_MODIFIED {
+        return response;
+    }
+    let freshness = response.extensions().get::<Freshness>().copied().unwrap_or_default();
+    let next_cursor = response
+        .headers()
+        .get(NEXT_CURSOR_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from);
+    let (mut head, body) = response.into_parts();
+
+    let Ok(bytes) = to_bytes(body).await else {
+        return HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to read response"));
+    };
+
+    let envelope = if status.is_success() {
+        let data: serde_json::Value = serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null);
+        let meta = ResponseMeta {
+            stale: freshness.stale,
+            cache_age_seconds: freshness.cache_age_seconds,
+            count: match &data {
+                serde_json::Value::Array(items) => items.len(),
+                serde_json::Value::Null => 0,
+                _ => 1,
+            },
+            next_cursor,
+        };
+        ApiResponse::ok(data, meta)
+    } else {
+        let error: ErrorResponse = serde_json::from_slice(&bytes)
+            .unwrap_or_else(|_| ErrorResponse::new(String::from_utf8_lossy(&bytes)));
+        // The envelope has no retry_after field, so the hint moves to the header
+        if let Some(seconds) = error.retry_after {
+            if !head.headers().contains_key(header::RETRY_AFTER) {
+                if let Ok(value) = header::HeaderValue::from_str(&seconds.to_string()) {
+                    head.headers_mut().insert(header::RETRY_AFTER, value);
+                }
+            }
+        }
+        ApiResponse::error(error.error)
+    };
+
+    match serde_json::to_vec(&envelope) {
+        Ok(json) => {
+            head.headers_mut().insert(header::CONTENT_TYPE, header::HeaderValue::from_static("application/json"));
+            head.set_body(BoxBody::new(json))
+        }
+        Err(_) => HttpResponse::InternalServerError().json(ApiResponse::<()>::error("Failed to encode response")),
+    }
+}
+
+// Opt-in `Envelope` for the /api scope. Only sized JSON bodies are rewritten: streams
+// (the exports, server-sent events), downloads, 204s and 304s pass through as they are.
+pub async fn opt_in(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string())
+        .map(web::Query::into_inner)
+        .unwrap_or_default();
+    let wanted = query.get("envelope").is_some_and(|v| v == "true")
+        || req
+            .headers()
+            .get(ENVELOPE_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.trim().eq_ignore_ascii_case("true"));
+
+    let res = next.call(req).await?.map_into_boxed_body();
+    if !wanted || !is_plain_json(res.response()) {
+        return Ok(res);
+    }
+    let (req, response) = res.into_parts();
+    Ok(ServiceResponse::new(req, envelop(response, requested_page(&query)).await))
+}
+
+fn is_plain_json(response: &HttpResponse) -> bool {
+    let json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    json && matches!(response.body().size(), BodySize::Sized(_))
+        && !response.headers().contains_key(header::CONTENT_DISPOSITION)
+        && !matches!(response.status(), StatusCode::NO_CONTENT | StatusCode::NOT_MODIFIED)
+}
+
+// Pagination as the request asked for it; the handler validates the same parameters
+fn requested_page(query: &HashMap<String, String>) -> Option<EnvelopePagination> {
+    let number = |key: &str| query.get(key).and_then(|v| v.parse::<u64>().ok());
+    let per_page = number("per_page").unwrap_or(DEFAULT_PER_PAGE);
+    if query.contains_key("cursor") {
+        Some(EnvelopePagination { page: None, per_page, next_cursor: None })
+    } else if query.contains_key("page") || query.contains_key("per_page") {
+        Some(EnvelopePagination { page: Some(number("page").unwrap_or(1)), per_page, next_cursor: None })
+    } else {
+        None
+    }
+}
+
+async fn envelop(response: HttpResponse, pagination: Option<EnvelopePagination>) -> HttpResponse {
+    let status = response.status();
+    let freshness = response.extensions().get::<Freshness>().copied();
+    let header_value = |name: &str| {
+        response.headers().get(name).and_then(|value| value.to_str().ok()).map(String::from)
+    };
+    // Handlers say where their data came from through `Freshness`; single-token lookups
+    // through X-Cache-Age, which is 0 for a fresh upstream answer
+    let cache = match (freshness, header_value("X-Cache-Age")) {
+        (Some(freshness), _) => Some(freshness.from_cache),
+        (None, Some(age)) => Some(age != "0"),
+        (None, None) => None,
+    }
+    .map(|hit| if hit { CacheOutcome::Hit } else { CacheOutcome::Miss });
+    let next_cursor = header_value(NEXT_CURSOR_HEADER);
+    let retry_after = header_value(header::RETRY_AFTER.as_str()).and_then(|v| v.parse().ok());
+    let (head, body) = response.into_parts();
+
+    let Ok(bytes) = to_bytes(body).await else {
+        return HttpResponse::InternalServerError().json(ErrorResponse::new("Failed to read response"));
+    };
+
+    let envelope = if status.is_success() {
+        let data: serde_json::Value = serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null);
+        let pagination = pagination.map(|pagination| EnvelopePagination { next_cursor, ..pagination });
+        Envelope {
+            data: Some(data),
+            meta: Some(EnvelopeMeta { generated_at: Utc::now(), cache, pagination }),
+            error: None,
+        }
+    } else {
+        let error: ErrorResponse = serde_json::from_slice(&bytes)
+            .unwrap_or_else(|_| ErrorResponse::new(String::from_utf8_lossy(&bytes)));
+        let code = status.canonical_reason().unwrap_or("error").to_lowercase().replace([' ', '-'], "_");
+        Envelope {
+            data: None,
+            meta: None,
+            error: Some(EnvelopeError {
+                code,
+                message: error.error,
+                retry_after: error.retry_after.or(retry_after),
+                fields: error.fields,
+            }),
+        }
+    };
+
+    match serde_json::to_vec(&envelope) {
+        Ok(json) => head.set_body(BoxBody::new(json)),
+        Err(_) => HttpResponse::InternalServerError().json(ErrorResponse::new("Failed to encode response")),
+    }
+}
+
+// Extractor failures under /api/v2, in the envelope instead of a bare ErrorResponse
+pub fn bad_request<E: std::fmt::Display + std::fmt::Debug + 'static>(err: E, _req: &HttpRequest) -> actix_web::Error {
+    let response = HttpResponse::BadRequest().json(ApiResponse::<()>::error(err.to_string()));
+    error::InternalError::from_response(err, response).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_success_is_wrapped_with_meta() {
+        let response = attach(
+            HttpResponse::Ok().insert_header(("X-Partial-Result", "true")).json(serde_json::json!([1, 2, 3])),
+            Freshness { stale: true, cache_age_seconds: 90, from_cache: true },
+        );
+        let wrapped = wrap(response).await;
+
+        assert_eq!(wrapped.status(), 200);
+        assert_eq!(wrapped.headers().get("X-Partial-Result").unwrap(), "true");
+        let body: serde_json::Value = serde_json::from_slice(&to_bytes(wrapped.into_body()).await.unwrap()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "data": [1, 2, 3],
+                "meta": { "stale": true, "cache_age_seconds": 90, "count": 3 },
+                "error": null
+            })
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_error_keeps_status_and_moves_retry_after_to_header() {
+        let response = HttpResponse::ServiceUnavailable().json(ErrorResponse::new("try later").with_retry_after(60));
+        let wrapped = wrap(response).await;
+
+        assert_eq!(wrapped.status(), 503);
+        assert_eq!(wrapped.headers().get(header::RETRY_AFTER).unwrap(), "60");
+        let body: serde_json::Value = serde_json::from_slice(&to_bytes(wrapped.into_body()).await.unwrap()).unwrap();
+        assert_eq!(body, serde_json::json!({ "data": null, "meta": null, "error": "try later" }));
+    }
+
+    #[test]
+    fn test_streams_and_downloads_are_not_plain_json() {
+        assert!(is_plain_json(&HttpResponse::Ok().json(serde_json::json!([1]))));
+        assert!(is_plain_json(&HttpResponse::NotFound().json(ErrorResponse::new("Token not found"))));
+
+        let stream = futures::stream::once(async { Ok::<_, Error>(actix_web::web::Bytes::from_static(b"[]")) });
+        assert!(!is_plain_json(&HttpResponse::Ok().content_type("application/json").streaming(stream)));
+        let download = HttpResponse::Ok()
+            .insert_header((header::CONTENT_DISPOSITION, "attachment; filename=\"tokens.json\""))
+            .json(serde_json::json!([]));
+        assert!(!is_plain_json(&download));
+        assert!(!is_plain_json(&HttpResponse::Ok().content_type("text/event-stream").body("data: 1\n\n")));
+        assert!(!is_plain_json(&HttpResponse::NoContent().finish()));
+    }
+}
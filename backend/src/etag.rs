@@ -0,0 +1,70 @@
+use actix_web::http::header;
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+// Weak validator over the serialized body: equal JSON means equal tag, whatever
+// produced it (memory cache, MongoDB or a fresh upstream fetch)
+pub fn weak_etag(body: &[u8]) -> String {
+    let digest = Sha256::digest(body);
+    let hex: String = digest[..16].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("W/\"{}\"", hex)
+}
+
+// Whether an If-None-Match header matches `etag`. Uses the weak comparison the header
+// calls for, so `W/` prefixes on either side are ignored.
+fn matches(if_none_match: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    let etag = opaque(etag);
+    if_none_match
+        .split(',')
+        .any(|candidate| candidate.trim() == "*" || opaque(candidate) == etag)
+}
+
+// Serializes `body` into the response started by `builder`, with an ETag. When the
+// request's If-None-Match already has that tag, the same headers go out on an empty 304.
+pub fn respond<T: Serialize>(req: &HttpRequest, mut builder: HttpResponseBuilder, body: &T) -> HttpResponse {
+    let json = match serde_json::to_vec(body) {
+        Ok(json) => json,
+        // Nothing to tag; let the regular path produce its error
+        Err(_) => return builder.json(body),
+    };
+    let etag = weak_etag(&json);
+    builder.insert_header((header::ETAG, etag.as_str()));
+
+    let not_modified = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| matches(v, &etag));
+    if not_modified {
+        return builder.status(actix_web::http::StatusCode::NOT_MODIFIED).finish();
+    }
+
+    builder.insert_header(header::ContentType::json()).body(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_etag_is_weak_and_tracks_content() {
+        let a = weak_etag(br#"{"total_tokens":1}"#);
+        assert!(a.starts_with("W/\"") && a.ends_with('"'));
+        assert_eq!(a, weak_etag(br#"{"total_tokens":1}"#));
+        assert_ne!(a, weak_etag(br#"{"total_tokens":2}"#));
+    }
+
+    #[test]
+    fn test_if_none_match_uses_weak_comparison() {
+        let etag = weak_etag(b"[]");
+        let opaque = etag.trim_start_matches("W/");
+
+        assert!(matches(&etag, &etag));
+        assert!(matches(opaque, &etag));
+        assert!(matches(&format!("W/\"other\", {}", etag), &etag));
+        assert!(matches("*", &etag));
+        assert!(!matches("W/\"other\"", &etag));
+    }
+}
@@ -0,0 +1,122 @@
+use chrono::{Duration, Utc};
+use mongodb::bson::Document;
+use crate::alerts;
+use crate::config::Config;
+use crate::db::DbClient;
+use crate::models::{AlertTrigger, CryptoToken, Event, EventKind};
+use crate::webhook::WebhookNotifier;
+
+// What a refresh changed about a token's price, given the fields the stored copy had
+// before the write (`ath` and `current_price`). Missing or non-positive stored values
+// can't be compared against and yield nothing.
+pub fn detect(token: &CryptoToken, previous: &Document, large_move_percent: f64) -> Vec<Event> {
+    let price = token.current_price;
+    let mut events = Vec::new();
+    if !price.is_finite() {
+        return events;
+    }
+    let event = |kind, old_value| Event {
+        token_id: token.token_id.clone(),
+        kind,
+        old_value,
+        new_value: price,
+        at: Utc::now(),
+    };
+
+    if let Ok(previous_ath) = previous.get_f64("ath") {
+        if previous_ath > 0.0 && price > previous_ath {
+            events.push(event(EventKind::AthBreak, previous_ath));
+        }
+    }
+    if let Ok(previous_price) = previous.get_f64("current_price") {
+        if previous_price > 0.0 {
+            let change_percentage = (price - previous_price) / previous_price * 100.0;
+            if change_percentage.abs() >= large_move_percent {
+                events.push(event(EventKind::LargeMove { change_percentage }, previous_price));
+            }
+        }
+    }
+    events
+}
+
+// Records the events refreshes notice into the `events` collection
+#[derive(Clone, Default)]
+pub struct EventRecorder {
+    db: Option<DbClient>,
+    large_move_percent: f64,
+    dedup_window: Duration,
+    alert_cooldown: Duration,
+}
+
+impl EventRecorder {
+    pub fn new(db: DbClient, config: &Config) -> Self {
+        Self {
+            db: Some(db),
+            large_move_percent: config.large_move_percent,
+            dedup_window: Duration::seconds(config.event_dedup_window_secs as i64),
+            alert_cooldown: Duration::seconds(config.alert_cooldown_secs as i64),
+        }
+    }
+
+    // Records nothing; for tests and tools that never store events
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    // Records what changed between `previous` and `token`, returning the events stored.
+    // Ones already recorded within the dedup window are skipped.
+    pub async fn observe(&self, token: &CryptoToken, previous: &Document) -> Vec<Event> {
+        let Some(db) = &self.db else {
+            return Vec::new();
+        };
+        let mut stored = Vec::new();
+        for event in detect(token, previous, self.large_move_percent) {
+            match db.record_event(&event, self.dedup_window).await {
+                Ok(true) => stored.push(event),
+                Ok(false) => {}
+                Err(e) => {
+                    tracing::warn!(token_id = %event.token_id, kind = event.kind.name(), error = %e, "Failed to record event");
+                }
+            }
+        }
+        stored
+    }
+
+    // Runs the stored alerts over a refresh, returning the triggers that weren't in cool-down
+    pub async fn check_alerts(&self, tokens: &[CryptoToken], notifier: &WebhookNotifier) -> Vec<AlertTrigger> {
+        match &self.db {
+            Some(db) => alerts::check(db, tokens, self.alert_cooldown, notifier).await,
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::test_token;
+    use mongodb::bson::doc;
+
+    fn token(price: f64) -> CryptoToken {
+        CryptoToken { symbol: "btc".to_string(), name: "Bitcoin".to_string(), ..test_token("bitcoin", price, 1000000.0) }
+    }
+
+    #[test]
+    fn test_detects_ath_break_and_large_move() {
+        let events = detect(&token(120.0), &doc! { "ath": 110.0, "current_price": 100.0 }, 10.0);
+        let kinds: Vec<_> = events.iter().map(|e| e.kind).collect();
+        assert_eq!(kinds, vec![EventKind::AthBreak, EventKind::LargeMove { change_percentage: 20.0 }]);
+        assert_eq!((events[0].old_value, events[0].new_value), (110.0, 120.0));
+        assert_eq!(events[1].old_value, 100.0);
+    }
+
+    #[test]
+    fn test_small_moves_and_unknown_values_are_ignored() {
+        assert!(detect(&token(105.0), &doc! { "ath": 110.0, "current_price": 100.0 }, 10.0).is_empty());
+        assert!(detect(&token(105.0), &doc! { "ath": 0.0, "current_price": 0.0 }, 1.0).is_empty());
+        assert!(detect(&token(105.0), &doc! {}, 1.0).is_empty());
+
+        let drop = detect(&token(80.0), &doc! { "current_price": 100.0 }, 10.0);
+        assert_eq!(drop[0].kind, EventKind::LargeMove { change_percentage: -20.0 });
+    }
+}
@@ -0,0 +1,216 @@
+use chrono::Utc;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::binance::BinanceService;
+use crate::config::Config;
+use crate::db::DbClient;
+use crate::models::{BinanceTicker, CryptoToken, PriceSource};
+
+// Tickers are reused this long so an outage doesn't turn every request into a Binance call
+const TICKER_REUSE: Duration = Duration::from_secs(10);
+
+struct RecentTickers {
+    fetched_at: Instant,
+    symbols: Vec<String>,
+    tickers: Arc<Vec<BinanceTicker>>,
+}
+
+// Second price source for when CoinGecko fails or we're backing off from it.
+// Only tokens with a row in the symbol_map collection are refreshed; the rest
+// keep their cached values.
+pub struct FallbackProvider {
+    binance: Option<BinanceService>,
+    recent: Mutex<Option<RecentTickers>>,
+}
+
+impl FallbackProvider {
+    pub fn new(binance: Option<BinanceService>) -> Self {
+        Self {
+            binance,
+            recent: Mutex::new(None),
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(BinanceService::from_config(config))
+    }
+
+    pub fn disabled() -> Self {
+        Self::new(None)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.binance.is_some()
+    }
+
+    // The cached list with Binance prices patched in where possible. Any failure
+    // (Mongo, Binance) just returns the list unchanged.
+    pub async fn refresh(&self, db: &DbClient, tokens: Arc<Vec<CryptoToken>>) -> Arc<Vec<CryptoToken>> {
+        let Some(binance) = &self.binance else {
+            return tokens;
+        };
+
+        let symbols = match db.load_symbol_map().await {
+            Ok(symbols) => symbols,
+            Err(e) => {
+                tracing::warn!("Failed to load Binance symbol map: {}", e);
+                return tokens;
+            }
+        };
+
+        let mut wanted: Vec<String> = tokens
+            .iter()
+            .filter_map(|t| symbols.get(&t.token_id).cloned())
+            .collect();
+        wanted.sort();
+        wanted.dedup();
+        if wanted.is_empty() {
+            return tokens;
+        }
+
+        let tickers = match self.recent_tickers(&wanted) {
+            Some(tickers) => tickers,
+            None => match binance.fetch_tickers(&wanted).await {
+                Ok(tickers) => {
+                    let tickers = Arc::new(tickers);
+                    *self.recent.lock().unwrap_or_else(|e| e.into_inner()) = Some(RecentTickers {
+                        fetched_at: Instant::now(),
+                        symbols: wanted,
+                        tickers: tickers.clone(),
+                    });
+                    tickers
+                }
+                Err(e) => {
+                    tracing::warn!("Binance fallback failed: {}", e);
+                    return tokens;
+                }
+            },
+        };
+
+        let patched = apply_tickers(&tokens, &symbols, &tickers);
+        tracing::info!("Refreshed prices for {} tokens from Binance", tickers.len());
+        Arc::new(patched)
+    }
+
+    fn recent_tickers(&self, wanted: &[String]) -> Option<Arc<Vec<BinanceTicker>>> {
+        let recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        match &*recent {
+            Some(recent) if recent.fetched_at.elapsed() < TICKER_REUSE && recent.symbols == wanted => {
+                Some(recent.tickers.clone())
+            }
+            _ => None,
+        }
+    }
+}
+
+// Overwrites price, 24h change, high/low and volume for every token whose mapped
+// symbol has a parseable ticker
+pub fn apply_tickers(
+    tokens: &[CryptoToken],
+    symbols: &HashMap<String, String>,
+    tickers: &[BinanceTicker],
+) -> Vec<CryptoToken> {
+    let by_symbol: HashMap<&str, &BinanceTicker> = tickers.iter().map(|t| (t.symbol.as_str(), t)).collect();
+    let now = Utc::now();
+
+    tokens
+        .iter()
+        .map(|token| {
+            let ticker = symbols
+                .get(&token.token_id)
+                .and_then(|symbol| by_symbol.get(symbol.as_str()));
+            let Some(ticker) = ticker else {
+                return token.clone();
+            };
+
+            let number = |raw: &str| raw.parse::<f64>().ok().filter(|v| v.is_finite());
+            let (Some(price), Some(change), Some(change_pct), Some(volume)) = (
+                number(&ticker.last_price),
+                number(&ticker.price_change),
+                number(&ticker.price_change_percent),
+                number(&ticker.quote_volume),
+            ) else {
+                tracing::warn!("Skipping unreadable Binance ticker for {}", ticker.symbol);
+                return token.clone();
+            };
+
+            CryptoToken {
+                current_price: price,
+                price_change_24h: change,
+                price_change_percentage_24h: change_pct,
+                price_change_percentage_7d: None,
+                price_change_percentage_30d: None,
+                price_change_percentage_1y: None,
+                sparkline_7d: None,
+                volume_24h: volume,
+                high_24h: number(&ticker.high_price).or(token.high_24h),
+                low_24h: number(&ticker.low_price).or(token.low_24h),
+                last_updated: now,
+                fetched_at: Some(now),
+                price_source: Some(PriceSource::Binance),
+                ..token.clone()
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::test_token;
+
+    fn token(token_id: &str, price: f64) -> CryptoToken {
+        CryptoToken {
+            last_updated: Utc::now() - chrono::Duration::hours(1),
+            price_source: Some(PriceSource::CoinGecko),
+            ..test_token(token_id, price, 1000000.0)
+        }
+    }
+
+    fn ticker(symbol: &str, price: &str) -> BinanceTicker {
+        BinanceTicker {
+            symbol: symbol.to_string(),
+            last_price: price.to_string(),
+            price_change: "100.0".to_string(),
+            price_change_percent: "1.5".to_string(),
+            high_price: "51000.0".to_string(),
+            low_price: "49000.0".to_string(),
+            quote_volume: "123456.0".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_mapped_tokens_take_binance_prices() {
+        let tokens = vec![token("bitcoin", 40000.0), token("obscure-coin", 0.5)];
+        let symbols = HashMap::from([("bitcoin".to_string(), "BTCUSDT".to_string())]);
+
+        let patched = apply_tickers(&tokens, &symbols, &[ticker("BTCUSDT", "50000.0")]);
+
+        assert_eq!(patched[0].current_price, 50000.0);
+        assert_eq!(patched[0].price_change_percentage_24h, 1.5);
+        assert_eq!(patched[0].volume_24h, 123456.0);
+        assert_eq!(patched[0].high_24h, Some(51000.0));
+        assert_eq!(patched[0].price_source, Some(PriceSource::Binance));
+        assert!(patched[0].last_updated > tokens[0].last_updated);
+
+        // Unmapped tokens keep everything they had
+        assert_eq!(patched[1].current_price, 0.5);
+        assert_eq!(patched[1].price_source, Some(PriceSource::CoinGecko));
+    }
+
+    #[test]
+    fn test_missing_or_unreadable_ticker_keeps_cached_values() {
+        let tokens = vec![token("bitcoin", 40000.0), token("ethereum", 3000.0)];
+        let symbols = HashMap::from([
+            ("bitcoin".to_string(), "BTCUSDT".to_string()),
+            ("ethereum".to_string(), "ETHUSDT".to_string()),
+        ]);
+
+        let patched = apply_tickers(&tokens, &symbols, &[ticker("BTCUSDT", "not a number")]);
+
+        assert_eq!(patched[0].current_price, 40000.0);
+        assert_eq!(patched[0].price_source, Some(PriceSource::CoinGecko));
+        assert_eq!(patched[1].current_price, 3000.0);
+    }
+}
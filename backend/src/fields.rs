@@ -0,0 +1,153 @@
+use mongodb::bson::Document;
+use serde::{ser::Error as _, Serialize, Serializer};
+use serde_json::Value;
+use utoipa::openapi::{RefOr, Schema};
+use utoipa::PartialSchema;
+use crate::models::{CryptoToken, DerivedMetrics};
+
+// Always part of a selection, so every trimmed token still says which one it is
+pub const ALWAYS_SELECTED: &str = "token_id";
+
+// Property names of a model, as its OpenAPI schema lists them
+fn schema_fields<T: PartialSchema>() -> Vec<String> {
+    match T::schema() {
+        RefOr::T(Schema::Object(object)) => object.properties.into_keys().filter(|name| name != "_id").collect(),
+        _ => Vec::new(),
+    }
+}
+
+// What ?fields= may name on /api/tokens
+pub fn token_fields() -> Vec<String> {
+    schema_fields::<CryptoToken>()
+}
+
+// What ?fields= may name on /api/tokens/{id}: the token's fields and the derived metrics
+pub fn token_detail_fields() -> Vec<String> {
+    let mut fields = token_fields();
+    fields.extend(schema_fields::<DerivedMetrics>());
+    fields.sort_unstable();
+    fields
+}
+
+// The top-level fields a client asked for with ?fields=, token_id included
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldSelection {
+    fields: Vec<String>,
+}
+
+impl FieldSelection {
+    // `raw` is the comma-separated query value; names outside `valid` are a 400 listing it
+    pub fn parse(raw: &str, valid: &[String]) -> Result<Self, String> {
+        let mut fields = vec![ALWAYS_SELECTED.to_string()];
+        let mut unknown = Vec::new();
+        for name in raw.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            if !valid.iter().any(|v| v == name) {
+                unknown.push(name);
+            } else if !fields.iter().any(|f| f == name) {
+                fields.push(name.to_string());
+            }
+        }
+        if !unknown.is_empty() {
+            return Err(format!("unknown fields: {}; valid fields are {}", unknown.join(", "), valid.join(", ")));
+        }
+        Ok(Self { fields })
+    }
+
+    pub fn fields(&self) -> &[String] {
+        &self.fields
+    }
+
+    // MongoDB projection of the selected fields plus `extra`, which whoever reads the
+    // documents needs for itself and `retain` drops again
+    pub fn projection(&self, extra: &[&str]) -> Document {
+        let mut projection = Document::new();
+        for field in self.fields.iter().map(String::as_str).chain(extra.iter().copied()) {
+            projection.insert(field, 1);
+        }
+        projection
+    }
+
+    // Keeps only the selected keys of an object, or of every object in an array
+    pub fn retain(&self, value: Value) -> Value {
+        match value {
+            Value::Object(mut object) => {
+                object.retain(|key, _| self.fields.contains(key));
+                Value::Object(object)
+            }
+            Value::Array(items) => Value::Array(items.into_iter().map(|item| self.retain(item)).collect()),
+            other => other,
+        }
+    }
+}
+
+// Serializes `value` whole, or trimmed to a selection. Trimming goes through a JSON value,
+// so a selected response lists its keys alphabetically.
+pub struct Selected<'a, T> {
+    pub value: T,
+    pub fields: Option<&'a FieldSelection>,
+}
+
+impl<T: Serialize> Serialize for Selected<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let Some(fields) = self.fields else {
+            return self.value.serialize(serializer);
+        };
+        let value = serde_json::to_value(&self.value).map_err(S::Error::custom)?;
+        fields.retain(value).serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::{doc, oid::ObjectId};
+
+    #[test]
+    fn valid_fields_come_from_the_model() {
+        let fields = token_fields();
+        for field in ["token_id", "symbol", "current_price", "ath", "sparkline_7d", "category"] {
+            assert!(fields.iter().any(|f| f == field), "{} missing", field);
+        }
+        assert!(!fields.iter().any(|f| f == "_id"));
+        assert!(!fields.iter().any(|f| f == "fdv"));
+        assert!(token_detail_fields().iter().any(|f| f == "fdv"));
+    }
+
+    #[test]
+    fn parse_always_includes_token_id() {
+        let selection = FieldSelection::parse("symbol, current_price,symbol,", &token_fields()).unwrap();
+        assert_eq!(selection.fields(), ["token_id", "symbol", "current_price"]);
+        assert_eq!(FieldSelection::parse("", &token_fields()).unwrap().fields(), ["token_id"]);
+    }
+
+    #[test]
+    fn parse_rejects_unknown_fields_listing_the_valid_ones() {
+        let err = FieldSelection::parse("symbol,price,_id", &token_fields()).unwrap_err();
+        assert!(err.starts_with("unknown fields: price, _id; valid fields are "), "{}", err);
+        assert!(err.contains("current_price"));
+    }
+
+    #[test]
+    fn projection_adds_the_extra_fields() {
+        let selection = FieldSelection::parse("ath", &token_fields()).unwrap();
+        assert_eq!(
+            selection.projection(&["_id", "market_cap"]),
+            doc! { "token_id": 1, "ath": 1, "_id": 1, "market_cap": 1 }
+        );
+    }
+
+    #[test]
+    fn projected_partial_documents_are_trimmed() {
+        // What MongoDB hands back for the projection above: no name, price or dates
+        let document = doc! { "_id": ObjectId::new(), "token_id": "bitcoin", "ath": 69000.0, "market_cap": 1.0e12 };
+        let selection = FieldSelection::parse("ath", &token_fields()).unwrap();
+        let value = serde_json::to_value(Selected { value: vec![document], fields: Some(&selection) }).unwrap();
+        assert_eq!(value, serde_json::json!([{ "token_id": "bitcoin", "ath": 69000.0 }]));
+    }
+
+    #[test]
+    fn no_selection_serializes_the_whole_value() {
+        let value = serde_json::json!({ "token_id": "bitcoin", "name": "Bitcoin" });
+        assert_eq!(serde_json::to_value(Selected { value: &value, fields: None }).unwrap(), value);
+    }
+}
@@ -0,0 +1,95 @@
+use actix_web::http::header::{self, HeaderMap};
+use actix_web::HttpRequest;
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use crate::config::Config;
+
+// Set on a forced request answered from the cache because upstream couldn't be asked
+pub const CACHE_HEADER: &str = "X-Cache";
+pub const STALE_FORCED: &str = "STALE-FORCED";
+
+// Whether the client asked to skip the cache, with `?fresh=true` or `Cache-Control: no-cache`
+pub fn requested(req: &HttpRequest, fresh: Option<bool>) -> bool {
+    fresh.unwrap_or(false) || no_cache(req.headers())
+}
+
+fn no_cache(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::CACHE_CONTROL)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|directive| directive.trim().eq_ignore_ascii_case("no-cache"))
+}
+
+// Address a forced request is counted against
+pub fn client_key(req: &HttpRequest) -> String {
+    req.connection_info().realip_remote_addr().unwrap_or("unknown").to_string()
+}
+
+// Lets each client force a fresh fetch at most once per interval, on top of the shared
+// upstream rate limiter, so one client can't spend the whole upstream budget.
+pub struct ForceFreshLimiter {
+    interval: Duration,
+    last_forced: Mutex<HashMap<String, Instant>>,
+}
+
+impl ForceFreshLimiter {
+    pub fn new(interval: Duration) -> Self {
+        Self { interval, last_forced: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(Duration::from_secs(config.force_fresh_interval_secs))
+    }
+
+    // Claims the client's slot, or returns the seconds until it frees up
+    pub fn try_acquire(&self, client: &str) -> Result<(), u64> {
+        let now = Instant::now();
+        let mut last_forced = self.last_forced.lock().unwrap_or_else(PoisonError::into_inner);
+        // Expired entries would allow a force anyway; dropping them keeps the map small
+        last_forced.retain(|_, at| now.duration_since(*at) < self.interval);
+        match last_forced.get(client) {
+            Some(at) => {
+                let wait = self.interval - now.duration_since(*at);
+                Err(wait.as_secs_f64().ceil().max(1.0) as u64)
+            }
+            None => {
+                last_forced.insert(client.to_string(), now);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn test_fresh_flag_or_no_cache_requests_a_forced_fetch() {
+        let plain = TestRequest::default().to_http_request();
+        assert!(!requested(&plain, None));
+        assert!(!requested(&plain, Some(false)));
+        assert!(requested(&plain, Some(true)));
+
+        let no_cache = TestRequest::default().insert_header((header::CACHE_CONTROL, "max-age=0, No-Cache")).to_http_request();
+        assert!(requested(&no_cache, None));
+        let max_age = TestRequest::default().insert_header((header::CACHE_CONTROL, "max-age=0")).to_http_request();
+        assert!(!requested(&max_age, None));
+    }
+
+    #[test]
+    fn test_each_client_forces_once_per_interval() {
+        let limiter = ForceFreshLimiter::new(Duration::from_secs(30));
+        assert_eq!(limiter.try_acquire("10.0.0.1"), Ok(()));
+        let retry_after = limiter.try_acquire("10.0.0.1").unwrap_err();
+        assert!((29..=30).contains(&retry_after), "{}", retry_after);
+        assert_eq!(limiter.try_acquire("10.0.0.2"), Ok(()));
+
+        let limiter = ForceFreshLimiter::new(Duration::ZERO);
+        assert_eq!(limiter.try_acquire("10.0.0.1"), Ok(()));
+        assert_eq!(limiter.try_acquire("10.0.0.1"), Ok(()));
+    }
+}
@@ -1,90 +1,142 @@
-use actix_web::{web, HttpResponse, Result};
-use mongodb::bson::doc;
-use crate::{db::DbClient, models::{FavoriteRequest, TokenStats, PriceHistory, CryptoToken}, crypto_service::CryptoService};
-use chrono::{Utc, Duration};
+use actix_web::{http::header, web, HttpRequest, HttpResponse, HttpResponseBuilder, Result};
+use mongodb::bson::{doc, oid::ObjectId};
+use crate::{analytics, backfill::BackfillStatus, auth::{self, Admin, ApiUser, MaybeUser}, config::Config, currency, envelope::{self, Freshness}, db::{stored_timestamp, DbClient, Movers}, etag, events::EventRecorder, ndjson::{self, LineSplitter}, listing::{CursorPage, ListParams, TokenFilter}, models::{is_valid_token_id, USER_OWNED_FIELDS, Alert, AlertRequest, AlertTrigger, ALERT_TRIGGER_LIMIT, AnnotationRequest, ExchangeRate, ExchangeRates, ExchangeRatesQuery, TokenAnnotation, BulkFavoriteRequest, FavoritesExport, FavoritesImport, FavoritesImportSummary, PortfolioPosition, PortfolioSummary, StoredTransaction, Transaction, TransactionEntry, Category, CoinProfile, CoinSearchQuery, CoinSearchResult, ChangeWindow, ImportFailure, ImportSummary, NewUser, StatsQuery, DailyReport, ReportQuery, ScoredToken, TokenBatch, TokenBatchQuery, TokenDetail, TokenQuery, TokenSupply, TokensQuery, USD, BulkFavoriteResponse, ConversionResult, ConvertQuery, CorrelationMatrix, CorrelationQuery, MarketCapPoint, MarketHistoryQuery, DeleteTokenQuery, TokenDeletion, PurgeQuery, TokenPurge, RepairPass, RepairQuery, RepairReport, DEFAULT_PURGE_AGE_HOURS, DominanceHistory, DominancePoint, DominanceQuery, DominanceSource, SkippedToken, MAX_HISTORY_DAYS, FavoriteMeta, FavoriteRequest, FavoritesQuery, HistoricalChange, HistoricalChangeQuery, PriceAt, PriceAtQuery, StoredHistoryQuery, HistoryDays, HistoryInterval, HistoryQuery, ListQuery, MarketStats, MoversQuery, NearAthQuery, ScreenedToken, ScreenerQuery, Overview, TokenChange, TokenStats, CryptoToken, CoinGeckoHistoricalData, CacheDebugInfo, CacheStatus, DeliveriesQuery, Event, EventsQuery, EVENT_KINDS, RateLimitStatus, Readiness, ErrorResponse, WebhookConfig, WebhookDelivery}, crypto_service::{CryptoService, CryptoServiceError}, fallback::FallbackProvider, fields::{self, FieldSelection, Selected}, image_proxy::{self, ImageProxy}, force_fresh::{self, ForceFreshLimiter}, ordering::cmp_f64, portfolio, rate_limiter::RateLimiter, report::{self, ReportFormat}, search, shutdown::BackgroundTasks, single_flight::SingleFlight, token_cache::TokenCache, upstream::UpstreamGate, webhook::{self, WebhookNotifier}};
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
 use std::sync::Arc;
-use tokio::sync::Mutex;
 
-// Simple in-memory rate limit tracker
-lazy_static::lazy_static! {
-    static ref LAST_API_CALL: Arc<Mutex<Option<chrono::DateTime<Utc>>>> = Arc::new(Mutex::new(None));
-    static ref RATE_LIMITED_UNTIL: Arc<Mutex<Option<chrono::DateTime<Utc>>>> = Arc::new(Mutex::new(None));
-}
+// Upper bound on token_ids in one POST /api/favorites/bulk
+pub const MAX_BULK_FAVORITES: usize = 200;
+// Upper bound on ids in one GET /api/tokens/batch; one CoinGecko markets page
+pub const MAX_BATCH_TOKENS: usize = 250;
+// Far more than are ever listed; bounds the $in of one import
+pub const MAX_FAVORITES_IMPORT: usize = 5000;
 
-const MIN_REQUEST_INTERVAL_SECS: i64 = 2; // Minimum 2 seconds between API calls
-const RATE_LIMIT_BACKOFF_SECS: i64 = 60; // Wait 60 seconds after rate limit
+// Default and largest `limit` for /api/gainers and /api/losers
+pub const DEFAULT_MOVERS_LIMIT: u64 = 10;
+pub const MAX_MOVERS_LIMIT: u64 = 100;
 
-async fn can_make_api_call() -> bool {
-    let rate_limited = RATE_LIMITED_UNTIL.lock().await;
-    if let Some(until) = *rate_limited {
-        if Utc::now() < until {
-            log::info!("Rate limited, waiting until {}", until);
-            return false;
-        }
-    }
-    drop(rate_limited);
+// Default and largest `limit` for /api/search
+pub const DEFAULT_SEARCH_LIMIT: usize = 50;
+pub const MAX_SEARCH_LIMIT: usize = 200;
+// Shortest query that may go to CoinGecko when nothing cached matches
+const MIN_LIVE_SEARCH_CHARS: usize = 2;
 
-    let last_call = LAST_API_CALL.lock().await;
-    if let Some(last) = *last_call {
-        if Utc::now() - last < Duration::seconds(MIN_REQUEST_INTERVAL_SECS) {
-            return false;
-        }
-    }
-    true
+// Copies what users set on the stored token onto a copy fetched upstream. Every field
+// in USER_OWNED_FIELDS is handled here.
+fn overlay_user_owned_fields(token: &mut CryptoToken, stored: &mongodb::bson::Document) {
+    token.is_favorite = stored.get_bool("is_favorite").unwrap_or(false);
+    token.hidden = stored.get_bool("hidden").unwrap_or(false);
+    token.tags = stored
+        .get_array("tags")
+        .map(|tags| tags.iter().filter_map(|tag| tag.as_str().map(String::from)).collect())
+        .unwrap_or_default();
+    token.note = stored.get_str("note").ok().map(String::from);
 }
 
-async fn record_api_call() {
-    let mut last_call = LAST_API_CALL.lock().await;
-    *last_call = Some(Utc::now());
-}
+// The whole token as stored, and the part of it a refresh may overwrite. Optional fields
+// CryptoToken skips when None (e.g. a category only filtered fetches know) are left as
+// stored rather than cleared.
+fn refresh_documents(token: &CryptoToken) -> mongodb::bson::ser::Result<(mongodb::bson::Document, mongodb::bson::Document)> {
+    let mut document = mongodb::bson::to_document(token)?;
+    document.remove("_id");
+    document.insert("last_updated", stored_timestamp(token.last_updated));
+    document.insert("fetched_at", stored_timestamp(token.fetched_at.unwrap_or_else(Utc::now)));
 
-async fn record_rate_limit() {
-    let mut rate_limited = RATE_LIMITED_UNTIL.lock().await;
-    *rate_limited = Some(Utc::now() + Duration::seconds(RATE_LIMIT_BACKOFF_SECS));
-    log::warn!("Rate limited! Backing off for {} seconds", RATE_LIMIT_BACKOFF_SECS);
+    let mut fields = document.clone();
+    for field in USER_OWNED_FIELDS {
+        fields.remove(field);
+    }
+    Ok((document, fields))
 }
 
-async fn save_tokens_to_cache(collection: &mongodb::Collection<CryptoToken>, tokens: &[CryptoToken]) {
+// Writes fetched tokens over the cached copies upstream has moved past and stamps the rest
+// as fetched, noting ATH breaks and large moves on the way, checks alerts, then drops the
+// memory cache. Also used by the startup warm-up.
+pub async fn save_tokens_to_cache(
+    collection: &mongodb::Collection<CryptoToken>,
+    token_cache: &TokenCache,
+    notifier: &WebhookNotifier,
+    events: &EventRecorder,
+    tokens: &[CryptoToken],
+) {
+    let raw = collection.clone_with_type::<mongodb::bson::Document>();
+    // A sparkline is 168 points; don't rewrite one the cached copy already holds
+    let cached = token_cache.get().await;
+    let stored_sparklines: std::collections::HashMap<&str, &Vec<f64>> = cached
+        .iter()
+        .flat_map(|tokens| tokens.iter())
+        .filter_map(|t| Some((t.token_id.as_str(), t.sparkline_7d.as_ref()?)))
+        .collect();
     for token in tokens {
-        let filter = doc! { "token_id": &token.token_id };
-        let update = doc! {
-            "$set": {
-                "token_id": &token.token_id,
-                "symbol": &token.symbol,
-                "name": &token.name,
-                "current_price": token.current_price,
-                "market_cap": token.market_cap,
-                "volume_24h": token.volume_24h,
-                "price_change_24h": token.price_change_24h,
-                "price_change_percentage_24h": token.price_change_percentage_24h,
-                "high_24h": token.high_24h,
-                "low_24h": token.low_24h,
-                "circulating_supply": token.circulating_supply,
-                "total_supply": token.total_supply,
-                "ath": token.ath,
-                "ath_change_percentage": token.ath_change_percentage,
-                "atl": token.atl,
-                "atl_change_percentage": token.atl_change_percentage,
-                "image": &token.image,
-                "last_updated": Utc::now(),
-            },
-            "$setOnInsert": {
-                "is_favorite": false,
+        let (mut document, mut fields) = match refresh_documents(token) {
+            Ok(documents) => documents,
+            Err(e) => {
+                tracing::warn!(token_id = %token.token_id, error = %e, "Failed to encode token");
+                continue;
             }
         };
-        
+        if let Some(sparkline) = &token.sparkline_7d {
+            if stored_sparklines.get(token.token_id.as_str()) == Some(&sparkline) {
+                fields.remove("sparkline_7d");
+            }
+        }
+
+        // Only overwrite a stored copy the upstream has moved past. Documents from before
+        // timestamps were stored as strings hold a BSON date, which never compares to
+        // one, so those are treated as stale too.
+        let newer = doc! {
+            "token_id": &token.token_id,
+            "$or": [
+                { "last_updated": { "$lt": stored_timestamp(token.last_updated) } },
+                { "last_updated": { "$not": { "$type": "string" } } },
+            ],
+        };
+        // The ATH and price we had before this write, to notice what the refresh changed
+        let options = mongodb::options::FindOneAndUpdateOptions::builder()
+            .projection(doc! { "ath": 1, "current_price": 1 })
+            .return_document(mongodb::options::ReturnDocument::Before)
+            .build();
+        match raw.find_one_and_update(newer, doc! { "$set": fields }, options).await {
+            Ok(Some(previous)) => {
+                if let Ok(previous_ath) = previous.get_f64("ath") {
+                    if previous_ath > 0.0 && token.current_price > previous_ath {
+                        notifier.notify(webhook::new_ath_event(token, previous_ath));
+                    }
+                }
+                events.observe(token, &previous).await;
+                continue;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                tracing::warn!(token_id = %token.token_id, error = %e, "Failed to update cached token");
+                continue;
+            }
+        }
+
+        // Nothing newer to replace: insert the token if it's new, otherwise only note that
+        // it was fetched, so an unchanged upstream copy still counts as fresh
+        let fetched_at = document.remove("fetched_at");
         let options = mongodb::options::UpdateOptions::builder()
             .upsert(true)
             .build();
-            
-        let _ = collection.update_one(filter, update, options).await;
+        let update = doc! { "$set": { "fetched_at": fetched_at }, "$setOnInsert": document };
+        if let Err(e) = collection.update_one(doc! { "token_id": &token.token_id }, update, options).await {
+            tracing::warn!(token_id = %token.token_id, error = %e, "Failed to update cached token");
+        }
     }
+    events.check_alerts(tokens, notifier).await;
+
+    token_cache.invalidate().await;
 }
 
-async fn get_cached_tokens(collection: &mongodb::Collection<CryptoToken>) -> Vec<CryptoToken> {
+async fn get_cached_tokens(
+    collection: &mongodb::Collection<CryptoToken>,
+    filter: impl Into<Option<mongodb::bson::Document>>,
+) -> Vec<CryptoToken> {
     let mut cached_tokens = Vec::new();
     
-    if let Ok(mut cursor) = collection.find(None, None).await {
+    if let Ok(mut cursor) = collection.find(filter, None).await {
         use futures::stream::StreamExt;
         while let Some(result) = cursor.next().await {
             if let Ok(token) = result {
@@ -93,322 +145,3664 @@ async fn get_cached_tokens(collection: &mongodb::Collection<CryptoToken>) -> Vec
         }
     }
     
-    // Sort by market cap descending
-    cached_tokens.sort_by(|a, b| b.market_cap.partial_cmp(&a.market_cap).unwrap_or(std::cmp::Ordering::Equal));
+    // Sort by market cap descending, ties by id so the order is the same every time
+    cached_tokens.sort_by(|a, b| cmp_f64(b.market_cap, a.market_cap).then_with(|| a.token_id.cmp(&b.token_id)));
     cached_tokens
 }
 
+// Cached token list, from memory when fresh enough, otherwise from MongoDB
+async fn load_tokens(
+    collection: &mongodb::Collection<CryptoToken>,
+    token_cache: &TokenCache,
+) -> Arc<Vec<CryptoToken>> {
+    if let Some(tokens) = token_cache.get().await {
+        return tokens;
+    }
+
+    let generation = token_cache.generation().await;
+    let tokens = get_cached_tokens(collection, None).await;
+    if tokens.is_empty() {
+        // Nothing worth remembering; an empty list would only hide the first write
+        return Arc::new(tokens);
+    }
+    token_cache.set_if_current(generation, tokens).await
+}
+
+// Cached tokens matching `filter`. The memory cache only holds the unfiltered list;
+// dropping hidden tokens from it is cheap, anything narrower is queried from MongoDB.
+async fn load_filtered_tokens(
+    collection: &mongodb::Collection<CryptoToken>,
+    token_cache: &TokenCache,
+    filter: &TokenFilter,
+) -> Arc<Vec<CryptoToken>> {
+    if filter.is_empty() {
+        load_tokens(collection, token_cache).await
+    } else if filter.hides_only() {
+        let tokens = load_tokens(collection, token_cache).await;
+        Arc::new(tokens.iter().filter(|t| filter.matches(t)).cloned().collect())
+    } else {
+        Arc::new(get_cached_tokens(collection, filter.to_document()).await)
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens",
+    tag = "tokens",
+    params(
+        ("sort_by" = Option<String>, Query,
+            description = "market_cap, current_price, volume_24h, price_change_percentage_24h, price_change_percentage_7d, price_change_percentage_30d, price_change_percentage_1y, name or symbol. Tokens without the 7d, 30d or 1y change sorted by are left out"),
+        ("order" = Option<String>, Query, description = "asc or desc; numbers default to desc, names to asc"),
+        ("page" = Option<u64>, Query, minimum = 1, description = "1-based page number, defaults to 1"),
+        ("per_page" = Option<u64>, Query, minimum = 1, maximum = 250,
+            description = "Page size, defaults to 20. Without page or per_page the whole list is returned"),
+        ("category" = Option<String>, Query, pattern = "^[a-z0-9-]{1,100}$",
+            description = "CoinGecko category id from /api/categories, e.g. decentralized-finance-defi"),
+        ("exclude_stablecoins" = Option<bool>, Query,
+            description = "Leave out tokens in the `stablecoins` category or with a known stablecoin symbol"),
+        ("cursor" = Option<String>, Query,
+            description = "Cursor pagination over the stored tokens: empty for the first page, then the previous page's next cursor. Not combinable with page"),
+        ("include_hidden" = Option<bool>, Query, description = "Also list tokens hidden through /api/admin/tokens/{id}/hide"),
+        ("sparkline" = Option<bool>, Query, description = "Include `sparkline_7d`, hourly prices over the last 7 days, where known"),
+        ("tag" = Option<String>, Query, description = "Only tokens annotated with this tag through /api/tokens/{id}/annotation"),
+        ("direction" = Option<String>, Query,
+            description = "up or down keeps only tokens whose 24h change is positive or negative (unchanged ones are in neither); all, the default, keeps both"),
+        ("fresh" = Option<bool>, Query,
+            description = "Ask upstream even if the cache is young, like `Cache-Control: no-cache`. Once per FORCE_FRESH_INTERVAL_SECS per client, and only when the rate limiter allows"),
+        ("fields" = Option<String>, Query,
+            description = "Comma-separated token fields to return, e.g. `symbol,current_price,ath`; token_id is always included")
+    ),
+    responses(
+        (status = 200, description = "Top TOP_TOKENS_LIMIT tokens (100 by default) by market cap, live or from cache", body = Vec<CryptoToken>,
+            headers(
+                ("X-Partial-Result" = String, description = "`true` when upstream paging stopped early"),
+                ("X-Cache" = String, description = "`STALE-FORCED` when a fresh fetch was asked for but not allowed, so the cache answered"),
+                ("Retry-After" = u64, description = "With STALE-FORCED from the per-client limit: seconds until this client may force again"),
+                ("ETag" = String, description = "Weak validator for If-None-Match"),
+                ("X-Next-Cursor" = String, description = "With cursor: pass it as cursor for the next page; absent on the last one")
+            )),
+        (status = 304, description = "If-None-Match matched the current list"),
+        (status = 400, description = "Unknown sort_by/order/direction or field, page out of range, malformed category or invalid/expired cursor", body = ErrorResponse),
+        (status = 503, description = "Rate limited and nothing cached yet", body = ErrorResponse)
+    )
+)]
+// One extractor per piece of app state, as actix handlers go
+#[allow(clippy::too_many_arguments)]
 pub async fn get_tokens(
+    req: HttpRequest,
+    config: web::Data<Config>,
     db: web::Data<DbClient>,
     crypto_service: web::Data<CryptoService>,
+    rate_limiter: web::Data<RateLimiter>,
+    background_tasks: web::Data<BackgroundTasks>,
+    token_cache: web::Data<TokenCache>,
+    notifier: web::Data<WebhookNotifier>,
+    events: web::Data<EventRecorder>,
+    fallback: web::Data<FallbackProvider>,
+    force_limiter: web::Data<ForceFreshLimiter>,
+    user: MaybeUser,
+    query: web::Query<ListQuery>,
+    filter: web::Query<TokensQuery>,
 ) -> Result<HttpResponse> {
+    let params = match ListParams::from_query(&query) {
+        Ok(params) => params,
+        Err(message) => return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message))),
+    };
+    let cursor_page = match filter.cursor.as_deref().map(|cursor| CursorPage::from_query(&query, &params, cursor)) {
+        Some(Ok(page)) => Some(page),
+        Some(Err(message)) => return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message))),
+        None => None,
+    };
+    let sparkline = filter.sparkline.unwrap_or(false);
+    let fresh_requested = force_fresh::requested(&req, filter.fresh);
+    let fields = match filter.fields.as_deref().map(|raw| FieldSelection::parse(raw, &fields::token_fields())) {
+        Some(Ok(selection)) => Some(selection),
+        Some(Err(message)) => return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message))),
+        None => None,
+    };
+    let tag = filter.tag.as_deref().map(|t| t.trim().to_lowercase()).filter(|t| !t.is_empty());
+    let mut filter = match TokenFilter::from_query(&filter) {
+        Ok(filter) => filter,
+        Err(message) => return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message))),
+    };
+    // Annotations live in their own collection; the tag narrows the list to their tokens
+    if let Some(tag) = tag {
+        match db.annotated_token_ids(&tag).await {
+            Ok(ids) => filter.token_ids = Some(ids),
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to load annotated tokens");
+                return Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")));
+            }
+        }
+    }
     let collection = db.get_tokens_collection();
+
+    // With an API key, is_favorite reflects that user's favorites instead of the shared flags
+    let user_favorites = match &user.0 {
+        Some(user) => match db.user_favorite_ids(user.id).await {
+            Ok(ids) => Some(ids),
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to load user favorites");
+                return Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")));
+            }
+        },
+        None => None,
+    };
+    // Sparklines are stored whenever fetched but only sent to clients that ask
+    let present = |mut tokens: Vec<CryptoToken>| {
+        for token in &mut tokens {
+            if let Some(favorites) = &user_favorites {
+                token.is_favorite = favorites.contains(&token.token_id);
+            }
+            if !sparkline {
+                token.sparkline_7d = None;
+            }
+        }
+        tokens
+    };
+
+    // Cursor pages come straight from MongoDB: a range query is only stable against
+    // the collection itself, not a list refetched from upstream on every request
+    if let Some(page) = cursor_page {
+        let mut response = HttpResponse::Ok();
+
+        // A field selection is projected in MongoDB, keeping what the cursor and the
+        // freshness need on top of it
+        if let Some(selection) = &fields {
+            let mut options = page.find_options();
+            let mut extra = page.key_fields().to_vec();
+            extra.extend(["fetched_at", "last_updated"]);
+            options.projection = Some(selection.projection(&extra));
+            let raw = collection.clone_with_type::<mongodb::bson::Document>();
+            let Some(mut documents) = find_cursor_page(&raw, page.filter(filter.to_document()), options).await else {
+                return Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")));
+            };
+            if let Some(next_cursor) = page.finish_documents(&mut documents) {
+                response.insert_header((envelope::NEXT_CURSOR_HEADER, next_cursor));
+            }
+            let newest = documents
+                .iter()
+                .filter_map(|d| d.get_str("fetched_at").or_else(|_| d.get_str("last_updated")).ok()?.parse().ok())
+                .max();
+            for document in &mut documents {
+                if let Some(favorites) = &user_favorites {
+                    let is_favorite = document.get_str("token_id").is_ok_and(|id| favorites.contains(id));
+                    document.insert("is_favorite", is_favorite);
+                }
+                if !sparkline {
+                    document.remove("sparkline_7d");
+                }
+            }
+            let response = etag::respond(&req, response, &Selected { value: documents, fields: Some(selection) });
+            return Ok(envelope::attach(response, Freshness::newest(newest, config.token_cache_ttl_secs)));
+        }
+
+        let Some(mut tokens) = find_cursor_page(&collection, page.filter(filter.to_document()), page.find_options()).await else {
+            return Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")));
+        };
+        if let Some(next_cursor) = page.finish(&mut tokens) {
+            response.insert_header((envelope::NEXT_CURSOR_HEADER, next_cursor));
+        }
+        let freshness = Freshness::of(&tokens, config.token_cache_ttl_secs);
+        let response = etag::respond(&req, response, &Selected { value: present(tokens), fields: None });
+        return Ok(envelope::attach(response, freshness));
+    }
     
     // Get cached tokens first
-    let cached_tokens = get_cached_tokens(&collection).await;
+    let cached_tokens = load_filtered_tokens(&collection, &token_cache, &filter).await;
     
-    // Check if we should try to refresh from API
-    if can_make_api_call().await {
-        record_api_call().await;
-        
-        match crypto_service.fetch_top_tokens(100).await {
-            Ok(tokens) if !tokens.is_empty() => {
-                log::info!("Successfully fetched {} tokens from API", tokens.len());
+    let mut primary_failed = false;
+
+    // A forced request skips the freshness check below, once per FORCE_FRESH_INTERVAL_SECS
+    // per client. The rate limiter still has the last word.
+    let force = (fresh_requested && !cached_tokens.is_empty())
+        .then(|| force_limiter.try_acquire(&force_fresh::client_key(&req)));
+
+    // A list fetched within TOKEN_CACHE_TTL_SECS is answered from the cache without
+    // spending an upstream call, unless it lacks the sparklines asked for. A TTL of 0
+    // asks upstream every time the rate limiter allows.
+    let cache_fresh = force != Some(Ok(()))
+        && config.token_cache_ttl_secs > 0
+        && !cached_tokens.is_empty()
+        && !Freshness::of(&cached_tokens, config.token_cache_ttl_secs).stale
+        && (!sparkline || cached_tokens.iter().all(|t| t.sparkline_7d.is_some()));
+
+    if !cache_fresh && rate_limiter.try_acquire().await {
+        match crypto_service.fetch_top_tokens_in(config.top_tokens_limit, filter.category.as_deref(), sparkline).await {
+            Ok(fetched) if !fetched.tokens.is_empty() => {
+                tracing::info!(count = fetched.tokens.len(), category = filter.category, "Fetched tokens from CoinGecko");
+
+                // A later page failing still leaves usable tokens; honour a 429 all the same
+                if let Some(error) = &fetched.partial {
+                    if error.error == CryptoServiceError::RateLimited {
+                        rate_limiter.record_rate_limit().await;
+                    }
+                }
+                let partial = fetched.is_partial();
+                let tokens = fetched.tokens;
+                // Upstream knows nothing of favorites, hidden flags, tags or notes; they live
+                // only in MongoDB
+                let stored = db.user_owned_fields(USER_OWNED_FIELDS).await.unwrap_or_else(|e| {
+                    tracing::warn!(error = %e, "Failed to load user-owned token fields");
+                    Default::default()
+                });
+                let listed: Vec<CryptoToken> = tokens
+                    .iter()
+                    .cloned()
+                    .map(|mut t| {
+                        if let Some(fields) = stored.get(&t.token_id) {
+                            overlay_user_owned_fields(&mut t, fields);
+                        }
+                        t
+                    })
+                    .filter(|t| filter.matches(t))
+                    .collect();
                 
-                // Save to cache in background, but return tokens immediately
+                // Save to cache in background, but return tokens immediately.
+                // Tracked so a shutdown waits for the write instead of cutting it off.
                 let save_collection = collection.clone();
                 let tokens_to_save = tokens.clone();
-                tokio::spawn(async move {
-                    save_tokens_to_cache(&save_collection, &tokens_to_save).await;
-                    log::info!("Saved {} tokens to cache", tokens_to_save.len());
+                let token_cache = token_cache.clone();
+                let notifier = notifier.clone();
+                let events = events.clone();
+                background_tasks.spawn(move |_| async move {
+                    save_tokens_to_cache(&save_collection, &token_cache, &notifier, &events, &tokens_to_save).await;
+                    tracing::info!(count = tokens_to_save.len(), "Saved tokens to cache");
                 });
                 
                 // Return the fetched tokens directly
-                return Ok(HttpResponse::Ok().json(tokens));
+                let mut response = HttpResponse::Ok();
+                if partial {
+                    response.insert_header(("X-Partial-Result", "true"));
+                }
+                let response = etag::respond(&req, response, &Selected { value: present(params.apply(&listed)), fields: fields.as_ref() });
+                return Ok(envelope::attach(response, Freshness::live()));
             }
             Ok(_) => {
-                log::warn!("API returned empty result");
+                tracing::warn!("CoinGecko returned an empty token list");
             }
             Err(e) => {
-                let error_msg = e.to_string().to_lowercase();
-                if error_msg.contains("429") || error_msg.contains("rate") {
-                    record_rate_limit().await;
+                if e == CryptoServiceError::RateLimited {
+                    rate_limiter.record_rate_limit().await;
                 }
-                log::error!("API error: {}", e);
+                tracing::error!(error = %e, "Failed to fetch tokens from CoinGecko");
+                primary_failed = true;
             }
         }
     }
     
     // Return cached data if available
     if !cached_tokens.is_empty() {
-        log::info!("Returning {} cached tokens", cached_tokens.len());
-        return Ok(HttpResponse::Ok().json(cached_tokens));
+        // CoinGecko is failing, backing off, or hasn't refreshed the cache in a while:
+        // patch in Binance prices where we can
+        let backing_off = rate_limiter.rate_limited_until().await.is_some_and(|until| until > Utc::now());
+        // Measured from our own fetches, like the envelope, not CoinGecko's last_updated
+        let stale = Freshness::of(&cached_tokens, config.token_cache_ttl_secs).stale;
+        let tokens = if primary_failed || backing_off || stale {
+            fallback.refresh(&db, cached_tokens).await
+        } else {
+            cached_tokens
+        };
+
+        tracing::info!(count = tokens.len(), "Returning cached tokens");
+        let mut response = HttpResponse::Ok();
+        if let Some(force) = force {
+            stale_forced(&mut response, force);
+        }
+        let response = etag::respond(&req, response, &Selected { value: present(params.apply(&tokens)), fields: fields.as_ref() });
+        return Ok(envelope::attach(response, Freshness::of(&tokens, config.token_cache_ttl_secs)));
     }
     
     // No cached data and can't fetch - return error with retry hint
-    Ok(HttpResponse::ServiceUnavailable().json(doc! {
-        "error": "Data temporarily unavailable. Please try again in a moment.",
-        "retry_after": 60
-    }))
+    Ok(HttpResponse::ServiceUnavailable().json(
+        ErrorResponse::new("Data temporarily unavailable. Please try again in a moment.").with_retry_after(60)
+    ))
+}
+
+// One cursor page as `CursorPage::find_options` asks for it; None when MongoDB fails
+async fn find_cursor_page<T>(
+    collection: &mongodb::Collection<T>,
+    filter: mongodb::bson::Document,
+    options: mongodb::options::FindOptions,
+) -> Option<Vec<T>>
+where
+    T: serde::de::DeserializeOwned + Unpin + Send + Sync,
+{
+    match collection.find(filter, options).await {
+        Ok(found) => match found.try_collect().await {
+            Ok(page) => Some(page),
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to read a cursor page");
+                None
+            }
+        },
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to query a cursor page");
+            None
+        }
+    }
+}
+
+// Marks a forced request answered from the cache, with when its client may force again
+// if that is what stood in the way
+fn stale_forced(response: &mut HttpResponseBuilder, force: std::result::Result<(), u64>) {
+    response.insert_header((force_fresh::CACHE_HEADER, force_fresh::STALE_FORCED));
+    if let Err(retry_after) = force {
+        response.insert_header((header::RETRY_AFTER, retry_after.to_string()));
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens/batch",
+    tag = "tokens",
+    params(
+        ("ids" = String, Query, description = "Comma-separated CoinGecko token ids, at most 250, e.g. `bitcoin,ethereum`")
+    ),
+    responses(
+        (status = 200, description = "Cached or freshly fetched tokens in the order asked for, plus `unresolved_ids`: malformed ids and ones CoinGecko doesn't list", body = TokenBatch),
+        (status = 400, description = "ids missing, empty or longer than 250", body = ErrorResponse),
+        (status = 503, description = "Some tokens aren't cached and upstream is rate limited or failing", body = ErrorResponse,
+            headers(("Retry-After" = u64, description = "Seconds until the next upstream call is allowed")))
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn get_tokens_batch(
+    db: web::Data<DbClient>,
+    crypto_service: web::Data<CryptoService>,
+    rate_limiter: web::Data<RateLimiter>,
+    background_tasks: web::Data<BackgroundTasks>,
+    token_cache: web::Data<TokenCache>,
+    notifier: web::Data<WebhookNotifier>,
+    events: web::Data<EventRecorder>,
+    query: web::Query<TokenBatchQuery>,
+) -> Result<HttpResponse> {
+    let mut requested: Vec<String> = Vec::new();
+    for id in query.ids.as_deref().unwrap_or("").split(',').map(str::trim).filter(|id| !id.is_empty()) {
+        if !requested.iter().any(|seen| seen == id) {
+            requested.push(id.to_string());
+        }
+    }
+    if requested.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("ids is required")));
+    }
+    if requested.len() > MAX_BATCH_TOKENS {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(format!("ids accepts at most {} ids", MAX_BATCH_TOKENS))));
+    }
+
+    let collection = db.get_tokens_collection();
+    let cached = load_tokens(&collection, &token_cache).await;
+    let mut found: std::collections::HashMap<String, CryptoToken> = cached
+        .iter()
+        .filter(|t| requested.contains(&t.token_id))
+        .map(|t| (t.token_id.clone(), t.clone()))
+        .collect();
+
+    // Malformed ids can't be listed upstream, so they're unresolved without asking
+    let missing: Vec<String> = requested.iter().filter(|id| is_valid_token_id(id) && !found.contains_key(*id)).cloned().collect();
+    if !missing.is_empty() {
+        let unavailable = |retry_after: u64| {
+            HttpResponse::ServiceUnavailable()
+                .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+                .json(ErrorResponse::new("Tokens not cached and upstream is rate limited").with_retry_after(retry_after))
+        };
+        if !rate_limiter.try_acquire().await {
+            return Ok(unavailable(rate_limiter.seconds_until_next_call().await.max(1)));
+        }
+
+        match crypto_service.fetch_tokens_by_ids(&missing).await {
+            Ok(fetched) => {
+                found.extend(fetched.iter().map(|t| (t.token_id.clone(), t.clone())));
+                if !fetched.is_empty() {
+                    let token_cache = token_cache.clone();
+                    let notifier = notifier.clone();
+                    let events = events.clone();
+                    background_tasks.spawn(move |_| async move {
+                        save_tokens_to_cache(&collection, &token_cache, &notifier, &events, &fetched).await;
+                    });
+                }
+            }
+            Err(e) => {
+                tracing::error!(ids = missing.len(), error = %e, "Failed to fetch tokens for batch");
+                if e == CryptoServiceError::RateLimited {
+                    rate_limiter.record_rate_limit().await;
+                }
+                return Ok(unavailable(rate_limiter.seconds_until_next_call().await.max(1)));
+            }
+        }
+    }
+
+    let mut batch = TokenBatch { tokens: Vec::with_capacity(found.len()), unresolved_ids: Vec::new() };
+    for id in requested {
+        match found.remove(&id) {
+            Some(token) => batch.tokens.push(token),
+            None => batch.unresolved_ids.push(id),
+        }
+    }
+    if !batch.unresolved_ids.is_empty() {
+        tracing::info!(unresolved = ?batch.unresolved_ids, "Batch ids CoinGecko doesn't list");
+    }
+    Ok(HttpResponse::Ok().json(batch))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/tokens/{id}",
+    tag = "tokens",
+    params(
+        ("id" = String, Path, description = "CoinGecko token id, e.g. `bitcoin`"),
+        ("fresh" = Option<bool>, Query,
+            description = "Refetch a cached token now, like `Cache-Control: no-cache`. Once per FORCE_FRESH_INTERVAL_SECS per client, and only when the rate limiter allows"),
+        ("fields" = Option<String>, Query,
+            description = "Comma-separated token or derived metric fields to return, e.g. `symbol,current_price,fdv`; token_id is always included")
+    ),
+    responses(
+        (status = 200, description = "Token details with derived supply metrics, possibly stale while a refresh runs in the background", body = TokenDetail,
+            headers(
+                ("X-Cache-Age" = u64, description = "Seconds since the token was last refreshed"),
+                ("X-Cache" = String, description = "`STALE-FORCED` when a fresh fetch was asked for but not allowed or failed, so the cache answered"),
+                ("Retry-After" = u64, description = "With STALE-FORCED from the per-client limit: seconds until this client may force again")
+            )),
+        (status = 400, description = "Unknown field; the message lists the valid ones", body = ErrorResponse),
+        (status = 404, description = "CoinGecko does not know this token", body = ErrorResponse),
+        (status = 503, description = "Token not cached and upstream is rate limited or failing", body = ErrorResponse,
+            headers(("Retry-After" = u64, description = "Seconds until the next upstream call is allowed")))
+    )
+)]
+#[allow(clippy::too_many_arguments)]
 pub async fn get_token(
+    req: HttpRequest,
+    config: web::Data<Config>,
     db: web::Data<DbClient>,
     crypto_service: web::Data<CryptoService>,
+    rate_limiter: web::Data<RateLimiter>,
+    background_tasks: web::Data<BackgroundTasks>,
+    token_cache: web::Data<TokenCache>,
+    notifier: web::Data<WebhookNotifier>,
+    events: web::Data<EventRecorder>,
+    upstream: web::Data<UpstreamGate>,
+    force_limiter: web::Data<ForceFreshLimiter>,
     token_id: web::Path<String>,
+    query: web::Query<TokenQuery>,
 ) -> Result<HttpResponse> {
+    let fields = match query.fields.as_deref().map(|raw| FieldSelection::parse(raw, &fields::token_detail_fields())) {
+        Some(Ok(selection)) => Some(selection),
+        Some(Err(message)) => return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message))),
+        None => None,
+    };
+    // Cached, refetched or fetched for the first time, the token is trimmed the same way
+    let detail = |token: CryptoToken| Selected { value: TokenDetail::from(token), fields: fields.as_ref() };
     let collection = db.get_tokens_collection();
     
-    // Try cached first
+    // Serve the cached copy right away, refreshing it in the background once it's too old
     if let Ok(Some(token)) = collection.find_one(doc! { "token_id": token_id.as_str() }, None).await {
-        return Ok(HttpResponse::Ok().json(token));
+        let fetched_at = token.fetched_at.unwrap_or(token.last_updated);
+        let age_secs = (Utc::now() - fetched_at).num_seconds().max(0) as u64;
+
+        // A forced request waits for the refetch instead, unless its client forced one
+        // too recently or the rate limiter says no
+        let force = force_fresh::requested(&req, query.fresh)
+            .then(|| force_limiter.try_acquire(&force_fresh::client_key(&req)));
+        if force == Some(Ok(())) {
+            if rate_limiter.try_acquire().await {
+                match upstream.run(crypto_service.fetch_token_details(&token_id)).await {
+                    Ok(mut fresh) => {
+                        save_tokens_to_cache(&collection, &token_cache, &notifier, &events, std::slice::from_ref(&fresh)).await;
+                        // The refetched copy knows nothing of what users set on the stored one
+                        if let Ok(stored) = mongodb::bson::to_document(&token) {
+                            overlay_user_owned_fields(&mut fresh, &stored);
+                        }
+                        return Ok(HttpResponse::Ok().insert_header(("X-Cache-Age", "0")).json(detail(fresh)));
+                    }
+                    Err(error) => {
+                        if error == CryptoServiceError::RateLimited {
+                            rate_limiter.record_rate_limit().await;
+                        }
+                        tracing::warn!(token_id = %token_id, error = %error, "Forced refresh failed");
+                    }
+                }
+            }
+        } else if age_secs > config.token_detail_max_age_secs && rate_limiter.try_acquire().await {
+            let crypto_service = crypto_service.clone();
+            let rate_limiter = rate_limiter.clone();
+            let collection = collection.clone();
+            let token_cache = token_cache.clone();
+            let notifier = notifier.clone();
+            let events = events.clone();
+            let upstream = upstream.clone();
+            let token_id = token_id.clone();
+            background_tasks.spawn(move |_| async move {
+                match upstream.run(crypto_service.fetch_token_details(&token_id)).await {
+                    Ok(fresh) => {
+                        save_tokens_to_cache(&collection, &token_cache, &notifier, &events, std::slice::from_ref(&fresh)).await;
+                        tracing::info!(token_id = %token_id, "Refreshed stale cache entry");
+                    }
+                    Err(error) => {
+                        if error == CryptoServiceError::RateLimited {
+                            rate_limiter.record_rate_limit().await;
+                        }
+                        tracing::warn!(token_id = %token_id, error = %error, "Background refresh failed");
+                    }
+                }
+            });
+        }
+
+        let mut response = HttpResponse::Ok();
+        response.insert_header(("X-Cache-Age", age_secs.to_string()));
+        if let Some(force) = force {
+            stale_forced(&mut response, force);
+        }
+        return Ok(response.json(detail(token)));
     }
     
     // Try API if not rate limited
-    if can_make_api_call().await {
-        record_api_call().await;
-        
-        match crypto_service.fetch_token_details(&token_id).await {
+    if rate_limiter.try_acquire().await {
+        tracing::info!(token_id = %token_id, "Token not cached, fetching from CoinGecko");
+        match upstream.run(crypto_service.fetch_token_details(&token_id)).await {
             Ok(token) => {
-                save_tokens_to_cache(&collection, &[token.clone()]).await;
-                return Ok(HttpResponse::Ok().json(token));
+                save_tokens_to_cache(&collection, &token_cache, &notifier, &events, std::slice::from_ref(&token)).await;
+                return Ok(HttpResponse::Ok().insert_header(("X-Cache-Age", "0")).json(detail(token)));
             }
             Err(e) => {
-                let error_msg = e.to_string().to_lowercase();
-                if error_msg.contains("429") || error_msg.contains("rate") {
-                    record_rate_limit().await;
+                tracing::error!(token_id = %token_id, error = %e, "Failed to fetch token details");
+                match e {
+                    CryptoServiceError::NotFound => {
+                        return Ok(HttpResponse::NotFound().json(ErrorResponse::new("Token not found")));
+                    }
+                    CryptoServiceError::RateLimited => rate_limiter.record_rate_limit().await,
+                    CryptoServiceError::Busy => {
+                        return Ok(HttpResponse::ServiceUnavailable()
+                            .insert_header((header::RETRY_AFTER, "1"))
+                            .json(ErrorResponse::new("Too many upstream requests in flight").with_retry_after(1)));
+                    }
+                    _ => {
+                        return Ok(HttpResponse::ServiceUnavailable()
+                            .insert_header((header::RETRY_AFTER, "30"))
+                            .json(ErrorResponse::new("Token not cached and CoinGecko is unavailable").with_retry_after(30)));
+                    }
                 }
-                log::error!("Error fetching token details: {}", e);
             }
         }
     }
-    
-    Ok(HttpResponse::NotFound().json(doc! {
-        "error": "Token not found"
-    }))
+
+    // Not cached and we can't ask upstream right now, so we don't know whether it exists
+    let retry_after = rate_limiter.seconds_until_next_call().await.max(1);
+    Ok(HttpResponse::ServiceUnavailable()
+        .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+        .json(ErrorResponse::new("Token not cached and upstream is rate limited").with_retry_after(retry_after)))
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/tokens/favorite",
+    tag = "favorites",
+    request_body = FavoriteRequest,
+    security((), ("api_key" = [])),
+    responses(
+        (status = 200, description = "Token with its favorite flag flipped, for the key's user when one is sent", body = CryptoToken),
+        (status = 401, description = "Unknown API key", body = ErrorResponse),
+        (status = 404, description = "Token not in the cache", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
 pub async fn toggle_favorite(
     db: web::Data<DbClient>,
+    token_cache: web::Data<TokenCache>,
+    user: MaybeUser,
     req: web::Json<FavoriteRequest>,
 ) -> Result<HttpResponse> {
     let collection = db.get_tokens_collection();
-    
-    // First, get current token to toggle favorite
     let filter = doc! { "token_id": &req.token_id };
-    
-    match collection.find_one(filter.clone(), None).await {
-        Ok(Some(token)) => {
-            let new_favorite = !token.is_favorite;
-            let update = doc! {
-                "$set": {
-                    "is_favorite": new_favorite
-                }
-            };
-            
-            match collection.update_one(filter.clone(), update, None).await {
-                Ok(_) => {
-                    match collection.find_one(filter, None).await {
-                        Ok(Some(token)) => Ok(HttpResponse::Ok().json(token)),
-                        _ => Ok(HttpResponse::Ok().json(doc! {
-                            "message": "Favorite updated successfully"
-                        })),
-                    }
+
+    if let Some(user) = user.0 {
+        return match collection.find_one(filter, None).await {
+            Ok(Some(mut token)) => match db.toggle_user_favorite(user.id, &req.token_id).await {
+                Ok(is_favorite) => {
+                    token.is_favorite = is_favorite;
+                    Ok(HttpResponse::Ok().json(token))
                 }
                 Err(e) => {
-                    log::error!("Failed to update favorite: {}", e);
-                    Ok(HttpResponse::InternalServerError().json(doc! {
-                        "error": "Failed to update favorite"
-                    }))
+                    tracing::error!(token_id = %req.token_id, error = %e, "Failed to update user favorite");
+                    Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Failed to update favorite")))
                 }
+            },
+            Ok(None) => Ok(HttpResponse::NotFound().json(ErrorResponse::new("Token not found"))),
+            Err(e) => {
+                tracing::error!(token_id = %req.token_id, error = %e, "Failed to find token");
+                Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")))
             }
+        };
+    }
+
+    // One atomic round trip: flipping the stored value in an update pipeline means two
+    // concurrent toggles can't both read false and both write true
+    let flip = vec![doc! { "$set": { "is_favorite": { "$not": ["$is_favorite"] } } }];
+    let options = mongodb::options::FindOneAndUpdateOptions::builder()
+        .return_document(mongodb::options::ReturnDocument::After)
+        .build();
+    match collection.find_one_and_update(filter, mongodb::options::UpdateModifications::Pipeline(flip), options).await {
+        Ok(Some(token)) => {
+            token_cache.invalidate().await;
+            Ok(HttpResponse::Ok().json(token))
+        }
+        Ok(None) => Ok(HttpResponse::NotFound().json(ErrorResponse::new("Token not found"))),
+        Err(e) => {
+            tracing::error!(token_id = %req.token_id, error = %e, "Failed to update favorite");
+            Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Failed to update favorite")))
         }
-        Ok(None) => {
-            Ok(HttpResponse::NotFound().json(doc! {
-                "error": "Token not found"
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/favorites/bulk",
+    tag = "favorites",
+    request_body = BulkFavoriteRequest,
+    responses(
+        (status = 200, description = "Counts of cached tokens matched and changed", body = BulkFavoriteResponse),
+        (status = 400, description = "token_ids empty or longer than 200", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn bulk_favorites(
+    db: web::Data<DbClient>,
+    token_cache: web::Data<TokenCache>,
+    req: web::Json<BulkFavoriteRequest>,
+) -> Result<HttpResponse> {
+    if req.token_ids.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("token_ids must not be empty")));
+    }
+    if req.token_ids.len() > MAX_BULK_FAVORITES {
+        return Ok(HttpResponse::BadRequest().json(
+            ErrorResponse::new(format!("token_ids accepts at most {} ids", MAX_BULK_FAVORITES))
+        ));
+    }
+
+    let collection = db.get_tokens_collection();
+
+    // Sets rather than toggles, so replaying the same request is harmless
+    let filter = doc! { "token_id": { "$in": &req.token_ids } };
+    let update = doc! { "$set": { "is_favorite": req.favorite } };
+
+    match collection.update_many(filter, update, None).await {
+        Ok(result) => {
+            token_cache.invalidate().await;
+            Ok(HttpResponse::Ok().json(BulkFavoriteResponse {
+                matched: result.matched_count,
+                modified: result.modified_count,
             }))
         }
         Err(e) => {
-            log::error!("Failed to find token: {}", e);
-            Ok(HttpResponse::InternalServerError().json(doc! {
-                "error": "Database error"
-            }))
+            tracing::error!(error = %e, "Failed to update favorites");
+            Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Failed to update favorites")))
         }
     }
 }
 
-pub async fn get_favorites(db: web::Data<DbClient>) -> Result<HttpResponse> {
-    let collection = db.get_tokens_collection();
-    
-    match collection.find(doc! { "is_favorite": true }, None).await {
-        Ok(mut cursor) => {
-            let mut favorites = Vec::new();
-            use futures::stream::StreamExt;
-            
-            while let Some(result) = cursor.next().await {
-                if let Ok(token) = result {
-                    favorites.push(token);
-                }
-            }
-            
-            Ok(HttpResponse::Ok().json(favorites))
+#[utoipa::path(
+    get,
+    path = "/api/favorites/export",
+    tag = "favorites",
+    responses(
+        (status = 200, description = "Ids of the tokens starred in the shared list, for POST /api/favorites/import", body = FavoritesExport),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn export_favorites(db: web::Data<DbClient>) -> Result<HttpResponse> {
+    match db.shared_favorite_ids().await {
+        Ok(token_ids) => Ok(HttpResponse::Ok().json(FavoritesExport { exported_at: Utc::now(), token_ids })),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to export favorites");
+            Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")))
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/favorites/import",
+    tag = "favorites",
+    request_body = FavoritesImport,
+    responses(
+        (status = 200, description = "Tokens starred and unstarred, and listed ids not in the cache", body = FavoritesImportSummary),
+        (status = 400, description = "More than 5000 token_ids", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn import_favorites(
+    db: web::Data<DbClient>,
+    token_cache: web::Data<TokenCache>,
+    req: web::Json<FavoritesImport>,
+) -> Result<HttpResponse> {
+    if req.token_ids.len() > MAX_FAVORITES_IMPORT {
+        return Ok(HttpResponse::BadRequest().json(
+            ErrorResponse::new(format!("token_ids accepts at most {} ids", MAX_FAVORITES_IMPORT))
+        ));
+    }
+
+    match db.import_favorites(&req.token_ids, req.mode).await {
+        Ok(summary) => {
+            token_cache.invalidate().await;
+            Ok(HttpResponse::Ok().json(summary))
         }
         Err(e) => {
-            log::error!("Error fetching favorites: {}", e);
-            Ok(HttpResponse::InternalServerError().json(doc! {
-                "error": format!("Database error: {}", e)
-            }))
+            tracing::error!(mode = ?req.mode, error = %e, "Failed to import favorites");
+            Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Failed to update favorites")))
         }
     }
 }
 
-pub async fn search_tokens(
+#[utoipa::path(
+    post,
+    path = "/api/admin/tokens/{id}/hide",
+    tag = "admin",
+    params(
+        ("id" = String, Path, description = "CoinGecko token id of a cached token")
+    ),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Token, now left out of lists, search, stats and movers", body = CryptoToken),
+        (status = 401, description = "Missing or wrong X-Admin-Token", body = ErrorResponse),
+        (status = 403, description = "ADMIN_TOKEN is not configured", body = ErrorResponse),
+        (status = 404, description = "Token not in the cache", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn hide_token(
+    _admin: Admin,
     db: web::Data<DbClient>,
-    query: web::Query<std::collections::HashMap<String, String>>,
+    token_cache: web::Data<TokenCache>,
+    path: web::Path<String>,
 ) -> Result<HttpResponse> {
-    let search_query = query.get("q").map(|s| s.as_str()).unwrap_or("");
-    
-    if search_query.is_empty() {
-        return Ok(HttpResponse::BadRequest().json(doc! {
-            "error": "Search query is required"
-        }));
+    set_hidden(&db, &token_cache, &path.into_inner(), true).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/tokens/{id}/unhide",
+    tag = "admin",
+    params(
+        ("id" = String, Path, description = "CoinGecko token id of a cached token")
+    ),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Token, listed and searchable again", body = CryptoToken),
+        (status = 401, description = "Missing or wrong X-Admin-Token", body = ErrorResponse),
+        (status = 403, description = "ADMIN_TOKEN is not configured", body = ErrorResponse),
+        (status = 404, description = "Token not in the cache", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn unhide_token(
+    _admin: Admin,
+    db: web::Data<DbClient>,
+    token_cache: web::Data<TokenCache>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    set_hidden(&db, &token_cache, &path.into_inner(), false).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/admin/tokens/{id}",
+    tag = "admin",
+    params(
+        ("id" = String, Path, description = "CoinGecko token id of a cached token"),
+        ("dry_run" = Option<bool>, Query, description = "Only count what would be deleted")
+    ),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Documents deleted (or that would be) per collection", body = TokenDeletion),
+        (status = 400, description = "Malformed token id", body = ErrorResponse),
+        (status = 401, description = "Missing or wrong X-Admin-Token", body = ErrorResponse),
+        (status = 403, description = "ADMIN_TOKEN is not configured", body = ErrorResponse),
+        (status = 404, description = "Token not in the cache", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn delete_token(
+    _admin: Admin,
+    db: web::Data<DbClient>,
+    token_cache: web::Data<TokenCache>,
+    path: web::Path<String>,
+    query: web::Query<DeleteTokenQuery>,
+) -> Result<HttpResponse> {
+    let token_id = path.into_inner();
+    if !is_valid_token_id(&token_id) {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("Invalid token id")));
     }
+    let dry_run = query.dry_run.unwrap_or(false);
 
-    let collection = db.get_tokens_collection();
-    let search_lower = search_query.to_lowercase();
-    
-    // Search in cached data instead of making API call
-    let cached_tokens = get_cached_tokens(&collection).await;
-    
-    let filtered: Vec<CryptoToken> = cached_tokens
-        .into_iter()
-        .filter(|t| {
-            t.name.to_lowercase().contains(&search_lower) ||
-            t.symbol.to_lowercase().contains(&search_lower) ||
-            t.token_id.to_lowercase().contains(&search_lower)
-        })
-        .collect();
-    
-    Ok(HttpResponse::Ok().json(filtered))
+    let found = match db.purge_token(&token_id, false).await {
+        Ok(found) => found,
+        Err(e) => {
+            tracing::error!(token_id = %token_id, error = %e, "Failed to count token documents");
+            return Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")));
+        }
+    };
+    if found.tokens == 0 {
+        return Ok(HttpResponse::NotFound().json(ErrorResponse::new("Token not found")));
+    }
+    if dry_run {
+        return Ok(HttpResponse::Ok().json(TokenDeletion { token_id, dry_run, deleted: found }));
+    }
+
+    match db.purge_token(&token_id, true).await {
+        Ok(deleted) => {
+            token_cache.invalidate().await;
+            tracing::info!(token_id = %token_id, ?deleted, "Deleted token");
+            Ok(HttpResponse::Ok().json(TokenDeletion { token_id, dry_run, deleted }))
+        }
+        Err(e) => {
+            tracing::error!(token_id = %token_id, error = %e, "Failed to delete token");
+            Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")))
+        }
+    }
 }
 
-pub async fn get_historical_data(
-    crypto_service: web::Data<CryptoService>,
+#[utoipa::path(
+    post,
+    path = "/api/admin/purge",
+    tag = "admin",
+    params(
+        ("older_than_hours" = Option<u32>, Query, minimum = 1,
+            description = "Delete tokens not fetched for this many hours, 24 by default. Favorites and hidden tokens are kept")
+    ),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Number of token and expired price history documents deleted", body = TokenPurge),
+        (status = 400, description = "older_than_hours is 0", body = ErrorResponse),
+        (status = 401, description = "Missing or wrong X-Admin-Token", body = ErrorResponse),
+        (status = 403, description = "ADMIN_TOKEN is not configured", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn purge_stale_tokens(
+    _admin: Admin,
     db: web::Data<DbClient>,
-    path: web::Path<(String, u32)>,
+    token_cache: web::Data<TokenCache>,
+    query: web::Query<PurgeQuery>,
 ) -> Result<HttpResponse> {
-    let (token_id, days) = path.into_inner();
-    
-    // Check rate limit before making API call
-    if !can_make_api_call().await {
-        // Try to return cached historical data
-        let collection = db.get_history_collection();
-        let filter = doc! { 
-            "token_id": &token_id,
-        };
-        
-        if let Ok(Some(history)) = collection.find_one(filter, None).await {
-            log::info!("Returning cached historical data for {}", token_id);
-            
-            // Convert back to API format
-            let response = crate::models::CoinGeckoHistoricalData {
-                prices: history.prices.iter().map(|(t, p)| vec![*t as f64, *p]).collect(),
-                market_caps: history.market_caps.iter().map(|(t, p)| vec![*t as f64, *p]).collect(),
-                total_volumes: history.total_volumes.iter().map(|(t, p)| vec![*t as f64, *p]).collect(),
-            };
-            
-            return Ok(HttpResponse::Ok().json(response));
+    let hours = query.older_than_hours.unwrap_or(DEFAULT_PURGE_AGE_HOURS);
+    if hours == 0 {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("older_than_hours must be at least 1")));
+    }
+    let older_than = Utc::now() - chrono::Duration::hours(i64::from(hours));
+
+    let deleted = match db.purge_stale_tokens(older_than).await {
+        Ok(deleted) => deleted,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to purge stale tokens");
+            return Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")));
         }
-        
-        return Ok(HttpResponse::ServiceUnavailable().json(doc! {
-            "error": "Historical data temporarily unavailable. Please try again shortly.",
-            "retry_after": 30
-        }));
+    };
+    if deleted > 0 {
+        token_cache.invalidate().await;
     }
-    
-    record_api_call().await;
-    
-    match crypto_service.fetch_historical_data(&token_id, days).await {
-        Ok(data) => {
-            // Cache the historical data
-            let history = PriceHistory {
-                id: None,
-                token_id: token_id.clone(),
-                symbol: token_id.clone(),
-                prices: data.prices.iter().map(|p| (p[0] as i64, p[1])).collect(),
-                market_caps: data.market_caps.iter().map(|p| (p[0] as i64, p[1])).collect(),
-                total_volumes: data.total_volumes.iter().map(|p| (p[0] as i64, p[1])).collect(),
-                timestamp: Utc::now(),
-            };
 
-            let collection = db.get_history_collection();
-            
-            // Upsert instead of insert to prevent duplicates
-            let filter = doc! { "token_id": &token_id };
-            let update = doc! {
-                "$set": {
-                    "token_id": &history.token_id,
-                    "symbol": &history.symbol,
-                    "prices": &history.prices.iter().map(|(t, p)| doc! { "t": *t, "p": *p }).collect::<Vec<_>>(),
-                    "timestamp": Utc::now(),
-                }
-            };
-            let options = mongodb::options::UpdateOptions::builder().upsert(true).build();
-            let _ = collection.update_one(filter, update, options).await;
-            
-            Ok(HttpResponse::Ok().json(data))
+    // Expired charts go too, rather than waiting for the next scheduled prune
+    match db.prune_history(Utc::now()).await {
+        Ok(history_deleted) => {
+            tracing::info!(%older_than, deleted, history_deleted, "Purged stale tokens and expired history");
+            Ok(HttpResponse::Ok().json(TokenPurge { older_than, deleted, history_deleted }))
         }
         Err(e) => {
-            let error_msg = e.to_string().to_lowercase();
-            if error_msg.contains("429") || error_msg.contains("rate") {
-                record_rate_limit().await;
+            tracing::error!(error = %e, "Failed to prune price history");
+            Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")))
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/repair",
+    tag = "admin",
+    params(
+        ("passes" = Option<String>, Query,
+            description = "Comma-separated passes to run: dedupe, backfill, empty_history. All of them by default; they always run in that order")
+    ),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "What each pass examined, fixed and deleted", body = Vec<RepairReport>),
+        (status = 400, description = "Unknown pass", body = ErrorResponse),
+        (status = 401, description = "Missing or wrong X-Admin-Token", body = ErrorResponse),
+        (status = 403, description = "ADMIN_TOKEN is not configured", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn repair_data(
+    _admin: Admin,
+    db: web::Data<DbClient>,
+    token_cache: web::Data<TokenCache>,
+    query: web::Query<RepairQuery>,
+) -> Result<HttpResponse> {
+    let passes = match RepairPass::parse_list(query.passes.as_deref()) {
+        Ok(passes) => passes,
+        Err(message) => return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message))),
+    };
+
+    match db.repair(&passes).await {
+        Ok(reports) => {
+            let tokens_changed = reports
+                .iter()
+                .any(|r| r.pass != RepairPass::EmptyHistory && (r.fixed > 0 || r.deleted > 0));
+            if tokens_changed {
+                token_cache.invalidate().await;
             }
-            log::error!("Error fetching historical data: {}", e);
-            
-            Ok(HttpResponse::ServiceUnavailable().json(doc! {
-                "error": "Failed to fetch historical data. Please try again shortly.",
-                "retry_after": 30
-            }))
+            for report in &reports {
+                tracing::info!(pass = ?report.pass, examined = report.examined, fixed = report.fixed, deleted = report.deleted, "Ran repair pass");
+            }
+            Ok(HttpResponse::Ok().json(reports))
+        }
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to repair stored data");
+            Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")))
+        }
+    }
+}
+
+// Sets rather than toggles, so hiding twice is harmless
+async fn set_hidden(db: &DbClient, token_cache: &TokenCache, token_id: &str, hidden: bool) -> Result<HttpResponse> {
+    let options = mongodb::options::FindOneAndUpdateOptions::builder()
+        .return_document(mongodb::options::ReturnDocument::After)
+        .build();
+    match db
+        .get_tokens_collection()
+        .find_one_and_update(doc! { "token_id": token_id }, doc! { "$set": { "hidden": hidden } }, options)
+        .await
+    {
+        Ok(Some(token)) => {
+            token_cache.invalidate().await;
+            Ok(HttpResponse::Ok().json(token))
+        }
+        Ok(None) => Ok(HttpResponse::NotFound().json(ErrorResponse::new("Token not found"))),
+        Err(e) => {
+            tracing::error!(token_id = %token_id, hidden, error = %e, "Failed to update hidden flag");
+            Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")))
         }
     }
 }
 
-pub async fn get_stats(db: web::Data<DbClient>) -> Result<HttpResponse> {
+// Tokens straight from an export's cursor. A cursor error or a document that doesn't
+// decode ends the body with an error instead of being left out, so the client sees a
+// failed download rather than a short one behind a 200.
+fn exported_tokens(
+    cursor: mongodb::Cursor<CryptoToken>,
+) -> impl futures::Stream<Item = std::result::Result<CryptoToken, actix_web::Error>> {
+    use futures::stream::StreamExt;
+    cursor.map(|result| {
+        result.map_err(|e| {
+            tracing::error!(error = %e, "Failed to read token for export");
+            actix_web::error::ErrorInternalServerError(e)
+        })
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens/export.json",
+    tag = "tokens",
+    responses(
+        (status = 200, description = "Every cached token as a downloadable JSON array", body = Vec<CryptoToken>,
+            headers(("Content-Disposition" = String, description = "attachment; filename=\"tokens-YYYY-MM-DD.json\""))),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn export_tokens(db: web::Data<DbClient>) -> Result<HttpResponse> {
+    use futures::stream::{self, StreamExt};
+    use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+    use actix_web::web::Bytes;
+
     let collection = db.get_tokens_collection();
-    let tokens = get_cached_tokens(&collection).await;
 
-    if tokens.is_empty() {
-        return Ok(HttpResponse::Ok().json(TokenStats {
-            total_tokens: 0,
-            total_market_cap: 0.0,
-            total_volume_24h: 0.0,
-            avg_price_change_24h: 0.0,
-            biggest_gainer: None,
-            biggest_loser: None,
-        }));
-    }
-
-    let total_market_cap: f64 = tokens.iter().map(|t| t.market_cap).sum();
-    let total_volume_24h: f64 = tokens.iter().map(|t| t.volume_24h).sum();
-    let avg_price_change_24h: f64 = 
-        tokens.iter().map(|t| t.price_change_percentage_24h).sum::<f64>() / tokens.len() as f64;
-
-    let biggest_gainer = tokens.iter()
-        .max_by(|a, b| a.price_change_percentage_24h.partial_cmp(&b.price_change_percentage_24h).unwrap())
-        .cloned();
-
-    let biggest_loser = tokens.iter()
-        .min_by(|a, b| a.price_change_percentage_24h.partial_cmp(&b.price_change_percentage_24h).unwrap())
-        .cloned();
-
-    let stats = TokenStats {
-        total_tokens: tokens.len(),
-        total_market_cap,
-        total_volume_24h,
-        avg_price_change_24h,
-        biggest_gainer,
-        biggest_loser,
-    };
-
-    Ok(HttpResponse::Ok().json(stats))
+    // Pure cache dump: stream straight from the cursor, never touch CoinGecko
+    let cursor = match collection.find(None, None).await {
+        Ok(cursor) => cursor,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to export tokens");
+            return Ok(HttpResponse::InternalServerError().json(
+                ErrorResponse::new(format!("Database error: {}", e))
+            ));
+        }
+    };
+
+    let items = exported_tokens(cursor)
+        .enumerate()
+        .map(|(index, token)| {
+            let token = token?;
+            let mut chunk = if index == 0 { Vec::new() } else { b",".to_vec() };
+            serde_json::to_writer(&mut chunk, &token)
+                .map_err(actix_web::error::ErrorInternalServerError)?;
+            Ok::<_, actix_web::Error>(Bytes::from(chunk))
+        });
+
+    let body = stream::once(async { Ok(Bytes::from_static(b"[")) })
+        .chain(items)
+        .chain(stream::once(async { Ok(Bytes::from_static(b"]")) }));
+
+    let filename = format!("tokens-{}.json", Utc::now().format("%Y-%m-%d"));
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(filename)],
+        })
+        .streaming(body))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens/{id}/supply",
+    tag = "tokens",
+    params(
+        ("id" = String, Path, description = "CoinGecko token id, e.g. `bitcoin`")
+    ),
+    responses(
+        (status = 200, description = "Supply figures; null where CoinGecko has none", body = TokenSupply),
+        (status = 400, description = "Malformed token id", body = ErrorResponse),
+        (status = 404, description = "CoinGecko does not know this token", body = ErrorResponse),
+        (status = 503, description = "Token not cached and upstream is rate limited or failing", body = ErrorResponse,
+            headers(("Retry-After" = u64, description = "Seconds until the next upstream call is allowed")))
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn get_token_supply(
+    db: web::Data<DbClient>,
+    crypto_service: web::Data<CryptoService>,
+    rate_limiter: web::Data<RateLimiter>,
+    token_cache: web::Data<TokenCache>,
+    notifier: web::Data<WebhookNotifier>,
+    events: web::Data<EventRecorder>,
+    upstream: web::Data<UpstreamGate>,
+    token_id: web::Path<String>,
+) -> Result<HttpResponse> {
+    if !is_valid_token_id(&token_id) {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("Invalid token id")));
+    }
+    let collection = db.get_tokens_collection();
+
+    // Supply barely moves, so any cached copy will do
+    if let Ok(Some(token)) = collection.find_one(doc! { "token_id": token_id.as_str() }, None).await {
+        return Ok(HttpResponse::Ok().json(TokenSupply::from(&token)));
+    }
+
+    if rate_limiter.try_acquire().await {
+        match upstream.run(crypto_service.fetch_token_details(&token_id)).await {
+            Ok(token) => {
+                save_tokens_to_cache(&collection, &token_cache, &notifier, &events, std::slice::from_ref(&token)).await;
+                return Ok(HttpResponse::Ok().json(TokenSupply::from(&token)));
+            }
+            Err(e) => {
+                tracing::error!(token_id = %token_id, error = %e, "Failed to fetch token supply");
+                match e {
+                    CryptoServiceError::NotFound => {
+                        return Ok(HttpResponse::NotFound().json(ErrorResponse::new("Token not found")));
+                    }
+                    CryptoServiceError::RateLimited => rate_limiter.record_rate_limit().await,
+                    CryptoServiceError::Busy => {
+                        return Ok(HttpResponse::ServiceUnavailable()
+                            .insert_header((header::RETRY_AFTER, "1"))
+                            .json(ErrorResponse::new("Too many upstream requests in flight").with_retry_after(1)));
+                    }
+                    _ => {
+                        return Ok(HttpResponse::ServiceUnavailable()
+                            .insert_header((header::RETRY_AFTER, "30"))
+                            .json(ErrorResponse::new("Token not cached and CoinGecko is unavailable").with_retry_after(30)));
+                    }
+                }
+            }
+        }
+    }
+
+    let retry_after = rate_limiter.seconds_until_next_call().await.max(1);
+    Ok(HttpResponse::ServiceUnavailable()
+        .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+        .json(ErrorResponse::new("Token not cached and upstream is rate limited").with_retry_after(retry_after)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens/{id}/profile",
+    tag = "tokens",
+    params(
+        ("id" = String, Path, description = "CoinGecko token id, e.g. `bitcoin`")
+    ),
+    responses(
+        (status = 200, description = "Description (plain text), links, genesis date and categories. A stored profile is served for PROFILE_CACHE_TTL_SECS, and past that whenever CoinGecko can't be reached", body = CoinProfile),
+        (status = 400, description = "Malformed token id", body = ErrorResponse),
+        (status = 404, description = "CoinGecko does not know this token", body = ErrorResponse),
+        (status = 503, description = "Profile not stored and upstream is rate limited or failing", body = ErrorResponse,
+            headers(("Retry-After" = u64, description = "Seconds until the next upstream call is allowed")))
+    )
+)]
+pub async fn get_token_profile(
+    config: web::Data<Config>,
+    db: web::Data<DbClient>,
+    crypto_service: web::Data<CryptoService>,
+    rate_limiter: web::Data<RateLimiter>,
+    background_tasks: web::Data<BackgroundTasks>,
+    upstream: web::Data<UpstreamGate>,
+    token_id: web::Path<String>,
+) -> Result<HttpResponse> {
+    if !is_valid_token_id(&token_id) {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("Invalid token id")));
+    }
+
+    let stored = db.load_coin_profile(&token_id).await.unwrap_or_else(|e| {
+        tracing::warn!(token_id = %token_id, error = %e, "Failed to load stored profile");
+        None
+    });
+    if let Some(profile) = &stored {
+        let age_secs = (Utc::now() - profile.fetched_at).num_seconds().max(0) as u64;
+        if age_secs <= config.profile_cache_ttl_secs {
+            return Ok(HttpResponse::Ok().json(profile));
+        }
+    }
+
+    if rate_limiter.try_acquire().await {
+        match upstream.run(crypto_service.fetch_coin_profile(&token_id)).await {
+            Ok(profile) => {
+                let db = db.clone();
+                let to_save = profile.clone();
+                background_tasks.spawn(move |_| async move {
+                    if let Err(e) = db.save_coin_profile(&to_save).await {
+                        tracing::error!(token_id = %to_save.token_id, error = %e, "Failed to save profile");
+                    }
+                });
+                return Ok(HttpResponse::Ok().json(profile));
+            }
+            Err(CryptoServiceError::NotFound) => {
+                return Ok(HttpResponse::NotFound().json(ErrorResponse::new("Token not found")));
+            }
+            Err(e) => {
+                if e == CryptoServiceError::RateLimited {
+                    rate_limiter.record_rate_limit().await;
+                }
+                tracing::error!(token_id = %token_id, error = %e, "Failed to fetch profile from CoinGecko");
+            }
+        }
+    }
+
+    // An outdated profile beats none; it's nearly static anyway
+    if let Some(profile) = stored {
+        return Ok(HttpResponse::Ok().json(profile));
+    }
+    let retry_after = rate_limiter.seconds_until_next_call().await.max(1);
+    Ok(HttpResponse::ServiceUnavailable()
+        .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+        .json(ErrorResponse::new("Profile not stored and CoinGecko is unavailable").with_retry_after(retry_after)))
+}
+
+// Default window for /api/tokens/{id}/change
+const DEFAULT_CHANGE_DAYS: u32 = 7;
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens/{id}/change",
+    tag = "tokens",
+    params(
+        ("id" = String, Path, description = "CoinGecko token id, e.g. `bitcoin`"),
+        ("days" = Option<u32>, Query, minimum = 1, maximum = 365, description = "Window in days, defaults to 7")
+    ),
+    responses(
+        (status = 200, description = "Change from the cached price closest to `days` ago to the latest one; coverage_days is smaller when history is shorter", body = HistoricalChange),
+        (status = 400, description = "Malformed token id or days out of range", body = ErrorResponse),
+        (status = 404, description = "No cached price history for this token", body = ErrorResponse),
+        (status = 422, description = "Fewer than two usable prices cached", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn get_historical_change(
+    db: web::Data<DbClient>,
+    token_id: web::Path<String>,
+    query: web::Query<HistoricalChangeQuery>,
+) -> Result<HttpResponse> {
+    if !is_valid_token_id(&token_id) {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("Invalid token id")));
+    }
+    let days = query.days.unwrap_or(DEFAULT_CHANGE_DAYS);
+    if !(1..=MAX_HISTORY_DAYS).contains(&days) {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(format!(
+            "days must be between 1 and {}",
+            MAX_HISTORY_DAYS
+        ))));
+    }
+
+    // Cached charts only, like /api/correlation; CoinGecko's own change fields are on the token
+    let token_id = token_id.into_inner();
+    let prices = match db.cached_price_series(std::slice::from_ref(&token_id)).await {
+        Ok(mut cached) => cached.remove(&token_id),
+        Err(e) => {
+            tracing::error!(token_id = %token_id, error = %e, "Failed to load cached price history");
+            return Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")));
+        }
+    };
+    let Some(prices) = prices else {
+        return Ok(HttpResponse::NotFound().json(ErrorResponse::new(
+            "No cached price history for this token; load it through /api/history first",
+        )));
+    };
+    let Some(((start_t, start_price), (end_t, end_price))) = analytics::change_window(&prices, days) else {
+        return Ok(HttpResponse::UnprocessableEntity().json(ErrorResponse::new("Not enough cached prices to compute a change")));
+    };
+
+    let at = |t: i64| DateTime::from_timestamp_millis(t).unwrap_or_default();
+    Ok(HttpResponse::Ok().json(HistoricalChange {
+        token_id,
+        days,
+        coverage_days: analytics::span_days(start_t, end_t),
+        start_price,
+        end_price,
+        start_at: at(start_t),
+        end_at: at(end_t),
+        change: end_price - start_price,
+        change_percentage: (end_price / start_price - 1.0) * 100.0,
+    }))
+}
+
+// How far outside the cached range /api/tokens/{id}/price_at still answers with the
+// first or last point
+const PRICE_AT_TOLERANCE_MS: i64 = 24 * 60 * 60 * 1000;
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens/{id}/price_at",
+    tag = "tokens",
+    params(
+        ("id" = String, Path, description = "CoinGecko token id, e.g. `bitcoin`"),
+        ("timestamp" = i64, Query, description = "Unix milliseconds")
+    ),
+    responses(
+        (status = 200, description = "Cached price nearest the timestamp, when it was recorded and how far off that is", body = PriceAt),
+        (status = 400, description = "Malformed token id or missing timestamp", body = ErrorResponse),
+        (status = 404, description = "No cached price history for this token", body = ErrorResponse),
+        (status = 422, description = "Timestamp more than a day outside the cached range", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn get_price_at(
+    db: web::Data<DbClient>,
+    token_id: web::Path<String>,
+    query: web::Query<PriceAtQuery>,
+) -> Result<HttpResponse> {
+    if !is_valid_token_id(&token_id) {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("Invalid token id")));
+    }
+    let Some(requested_at) = DateTime::from_timestamp_millis(query.timestamp) else {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("timestamp is out of range")));
+    };
+
+    let token_id = token_id.into_inner();
+    let prices = match db.cached_price_series(std::slice::from_ref(&token_id)).await {
+        Ok(mut cached) => cached.remove(&token_id),
+        Err(e) => {
+            tracing::error!(token_id = %token_id, error = %e, "Failed to load cached price history");
+            return Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")));
+        }
+    };
+    let mut points: Vec<(i64, f64)> = prices.unwrap_or_default().into_iter().filter(|(_, p)| p.is_finite() && *p > 0.0).collect();
+    if points.is_empty() {
+        return Ok(HttpResponse::NotFound().json(ErrorResponse::new(
+            "No cached price history for this token; load it through /api/history first",
+        )));
+    }
+    // Charts are stored in time order; only an odd one pays for a sort
+    if !points.is_sorted_by_key(|(t, _)| *t) {
+        points.sort_by_key(|(t, _)| *t);
+    }
+
+    let (first, last) = (points[0].0, points[points.len() - 1].0);
+    if query.timestamp < first - PRICE_AT_TOLERANCE_MS || query.timestamp > last + PRICE_AT_TOLERANCE_MS {
+        let at = |t: i64| DateTime::from_timestamp_millis(t).unwrap_or_default().to_rfc3339();
+        return Ok(HttpResponse::UnprocessableEntity().json(ErrorResponse::new(format!(
+            "Cached history covers {} to {}",
+            at(first),
+            at(last)
+        ))));
+    }
+    let Some((t, price)) = analytics::nearest_point(&points, query.timestamp) else {
+        return Ok(HttpResponse::NotFound().json(ErrorResponse::new("No cached price history for this token")));
+    };
+
+    Ok(HttpResponse::Ok().json(PriceAt {
+        token_id,
+        requested_at,
+        price,
+        at: DateTime::from_timestamp_millis(t).unwrap_or_default(),
+        delta_ms: t - query.timestamp,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens/{id}/history",
+    tag = "history",
+    params(
+        ("id" = String, Path, pattern = "^[a-z0-9-]{1,100}$", description = "CoinGecko token id"),
+        ("interval" = Option<String>, Query,
+            description = "hourly or daily to get only that series. By default both are merged, hourly points winning on a shared timestamp")
+    ),
+    responses(
+        (status = 200, description = "Everything stored in price_history for the token, as [timestamp_ms, value] pairs", body = CoinGeckoHistoricalData),
+        (status = 400, description = "Malformed token id or an unknown interval", body = ErrorResponse),
+        (status = 404, description = "No history cached for this token", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+// Never goes upstream or waits on the rate limiter: whatever /api/history has cached
+// so far, or 404
+pub async fn get_stored_history(
+    db: web::Data<DbClient>,
+    token_id: web::Path<String>,
+    query: web::Query<StoredHistoryQuery>,
+) -> Result<HttpResponse> {
+    if !is_valid_token_id(&token_id) {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("Invalid token id")));
+    }
+    let interval = match query.interval.as_deref().map(str::parse::<HistoryInterval>) {
+        None => None,
+        Some(Ok(interval)) => Some(interval),
+        Some(Err(message)) => return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message))),
+    };
+
+    let stored = match db.stored_history(&token_id).await {
+        Ok(stored) => stored,
+        Err(e) => {
+            tracing::error!(token_id = %token_id, error = %e, "Failed to load stored price history");
+            return Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")));
+        }
+    };
+    let data = stored.map(|stored| match interval {
+        Some(interval) => stored.series(interval).clone(),
+        None => analytics::merge_history(&stored.daily, &stored.hourly),
+    });
+    match data {
+        Some(data) if !data.prices.is_empty() => Ok(HttpResponse::Ok().json(data)),
+        _ => Ok(HttpResponse::NotFound().json(ErrorResponse::new(
+            "No history cached for this token; load it through /api/history first",
+        ))),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/export/tokens.ndjson",
+    tag = "tokens",
+    responses(
+        (status = 200, description = "Every cached token, one JSON object per line", content_type = "application/x-ndjson",
+            body = CryptoToken,
+            headers(("Content-Disposition" = String, description = "attachment; filename=\"tokens-YYYY-MM-DD.ndjson\""))),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn export_tokens_ndjson(db: web::Data<DbClient>) -> Result<HttpResponse> {
+    use futures::stream::StreamExt;
+    use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+    use actix_web::web::Bytes;
+
+    // Straight from the cursor, one document in memory at a time
+    let cursor = match db.get_tokens_collection().find(None, None).await {
+        Ok(cursor) => cursor,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to export tokens");
+            return Ok(HttpResponse::InternalServerError().json(
+                ErrorResponse::new(format!("Database error: {}", e))
+            ));
+        }
+    };
+
+    let body = exported_tokens(cursor)
+        .map(|token| {
+            let mut line = serde_json::to_vec(&token?).map_err(actix_web::error::ErrorInternalServerError)?;
+            line.push(b'\n');
+            Ok::<_, actix_web::Error>(Bytes::from(line))
+        });
+
+    let filename = format!("tokens-{}.ndjson", Utc::now().format("%Y-%m-%d"));
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(filename)],
+        })
+        .streaming(body))
+}
+
+// Documents per `update` command during an import
+const IMPORT_BATCH_SIZE: usize = 100;
+
+// Writes the pending batch and folds the result into the summary
+async fn flush_import_batch(
+    db: &DbClient,
+    batch: &mut Vec<(usize, CryptoToken)>,
+    summary: &mut ImportSummary,
+) -> mongodb::error::Result<()> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    let (lines, tokens): (Vec<usize>, Vec<CryptoToken>) = std::mem::take(batch).into_iter().unzip();
+    let outcome = db.upsert_tokens(&tokens).await?;
+    summary.inserted += outcome.inserted;
+    summary.updated += outcome.updated;
+    summary.failed.extend(outcome.failed.into_iter().map(|(index, error)| ImportFailure { line: lines[index], error }));
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/import/tokens",
+    tag = "tokens",
+    request_body(content = String, content_type = "application/x-ndjson",
+        description = "Tokens in the format of /api/export/tokens.ndjson, one per line"),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Counts of inserted and updated tokens, plus each line that failed and why", body = ImportSummary),
+        (status = 401, description = "Missing or wrong X-Admin-Token", body = ErrorResponse),
+        (status = 403, description = "ADMIN_TOKEN is not configured", body = ErrorResponse),
+        (status = 500, description = "Database error; batches before it were written", body = ErrorResponse)
+    )
+)]
+pub async fn import_tokens(
+    _admin: Admin,
+    db: web::Data<DbClient>,
+    token_cache: web::Data<TokenCache>,
+    mut payload: web::Payload,
+) -> Result<HttpResponse> {
+    use futures::stream::StreamExt;
+
+    let mut splitter = LineSplitter::default();
+    let mut summary = ImportSummary::default();
+    let mut batch = Vec::with_capacity(IMPORT_BATCH_SIZE);
+    let mut line_number = 0;
+    let mut finished = false;
+
+    // The body is read as it arrives, so memory stays at one chunk plus one batch
+    while !finished {
+        let lines = match payload.next().await {
+            Some(chunk) => splitter.push(&chunk?),
+            None => {
+                finished = true;
+                std::mem::take(&mut splitter).finish().into_iter().collect()
+            }
+        };
+
+        for line in lines {
+            line_number += 1;
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            match ndjson::parse_token_line(&line) {
+                Ok(token) => batch.push((line_number, token)),
+                Err(error) => summary.failed.push(ImportFailure { line: line_number, error }),
+            }
+            if batch.len() >= IMPORT_BATCH_SIZE {
+                if let Err(e) = flush_import_batch(&db, &mut batch, &mut summary).await {
+                    return Ok(import_failed(&token_cache, line_number, e).await);
+                }
+            }
+        }
+    }
+    if let Err(e) = flush_import_batch(&db, &mut batch, &mut summary).await {
+        return Ok(import_failed(&token_cache, line_number, e).await);
+    }
+
+    summary.failed.sort_by_key(|failure| failure.line);
+    token_cache.invalidate().await;
+    tracing::info!(
+        inserted = summary.inserted,
+        updated = summary.updated,
+        failed = summary.failed.len(),
+        "Imported tokens"
+    );
+    Ok(HttpResponse::Ok().json(summary))
+}
+
+async fn import_failed(token_cache: &TokenCache, line: usize, error: mongodb::error::Error) -> HttpResponse {
+    // Earlier batches may have landed
+    token_cache.invalidate().await;
+    tracing::error!(line, error = %error, "Token import failed");
+    HttpResponse::InternalServerError().json(ErrorResponse::new(format!("Database error near line {}: {}", line, error)))
+}
+
+// Default and largest `limit` for GET /api/admin/webhook/deliveries
+pub const DEFAULT_DELIVERIES_LIMIT: i64 = 50;
+pub const MAX_DELIVERIES_LIMIT: i64 = 200;
+
+#[utoipa::path(
+    put,
+    path = "/api/admin/webhook",
+    tag = "admin",
+    request_body = WebhookConfig,
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Saved config; the secret is never echoed back", body = WebhookConfig),
+        (status = 400, description = "url is not an absolute http(s) URL, or the secret is blank", body = ErrorResponse),
+        (status = 401, description = "Missing or wrong X-Admin-Token", body = ErrorResponse),
+        (status = 403, description = "ADMIN_TOKEN is not configured", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn put_webhook(
+    _admin: Admin,
+    db: web::Data<DbClient>,
+    body: web::Json<WebhookConfig>,
+) -> Result<HttpResponse> {
+    let config = body.into_inner();
+    let valid_url = reqwest::Url::parse(&config.url)
+        .is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.has_host());
+    if !valid_url {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("url must be an absolute http or https URL")));
+    }
+    if config.secret.as_deref().is_some_and(|secret| secret.trim().is_empty()) {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("secret must not be blank; omit it to send unsigned")));
+    }
+
+    match db.save_webhook_config(&config).await {
+        Ok(()) => Ok(HttpResponse::Ok().json(config)),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to save webhook config");
+            Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Failed to save webhook config")))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/webhook/deliveries",
+    tag = "admin",
+    params(
+        ("limit" = Option<i64>, Query, minimum = 1, maximum = 200, description = "Attempts to return, defaults to 50")
+    ),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Delivery attempts, most recent first", body = Vec<WebhookDelivery>),
+        (status = 400, description = "limit out of range", body = ErrorResponse),
+        (status = 401, description = "Missing or wrong X-Admin-Token", body = ErrorResponse),
+        (status = 403, description = "ADMIN_TOKEN is not configured", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn get_webhook_deliveries(
+    _admin: Admin,
+    db: web::Data<DbClient>,
+    query: web::Query<DeliveriesQuery>,
+) -> Result<HttpResponse> {
+    let limit = query.limit.unwrap_or(DEFAULT_DELIVERIES_LIMIT);
+    if !(1..=MAX_DELIVERIES_LIMIT).contains(&limit) {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(format!(
+            "limit must be between 1 and {}",
+            MAX_DELIVERIES_LIMIT
+        ))));
+    }
+
+    match db.recent_webhook_deliveries(limit).await {
+        Ok(deliveries) => Ok(HttpResponse::Ok().json(deliveries)),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to load webhook deliveries");
+            Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")))
+        }
+    }
+}
+
+// Default and largest `limit` for GET /api/events
+pub const DEFAULT_EVENTS_LIMIT: i64 = 100;
+pub const MAX_EVENTS_LIMIT: i64 = 1000;
+
+// RFC3339, or Unix milliseconds like the history endpoints return
+fn parse_since(raw: &str) -> Option<DateTime<Utc>> {
+    match raw.parse::<i64>() {
+        Ok(ms) => DateTime::from_timestamp_millis(ms),
+        Err(_) => DateTime::parse_from_rfc3339(raw).ok().map(|t| t.with_timezone(&Utc)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/events",
+    tag = "tokens",
+    params(
+        ("since" = Option<String>, Query, description = "Only events at or after this time: RFC3339 or Unix milliseconds"),
+        ("kind" = Option<String>, Query, description = "ath_break or large_move"),
+        ("limit" = Option<i64>, Query, minimum = 1, maximum = 1000, description = "Events to return, defaults to 100")
+    ),
+    responses(
+        (status = 200, description = "Price events noticed by cache refreshes, newest first", body = Vec<Event>),
+        (status = 400, description = "Unknown kind, unreadable since or limit out of range", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn get_events(db: web::Data<DbClient>, query: web::Query<EventsQuery>) -> Result<HttpResponse> {
+    let limit = query.limit.unwrap_or(DEFAULT_EVENTS_LIMIT);
+    if !(1..=MAX_EVENTS_LIMIT).contains(&limit) {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(format!(
+            "limit must be between 1 and {}",
+            MAX_EVENTS_LIMIT
+        ))));
+    }
+    if let Some(kind) = query.kind.as_deref() {
+        if !EVENT_KINDS.contains(&kind) {
+            return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(format!(
+                "kind must be one of: {}",
+                EVENT_KINDS.join(", ")
+            ))));
+        }
+    }
+    let since = match query.since.as_deref() {
+        Some(raw) => match parse_since(raw) {
+            Some(since) => Some(since),
+            None => {
+                return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(
+                    "since must be an RFC3339 timestamp or Unix milliseconds",
+                )))
+            }
+        },
+        None => None,
+    };
+
+    match db.recent_events(since, query.kind.as_deref(), limit).await {
+        Ok(events) => Ok(HttpResponse::Ok().json(events)),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to load events");
+            Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")))
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/users",
+    tag = "users",
+    responses(
+        (status = 201, description = "New user and its API key. The key is only ever shown here", body = NewUser),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn create_user(db: web::Data<DbClient>) -> Result<HttpResponse> {
+    let api_key = auth::generate_api_key();
+    match db.create_user(auth::hash_api_key(&api_key)).await {
+        Ok(user) => Ok(HttpResponse::Created().json(NewUser {
+            user_id: user.id.map(|id| id.to_hex()).unwrap_or_default(),
+            api_key,
+            created_at: user.created_at,
+        })),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to create user");
+            Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Failed to create user")))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/favorites",
+    tag = "favorites",
+    params(
+        ("sort_by" = Option<String>, Query,
+            description = "market_cap, current_price, volume_24h, price_change_percentage_24h, price_change_percentage_7d, price_change_percentage_30d, price_change_percentage_1y, name or symbol. Tokens without the 7d, 30d or 1y change sorted by are left out"),
+        ("order" = Option<String>, Query, description = "asc or desc; numbers default to desc, names to asc"),
+        ("page" = Option<u64>, Query, minimum = 1, description = "1-based page number, defaults to 1"),
+        ("per_page" = Option<u64>, Query, minimum = 1, maximum = 250,
+            description = "Page size, defaults to 20. Without page or per_page every favorite is returned"),
+        ("tag" = Option<String>, Query, description = "Only favorites carrying this tag (case-insensitive)")
+    ),
+    security((), ("api_key" = [])),
+    responses(
+        (status = 200, description = "Tokens marked as favorite, by the key's user when one is sent", body = Vec<CryptoToken>),
+        (status = 400, description = "Unknown sort_by/order or page out of range", body = ErrorResponse),
+        (status = 401, description = "Unknown API key", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn get_favorites(
+    config: web::Data<Config>,
+    db: web::Data<DbClient>,
+    user: MaybeUser,
+    query: web::Query<ListQuery>,
+    filter: web::Query<FavoritesQuery>,
+) -> Result<HttpResponse> {
+    let params = match ListParams::from_query(&query) {
+        Ok(params) => params,
+        Err(message) => return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message))),
+    };
+    let collection = db.get_tokens_collection();
+    
+    // Without a key the shared flag decides; with one, the user's own list
+    let mut query_filter = match &user.0 {
+        Some(user) => match db.user_favorite_ids(user.id).await {
+            Ok(ids) => doc! { "token_id": { "$in": ids.into_iter().collect::<Vec<_>>() } },
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to load user favorites");
+                return Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")));
+            }
+        },
+        None => doc! { "is_favorite": true },
+    };
+    // Tags are stored normalized, so the filter is too
+    if let Some(tag) = filter.tag.as_deref().map(|t| t.trim().to_lowercase()).filter(|t| !t.is_empty()) {
+        query_filter.insert("tags", tag);
+    }
+
+    // Sorting and paging happen in MongoDB so only the requested page is read.
+    // Sparklines are only sent with ?sparkline=true on /api/tokens.
+    let mut options = params.find_options();
+    options.projection = Some(doc! { "sparkline_7d": 0 });
+    match collection.find(params.filter(query_filter), options).await {
+        Ok(mut cursor) => {
+            let mut favorites = Vec::new();
+            use futures::stream::StreamExt;
+            
+            while let Some(result) = cursor.next().await {
+                if let Ok(mut token) = result {
+                    token.is_favorite = true;
+                    favorites.push(token);
+                }
+            }
+            
+            let freshness = Freshness::of(&favorites, config.token_cache_ttl_secs);
+            Ok(envelope::attach(HttpResponse::Ok().json(favorites), freshness))
+        }
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to fetch favorites");
+            Ok(HttpResponse::InternalServerError().json(
+                ErrorResponse::new(format!("Database error: {}", e))
+            ))
+        }
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/favorites/{id}/meta",
+    tag = "favorites",
+    params(
+        ("id" = String, Path, description = "CoinGecko token id of a favorite")
+    ),
+    request_body = FavoriteMeta,
+    responses(
+        (status = 200, description = "Favorite with its new tags and note", body = CryptoToken),
+        (status = 400, description = "Too many or too long tags, or note too long", body = ErrorResponse),
+        (status = 404, description = "Token is not a favorite", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn update_favorite_meta(
+    db: web::Data<DbClient>,
+    token_cache: web::Data<TokenCache>,
+    path: web::Path<String>,
+    req: web::Json<FavoriteMeta>,
+) -> Result<HttpResponse> {
+    let token_id = path.into_inner();
+    let meta = match req.into_inner().normalized() {
+        Ok(meta) => meta,
+        Err(message) => return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message))),
+    };
+
+    let collection = db.get_tokens_collection();
+    let filter = doc! { "token_id": &token_id, "is_favorite": true };
+    let update = match &meta.note {
+        Some(note) => doc! { "$set": { "tags": &meta.tags, "note": note } },
+        None => doc! { "$set": { "tags": &meta.tags }, "$unset": { "note": "" } },
+    };
+    let options = mongodb::options::FindOneAndUpdateOptions::builder()
+        .return_document(mongodb::options::ReturnDocument::After)
+        .build();
+
+    match collection.find_one_and_update(filter, update, options).await {
+        Ok(Some(token)) => {
+            token_cache.invalidate().await;
+            Ok(HttpResponse::Ok().json(token))
+        }
+        Ok(None) => Ok(HttpResponse::NotFound().json(ErrorResponse::new("Favorite not found"))),
+        Err(e) => {
+            tracing::error!(token_id = %token_id, error = %e, "Failed to update favorite metadata");
+            Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Failed to update favorite")))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens/{id}/image",
+    tag = "tokens",
+    params(
+        ("id" = String, Path, description = "CoinGecko token id, e.g. `bitcoin`")
+    ),
+    responses(
+        (status = 200, description = "The token's logo, fetched server-side and kept in memory",
+            content_type = "image/*",
+            headers(("Cache-Control" = String, description = "Public, for a day"))),
+        (status = 302, description = "The logo couldn't be fetched within a couple of seconds; redirects to its original URL",
+            headers(("Location" = String, description = "The token's `image` URL"))),
+        (status = 400, description = "Malformed token id", body = ErrorResponse),
+        (status = 404, description = "Unknown token, or one without a logo", body = ErrorResponse)
+    )
+)]
+pub async fn get_token_image(
+    db: web::Data<DbClient>,
+    token_cache: web::Data<TokenCache>,
+    images: web::Data<ImageProxy>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let token_id = path.into_inner();
+    if !is_valid_token_id(&token_id) {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("Invalid token id")));
+    }
+    let tokens = load_tokens(&db.get_tokens_collection(), &token_cache).await;
+    let Some(token) = tokens.iter().find(|t| t.token_id == token_id) else {
+        return Ok(HttpResponse::NotFound().json(ErrorResponse::new("Token not found")));
+    };
+    let Some(url) = token.image.as_deref().filter(|url| url.starts_with("https://") || url.starts_with("http://")) else {
+        return Ok(HttpResponse::NotFound().json(ErrorResponse::new("Token has no image")));
+    };
+
+    match images.get(url).await {
+        Ok(image) => Ok(HttpResponse::Ok()
+            .content_type(image.content_type)
+            .insert_header((header::CACHE_CONTROL, format!("public, max-age={}", image_proxy::IMAGE_TTL.as_secs())))
+            .body(image.bytes)),
+        Err(e) => {
+            tracing::warn!(token_id = %token_id, url = %url, error = %e, "Failed to fetch token image, redirecting");
+            Ok(HttpResponse::Found().insert_header((header::LOCATION, url)).finish())
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tokens/{id}/annotation",
+    tag = "tokens",
+    params(
+        ("id" = String, Path, description = "CoinGecko token id, e.g. `bitcoin`")
+    ),
+    responses(
+        (status = 200, description = "The token's tags and note", body = TokenAnnotation),
+        (status = 400, description = "Malformed token id", body = ErrorResponse),
+        (status = 404, description = "Token has no annotation", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn get_annotation(db: web::Data<DbClient>, path: web::Path<String>) -> Result<HttpResponse> {
+    let token_id = path.into_inner();
+    if !is_valid_token_id(&token_id) {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("Invalid token id")));
+    }
+
+    match db.load_annotation(&token_id).await {
+        Ok(Some(annotation)) => Ok(HttpResponse::Ok().json(annotation)),
+        Ok(None) => Ok(HttpResponse::NotFound().json(ErrorResponse::new("Token has no annotation"))),
+        Err(e) => {
+            tracing::error!(token_id = %token_id, error = %e, "Failed to load annotation");
+            Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")))
+        }
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/tokens/{id}/annotation",
+    tag = "tokens",
+    params(
+        ("id" = String, Path, description = "CoinGecko token id of a cached token")
+    ),
+    request_body = AnnotationRequest,
+    responses(
+        (status = 200, description = "The saved annotation", body = TokenAnnotation),
+        (status = 400, description = "Malformed token id, or tags or note over their limits, listed per field in `fields`", body = ErrorResponse),
+        (status = 404, description = "Token not in the cache", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn put_annotation(
+    db: web::Data<DbClient>,
+    path: web::Path<String>,
+    req: web::Json<AnnotationRequest>,
+) -> Result<HttpResponse> {
+    let token_id = path.into_inner();
+    if !is_valid_token_id(&token_id) {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("Invalid token id")));
+    }
+    let (tags, note) = match req.into_inner().validated() {
+        Ok(valid) => valid,
+        Err(fields) => {
+            return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("Invalid annotation").with_fields(fields)));
+        }
+    };
+
+    match db.get_tokens_collection().count_documents(doc! { "token_id": &token_id }, None).await {
+        Ok(0) => return Ok(HttpResponse::NotFound().json(ErrorResponse::new("Token not found"))),
+        Ok(_) => {}
+        Err(e) => {
+            tracing::error!(token_id = %token_id, error = %e, "Failed to look up token");
+            return Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")));
+        }
+    }
+
+    let annotation = TokenAnnotation { token_id, tags, note, updated_at: Utc::now() };
+    match db.save_annotation(&annotation).await {
+        Ok(()) => Ok(HttpResponse::Ok().json(annotation)),
+        Err(e) => {
+            tracing::error!(token_id = %annotation.token_id, error = %e, "Failed to save annotation");
+            Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")))
+        }
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/tokens/{id}/annotation",
+    tag = "tokens",
+    params(
+        ("id" = String, Path, description = "CoinGecko token id, e.g. `bitcoin`")
+    ),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 400, description = "Malformed token id", body = ErrorResponse),
+        (status = 404, description = "Token has no annotation", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn delete_annotation(db: web::Data<DbClient>, path: web::Path<String>) -> Result<HttpResponse> {
+    let token_id = path.into_inner();
+    if !is_valid_token_id(&token_id) {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("Invalid token id")));
+    }
+
+    match db.delete_annotation(&token_id).await {
+        Ok(true) => Ok(HttpResponse::NoContent().finish()),
+        Ok(false) => Ok(HttpResponse::NotFound().json(ErrorResponse::new("Token has no annotation"))),
+        Err(e) => {
+            tracing::error!(token_id = %token_id, error = %e, "Failed to delete annotation");
+            Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")))
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/alerts",
+    tag = "alerts",
+    request_body = AlertRequest,
+    responses(
+        (status = 201, description = "Created alert with its id", body = Alert),
+        (status = 400, description = "Percent outside 0.1-1000 or a scope that's neither a token id nor favorites", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn create_alert(db: web::Data<DbClient>, body: web::Json<AlertRequest>) -> Result<HttpResponse> {
+    let (condition, scope) = match body.into_inner().validated() {
+        Ok(valid) => valid,
+        Err(fields) => {
+            return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("Invalid alert").with_fields(fields)));
+        }
+    };
+
+    match db.create_alert(condition, scope).await {
+        Ok(alert) => Ok(HttpResponse::Created().json(Alert::from(&alert))),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to create alert");
+            Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/alerts",
+    tag = "alerts",
+    responses(
+        (status = 200, description = "Every alert, oldest first", body = Vec<Alert>),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn list_alerts(db: web::Data<DbClient>) -> Result<HttpResponse> {
+    match db.load_alerts().await {
+        Ok(alerts) => Ok(HttpResponse::Ok().json(alerts.iter().map(Alert::from).collect::<Vec<_>>())),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to load alerts");
+            Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")))
+        }
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/alerts/{id}",
+    tag = "alerts",
+    params(
+        ("id" = String, Path, description = "Alert id from the list")
+    ),
+    responses(
+        (status = 204, description = "Deleted, with its triggers"),
+        (status = 400, description = "Malformed id", body = ErrorResponse),
+        (status = 404, description = "No such alert", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn delete_alert(db: web::Data<DbClient>, id: web::Path<String>) -> Result<HttpResponse> {
+    let Ok(id) = ObjectId::parse_str(id.as_str()) else {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("Invalid alert id")));
+    };
+
+    match db.delete_alert(id).await {
+        Ok(true) => Ok(HttpResponse::NoContent().finish()),
+        Ok(false) => Ok(HttpResponse::NotFound().json(ErrorResponse::new("Alert not found"))),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to delete alert");
+            Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/alerts/{id}/triggers",
+    tag = "alerts",
+    params(
+        ("id" = String, Path, description = "Alert id from the list")
+    ),
+    responses(
+        (status = 200, description = "The alert's latest triggers, newest first, each naming the token that moved", body = Vec<AlertTrigger>),
+        (status = 400, description = "Malformed id", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn get_alert_triggers(db: web::Data<DbClient>, id: web::Path<String>) -> Result<HttpResponse> {
+    let Ok(id) = ObjectId::parse_str(id.as_str()) else {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("Invalid alert id")));
+    };
+
+    match db.alert_triggers(&id.to_hex(), ALERT_TRIGGER_LIMIT).await {
+        Ok(triggers) => Ok(HttpResponse::Ok().json(triggers)),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to load alert triggers");
+            Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")))
+        }
+    }
+}
+
+// The caller's transactions, or the response to send when they can't be loaded
+async fn load_user_transactions(db: &DbClient, user: &ApiUser) -> std::result::Result<Vec<StoredTransaction>, HttpResponse> {
+    db.user_transactions(user.id).await.map_err(|e| {
+        tracing::error!(error = %e, "Failed to load portfolio transactions");
+        HttpResponse::InternalServerError().json(ErrorResponse::new("Database error"))
+    })
+}
+
+// Replays the transactions as they'd be after a change: a sell beyond what's held at
+// that point, anywhere in the history, is a 422 rejecting the change
+fn check_transactions(transactions: &[StoredTransaction]) -> Option<HttpResponse> {
+    let replayed: Vec<Transaction> = transactions.iter().map(StoredTransaction::transaction).collect();
+    portfolio::positions(&replayed)
+        .err()
+        .map(|oversold| HttpResponse::UnprocessableEntity().json(ErrorResponse::new(oversold.to_string())))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/portfolio/transactions",
+    tag = "portfolio",
+    security(("api_key" = [])),
+    responses(
+        (status = 200, description = "The key's transactions, oldest first", body = Vec<TransactionEntry>),
+        (status = 401, description = "Missing or unknown API key", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn list_transactions(db: web::Data<DbClient>, user: ApiUser) -> Result<HttpResponse> {
+    let mut transactions = match load_user_transactions(&db, &user).await {
+        Ok(transactions) => transactions,
+        Err(response) => return Ok(response),
+    };
+    transactions.sort_by_key(|t| t.timestamp);
+    let entries: Vec<TransactionEntry> = transactions.iter().map(TransactionEntry::from).collect();
+    Ok(HttpResponse::Ok().json(entries))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/portfolio/transactions",
+    tag = "portfolio",
+    request_body = Transaction,
+    security(("api_key" = [])),
+    responses(
+        (status = 201, description = "Recorded transaction with its id", body = TransactionEntry),
+        (status = 400, description = "Malformed token id or a negative, zero or non-finite number", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown API key", body = ErrorResponse),
+        (status = 422, description = "A sell would exceed the quantity held at its time", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn create_transaction(
+    db: web::Data<DbClient>,
+    user: ApiUser,
+    body: web::Json<Transaction>,
+) -> Result<HttpResponse> {
+    let transaction = body.into_inner();
+    if let Err(message) = transaction.validate() {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message)));
+    }
+    let mut transactions = match load_user_transactions(&db, &user).await {
+        Ok(transactions) => transactions,
+        Err(response) => return Ok(response),
+    };
+    let mut stored = StoredTransaction::new(user.id, transaction);
+    stored.id = Some(ObjectId::new());
+    transactions.push(stored.clone());
+    if let Some(rejected) = check_transactions(&transactions) {
+        return Ok(rejected);
+    }
+
+    match db.insert_transaction(&stored).await {
+        Ok(()) => Ok(HttpResponse::Created().json(TransactionEntry::from(&stored))),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to record portfolio transaction");
+            Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")))
+        }
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/portfolio/transactions/{id}",
+    tag = "portfolio",
+    params(
+        ("id" = String, Path, description = "Transaction id from the list")
+    ),
+    request_body = Transaction,
+    security(("api_key" = [])),
+    responses(
+        (status = 200, description = "Transaction as replaced", body = TransactionEntry),
+        (status = 400, description = "Malformed id, token id or number", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown API key", body = ErrorResponse),
+        (status = 404, description = "No such transaction for this key", body = ErrorResponse),
+        (status = 422, description = "A sell would exceed the quantity held at its time", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn update_transaction(
+    db: web::Data<DbClient>,
+    user: ApiUser,
+    id: web::Path<String>,
+    body: web::Json<Transaction>,
+) -> Result<HttpResponse> {
+    let Ok(id) = ObjectId::parse_str(id.as_str()) else {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("Invalid transaction id")));
+    };
+    let transaction = body.into_inner();
+    if let Err(message) = transaction.validate() {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message)));
+    }
+    let mut transactions = match load_user_transactions(&db, &user).await {
+        Ok(transactions) => transactions,
+        Err(response) => return Ok(response),
+    };
+    let Some(existing) = transactions.iter_mut().find(|t| t.id == Some(id)) else {
+        return Ok(HttpResponse::NotFound().json(ErrorResponse::new("Transaction not found")));
+    };
+    let mut stored = StoredTransaction::new(user.id, transaction);
+    stored.id = Some(id);
+    *existing = stored.clone();
+    if let Some(rejected) = check_transactions(&transactions) {
+        return Ok(rejected);
+    }
+
+    match db.replace_transaction(&stored).await {
+        Ok(true) => Ok(HttpResponse::Ok().json(TransactionEntry::from(&stored))),
+        Ok(false) => Ok(HttpResponse::NotFound().json(ErrorResponse::new("Transaction not found"))),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to update portfolio transaction");
+            Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")))
+        }
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/portfolio/transactions/{id}",
+    tag = "portfolio",
+    params(
+        ("id" = String, Path, description = "Transaction id from the list")
+    ),
+    security(("api_key" = [])),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 400, description = "Malformed id", body = ErrorResponse),
+        (status = 401, description = "Missing or unknown API key", body = ErrorResponse),
+        (status = 404, description = "No such transaction for this key", body = ErrorResponse),
+        (status = 422, description = "A later sell would exceed what's held without this buy", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn delete_transaction(db: web::Data<DbClient>, user: ApiUser, id: web::Path<String>) -> Result<HttpResponse> {
+    let Ok(id) = ObjectId::parse_str(id.as_str()) else {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("Invalid transaction id")));
+    };
+    let mut transactions = match load_user_transactions(&db, &user).await {
+        Ok(transactions) => transactions,
+        Err(response) => return Ok(response),
+    };
+    let before = transactions.len();
+    transactions.retain(|t| t.id != Some(id));
+    if transactions.len() == before {
+        return Ok(HttpResponse::NotFound().json(ErrorResponse::new("Transaction not found")));
+    }
+    if let Some(rejected) = check_transactions(&transactions) {
+        return Ok(rejected);
+    }
+
+    match db.delete_transaction(user.id, id).await {
+        Ok(true) => Ok(HttpResponse::NoContent().finish()),
+        Ok(false) => Ok(HttpResponse::NotFound().json(ErrorResponse::new("Transaction not found"))),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to delete portfolio transaction");
+            Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/portfolio/summary",
+    tag = "portfolio",
+    security(("api_key" = [])),
+    responses(
+        (status = 200, description = "Holdings, average cost and realized P&L per token by FIFO lot matching, unrealized P&L at the cached price, and totals", body = PortfolioSummary),
+        (status = 401, description = "Missing or unknown API key", body = ErrorResponse),
+        (status = 422, description = "Stored transactions sell more than was held", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn get_portfolio_summary(
+    db: web::Data<DbClient>,
+    token_cache: web::Data<TokenCache>,
+    user: ApiUser,
+) -> Result<HttpResponse> {
+    let transactions = match load_user_transactions(&db, &user).await {
+        Ok(transactions) => transactions,
+        Err(response) => return Ok(response),
+    };
+    let replayed: Vec<Transaction> = transactions.iter().map(StoredTransaction::transaction).collect();
+    let positions = match portfolio::positions(&replayed) {
+        Ok(positions) => positions,
+        Err(oversold) => return Ok(HttpResponse::UnprocessableEntity().json(ErrorResponse::new(oversold.to_string()))),
+    };
+
+    let cached = load_tokens(&db.get_tokens_collection(), &token_cache).await;
+    let prices: std::collections::HashMap<&str, f64> = cached
+        .iter()
+        .filter(|t| t.current_price.is_finite())
+        .map(|t| (t.token_id.as_str(), t.current_price))
+        .collect();
+    let positions: Vec<PortfolioPosition> = positions
+        .into_iter()
+        .map(|position| {
+            let current_price = prices.get(position.token_id.as_str()).copied();
+            let market_value = current_price.map(|price| position.quantity * price);
+            PortfolioPosition {
+                average_cost: position.average_cost(),
+                quantity: position.quantity,
+                cost_basis: position.cost_basis,
+                realized_pnl: position.realized_pnl,
+                current_price,
+                market_value,
+                unrealized_pnl: market_value.map(|value| value - position.cost_basis),
+                token_id: position.token_id,
+            }
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(PortfolioSummary {
+        total_cost_basis: positions.iter().map(|p| p.cost_basis).sum(),
+        total_market_value: positions.iter().filter_map(|p| p.market_value).sum(),
+        total_realized_pnl: positions.iter().map(|p| p.realized_pnl).sum(),
+        total_unrealized_pnl: positions.iter().filter_map(|p| p.unrealized_pnl).sum(),
+        positions,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/search",
+    tag = "search",
+    params(
+        ("q" = Option<String>, Query, description = "Matched against name, symbol and id (case-insensitive). \
+            Whole words go through the text index, best score first with symbol matches weighted highest; \
+            queries under 3 characters, with symbols, or the index finds nothing for match substrings, \
+            exact symbol matches first, then symbol prefixes, names and ids"),
+        ("all" = Option<bool>, Query, description = "With no `q`, return every token by market cap instead of a 400"),
+        ("limit" = Option<usize>, Query, minimum = 1, maximum = 200, description = "Most results to return, defaults to 50"),
+        ("fuzzy" = Option<bool>, Query, description = "Fall back to typo-tolerant matching when nothing matches exactly"),
+        ("min_score" = Option<f64>, Query, minimum = 0.0, maximum = 1.0,
+            description = "Minimum similarity for fuzzy matches, defaults to 0.7"),
+        ("include_hidden" = Option<bool>, Query, description = "Also match tokens hidden through /api/admin/tokens/{id}/hide"),
+        ("debug" = Option<bool>, Query, description = "Add each result's text search `score`; results found another way have none")
+    ),
+    responses(
+        (status = 200, description = "Matching cached tokens, most relevant first; CoinGecko's own matches when nothing cached matches", body = Vec<ScoredToken>),
+        (status = 400, description = "Missing query without all=true, or invalid limit or min_score", body = ErrorResponse)
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn search_tokens(
+    config: web::Data<Config>,
+    db: web::Data<DbClient>,
+    crypto_service: web::Data<CryptoService>,
+    rate_limiter: web::Data<RateLimiter>,
+    upstream: web::Data<UpstreamGate>,
+    background_tasks: web::Data<BackgroundTasks>,
+    token_cache: web::Data<TokenCache>,
+    notifier: web::Data<WebhookNotifier>,
+    events: web::Data<EventRecorder>,
+    query: web::Query<std::collections::HashMap<String, String>>,
+) -> Result<HttpResponse> {
+    let search_query = query.get("q").map(|s| s.as_str()).unwrap_or("");
+    let all = query.get("all").is_some_and(|v| v == "true");
+
+    // Matching everything has to be asked for, so a cleared search box isn't a full dump
+    if search_query.is_empty() && !all {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("Search query is required (or all=true for every token)")));
+    }
+
+    let limit = match query.get("limit") {
+        Some(raw) => match raw.parse::<usize>() {
+            Ok(limit) if (1..=MAX_SEARCH_LIMIT).contains(&limit) => limit,
+            _ => {
+                return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(format!(
+                    "limit must be between 1 and {}",
+                    MAX_SEARCH_LIMIT
+                ))));
+            }
+        },
+        None => DEFAULT_SEARCH_LIMIT,
+    };
+
+    let fuzzy = query.get("fuzzy").map(|v| v == "true").unwrap_or(false);
+    let min_score = match query.get("min_score") {
+        Some(raw) => match raw.parse::<f64>() {
+            Ok(score) if (0.0..=1.0).contains(&score) => score,
+            _ => {
+                return Ok(HttpResponse::BadRequest().json(
+                    ErrorResponse::new("min_score must be a number between 0 and 1")
+                ));
+            }
+        },
+        None => search::DEFAULT_FUZZY_MIN_SCORE,
+    };
+
+    let include_hidden = query.get("include_hidden").is_some_and(|v| v == "true");
+    let debug = query.get("debug").is_some_and(|v| v == "true");
+
+    let collection = db.get_tokens_collection();
+    let filter = TokenFilter { exclude_hidden: !include_hidden, ..TokenFilter::default() };
+    let visible = |tokens: &[CryptoToken]| -> Vec<CryptoToken> {
+        tokens.iter().filter(|t| filter.matches(t)).cloned().collect()
+    };
+
+    // Whole words go to the text index, which ranks by relevance and matches them in any
+    // order. It misses prefixes still being typed, so finding nothing (or no index) falls
+    // through to the substring matching below.
+    let text_matches = if search::uses_text_index(search_query) {
+        db.text_search(search_query, filter.to_document(), limit).await.unwrap_or_else(|e| {
+            tracing::warn!(query = search_query, error = %e, "Text search failed, matching substrings instead");
+            Vec::new()
+        })
+    } else {
+        Vec::new()
+    };
+    let scores: std::collections::HashMap<String, f64> =
+        text_matches.iter().map(|(token, score)| (token.token_id.clone(), *score)).collect();
+
+    // Search in cached data instead of making API call. A warm memory cache is searched
+    // as is; otherwise MongoDB does the substring matching and only matches are loaded.
+    let (mut filtered, mut freshness) = if !text_matches.is_empty() {
+        let matches: Vec<CryptoToken> = text_matches.into_iter().map(|(token, _)| token).collect();
+        let freshness = Freshness::of(&matches, config.token_cache_ttl_secs);
+        (matches, freshness)
+    } else if search_query.is_empty() {
+        let cached_tokens = load_tokens(&collection, &token_cache).await;
+        (visible(&cached_tokens), Freshness::of(&cached_tokens, config.token_cache_ttl_secs))
+    } else if let Some(cached_tokens) = token_cache.get().await {
+        let matches = search::substring_matches(&visible(&cached_tokens), search_query);
+        (matches, Freshness::of(&cached_tokens, config.token_cache_ttl_secs))
+    } else {
+        let mut clauses = vec![search::substring_filter(search_query)];
+        if !include_hidden {
+            clauses.push(filter.to_document());
+        }
+        let matches = get_cached_tokens(&collection, doc! { "$and": clauses }).await;
+        let freshness = Freshness::of(&matches, config.token_cache_ttl_secs);
+        (search::substring_matches(&matches, search_query), freshness)
+    };
+
+    // Only fall back to the slower fuzzy pass, which needs every token, when the fast
+    // path finds nothing
+    if filtered.is_empty() && fuzzy && !search_query.is_empty() {
+        let cached_tokens = load_tokens(&collection, &token_cache).await;
+        filtered = search::fuzzy_matches(visible(&cached_tokens), search_query, min_score);
+    }
+
+    // Coins outside the cached list are only found upstream. Asked as a last resort, and
+    // never for a one-letter query, so typing into a search box doesn't spend the budget.
+    if filtered.is_empty() && search_query.chars().count() >= MIN_LIVE_SEARCH_CHARS && rate_limiter.try_acquire().await {
+        match upstream.run(crypto_service.search_tokens(search_query)).await {
+            Ok(found) if !found.is_empty() => {
+                let hidden = db.hidden_token_ids().await.unwrap_or_else(|e| {
+                    tracing::warn!(error = %e, "Failed to load hidden tokens");
+                    Default::default()
+                });
+                for token in &found {
+                    if (include_hidden || !hidden.contains(&token.token_id))
+                        && !filtered.iter().any(|t| t.token_id == token.token_id)
+                    {
+                        filtered.push(token.clone());
+                    }
+                }
+                freshness = Freshness::live();
+                tracing::info!(query = search_query, count = found.len(), "Found tokens through CoinGecko search");
+
+                // Cached like any other token so the next search finds them locally
+                let collection = collection.clone();
+                let token_cache = token_cache.clone();
+                let notifier = notifier.clone();
+                let events = events.clone();
+                background_tasks.spawn(move |_| async move {
+                    save_tokens_to_cache(&collection, &token_cache, &notifier, &events, &found).await;
+                });
+            }
+            Ok(_) => {}
+            Err(e) => {
+                if e == CryptoServiceError::RateLimited {
+                    rate_limiter.record_rate_limit().await;
+                }
+                tracing::warn!(query = search_query, error = %e, "CoinGecko search failed");
+            }
+        }
+    }
+    filtered.truncate(limit);
+    // Sparklines are only sent with ?sparkline=true on /api/tokens
+    for token in &mut filtered {
+        token.sparkline_7d = None;
+    }
+
+    if debug {
+        let scored: Vec<ScoredToken> = filtered
+            .into_iter()
+            .map(|token| ScoredToken { score: scores.get(&token.token_id).copied(), token })
+            .collect();
+        return Ok(envelope::attach(HttpResponse::Ok().json(scored), freshness));
+    }
+    Ok(envelope::attach(HttpResponse::Ok().json(filtered), freshness))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/search/coins",
+    tag = "search",
+    params(
+        ("q" = String, Query, description = "Name or symbol, at least two characters")
+    ),
+    responses(
+        (status = 200, description = "Up to 10 coins CoinGecko lists matching the query, best match first, whether cached here or not", body = Vec<CoinSearchResult>),
+        (status = 400, description = "Missing or one-character query", body = ErrorResponse),
+        (status = 503, description = "Upstream is rate limited, busy or failing", body = ErrorResponse,
+            headers(("Retry-After" = u64, description = "Seconds until the next upstream call is allowed")))
+    )
+)]
+pub async fn search_coins(
+    crypto_service: web::Data<CryptoService>,
+    rate_limiter: web::Data<RateLimiter>,
+    upstream: web::Data<UpstreamGate>,
+    query: web::Query<CoinSearchQuery>,
+) -> Result<HttpResponse> {
+    let search_query = query.q.as_deref().map(str::trim).unwrap_or("");
+    if search_query.chars().count() < MIN_LIVE_SEARCH_CHARS {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(format!(
+            "q must be at least {} characters",
+            MIN_LIVE_SEARCH_CHARS
+        ))));
+    }
+
+    if rate_limiter.try_acquire().await {
+        match upstream.run(crypto_service.search_coins(search_query)).await {
+            Ok(coins) => return Ok(HttpResponse::Ok().json(coins)),
+            Err(CryptoServiceError::Busy) => {
+                return Ok(HttpResponse::ServiceUnavailable()
+                    .insert_header((header::RETRY_AFTER, "1"))
+                    .json(ErrorResponse::new("Too many upstream requests in flight").with_retry_after(1)));
+            }
+            Err(e) => {
+                if e == CryptoServiceError::RateLimited {
+                    rate_limiter.record_rate_limit().await;
+                }
+                tracing::error!(query = search_query, error = %e, "CoinGecko search failed");
+                return Ok(HttpResponse::ServiceUnavailable()
+                    .insert_header((header::RETRY_AFTER, "30"))
+                    .json(ErrorResponse::new("CoinGecko search is unavailable").with_retry_after(30)));
+            }
+        }
+    }
+
+    let retry_after = rate_limiter.seconds_until_next_call().await.max(1);
+    Ok(HttpResponse::ServiceUnavailable()
+        .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+        .json(ErrorResponse::new("Upstream is rate limited").with_retry_after(retry_after)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/convert",
+    tag = "tokens",
+    params(
+        ("from" = String, Query, description = "CoinGecko id of the token to convert from, e.g. `bitcoin`"),
+        ("to" = String, Query, description = "CoinGecko id of the token to convert to, or `usd`"),
+        ("amount" = Option<f64>, Query, description = "Amount of `from`, must be positive; defaults to 1")
+    ),
+    responses(
+        (status = 200, description = "Converted amount, the unit prices used and when each was updated", body = ConversionResult),
+        (status = 400, description = "Missing from/to or non-positive amount", body = ErrorResponse),
+        (status = 404, description = "Token ids CoinGecko doesn't know, with the side (from/to) of each", body = ErrorResponse),
+        (status = 422, description = "A price is zero or unusable, so the conversion has no finite result", body = ErrorResponse),
+        (status = 503, description = "A price isn't cached and upstream can't be asked right now", body = ErrorResponse,
+            headers(("Retry-After" = u64, description = "Seconds until the next upstream call is allowed")))
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn convert(
+    db: web::Data<DbClient>,
+    crypto_service: web::Data<CryptoService>,
+    rate_limiter: web::Data<RateLimiter>,
+    background_tasks: web::Data<BackgroundTasks>,
+    token_cache: web::Data<TokenCache>,
+    notifier: web::Data<WebhookNotifier>,
+    events: web::Data<EventRecorder>,
+    query: web::Query<ConvertQuery>,
+) -> Result<HttpResponse> {
+    let param = |value: &Option<String>| value.as_deref().map(str::trim).filter(|v| !v.is_empty()).map(String::from);
+    let (Some(from), Some(to)) = (param(&query.from), param(&query.to)) else {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("from and to are required")));
+    };
+    let amount = query.amount.unwrap_or(1.0);
+    if !amount.is_finite() || amount <= 0.0 {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("amount must be a positive number")));
+    }
+
+    // Both prices come from the cache when possible, so they share one USD base
+    let to_usd = to == USD;
+    let wanted: Vec<&String> = if to_usd { vec![&from] } else { vec![&from, &to] };
+    let collection = db.get_tokens_collection();
+    let cached = load_tokens(&collection, &token_cache).await;
+    let mut prices: std::collections::HashMap<String, (f64, DateTime<Utc>)> = cached
+        .iter()
+        .filter(|t| wanted.contains(&&t.token_id))
+        .map(|t| (t.token_id.clone(), (t.current_price, t.last_updated)))
+        .collect();
+
+    let mut missing: Vec<String> = wanted.iter().filter(|id| !prices.contains_key(**id)).map(|id| id.to_string()).collect();
+    missing.dedup();
+
+    if !missing.is_empty() {
+        let unavailable = |rate_limiter_wait: u64| {
+            HttpResponse::ServiceUnavailable()
+                .insert_header((header::RETRY_AFTER, rate_limiter_wait.to_string()))
+                .json(ErrorResponse::new("Price not cached and upstream is rate limited").with_retry_after(rate_limiter_wait))
+        };
+        if !rate_limiter.try_acquire().await {
+            return Ok(unavailable(rate_limiter.seconds_until_next_call().await.max(1)));
+        }
+
+        // One request for whatever is missing
+        match crypto_service.fetch_tokens_by_ids(&missing).await {
+            Ok(fetched) => {
+                prices.extend(fetched.iter().map(|t| (t.token_id.clone(), (t.current_price, t.last_updated))));
+                if !fetched.is_empty() {
+                    let token_cache = token_cache.clone();
+                    let notifier = notifier.clone();
+                    let events = events.clone();
+                    background_tasks.spawn(move |_| async move {
+                        save_tokens_to_cache(&collection, &token_cache, &notifier, &events, &fetched).await;
+                    });
+                }
+            }
+            Err(e) => {
+                tracing::error!(from = %from, to = %to, error = %e, "Failed to fetch prices for conversion");
+                if e == CryptoServiceError::RateLimited {
+                    rate_limiter.record_rate_limit().await;
+                }
+                return Ok(unavailable(rate_limiter.seconds_until_next_call().await.max(1)));
+            }
+        }
+    }
+
+    let unknown: Vec<String> = [("from", &from), ("to", &to)]
+        .into_iter()
+        .filter(|(_, id)| wanted.contains(id) && !prices.contains_key(*id))
+        .map(|(side, id)| format!("{} ({})", id, side))
+        .collect();
+    if !unknown.is_empty() {
+        return Ok(HttpResponse::NotFound().json(ErrorResponse::new(format!("Unknown token: {}", unknown.join(", ")))));
+    }
+
+    let (from_price, from_updated_at) = prices[&from];
+    let (to_price, to_updated_at) = if to_usd {
+        (1.0, None)
+    } else {
+        let (price, updated_at) = prices[&to];
+        (price, Some(updated_at))
+    };
+
+    let Some((result, rate)) = analytics::convert(amount, from_price, to_price) else {
+        return Ok(HttpResponse::UnprocessableEntity().json(ErrorResponse::new(format!(
+            "No usable price to convert {} into {}",
+            from, to
+        ))));
+    };
+
+    Ok(HttpResponse::Ok().json(ConversionResult {
+        from,
+        to,
+        amount,
+        result,
+        rate,
+        from_price,
+        to_price,
+        from_updated_at,
+        to_updated_at,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/history/{id}/{days}",
+    tag = "history",
+    params(
+        ("id" = String, Path, pattern = "^[a-z0-9-]{1,100}$", description = "CoinGecko token id"),
+        ("days" = String, Path, pattern = "^([0-9]{1,3}|max)$",
+            description = "Days of history, 1 to MAX_HISTORY_DAYS (365 by default), or `max`, which gets that many"),
+        ("points" = Option<usize>, Query, minimum = 2, maximum = 2000,
+            description = "Downsample each series to at most this many points, keeping the endpoints"),
+        ("currency" = Option<String>, Query,
+            description = "Quote currency from /api/currencies, defaults to usd. Only usd charts are served from cache"),
+        ("interval" = Option<String>, Query,
+            description = "hourly or daily. Defaults to CoinGecko's choice for the range: hourly up to 90 days, daily beyond")
+    ),
+    responses(
+        (status = 200, description = "Price, market cap and volume series as [timestamp_ms, value] pairs", body = CoinGeckoHistoricalData),
+        (status = 400, description = "Malformed token id, days outside 1..=MAX_HISTORY_DAYS (and not `max`), points outside 2..=2000, an unsupported currency or an unknown interval", body = ErrorResponse),
+        (status = 503, description = "Rate limited or upstream failure with nothing cached", body = ErrorResponse)
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn get_historical_data(
+    config: web::Data<Config>,
+    crypto_service: web::Data<CryptoService>,
+    db: web::Data<DbClient>,
+    rate_limiter: web::Data<RateLimiter>,
+    upstream: web::Data<UpstreamGate>,
+    history_flights: web::Data<HistoryFlights>,
+    path: web::Path<(String, String)>,
+    query: web::Query<HistoryQuery>,
+) -> Result<HttpResponse> {
+    let (token_id, days) = path.into_inner();
+
+    // Everything is checked before the rate limiter so bad requests never cost an upstream slot
+    if !is_valid_token_id(&token_id) {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(
+            "token id must be 1 to 100 lowercase letters, digits or dashes",
+        )));
+    }
+    // `max` is bounded like any other range, so it costs no more than the longest one
+    let limit = config.max_history_days;
+    let days = match days.parse::<HistoryDays>() {
+        Ok(HistoryDays::Days(days)) if days <= limit => HistoryDays::Days(days),
+        Ok(HistoryDays::Max) => HistoryDays::Days(limit),
+        _ => {
+            return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(format!(
+                "days must be a positive integer up to {} (MAX_HISTORY_DAYS) or 'max'",
+                limit
+            ))));
+        }
+    };
+
+    if let Some(points) = query.points {
+        if !(analytics::MIN_POINTS..=analytics::MAX_POINTS).contains(&points) {
+            return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(format!(
+                "points must be between {} and {}",
+                analytics::MIN_POINTS,
+                analytics::MAX_POINTS
+            ))));
+        }
+    }
+    let currency = match currency::parse(query.currency.as_deref()) {
+        Ok(currency) => currency,
+        Err(message) => return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message))),
+    };
+    let requested = match query.interval.as_deref().map(str::parse::<HistoryInterval>) {
+        None => None,
+        Some(Ok(interval)) => Some(interval),
+        Some(Err(message)) => return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message))),
+    };
+    // Charts are cached under the granularity they hold, asked for or picked by CoinGecko
+    let interval = requested.unwrap_or_else(|| HistoryInterval::auto(days));
+    // Only usd charts are cached; other currencies always come straight from upstream
+    let cacheable = currency == currency::DEFAULT_CURRENCY;
+    let shape = |data: CoinGeckoHistoricalData| match query.points {
+        Some(points) => analytics::downsample_history(data, points),
+        None => data,
+    };
+
+    // A stored series covering the window that hasn't expired answers without spending
+    // a rate-limit slot
+    if cacheable {
+        if let Some(data) = cached_history(&db, &token_id, days, interval, Some(Utc::now())).await {
+            tracing::debug!(token_id = %token_id, days = %days, %interval, "Serving fresh cached historical data");
+            return Ok(HttpResponse::Ok().json(shape(data)));
+        }
+    }
+
+    // Whatever is cached, for when upstream can't be asked or doesn't answer in time
+    let serve_cached = || async {
+        let cached = if cacheable { cached_history(&db, &token_id, days, interval, None).await } else { None };
+        if let Some(data) = cached {
+            tracing::info!(token_id = %token_id, days = %days, %interval, "Returning cached historical data");
+            return HttpResponse::Ok().json(shape(data));
+        }
+        
+        HttpResponse::ServiceUnavailable().json(
+            ErrorResponse::new("Historical data temporarily unavailable. Please try again shortly.").with_retry_after(30)
+        )
+    };
+
+    // Identical requests arriving together share one rate-limit slot and one upstream
+    // call, and the chart is saved once
+    let fetch = {
+        let (crypto_service, rate_limiter, upstream, db) = (crypto_service.clone(), rate_limiter.clone(), upstream.clone(), db.clone());
+        let token_id = token_id.clone();
+        move || async move {
+            if !rate_limiter.try_acquire().await {
+                return None;
+            }
+
+            tracing::info!(token_id = %token_id, days = %days, currency, %interval, "Fetching historical data from CoinGecko");
+            let result = upstream.run(crypto_service.fetch_historical_data_in(&token_id, days, currency, requested)).await;
+            match &result {
+                Ok(data) if cacheable => {
+                    if let Err(e) = db.save_history(&token_id, days, interval, data).await {
+                        tracing::warn!(token_id = %token_id, days = %days, error = %e, "Failed to cache historical data");
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    if *e == CryptoServiceError::RateLimited {
+                        rate_limiter.record_rate_limit().await;
+                    }
+                    tracing::error!(token_id = %token_id, days = %days, error = %e, "Failed to fetch historical data");
+                }
+            }
+            Some(result)
+        }
+    };
+    match history_flights.run((token_id.clone(), days, requested, currency), fetch).await {
+        Some(Ok(data)) => Ok(HttpResponse::Ok().json(shape(data))),
+        // No rate-limit slot, or upstream didn't answer in time
+        _ => Ok(serve_cached().await),
+    }
+}
+
+// Concurrent /api/history fetches by (token_id, days, interval asked for, currency)
+pub type HistoryFlightKey = (String, HistoryDays, Option<HistoryInterval>, &'static str);
+// None when the rate limiter had no slot for the fetch
+pub type HistoryFlights = SingleFlight<HistoryFlightKey, Option<Result<CoinGeckoHistoricalData, CryptoServiceError>>>;
+
+// The window of the stored series shaped like CoinGecko's own chart for it. Daily
+// charts get one point per UTC day (hourly points rolled up to the last of each day),
+// hourly ones past a single day one per hour, since the series may hold the 5-minutely
+// points of single-day charts. Database errors count as a miss.
+async fn cached_history(
+    db: &DbClient,
+    token_id: &str,
+    days: HistoryDays,
+    interval: HistoryInterval,
+    fresh_at: Option<DateTime<Utc>>,
+) -> Option<CoinGeckoHistoricalData> {
+    match db.cached_history(token_id, days, interval, fresh_at).await {
+        Ok(Some((HistoryInterval::Daily, data))) => Some(analytics::compact_daily_history(data)),
+        Ok(Some((_, hourly))) if interval == HistoryInterval::Daily => Some(analytics::daily_history(hourly)),
+        Ok(Some((_, data))) if days == HistoryDays::Days(1) => Some(data),
+        Ok(Some((_, data))) => Some(analytics::hourly_history(data)),
+        Ok(None) => None,
+        Err(e) => {
+            tracing::warn!(token_id, error = %e, "Failed to read cached historical data");
+            None
+        }
+    }
+}
+
+const MAX_CORRELATION_TOKENS: usize = 10;
+const DEFAULT_CORRELATION_DAYS: u32 = 90;
+// Fewer common days than this and a correlation says very little
+const MIN_CORRELATION_DAYS: usize = 5;
+
+#[utoipa::path(
+    get,
+    path = "/api/correlation",
+    tag = "stats",
+    params(
+        ("tokens" = String, Query, description = "2 to 10 comma-separated token ids, e.g. bitcoin,ethereum,solana"),
+        ("days" = Option<u32>, Query, minimum = 2, maximum = 365, description = "Window in days up to MAX_HISTORY_DAYS (365 by default), defaults to 90 or MAX_HISTORY_DAYS if lower")
+    ),
+    responses(
+        (status = 200, description = "Correlation of daily returns over cached history, plus the tokens left out", body = CorrelationMatrix),
+        (status = 400, description = "Missing, malformed or too many tokens, or days out of range", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn get_correlation(
+    db: web::Data<DbClient>,
+    config: web::Data<Config>,
+    query: web::Query<CorrelationQuery>,
+) -> Result<HttpResponse> {
+    let mut token_ids: Vec<String> = Vec::new();
+    for token_id in query.tokens.as_deref().unwrap_or("").split(',').map(str::trim).filter(|t| !t.is_empty()) {
+        if !is_valid_token_id(token_id) {
+            return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(format!("Invalid token id '{}'", token_id))));
+        }
+        if !token_ids.iter().any(|t| t == token_id) {
+            token_ids.push(token_id.to_string());
+        }
+    }
+    if !(2..=MAX_CORRELATION_TOKENS).contains(&token_ids.len()) {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(format!(
+            "tokens must list between 2 and {} distinct token ids",
+            MAX_CORRELATION_TOKENS
+        ))));
+    }
+    // Bounded like /api/history, so a lowered MAX_HISTORY_DAYS applies here too
+    let days = query.days.unwrap_or(DEFAULT_CORRELATION_DAYS.min(config.max_history_days));
+    if !(2..=config.max_history_days).contains(&days) {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(format!(
+            "days must be between 2 and {} (MAX_HISTORY_DAYS)",
+            config.max_history_days
+        ))));
+    }
+
+    // Cached charts only; this never spends upstream calls
+    let mut cached = match db.cached_price_series(&token_ids).await {
+        Ok(cached) => cached,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to load cached price history");
+            return Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")));
+        }
+    };
+
+    let cutoff = (Utc::now() - chrono::Duration::days(days as i64)).timestamp_millis();
+    let mut included: Vec<(String, Vec<(i64, f64)>)> = Vec::new();
+    let mut skipped = Vec::new();
+    for token_id in token_ids {
+        let Some(prices) = cached.remove(&token_id) else {
+            skipped.push(SkippedToken { token_id, reason: "no cached price history".to_string() });
+            continue;
+        };
+        let in_window: Vec<(i64, f64)> = prices.into_iter().filter(|(t, _)| *t >= cutoff).collect();
+        let closes = analytics::daily_closes(&in_window);
+        if closes.len() < MIN_CORRELATION_DAYS {
+            let reason = format!("only {} days of cached history in the last {} days", closes.len(), days);
+            skipped.push(SkippedToken { token_id, reason });
+        } else {
+            included.push((token_id, closes));
+        }
+    }
+
+    // Drop the shortest series until the rest share enough days
+    let (common_days, aligned) = loop {
+        let series: Vec<Vec<(i64, f64)>> = included.iter().map(|(_, closes)| closes.clone()).collect();
+        let (common_days, aligned) = analytics::align(&series);
+        if common_days.len() >= MIN_CORRELATION_DAYS || included.len() <= 1 {
+            break (common_days, aligned);
+        }
+        let shortest = (0..included.len()).min_by_key(|&i| included[i].1.len()).unwrap_or(0);
+        let (token_id, _) = included.remove(shortest);
+        skipped.push(SkippedToken { token_id, reason: "not enough history overlapping the other tokens".to_string() });
+    };
+
+    let returns: Vec<Vec<f64>> = aligned.iter().map(|prices| analytics::returns(prices)).collect();
+    Ok(HttpResponse::Ok().json(CorrelationMatrix {
+        days,
+        tokens: included.into_iter().map(|(token_id, _)| token_id).collect(),
+        points: common_days.len(),
+        matrix: analytics::correlation_matrix(&returns),
+        skipped,
+    }))
+}
+
+// Tokens, by current market cap, whose summed market caps bitcoin's dominance is taken over
+const DOMINANCE_TOP_TOKENS: usize = 10;
+const DEFAULT_DOMINANCE_DAYS: u32 = 30;
+// A token's cached market cap counts at a timestamp up to this far from it
+const DOMINANCE_TOLERANCE_MS: i64 = 24 * 60 * 60 * 1000;
+// Share of the top tokens' current market cap that must have history at a point
+const MIN_DOMINANCE_COVERAGE: f64 = 0.8;
+
+#[utoipa::path(
+    get,
+    path = "/api/dominance",
+    tag = "stats",
+    params(
+        ("days" = Option<u32>, Query, minimum = 1, maximum = 365, description = "Window in days, defaults to 30")
+    ),
+    responses(
+        (status = 200, description = "Bitcoin's share of the top 10 tokens' market cap over cached history, \
+            or a single current point when too little history is cached", body = DominanceHistory),
+        (status = 400, description = "days out of range", body = ErrorResponse),
+        (status = 404, description = "Bitcoin isn't in the cached token list yet", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn get_dominance(
+    db: web::Data<DbClient>,
+    token_cache: web::Data<TokenCache>,
+    query: web::Query<DominanceQuery>,
+) -> Result<HttpResponse> {
+    let days = query.days.unwrap_or(DEFAULT_DOMINANCE_DAYS);
+    if !(1..=MAX_HISTORY_DAYS).contains(&days) {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(format!(
+            "days must be between 1 and {}",
+            MAX_HISTORY_DAYS
+        ))));
+    }
+
+    let collection = db.get_tokens_collection();
+    let tokens = load_filtered_tokens(&collection, &token_cache, &TokenFilter::listed(None)).await;
+    let mut top: Vec<&CryptoToken> = tokens.iter().filter(|t| t.market_cap.is_finite() && t.market_cap > 0.0).collect();
+    top.sort_by(|a, b| cmp_f64(b.market_cap, a.market_cap));
+    top.truncate(DOMINANCE_TOP_TOKENS);
+    let Some(bitcoin) = top.iter().find(|t| t.token_id == "bitcoin").copied() else {
+        return Ok(HttpResponse::NotFound().json(ErrorResponse::new("bitcoin isn't among the cached top tokens yet")));
+    };
+    let token_ids: Vec<String> = top.iter().map(|t| t.token_id.clone()).collect();
+
+    // Cached charts only, like /api/correlation; history exists for tokens someone viewed
+    let mut caps = match db.cached_market_cap_series(&token_ids).await {
+        Ok(caps) => caps,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to load cached market cap history");
+            return Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")));
+        }
+    };
+    let mut sorted_caps = |token_id: &str| {
+        let mut points = caps.remove(token_id).unwrap_or_default();
+        points.sort_by_key(|(t, _)| *t);
+        points
+    };
+
+    let cutoff = (Utc::now() - chrono::Duration::days(days as i64)).timestamp_millis();
+    let btc: Vec<(i64, f64)> = sorted_caps("bitcoin").into_iter().filter(|(t, _)| *t >= cutoff).collect();
+    let others: Vec<(f64, Vec<(i64, f64)>)> = top
+        .iter()
+        .filter(|t| t.token_id != "bitcoin")
+        .map(|t| (t.market_cap, sorted_caps(&t.token_id)))
+        .collect();
+    let history = analytics::dominance_series(&btc, bitcoin.market_cap, &others, DOMINANCE_TOLERANCE_MS, MIN_DOMINANCE_COVERAGE);
+
+    let (source, series) = if history.len() >= 2 {
+        let series = history.into_iter().map(|(timestamp, btc_dominance)| DominancePoint { timestamp, btc_dominance }).collect();
+        (DominanceSource::History, series)
+    } else {
+        // Not enough of the top tokens' history cached; today's figure is all there is
+        let total: f64 = top.iter().map(|t| t.market_cap).sum();
+        let newest = top.iter().map(|t| t.fetched_at.unwrap_or(t.last_updated)).max().unwrap_or_else(Utc::now);
+        let point = DominancePoint { timestamp: newest.timestamp_millis(), btc_dominance: bitcoin.market_cap / total * 100.0 };
+        (DominanceSource::Snapshot, vec![point])
+    };
+
+    Ok(HttpResponse::Ok().json(DominanceHistory { days, source, tokens: token_ids, series }))
+}
+
+const DEFAULT_MARKET_HISTORY_DAYS: u32 = 30;
+
+#[utoipa::path(
+    get,
+    path = "/api/market/history",
+    tag = "stats",
+    params(
+        ("days" = Option<u32>, Query, minimum = 1, maximum = 365, description = "Window in days, defaults to 30")
+    ),
+    responses(
+        (status = 200, description = "Summed cached market caps per UTC day, with how many tokens each day covers", body = Vec<MarketCapPoint>),
+        (status = 400, description = "days out of range", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn get_market_history(db: web::Data<DbClient>, query: web::Query<MarketHistoryQuery>) -> Result<HttpResponse> {
+    let days = query.days.unwrap_or(DEFAULT_MARKET_HISTORY_DAYS);
+    if !(1..=MAX_HISTORY_DAYS).contains(&days) {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(format!(
+            "days must be between 1 and {}",
+            MAX_HISTORY_DAYS
+        ))));
+    }
+
+    // Cached charts only, like /api/correlation; hidden tokens stay out as in /api/stats
+    let caps = async {
+        let hidden = db.hidden_token_ids().await?;
+        let token_ids: Vec<String> = db.history_token_ids().await?.into_iter().filter(|id| !hidden.contains(id)).collect();
+        db.cached_market_cap_series(&token_ids).await
+    };
+    let caps = match caps.await {
+        Ok(caps) => caps,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to load cached market cap history");
+            return Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")));
+        }
+    };
+
+    let cutoff = (Utc::now() - chrono::Duration::days(days as i64)).timestamp_millis();
+    let daily: Vec<Vec<(i64, f64)>> = caps
+        .into_values()
+        .map(|points| {
+            let in_window: Vec<(i64, f64)> = points.into_iter().filter(|(t, _)| *t >= cutoff).collect();
+            analytics::daily_closes(&in_window)
+        })
+        .collect();
+    let series: Vec<MarketCapPoint> = analytics::daily_totals(&daily)
+        .into_iter()
+        .map(|(day, total_market_cap, tokens_included)| MarketCapPoint {
+            timestamp: analytics::day_start_ms(day),
+            total_market_cap,
+            tokens_included,
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(series))
+}
+
+// Events the daily report lists at most
+const REPORT_EVENT_LIMIT: i64 = 50;
+
+#[utoipa::path(
+    get,
+    path = "/api/report/daily",
+    tag = "stats",
+    params(
+        ("format" = Option<String>, Query, description = "json (the default) or markdown")
+    ),
+    responses(
+        (status = 200, description = "Digest of the stored market: total market cap against 24h earlier, top 5 gainers and losers, favorites that moved more than 5% and the last day's events. As `text/markdown` with format=markdown", body = DailyReport),
+        (status = 400, description = "Unknown format", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn get_daily_report(
+    db: web::Data<DbClient>,
+    token_cache: web::Data<TokenCache>,
+    query: web::Query<ReportQuery>,
+) -> Result<HttpResponse> {
+    let format = match ReportFormat::parse(query.format.as_deref()) {
+        Ok(format) => format,
+        Err(message) => return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message))),
+    };
+
+    // Stored data only, so the report costs no upstream calls
+    let now = Utc::now();
+    let tokens = load_filtered_tokens(&db.get_tokens_collection(), &token_cache, &TokenFilter::listed(None)).await;
+    let stored = async {
+        let favorites = db.favorite_token_ids().await?;
+        let events = db.recent_events(Some(now - chrono::Duration::hours(24)), None, REPORT_EVENT_LIMIT).await?;
+        Ok::<_, mongodb::error::Error>((favorites, events))
+    };
+    let (favorites, events) = match stored.await {
+        Ok(stored) => stored,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to load favorites and events for the daily report");
+            return Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")));
+        }
+    };
+
+    let daily: DailyReport = report::build(&tokens, &favorites, events, now);
+    Ok(match format {
+        ReportFormat::Json => HttpResponse::Ok().json(daily),
+        ReportFormat::Markdown => HttpResponse::Ok().content_type("text/markdown; charset=utf-8").body(report::to_markdown(&daily)),
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/currencies",
+    tag = "stats",
+    responses(
+        (status = 200, description = "Currency codes accepted by `currency` parameters", body = [String])
+    )
+)]
+pub async fn get_currencies() -> HttpResponse {
+    HttpResponse::Ok().json(currency::SUPPORTED_CURRENCIES)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/categories",
+    tag = "tokens",
+    responses(
+        (status = 200, description = "CoinGecko categories; `category_id` is what the `category` filter takes", body = [Category]),
+        (status = 503, description = "Rate limited and nothing stored yet", body = ErrorResponse)
+    )
+)]
+pub async fn get_categories(
+    db: web::Data<DbClient>,
+    crypto_service: web::Data<CryptoService>,
+    rate_limiter: web::Data<RateLimiter>,
+    background_tasks: web::Data<BackgroundTasks>,
+) -> Result<HttpResponse> {
+    if rate_limiter.try_acquire().await {
+        match crypto_service.fetch_categories().await {
+            Ok(categories) if !categories.is_empty() => {
+                let db = db.clone();
+                let to_save = categories.clone();
+                background_tasks.spawn(move |_| async move {
+                    if let Err(e) = db.save_categories(&to_save).await {
+                        tracing::error!(error = %e, "Failed to save categories");
+                    }
+                });
+                return Ok(HttpResponse::Ok().json(categories));
+            }
+            Ok(_) => tracing::warn!("CoinGecko returned no categories"),
+            Err(e) => {
+                if e == CryptoServiceError::RateLimited {
+                    rate_limiter.record_rate_limit().await;
+                }
+                tracing::error!(error = %e, "Failed to fetch categories from CoinGecko");
+            }
+        }
+    }
+
+    match db.load_categories().await {
+        Ok(categories) if !categories.is_empty() => Ok(HttpResponse::Ok().json(categories)),
+        Ok(_) => Ok(HttpResponse::ServiceUnavailable().json(
+            ErrorResponse::new("Categories temporarily unavailable. Please try again in a moment.").with_retry_after(60)
+        )),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to load stored categories");
+            Ok(HttpResponse::ServiceUnavailable().json(
+                ErrorResponse::new("Categories temporarily unavailable. Please try again in a moment.").with_retry_after(60)
+            ))
+        }
+    }
+}
+
+// How long stored exchange rates are served before CoinGecko is asked again
+const EXCHANGE_RATES_TTL_SECS: i64 = 3600;
+
+// Values of `type` in CoinGecko's exchange rates; /api/exchange-rates also takes `all`
+const EXCHANGE_RATE_TYPES: [&str; 3] = ["fiat", "crypto", "commodity"];
+
+// The stored rates while they're under an hour old, then a fresh copy when the rate
+// limiter allows one, then the stored copy however old; None without any of them
+async fn current_exchange_rates(
+    db: &web::Data<DbClient>,
+    crypto_service: &CryptoService,
+    rate_limiter: &RateLimiter,
+    background_tasks: &BackgroundTasks,
+) -> Option<ExchangeRates> {
+    let stored = db.load_exchange_rates().await.unwrap_or_else(|e| {
+        tracing::warn!(error = %e, "Failed to load stored exchange rates");
+        None
+    });
+    if let Some(rates) = &stored {
+        if (Utc::now() - rates.fetched_at).num_seconds() < EXCHANGE_RATES_TTL_SECS {
+            return stored;
+        }
+    }
+
+    if rate_limiter.try_acquire().await {
+        match crypto_service.fetch_exchange_rates().await {
+            Ok(rates) if !rates.rates.is_empty() => {
+                let db = db.clone();
+                let to_save = rates.clone();
+                background_tasks.spawn(move |_| async move {
+                    if let Err(e) = db.save_exchange_rates(&to_save).await {
+                        tracing::error!(error = %e, "Failed to save exchange rates");
+                    }
+                });
+                return Some(rates);
+            }
+            Ok(_) => tracing::warn!("CoinGecko returned no exchange rates"),
+            Err(e) => {
+                if e == CryptoServiceError::RateLimited {
+                    rate_limiter.record_rate_limit().await;
+                }
+                tracing::error!(error = %e, "Failed to fetch exchange rates from CoinGecko");
+            }
+        }
+    }
+    stored
+}
+
+async fn exchange_rates_unavailable(rate_limiter: &RateLimiter) -> HttpResponse {
+    let retry_after = rate_limiter.seconds_until_next_call().await.max(1);
+    HttpResponse::ServiceUnavailable()
+        .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+        .json(ErrorResponse::new("Exchange rates not stored and CoinGecko is unavailable").with_retry_after(retry_after))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/exchange-rates",
+    tag = "tokens",
+    params(
+        ("type" = Option<String>, Query, description = "fiat (default), crypto, commodity, or all")
+    ),
+    responses(
+        (status = 200, description = "BTC exchange rates keyed by lowercase currency code, stored for an hour and served past that while CoinGecko is unavailable", body = ExchangeRates),
+        (status = 400, description = "Unknown type", body = ErrorResponse),
+        (status = 503, description = "Nothing stored and upstream is rate limited or failing", body = ErrorResponse,
+            headers(("Retry-After" = u64, description = "Seconds until the next upstream call is allowed")))
+    )
+)]
+pub async fn get_exchange_rates(
+    db: web::Data<DbClient>,
+    crypto_service: web::Data<CryptoService>,
+    rate_limiter: web::Data<RateLimiter>,
+    background_tasks: web::Data<BackgroundTasks>,
+    query: web::Query<ExchangeRatesQuery>,
+) -> Result<HttpResponse> {
+    let rate_type = query.rate_type.as_deref().unwrap_or("fiat");
+    if rate_type != "all" && !EXCHANGE_RATE_TYPES.contains(&rate_type) {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(format!(
+            "Unknown type '{}'; expected fiat, crypto, commodity or all",
+            rate_type
+        ))));
+    }
+
+    let Some(mut rates) = current_exchange_rates(&db, &crypto_service, &rate_limiter, &background_tasks).await else {
+        return Ok(exchange_rates_unavailable(&rate_limiter).await);
+    };
+    if rate_type != "all" {
+        rates.rates.retain(|_, rate| rate.rate_type == rate_type);
+    }
+    Ok(HttpResponse::Ok().json(rates))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/exchange-rates/{code}",
+    tag = "tokens",
+    params(
+        ("code" = String, Path, description = "Currency code, e.g. `eur`; case-insensitive")
+    ),
+    responses(
+        (status = 200, description = "How many of the currency one BTC buys", body = ExchangeRate),
+        (status = 404, description = "CoinGecko has no rate for this code", body = ErrorResponse),
+        (status = 503, description = "Nothing stored and upstream is rate limited or failing", body = ErrorResponse,
+            headers(("Retry-After" = u64, description = "Seconds until the next upstream call is allowed")))
+    )
+)]
+pub async fn get_exchange_rate(
+    db: web::Data<DbClient>,
+    crypto_service: web::Data<CryptoService>,
+    rate_limiter: web::Data<RateLimiter>,
+    background_tasks: web::Data<BackgroundTasks>,
+    code: web::Path<String>,
+) -> Result<HttpResponse> {
+    let Some(mut rates) = current_exchange_rates(&db, &crypto_service, &rate_limiter, &background_tasks).await else {
+        return Ok(exchange_rates_unavailable(&rate_limiter).await);
+    };
+    match rates.rates.remove(&code.to_lowercase()) {
+        Some(rate) => Ok(HttpResponse::Ok().json(rate)),
+        None => Ok(HttpResponse::NotFound().json(ErrorResponse::new(format!("Unknown currency code '{}'", code)))),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/stats",
+    tag = "stats",
+    params(
+        ("exclude_stablecoins" = Option<bool>, Query, description = "Leave pegged assets out of every figure")
+    ),
+    responses(
+        (status = 200, description = "Aggregate market statistics over the cached tokens", body = TokenStats,
+            headers(("ETag" = String, description = "Weak validator for If-None-Match"))),
+        (status = 304, description = "If-None-Match matched the current stats")
+    )
+)]
+pub async fn get_stats(
+    req: HttpRequest,
+    config: web::Data<Config>,
+    db: web::Data<DbClient>,
+    token_cache: web::Data<TokenCache>,
+    query: web::Query<StatsQuery>,
+) -> Result<HttpResponse> {
+    let collection = db.get_tokens_collection();
+    let filter = TokenFilter::listed(query.exclude_stablecoins);
+    let tokens = load_filtered_tokens(&collection, &token_cache, &filter).await;
+
+    let stats = TokenStats::from_tokens(&tokens);
+    if tokens.is_empty() {
+        return Ok(etag::respond(&req, HttpResponse::Ok(), &stats));
+    }
+
+    let freshness = Freshness::of(&tokens, config.token_cache_ttl_secs);
+    Ok(envelope::attach(etag::respond(&req, HttpResponse::Ok(), &stats), freshness))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/overview",
+    tag = "stats",
+    params(
+        ("exclude_stablecoins" = Option<bool>, Query, description = "Leave pegged assets out of every panel")
+    ),
+    responses(
+        (status = 200, description = "/api/stats, the top 5 gainers and losers by 24h change and the market summary, all from the same snapshot of the cached tokens", body = Overview,
+            headers(("ETag" = String, description = "Weak validator for If-None-Match"))),
+        (status = 304, description = "If-None-Match matched the current overview")
+    )
+)]
+pub async fn get_overview(
+    req: HttpRequest,
+    config: web::Data<Config>,
+    db: web::Data<DbClient>,
+    token_cache: web::Data<TokenCache>,
+    query: web::Query<StatsQuery>,
+) -> Result<HttpResponse> {
+    // One read, so no panel can be a refresh ahead of another
+    let filter = TokenFilter::listed(query.exclude_stablecoins);
+    let tokens = load_filtered_tokens(&db.get_tokens_collection(), &token_cache, &filter).await;
+
+    let stats = TokenStats::from_tokens(&tokens);
+    let market = MarketStats::from_stats(&stats, &tokens);
+    let (top_gainers, top_losers) = report::top_movers(&tokens);
+    let overview = Overview { stats, top_gainers, top_losers, market };
+    if tokens.is_empty() {
+        return Ok(etag::respond(&req, HttpResponse::Ok(), &overview));
+    }
+
+    let freshness = Freshness::of(&tokens, config.token_cache_ttl_secs);
+    Ok(envelope::attach(etag::respond(&req, HttpResponse::Ok(), &overview), freshness))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/gainers",
+    tag = "stats",
+    params(
+        ("limit" = Option<u64>, Query, minimum = 1, maximum = 100, description = "How many tokens to return, defaults to 10"),
+        ("exclude_stablecoins" = Option<bool>, Query, description = "Leave pegged assets out of the ranking"),
+        ("window" = Option<String>, Query, description = "24h, 7d or 30d, defaults to 24h")
+    ),
+    responses(
+        (status = 200, description = "Biggest gainers over the window first; tokens with no change for it are left out", body = [TokenChange]),
+        (status = 400, description = "limit out of range or unknown window", body = ErrorResponse),
+        (status = 422, description = "No token has a change stored for the window yet", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn get_gainers(db: web::Data<DbClient>, query: web::Query<MoversQuery>) -> Result<HttpResponse> {
+    top_movers(&db, &query, Movers::Gainers).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/losers",
+    tag = "stats",
+    params(
+        ("limit" = Option<u64>, Query, minimum = 1, maximum = 100, description = "How many tokens to return, defaults to 10"),
+        ("exclude_stablecoins" = Option<bool>, Query, description = "Leave pegged assets out of the ranking"),
+        ("window" = Option<String>, Query, description = "24h, 7d or 30d, defaults to 24h")
+    ),
+    responses(
+        (status = 200, description = "Biggest losers over the window first; tokens with no change for it are left out", body = [TokenChange]),
+        (status = 400, description = "limit out of range or unknown window", body = ErrorResponse),
+        (status = 422, description = "No token has a change stored for the window yet", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn get_losers(db: web::Data<DbClient>, query: web::Query<MoversQuery>) -> Result<HttpResponse> {
+    top_movers(&db, &query, Movers::Losers).await
+}
+
+async fn top_movers(db: &DbClient, query: &MoversQuery, movers: Movers) -> Result<HttpResponse> {
+    let limit = query.limit.unwrap_or(DEFAULT_MOVERS_LIMIT);
+    if !(1..=MAX_MOVERS_LIMIT).contains(&limit) {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(format!(
+            "limit must be between 1 and {}",
+            MAX_MOVERS_LIMIT
+        ))));
+    }
+    let window = match query.window.as_deref().map(str::parse::<ChangeWindow>).transpose() {
+        Ok(window) => window.unwrap_or_default(),
+        Err(message) => return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message))),
+    };
+
+    let filter = TokenFilter::listed(query.exclude_stablecoins);
+    let changes = match db.top_movers(movers, window, limit as i64, filter.to_document()).await {
+        Ok(changes) => changes,
+        Err(e) => {
+            tracing::error!(?movers, window = window.as_str(), error = %e, "Failed to rank tokens by change");
+            return Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")));
+        }
+    };
+
+    // An empty ranking for a longer window usually means no token has the figure yet;
+    // say so instead of implying nothing moved. The 24h change is always stored.
+    if changes.is_empty() && window != ChangeWindow::Day {
+        match db.has_change_data(window).await {
+            Ok(false) => {
+                return Ok(HttpResponse::UnprocessableEntity().json(ErrorResponse::new(format!(
+                    "No {} change is stored for any token yet; it arrives with the next token refresh",
+                    window.as_str()
+                ))))
+            }
+            Ok(true) => {}
+            Err(e) => tracing::warn!(window = window.as_str(), error = %e, "Failed to check for change data"),
+        }
+    }
+    Ok(HttpResponse::Ok().json(changes))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/screener",
+    tag = "stats",
+    params(
+        ("min_volume_mcap_ratio" = Option<f64>, Query, description = "Lowest 24h volume / market cap, e.g. 0.2"),
+        ("max_volume_mcap_ratio" = Option<f64>, Query, description = "Highest 24h volume / market cap"),
+        ("min_market_cap" = Option<f64>, Query, description = "Lowest market cap in USD"),
+        ("max_market_cap" = Option<f64>, Query, description = "Highest market cap in USD"),
+        ("min_change_24h" = Option<f64>, Query, description = "Lowest 24h price change, in percent"),
+        ("max_change_24h" = Option<f64>, Query, description = "Highest 24h price change, in percent"),
+        ("sort" = Option<String>, Query,
+            description = "volume_mcap_ratio (default), volume_24h, market_cap or price_change_percentage_24h; highest first"),
+        ("limit" = Option<u64>, Query, minimum = 1, maximum = 250, description = "How many tokens to return, defaults to 50"),
+        ("exclude_stablecoins" = Option<bool>, Query, description = "Leave pegged assets out")
+    ),
+    responses(
+        (status = 200, description = "Cached tokens within every bound given, each with its volume/market-cap ratio; null for a zero market cap, and such tokens never pass a ratio bound",
+            body = [ScreenedToken]),
+        (status = 400, description = "A min above its max, a non-numeric bound, unknown sort or limit out of range", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn get_screener(db: web::Data<DbClient>, query: web::Query<ScreenerQuery>) -> Result<HttpResponse> {
+    let screener = match query.validated() {
+        Ok(screener) => screener,
+        Err(message) => return Ok(HttpResponse::BadRequest().json(ErrorResponse::new(message))),
+    };
+
+    let filter = TokenFilter::listed(query.exclude_stablecoins);
+    match db.screen_tokens(&screener, filter.to_document()).await {
+        Ok(tokens) => Ok(HttpResponse::Ok().json(tokens)),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to screen tokens");
+            Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")))
+        }
+    }
+}
+
+// Percent below the all-time high /api/near_ath reaches by default
+const DEFAULT_NEAR_ATH_THRESHOLD: f64 = 5.0;
+
+#[utoipa::path(
+    get,
+    path = "/api/near_ath",
+    tag = "stats",
+    params(
+        ("threshold" = Option<f64>, Query, description = "How far below its all-time high, in percent, a token may trade; defaults to 5")
+    ),
+    responses(
+        (status = 200, description = "Cached tokens trading within threshold of their all-time high, closest first; tokens with no ATH change stored are left out", body = [CryptoToken]),
+        (status = 400, description = "threshold is not a positive number", body = ErrorResponse)
+    )
+)]
+pub async fn get_near_ath(
+    config: web::Data<Config>,
+    db: web::Data<DbClient>,
+    token_cache: web::Data<TokenCache>,
+    query: web::Query<NearAthQuery>,
+) -> Result<HttpResponse> {
+    let threshold = query.threshold.unwrap_or(DEFAULT_NEAR_ATH_THRESHOLD);
+    if !threshold.is_finite() || threshold <= 0.0 {
+        return Ok(HttpResponse::BadRequest().json(ErrorResponse::new("threshold must be a positive number")));
+    }
+
+    let collection = db.get_tokens_collection();
+    let tokens = load_filtered_tokens(&collection, &token_cache, &TokenFilter::listed(None)).await;
+    let freshness = Freshness::of(&tokens, config.token_cache_ttl_secs);
+
+    let mut near: Vec<(f64, CryptoToken)> = tokens
+        .iter()
+        .filter_map(|token| {
+            // Slightly positive when the price moved past an ATH that hasn't been refreshed yet
+            let change = token.ath_change_percentage.filter(|c| c.is_finite())?;
+            (change >= -threshold).then(|| (change.abs(), token.clone()))
+        })
+        .collect();
+    near.sort_by(|(a, x), (b, y)| cmp_f64(*a, *b).then_with(|| x.token_id.cmp(&y.token_id)));
+
+    let near: Vec<CryptoToken> = near
+        .into_iter()
+        .map(|(_, mut token)| {
+            token.sparkline_7d = None;
+            token
+        })
+        .collect();
+    Ok(envelope::attach(HttpResponse::Ok().json(near), freshness))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/debug/cache",
+    tag = "debug",
+    responses(
+        (status = 200, description = "Cache contents and rate limiter state", body = CacheDebugInfo),
+        (status = 404, description = "DEBUG_ENDPOINTS is not enabled", body = ErrorResponse)
+    )
+)]
+pub async fn debug_cache(
+    config: web::Data<Config>,
+    db: web::Data<DbClient>,
+    rate_limiter: web::Data<RateLimiter>,
+) -> Result<HttpResponse> {
+    // Indistinguishable from an unknown route unless explicitly enabled
+    if !config.debug_endpoints {
+        return Ok(HttpResponse::NotFound().json(ErrorResponse::new("Not found")));
+    }
+
+    let tokens = get_cached_tokens(&db.get_tokens_collection(), None).await;
+
+    let info = CacheDebugInfo {
+        cached_tokens: tokens.len(),
+        oldest_last_updated: tokens.iter().map(|t| t.last_updated).min(),
+        newest_last_updated: tokens.iter().map(|t| t.last_updated).max(),
+        rate_limited_until: rate_limiter.rate_limited_until().await,
+        seconds_until_next_call: rate_limiter.seconds_until_next_call().await,
+    };
+
+    Ok(HttpResponse::Ok().json(info))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/cache/status",
+    tag = "stats",
+    responses(
+        (status = 200, description = "Per-collection counts and timestamp ranges, cached charts and rate-limit state", body = CacheStatus),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+pub async fn get_cache_status(db: web::Data<DbClient>, rate_limiter: web::Data<RateLimiter>) -> Result<HttpResponse> {
+    // Aggregations only; no documents are loaded
+    let (tokens, price_history) = futures::join!(db.token_cache_summary(), db.history_cache_summary());
+    let (tokens, price_history) = match (tokens, price_history) {
+        (Ok(tokens), Ok(price_history)) => (tokens, price_history),
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::error!(error = %e, "Failed to summarize the cache");
+            return Ok(HttpResponse::InternalServerError().json(ErrorResponse::new("Database error")));
+        }
+    };
+
+    let until = rate_limiter.rate_limited_until().await.filter(|until| *until > Utc::now());
+    Ok(HttpResponse::Ok().json(CacheStatus {
+        tokens,
+        price_history,
+        rate_limit: RateLimitStatus {
+            backing_off: until.is_some(),
+            until,
+            seconds_until_next_call: rate_limiter.seconds_until_next_call().await,
+        },
+    }))
+}
+
+// How long the readiness probe waits on MongoDB before calling it unreachable
+const READINESS_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+// Liveness: the process is up and answering. Deliberately checks nothing else, so a
+// database outage doesn't get the pod restarted.
+pub async fn health_live() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "status": "alive" }))
+}
+
+// Readiness: MongoDB answers and the first token fetch has landed
+pub async fn health_ready(db: web::Data<DbClient>) -> HttpResponse {
+    let checks = async {
+        db.ping().await?;
+        db.has_cached_tokens().await
+    };
+    let (mongodb, tokens_cached) = match tokio::time::timeout(READINESS_TIMEOUT, checks).await {
+        Ok(Ok(tokens_cached)) => (true, tokens_cached),
+        Ok(Err(e)) => {
+            tracing::warn!(error = %e, "Readiness check failed");
+            (false, false)
+        }
+        Err(_) => {
+            tracing::warn!("Readiness check timed out");
+            (false, false)
+        }
+    };
+
+    let readiness = Readiness { ready: mongodb && tokens_cached, mongodb, tokens_cached };
+    if readiness.ready {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
+
+// Prometheus text exposition, for scraping rather than for API clients
+pub async fn metrics(upstream: web::Data<UpstreamGate>, backfill: web::Data<BackfillStatus>) -> HttpResponse {
+    let body = format!(
+        "# HELP upstream_permits_in_use CoinGecko calls currently in flight from request handlers\n\
+         # TYPE upstream_permits_in_use gauge\n\
+         upstream_permits_in_use {}\n\
+         # HELP upstream_permits_max Concurrent CoinGecko calls allowed (MAX_CONCURRENT_UPSTREAM)\n\
+         # TYPE upstream_permits_max gauge\n\
+         upstream_permits_max {}\n\
+         # HELP history_backfill_pending Favorite charts stale in the cache and waiting for the backfill task\n\
+         # TYPE history_backfill_pending gauge\n\
+         history_backfill_pending {}\n",
+        upstream.in_use(),
+        upstream.max_permits(),
+        backfill.pending()
+    );
+    HttpResponse::Ok().content_type("text/plain; version=0.0.4").body(body)
 }
@@ -0,0 +1,103 @@
+use actix_web::web::Bytes;
+use reqwest::Client;
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use crate::crypto_service::DEFAULT_USER_AGENT;
+
+// A logo fetch taking longer than this is abandoned and the client redirected instead
+pub const FETCH_TIMEOUT: Duration = Duration::from_secs(2);
+
+// How long a fetched logo is served from memory, and what Cache-Control tells browsers
+pub const IMAGE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+// Logos kept in memory; past this the oldest fetch is dropped
+const MAX_IMAGES: usize = 500;
+
+// Bigger bodies aren't logos and aren't kept
+const MAX_IMAGE_BYTES: usize = 1024 * 1024;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    pub content_type: String,
+    pub bytes: Bytes,
+}
+
+struct CachedImage {
+    image: Image,
+    fetched_at: Instant,
+}
+
+// Fetches token logos server-side and keeps the bytes in memory, keyed by URL, so
+// clients never have to reach CoinGecko's CDN themselves
+pub struct ImageProxy {
+    client: Client,
+    ttl: Duration,
+    images: Mutex<HashMap<String, CachedImage>>,
+}
+
+impl Default for ImageProxy {
+    fn default() -> Self {
+        Self::new(FETCH_TIMEOUT, IMAGE_TTL)
+    }
+}
+
+impl ImageProxy {
+    pub fn new(timeout: Duration, ttl: Duration) -> Self {
+        let client = Client::builder()
+            .user_agent(DEFAULT_USER_AGENT)
+            .timeout(timeout)
+            .build()
+            .unwrap_or_else(|_| Client::new());
+        Self { client, ttl, images: Mutex::new(HashMap::new()) }
+    }
+
+    fn images(&self) -> std::sync::MutexGuard<'_, HashMap<String, CachedImage>> {
+        self.images.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // The logo at `url`, from memory while it's younger than the TTL, otherwise fetched.
+    // Anything but an image body under the size cap is an error.
+    pub async fn get(&self, url: &str) -> Result<Image, String> {
+        if let Some(cached) = self.images().get(url) {
+            if cached.fetched_at.elapsed() < self.ttl {
+                return Ok(cached.image.clone());
+            }
+        }
+
+        let image = self.fetch(url).await?;
+        let mut images = self.images();
+        if images.len() >= MAX_IMAGES && !images.contains_key(url) {
+            let oldest = images.iter().min_by_key(|(_, cached)| cached.fetched_at).map(|(url, _)| url.clone());
+            if let Some(oldest) = oldest {
+                images.remove(&oldest);
+            }
+        }
+        images.insert(url.to_string(), CachedImage { image: image.clone(), fetched_at: Instant::now() });
+        Ok(image)
+    }
+
+    async fn fetch(&self, url: &str) -> Result<Image, String> {
+        let response = self.client.get(url).send().await.map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("status {}", response.status()));
+        }
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        if !content_type.starts_with("image/") {
+            return Err(format!("content type {:?} is not an image", content_type));
+        }
+        if response.content_length().is_some_and(|length| length as usize > MAX_IMAGE_BYTES) {
+            return Err("image too large".to_string());
+        }
+        let bytes = response.bytes().await.map_err(|e| e.to_string())?;
+        if bytes.len() > MAX_IMAGE_BYTES {
+            return Err("image too large".to_string());
+        }
+        Ok(Image { content_type, bytes })
+    }
+}
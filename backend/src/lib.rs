@@ -1,5 +1,43 @@
 // Library exports for testing
+pub mod alerts;
+pub mod analytics;
+pub mod auth;
+pub mod backfill;
+pub mod binance;
+pub mod cache_store;
+pub mod compression;
+pub mod config;
 pub mod models;
 pub mod db;
+pub mod envelope;
+pub mod etag;
+pub mod events;
+pub mod fallback;
+pub mod fields;
+pub mod image_proxy;
+pub mod force_fresh;
 pub mod crypto_service;
+pub mod currency;
 pub mod handlers;
+pub mod listing;
+pub mod maintenance;
+pub mod ndjson;
+pub mod openapi;
+pub mod ordering;
+pub mod portfolio;
+pub mod report;
+pub mod routes;
+pub mod rate_limiter;
+#[cfg(feature = "redis")]
+pub mod redis_store;
+pub mod search;
+pub mod shutdown;
+pub mod single_flight;
+pub mod stablecoins;
+pub mod telemetry;
+pub mod timeout;
+pub mod token_cache;
+pub mod upstream;
+pub mod v2;
+pub mod warmup;
+pub mod webhook;
@@ -0,0 +1,809 @@
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use mongodb::options::FindOptions;
+use serde::{Deserialize, Serialize};
+use crate::models::{is_valid_category_id, CryptoToken, ListQuery, TokensQuery};
+use crate::stablecoins;
+
+pub const DEFAULT_PER_PAGE: u64 = 20;
+pub const MAX_PER_PAGE: u64 = 250;
+
+// How long a next_cursor stays valid; older ones get a 400 and the client starts over
+pub const CURSOR_TTL_SECS: i64 = 3600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortField {
+    MarketCap,
+    CurrentPrice,
+    Volume24h,
+    PriceChangePercentage24h,
+    PriceChangePercentage7d,
+    PriceChangePercentage30d,
+    PriceChangePercentage1y,
+    Name,
+    Symbol,
+}
+
+impl SortField {
+    const ALL: [SortField; 9] = [
+        SortField::MarketCap,
+        SortField::CurrentPrice,
+        SortField::Volume24h,
+        SortField::PriceChangePercentage24h,
+        SortField::PriceChangePercentage7d,
+        SortField::PriceChangePercentage30d,
+        SortField::PriceChangePercentage1y,
+        SortField::Name,
+        SortField::Symbol,
+    ];
+
+    // Also the MongoDB field name
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SortField::MarketCap => "market_cap",
+            SortField::CurrentPrice => "current_price",
+            SortField::Volume24h => "volume_24h",
+            SortField::PriceChangePercentage24h => "price_change_percentage_24h",
+            SortField::PriceChangePercentage7d => "price_change_percentage_7d",
+            SortField::PriceChangePercentage30d => "price_change_percentage_30d",
+            SortField::PriceChangePercentage1y => "price_change_percentage_1y",
+            SortField::Name => "name",
+            SortField::Symbol => "symbol",
+        }
+    }
+
+    // Biggest numbers first, names alphabetically
+    fn default_order(self) -> SortOrder {
+        match self {
+            SortField::Name | SortField::Symbol => SortOrder::Asc,
+            _ => SortOrder::Desc,
+        }
+    }
+
+    fn compare(self, a: &CryptoToken, b: &CryptoToken) -> Ordering {
+        let by_number = crate::ordering::cmp_f64;
+        match self {
+            SortField::MarketCap => by_number(a.market_cap, b.market_cap),
+            SortField::CurrentPrice => by_number(a.current_price, b.current_price),
+            SortField::Volume24h => by_number(a.volume_24h, b.volume_24h),
+            SortField::PriceChangePercentage24h => {
+                by_number(a.price_change_percentage_24h, b.price_change_percentage_24h)
+            }
+            SortField::PriceChangePercentage7d
+            | SortField::PriceChangePercentage30d
+            | SortField::PriceChangePercentage1y => by_number(
+                self.optional_value(a).unwrap_or(f64::NAN),
+                self.optional_value(b).unwrap_or(f64::NAN),
+            ),
+            SortField::Name => a.name.cmp(&b.name),
+            SortField::Symbol => a.symbol.cmp(&b.symbol),
+        }
+    }
+
+    fn is_text(self) -> bool {
+        matches!(self, SortField::Name | SortField::Symbol)
+    }
+
+    // The longer change windows, which CoinGecko has no figure for on young coins
+    fn optional_value(self, token: &CryptoToken) -> Option<f64> {
+        match self {
+            SortField::PriceChangePercentage7d => token.price_change_percentage_7d,
+            SortField::PriceChangePercentage30d => token.price_change_percentage_30d,
+            SortField::PriceChangePercentage1y => token.price_change_percentage_1y,
+            _ => None,
+        }
+    }
+
+    fn is_optional(self) -> bool {
+        matches!(
+            self,
+            SortField::PriceChangePercentage7d | SortField::PriceChangePercentage30d | SortField::PriceChangePercentage1y
+        )
+    }
+
+    // Whether the token can be ranked by this field. Tokens without a figure for an
+    // optional one are left out of lists sorted by it rather than ranked as zero.
+    fn ranks(self, token: &CryptoToken) -> bool {
+        !self.is_optional() || self.optional_value(token).is_some_and(f64::is_finite)
+    }
+
+    // MongoDB clause matching the same tokens as `ranks`
+    fn ranked_filter(self) -> Option<Document> {
+        self.is_optional().then(|| {
+            let mut clause = Document::new();
+            clause.insert(self.as_str(), doc! { "$type": "number", "$ne": f64::NAN });
+            clause
+        })
+    }
+
+    // The token's value for this field, as stored in MongoDB
+    fn key(self, token: &CryptoToken) -> Bson {
+        match self {
+            SortField::MarketCap => Bson::Double(token.market_cap),
+            SortField::CurrentPrice => Bson::Double(token.current_price),
+            SortField::Volume24h => Bson::Double(token.volume_24h),
+            SortField::PriceChangePercentage24h => Bson::Double(token.price_change_percentage_24h),
+            SortField::PriceChangePercentage7d
+            | SortField::PriceChangePercentage30d
+            | SortField::PriceChangePercentage1y => Bson::Double(self.optional_value(token).unwrap_or_default()),
+            SortField::Name => Bson::String(token.name.clone()),
+            SortField::Symbol => Bson::String(token.symbol.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SortOrder::Asc => "asc",
+            SortOrder::Desc => "desc",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Page {
+    pub page: u64,
+    pub per_page: u64,
+}
+
+impl Page {
+    pub fn skip(&self) -> u64 {
+        (self.page - 1).saturating_mul(self.per_page)
+    }
+}
+
+// Validated sort and pagination for the token list endpoints. Both are optional:
+// without them a list comes back whole and in its natural order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ListParams {
+    pub sort: Option<(SortField, SortOrder)>,
+    pub page: Option<Page>,
+}
+
+impl ListParams {
+    pub fn from_query(query: &ListQuery) -> Result<Self, String> {
+        let field = match query.sort_by.as_deref() {
+            Some(name) => Some(
+                SortField::ALL
+                    .into_iter()
+                    .find(|f| f.as_str() == name)
+                    .ok_or_else(|| {
+                        let names: Vec<&str> = SortField::ALL.iter().map(|f| f.as_str()).collect();
+                        format!("sort_by must be one of: {}", names.join(", "))
+                    })?,
+            ),
+            None => None,
+        };
+
+        let order = match query.order.as_deref() {
+            Some("asc") => Some(SortOrder::Asc),
+            Some("desc") => Some(SortOrder::Desc),
+            Some(_) => return Err("order must be asc or desc".to_string()),
+            None => None,
+        };
+
+        // An order on its own applies to the default market cap sort
+        let sort = match (field, order) {
+            (Some(field), order) => Some((field, order.unwrap_or_else(|| field.default_order()))),
+            (None, Some(order)) => Some((SortField::MarketCap, order)),
+            (None, None) => None,
+        };
+
+        let page = if query.page.is_some() || query.per_page.is_some() {
+            let page = query.page.unwrap_or(1);
+            let per_page = query.per_page.unwrap_or(DEFAULT_PER_PAGE);
+            if page == 0 {
+                return Err("page must be at least 1".to_string());
+            }
+            if !(1..=MAX_PER_PAGE).contains(&per_page) {
+                return Err(format!("per_page must be between 1 and {}", MAX_PER_PAGE));
+            }
+            Some(Page { page, per_page })
+        } else {
+            None
+        };
+
+        Ok(Self { sort, page })
+    }
+
+    // `base` narrowed to the tokens the sort can rank, for use with `find_options`
+    pub fn filter(&self, base: Document) -> Document {
+        match self.sort.and_then(|(field, _)| field.ranked_filter()) {
+            Some(clause) => doc! { "$and": [base, clause] },
+            None => base,
+        }
+    }
+
+    // Sort, skip and limit for a MongoDB find. Ties break on token_id so pages never overlap.
+    pub fn find_options(&self) -> FindOptions {
+        let mut options = FindOptions::default();
+        if let Some((field, order)) = self.sort {
+            let direction = if order == SortOrder::Asc { 1 } else { -1 };
+            let mut sort = Document::new();
+            sort.insert(field.as_str(), direction);
+            sort.insert("token_id", 1);
+            options.sort = Some(sort);
+        } else if self.page.is_some() {
+            options.sort = Some(doc! { "token_id": 1 });
+        }
+        if let Some(page) = self.page {
+            options.skip = Some(page.skip());
+            options.limit = Some(page.per_page as i64);
+        }
+        options
+    }
+
+    // Same as `find_options`, for lists that are already in memory. Unsorted pages
+    // keep the list's own order, which is already stable.
+    pub fn apply(&self, tokens: &[CryptoToken]) -> Vec<CryptoToken> {
+        let mut sorted: Vec<&CryptoToken> = match self.sort {
+            Some((field, _)) => tokens.iter().filter(|t| field.ranks(t)).collect(),
+            None => tokens.iter().collect(),
+        };
+        if let Some((field, order)) = self.sort {
+            sorted.sort_by(|a, b| {
+                let ordering = field.compare(a, b);
+                let ordering = if order == SortOrder::Desc { ordering.reverse() } else { ordering };
+                ordering.then_with(|| a.token_id.cmp(&b.token_id))
+            });
+        }
+
+        let (skip, take) = match self.page {
+            Some(page) => (page.skip() as usize, page.per_page as usize),
+            None => (0, usize::MAX),
+        };
+        sorted.into_iter().skip(skip).take(take).cloned().collect()
+    }
+}
+
+// What a cursor carries, before base64. Short keys keep the token short.
+#[derive(Serialize, Deserialize)]
+struct CursorKey {
+    s: String,
+    o: String,
+    v: serde_json::Value,
+    id: String,
+    t: i64,
+}
+
+// Where a cursor page left off: the last token's sort key and _id, for the sort it was
+// issued under
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    field: SortField,
+    order: SortOrder,
+    value: Bson,
+    id: ObjectId,
+    issued_at: DateTime<Utc>,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        let value = match &self.value {
+            Bson::String(text) => serde_json::Value::from(text.as_str()),
+            Bson::Double(number) => serde_json::Value::from(*number),
+            _ => serde_json::Value::Null,
+        };
+        let key = CursorKey {
+            s: self.field.as_str().to_string(),
+            o: self.order.as_str().to_string(),
+            v: value,
+            id: self.id.to_hex(),
+            t: self.issued_at.timestamp(),
+        };
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(&key).expect("cursor keys always serialize"))
+    }
+
+    // Refuses cursors that don't parse, were issued for another sort, or are past CURSOR_TTL_SECS
+    pub fn decode(raw: &str, field: SortField, order: SortOrder, now: DateTime<Utc>) -> Result<Self, String> {
+        let invalid = || "cursor is invalid; pass an empty cursor to start from the first page".to_string();
+        let bytes = URL_SAFE_NO_PAD.decode(raw).map_err(|_| invalid())?;
+        let key: CursorKey = serde_json::from_slice(&bytes).map_err(|_| invalid())?;
+
+        if key.s != field.as_str() || key.o != order.as_str() {
+            return Err(format!(
+                "cursor was issued for sort_by={}&order={}; repeat those or start over with an empty cursor",
+                key.s, key.o
+            ));
+        }
+        let issued_at = DateTime::from_timestamp(key.t, 0).ok_or_else(invalid)?;
+        if (now - issued_at).num_seconds() > CURSOR_TTL_SECS {
+            return Err("cursor has expired; pass an empty cursor to start from the first page".to_string());
+        }
+        let value = match key.v {
+            serde_json::Value::String(text) if field.is_text() => Bson::String(text),
+            serde_json::Value::Number(number) if !field.is_text() => Bson::Double(number.as_f64().ok_or_else(invalid)?),
+            _ => return Err(invalid()),
+        };
+        let id = ObjectId::parse_str(&key.id).map_err(|_| invalid())?;
+
+        Ok(Self { field, order, value, id, issued_at })
+    }
+}
+
+// Keyset pagination for /api/tokens?cursor=. Each page is a range query past the
+// previous page's last (sort key, _id), so upserts between requests can't shift
+// tokens across page boundaries the way they do with skip.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CursorPage {
+    pub field: SortField,
+    pub order: SortOrder,
+    pub per_page: u64,
+    pub after: Option<Cursor>,
+}
+
+impl CursorPage {
+    // `cursor` is the raw query value; empty starts from the first page
+    pub fn from_query(query: &ListQuery, params: &ListParams, cursor: &str) -> Result<Self, String> {
+        if query.page.is_some() {
+            return Err("page can't be combined with cursor; follow next_cursor instead".to_string());
+        }
+        let (field, order) = params.sort.unwrap_or((SortField::MarketCap, SortOrder::Desc));
+        let after = if cursor.is_empty() {
+            None
+        } else {
+            Some(Cursor::decode(cursor, field, order, Utc::now())?)
+        };
+        Ok(Self {
+            field,
+            order,
+            per_page: params.page.map_or(DEFAULT_PER_PAGE, |p| p.per_page),
+            after,
+        })
+    }
+
+    // `base` narrowed to the tokens the sort can rank that come after the cursor
+    pub fn filter(&self, base: Document) -> Document {
+        let base = match self.field.ranked_filter() {
+            Some(clause) => doc! { "$and": [base, clause] },
+            None => base,
+        };
+        let Some(after) = &self.after else {
+            return base;
+        };
+        let past = if self.order == SortOrder::Asc { "$gt" } else { "$lt" };
+        let field = self.field.as_str();
+        let mut beyond_key = Document::new();
+        beyond_key.insert(field, doc! { past: after.value.clone() });
+        let mut same_key = Document::new();
+        same_key.insert(field, after.value.clone());
+        same_key.insert("_id", doc! { past: after.id });
+        doc! { "$and": [base, { "$or": [beyond_key, same_key] }] }
+    }
+
+    // Sorted on the field with _id breaking ties. One extra document is fetched to
+    // tell whether another page follows.
+    pub fn find_options(&self) -> FindOptions {
+        let direction = if self.order == SortOrder::Asc { 1 } else { -1 };
+        let mut sort = Document::new();
+        sort.insert(self.field.as_str(), direction);
+        sort.insert("_id", direction);
+        let mut options = FindOptions::default();
+        options.sort = Some(sort);
+        options.limit = Some(self.per_page as i64 + 1);
+        options
+    }
+
+    // Trims what `find_options` fetched down to the page and returns the cursor for
+    // the next one, if there is one
+    pub fn finish(&self, tokens: &mut Vec<CryptoToken>) -> Option<String> {
+        if tokens.len() as u64 <= self.per_page {
+            return None;
+        }
+        tokens.truncate(self.per_page as usize);
+        let last = tokens.last()?;
+        Some(self.cursor_after(self.field.key(last), last.id?))
+    }
+
+    // Fields a page read through a projection has to keep for `finish_documents`
+    pub fn key_fields(&self) -> [&'static str; 2] {
+        ["_id", self.field.as_str()]
+    }
+
+    // `finish` for documents read through a projection
+    pub fn finish_documents(&self, documents: &mut Vec<Document>) -> Option<String> {
+        if documents.len() as u64 <= self.per_page {
+            return None;
+        }
+        documents.truncate(self.per_page as usize);
+        let last = documents.last()?;
+        Some(self.cursor_after(last.get(self.field.as_str())?.clone(), last.get_object_id("_id").ok()?))
+    }
+
+    fn cursor_after(&self, value: Bson, id: ObjectId) -> String {
+        Cursor { field: self.field, order: self.order, value, id, issued_at: Utc::now() }.encode()
+    }
+}
+
+// Sign of the 24h change a list is narrowed to. Tokens with no change at all are
+// in neither direction.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Direction {
+    #[default]
+    All,
+    Up,
+    Down,
+}
+
+impl Direction {
+    fn matches(self, change: f64) -> bool {
+        match self {
+            Direction::All => true,
+            Direction::Up => change > 0.0,
+            Direction::Down => change < 0.0,
+        }
+    }
+}
+
+impl std::str::FromStr for Direction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(Direction::All),
+            "up" => Ok(Direction::Up),
+            "down" => Ok(Direction::Down),
+            _ => Err("direction must be up, down or all".to_string()),
+        }
+    }
+}
+
+// Which tokens a list covers, on top of sorting and paging
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TokenFilter {
+    pub category: Option<String>,
+    pub exclude_stablecoins: bool,
+    pub exclude_hidden: bool,
+    // Only these tokens, e.g. the ones annotated with a `tag`; resolved by the caller
+    pub token_ids: Option<BTreeSet<String>>,
+    pub direction: Direction,
+}
+
+impl TokenFilter {
+    pub fn from_query(query: &TokensQuery) -> Result<Self, String> {
+        if query.category.as_deref().is_some_and(|c| !is_valid_category_id(c)) {
+            return Err("category must be a CoinGecko category id such as 'decentralized-finance-defi', 'layer-1' \
+                 or 'meme-token' (not the display name); see /api/categories"
+                .to_string());
+        }
+        let direction = query.direction.as_deref().map(str::parse).transpose()?.unwrap_or_default();
+        Ok(Self {
+            category: query.category.clone(),
+            exclude_stablecoins: query.exclude_stablecoins.unwrap_or(false),
+            exclude_hidden: !query.include_hidden.unwrap_or(false),
+            token_ids: None,
+            direction,
+        })
+    }
+
+    // What stats and movers cover: every token not hidden, optionally without stablecoins
+    pub fn listed(exclude_stablecoins: Option<bool>) -> Self {
+        Self {
+            category: None,
+            exclude_stablecoins: exclude_stablecoins.unwrap_or(false),
+            exclude_hidden: true,
+            token_ids: None,
+            direction: Direction::All,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.category.is_none()
+            && !self.exclude_stablecoins
+            && !self.exclude_hidden
+            && self.token_ids.is_none()
+            && self.direction == Direction::All
+    }
+
+    // Nothing filtered but hidden tokens, which the memory cache can drop itself
+    pub fn hides_only(&self) -> bool {
+        self.category.is_none()
+            && !self.exclude_stablecoins
+            && self.exclude_hidden
+            && self.token_ids.is_none()
+            && self.direction == Direction::All
+    }
+
+    // MongoDB filter for the stored tokens; empty when nothing is filtered
+    pub fn to_document(&self) -> Document {
+        let mut clauses = Vec::new();
+        if let Some(category) = &self.category {
+            clauses.push(doc! { "category": category });
+        }
+        if self.exclude_stablecoins {
+            clauses.push(stablecoins::exclusion_filter());
+        }
+        if self.exclude_hidden {
+            // Documents from before the flag existed have no `hidden` at all
+            clauses.push(doc! { "hidden": { "$ne": true } });
+        }
+        if let Some(token_ids) = &self.token_ids {
+            clauses.push(doc! { "token_id": { "$in": token_ids.iter().collect::<Vec<_>>() } });
+        }
+        match self.direction {
+            Direction::All => {}
+            Direction::Up => clauses.push(doc! { "price_change_percentage_24h": { "$gt": 0.0 } }),
+            Direction::Down => clauses.push(doc! { "price_change_percentage_24h": { "$lt": 0.0 } }),
+        }
+        if clauses.is_empty() {
+            Document::new()
+        } else {
+            doc! { "$and": clauses }
+        }
+    }
+
+    // Same test on a token in hand, for lists fresh from upstream
+    pub fn matches(&self, token: &CryptoToken) -> bool {
+        self.category.as_ref().is_none_or(|c| token.category.as_ref() == Some(c))
+            && !(self.exclude_stablecoins && stablecoins::is_stablecoin(token))
+            && !(self.exclude_hidden && token.hidden)
+            && self.token_ids.as_ref().is_none_or(|ids| ids.contains(&token.token_id))
+            && self.direction.matches(token.price_change_percentage_24h)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::test_token;
+    use chrono::Utc;
+
+    fn query(sort_by: Option<&str>, order: Option<&str>, page: Option<u64>, per_page: Option<u64>) -> ListQuery {
+        ListQuery {
+            sort_by: sort_by.map(String::from),
+            order: order.map(String::from),
+            page,
+            per_page,
+        }
+    }
+
+    fn token(token_id: &str, name: &str, market_cap: f64) -> CryptoToken {
+        CryptoToken {
+            name: name.to_string(),
+            volume_24h: 0.0,
+            is_favorite: true,
+            ..test_token(token_id, 1.0, market_cap)
+        }
+    }
+
+    fn ids(tokens: &[CryptoToken]) -> Vec<&str> {
+        tokens.iter().map(|t| t.token_id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_no_params_means_everything_in_natural_order() {
+        let params = ListParams::from_query(&query(None, None, None, None)).unwrap();
+        assert_eq!(params, ListParams::default());
+
+        let options = params.find_options();
+        assert!(options.sort.is_none() && options.skip.is_none() && options.limit.is_none());
+
+        let tokens = vec![token("b", "B", 1.0), token("a", "A", 2.0)];
+        assert_eq!(ids(&params.apply(&tokens)), vec!["b", "a"]);
+    }
+
+    #[test]
+    fn test_defaults_fill_in_missing_params() {
+        let params = ListParams::from_query(&query(Some("name"), None, Some(2), None)).unwrap();
+        assert_eq!(params.sort, Some((SortField::Name, SortOrder::Asc)));
+        assert_eq!(params.page, Some(Page { page: 2, per_page: DEFAULT_PER_PAGE }));
+
+        let params = ListParams::from_query(&query(None, Some("asc"), None, None)).unwrap();
+        assert_eq!(params.sort, Some((SortField::MarketCap, SortOrder::Asc)));
+        assert!(params.page.is_none());
+    }
+
+    #[test]
+    fn test_invalid_params_rejected() {
+        assert!(ListParams::from_query(&query(Some("price"), None, None, None)).is_err());
+        assert!(ListParams::from_query(&query(None, Some("up"), None, None)).is_err());
+        assert!(ListParams::from_query(&query(None, None, Some(0), None)).is_err());
+        assert!(ListParams::from_query(&query(None, None, None, Some(0))).is_err());
+        assert!(ListParams::from_query(&query(None, None, None, Some(MAX_PER_PAGE + 1))).is_err());
+    }
+
+    #[test]
+    fn test_find_options_match_params() {
+        let params = ListParams::from_query(&query(Some("market_cap"), Some("desc"), Some(3), Some(20))).unwrap();
+        let options = params.find_options();
+
+        assert_eq!(options.sort, Some(doc! { "market_cap": -1, "token_id": 1 }));
+        assert_eq!(options.skip, Some(40));
+        assert_eq!(options.limit, Some(20));
+    }
+
+    #[test]
+    fn test_token_filter_in_memory_and_in_mongo() {
+        let mut usdt = token("tether", "Tether", 100.0);
+        usdt.symbol = "usdt".to_string();
+        let mut pegged = token("new-dollar", "New Dollar", 5.0);
+        pegged.category = Some("stablecoins".to_string());
+        let mut l1 = token("solana", "Solana", 50.0);
+        l1.category = Some("layer-1".to_string());
+
+        let filter = TokenFilter::listed(Some(true));
+        assert!(!filter.matches(&usdt) && !filter.matches(&pegged) && filter.matches(&l1));
+        assert_eq!(
+            filter.to_document(),
+            doc! { "$and": [stablecoins::exclusion_filter(), { "hidden": { "$ne": true } }] }
+        );
+        assert!(TokenFilter::listed(None).hides_only());
+
+        let query = TokensQuery { category: Some("layer-1".to_string()), exclude_stablecoins: None, cursor: None, include_hidden: Some(true), sparkline: None, tag: None, direction: None, fresh: None, fields: None };
+        let filter = TokenFilter::from_query(&query).unwrap();
+        assert!(filter.matches(&l1) && !filter.matches(&usdt));
+        assert_eq!(filter.to_document(), doc! { "$and": [{ "category": "layer-1" }] });
+
+        assert!(TokenFilter::default().is_empty());
+        assert!(TokenFilter::default().to_document().is_empty());
+        let query = TokensQuery { category: Some("Layer 1".to_string()), exclude_stablecoins: None, cursor: None, include_hidden: None, sparkline: None, tag: None, direction: None, fresh: None, fields: None };
+        assert!(TokenFilter::from_query(&query).is_err());
+    }
+
+    #[test]
+    fn test_hidden_tokens_are_left_out_unless_asked_for() {
+        let mut scam = token("scam-coin", "Scam Coin", 1.0);
+        scam.hidden = true;
+        let btc = token("bitcoin", "Bitcoin", 100.0);
+
+        let query = TokensQuery { category: None, exclude_stablecoins: None, cursor: None, include_hidden: None, sparkline: None, tag: None, direction: None, fresh: None, fields: None };
+        let filter = TokenFilter::from_query(&query).unwrap();
+        assert!(filter.hides_only() && !filter.is_empty());
+        assert!(!filter.matches(&scam) && filter.matches(&btc));
+        assert_eq!(filter.to_document(), doc! { "$and": [{ "hidden": { "$ne": true } }] });
+
+        let query = TokensQuery { include_hidden: Some(true), ..query };
+        let filter = TokenFilter::from_query(&query).unwrap();
+        assert!(filter.is_empty() && !filter.hides_only());
+        assert!(filter.matches(&scam));
+    }
+
+    #[test]
+    fn test_direction_splits_on_the_sign_of_the_24h_change() {
+        let mut up = token("up", "Up", 1.0);
+        up.price_change_percentage_24h = 2.5;
+        let mut down = token("down", "Down", 1.0);
+        down.price_change_percentage_24h = -0.1;
+        let flat = token("flat", "Flat", 1.0);
+
+        let query = TokensQuery { category: None, exclude_stablecoins: None, cursor: None, include_hidden: Some(true), sparkline: None, tag: None, direction: Some("up".to_string()), fresh: None, fields: None };
+        let filter = TokenFilter::from_query(&query).unwrap();
+        assert!(filter.matches(&up) && !filter.matches(&down) && !filter.matches(&flat));
+        assert!(!filter.is_empty());
+        assert_eq!(filter.to_document(), doc! { "$and": [{ "price_change_percentage_24h": { "$gt": 0.0 } }] });
+
+        let filter = TokenFilter::from_query(&TokensQuery { direction: Some("down".to_string()), ..query }).unwrap();
+        assert!(!filter.matches(&up) && filter.matches(&down) && !filter.matches(&flat));
+        assert_eq!(filter.to_document(), doc! { "$and": [{ "price_change_percentage_24h": { "$lt": 0.0 } }] });
+
+        let query = TokensQuery { category: None, exclude_stablecoins: None, cursor: None, include_hidden: None, sparkline: None, tag: None, direction: Some("all".to_string()), fresh: None, fields: None };
+        let filter = TokenFilter::from_query(&query).unwrap();
+        assert!(filter.hides_only() && filter.matches(&flat));
+        assert!(TokenFilter::from_query(&TokensQuery { direction: Some("sideways".to_string()), ..query }).is_err());
+    }
+
+    #[test]
+    fn test_apply_sorts_then_pages_with_stable_ties() {
+        let tokens = vec![
+            token("c", "Gamma", 10.0),
+            token("a", "Alpha", 30.0),
+            token("d", "Delta", 10.0),
+            token("b", "Beta", 20.0),
+        ];
+
+        let params = ListParams::from_query(&query(Some("market_cap"), None, Some(1), Some(3))).unwrap();
+        assert_eq!(ids(&params.apply(&tokens)), vec!["a", "b", "c"]);
+
+        let params = ListParams::from_query(&query(Some("market_cap"), None, Some(2), Some(3))).unwrap();
+        assert_eq!(ids(&params.apply(&tokens)), vec!["d"]);
+
+        let params = ListParams::from_query(&query(Some("name"), None, None, None)).unwrap();
+        assert_eq!(ids(&params.apply(&tokens)), vec!["a", "b", "d", "c"]);
+
+        let params = ListParams::from_query(&query(None, None, Some(5), Some(3))).unwrap();
+        assert!(params.apply(&tokens).is_empty());
+    }
+
+    #[test]
+    fn test_apply_orders_nan_and_infinity_the_same_every_time() {
+        let tokens = vec![
+            token("a", "A", f64::NAN),
+            token("b", "B", 10.0),
+            token("c", "C", f64::INFINITY),
+            token("d", "D", f64::NAN),
+            token("e", "E", f64::NEG_INFINITY),
+        ];
+        let mut reversed = tokens.clone();
+        reversed.reverse();
+
+        let params = ListParams::from_query(&query(Some("market_cap"), None, None, None)).unwrap();
+        assert_eq!(ids(&params.apply(&tokens)), vec!["c", "b", "e", "a", "d"]);
+        assert_eq!(ids(&params.apply(&reversed)), vec!["c", "b", "e", "a", "d"]);
+
+        let params = ListParams::from_query(&query(Some("market_cap"), Some("asc"), None, None)).unwrap();
+        assert_eq!(ids(&params.apply(&reversed)), vec!["a", "d", "e", "b", "c"]);
+    }
+
+    #[test]
+    fn test_sorting_by_a_longer_window_leaves_out_tokens_without_it() {
+        let mut tokens = vec![token("a", "A", 1.0), token("b", "B", 1.0), token("c", "C", 1.0)];
+        tokens[0].price_change_percentage_7d = Some(-2.0);
+        tokens[2].price_change_percentage_7d = Some(5.0);
+
+        let params = ListParams::from_query(&query(Some("price_change_percentage_7d"), None, None, None)).unwrap();
+        assert_eq!(ids(&params.apply(&tokens)), vec!["c", "a"]);
+        // NaN never equals itself, so the documents are compared as text
+        assert_eq!(
+            params.filter(doc! { "is_favorite": true }).to_string(),
+            doc! { "$and": [
+                { "is_favorite": true },
+                { "price_change_percentage_7d": { "$type": "number", "$ne": f64::NAN } },
+            ] }
+            .to_string()
+        );
+
+        // The always-present fields don't narrow anything
+        let params = ListParams::from_query(&query(Some("price_change_percentage_24h"), None, None, None)).unwrap();
+        assert_eq!(params.apply(&tokens).len(), 3);
+        assert_eq!(params.filter(doc! { "is_favorite": true }), doc! { "is_favorite": true });
+    }
+
+    #[test]
+    fn test_cursor_round_trips_and_is_checked() {
+        let id = ObjectId::new();
+        let cursor = Cursor {
+            field: SortField::Name,
+            order: SortOrder::Asc,
+            value: Bson::String("Bitcoin".to_string()),
+            id,
+            issued_at: DateTime::from_timestamp(Utc::now().timestamp(), 0).unwrap(),
+        };
+        let raw = cursor.encode();
+        assert_eq!(Cursor::decode(&raw, SortField::Name, SortOrder::Asc, Utc::now()), Ok(cursor.clone()));
+
+        let err = Cursor::decode(&raw, SortField::MarketCap, SortOrder::Desc, Utc::now()).unwrap_err();
+        assert!(err.contains("sort_by=name&order=asc"), "{}", err);
+        let later = Utc::now() + chrono::Duration::seconds(CURSOR_TTL_SECS + 1);
+        assert!(Cursor::decode(&raw, SortField::Name, SortOrder::Asc, later).unwrap_err().contains("expired"));
+        assert!(Cursor::decode("not a cursor", SortField::Name, SortOrder::Asc, Utc::now()).unwrap_err().contains("invalid"));
+    }
+
+    #[test]
+    fn test_cursor_page_filters_past_the_last_key() {
+        let mut tokens: Vec<CryptoToken> = (0..3).map(|i| token(&format!("t{}", i), "T", 10.0 - i as f64)).collect();
+        for token in &mut tokens {
+            token.id = Some(ObjectId::new());
+        }
+        let list = query(None, None, None, Some(2));
+        let first = CursorPage::from_query(&list, &ListParams::from_query(&list).unwrap(), "").unwrap();
+        assert_eq!(first.find_options().sort, Some(doc! { "market_cap": -1, "_id": -1 }));
+        assert_eq!(first.find_options().limit, Some(3));
+        assert_eq!(first.filter(Document::new()), Document::new());
+
+        let next = first.finish(&mut tokens).expect("a third token means another page");
+        assert_eq!(ids(&tokens), vec!["t0", "t1"]);
+        let second = CursorPage::from_query(&list, &ListParams::from_query(&list).unwrap(), &next).unwrap();
+        assert_eq!(
+            second.filter(Document::new()),
+            doc! { "$and": [{}, { "$or": [
+                { "market_cap": { "$lt": 9.0 } },
+                { "market_cap": 9.0, "_id": { "$lt": tokens[1].id.unwrap() } },
+            ] }] }
+        );
+
+        let mut last = vec![tokens[0].clone()];
+        assert_eq!(second.finish(&mut last), None);
+        let paged = query(None, None, Some(2), None);
+        assert!(CursorPage::from_query(&paged, &ListParams::from_query(&paged).unwrap(), "").is_err());
+    }
+}
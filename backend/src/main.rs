@@ -1,57 +1,179 @@
-mod models;
-mod handlers;
-mod db;
-mod crypto_service;
-
-use actix_web::{web, App, HttpServer, middleware::Logger};
+use actix_web::{web, App, HttpServer, middleware::{from_fn, Compress}};
 use actix_cors::Cors;
 use dotenv::dotenv;
-use std::env;
-use crypto_service::CryptoService;
+use crypto_tracker_backend::{backfill::{self, BackfillStatus, HistoryBackfill}, cache_store::CacheBackend, compression, config::Config, crypto_service::CryptoService, db, events::EventRecorder, fallback::FallbackProvider, force_fresh::ForceFreshLimiter, handlers::HistoryFlights, image_proxy::ImageProxy, maintenance, rate_limiter::RateLimiter, routes, shutdown::{self, BackgroundTasks}, telemetry::{self, RequestSpan}, timeout, token_cache::TokenCache, upstream::UpstreamGate, warmup::{CacheWarmUp, WARMUP_TIMEOUT}, webhook::{WebhookDispatcher, WebhookNotifier}};
+use tracing_actix_web::TracingLogger;
+use std::time::Duration;
+#[cfg(feature = "redis")]
+use crypto_tracker_backend::{cache_store::CacheStore, redis_store::RedisStore};
+#[cfg(feature = "redis")]
+use std::sync::Arc;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
-    env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
 
-    let mongodb_uri = env::var("MONGODB_URI").expect("MONGODB_URI must be set");
-    let database_name = env::var("DATABASE_NAME").expect("DATABASE_NAME must be set");
-    let host = env::var("SERVER_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
-    let port = env::var("SERVER_PORT").unwrap_or_else(|_| "8080".to_string());
-    let coingecko_api = env::var("COINGECKO_API_URL")
-        .unwrap_or_else(|_| "https://api.coingecko.com/api/v3".to_string());
+    let config = Config::from_env()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    telemetry::init(config.log_format);
+
+    tracing::info!("Connecting to MongoDB at {}", config.mongodb_uri);
+    let db_client = db::init_db(&config).await.expect("Failed to connect to MongoDB");
+    if let Err(e) = db_client.ensure_indexes().await {
+        tracing::warn!(error = %e, "Failed to create database indexes");
+    }
+
+    tracing::info!("Initializing CoinGecko API client");
+    let crypto_service = CryptoService::from_config(&config);
+    let fallback = web::Data::new(FallbackProvider::from_config(&config));
+    // One gate for every worker, so the concurrency cap is process-wide
+    let upstream_gate = web::Data::new(UpstreamGate::from_config(&config));
+    // Shared across workers too, so identical chart requests coalesce wherever they land
+    let history_flights = web::Data::new(HistoryFlights::new());
+    // Token logos fetched for /api/tokens/{id}/image, shared so each is fetched once
+    let images = web::Data::new(ImageProxy::default());
+    // Per-client allowance for requests that skip the cache
+    let force_fresh = web::Data::new(ForceFreshLimiter::from_config(&config));
+    if fallback.is_enabled() {
+        tracing::info!("Binance fallback enabled for tokens listed in symbol_map");
+    }
+    let (rate_limiter, token_cache) = build_shared_state(&config).await?;
+    let rate_limiter = web::Data::new(rate_limiter);
+    let token_cache = web::Data::new(token_cache);
+    let config_data = web::Data::new(config.clone());
+    let background_tasks = BackgroundTasks::new();
+    let tasks_data = web::Data::new(background_tasks.clone());
+
+    // 0 turns pruning off, e.g. when a second instance already runs it
+    if config.history_prune_interval_secs > 0 {
+        maintenance::spawn_history_pruning(
+            &background_tasks,
+            db_client.clone(),
+            Duration::from_secs(config.history_prune_interval_secs),
+        );
+    }
 
-    log::info!("Connecting to MongoDB at {}", mongodb_uri);
-    let db_client = db::init_db(&mongodb_uri, &database_name).await;
+    // Keeps favorites' charts cached, one upstream call per tick at most; 0 turns it off
+    let backfill_status = web::Data::new(BackfillStatus::default());
+    if config.history_backfill_interval_secs > 0 {
+        let backfill = HistoryBackfill::new(
+            db_client.clone(),
+            crypto_service.clone(),
+            rate_limiter.clone().into_inner(),
+            backfill_status.get_ref().clone(),
+        );
+        backfill::spawn_history_backfill(
+            &background_tasks,
+            backfill,
+            Duration::from_secs(config.history_backfill_interval_secs),
+        );
+    }
 
-    log::info!("Initializing CoinGecko API client");
-    let crypto_service = CryptoService::new(coingecko_api);
+    // Webhook events are delivered by a background task, off the request path
+    let notifier = web::Data::new(WebhookNotifier::spawn(&background_tasks, WebhookDispatcher::new(db_client.clone())));
 
-    log::info!("Starting server at {}:{}", host, port);
+    let events = web::Data::new(EventRecorder::new(db_client.clone(), &config));
 
-    HttpServer::new(move || {
+    // Before binding, so the first request finds the cache filled; capped so a CoinGecko
+    // outage only delays startup by WARMUP_TIMEOUT
+    if config.startup_warmup {
+        CacheWarmUp {
+            db: &db_client,
+            crypto_service: &crypto_service,
+            rate_limiter: &rate_limiter,
+            token_cache: &token_cache,
+            notifier: &notifier,
+            events: &events,
+            max_age_secs: config.token_cache_ttl_secs,
+            token_limit: config.top_tokens_limit,
+        }
+        .run(WARMUP_TIMEOUT)
+        .await;
+    }
+
+    // /api/v1 and the deprecated /api aliases serve the same handlers; /api/v2 sits alongside
+    let api_versions = routes::VersionRegistry::standard();
+
+    tracing::info!("Starting server at {}", config.bind_address());
+
+    let server = HttpServer::new(move || {
         let cors = Cors::default()
             .allow_any_origin()
             .allow_any_method()
             .allow_any_header();
 
         App::new()
+            .app_data(config_data.clone())
             .app_data(web::Data::new(db_client.clone()))
             .app_data(web::Data::new(crypto_service.clone()))
+            .app_data(rate_limiter.clone())
+            .app_data(tasks_data.clone())
+            .app_data(token_cache.clone())
+            .app_data(notifier.clone())
+            .app_data(events.clone())
+            .app_data(fallback.clone())
+            .app_data(upstream_gate.clone())
+            .app_data(history_flights.clone())
+            .app_data(images.clone())
+            .app_data(force_fresh.clone())
+            .app_data(backfill_status.clone())
+            // Compress sees the final body; the marker inside it opts small and SSE responses out
+            .wrap(from_fn(compression::skip_uncompressible))
+            .wrap(Compress::default())
+            .wrap(from_fn(timeout::request_timeout))
             .wrap(cors)
-            .wrap(Logger::default())
-            .service(
-                web::scope("/api")
-                    .route("/tokens", web::get().to(handlers::get_tokens))
-                    .route("/tokens/{id}", web::get().to(handlers::get_token))
-                    .route("/tokens/favorite", web::post().to(handlers::toggle_favorite))
-                    .route("/favorites", web::get().to(handlers::get_favorites))
-                    .route("/search", web::get().to(handlers::search_tokens))
-                    .route("/history/{id}/{days}", web::get().to(handlers::get_historical_data))
-                    .route("/stats", web::get().to(handlers::get_stats))
-            )
+            .wrap(TracingLogger::<RequestSpan>::new())
+            .wrap(from_fn(telemetry::request_id))
+            .configure(|cfg| api_versions.configure(cfg))
     })
-    .bind(format!("{}:{}", host, port))?
-    .run()
-    .await
+    .bind(config.bind_address())?
+    .disable_signals()
+    .run();
+
+    // Signal handling is ours so background tasks hear about shutdown alongside actix
+    let server_handle = server.handle();
+    let shutdown_token = background_tasks.token();
+    tokio::spawn(async move {
+        shutdown::wait_for_signal().await;
+        shutdown_token.cancel();
+        server_handle.stop(true).await;
+    });
+
+    server.await?;
+
+    let grace = Duration::from_secs(config.shutdown_grace_secs);
+    tracing::info!("Waiting up to {:?} for background tasks to finish", grace);
+    background_tasks.shutdown(grace).await;
+
+    Ok(())
+}
+
+// Rate-limit and token cache state lives in Redis with CACHE_BACKEND=redis so every
+// instance shares one upstream budget; otherwise it stays in this process.
+async fn build_shared_state(config: &Config) -> std::io::Result<(RateLimiter, TokenCache)> {
+    let rate_limiter = RateLimiter::from_config(config);
+    let token_cache = TokenCache::from_config(config);
+
+    let (CacheBackend::Redis, Some(redis_url)) = (config.cache_backend, &config.redis_url) else {
+        return Ok((rate_limiter, token_cache));
+    };
+
+    #[cfg(feature = "redis")]
+    {
+        tracing::info!("Sharing rate-limit and cache state through Redis");
+        let store = RedisStore::connect(redis_url)
+            .await
+            .map_err(|e| std::io::Error::other(format!("Failed to connect to Redis: {}", e)))?;
+        let store: Arc<dyn CacheStore> = Arc::new(store);
+        Ok((rate_limiter.with_store(store.clone()), token_cache.with_store(store)))
+    }
+
+    #[cfg(not(feature = "redis"))]
+    {
+        let _ = redis_url;
+        Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "CACHE_BACKEND=redis but this build does not include the `redis` feature",
+        ))
+    }
 }
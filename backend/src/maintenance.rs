@@ -0,0 +1,23 @@
+use chrono::Utc;
+use std::time::Duration;
+use crate::db::DbClient;
+use crate::shutdown::BackgroundTasks;
+
+// Periodically drops expired price_history documents. Runs once at startup, then
+// every `interval`; stops between runs when shutdown is signalled.
+pub fn spawn_history_pruning(tasks: &BackgroundTasks, db: DbClient, interval: Duration) {
+    tasks.spawn(move |token| async move {
+        loop {
+            match db.prune_history(Utc::now()).await {
+                Ok(0) => tracing::debug!("No expired price history to prune"),
+                Ok(deleted) => tracing::info!("Pruned {} expired price history documents", deleted),
+                Err(e) => tracing::warn!("Failed to prune price history: {}", e),
+            }
+
+            tokio::select! {
+                _ = token.cancelled() => break,
+                _ = tokio::time::sleep(interval) => {}
+            }
+        }
+    });
+}
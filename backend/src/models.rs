@@ -1,10 +1,17 @@
 use serde::{Deserialize, Serialize};
 use mongodb::bson::oid::ObjectId;
 use chrono::{DateTime, Utc};
+use utoipa::ToSchema;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+// Fields of a stored token only users change. A refresh never $sets them; a new token is
+// inserted with the fetched copy's values, i.e. the defaults. Add any new user-owned field
+// here, to the overlay on fetched copies in handlers and to the dedupe merge in db.
+pub const USER_OWNED_FIELDS: &[&str] = &["is_favorite", "hidden", "tags", "note"];
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct CryptoToken {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
     pub id: Option<ObjectId>,
     pub token_id: String,
     pub symbol: String,
@@ -14,75 +21,1670 @@ pub struct CryptoToken {
     pub volume_24h: f64,
     pub price_change_24h: f64,
     pub price_change_percentage_24h: f64,
+    // Longer-horizon changes in percent. None when CoinGecko has no figure, e.g. for a
+    // coin younger than the window, and on documents written before they were stored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price_change_percentage_7d: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price_change_percentage_30d: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price_change_percentage_1y: Option<f64>,
+    // Hourly prices over the last 7 days, oldest first. Stored once a list is fetched
+    // with ?sparkline=true and only returned to clients that ask for it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sparkline_7d: Option<Vec<f64>>,
     pub high_24h: Option<f64>,
     pub low_24h: Option<f64>,
     pub circulating_supply: Option<f64>,
     pub total_supply: Option<f64>,
+    // Absent on documents written before they were stored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_supply: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fully_diluted_valuation: Option<f64>,
     pub ath: Option<f64>,
     pub ath_change_percentage: Option<f64>,
     pub atl: Option<f64>,
     pub atl_change_percentage: Option<f64>,
     pub image: Option<String>,
+    // When the upstream source last updated these figures
     pub last_updated: DateTime<Utc>,
+    // When we retrieved them; absent on documents written before it was tracked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fetched_at: Option<DateTime<Utc>>,
     pub is_favorite: bool,
+    // Left out of lists, search, stats and movers; lists and search bring it back with
+    // include_hidden=true. Set only through /api/admin/tokens/{id}/hide, never by a
+    // refresh; absent on older documents.
+    #[serde(default)]
+    pub hidden: bool,
+    // User metadata for favorites; absent on documents written before it existed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    // Where current_price and the 24h figures came from; absent on older documents
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price_source: Option<PriceSource>,
+    // CoinGecko category id (e.g. `layer-1`), set when the token was fetched through a
+    // category filter. A coin can sit in several categories; this is the last one seen.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+}
+
+// Figures computed from a token's supply and price, never stored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct DerivedMetrics {
+    // Circulating supply as a percentage of max supply, or of total supply when there's no max
+    pub circulating_supply_percentage: Option<f64>,
+    // CoinGecko's fully diluted valuation, or price × max supply when it doesn't give one
+    pub fdv: Option<f64>,
+    pub mcap_to_fdv_ratio: Option<f64>,
+}
+
+impl CryptoToken {
+    // Every field is None when an input it needs is missing, zero or not finite
+    pub fn derived_metrics(&self) -> DerivedMetrics {
+        let positive = |value: Option<f64>| value.filter(|v| v.is_finite() && *v > 0.0);
+
+        let circulating_supply_percentage = positive(self.circulating_supply)
+            .zip(positive(self.max_supply).or(positive(self.total_supply)))
+            .map(|(circulating, supply)| circulating / supply * 100.0);
+        let fdv = positive(self.fully_diluted_valuation).or_else(|| {
+            positive(Some(self.current_price))
+                .zip(positive(self.max_supply))
+                .map(|(price, max_supply)| price * max_supply)
+        });
+        let mcap_to_fdv_ratio = positive(Some(self.market_cap))
+            .zip(fdv)
+            .map(|(market_cap, fdv)| market_cap / fdv);
+
+        DerivedMetrics {
+            circulating_supply_percentage,
+            fdv,
+            mcap_to_fdv_ratio,
+        }
+    }
+}
+
+// A token for unit tests: `token_id` doubles as symbol and name, and every optional field
+// is unset. Tests set whatever else they need with struct update syntax.
+#[cfg(test)]
+pub(crate) fn test_token(token_id: &str, current_price: f64, market_cap: f64) -> CryptoToken {
+    CryptoToken {
+        id: None,
+        token_id: token_id.to_string(),
+        symbol: token_id.to_string(),
+        name: token_id.to_string(),
+        current_price,
+        market_cap,
+        volume_24h: 10000.0,
+        price_change_24h: 0.0,
+        price_change_percentage_24h: 0.0,
+        price_change_percentage_7d: None,
+        price_change_percentage_30d: None,
+        price_change_percentage_1y: None,
+        sparkline_7d: None,
+        high_24h: None,
+        low_24h: None,
+        circulating_supply: None,
+        total_supply: None,
+        max_supply: None,
+        fully_diluted_valuation: None,
+        ath: None,
+        ath_change_percentage: None,
+        atl: None,
+        atl_change_percentage: None,
+        image: None,
+        last_updated: Utc::now(),
+        fetched_at: None,
+        is_favorite: false,
+        hidden: false,
+        tags: Vec::new(),
+        note: None,
+        price_source: None,
+        category: None,
+    }
+}
+
+// GET /api/tokens/{id}/supply. Fields CoinGecko didn't provide stay null.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct TokenSupply {
+    pub circulating_supply: Option<f64>,
+    pub total_supply: Option<f64>,
+    pub max_supply: Option<f64>,
+    pub fully_diluted_valuation: Option<f64>,
+    // circulating / max × 100, only when both are known and max is positive
+    pub circulating_percent: Option<f64>,
+}
+
+impl From<&CryptoToken> for TokenSupply {
+    fn from(token: &CryptoToken) -> Self {
+        let circulating_percent = token
+            .circulating_supply
+            .zip(token.max_supply.filter(|max| *max > 0.0))
+            .map(|(circulating, max)| circulating / max * 100.0);
+        Self {
+            circulating_supply: token.circulating_supply,
+            total_supply: token.total_supply,
+            max_supply: token.max_supply,
+            fully_diluted_valuation: token.fully_diluted_valuation,
+            circulating_percent,
+        }
+    }
+}
+
+// /api/search result with ?debug=true: the token and its text search relevance, None
+// for results the text index didn't find
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ScoredToken {
+    #[serde(flatten)]
+    pub token: CryptoToken,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<f64>,
+}
+
+// GET /api/tokens/{id}: the token with its derived metrics alongside. The list
+// endpoints return plain tokens to keep their payloads small.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TokenDetail {
+    #[serde(flatten)]
+    pub token: CryptoToken,
+    #[serde(flatten)]
+    pub metrics: DerivedMetrics,
+}
+
+impl From<CryptoToken> for TokenDetail {
+    fn from(token: CryptoToken) -> Self {
+        let metrics = token.derived_metrics();
+        Self { token, metrics }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PriceSource {
+    CoinGecko,
+    Binance,
+}
+
+impl PriceSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PriceSource::CoinGecko => "coingecko",
+            PriceSource::Binance => "binance",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SparklineIn7d {
+    pub price: Vec<f64>,
+}
+
+// A number as CoinGecko sends it: usually a JSON number (integer or float), sometimes
+// a numeric string; anything else reads as missing
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LenientNumber {
+    Number(f64),
+    Text(String),
+    Other(serde::de::IgnoredAny),
+}
+
+fn optional_number<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    Ok(match Option::<LenientNumber>::deserialize(deserializer)? {
+        Some(LenientNumber::Number(n)) => Some(n),
+        Some(LenientNumber::Text(text)) => text.trim().parse::<f64>().ok().filter(|n| n.is_finite()),
+        Some(LenientNumber::Other(_)) | None => None,
+    })
+}
+
+// Fails on null or garbage, so the entry is dropped rather than served with a made-up price
+fn required_number<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    optional_number(deserializer)?.ok_or_else(|| serde::de::Error::custom("expected a number or numeric string"))
+}
+
+fn number_or_zero<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    Ok(optional_number(deserializer)?.unwrap_or(0.0))
+}
+
+// One entry of GET /coins/markets. Numbers go through the lenient readers above, since
+// CoinGecko has switched fields between integers, floats and strings before; an entry
+// that still doesn't fit is skipped by CryptoService rather than failing the page.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CoinGeckoMarket {
+    pub id: String,
+    pub symbol: String,
+    pub name: String,
+    #[serde(default)]
+    pub image: String,
+    #[serde(deserialize_with = "required_number")]
+    pub current_price: f64,
+    #[serde(default, deserialize_with = "number_or_zero")]
+    pub market_cap: f64,
+    #[serde(default)]
+    pub market_cap_rank: Option<u32>,
+    #[serde(default, deserialize_with = "optional_number")]
+    pub fully_diluted_valuation: Option<f64>,
+    #[serde(default, deserialize_with = "number_or_zero")]
+    pub total_volume: f64,
+    #[serde(default, deserialize_with = "optional_number")]
+    pub high_24h: Option<f64>,
+    #[serde(default, deserialize_with = "optional_number")]
+    pub low_24h: Option<f64>,
+    #[serde(default, deserialize_with = "optional_number")]
+    pub price_change_24h: Option<f64>,
+    #[serde(default, deserialize_with = "optional_number")]
+    pub price_change_percentage_24h: Option<f64>,
+    #[serde(default, deserialize_with = "optional_number")]
+    pub market_cap_change_24h: Option<f64>,
+    #[serde(default, deserialize_with = "optional_number")]
+    pub market_cap_change_percentage_24h: Option<f64>,
+    #[serde(default, deserialize_with = "optional_number")]
+    pub circulating_supply: Option<f64>,
+    #[serde(default, deserialize_with = "optional_number")]
+    pub total_supply: Option<f64>,
+    #[serde(default, deserialize_with = "optional_number")]
+    pub max_supply: Option<f64>,
+    #[serde(default, deserialize_with = "optional_number")]
+    pub ath: Option<f64>,
+    #[serde(default, deserialize_with = "optional_number")]
+    pub ath_change_percentage: Option<f64>,
+    #[serde(default)]
+    pub ath_date: Option<String>,
+    #[serde(default, deserialize_with = "optional_number")]
+    pub atl: Option<f64>,
+    #[serde(default, deserialize_with = "optional_number")]
+    pub atl_change_percentage: Option<f64>,
+    #[serde(default)]
+    pub atl_date: Option<String>,
+    // Only present when asked for through `price_change_percentage`
+    #[serde(default, deserialize_with = "optional_number")]
+    pub price_change_percentage_7d_in_currency: Option<f64>,
+    #[serde(default, deserialize_with = "optional_number")]
+    pub price_change_percentage_30d_in_currency: Option<f64>,
+    #[serde(default, deserialize_with = "optional_number")]
+    pub price_change_percentage_1y_in_currency: Option<f64>,
+    // Only present with `sparkline=true`
+    #[serde(default)]
+    pub sparkline_in_7d: Option<SparklineIn7d>,
+    // RFC3339; null for coins CoinGecko hasn't priced recently
+    #[serde(default)]
+    pub last_updated: Option<String>,
+}
+
+// Query string accepted by /api/search/coins
+#[derive(Debug, Deserialize)]
+pub struct CoinSearchQuery {
+    pub q: Option<String>,
+}
+
+// GET /search: coins matching a query by name or symbol, best match first
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CoinGeckoSearch {
+    #[serde(default)]
+    pub coins: Vec<CoinSearchResult>,
+}
+
+// One coin CoinGecko's search found, listed by us or not. Served as-is by
+// /api/search/coins; market data for it comes from /coins/markets.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct CoinSearchResult {
+    pub id: String,
+    pub name: String,
+    pub symbol: String,
+    // Absent for coins too small to be ranked
+    #[serde(default)]
+    pub market_cap_rank: Option<u32>,
+    // Logo URLs, small and large
+    #[serde(default)]
+    pub thumb: Option<String>,
+    #[serde(default)]
+    pub large: Option<String>,
+}
+
+// GET /coins/{id} with localization, tickers and market data turned off. CoinGecko
+// sends null for much of this on small coins.
+#[derive(Debug, Deserialize)]
+pub struct CoinGeckoCoin {
+    pub id: String,
+    pub symbol: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<CoinGeckoDescription>,
+    #[serde(default)]
+    pub links: Option<CoinGeckoLinks>,
+    // YYYY-MM-DD
+    #[serde(default)]
+    pub genesis_date: Option<String>,
+    #[serde(default)]
+    pub categories: Option<Vec<Option<String>>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CoinGeckoDescription {
+    // HTML
+    pub en: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CoinGeckoLinks {
+    // Padded with empty strings
+    #[serde(default)]
+    pub homepage: Option<Vec<String>>,
+    pub twitter_screen_name: Option<String>,
+    pub subreddit_url: Option<String>,
+}
+
+// One entry of CoinGecko's GET /exchange_rates: how many `unit` one BTC buys
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct ExchangeRate {
+    pub name: String,
+    pub unit: String,
+    pub value: f64,
+    // fiat, crypto or commodity
+    #[serde(rename = "type")]
+    pub rate_type: String,
+}
+
+// GET /exchange_rates, keyed by lowercase currency code (usd, eur, eth, xau, ...)
+#[derive(Debug, Deserialize)]
+pub struct CoinGeckoExchangeRates {
+    pub rates: std::collections::BTreeMap<String, ExchangeRate>,
+}
+
+// GET /api/exchange-rates, stored as the one document of the exchange_rates collection
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct ExchangeRates {
+    pub rates: std::collections::BTreeMap<String, ExchangeRate>,
+    pub fetched_at: DateTime<Utc>,
+}
+
+// Query string accepted by /api/exchange-rates
+#[derive(Debug, Deserialize)]
+pub struct ExchangeRatesQuery {
+    #[serde(rename = "type")]
+    pub rate_type: Option<String>,
+}
+
+// GET /api/tokens/{id}/profile, stored in the coin_profiles collection
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct CoinProfile {
+    pub token_id: String,
+    pub symbol: String,
+    pub name: String,
+    // Plain text: CoinGecko's HTML tags are stripped and entities decoded
+    pub description: String,
+    pub homepage: Option<String>,
+    pub twitter: Option<String>,
+    pub reddit: Option<String>,
+    // YYYY-MM-DD, when CoinGecko knows it
+    pub genesis_date: Option<String>,
+    pub categories: Vec<String>,
+    pub fetched_at: DateTime<Utc>,
+}
+
+impl From<CoinGeckoCoin> for CoinProfile {
+    fn from(coin: CoinGeckoCoin) -> Self {
+        let non_empty = |value: Option<String>| value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+        let links = coin.links;
+        let (homepage, twitter, reddit) = match links {
+            Some(links) => (
+                non_empty(links.homepage.and_then(|pages| pages.into_iter().find(|p| !p.trim().is_empty()))),
+                non_empty(links.twitter_screen_name).map(|name| format!("https://twitter.com/{}", name)),
+                non_empty(links.subreddit_url),
+            ),
+            None => (None, None, None),
+        };
+        Self {
+            token_id: coin.id,
+            symbol: coin.symbol,
+            name: coin.name,
+            description: strip_html(coin.description.and_then(|d| d.en).as_deref().unwrap_or_default()),
+            homepage,
+            twitter,
+            reddit,
+            genesis_date: non_empty(coin.genesis_date),
+            categories: coin.categories.unwrap_or_default().into_iter().flatten().collect(),
+            fetched_at: Utc::now(),
+        }
+    }
+}
+
+// Text content of an HTML fragment: tags dropped, the common entities decoded
+pub fn strip_html(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    let text = text
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&");
+    text.trim().to_string()
+}
+
+
+
+// Entry in the symbol_map collection: which Binance pair prices a token, e.g. bitcoin -> BTCUSDT
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SymbolMapping {
+    pub token_id: String,
+    pub symbol: String,
+}
+
+// Query string for /api/market/history
+#[derive(Debug, Deserialize)]
+pub struct MarketHistoryQuery {
+    pub days: Option<u32>,
+}
+
+// One UTC day of GET /api/market/history
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct MarketCapPoint {
+    // Start of the day, Unix milliseconds
+    pub timestamp: i64,
+    pub total_market_cap: f64,
+    // Tokens with cached history that day; the total only covers these
+    pub tokens_included: usize,
+}
+
+// Query string for DELETE /api/admin/tokens/{id}
+#[derive(Debug, Deserialize, Default)]
+pub struct DeleteTokenQuery {
+    pub dry_run: Option<bool>,
+}
+
+// Documents stored under one token id, per collection
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+pub struct TokenDocuments {
+    pub tokens: u64,
+    pub price_history: u64,
+    pub user_favorites: u64,
+    pub coin_profiles: u64,
+    pub symbol_map: u64,
+    pub events: u64,
+    pub annotations: u64,
+}
+
+// DELETE /api/admin/tokens/{id}: what was removed, or with dry_run what would be
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct TokenDeletion {
+    pub token_id: String,
+    pub dry_run: bool,
+    pub deleted: TokenDocuments,
+}
+
+// Query string for POST /api/admin/purge
+#[derive(Debug, Deserialize, Default)]
+pub struct PurgeQuery {
+    pub older_than_hours: Option<u32>,
+}
+
+// How long a token may go without a refresh before /api/admin/purge removes it
+pub const DEFAULT_PURGE_AGE_HOURS: u32 = 24;
+
+// POST /api/admin/purge: the cutoff applied, how many tokens were older, and how many
+// expired price history documents went with them
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct TokenPurge {
+    pub older_than: DateTime<Utc>,
+    pub deleted: u64,
+    pub history_deleted: u64,
+}
+
+// One of the idempotent fixes POST /api/admin/repair runs, in the order they run
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RepairPass {
+    // Keep the newest document per token_id, merging what users set on every copy
+    Dedupe,
+    // Null out fields older documents lack, so they deserialize again
+    Backfill,
+    // Drop cached charts holding no prices
+    EmptyHistory,
+}
+
+impl RepairPass {
+    pub const ALL: [RepairPass; 3] = [RepairPass::Dedupe, RepairPass::Backfill, RepairPass::EmptyHistory];
+
+    // Passes named in a comma-separated ?passes=, deduplicated and in running order;
+    // all of them when none are named
+    pub fn parse_list(raw: Option<&str>) -> Result<Vec<RepairPass>, String> {
+        let names: Vec<&str> = raw.unwrap_or_default().split(',').map(str::trim).filter(|s| !s.is_empty()).collect();
+        if names.is_empty() {
+            return Ok(Self::ALL.to_vec());
+        }
+        let mut passes = names.into_iter().map(str::parse).collect::<Result<Vec<RepairPass>, _>>()?;
+        passes.sort_unstable();
+        passes.dedup();
+        Ok(passes)
+    }
+}
+
+impl std::str::FromStr for RepairPass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dedupe" => Ok(RepairPass::Dedupe),
+            "backfill" => Ok(RepairPass::Backfill),
+            "empty_history" => Ok(RepairPass::EmptyHistory),
+            _ => Err(format!("unknown pass {:?}; passes are dedupe, backfill and empty_history", s)),
+        }
+    }
+}
+
+// Query string for POST /api/admin/repair
+#[derive(Debug, Deserialize, Default)]
+pub struct RepairQuery {
+    pub passes: Option<String>,
+}
+
+// What one repair pass did. Running it again right after reports nothing fixed or deleted.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct RepairReport {
+    pub pass: RepairPass,
+    pub examined: u64,
+    pub fixed: u64,
+    pub deleted: u64,
+}
+
+// Entry in the users collection. The API key itself is never stored, only its hash.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct User {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub api_key_hash: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// Where triggered events are POSTed. Set with PUT /api/admin/webhook; a single
+// document in the webhook_config collection.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+pub struct WebhookConfig {
+    pub url: String,
+    // Signs each body with HMAC-SHA256 when set. Write-only: never sent back.
+    #[serde(default, skip_serializing)]
+    pub secret: Option<String>,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+// Body POSTed to the webhook
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct WebhookEvent {
+    // e.g. `new_ath`
+    pub event: String,
+    pub token_id: String,
+    #[schema(value_type = Object)]
+    pub data: serde_json::Value,
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryOutcome {
+    Delivered,
+    // This attempt failed and another one follows
+    Retrying,
+    // Last attempt failed; the event is dropped
+    Failed,
+}
+
+// One attempt to deliver an event, in the webhook_deliveries collection
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct WebhookDelivery {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub id: Option<ObjectId>,
+    pub event: String,
+    pub token_id: String,
+    pub url: String,
+    // 1-based
+    pub attempt: u32,
+    // HTTP status, absent when the request didn't get a response
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    pub outcome: DeliveryOutcome,
+    pub attempted_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct DeliveriesQuery {
+    pub limit: Option<i64>,
+}
+
+// What a refresh noticed. The tag is stored and returned as `kind`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, ToSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum EventKind {
+    // The price went above the stored all-time high
+    AthBreak,
+    // The price moved at least LARGE_MOVE_PERCENT since the previous refresh
+    LargeMove { change_percentage: f64 },
+}
+
+// Every `kind` /api/events filters on
+pub const EVENT_KINDS: &[&str] = &["ath_break", "large_move"];
+
+impl EventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::AthBreak => "ath_break",
+            EventKind::LargeMove { .. } => "large_move",
+        }
+    }
+}
+
+// Entry in the events collection. For an ATH break the values are the old ATH and the
+// new price; for a large move, the previous and the new price.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+pub struct Event {
+    pub token_id: String,
+    #[serde(flatten)]
+    pub kind: EventKind,
+    pub old_value: f64,
+    pub new_value: f64,
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct EventsQuery {
+    // RFC3339 or Unix milliseconds
+    pub since: Option<String>,
+    pub kind: Option<String>,
+    pub limit: Option<i64>,
+}
+
+// Smallest and largest move, in percent, a PercentMove alert can watch for
+pub const MIN_ALERT_PERCENT: f64 = 0.1;
+pub const MAX_ALERT_PERCENT: f64 = 1000.0;
+
+// Triggers GET /api/alerts/{id}/triggers returns
+pub const ALERT_TRIGGER_LIMIT: i64 = 100;
+
+// Period a PercentMove alert measures the move over; CoinGecko's 24h change for now
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, ToSchema)]
+pub enum MoveWindow {
+    #[default]
+    #[serde(rename = "24h")]
+    Day,
+}
+
+// What an alert watches for. The tag is stored and sent as `type`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AlertCondition {
+    // The price moved at least `percent` either way over `window`
+    PercentMove {
+        percent: f64,
+        #[serde(default)]
+        window: MoveWindow,
+    },
+}
+
+// Tokens an alert covers: one token, or `favorites`, whatever is starred in the shared
+// list or by any user when the alert is checked. Stored and sent as that string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AlertScope {
+    Token(String),
+    Favorites,
+}
+
+impl std::str::FromStr for AlertScope {
+    type Err = String;
+
+    fn from_str(scope: &str) -> Result<Self, Self::Err> {
+        if scope == "favorites" {
+            Ok(AlertScope::Favorites)
+        } else if scope.starts_with("watchlist:") {
+            Err("watchlists don't exist yet; use a token id or favorites".to_string())
+        } else if is_valid_token_id(scope) {
+            Ok(AlertScope::Token(scope.to_string()))
+        } else {
+            Err("must be a token id or favorites".to_string())
+        }
+    }
+}
+
+impl std::fmt::Display for AlertScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AlertScope::Token(token_id) => f.write_str(token_id),
+            AlertScope::Favorites => f.write_str("favorites"),
+        }
+    }
+}
+
+impl Serialize for AlertScope {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for AlertScope {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+// Body of POST /api/alerts
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AlertRequest {
+    pub condition: AlertCondition,
+    // A token id or `favorites`
+    pub scope: String,
+}
+
+impl AlertRequest {
+    // Every problem is reported against the field it concerns
+    pub fn validated(self) -> Result<(AlertCondition, AlertScope), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        let AlertCondition::PercentMove { percent, .. } = self.condition;
+        if !(MIN_ALERT_PERCENT..=MAX_ALERT_PERCENT).contains(&percent) {
+            errors.push(FieldError::new(
+                "condition.percent",
+                format!("must be between {} and {}", MIN_ALERT_PERCENT, MAX_ALERT_PERCENT),
+            ));
+        }
+        let scope = match self.scope.trim().parse::<AlertScope>() {
+            Ok(scope) => Some(scope),
+            Err(message) => {
+                errors.push(FieldError::new("scope", message));
+                None
+            }
+        };
+
+        match scope {
+            Some(scope) if errors.is_empty() => Ok((self.condition, scope)),
+            _ => Err(errors),
+        }
+    }
+}
+
+// Entry in the alerts collection
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StoredAlert {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub condition: AlertCondition,
+    pub scope: AlertScope,
+    pub created_at: DateTime<Utc>,
+}
+
+// An alert as /api/alerts lists it, with the id the other endpoints take
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+pub struct Alert {
+    pub id: String,
+    pub condition: AlertCondition,
+    #[schema(value_type = String)]
+    pub scope: AlertScope,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<&StoredAlert> for Alert {
+    fn from(stored: &StoredAlert) -> Self {
+        Self {
+            id: stored.id.map(|id| id.to_hex()).unwrap_or_default(),
+            condition: stored.condition,
+            scope: stored.scope.clone(),
+            created_at: stored.created_at,
+        }
+    }
+}
+
+// Entry in the alert_triggers collection: an alert firing for the token that moved
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+pub struct AlertTrigger {
+    pub alert_id: String,
+    pub token_id: String,
+    pub change_percentage: f64,
+    pub at: DateTime<Utc>,
+}
+
+// Entry in the user_favorites collection, one per (user, token)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UserFavorite {
+    pub user_id: ObjectId,
+    pub token_id: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// Returned once by POST /api/users; the key can't be recovered afterwards
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct NewUser {
+    pub user_id: String,
+    pub api_key: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TradeSide {
+    Buy,
+    Sell,
+}
+
+// One buy or sell; body of POST and PUT /api/portfolio/transactions. `fee` is in USD:
+// it adds to the cost of a buy and comes off the proceeds of a sell.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+pub struct Transaction {
+    pub token_id: String,
+    pub side: TradeSide,
+    pub quantity: f64,
+    pub price_per_unit: f64,
+    #[serde(default)]
+    pub fee: f64,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl Transaction {
+    pub fn validate(&self) -> Result<(), String> {
+        if !is_valid_token_id(&self.token_id) {
+            return Err("Invalid token id".to_string());
+        }
+        if !self.quantity.is_finite() || self.quantity <= 0.0 {
+            return Err("quantity must be a positive number".to_string());
+        }
+        if !self.price_per_unit.is_finite() || self.price_per_unit < 0.0 {
+            return Err("price_per_unit must be a non-negative number".to_string());
+        }
+        if !self.fee.is_finite() || self.fee < 0.0 {
+            return Err("fee must be a non-negative number".to_string());
+        }
+        Ok(())
+    }
+}
+
+// Entry in the portfolio_transactions collection
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StoredTransaction {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub user_id: ObjectId,
+    pub token_id: String,
+    pub side: TradeSide,
+    pub quantity: f64,
+    pub price_per_unit: f64,
+    pub fee: f64,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl StoredTransaction {
+    pub fn new(user_id: ObjectId, transaction: Transaction) -> Self {
+        Self {
+            id: None,
+            user_id,
+            token_id: transaction.token_id,
+            side: transaction.side,
+            quantity: transaction.quantity,
+            price_per_unit: transaction.price_per_unit,
+            fee: transaction.fee,
+            timestamp: transaction.timestamp,
+        }
+    }
+
+    pub fn transaction(&self) -> Transaction {
+        Transaction {
+            token_id: self.token_id.clone(),
+            side: self.side,
+            quantity: self.quantity,
+            price_per_unit: self.price_per_unit,
+            fee: self.fee,
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+// A transaction as GET /api/portfolio/transactions lists it, with the id the other
+// endpoints take
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+pub struct TransactionEntry {
+    pub id: String,
+    pub token_id: String,
+    pub side: TradeSide,
+    pub quantity: f64,
+    pub price_per_unit: f64,
+    pub fee: f64,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl From<&StoredTransaction> for TransactionEntry {
+    fn from(stored: &StoredTransaction) -> Self {
+        Self {
+            id: stored.id.map(|id| id.to_hex()).unwrap_or_default(),
+            token_id: stored.token_id.clone(),
+            side: stored.side,
+            quantity: stored.quantity,
+            price_per_unit: stored.price_per_unit,
+            fee: stored.fee,
+            timestamp: stored.timestamp,
+        }
+    }
+}
+
+// One token in GET /api/portfolio/summary. The market figures are null while the token
+// has no cached price.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+pub struct PortfolioPosition {
+    pub token_id: String,
+    pub quantity: f64,
+    // Of the lots still held, fees included; null once everything is sold
+    pub average_cost: Option<f64>,
+    pub cost_basis: f64,
+    pub realized_pnl: f64,
+    pub current_price: Option<f64>,
+    pub market_value: Option<f64>,
+    pub unrealized_pnl: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+pub struct PortfolioSummary {
+    pub positions: Vec<PortfolioPosition>,
+    pub total_cost_basis: f64,
+    // Over the positions with a cached price
+    pub total_market_value: f64,
+    pub total_realized_pnl: f64,
+    pub total_unrealized_pnl: f64,
+}
+
+// Binance /api/v3/ticker/24hr entry; Binance sends the numbers as strings
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BinanceTicker {
+    pub symbol: String,
+    pub last_price: String,
+    pub price_change: String,
+    pub price_change_percent: String,
+    pub high_price: String,
+    pub low_price: String,
+    pub quote_volume: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct FavoriteRequest {
+    pub token_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BulkFavoriteRequest {
+    pub token_ids: Vec<String>,
+    pub favorite: bool,
+}
+
+pub const MAX_FAVORITE_TAGS: usize = 20;
+pub const MAX_TAG_LENGTH: usize = 32;
+pub const MAX_NOTE_LENGTH: usize = 1000;
+
+// Body of PUT /api/favorites/{id}/meta. Replaces both fields: omitted tags clear
+// the tags, an omitted or null note clears the note.
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct FavoriteMeta {
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub note: Option<String>,
+}
+
+impl FavoriteMeta {
+    // Tags are trimmed, lowercased and deduplicated so `?tag=` matching is predictable
+    pub fn normalized(self) -> Result<Self, String> {
+        let mut tags: Vec<String> = Vec::new();
+        for tag in self.tags {
+            let tag = tag.trim().to_lowercase();
+            if tag.is_empty() || tags.contains(&tag) {
+                continue;
+            }
+            if tag.chars().count() > MAX_TAG_LENGTH {
+                return Err(format!("tags must be at most {} characters", MAX_TAG_LENGTH));
+            }
+            tags.push(tag);
+        }
+        if tags.len() > MAX_FAVORITE_TAGS {
+            return Err(format!("at most {} tags are allowed", MAX_FAVORITE_TAGS));
+        }
+
+        let note = self.note.map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+        if note.as_ref().is_some_and(|n| n.chars().count() > MAX_NOTE_LENGTH) {
+            return Err(format!("note must be at most {} characters", MAX_NOTE_LENGTH));
+        }
+
+        Ok(Self { tags, note })
+    }
+}
+
+pub const MAX_ANNOTATION_TAGS: usize = 20;
+pub const MAX_ANNOTATION_TAG_LENGTH: usize = 30;
+pub const MAX_ANNOTATION_NOTE_LENGTH: usize = 2000;
+
+// Entry in the annotations collection, one per token. Kept apart from the tokens
+// collection so refreshes never touch it, and for any token rather than only favorites.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct TokenAnnotation {
+    pub token_id: String,
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// Body of PUT /api/tokens/{id}/annotation. Replaces the whole annotation, so omitted
+// tags or note clear them.
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct AnnotationRequest {
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub note: Option<String>,
+}
+
+impl AnnotationRequest {
+    // Tags trimmed, lowercased and deduplicated like favorites' tags; every problem is
+    // reported against the field it concerns
+    pub fn validated(self) -> Result<(Vec<String>, Option<String>), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        let mut tags: Vec<String> = Vec::new();
+        for (i, tag) in self.tags.iter().enumerate() {
+            let tag = tag.trim().to_lowercase();
+            if tag.chars().count() > MAX_ANNOTATION_TAG_LENGTH {
+                errors.push(FieldError::new(
+                    format!("tags[{}]", i),
+                    format!("must be at most {} characters", MAX_ANNOTATION_TAG_LENGTH),
+                ));
+            } else if !tag.is_empty() && !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        if tags.len() > MAX_ANNOTATION_TAGS {
+            errors.push(FieldError::new("tags", format!("at most {} tags are allowed", MAX_ANNOTATION_TAGS)));
+        }
+
+        let note = self.note.map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+        if note.as_ref().is_some_and(|n| n.chars().count() > MAX_ANNOTATION_NOTE_LENGTH) {
+            errors.push(FieldError::new("note", format!("must be at most {} characters", MAX_ANNOTATION_NOTE_LENGTH)));
+        }
+
+        if errors.is_empty() {
+            Ok((tags, note))
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+// Query string for /api/tokens on top of the shared list parameters
+#[derive(Debug, Deserialize)]
+pub struct TokensQuery {
+    pub category: Option<String>,
+    pub exclude_stablecoins: Option<bool>,
+    // Keyset pagination instead of page; empty for the first page
+    pub cursor: Option<String>,
+    pub include_hidden: Option<bool>,
+    // Include each token's 7-day sparkline
+    pub sparkline: Option<bool>,
+    // Only tokens annotated with this tag
+    pub tag: Option<String>,
+    // up, down or all, by the sign of the 24h change
+    pub direction: Option<String>,
+    // Skip the cache freshness check and ask upstream, like Cache-Control: no-cache
+    pub fresh: Option<bool>,
+    // Comma-separated token fields to return; token_id always comes along
+    pub fields: Option<String>,
+}
+
+// Query string for /api/tokens/{id}
+#[derive(Debug, Deserialize, Default)]
+pub struct TokenQuery {
+    pub fresh: Option<bool>,
+    pub fields: Option<String>,
+}
+
+// Query string for /api/stats
+#[derive(Debug, Deserialize)]
+pub struct StatsQuery {
+    pub exclude_stablecoins: Option<bool>,
+}
+
+// Query string for /api/favorites on top of the shared list parameters
+#[derive(Debug, Deserialize)]
+pub struct FavoritesQuery {
+    pub tag: Option<String>,
+}
+
+// Result of POST /api/import/tokens. Lines are numbered from 1; blank lines are skipped.
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct ImportSummary {
+    pub inserted: u64,
+    pub updated: u64,
+    pub failed: Vec<ImportFailure>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ImportFailure {
+    pub line: usize,
+    pub error: String,
+}
+
+// Outcome of a bulk update; tokens already in the requested state match but aren't modified
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct BulkFavoriteResponse {
+    pub matched: u64,
+    pub modified: u64,
+}
+
+// Body of GET /api/favorites/export: the shared favorites, by id
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FavoritesExport {
+    pub exported_at: DateTime<Utc>,
+    pub token_ids: Vec<String>,
+}
+
+// How POST /api/favorites/import treats tokens the document doesn't list
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FavoritesImportMode {
+    // Leave them as they are
+    #[default]
+    Merge,
+    // Unstar them
+    Replace,
+}
+
+// Body of POST /api/favorites/import: an export, plus the mode. `exported_at` is
+// accepted but not needed.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct FavoritesImport {
+    pub token_ids: Vec<String>,
+    #[serde(default)]
+    pub mode: FavoritesImportMode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exported_at: Option<DateTime<Utc>>,
+}
+
+// Result of POST /api/favorites/import. Only tokens whose flag actually changed are
+// counted, so re-importing the same document reports zeros.
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct FavoritesImportSummary {
+    pub mode: FavoritesImportMode,
+    pub favorited: u64,
+    pub unfavorited: u64,
+    // Listed ids no stored token has; skipped
+    pub unknown: Vec<String>,
+}
+
+// Sort and pagination query string shared by /api/tokens and /api/favorites
+#[derive(Debug, Default, Deserialize)]
+pub struct ListQuery {
+    pub sort_by: Option<String>,
+    pub order: Option<String>,
+    pub page: Option<u64>,
+    pub per_page: Option<u64>,
+}
+
+// Query string for /api/gainers and /api/losers
+#[derive(Debug, Deserialize)]
+pub struct MoversQuery {
+    pub limit: Option<u64>,
+    pub exclude_stablecoins: Option<bool>,
+    pub window: Option<String>,
+}
+
+// Query string for /api/screener; every bound is optional and inclusive
+#[derive(Debug, Deserialize, Default)]
+pub struct ScreenerQuery {
+    pub min_volume_mcap_ratio: Option<f64>,
+    pub max_volume_mcap_ratio: Option<f64>,
+    pub min_market_cap: Option<f64>,
+    pub max_market_cap: Option<f64>,
+    pub min_change_24h: Option<f64>,
+    pub max_change_24h: Option<f64>,
+    pub sort: Option<String>,
+    pub limit: Option<u64>,
+    pub exclude_stablecoins: Option<bool>,
+}
+
+pub const DEFAULT_SCREENER_LIMIT: u64 = 50;
+pub const MAX_SCREENER_LIMIT: u64 = 250;
+
+// Fields /api/screener can rank by, highest first
+pub const SCREENER_SORTS: &[&str] = &["volume_mcap_ratio", "volume_24h", "market_cap", "price_change_percentage_24h"];
+
+// A screener query checked and ready to become an aggregation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Screener {
+    // (stored field, min, max), for the bounds given
+    pub bounds: Vec<(&'static str, Option<f64>, Option<f64>)>,
+    pub sort: &'static str,
+    pub limit: u64,
+}
+
+impl ScreenerQuery {
+    pub fn validated(&self) -> Result<Screener, String> {
+        let ranges = [
+            ("volume_mcap_ratio", "volume_mcap_ratio", self.min_volume_mcap_ratio, self.max_volume_mcap_ratio),
+            ("market_cap", "market_cap", self.min_market_cap, self.max_market_cap),
+            ("change_24h", "price_change_percentage_24h", self.min_change_24h, self.max_change_24h),
+        ];
+        let mut bounds = Vec::new();
+        for (name, field, min, max) in ranges {
+            if min.into_iter().chain(max).any(|bound| !bound.is_finite()) {
+                return Err(format!("min_{0} and max_{0} must be numbers", name));
+            }
+            if let (Some(min), Some(max)) = (min, max) {
+                if min > max {
+                    return Err(format!("min_{0} ({1}) is above max_{0} ({2})", name, min, max));
+                }
+            }
+            if min.is_some() || max.is_some() {
+                bounds.push((field, min, max));
+            }
+        }
+
+        let sort = match self.sort.as_deref() {
+            None => SCREENER_SORTS[0],
+            Some(sort) => SCREENER_SORTS
+                .iter()
+                .copied()
+                .find(|s| *s == sort)
+                .ok_or_else(|| format!("sort must be one of {}", SCREENER_SORTS.join(", ")))?,
+        };
+        let limit = self.limit.unwrap_or(DEFAULT_SCREENER_LIMIT);
+        if !(1..=MAX_SCREENER_LIMIT).contains(&limit) {
+            return Err(format!("limit must be between 1 and {}", MAX_SCREENER_LIMIT));
+        }
+        Ok(Screener { bounds, sort, limit })
+    }
+}
+
+// /api/screener result: the token with its 24h volume over market cap, None when the
+// market cap is zero or unknown
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ScreenedToken {
+    #[serde(flatten)]
+    pub token: CryptoToken,
+    pub volume_mcap_ratio: Option<f64>,
+}
+
+// Query string for /api/near_ath
+#[derive(Debug, Deserialize)]
+pub struct NearAthQuery {
+    pub threshold: Option<f64>,
+}
+
+// Change window /api/gainers and /api/losers rank by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChangeWindow {
+    #[default]
+    Day,
+    Week,
+    Month,
+}
+
+impl ChangeWindow {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChangeWindow::Day => "24h",
+            ChangeWindow::Week => "7d",
+            ChangeWindow::Month => "30d",
+        }
+    }
+
+    // The stored CryptoToken field holding the change over this window
+    pub fn field(self) -> &'static str {
+        match self {
+            ChangeWindow::Day => "price_change_percentage_24h",
+            ChangeWindow::Week => "price_change_percentage_7d",
+            ChangeWindow::Month => "price_change_percentage_30d",
+        }
+    }
+}
+
+impl std::str::FromStr for ChangeWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "24h" => Ok(ChangeWindow::Day),
+            "7d" => Ok(ChangeWindow::Week),
+            "30d" => Ok(ChangeWindow::Month),
+            _ => Err("window must be 24h, 7d or 30d".to_string()),
+        }
+    }
+}
+
+// Query string for /api/correlation
+#[derive(Debug, Deserialize)]
+pub struct CorrelationQuery {
+    pub tokens: Option<String>,
+    pub days: Option<u32>,
+}
+
+// Query string for /api/tokens/{id}/change
+#[derive(Debug, Deserialize)]
+pub struct HistoricalChangeQuery {
+    pub days: Option<u32>,
+}
+
+// GET /api/tokens/{id}/change: the move between two cached history points
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct HistoricalChange {
+    pub token_id: String,
+    // Window asked for, and the span actually covered; less when history is shorter
+    pub days: u32,
+    pub coverage_days: f64,
+    pub start_price: f64,
+    pub end_price: f64,
+    pub start_at: DateTime<Utc>,
+    pub end_at: DateTime<Utc>,
+    pub change: f64,
+    pub change_percentage: f64,
+}
+
+// Query string for /api/tokens/{id}/price_at
+#[derive(Debug, Deserialize)]
+pub struct PriceAtQuery {
+    // Unix milliseconds
+    pub timestamp: i64,
+}
+
+// GET /api/tokens/{id}/price_at: the cached history point nearest a moment
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct PriceAt {
+    pub token_id: String,
+    pub requested_at: DateTime<Utc>,
+    pub price: f64,
+    // When that price was recorded
+    pub at: DateTime<Utc>,
+    // `at` minus `requested_at`, negative when the point is earlier
+    pub delta_ms: i64,
+}
+
+// A requested token left out of the correlation matrix, and why
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+pub struct SkippedToken {
+    pub token_id: String,
+    pub reason: String,
+}
+
+// Pearson correlation of daily returns. `matrix[i][j]` pairs `tokens[i]` with
+// `tokens[j]`; an entry is null when either series is flat.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CorrelationMatrix {
+    pub days: u32,
+    pub tokens: Vec<String>,
+    // Daily closes the tokens had in common
+    pub points: usize,
+    #[schema(value_type = Vec<Vec<Option<f64>>>)]
+    pub matrix: Vec<Vec<Option<f64>>>,
+    pub skipped: Vec<SkippedToken>,
+}
+
+// Query string for /api/dominance
+#[derive(Debug, Deserialize)]
+pub struct DominanceQuery {
+    pub days: Option<u32>,
+}
+
+// Where a dominance series came from: cached market-cap history, or the current token
+// list when too little of the top tokens' history is cached
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DominanceSource {
+    History,
+    Snapshot,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct DominancePoint {
+    // Unix milliseconds
+    pub timestamp: i64,
+    // Percent of the top tokens' summed market cap
+    pub btc_dominance: f64,
+}
+
+// GET /api/dominance
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct DominanceHistory {
+    pub days: u32,
+    pub source: DominanceSource,
+    // The top tokens by current market cap the dominance is taken over
+    pub tokens: Vec<String>,
+    pub series: Vec<DominancePoint>,
+}
+
+// Query string for /api/tokens/batch: comma-separated token ids
+#[derive(Debug, Deserialize)]
+pub struct TokenBatchQuery {
+    pub ids: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct CoinGeckoMarket {
-    pub id: String,
-    pub symbol: String,
-    pub name: String,
-    pub image: String,
-    pub current_price: f64,
-    pub market_cap: f64,
-    pub market_cap_rank: Option<u32>,
-    pub fully_diluted_valuation: Option<f64>,
-    pub total_volume: f64,
-    pub high_24h: Option<f64>,
-    pub low_24h: Option<f64>,
-    pub price_change_24h: Option<f64>,
-    pub price_change_percentage_24h: Option<f64>,
-    pub market_cap_change_24h: Option<f64>,
-    pub market_cap_change_percentage_24h: Option<f64>,
-    pub circulating_supply: Option<f64>,
-    pub total_supply: Option<f64>,
-    pub max_supply: Option<f64>,
-    pub ath: Option<f64>,
-    pub ath_change_percentage: Option<f64>,
-    pub ath_date: Option<String>,
-    pub atl: Option<f64>,
-    pub atl_change_percentage: Option<f64>,
-    pub atl_date: Option<String>,
-    pub last_updated: String,
+// GET /api/tokens/batch: the tokens found, in the order asked for, and the ids that
+// didn't resolve, i.e. malformed ones and ones CoinGecko doesn't list (delisted or
+// renamed coins), which it leaves out without saying so
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TokenBatch {
+    pub tokens: Vec<CryptoToken>,
+    pub unresolved_ids: Vec<String>,
 }
 
+// Query string for /api/convert; amount defaults to 1
+#[derive(Debug, Deserialize)]
+pub struct ConvertQuery {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub amount: Option<f64>,
+}
 
+// The pseudo token id /api/convert accepts as `to` for a plain USD value
+pub const USD: &str = "usd";
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct FavoriteRequest {
-    pub token_id: String,
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct ConversionResult {
+    pub from: String,
+    pub to: String,
+    pub amount: f64,
+    pub result: f64,
+    // Units of `to` per unit of `from`
+    pub rate: f64,
+    // USD unit prices used, and when each was last updated so clients can show staleness
+    pub from_price: f64,
+    pub to_price: f64,
+    pub from_updated_at: DateTime<Utc>,
+    // Absent when converting to usd
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to_updated_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+// Longest range /api/history ever serves, which is as far back as CoinGecko's public
+// API goes; MAX_HISTORY_DAYS in the config can lower it
+pub const MAX_HISTORY_DAYS: u32 = 365;
+
+// The {days} segment of /api/history: 1..=365 or the literal `max` (full history)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum HistoryDays {
+    Days(u32),
+    Max,
+}
+
+impl HistoryDays {
+    // Days to size the cache window with; `max` counts as the longest range
+    pub fn span(self) -> u32 {
+        match self {
+            HistoryDays::Days(days) => days,
+            HistoryDays::Max => u32::MAX,
+        }
+    }
+}
+
+impl std::str::FromStr for HistoryDays {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "max" {
+            return Ok(HistoryDays::Max);
+        }
+        match s.parse::<u32>() {
+            Ok(days) if (1..=MAX_HISTORY_DAYS).contains(&days) => Ok(HistoryDays::Days(days)),
+            _ => Err(format!("days must be a positive integer up to {} or 'max'", MAX_HISTORY_DAYS)),
+        }
+    }
+}
+
+// Formats as CoinGecko's `days` parameter
+impl std::fmt::Display for HistoryDays {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HistoryDays::Days(days) => write!(f, "{}", days),
+            HistoryDays::Max => f.write_str("max"),
+        }
+    }
+}
+
+impl From<u32> for HistoryDays {
+    fn from(days: u32) -> Self {
+        HistoryDays::Days(days)
+    }
+}
+
+impl From<HistoryDays> for mongodb::bson::Bson {
+    fn from(days: HistoryDays) -> Self {
+        match days {
+            HistoryDays::Days(days) => mongodb::bson::Bson::Int64(days.into()),
+            HistoryDays::Max => mongodb::bson::Bson::String("max".to_string()),
+        }
+    }
+}
+
+// Granularity of a history chart. CoinGecko picks it from the range unless told:
+// up to 90 days is hourly (5-minutely for a single day, which counts as hourly here
+// since it rolls up to daily the same way), anything longer daily.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryInterval {
+    Hourly,
+    Daily,
+}
+
+impl HistoryInterval {
+    // What CoinGecko returns for `days` when no interval is asked for
+    pub fn auto(days: HistoryDays) -> Self {
+        if days.span() <= 90 {
+            HistoryInterval::Hourly
+        } else {
+            HistoryInterval::Daily
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HistoryInterval::Hourly => "hourly",
+            HistoryInterval::Daily => "daily",
+        }
+    }
+}
+
+impl std::str::FromStr for HistoryInterval {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hourly" => Ok(HistoryInterval::Hourly),
+            "daily" => Ok(HistoryInterval::Daily),
+            _ => Err("interval must be hourly or daily".to_string()),
+        }
+    }
+}
+
+// Formats as CoinGecko's `interval` parameter
+impl std::fmt::Display for HistoryInterval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// One CoinGecko chart merged into a token's stored history: the range and granularity
+// asked for, and when. What the stored series can answer is worked out from these.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryFetch {
+    pub days: HistoryDays,
+    pub interval: HistoryInterval,
+    pub fetched_at: DateTime<Utc>,
+}
+
+// CoinGecko ids are lowercase ascii, digits and dashes
+pub fn is_valid_token_id(token_id: &str) -> bool {
+    (1..=100).contains(&token_id.len())
+        && token_id.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+}
+
+// Category ids are the slugs from CoinGecko's /coins/categories/list, e.g.
+// `decentralized-finance-defi`, `layer-1`, `meme-token` or `stablecoins`, not the
+// display names. Same character set as token ids.
+pub fn is_valid_category_id(category_id: &str) -> bool {
+    is_valid_token_id(category_id)
+}
+
+// One entry of GET /api/categories
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+pub struct Category {
+    pub category_id: String,
+    pub name: String,
+}
+
+// Query string accepted by /api/history/{id}/{days}
+#[derive(Debug, Deserialize)]
+pub struct HistoryQuery {
+    pub points: Option<usize>,
+    pub currency: Option<String>,
+    pub interval: Option<String>,
+}
+
+// Query string accepted by /api/tokens/{id}/history
+#[derive(Debug, Deserialize)]
+pub struct StoredHistoryQuery {
+    pub interval: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct PriceHistory {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
     pub id: Option<ObjectId>,
     pub token_id: String,
     pub symbol: String,
+    #[schema(value_type = Vec<Vec<f64>>)]
     pub prices: Vec<(i64, f64)>,
+    #[schema(value_type = Vec<Vec<f64>>)]
     pub market_caps: Vec<(i64, f64)>,
+    #[schema(value_type = Vec<Vec<f64>>)]
     pub total_volumes: Vec<(i64, f64)>,
+    // Granularity actually stored; absent on charts cached before it was recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval: Option<HistoryInterval>,
     pub timestamp: DateTime<Utc>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
 pub struct CoinGeckoHistoricalData {
     pub prices: Vec<Vec<f64>>,
     pub market_caps: Vec<Vec<f64>>,
     pub total_volumes: Vec<Vec<f64>>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct TokenStats {
     pub total_tokens: usize,
     pub total_market_cap: f64,
@@ -90,9 +1692,65 @@ pub struct TokenStats {
     pub avg_price_change_24h: f64,
     pub biggest_gainer: Option<CryptoToken>,
     pub biggest_loser: Option<CryptoToken>,
+    // Tokens left out of every figure above for a NaN or infinite market cap, volume
+    // or 24h change; total_tokens doesn't count them
+    pub excluded_tokens: usize,
+    // Spread of the 24h change, which one outlier can't drag like the average; absent
+    // with no tokens to go by
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub median_price_change_24h: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p10_price_change_24h: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p90_price_change_24h: Option<f64>,
+    // Sum of change x market cap over the total market cap; absent when that is zero
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub market_cap_weighted_change_24h: Option<f64>,
+    // Tokens up, down and exactly unchanged over 24h
+    pub gainers: usize,
+    pub losers: usize,
+    pub flat: usize,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+impl TokenStats {
+    pub fn from_tokens(tokens: &[CryptoToken]) -> Self {
+        let (usable, excluded): (Vec<&CryptoToken>, Vec<&CryptoToken>) = tokens.iter().partition(|t| {
+            t.market_cap.is_finite() && t.volume_24h.is_finite() && t.price_change_percentage_24h.is_finite()
+        });
+
+        let avg_price_change_24h = if usable.is_empty() {
+            0.0
+        } else {
+            usable.iter().map(|t| t.price_change_percentage_24h).sum::<f64>() / usable.len() as f64
+        };
+        // Ties go to the first token in list order either way
+        let by_change = |a: &&&CryptoToken, b: &&&CryptoToken| {
+            crate::ordering::cmp_f64(a.price_change_percentage_24h, b.price_change_percentage_24h)
+        };
+        let mut changes: Vec<f64> = usable.iter().map(|t| t.price_change_percentage_24h).collect();
+        changes.sort_by(|a, b| crate::ordering::cmp_f64(*a, *b));
+        let total_market_cap: f64 = usable.iter().map(|t| t.market_cap).sum();
+        let weighted_change = usable.iter().map(|t| t.price_change_percentage_24h * t.market_cap).sum::<f64>() / total_market_cap;
+        Self {
+            total_tokens: usable.len(),
+            total_market_cap,
+            total_volume_24h: usable.iter().map(|t| t.volume_24h).sum(),
+            avg_price_change_24h,
+            biggest_gainer: usable.iter().rev().max_by(by_change).map(|t| (*t).clone()),
+            biggest_loser: usable.iter().min_by(by_change).map(|t| (*t).clone()),
+            excluded_tokens: excluded.len(),
+            median_price_change_24h: crate::analytics::percentile(&changes, 50.0),
+            p10_price_change_24h: crate::analytics::percentile(&changes, 10.0),
+            p90_price_change_24h: crate::analytics::percentile(&changes, 90.0),
+            market_cap_weighted_change_24h: weighted_change.is_finite().then_some(weighted_change),
+            gainers: changes.iter().filter(|c| **c > 0.0).count(),
+            losers: changes.iter().filter(|c| **c < 0.0).count(),
+            flat: changes.iter().filter(|c| **c == 0.0).count(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct MarketStats {
     pub total_market_cap: f64,
     pub total_volume_24h: f64,
@@ -101,7 +1759,62 @@ pub struct MarketStats {
     pub top_loser: Option<TokenChange>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+impl MarketStats {
+    // The headline figures of `stats`, computed over `tokens`, with bitcoin's share of the
+    // total market cap in percent (0 when bitcoin isn't among them)
+    pub fn from_stats(stats: &TokenStats, tokens: &[CryptoToken]) -> Self {
+        let bitcoin_market_cap = tokens
+            .iter()
+            .find(|t| t.token_id == "bitcoin")
+            .map(|t| t.market_cap)
+            .filter(|cap| cap.is_finite())
+            .unwrap_or_default();
+        let dominance = bitcoin_market_cap / stats.total_market_cap * 100.0;
+        Self {
+            total_market_cap: stats.total_market_cap,
+            total_volume_24h: stats.total_volume_24h,
+            bitcoin_dominance: if dominance.is_finite() { dominance } else { 0.0 },
+            top_gainer: stats.biggest_gainer.as_ref().map(TokenChange::from),
+            top_loser: stats.biggest_loser.as_ref().map(TokenChange::from),
+        }
+    }
+}
+
+// GET /api/overview: the dashboard's panels, all computed from the same read of the cache
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Overview {
+    pub stats: TokenStats,
+    // report::TOP_MOVERS of each, by 24h change
+    pub top_gainers: Vec<TokenChange>,
+    pub top_losers: Vec<TokenChange>,
+    pub market: MarketStats,
+}
+
+// GET /api/report/daily: a digest of the stored market, built without upstream calls
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+pub struct DailyReport {
+    pub generated_at: DateTime<Utc>,
+    pub total_market_cap: f64,
+    // The same tokens' total 24 hours earlier, going by their 24h price change
+    pub previous_total_market_cap: f64,
+    // Absent when there was nothing to compare against
+    pub market_cap_change_percentage: Option<f64>,
+    pub top_gainers: Vec<TokenChange>,
+    pub top_losers: Vec<TokenChange>,
+    // Favorites whose price moved more than 5% either way over 24h, biggest move first
+    pub favorite_moves: Vec<TokenChange>,
+    // Events recorded in the last 24 hours, newest first
+    pub events: Vec<Event>,
+}
+
+// Query string for /api/report/daily
+#[derive(Debug, Deserialize, Default)]
+pub struct ReportQuery {
+    // json (the default) or markdown
+    pub format: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
 pub struct TokenChange {
     pub token_id: String,
     pub name: String,
@@ -110,26 +1823,220 @@ pub struct TokenChange {
     pub current_price: f64,
 }
 
+impl From<&CryptoToken> for TokenChange {
+    fn from(token: &CryptoToken) -> Self {
+        Self {
+            token_id: token.token_id.clone(),
+            name: token.name.clone(),
+            symbol: token.symbol.clone(),
+            change_percentage: token.price_change_percentage_24h,
+            current_price: token.current_price,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PriceHistoryEntry {
     pub timestamp: i64,
     pub price: f64,
 }
 
+// Internal cache and rate-limit state, served only when DEBUG_ENDPOINTS is on
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct CacheDebugInfo {
+    pub cached_tokens: usize,
+    pub oldest_last_updated: Option<DateTime<Utc>>,
+    pub newest_last_updated: Option<DateTime<Utc>>,
+    pub rate_limited_until: Option<DateTime<Utc>>,
+    pub seconds_until_next_call: u64,
+}
+
+// GET /health/ready: ready only when every check passes
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Readiness {
+    pub ready: bool,
+    pub mongodb: bool,
+    // At least one token stored, so a new instance isn't routed traffic before its first fetch
+    pub tokens_cached: bool,
+}
+
+// GET /api/cache/status: how much is cached and how old it is
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct CacheStatus {
+    pub tokens: CollectionSummary,
+    pub price_history: HistoryCacheStatus,
+    pub rate_limit: RateLimitStatus,
+}
+
+// Documents in a cache collection and the range of their timestamps (`last_updated`
+// for tokens, the fetch time for history)
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, ToSchema)]
+pub struct CollectionSummary {
+    pub count: u64,
+    pub oldest: Option<DateTime<Utc>>,
+    pub newest: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, ToSchema)]
+pub struct HistoryCacheStatus {
+    #[serde(flatten)]
+    pub summary: CollectionSummary,
+    // One per cached (token_id, days) chart, by token
+    pub entries: Vec<HistoryCacheEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+pub struct HistoryCacheEntry {
+    pub token_id: String,
+    // As in the URL: a number of days or `max`; absent on charts cached before it was stored
+    pub days: Option<String>,
+    // hourly or daily; absent on charts cached before it was stored
+    pub interval: Option<String>,
+    pub fetched_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+pub struct RateLimitStatus {
+    // Whether a 429 backoff is in effect
+    pub backing_off: bool,
+    pub until: Option<DateTime<Utc>>,
+    pub seconds_until_next_call: u64,
+}
+
+// Body returned by every endpoint on failure
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct ErrorResponse {
+    pub error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
+    // Per-field problems when a request body fails validation
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldError>,
+}
+
+impl ErrorResponse {
+    pub fn new(error: impl Into<String>) -> Self {
+        Self {
+            error: error.into(),
+            retry_after: None,
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn with_retry_after(mut self, seconds: u64) -> Self {
+        self.retry_after = Some(seconds);
+        self
+    }
+
+    pub fn with_fields(mut self, fields: Vec<FieldError>) -> Self {
+        self.fields = fields;
+        self
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+pub struct FieldError {
+    // `tags`, `tags[3]`, `note`, ...
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self { field: field.into(), message: message.into() }
+    }
+}
+
+// Envelope every /api/v2 list endpoint answers with: `data` and `meta` on success,
+// `data: null` with `error` set on failure
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+pub struct ApiResponse<T> {
+    pub data: Option<T>,
+    pub meta: Option<ResponseMeta>,
+    pub error: Option<String>,
+}
+
+impl<T> ApiResponse<T> {
+    pub fn ok(data: T, meta: ResponseMeta) -> Self {
+        Self { data: Some(data), meta: Some(meta), error: None }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self { data: None, meta: None, error: Some(message.into()) }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, ToSchema)]
+pub struct ResponseMeta {
+    // Whether the data is older than the token cache TTL
+    pub stale: bool,
+    // Seconds since the newest item in `data` was fetched; 0 for a live upstream answer
+    pub cache_age_seconds: u64,
+    // Items in `data`: the array length, or 1 for a single object
+    pub count: usize,
+    // Pass as `cursor` for the next page of a cursor walk; absent on the last page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+// Opt-in envelope for /api, asked for with `X-Response-Envelope: true` or `?envelope=true`:
+// `data` and `meta` on success, only `error` on failure
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Envelope {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<EnvelopeMeta>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<EnvelopeError>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct EnvelopeMeta {
+    pub generated_at: DateTime<Utc>,
+    // Null where the endpoint doesn't say where its data came from
+    pub cache: Option<CacheOutcome>,
+    // Null unless the request paged with page, per_page or cursor
+    pub pagination: Option<EnvelopePagination>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheOutcome {
+    Hit,
+    Miss,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct EnvelopePagination {
+    // Absent on a cursor walk
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page: Option<u64>,
+    pub per_page: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct EnvelopeError {
+    // The status's reason phrase in snake_case, e.g. `not_found`
+    pub code: String,
+    pub message: String,
+    pub retry_after: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldError>,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use chrono::Utc;
 
-    #[test]
-    fn test_crypto_token_creation() {
-        let token = CryptoToken {
-            id: None,
-            token_id: "bitcoin".to_string(),
+    fn sample_token() -> CryptoToken {
+        CryptoToken {
             symbol: "btc".to_string(),
             name: "Bitcoin".to_string(),
-            current_price: 50000.0,
-            market_cap: 1000000000000.0,
             volume_24h: 50000000000.0,
             price_change_24h: 1000.0,
             price_change_percentage_24h: 2.5,
@@ -142,9 +2049,13 @@ mod tests {
             atl: Some(67.81),
             atl_change_percentage: Some(73600.0),
             image: Some("https://example.com/bitcoin.png".to_string()),
-            last_updated: Utc::now(),
-            is_favorite: false,
-        };
+            ..test_token("bitcoin", 50000.0, 1000000000000.0)
+        }
+    }
+
+    #[test]
+    fn test_crypto_token_creation() {
+        let token = sample_token();
 
         assert_eq!(token.token_id, "bitcoin");
         assert_eq!(token.current_price, 50000.0);
@@ -191,4 +2102,413 @@ mod tests {
         assert!(stats.top_gainer.as_ref().unwrap().change_percentage > 0.0);
         assert!(stats.top_loser.as_ref().unwrap().change_percentage < 0.0);
     }
+
+    #[test]
+    fn test_annotation_request_validated_per_field() {
+        let request = AnnotationRequest {
+            tags: vec![" Long-Term ".to_string(), "long-term".to_string(), "".to_string(), "avoid".to_string()],
+            note: Some("  ".to_string()),
+        };
+        assert_eq!(request.validated().unwrap(), (vec!["long-term".to_string(), "avoid".to_string()], None));
+
+        let at_limits = AnnotationRequest {
+            tags: (0..MAX_ANNOTATION_TAGS).map(|i| format!("{}{}", i, "x".repeat(MAX_ANNOTATION_TAG_LENGTH - 2))).collect(),
+            note: Some("n".repeat(MAX_ANNOTATION_NOTE_LENGTH)),
+        };
+        assert!(at_limits.validated().is_ok());
+
+        let over = AnnotationRequest {
+            tags: (0..=MAX_ANNOTATION_TAGS)
+                .map(|i| i.to_string())
+                .chain(["x".repeat(MAX_ANNOTATION_TAG_LENGTH + 1)])
+                .collect(),
+            note: Some("n".repeat(MAX_ANNOTATION_NOTE_LENGTH + 1)),
+        };
+        let fields: Vec<String> = over.validated().unwrap_err().into_iter().map(|e| e.field).collect();
+        assert_eq!(fields, vec![format!("tags[{}]", MAX_ANNOTATION_TAGS + 1), "tags".to_string(), "note".to_string()]);
+    }
+
+    #[test]
+    fn test_favorite_meta_normalized() {
+        let meta = FavoriteMeta {
+            tags: vec![" DeFi ".to_string(), "defi".to_string(), "".to_string(), "watch".to_string()],
+            note: Some("   ".to_string()),
+        }
+        .normalized()
+        .unwrap();
+
+        assert_eq!(meta.tags, vec!["defi", "watch"]);
+        assert!(meta.note.is_none());
+
+        let too_long = FavoriteMeta { tags: vec!["x".repeat(MAX_TAG_LENGTH + 1)], note: None };
+        assert!(too_long.normalized().is_err());
+
+        let too_many = FavoriteMeta { tags: (0..=MAX_FAVORITE_TAGS).map(|i| i.to_string()).collect(), note: None };
+        assert!(too_many.normalized().is_err());
+    }
+
+    #[test]
+    fn test_token_without_metadata_deserializes() {
+        let json = serde_json::to_value(CryptoToken {
+            tags: vec!["defi".to_string()],
+            ..sample_token()
+        })
+        .unwrap();
+        assert_eq!(json["tags"], serde_json::json!(["defi"]));
+        assert!(json.get("note").is_none());
+
+        let mut legacy = json.clone();
+        legacy.as_object_mut().unwrap().remove("tags");
+        let token: CryptoToken = serde_json::from_value(legacy).unwrap();
+        assert!(token.tags.is_empty() && token.note.is_none());
+    }
+
+    #[test]
+    fn test_market_numbers_read_leniently() {
+        let market: CoinGeckoMarket = serde_json::from_value(serde_json::json!({
+            "id": "bitcoin",
+            "symbol": "btc",
+            "name": "Bitcoin",
+            "current_price": "50000.5",
+            "market_cap": 1_000_000_000_000_u64,
+            "total_volume": null,
+            "high_24h": " 51000 ",
+            "low_24h": "n/a",
+            "ath": { "usd": 69000 },
+        }))
+        .unwrap();
+        assert_eq!(market.current_price, 50000.5);
+        assert_eq!(market.market_cap, 1.0e12);
+        assert_eq!(market.total_volume, 0.0);
+        assert_eq!(market.high_24h, Some(51000.0));
+        assert_eq!((market.low_24h, market.ath), (None, None));
+        assert!(market.image.is_empty());
+
+        // Without a usable price there's nothing to serve
+        for price in [serde_json::Value::Null, serde_json::json!("soon")] {
+            let market = serde_json::json!({ "id": "x", "symbol": "x", "name": "X", "current_price": price });
+            assert!(serde_json::from_value::<CoinGeckoMarket>(market).is_err());
+        }
+    }
+
+    #[test]
+    fn test_market_change_windows_are_optional() {
+        let mut market = serde_json::json!({
+            "id": "bitcoin",
+            "symbol": "btc",
+            "name": "Bitcoin",
+            "image": "https://example.com/btc.png",
+            "current_price": 50000.0,
+            "market_cap": 1.0e12,
+            "total_volume": 5.0e10,
+        });
+        let without: CoinGeckoMarket = serde_json::from_value(market.clone()).unwrap();
+        assert_eq!(without.price_change_percentage_7d_in_currency, None);
+        assert_eq!(without.price_change_percentage_1y_in_currency, None);
+
+        market["price_change_percentage_7d_in_currency"] = serde_json::json!(-3.5);
+        market["price_change_percentage_30d_in_currency"] = serde_json::json!(8.0);
+        market["price_change_percentage_1y_in_currency"] = serde_json::Value::Null;
+        let with: CoinGeckoMarket = serde_json::from_value(market).unwrap();
+        assert_eq!(with.price_change_percentage_7d_in_currency, Some(-3.5));
+        assert_eq!(with.price_change_percentage_30d_in_currency, Some(8.0));
+        assert_eq!(with.price_change_percentage_1y_in_currency, None);
+
+        // Tokens stored before the windows existed read back without them
+        let json = serde_json::to_value(sample_token()).unwrap();
+        assert!(json.get("price_change_percentage_7d").is_none());
+        let token: CryptoToken = serde_json::from_value(json).unwrap();
+        assert_eq!(token.price_change_percentage_30d, None);
+    }
+
+    #[test]
+    fn test_stats_leave_out_non_finite_figures() {
+        let token = |id: &str, market_cap: f64, change: f64| CryptoToken {
+            token_id: id.to_string(),
+            market_cap,
+            volume_24h: 10.0,
+            price_change_percentage_24h: change,
+            ..sample_token()
+        };
+        let tokens = vec![
+            token("up", 100.0, 5.0),
+            token("nan-change", 100.0, f64::NAN),
+            token("down", 300.0, -3.0),
+            token("inf-cap", f64::INFINITY, 50.0),
+            token("flat", 600.0, 0.0),
+        ];
+
+        let stats = TokenStats::from_tokens(&tokens);
+        assert_eq!((stats.total_tokens, stats.excluded_tokens), (3, 2));
+        assert_eq!(stats.total_market_cap, 1000.0);
+        assert_eq!(stats.total_volume_24h, 30.0);
+        assert!((stats.avg_price_change_24h - 2.0 / 3.0).abs() < 1e-12);
+        assert_eq!(stats.biggest_gainer.unwrap().token_id, "up");
+        assert_eq!(stats.biggest_loser.unwrap().token_id, "down");
+        // Only the three usable tokens: -3, 0, 5
+        assert_eq!(stats.median_price_change_24h, Some(0.0));
+        assert!((stats.p10_price_change_24h.unwrap() - -2.4).abs() < 1e-12);
+        assert!((stats.p90_price_change_24h.unwrap() - 4.0).abs() < 1e-12);
+        assert_eq!((stats.gainers, stats.losers, stats.flat), (1, 1, 1));
+        // (5 x 100 - 3 x 300 + 0 x 600) / 1000
+        assert!((stats.market_cap_weighted_change_24h.unwrap() - -0.4).abs() < 1e-12);
+
+        let stats = TokenStats::from_tokens(&[token("nan", f64::NAN, f64::NAN)]);
+        assert_eq!((stats.total_tokens, stats.excluded_tokens), (0, 1));
+        assert_eq!(stats.avg_price_change_24h, 0.0);
+        assert!(stats.biggest_gainer.is_none());
+        assert_eq!(stats.median_price_change_24h, None);
+        assert_eq!(stats.market_cap_weighted_change_24h, None);
+        assert_eq!((stats.gainers, stats.losers, stats.flat), (0, 0, 0));
+        let json = serde_json::to_value(&stats).unwrap();
+        assert!(json.get("median_price_change_24h").is_none());
+    }
+
+    #[test]
+    fn test_market_sparkline_is_nested() {
+        let mut market = serde_json::json!({
+            "id": "bitcoin",
+            "symbol": "btc",
+            "name": "Bitcoin",
+            "image": "https://example.com/btc.png",
+            "current_price": 50000.0,
+            "market_cap": 1.0e12,
+            "total_volume": 5.0e10,
+        });
+        let without: CoinGeckoMarket = serde_json::from_value(market.clone()).unwrap();
+        assert!(without.sparkline_in_7d.is_none());
+
+        market["sparkline_in_7d"] = serde_json::json!({ "price": [49000.0, 49500.5, 50000.0] });
+        let with: CoinGeckoMarket = serde_json::from_value(market).unwrap();
+        assert_eq!(with.sparkline_in_7d.unwrap().price, vec![49000.0, 49500.5, 50000.0]);
+
+        // Left out of the JSON entirely when not stored
+        let json = serde_json::to_value(sample_token()).unwrap();
+        assert!(json.get("sparkline_7d").is_none());
+    }
+
+    #[test]
+    fn test_change_window_parsing() {
+        assert_eq!("24h".parse::<ChangeWindow>(), Ok(ChangeWindow::Day));
+        assert_eq!("7d".parse::<ChangeWindow>().map(ChangeWindow::field), Ok("price_change_percentage_7d"));
+        assert_eq!("30d".parse::<ChangeWindow>().map(ChangeWindow::field), Ok("price_change_percentage_30d"));
+        for bad in ["", "1y", "7D", "week"] {
+            assert!(bad.parse::<ChangeWindow>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_error_response_omits_missing_retry_after() {
+        let json = serde_json::to_value(ErrorResponse::new("Token not found")).unwrap();
+        assert_eq!(json, serde_json::json!({ "error": "Token not found" }));
+
+        let json = serde_json::to_value(ErrorResponse::new("Busy").with_retry_after(30)).unwrap();
+        assert_eq!(json["retry_after"], 30);
+    }
+
+    #[test]
+    fn test_history_days_accepts_range_and_max() {
+        assert_eq!("1".parse::<HistoryDays>(), Ok(HistoryDays::Days(1)));
+        assert_eq!("365".parse::<HistoryDays>(), Ok(HistoryDays::Days(365)));
+        assert_eq!("max".parse::<HistoryDays>(), Ok(HistoryDays::Max));
+        for bad in ["0", "366", "4294967295", "-1", "MAX", "7d", ""] {
+            assert!(bad.parse::<HistoryDays>().is_err(), "{}", bad);
+        }
+        assert_eq!(HistoryDays::Max.to_string(), "max");
+        assert_eq!(HistoryDays::Days(30).to_string(), "30");
+    }
+
+    #[test]
+    fn test_history_interval_parsing_and_auto() {
+        assert_eq!("hourly".parse::<HistoryInterval>(), Ok(HistoryInterval::Hourly));
+        assert_eq!("daily".parse::<HistoryInterval>(), Ok(HistoryInterval::Daily));
+        for bad in ["", "Daily", "5m", "weekly"] {
+            assert!(bad.parse::<HistoryInterval>().is_err(), "{}", bad);
+        }
+        assert_eq!(HistoryInterval::auto(HistoryDays::Days(1)), HistoryInterval::Hourly);
+        assert_eq!(HistoryInterval::auto(HistoryDays::Days(90)), HistoryInterval::Hourly);
+        assert_eq!(HistoryInterval::auto(HistoryDays::Days(91)), HistoryInterval::Daily);
+        assert_eq!(HistoryInterval::auto(HistoryDays::Max), HistoryInterval::Daily);
+    }
+
+    #[test]
+    fn test_token_id_format() {
+        assert!(is_valid_token_id("bitcoin"));
+        assert!(is_valid_token_id("usd-coin-2"));
+        assert!(!is_valid_token_id(""));
+        assert!(!is_valid_token_id("Bitcoin"));
+        assert!(!is_valid_token_id("bit coin"));
+        assert!(!is_valid_token_id("../etc"));
+        assert!(!is_valid_token_id(&"a".repeat(101)));
+    }
+
+    #[test]
+    fn test_derived_metrics_normal_path() {
+        let mut token = sample_token();
+        token.market_cap = 900000000000.0;
+        token.max_supply = Some(21000000.0);
+        token.fully_diluted_valuation = Some(1000000000000.0);
+
+        let metrics = token.derived_metrics();
+        let percentage = metrics.circulating_supply_percentage.unwrap();
+        assert!((percentage - 19.0 / 21.0 * 100.0).abs() < 1e-9);
+        assert_eq!(metrics.fdv, Some(1000000000000.0));
+        assert!((metrics.mcap_to_fdv_ratio.unwrap() - 0.9).abs() < 1e-12);
+
+        // Without CoinGecko's figure, FDV comes from price × max supply
+        token.fully_diluted_valuation = None;
+        assert_eq!(token.derived_metrics().fdv, Some(50000.0 * 21000000.0));
+    }
+
+    #[test]
+    fn test_derived_metrics_without_max_supply() {
+        let mut token = sample_token();
+        token.max_supply = None;
+        token.circulating_supply = Some(10000000.0);
+        token.total_supply = Some(20000000.0);
+
+        let metrics = token.derived_metrics();
+        assert_eq!(metrics.circulating_supply_percentage, Some(50.0));
+        assert_eq!(metrics.fdv, None);
+        assert_eq!(metrics.mcap_to_fdv_ratio, None);
+    }
+
+    #[test]
+    fn test_derived_metrics_zero_supply_is_none() {
+        let mut token = sample_token();
+        token.circulating_supply = Some(0.0);
+        token.total_supply = Some(0.0);
+        token.max_supply = Some(0.0);
+        assert_eq!(token.derived_metrics(), DerivedMetrics::default());
+
+        // A zero max falls back to total supply rather than dividing by zero
+        token.circulating_supply = Some(5.0);
+        token.total_supply = Some(10.0);
+        assert_eq!(token.derived_metrics().circulating_supply_percentage, Some(50.0));
+    }
+
+    #[test]
+    fn test_supply_percent_needs_max_supply() {
+        let mut token = sample_token();
+        token.max_supply = Some(21000000.0);
+        let supply = TokenSupply::from(&token);
+        assert!((supply.circulating_percent.unwrap() - 19.0 / 21.0 * 100.0).abs() < 1e-9);
+
+        // No guessing from total supply or a computed FDV
+        token.max_supply = None;
+        let supply = TokenSupply::from(&token);
+        assert_eq!(supply.circulating_percent, None);
+        assert_eq!(supply.fully_diluted_valuation, None);
+        let json = serde_json::to_value(supply).unwrap();
+        assert!(json["max_supply"].is_null());
+    }
+
+    #[test]
+    fn test_token_detail_flattens_metrics_into_token_json() {
+        let mut token = sample_token();
+        token.max_supply = Some(21000000.0);
+        let json = serde_json::to_value(TokenDetail::from(token)).unwrap();
+
+        assert_eq!(json["token_id"], "bitcoin");
+        assert!(json["circulating_supply_percentage"].is_f64());
+        assert!(json["fdv"].is_f64());
+        assert!(json.get("metrics").is_none());
+    }
+
+    #[test]
+    fn test_category_id_format() {
+        assert!(is_valid_category_id("decentralized-finance-defi"));
+        assert!(is_valid_category_id("layer-1"));
+        assert!(!is_valid_category_id("Decentralized Finance (DeFi)"));
+        assert!(!is_valid_category_id(""));
+    }
+
+    #[test]
+    fn test_coin_profile_from_coingecko() {
+        let coin: CoinGeckoCoin = serde_json::from_value(serde_json::json!({
+            "id": "bitcoin",
+            "symbol": "btc",
+            "name": "Bitcoin",
+            "description": { "en": "<a href=\"https://bitcoin.org\">Bitcoin</a> is P2P cash &amp; more.\r\n" },
+            "links": {
+                "homepage": ["", "https://bitcoin.org", ""],
+                "twitter_screen_name": "bitcoin",
+                "subreddit_url": ""
+            },
+            "genesis_date": "2009-01-03",
+            "categories": ["Cryptocurrency", null, "Layer 1 (L1)"]
+        }))
+        .unwrap();
+        let profile = CoinProfile::from(coin);
+
+        assert_eq!(profile.description, "Bitcoin is P2P cash & more.");
+        assert_eq!(profile.homepage.as_deref(), Some("https://bitcoin.org"));
+        assert_eq!(profile.twitter.as_deref(), Some("https://twitter.com/bitcoin"));
+        assert_eq!(profile.reddit, None);
+        assert_eq!(profile.genesis_date.as_deref(), Some("2009-01-03"));
+        assert_eq!(profile.categories, vec!["Cryptocurrency", "Layer 1 (L1)"]);
+    }
+
+    #[test]
+    fn test_coin_profile_tolerates_nulls() {
+        let coin: CoinGeckoCoin = serde_json::from_value(serde_json::json!({
+            "id": "tiny", "symbol": "tny", "name": "Tiny",
+            "description": null, "links": null, "genesis_date": null, "categories": null
+        }))
+        .unwrap();
+        let profile = CoinProfile::from(coin);
+        assert_eq!(profile.description, "");
+        assert!(profile.homepage.is_none() && profile.twitter.is_none() && profile.categories.is_empty());
+    }
+
+    #[test]
+    fn test_event_kind_is_stored_inline() {
+        let event = Event {
+            token_id: "bitcoin".to_string(),
+            kind: EventKind::LargeMove { change_percentage: -12.5 },
+            old_value: 80.0,
+            new_value: 70.0,
+            at: Utc::now(),
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["kind"], "large_move");
+        assert_eq!(json["change_percentage"], -12.5);
+
+        // As read back from Mongo, _id included
+        let mut document = mongodb::bson::to_document(&event).unwrap();
+        document.insert("_id", ObjectId::new());
+        assert_eq!(mongodb::bson::from_document::<Event>(document).unwrap(), event);
+
+        let ath = Event { kind: EventKind::AthBreak, ..event };
+        assert_eq!(serde_json::to_value(&ath).unwrap()["kind"], "ath_break");
+        assert_eq!(ath.kind.name(), "ath_break");
+    }
+
+    #[test]
+    fn test_screener_bounds_are_checked() {
+        let query = ScreenerQuery { min_volume_mcap_ratio: Some(0.2), min_market_cap: Some(1e8), sort: Some("volume_24h".to_string()), ..Default::default() };
+        let screener = query.validated().unwrap();
+        assert_eq!(screener.bounds, vec![("volume_mcap_ratio", Some(0.2), None), ("market_cap", Some(1e8), None)]);
+        assert_eq!((screener.sort, screener.limit), ("volume_24h", DEFAULT_SCREENER_LIMIT));
+
+        let conflicting = ScreenerQuery { min_change_24h: Some(5.0), max_change_24h: Some(-5.0), ..Default::default() };
+        assert!(conflicting.validated().unwrap_err().contains("min_change_24h"));
+        let equal = ScreenerQuery { min_market_cap: Some(1.0), max_market_cap: Some(1.0), ..Default::default() };
+        assert!(equal.validated().is_ok());
+        assert!(ScreenerQuery { max_market_cap: Some(f64::NAN), ..Default::default() }.validated().is_err());
+        assert!(ScreenerQuery { sort: Some("name".to_string()), ..Default::default() }.validated().is_err());
+        assert!(ScreenerQuery { limit: Some(0), ..Default::default() }.validated().is_err());
+    }
+
+    #[test]
+    fn test_repair_passes_parse_into_running_order() {
+        assert_eq!(RepairPass::parse_list(None).unwrap(), RepairPass::ALL);
+        assert_eq!(RepairPass::parse_list(Some("")).unwrap(), RepairPass::ALL);
+        assert_eq!(
+            RepairPass::parse_list(Some("backfill, dedupe,backfill")).unwrap(),
+            vec![RepairPass::Dedupe, RepairPass::Backfill]
+        );
+        let err = RepairPass::parse_list(Some("dedupe,vacuum")).unwrap_err();
+        assert!(err.contains("\"vacuum\""), "{}", err);
+    }
 }
@@ -21,13 +21,20 @@ mod tests {
             low_24h: Some(49000.0),
             circulating_supply: Some(19000000.0),
             total_supply: Some(21000000.0),
+            max_supply: None,
+            fully_diluted_valuation: None,
             ath: Some(69000.0),
             ath_change_percentage: Some(-27.5),
             atl: Some(67.81),
             atl_change_percentage: Some(73600.0),
             image: Some("https://example.com/bitcoin.png".to_string()),
             last_updated: Utc::now(),
+            fetched_at: None,
             is_favorite: false,
+            tags: Vec::new(),
+            note: None,
+            price_source: None,
+            category: None,
         };
 
         let json = serde_json::to_string(&token).expect("Failed to serialize");
@@ -55,13 +62,20 @@ mod tests {
             low_24h: None,
             circulating_supply: None,
             total_supply: None,
+            max_supply: None,
+            fully_diluted_valuation: None,
             ath: None,
             ath_change_percentage: None,
             atl: None,
             atl_change_percentage: None,
             image: None,
             last_updated: Utc::now(),
+            fetched_at: None,
             is_favorite: false,
+            tags: Vec::new(),
+            note: None,
+            price_source: None,
+            category: None,
         };
 
         let json = serde_json::to_string(&token).expect("Failed to serialize");
@@ -207,13 +221,20 @@ mod tests {
             low_24h: None,
             circulating_supply: None,
             total_supply: None,
+            max_supply: None,
+            fully_diluted_valuation: None,
             ath: None,
             ath_change_percentage: None,
             atl: None,
             atl_change_percentage: None,
             image: None,
             last_updated: Utc::now(),
+            fetched_at: None,
             is_favorite: false,
+            tags: Vec::new(),
+            note: None,
+            price_source: None,
+            category: None,
         };
 
         assert!(!token.is_favorite);
@@ -0,0 +1,63 @@
+use crate::models::{is_valid_token_id, CryptoToken};
+
+// Splits a byte stream into lines as chunks arrive, holding back only the unfinished tail
+#[derive(Debug, Default)]
+pub struct LineSplitter {
+    pending: Vec<u8>,
+}
+
+impl LineSplitter {
+    // Complete lines in `chunk` (plus whatever was pending), without their newlines
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<Vec<u8>> {
+        self.pending.extend_from_slice(chunk);
+        let Some(last_newline) = self.pending.iter().rposition(|&b| b == b'\n') else {
+            return Vec::new();
+        };
+
+        let rest = self.pending.split_off(last_newline + 1);
+        let complete = std::mem::replace(&mut self.pending, rest);
+        complete[..last_newline]
+            .split(|&b| b == b'\n')
+            .map(|line| line.strip_suffix(b"\r").unwrap_or(line).to_vec())
+            .collect()
+    }
+
+    // The last line when the input didn't end with a newline
+    pub fn finish(self) -> Option<Vec<u8>> {
+        (!self.pending.is_empty()).then_some(self.pending)
+    }
+}
+
+// One exported token. `_id` is dropped so the import upserts by token_id alone.
+pub fn parse_token_line(line: &[u8]) -> Result<CryptoToken, String> {
+    let mut token: CryptoToken = serde_json::from_slice(line).map_err(|e| e.to_string())?;
+    if !is_valid_token_id(&token.token_id) {
+        return Err(format!("invalid token_id '{}'", token.token_id));
+    }
+    token.id = None;
+    Ok(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lines_split_across_chunks() {
+        let mut splitter = LineSplitter::default();
+        assert!(splitter.push(b"{\"a\":").is_empty());
+        assert_eq!(splitter.push(b"1}\r\n{\"b\":2}\n{\"c\""), vec![b"{\"a\":1}".to_vec(), b"{\"b\":2}".to_vec()]);
+        assert_eq!(splitter.push(b":3}\n\n"), vec![b"{\"c\":3}".to_vec(), Vec::new()]);
+        assert_eq!(splitter.finish(), None);
+
+        let mut splitter = LineSplitter::default();
+        assert!(splitter.push(b"no newline").is_empty());
+        assert_eq!(splitter.finish(), Some(b"no newline".to_vec()));
+    }
+
+    #[test]
+    fn test_parse_token_line_reports_why() {
+        assert!(parse_token_line(b"not json").unwrap_err().contains("expected"));
+        assert!(parse_token_line(b"{\"token_id\":\"bitcoin\"}").unwrap_err().contains("missing field"));
+    }
+}
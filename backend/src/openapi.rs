@@ -0,0 +1,231 @@
+use utoipa::openapi::path::{Operation, PathItem};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, Http, HttpAuthScheme, SecurityScheme};
+use utoipa::openapi::Deprecated;
+use utoipa::{Modify, OpenApi};
+use crate::auth::ADMIN_TOKEN_HEADER;
+use crate::routes::{RouteGroup, VersionRegistry, LEGACY_PREFIX};
+use crate::{handlers, v2};
+use crate::models::{
+    Alert, AlertCondition, AlertRequest, AlertTrigger, MoveWindow, ApiResponse, ResponseMeta, BulkFavoriteRequest, BulkFavoriteResponse, FavoritesExport, FavoritesImport, FavoritesImportMode, FavoritesImportSummary, CacheDebugInfo, CacheStatus, CoinProfile, CoinSearchResult, CollectionSummary, HistoryCacheEntry, HistoryCacheStatus, RateLimitStatus, Category, ExchangeRate, ExchangeRates, CoinGeckoHistoricalData, ConversionResult, CorrelationMatrix, CryptoToken, ScoredToken, ScreenedToken, TokenBatch, DailyReport, AnnotationRequest, FieldError, TokenAnnotation, MarketCapPoint, TokenDeletion, TokenPurge, RepairPass, RepairReport, TokenDocuments, DominanceHistory, DominancePoint, DominanceSource, DerivedMetrics, ErrorResponse, ImportFailure, ImportSummary, FavoriteMeta, FavoriteRequest, PortfolioPosition, PortfolioSummary, TradeSide, Transaction, TransactionEntry, HistoryInterval, MarketStats, Overview, NewUser, PriceHistory, SkippedToken,
+    Event, EventKind, HistoricalChange, PriceAt, TokenChange, TokenDetail, TokenStats, TokenSupply, WebhookConfig, WebhookDelivery, WebhookEvent, DeliveryOutcome,
+};
+
+// Generated spec served at /api/openapi.json. Every handler mounted in routes.rs
+// must be listed under `paths`, which tests/openapi_test.rs enforces. Handlers document
+// their unversioned /api path; VersionedPaths copies it to /api/v1.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Crypto Tracker API",
+        description = "Token prices, favorites and history backed by CoinGecko with a MongoDB cache"
+    ),
+    paths(
+        handlers::get_tokens,
+        handlers::export_tokens,
+        handlers::get_tokens_batch,
+        handlers::get_token,
+        handlers::toggle_favorite,
+        handlers::get_favorites,
+        handlers::create_user,
+        handlers::get_token_supply,
+        handlers::get_token_profile,
+        handlers::get_historical_change,
+        handlers::get_price_at,
+        handlers::get_stored_history,
+        handlers::get_token_image,
+        handlers::get_annotation,
+        handlers::put_annotation,
+        handlers::delete_annotation,
+        handlers::get_events,
+        handlers::export_tokens_ndjson,
+        handlers::import_tokens,
+        handlers::put_webhook,
+        handlers::get_webhook_deliveries,
+        handlers::hide_token,
+        handlers::unhide_token,
+        handlers::delete_token,
+        handlers::purge_stale_tokens,
+        handlers::repair_data,
+        handlers::bulk_favorites,
+        handlers::export_favorites,
+        handlers::import_favorites,
+        handlers::update_favorite_meta,
+        handlers::list_transactions,
+        handlers::create_transaction,
+        handlers::update_transaction,
+        handlers::delete_transaction,
+        handlers::get_portfolio_summary,
+        handlers::list_alerts,
+        handlers::create_alert,
+        handlers::delete_alert,
+        handlers::get_alert_triggers,
+        handlers::search_tokens,
+        handlers::search_coins,
+        handlers::convert,
+        handlers::get_historical_data,
+        handlers::get_stats,
+        handlers::get_overview,
+        handlers::get_currencies,
+        handlers::get_correlation,
+        handlers::get_dominance,
+        handlers::get_market_history,
+        handlers::get_daily_report,
+        handlers::get_categories,
+        handlers::get_exchange_rates,
+        handlers::get_exchange_rate,
+        handlers::get_gainers,
+        handlers::get_losers,
+        handlers::get_near_ath,
+        handlers::get_screener,
+        handlers::get_cache_status,
+        handlers::debug_cache,
+        v2::get_tokens,
+        v2::get_favorites,
+        v2::search_tokens,
+        v2::get_stats,
+    ),
+    components(schemas(
+        CryptoToken,
+        CoinSearchResult,
+        FavoriteRequest,
+        BulkFavoriteRequest,
+        BulkFavoriteResponse,
+        FavoritesExport,
+        FavoritesImport,
+        FavoritesImportMode,
+        FavoritesImportSummary,
+        FavoriteMeta,
+        TradeSide,
+        Transaction,
+        TransactionEntry,
+        PortfolioPosition,
+        PortfolioSummary,
+        AlertRequest,
+        AlertCondition,
+        MoveWindow,
+        Alert,
+        AlertTrigger,
+        PriceHistory,
+        HistoryInterval,
+        CoinGeckoHistoricalData,
+        TokenStats,
+        MarketStats,
+        Overview,
+        DailyReport,
+        TokenChange,
+        CacheDebugInfo,
+        CacheStatus,
+        CollectionSummary,
+        HistoryCacheStatus,
+        HistoryCacheEntry,
+        RateLimitStatus,
+        ConversionResult,
+        Category,
+        ExchangeRate,
+        ExchangeRates,
+        DerivedMetrics,
+        TokenBatch,
+        ScoredToken,
+        ScreenedToken,
+        TokenDetail,
+        TokenSupply,
+        CoinProfile,
+        HistoricalChange,
+        PriceAt,
+        Event,
+        EventKind,
+        CorrelationMatrix,
+        AnnotationRequest,
+        FieldError,
+        TokenAnnotation,
+        TokenDeletion,
+        TokenPurge,
+        RepairPass,
+        RepairReport,
+        TokenDocuments,
+        DominanceHistory,
+        DominancePoint,
+        DominanceSource,
+        MarketCapPoint,
+        SkippedToken,
+        NewUser,
+        ImportSummary,
+        ImportFailure,
+        WebhookConfig,
+        WebhookEvent,
+        WebhookDelivery,
+        DeliveryOutcome,
+        ErrorResponse,
+        ApiResponse<Vec<CryptoToken>>,
+        ApiResponse<TokenStats>,
+        ResponseMeta,
+    )),
+    tags(
+        (name = "tokens", description = "Market data for the top tokens"),
+        (name = "favorites", description = "Tokens the user has starred; per user with an API key, shared without"),
+        (name = "users", description = "API key provisioning"),
+        (name = "portfolio", description = "Per-user buys and sells and the FIFO P&L they add up to; needs an API key"),
+        (name = "alerts", description = "Percent-move alerts on a token or the favorites, and what set them off"),
+        (name = "search", description = "Search over cached tokens, and CoinGecko's own search"),
+        (name = "history", description = "Historical price series"),
+        (name = "stats", description = "Aggregate market statistics"),
+        (name = "admin", description = "Operator settings, behind X-Admin-Token"),
+        (name = "debug", description = "Internal state, only with DEBUG_ENDPOINTS=true"),
+        (name = "v2", description = "List endpoints answering with a data/meta/error envelope"),
+    ),
+    modifiers(&SecuritySchemes, &VersionedPaths)
+)]
+pub struct ApiDoc;
+
+// Schemes handlers reference by name: `api_key` is `Authorization: Bearer <key>` from
+// POST /api/users, `admin_token` is the ADMIN_TOKEN sent as X-Admin-Token (admin
+// endpoints also take it as `Authorization: Bearer`)
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme("api_key", SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)));
+            components.add_security_scheme(
+                "admin_token",
+                SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(ADMIN_TOKEN_HEADER))),
+            );
+        }
+    }
+}
+
+// Every version serving the plain routes gets its own copy of their paths. Operations of
+// a deprecated version are marked so, with an operationId of their own.
+struct VersionedPaths;
+
+impl Modify for VersionedPaths {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let mut paths: Vec<&str> = RouteGroup::Current.routes().into_iter().map(|(_, path)| path).collect();
+        paths.sort_unstable();
+        paths.dedup();
+        let originals: Vec<(&str, PathItem)> = paths
+            .into_iter()
+            .filter_map(|path| Some((path, openapi.paths.paths.get(&format!("{}{}", LEGACY_PREFIX, path))?.clone())))
+            .collect();
+
+        let registry = VersionRegistry::standard();
+        for version in registry.versions().iter().filter(|v| v.group == RouteGroup::Current) {
+            for (path, item) in &originals {
+                let mut item = item.clone();
+                if version.deprecation.is_some() {
+                    for operation in operations_mut(&mut item) {
+                        operation.deprecated = Some(Deprecated::True);
+                        operation.operation_id = operation.operation_id.take().map(|id| format!("{}_deprecated", id));
+                    }
+                }
+                openapi.paths.paths.insert(format!("{}{}", version.prefix, path), item);
+            }
+        }
+    }
+}
+
+fn operations_mut(item: &mut PathItem) -> impl Iterator<Item = &mut Operation> {
+    [&mut item.get, &mut item.put, &mut item.post, &mut item.delete, &mut item.patch, &mut item.head, &mut item.options, &mut item.trace]
+        .into_iter()
+        .filter_map(Option::as_mut)
+}
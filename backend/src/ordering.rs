@@ -0,0 +1,28 @@
+use std::cmp::Ordering;
+
+// Total order over f64 for everything we sort or rank by a float. NaN sorts below every
+// number, -inf included, so a bad figure ends up last in a descending list and first in
+// an ascending one, the same way on every run. Numbers compare as usual; -0.0 == 0.0.
+pub fn cmp_f64(a: f64, b: f64) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Less,
+        (false, true) => Ordering::Greater,
+        (false, false) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nan_sorts_below_everything() {
+        let mut values = [1.0, f64::NAN, f64::NEG_INFINITY, -0.0, f64::INFINITY, f64::NAN, -2.5];
+        values.sort_by(|a, b| cmp_f64(*a, *b));
+
+        assert!(values[0].is_nan() && values[1].is_nan());
+        assert_eq!(&values[2..], &[f64::NEG_INFINITY, -2.5, -0.0, 1.0, f64::INFINITY]);
+        assert_eq!(cmp_f64(0.0, -0.0), Ordering::Equal);
+    }
+}
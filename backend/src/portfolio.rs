@@ -0,0 +1,245 @@
+use std::collections::{BTreeMap, VecDeque};
+use chrono::{DateTime, Utc};
+use crate::models::{TradeSide, Transaction};
+
+// Quantities are floats; a sell within this fraction of what's held closes the position
+// instead of failing on rounding left over from earlier lots
+const QUANTITY_TOLERANCE: f64 = 1e-9;
+
+// What a token's transactions add up to under FIFO lot matching
+#[derive(Debug, Clone, PartialEq)]
+pub struct Position {
+    pub token_id: String,
+    pub quantity: f64,
+    // What the lots still held cost, buy fees included
+    pub cost_basis: f64,
+    // Sell proceeds net of fees, minus the cost of the lots they consumed
+    pub realized_pnl: f64,
+}
+
+impl Position {
+    pub fn average_cost(&self) -> Option<f64> {
+        (self.quantity > 0.0).then(|| self.cost_basis / self.quantity)
+    }
+}
+
+// A sell larger than what was held at its time
+#[derive(Debug, Clone, PartialEq)]
+pub struct Oversold {
+    pub token_id: String,
+    pub timestamp: DateTime<Utc>,
+    pub held: f64,
+    pub requested: f64,
+}
+
+impl std::fmt::Display for Oversold {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Sell of {} {} at {} exceeds the {} held",
+            self.requested,
+            self.token_id,
+            self.timestamp.to_rfc3339(),
+            self.held
+        )
+    }
+}
+
+// A buy still (partly) held, at its per-unit cost with the fee spread over it
+struct Lot {
+    quantity: f64,
+    unit_cost: f64,
+}
+
+#[derive(Default)]
+struct Book {
+    lots: VecDeque<Lot>,
+    realized_pnl: f64,
+}
+
+impl Book {
+    fn held(&self) -> f64 {
+        self.lots.iter().map(|lot| lot.quantity).sum()
+    }
+
+    fn buy(&mut self, transaction: &Transaction) {
+        let cost = transaction.quantity * transaction.price_per_unit + transaction.fee;
+        self.lots.push_back(Lot {
+            quantity: transaction.quantity,
+            unit_cost: cost / transaction.quantity,
+        });
+    }
+
+    // Consumes the oldest lots first
+    fn sell(&mut self, transaction: &Transaction) -> Result<(), Oversold> {
+        let held = self.held();
+        if transaction.quantity > held * (1.0 + QUANTITY_TOLERANCE) {
+            return Err(Oversold {
+                token_id: transaction.token_id.clone(),
+                timestamp: transaction.timestamp,
+                held,
+                requested: transaction.quantity,
+            });
+        }
+
+        let mut remaining = transaction.quantity;
+        let mut consumed_cost = 0.0;
+        while remaining > 0.0 {
+            let Some(lot) = self.lots.front_mut() else {
+                break;
+            };
+            let taken = lot.quantity.min(remaining);
+            consumed_cost += taken * lot.unit_cost;
+            lot.quantity -= taken;
+            remaining -= taken;
+            if lot.quantity <= taken * QUANTITY_TOLERANCE {
+                self.lots.pop_front();
+            }
+        }
+        let proceeds = transaction.quantity * transaction.price_per_unit - transaction.fee;
+        self.realized_pnl += proceeds - consumed_cost;
+        Ok(())
+    }
+}
+
+// Replays the transactions in time order (ties keep their order in the slice) and
+// returns one position per token, sorted by token id. Fully sold tokens stay in with
+// a zero quantity since their realized P&L still counts.
+pub fn positions(transactions: &[Transaction]) -> Result<Vec<Position>, Oversold> {
+    let mut ordered: Vec<&Transaction> = transactions.iter().collect();
+    ordered.sort_by_key(|t| t.timestamp);
+
+    let mut books: BTreeMap<&str, Book> = BTreeMap::new();
+    for transaction in ordered {
+        let book = books.entry(transaction.token_id.as_str()).or_default();
+        match transaction.side {
+            TradeSide::Buy => book.buy(transaction),
+            TradeSide::Sell => book.sell(transaction)?,
+        }
+    }
+
+    Ok(books
+        .into_iter()
+        .map(|(token_id, book)| Position {
+            token_id: token_id.to_string(),
+            quantity: book.held(),
+            cost_basis: book.lots.iter().map(|lot| lot.quantity * lot.unit_cost).sum(),
+            realized_pnl: book.realized_pnl,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, day, 0, 0, 0).unwrap()
+    }
+
+    fn trade(side: TradeSide, day: u32, quantity: f64, price: f64, fee: f64) -> Transaction {
+        Transaction {
+            token_id: "bitcoin".to_string(),
+            side,
+            quantity,
+            price_per_unit: price,
+            fee,
+            timestamp: at(day),
+        }
+    }
+
+    fn buy(day: u32, quantity: f64, price: f64) -> Transaction {
+        trade(TradeSide::Buy, day, quantity, price, 0.0)
+    }
+
+    fn sell(day: u32, quantity: f64, price: f64) -> Transaction {
+        trade(TradeSide::Sell, day, quantity, price, 0.0)
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn test_single_buy_is_held_at_cost() {
+        let positions = positions(&[buy(1, 2.0, 100.0)]).unwrap();
+        assert_eq!(positions.len(), 1);
+        assert_eq!(positions[0].quantity, 2.0);
+        assert_eq!(positions[0].cost_basis, 200.0);
+        assert_eq!(positions[0].average_cost(), Some(100.0));
+        assert_eq!(positions[0].realized_pnl, 0.0);
+    }
+
+    #[test]
+    fn test_partial_sell_consumes_part_of_a_lot() {
+        let positions = positions(&[buy(1, 10.0, 100.0), sell(2, 4.0, 150.0)]).unwrap();
+        let position = &positions[0];
+        assert_eq!(position.quantity, 6.0);
+        assert_eq!(position.cost_basis, 600.0);
+        assert_eq!(position.realized_pnl, 200.0);
+    }
+
+    #[test]
+    fn test_sell_spans_lots_oldest_first() {
+        let transactions = [buy(1, 1.0, 100.0), buy(2, 1.0, 200.0), buy(3, 1.0, 300.0), sell(4, 1.5, 400.0)];
+        let position = &positions(&transactions).unwrap()[0];
+        // Sold the 100 lot and half the 200 lot: cost 200, proceeds 600
+        assert_eq!(position.realized_pnl, 400.0);
+        assert_eq!(position.quantity, 1.5);
+        assert_eq!(position.cost_basis, 400.0);
+        assert!(close(position.average_cost().unwrap(), 400.0 / 1.5));
+    }
+
+    #[test]
+    fn test_fees_raise_cost_and_lower_proceeds() {
+        let transactions = [
+            trade(TradeSide::Buy, 1, 2.0, 100.0, 10.0),
+            trade(TradeSide::Sell, 2, 1.0, 120.0, 4.0),
+        ];
+        let position = &positions(&transactions).unwrap()[0];
+        // Each unit cost 105; one sold for 120 - 4
+        assert_eq!(position.realized_pnl, 11.0);
+        assert_eq!(position.cost_basis, 105.0);
+        assert_eq!(position.average_cost(), Some(105.0));
+    }
+
+    #[test]
+    fn test_transactions_are_replayed_in_time_order() {
+        // The sell is listed first but happened after both buys
+        let transactions = [sell(5, 1.0, 50.0), buy(2, 1.0, 30.0), buy(1, 1.0, 10.0)];
+        let position = &positions(&transactions).unwrap()[0];
+        assert_eq!(position.realized_pnl, 40.0);
+        assert_eq!(position.cost_basis, 30.0);
+    }
+
+    #[test]
+    fn test_overselling_is_rejected() {
+        let err = positions(&[buy(1, 1.0, 100.0), sell(2, 0.5, 100.0), sell(3, 0.6, 100.0)]).unwrap_err();
+        assert_eq!(err.token_id, "bitcoin");
+        assert_eq!(err.timestamp, at(3));
+        assert_eq!((err.held, err.requested), (0.5, 0.6));
+
+        // A sell before the buy it would need
+        assert!(positions(&[buy(2, 1.0, 100.0), sell(1, 1.0, 100.0)]).is_err());
+    }
+
+    #[test]
+    fn test_selling_everything_despite_rounding_closes_the_position() {
+        let transactions = [buy(1, 0.3, 10.0), sell(2, 0.1, 20.0), sell(3, 0.2, 20.0)];
+        let position = &positions(&transactions).unwrap()[0];
+        assert!(close(position.quantity, 0.0));
+        assert_eq!(position.average_cost(), None);
+        assert!(close(position.realized_pnl, 3.0));
+    }
+
+    #[test]
+    fn test_tokens_are_tracked_separately() {
+        let mut eth = buy(1, 5.0, 2000.0);
+        eth.token_id = "ethereum".to_string();
+        let positions = positions(&[eth, buy(1, 1.0, 100.0)]).unwrap();
+        let ids: Vec<_> = positions.iter().map(|p| p.token_id.as_str()).collect();
+        assert_eq!(ids, vec!["bitcoin", "ethereum"]);
+        assert_eq!(positions[1].cost_basis, 10000.0);
+    }
+}
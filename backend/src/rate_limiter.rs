@@ -0,0 +1,239 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use std::sync::Arc;
+use crate::cache_store::{CacheStore, MemoryCacheStore};
+use crate::config::Config;
+
+const LAST_API_CALL_KEY: &str = "last_api_call";
+const RATE_LIMITED_UNTIL_KEY: &str = "rate_limited_until";
+
+// A claim that lost its compare-and-set retries this many times before giving up
+const MAX_ACQUIRE_ATTEMPTS: usize = 3;
+
+fn parse_timestamp(raw: &str) -> Option<DateTime<Utc>> {
+    raw.parse().ok().and_then(|ms| Utc.timestamp_millis_opt(ms).single())
+}
+
+// Tracker for upstream API calls, shared across workers via app_data. Both timestamps
+// live in a CacheStore as epoch milliseconds, so a shared store shares one budget.
+pub struct RateLimiter {
+    store: Arc<dyn CacheStore>,
+    min_request_interval: Duration,
+    backoff: Duration,
+}
+
+impl RateLimiter {
+    pub fn new(min_request_interval_secs: f64, backoff_secs: i64) -> Self {
+        Self {
+            store: Arc::new(MemoryCacheStore::default()),
+            min_request_interval: Duration::milliseconds((min_request_interval_secs * 1000.0).round() as i64),
+            backoff: Duration::seconds(backoff_secs),
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(config.min_request_interval_secs, config.rate_limit_backoff_secs)
+    }
+
+    pub fn with_store(mut self, store: Arc<dyn CacheStore>) -> Self {
+        self.store = store;
+        self
+    }
+
+    async fn timestamp(&self, key: &str) -> Option<DateTime<Utc>> {
+        parse_timestamp(&self.store.get(key).await?)
+    }
+
+    // Stored only as long as it matters; a non-positive lifetime just clears it
+    async fn set_timestamp(&self, key: &str, at: DateTime<Utc>, keep_for: Duration) {
+        match keep_for.to_std() {
+            Ok(ttl) if !ttl.is_zero() => self.store.set(key, at.timestamp_millis().to_string(), ttl).await,
+            _ => self.store.delete(key).await,
+        }
+    }
+
+    async fn backing_off(&self) -> bool {
+        match self.rate_limited_until().await {
+            Some(until) if Utc::now() < until => {
+                tracing::info!("Rate limited, waiting until {}", until);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub async fn can_make_api_call(&self) -> bool {
+        if self.backing_off().await {
+            return false;
+        }
+
+        if let Some(last) = self.timestamp(LAST_API_CALL_KEY).await {
+            if Utc::now() - last < self.min_request_interval {
+                return false;
+            }
+        }
+        true
+    }
+
+    // Checks and records a call in one step. With a shared store only one replica
+    // wins a given slot, where can_make_api_call + record_api_call could let several through.
+    pub async fn try_acquire(&self) -> bool {
+        if self.backing_off().await {
+            return false;
+        }
+
+        for _ in 0..MAX_ACQUIRE_ATTEMPTS {
+            let now = Utc::now();
+            let previous = self.store.get(LAST_API_CALL_KEY).await;
+            if let Some(last) = previous.as_deref().and_then(parse_timestamp) {
+                if now - last < self.min_request_interval {
+                    return false;
+                }
+            }
+
+            // Kept at least a millisecond so the claim is visible to the next contender
+            let ttl = self.min_request_interval.to_std().unwrap_or_default().max(std::time::Duration::from_millis(1));
+            if self
+                .store
+                .compare_and_set(LAST_API_CALL_KEY, previous.as_deref(), now.timestamp_millis().to_string(), ttl)
+                .await
+            {
+                return true;
+            }
+        }
+        false
+    }
+
+    pub async fn rate_limited_until(&self) -> Option<DateTime<Utc>> {
+        self.timestamp(RATE_LIMITED_UNTIL_KEY).await
+    }
+
+    // Whole seconds until can_make_api_call would return true, 0 if it already does
+    pub async fn seconds_until_next_call(&self) -> u64 {
+        let now = Utc::now();
+        let backoff_end = self.rate_limited_until().await;
+        let interval_end = self.timestamp(LAST_API_CALL_KEY).await.map(|last| last + self.min_request_interval);
+
+        let wait = [backoff_end, interval_end]
+            .into_iter()
+            .flatten()
+            .map(|until| until - now)
+            .max()
+            .unwrap_or_else(Duration::zero);
+
+        if wait <= Duration::zero() {
+            0
+        } else {
+            // Round up so a client waiting this long is never turned away
+            (wait.num_milliseconds() as u64).div_ceil(1000)
+        }
+    }
+
+    pub async fn record_api_call(&self) {
+        self.set_timestamp(LAST_API_CALL_KEY, Utc::now(), self.min_request_interval).await;
+    }
+
+    pub async fn record_rate_limit(&self) {
+        self.set_timestamp(RATE_LIMITED_UNTIL_KEY, Utc::now() + self.backoff, self.backoff).await;
+        tracing::warn!("Rate limited! Backing off for {} seconds", self.backoff.num_seconds());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_interval_blocks_back_to_back_calls() {
+        let limiter = RateLimiter::new(60.0, 60);
+        assert!(limiter.can_make_api_call().await);
+
+        limiter.record_api_call().await;
+        assert!(!limiter.can_make_api_call().await);
+    }
+
+    #[tokio::test]
+    async fn test_zero_interval_allows_consecutive_calls() {
+        let limiter = RateLimiter::new(0.0, 60);
+        limiter.record_api_call().await;
+        assert!(limiter.can_make_api_call().await);
+    }
+
+    #[tokio::test]
+    async fn test_backoff_blocks_calls() {
+        let limiter = RateLimiter::new(0.0, 60);
+        limiter.record_rate_limit().await;
+        assert!(!limiter.can_make_api_call().await);
+    }
+
+    #[tokio::test]
+    async fn test_from_config_uses_configured_values() {
+        let mut config = Config::default_for_tests();
+        config.min_request_interval_secs = 0.0;
+        config.rate_limit_backoff_secs = 0;
+
+        let limiter = RateLimiter::from_config(&config);
+        limiter.record_rate_limit().await;
+        limiter.record_api_call().await;
+        assert!(limiter.can_make_api_call().await);
+    }
+
+    #[tokio::test]
+    async fn test_seconds_until_next_call_reports_longest_wait() {
+        let limiter = RateLimiter::new(5.0, 60);
+        assert_eq!(limiter.seconds_until_next_call().await, 0);
+        assert!(limiter.rate_limited_until().await.is_none());
+
+        limiter.record_api_call().await;
+        let wait = limiter.seconds_until_next_call().await;
+        assert!((1..=5).contains(&wait));
+
+        limiter.record_rate_limit().await;
+        assert!(limiter.rate_limited_until().await.is_some());
+        assert!(limiter.seconds_until_next_call().await > 5);
+    }
+
+    #[tokio::test]
+    async fn test_limiters_sharing_a_store_share_state() {
+        // Two instances pointed at the same store, as with Redis
+        let store: Arc<dyn CacheStore> = Arc::new(MemoryCacheStore::default());
+        let first = RateLimiter::new(60.0, 60).with_store(store.clone());
+        let second = RateLimiter::new(60.0, 60).with_store(store);
+
+        first.record_api_call().await;
+        assert!(!second.can_make_api_call().await);
+        assert!(second.seconds_until_next_call().await > 0);
+    }
+
+    #[tokio::test]
+    async fn test_try_acquire_claims_one_slot_per_interval() {
+        let limiter = RateLimiter::new(60.0, 60);
+        assert!(limiter.try_acquire().await);
+        assert!(!limiter.try_acquire().await);
+        assert!(!limiter.can_make_api_call().await);
+
+        let limiter = RateLimiter::new(0.0, 60);
+        assert!(limiter.try_acquire().await);
+        limiter.record_rate_limit().await;
+        assert!(!limiter.try_acquire().await);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_replicas_racing_for_a_slot_get_exactly_one() {
+        let store: Arc<dyn CacheStore> = Arc::new(MemoryCacheStore::default());
+
+        let attempts: Vec<_> = (0..16)
+            .map(|_| {
+                let limiter = RateLimiter::new(60.0, 60).with_store(store.clone());
+                tokio::spawn(async move { limiter.try_acquire().await })
+            })
+            .collect();
+
+        let mut granted = 0;
+        for attempt in attempts {
+            if attempt.await.unwrap() {
+                granted += 1;
+            }
+        }
+        assert_eq!(granted, 1);
+    }
+}
@@ -0,0 +1,126 @@
+use async_trait::async_trait;
+use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, Script};
+use std::time::Duration;
+use crate::cache_store::CacheStore;
+
+// INCRBY that sets the expiry only when it creates the key
+const INCR_SCRIPT: &str = r#"
+local value = redis.call('INCRBY', KEYS[1], ARGV[1])
+if ARGV[2] ~= '' and redis.call('PTTL', KEYS[1]) == -1 then
+    redis.call('PEXPIRE', KEYS[1], ARGV[2])
+end
+return value
+"#;
+
+// ARGV[1] is '1' when an expected value is given in ARGV[2], '0' for "must be absent"
+const COMPARE_AND_SET_SCRIPT: &str = r#"
+local current = redis.call('GET', KEYS[1])
+if ARGV[1] == '1' then
+    if current ~= ARGV[2] then return 0 end
+elseif current then
+    return 0
+end
+redis.call('SET', KEYS[1], ARGV[3], 'PX', ARGV[4])
+return 1
+"#;
+
+// Redis-backed CacheStore shared by every instance pointed at the same REDIS_URL.
+// Values are stored with an expiry, so a key that's gone simply means "not set".
+// Redis errors are logged and treated as missing values rather than failing requests.
+#[derive(Clone)]
+pub struct RedisStore {
+    conn: ConnectionManager,
+    prefix: String,
+}
+
+impl RedisStore {
+    pub async fn connect(url: &str) -> redis::RedisResult<Self> {
+        let client = redis::Client::open(url)?;
+        let conn = ConnectionManager::new(client).await?;
+        Ok(Self {
+            conn,
+            prefix: "crypto_tracker".to_string(),
+        })
+    }
+
+    // Namespaces the keys, mainly so tests can run side by side
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    fn key(&self, name: &str) -> String {
+        format!("{}:{}", self.prefix, name)
+    }
+}
+
+// PX rejects 0, so anything shorter than a millisecond rounds up
+fn ttl_millis(ttl: Duration) -> u64 {
+    (ttl.as_millis() as u64).max(1)
+}
+
+#[async_trait]
+impl CacheStore for RedisStore {
+    async fn get(&self, key: &str) -> Option<String> {
+        let mut conn = self.conn.clone();
+        match conn.get(self.key(key)).await {
+            Ok(value) => value,
+            Err(e) => {
+                tracing::warn!("Redis GET {} failed: {}", key, e);
+                None
+            }
+        }
+    }
+
+    async fn set(&self, key: &str, value: String, ttl: Duration) {
+        let mut conn = self.conn.clone();
+        if let Err(e) = conn.pset_ex::<_, _, ()>(self.key(key), value, ttl_millis(ttl)).await {
+            tracing::warn!("Redis SET {} failed: {}", key, e);
+        }
+    }
+
+    async fn delete(&self, key: &str) {
+        let mut conn = self.conn.clone();
+        if let Err(e) = conn.del::<_, ()>(self.key(key)).await {
+            tracing::warn!("Redis DEL {} failed: {}", key, e);
+        }
+    }
+
+    async fn incr(&self, key: &str, by: i64, ttl: Option<Duration>) -> Option<i64> {
+        let mut conn = self.conn.clone();
+        let ttl = ttl.map(|ttl| ttl_millis(ttl).to_string()).unwrap_or_default();
+        match Script::new(INCR_SCRIPT)
+            .key(self.key(key))
+            .arg(by)
+            .arg(ttl)
+            .invoke_async(&mut conn)
+            .await
+        {
+            Ok(value) => Some(value),
+            Err(e) => {
+                tracing::warn!("Redis INCRBY {} failed: {}", key, e);
+                None
+            }
+        }
+    }
+
+    async fn compare_and_set(&self, key: &str, expected: Option<&str>, value: String, ttl: Duration) -> bool {
+        let mut conn = self.conn.clone();
+        match Script::new(COMPARE_AND_SET_SCRIPT)
+            .key(self.key(key))
+            .arg(if expected.is_some() { "1" } else { "0" })
+            .arg(expected.unwrap_or_default())
+            .arg(value)
+            .arg(ttl_millis(ttl))
+            .invoke_async::<i64>(&mut conn)
+            .await
+        {
+            Ok(swapped) => swapped == 1,
+            Err(e) => {
+                tracing::warn!("Redis compare-and-set {} failed: {}", key, e);
+                false
+            }
+        }
+    }
+}
@@ -0,0 +1,336 @@
+use chrono::{DateTime, Utc};
+use std::collections::HashSet;
+use crate::models::{CryptoToken, DailyReport, Event, EventKind, TokenChange};
+use crate::ordering::cmp_f64;
+
+// Gainers and losers listed in the daily report
+pub const TOP_MOVERS: usize = 5;
+// A favorite makes the report once its 24h price change exceeds this, either way
+pub const FAVORITE_MOVE_PERCENT: f64 = 5.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Json,
+    Markdown,
+}
+
+impl ReportFormat {
+    pub fn parse(format: Option<&str>) -> Result<Self, String> {
+        match format.map(str::to_lowercase).as_deref() {
+            None | Some("json") => Ok(ReportFormat::Json),
+            Some("markdown") | Some("md") => Ok(ReportFormat::Markdown),
+            Some(other) => Err(format!("Unknown format '{}': use json or markdown", other)),
+        }
+    }
+}
+
+// Assembles the report from the stored tokens, the starred token ids and the events of
+// the last day. Tokens with a non-finite market cap or 24h change are left out, as in
+// /api/stats.
+pub fn build(tokens: &[CryptoToken], favorites: &HashSet<String>, events: Vec<Event>, at: DateTime<Utc>) -> DailyReport {
+    let usable: Vec<&CryptoToken> = tokens
+        .iter()
+        .filter(|t| t.market_cap.is_finite() && t.price_change_percentage_24h.is_finite())
+        .collect();
+
+    let total_market_cap: f64 = usable.iter().map(|t| t.market_cap).sum();
+    let previous_total_market_cap: f64 = usable.iter().map(|t| previous_market_cap(t)).sum();
+    let change = (total_market_cap / previous_total_market_cap - 1.0) * 100.0;
+
+    let (top_gainers, top_losers) = top_movers(tokens);
+
+    let mut favorite_moves: Vec<&CryptoToken> = usable
+        .iter()
+        .copied()
+        .filter(|t| favorites.contains(&t.token_id) && t.price_change_percentage_24h.abs() > FAVORITE_MOVE_PERCENT)
+        .collect();
+    favorite_moves.sort_by(|a, b| cmp_f64(b.price_change_percentage_24h.abs(), a.price_change_percentage_24h.abs()));
+
+    DailyReport {
+        generated_at: at,
+        total_market_cap,
+        previous_total_market_cap,
+        market_cap_change_percentage: change.is_finite().then_some(change),
+        top_gainers,
+        top_losers,
+        favorite_moves: favorite_moves.into_iter().map(TokenChange::from).collect(),
+        events,
+    }
+}
+
+// The TOP_MOVERS biggest 24h gainers and losers, biggest move first. Tokens with a
+// non-finite market cap or 24h change are left out; ties keep list order, which is
+// market cap order.
+pub fn top_movers(tokens: &[CryptoToken]) -> (Vec<TokenChange>, Vec<TokenChange>) {
+    let mut by_change: Vec<&CryptoToken> = tokens
+        .iter()
+        .filter(|t| t.market_cap.is_finite() && t.price_change_percentage_24h.is_finite())
+        .collect();
+    by_change.sort_by(|a, b| cmp_f64(b.price_change_percentage_24h, a.price_change_percentage_24h));
+    let gainers = by_change.iter().filter(|t| t.price_change_percentage_24h > 0.0).take(TOP_MOVERS);
+    let losers = by_change.iter().rev().filter(|t| t.price_change_percentage_24h < 0.0).take(TOP_MOVERS);
+    (gainers.map(|t| TokenChange::from(*t)).collect(), losers.map(|t| TokenChange::from(*t)).collect())
+}
+
+// Market cap before the last 24h move; a token whose change can't be undone (-100%)
+// counts as it is now
+fn previous_market_cap(token: &CryptoToken) -> f64 {
+    let factor = 1.0 + token.price_change_percentage_24h / 100.0;
+    if factor > 0.0 {
+        token.market_cap / factor
+    } else {
+        token.market_cap
+    }
+}
+
+pub fn to_markdown(report: &DailyReport) -> String {
+    let mut out = format!("# Daily report for {}\n\n", report.generated_at.format("%Y-%m-%d"));
+    out.push_str(&format!("Total market cap: {}", usd(report.total_market_cap)));
+    match report.market_cap_change_percentage {
+        Some(change) => out.push_str(&format!(
+            " ({} vs {} 24h ago)\n",
+            percent(change),
+            usd(report.previous_total_market_cap)
+        )),
+        None => out.push('\n'),
+    }
+
+    section(&mut out, "Top gainers", &report.top_gainers);
+    section(&mut out, "Top losers", &report.top_losers);
+    section(&mut out, &format!("Favorites moving more than {}%", FAVORITE_MOVE_PERCENT), &report.favorite_moves);
+
+    out.push_str("\n## Events in the last 24 hours\n\n");
+    if report.events.is_empty() {
+        out.push_str("None\n");
+    } else {
+        let rows: Vec<Vec<String>> = report
+            .events
+            .iter()
+            .map(|e| {
+                vec![
+                    e.at.format("%H:%M").to_string(),
+                    e.token_id.clone(),
+                    event_name(&e.kind),
+                    price(e.old_value),
+                    price(e.new_value),
+                ]
+            })
+            .collect();
+        out.push_str(&table(&["Time (UTC)", "Token", "Event", "From", "To"], &[false, false, false, true, true], &rows));
+    }
+    out
+}
+
+fn section(out: &mut String, title: &str, tokens: &[TokenChange]) {
+    out.push_str(&format!("\n## {}\n\n", title));
+    if tokens.is_empty() {
+        out.push_str("None\n");
+        return;
+    }
+    let rows: Vec<Vec<String>> = tokens
+        .iter()
+        .map(|t| vec![format!("{} ({})", t.name, t.symbol.to_uppercase()), price(t.current_price), percent(t.change_percentage)])
+        .collect();
+    out.push_str(&table(&["Token", "Price", "24h"], &[false, true, true], &rows));
+}
+
+fn event_name(kind: &EventKind) -> String {
+    match kind {
+        EventKind::AthBreak => "New all-time high".to_string(),
+        EventKind::LargeMove { change_percentage } => format!("Large move ({})", percent(*change_percentage)),
+    }
+}
+
+// A markdown table padded so the columns line up in plain text too; `right` marks the
+// columns aligned to the right, numbers mostly
+fn table(headers: &[&str], right: &[bool], rows: &[Vec<String>]) -> String {
+    let widths: Vec<usize> = headers
+        .iter()
+        .enumerate()
+        .map(|(i, h)| rows.iter().map(|r| r[i].chars().count()).chain([h.chars().count(), 3]).max().unwrap_or(3))
+        .collect();
+    let pad = |cell: &str, i: usize| {
+        if right[i] {
+            format!("{:>width$}", cell, width = widths[i])
+        } else {
+            format!("{:<width$}", cell, width = widths[i])
+        }
+    };
+    let line = |cells: Vec<String>| format!("| {} |\n", cells.join(" | "));
+
+    let mut out = line(headers.iter().enumerate().map(|(i, h)| pad(h, i)).collect());
+    out.push_str(&line(
+        widths
+            .iter()
+            .zip(right)
+            .map(|(w, r)| if *r { format!("{}:", "-".repeat(w - 1)) } else { format!(":{}", "-".repeat(w - 1)) })
+            .collect(),
+    ));
+    for row in rows {
+        out.push_str(&line(row.iter().enumerate().map(|(i, c)| pad(c, i)).collect()));
+    }
+    out
+}
+
+// $1.23T, $456.70B, $12.00M, or the plain amount below a million
+fn usd(value: f64) -> String {
+    let (scaled, suffix) = match value.abs() {
+        v if v >= 1e12 => (value / 1e12, "T"),
+        v if v >= 1e9 => (value / 1e9, "B"),
+        v if v >= 1e6 => (value / 1e6, "M"),
+        _ => (value, ""),
+    };
+    format!("${:.2}{}", scaled, suffix)
+}
+
+// Two decimals, more for prices under a dollar so they don't all read $0.00
+fn price(value: f64) -> String {
+    if value.abs() >= 1.0 {
+        format!("${:.2}", value)
+    } else {
+        format!("${:.6}", value)
+    }
+}
+
+fn percent(value: f64) -> String {
+    format!("{:+.2}%", value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn token(token_id: &str, symbol: &str, price: f64, market_cap: f64, change: f64) -> CryptoToken {
+        serde_json::from_value(serde_json::json!({
+            "token_id": token_id,
+            "symbol": symbol,
+            "name": token_id[..1].to_uppercase() + &token_id[1..],
+            "current_price": price,
+            "market_cap": market_cap,
+            "volume_24h": 0.0,
+            "price_change_24h": 0.0,
+            "price_change_percentage_24h": change,
+            "last_updated": "2026-10-16T08:00:00Z",
+            "is_favorite": false,
+        }))
+        .unwrap()
+    }
+
+    fn at() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, 16, 9, 30, 0).unwrap()
+    }
+
+    fn market() -> Vec<CryptoToken> {
+        let mut broken = token("broken", "brk", 1.0, 1.0, 50.0);
+        broken.market_cap = f64::NAN;
+        vec![
+            token("bitcoin", "btc", 60000.0, 1.2e12, 2.0),
+            token("ethereum", "eth", 3000.0, 3.6e11, -4.0),
+            token("solana", "sol", 150.0, 7.0e10, 8.0),
+            token("dogecoin", "doge", 0.125, 1.8e10, -6.5),
+            token("tether", "usdt", 1.0, 1.1e11, 0.0),
+            broken,
+        ]
+    }
+
+    #[test]
+    fn test_report_totals_and_movers() {
+        let favorites: HashSet<String> = ["dogecoin", "ethereum", "broken"].iter().map(|s| s.to_string()).collect();
+        let report = build(&market(), &favorites, Vec::new(), at());
+
+        assert_eq!(report.total_market_cap, 1.2e12 + 3.6e11 + 7.0e10 + 1.8e10 + 1.1e11);
+        let previous = 1.2e12 / 1.02 + 3.6e11 / 0.96 + 7.0e10 / 1.08 + 1.8e10 / 0.935 + 1.1e11;
+        assert!((report.previous_total_market_cap - previous).abs() < 1.0);
+        let change = report.market_cap_change_percentage.unwrap();
+        assert!((change - (report.total_market_cap / previous - 1.0) * 100.0).abs() < 1e-9);
+
+        let ids = |changes: &[TokenChange]| changes.iter().map(|c| c.token_id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids(&report.top_gainers), ["solana", "bitcoin"]);
+        assert_eq!(ids(&report.top_losers), ["dogecoin", "ethereum"]);
+        // Ethereum's 4% stays under the threshold; the NaN market cap keeps "broken" out
+        assert_eq!(ids(&report.favorite_moves), ["dogecoin"]);
+    }
+
+    #[test]
+    fn test_empty_market_has_no_change() {
+        let report = build(&[], &HashSet::new(), Vec::new(), at());
+        assert_eq!(report.total_market_cap, 0.0);
+        assert_eq!(report.market_cap_change_percentage, None);
+        assert!(report.top_gainers.is_empty() && report.top_losers.is_empty());
+    }
+
+    #[test]
+    fn test_unknown_format_rejected() {
+        assert_eq!(ReportFormat::parse(None), Ok(ReportFormat::Json));
+        assert_eq!(ReportFormat::parse(Some("Markdown")), Ok(ReportFormat::Markdown));
+        assert!(ReportFormat::parse(Some("html")).is_err());
+    }
+
+    #[test]
+    fn test_markdown_snapshot() {
+        let favorites: HashSet<String> = ["dogecoin".to_string()].into();
+        let events = vec![
+            Event {
+                token_id: "solana".to_string(),
+                kind: EventKind::LargeMove { change_percentage: 12.5 },
+                old_value: 133.33,
+                new_value: 150.0,
+                at: Utc.with_ymd_and_hms(2026, 10, 16, 7, 5, 0).unwrap(),
+            },
+            Event {
+                token_id: "bitcoin".to_string(),
+                kind: EventKind::AthBreak,
+                old_value: 59000.0,
+                new_value: 60000.0,
+                at: Utc.with_ymd_and_hms(2026, 10, 15, 22, 40, 0).unwrap(),
+            },
+        ];
+        let markdown = to_markdown(&build(&market(), &favorites, events, at()));
+
+        let expected = "\
+# Daily report for 2026-10-16
+
+Total market cap: $1.76T (+0.71% vs $1.75T 24h ago)
+
+## Top gainers
+
+| Token         |     Price |    24h |
+| :------------ | --------: | -----: |
+| Solana (SOL)  |   $150.00 | +8.00% |
+| Bitcoin (BTC) | $60000.00 | +2.00% |
+
+## Top losers
+
+| Token           |     Price |    24h |
+| :-------------- | --------: | -----: |
+| Dogecoin (DOGE) | $0.125000 | -6.50% |
+| Ethereum (ETH)  |  $3000.00 | -4.00% |
+
+## Favorites moving more than 5%
+
+| Token           |     Price |    24h |
+| :-------------- | --------: | -----: |
+| Dogecoin (DOGE) | $0.125000 | -6.50% |
+
+## Events in the last 24 hours
+
+| Time (UTC) | Token   | Event                |      From |        To |
+| :--------- | :------ | :------------------- | --------: | --------: |
+| 07:05      | solana  | Large move (+12.50%) |   $133.33 |   $150.00 |
+| 22:40      | bitcoin | New all-time high    | $59000.00 | $60000.00 |
+";
+        assert_eq!(markdown, expected);
+    }
+
+    #[test]
+    fn test_markdown_without_movers_or_events() {
+        let markdown = to_markdown(&build(&[token("tether", "usdt", 1.0, 1.1e11, 0.0)], &HashSet::new(), Vec::new(), at()));
+        assert_eq!(
+            markdown,
+            "# Daily report for 2026-10-16\n\nTotal market cap: $110.00B (+0.00% vs $110.00B 24h ago)\n\n\
+             ## Top gainers\n\nNone\n\n## Top losers\n\nNone\n\n## Favorites moving more than 5%\n\nNone\n\n\
+             ## Events in the last 24 hours\n\nNone\n"
+        );
+    }
+}
@@ -0,0 +1,233 @@
+use actix_web::http::header;
+use actix_web::middleware::{from_fn, DefaultHeaders};
+use actix_web::{error, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, TimeZone, Utc};
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
+use crate::{auth, envelope, handlers, models::ErrorResponse, openapi::ApiDoc, v2};
+
+// Single source of truth for the API routes: expands to one function per route group
+// that mounts it under a version's prefix, and one listing its (method, path) pairs for
+// the OpenAPI sync test.
+macro_rules! api_routes {
+    ($($group:ident, $list:ident ($bad_request:path) {
+        $($method:ident $path:literal => $handler:path),* $(,)?
+    })*) => {
+        $(fn $group(prefix: &str) -> actix_web::Scope {
+            web::scope(prefix)
+                .app_data(web::PathConfig::default().error_handler($bad_request))
+                .app_data(web::QueryConfig::default().error_handler($bad_request))
+                .app_data(web::JsonConfig::default().error_handler($bad_request))
+                $(.route($path, web::$method().to($handler)))*
+        }
+
+        fn $list() -> Vec<(&'static str, &'static str)> {
+            vec![$((stringify!($method), $path)),*]
+        })*
+    };
+}
+
+api_routes! {
+    // Enveloped responses; see v2.rs
+    enveloped_scope, enveloped_routes(envelope::bad_request) {
+        get "/stats" => v2::get_stats,
+        get "/tokens" => v2::get_tokens,
+        get "/favorites" => v2::get_favorites,
+        get "/search" => v2::search_tokens,
+    }
+
+    current_scope, current_routes(bad_request) {
+        get "/tokens" => handlers::get_tokens,
+        // Registered before /tokens/{id} so they aren't captured as a token id
+        get "/tokens/export.json" => handlers::export_tokens,
+        get "/tokens/batch" => handlers::get_tokens_batch,
+        get "/tokens/{id}" => handlers::get_token,
+        get "/tokens/{id}/supply" => handlers::get_token_supply,
+        get "/tokens/{id}/profile" => handlers::get_token_profile,
+        get "/tokens/{id}/change" => handlers::get_historical_change,
+        get "/tokens/{id}/price_at" => handlers::get_price_at,
+        get "/tokens/{id}/history" => handlers::get_stored_history,
+        get "/tokens/{id}/image" => handlers::get_token_image,
+        get "/tokens/{id}/annotation" => handlers::get_annotation,
+        put "/tokens/{id}/annotation" => handlers::put_annotation,
+        delete "/tokens/{id}/annotation" => handlers::delete_annotation,
+        get "/events" => handlers::get_events,
+        get "/export/tokens.ndjson" => handlers::export_tokens_ndjson,
+        post "/import/tokens" => handlers::import_tokens,
+        put "/admin/webhook" => handlers::put_webhook,
+        get "/admin/webhook/deliveries" => handlers::get_webhook_deliveries,
+        post "/admin/tokens/{id}/hide" => handlers::hide_token,
+        post "/admin/tokens/{id}/unhide" => handlers::unhide_token,
+        delete "/admin/tokens/{id}" => handlers::delete_token,
+        post "/admin/purge" => handlers::purge_stale_tokens,
+        post "/admin/repair" => handlers::repair_data,
+        post "/tokens/favorite" => handlers::toggle_favorite,
+        get "/favorites" => handlers::get_favorites,
+        post "/users" => handlers::create_user,
+        post "/favorites/bulk" => handlers::bulk_favorites,
+        get "/favorites/export" => handlers::export_favorites,
+        post "/favorites/import" => handlers::import_favorites,
+        put "/favorites/{id}/meta" => handlers::update_favorite_meta,
+        get "/portfolio/transactions" => handlers::list_transactions,
+        post "/portfolio/transactions" => handlers::create_transaction,
+        put "/portfolio/transactions/{id}" => handlers::update_transaction,
+        delete "/portfolio/transactions/{id}" => handlers::delete_transaction,
+        get "/portfolio/summary" => handlers::get_portfolio_summary,
+        get "/alerts" => handlers::list_alerts,
+        post "/alerts" => handlers::create_alert,
+        delete "/alerts/{id}" => handlers::delete_alert,
+        get "/alerts/{id}/triggers" => handlers::get_alert_triggers,
+        get "/search" => handlers::search_tokens,
+        get "/search/coins" => handlers::search_coins,
+        get "/convert" => handlers::convert,
+        get "/history/{id}/{days}" => handlers::get_historical_data,
+        get "/stats" => handlers::get_stats,
+        get "/overview" => handlers::get_overview,
+        get "/correlation" => handlers::get_correlation,
+        get "/dominance" => handlers::get_dominance,
+        get "/market/history" => handlers::get_market_history,
+        get "/report/daily" => handlers::get_daily_report,
+        get "/currencies" => handlers::get_currencies,
+        get "/categories" => handlers::get_categories,
+        get "/exchange-rates" => handlers::get_exchange_rates,
+        get "/exchange-rates/{code}" => handlers::get_exchange_rate,
+        get "/gainers" => handlers::get_gainers,
+        get "/losers" => handlers::get_losers,
+        get "/near_ath" => handlers::get_near_ath,
+        get "/screener" => handlers::get_screener,
+        get "/cache/status" => handlers::get_cache_status,
+        get "/debug/cache" => handlers::debug_cache,
+    }
+}
+
+// Which route group a version serves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteGroup {
+    // Plain bodies, with the envelope opt-in
+    Current,
+    // data/meta/error envelopes throughout
+    Enveloped,
+}
+
+impl RouteGroup {
+    // (method, path) of every route, relative to the version's prefix
+    pub fn routes(self) -> Vec<(&'static str, &'static str)> {
+        match self {
+            RouteGroup::Current => current_routes(),
+            RouteGroup::Enveloped => enveloped_routes(),
+        }
+    }
+}
+
+// When a version stops being served, and what replaces it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deprecation {
+    pub since: DateTime<Utc>,
+    pub sunset: DateTime<Utc>,
+    pub successor: &'static str,
+}
+
+impl Deprecation {
+    // Deprecation (RFC 9745), Sunset (RFC 8594) and a Link to the successor, sent on every
+    // response of the deprecated version
+    fn headers(&self) -> DefaultHeaders {
+        DefaultHeaders::new()
+            .add(("Deprecation", format!("@{}", self.since.timestamp())))
+            .add(("Sunset", self.sunset.format("%a, %d %b %Y %H:%M:%S GMT").to_string()))
+            .add((header::LINK, format!("<{}>; rel=\"successor-version\"", self.successor)))
+    }
+}
+
+// One mounted copy of the API
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApiVersion {
+    pub prefix: &'static str,
+    pub group: RouteGroup,
+    pub deprecation: Option<Deprecation>,
+}
+
+impl ApiVersion {
+    // (method, path) of every route as mounted under this version
+    pub fn routes(&self) -> Vec<(&'static str, String)> {
+        self.group.routes().into_iter().map(|(method, path)| (method, format!("{}{}", self.prefix, path))).collect()
+    }
+
+    fn mount(&self, cfg: &mut web::ServiceConfig) {
+        let deprecation = self.deprecation.map(|d| d.headers()).unwrap_or_default();
+        match self.group {
+            RouteGroup::Current => {
+                cfg.service(
+                    current_scope(self.prefix)
+                        .wrap(from_fn(auth::require_api_token))
+                        .wrap(from_fn(envelope::opt_in))
+                        .wrap(deprecation),
+                );
+            }
+            RouteGroup::Enveloped => {
+                cfg.service(enveloped_scope(self.prefix).wrap(from_fn(auth::require_api_token)).wrap(deprecation));
+            }
+        }
+    }
+}
+
+// The unversioned paths the API was first served under, now an alias of /api/v1
+pub const LEGACY_PREFIX: &str = "/api";
+
+// The API versions an app serves, mounted in order: a version whose prefix starts
+// another's (`/api` before `/api/v1`) has to come after it or it would take its paths
+#[derive(Debug, Clone)]
+pub struct VersionRegistry {
+    versions: Vec<ApiVersion>,
+}
+
+impl VersionRegistry {
+    pub fn new(versions: Vec<ApiVersion>) -> Self {
+        Self { versions }
+    }
+
+    // /api/v2 (enveloped), /api/v1, and the unversioned /api alias of v1, deprecated
+    pub fn standard() -> Self {
+        let day = |y, m, d| Utc.with_ymd_and_hms(y, m, d, 0, 0, 0).unwrap();
+        Self::new(vec![
+            ApiVersion { prefix: "/api/v2", group: RouteGroup::Enveloped, deprecation: None },
+            ApiVersion { prefix: "/api/v1", group: RouteGroup::Current, deprecation: None },
+            ApiVersion {
+                prefix: LEGACY_PREFIX,
+                group: RouteGroup::Current,
+                deprecation: Some(Deprecation { since: day(2026, 10, 16), sunset: day(2027, 4, 16), successor: "/api/v1" }),
+            },
+        ])
+    }
+
+    pub fn versions(&self) -> &[ApiVersion] {
+        &self.versions
+    }
+
+    pub fn configure(&self, cfg: &mut web::ServiceConfig) {
+        // Docs go first: the /api scope would otherwise swallow their paths
+        cfg.service(SwaggerUi::new("/api/docs/{_:.*}").url("/api/openapi.json", ApiDoc::openapi()))
+            .route("/metrics", web::get().to(handlers::metrics))
+            .route("/health/live", web::get().to(handlers::health_live))
+            .route("/health/ready", web::get().to(handlers::health_ready));
+        for version in &self.versions {
+            version.mount(cfg);
+        }
+    }
+}
+
+// Every (method, path) the standard versions serve; the OpenAPI sync test checks these
+// against the spec
+pub fn registered_routes() -> Vec<(&'static str, String)> {
+    VersionRegistry::standard().versions().iter().flat_map(ApiVersion::routes).collect()
+}
+
+// Extractor failures (a non-numeric query value, malformed JSON) get the same JSON
+// error body as everything else instead of actix's plain-text default
+fn bad_request<E: std::fmt::Display + std::fmt::Debug + 'static>(err: E, _req: &HttpRequest) -> actix_web::Error {
+    let response = HttpResponse::BadRequest().json(ErrorResponse::new(err.to_string()));
+    error::InternalError::from_response(err, response).into()
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    VersionRegistry::standard().configure(cfg);
+}
@@ -0,0 +1,190 @@
+use mongodb::bson::{doc, Document};
+use crate::models::CryptoToken;
+use crate::ordering::cmp_f64;
+
+pub const DEFAULT_FUZZY_MIN_SCORE: f64 = 0.7;
+
+// Shorter queries are prefixes being typed, which the text index can't match
+pub const MIN_TEXT_SEARCH_CHARS: usize = 3;
+
+// Whether the text index can answer `query`: whole words of letters and digits. Short
+// queries and ones with symbols like `$` or `-` (a negation to $text) go through the
+// substring filter instead.
+pub fn uses_text_index(query: &str) -> bool {
+    query.trim().chars().count() >= MIN_TEXT_SEARCH_CHARS
+        && query.chars().all(|c| c.is_alphanumeric() || c.is_whitespace())
+}
+
+// How well a token matches a lowercased query, lower is better: exact symbol, symbol
+// prefix, name substring, then id or symbol substring. None when nothing contains it.
+pub fn relevance(token: &CryptoToken, query_lower: &str) -> Option<u8> {
+    let symbol = token.symbol.to_lowercase();
+    if symbol == query_lower {
+        Some(0)
+    } else if symbol.starts_with(query_lower) {
+        Some(1)
+    } else if token.name.to_lowercase().contains(query_lower) {
+        Some(2)
+    } else if token.token_id.to_lowercase().contains(query_lower) || symbol.contains(query_lower) {
+        Some(3)
+    } else {
+        None
+    }
+}
+
+// Case-insensitive substring matches against name, symbol, or token id, most relevant
+// first and by market cap within the same relevance
+pub fn substring_matches(tokens: &[CryptoToken], query: &str) -> Vec<CryptoToken> {
+    let query_lower = query.to_lowercase();
+
+    let mut ranked: Vec<(u8, &CryptoToken)> = tokens
+        .iter()
+        .filter_map(|t| relevance(t, &query_lower).map(|rank| (rank, t)))
+        .collect();
+
+    ranked.sort_by(|(a_rank, a), (b_rank, b)| {
+        a_rank
+            .cmp(b_rank)
+            .then_with(|| cmp_f64(b.market_cap, a.market_cap))
+            .then_with(|| a.token_id.cmp(&b.token_id))
+    });
+    ranked.into_iter().map(|(_, t)| t.clone()).collect()
+}
+
+// MongoDB filter for the same substring match, so only candidates leave the database
+pub fn substring_filter(query: &str) -> Document {
+    let pattern = escape_regex(query);
+    doc! {
+        "$or": [
+            { "name": { "$regex": &pattern, "$options": "i" } },
+            { "symbol": { "$regex": &pattern, "$options": "i" } },
+            { "token_id": { "$regex": &pattern, "$options": "i" } },
+        ]
+    }
+}
+
+// The query as a regex matching it literally
+fn escape_regex(query: &str) -> String {
+    let mut escaped = String::with_capacity(query.len());
+    for c in query.chars() {
+        if "\\^$.|?*+()[]{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Best Jaro-Winkler similarity of the query against the token's name and symbol
+pub fn fuzzy_score(token: &CryptoToken, query_lower: &str) -> f64 {
+    let name_score = strsim::jaro_winkler(&token.name.to_lowercase(), query_lower);
+    let symbol_score = strsim::jaro_winkler(&token.symbol.to_lowercase(), query_lower);
+    name_score.max(symbol_score)
+}
+
+// Tokens scoring at least `min_score`, best match first
+pub fn fuzzy_matches(tokens: Vec<CryptoToken>, query: &str, min_score: f64) -> Vec<CryptoToken> {
+    let query_lower = query.to_lowercase();
+
+    let mut scored: Vec<(f64, CryptoToken)> = tokens
+        .into_iter()
+        .map(|t| (fuzzy_score(&t, &query_lower), t))
+        .filter(|(score, _)| *score >= min_score)
+        .collect();
+
+    scored.sort_by(|a, b| cmp_f64(b.0, a.0));
+    scored.into_iter().map(|(_, t)| t).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::test_token;
+
+    fn token(token_id: &str, symbol: &str, name: &str) -> CryptoToken {
+        CryptoToken { symbol: symbol.to_string(), name: name.to_string(), ..test_token(token_id, 1.0, 1000000.0) }
+    }
+
+    fn sample() -> Vec<CryptoToken> {
+        vec![
+            token("bitcoin", "btc", "Bitcoin"),
+            token("ethereum", "eth", "Ethereum"),
+            token("bitcoin-cash", "bch", "Bitcoin Cash"),
+            token("solana", "sol", "Solana"),
+        ]
+    }
+
+    #[test]
+    fn test_text_index_only_for_longer_plain_queries() {
+        assert!(uses_text_index("bitcoin"));
+        assert!(uses_text_index("bitcoin wrapped"));
+        assert!(!uses_text_index("bt"));
+        assert!(!uses_text_index(" b "));
+        assert!(!uses_text_index("$btc"));
+        assert!(!uses_text_index("usd.e"));
+        assert!(!uses_text_index("shiba-inu"));
+    }
+
+    #[test]
+    fn test_substring_matches_name_symbol_and_id() {
+        let ids: Vec<String> = substring_matches(&sample(), "BIT")
+            .into_iter()
+            .map(|t| t.token_id)
+            .collect();
+        assert_eq!(ids, vec!["bitcoin", "bitcoin-cash"]);
+
+        assert_eq!(substring_matches(&sample(), "sol").len(), 1);
+        assert!(substring_matches(&sample(), "bitcon").is_empty());
+    }
+
+    #[test]
+    fn test_substring_matches_ranked_by_relevance() {
+        let mut tokens = vec![
+            token("wrapped-bitcoin", "wbtc", "Wrapped Bitcoin"),
+            token("bitcoin", "btc", "Bitcoin"),
+            token("btc-cash-token", "xyz", "Cash Token"),
+            token("bitcoin-gold", "btg", "Bitcoin Gold"),
+            token("bitcoin-token", "btcb", "Bitcoin BEP2"),
+        ];
+        // Market cap only orders tokens of the same relevance
+        tokens[0].market_cap = 1e12;
+
+        let ids: Vec<String> = substring_matches(&tokens, "btc").into_iter().map(|t| t.token_id).collect();
+        assert_eq!(ids, vec!["bitcoin", "bitcoin-token", "wrapped-bitcoin", "btc-cash-token"]);
+
+        let ids: Vec<String> = substring_matches(&tokens, "bitcoin").into_iter().map(|t| t.token_id).collect();
+        assert_eq!(ids, vec!["wrapped-bitcoin", "bitcoin", "bitcoin-gold", "bitcoin-token"]);
+    }
+
+    #[test]
+    fn test_substring_filter_matches_the_query_literally() {
+        let filter = substring_filter("a.b(");
+        let clauses = filter.get_array("$or").unwrap();
+        assert_eq!(clauses.len(), 3);
+        let name = clauses[0].as_document().unwrap().get_document("name").unwrap();
+        assert_eq!(name.get_str("$regex").unwrap(), r"a\.b\(");
+        assert_eq!(name.get_str("$options").unwrap(), "i");
+    }
+
+    #[test]
+    fn test_fuzzy_matches_typo_ranked_by_similarity() {
+        let results = fuzzy_matches(sample(), "bitcon", DEFAULT_FUZZY_MIN_SCORE);
+
+        assert!(!results.is_empty());
+        assert_eq!(results[0].token_id, "bitcoin");
+        assert!(results.iter().all(|t| t.token_id != "solana"));
+    }
+
+    #[test]
+    fn test_fuzzy_threshold_filters_weak_matches() {
+        assert!(fuzzy_matches(sample(), "bitcon", 0.99).is_empty());
+        assert!(fuzzy_matches(sample(), "zzzz", DEFAULT_FUZZY_MIN_SCORE).is_empty());
+    }
+
+    #[test]
+    fn test_fuzzy_score_uses_best_of_name_and_symbol() {
+        let eth = token("ethereum", "eth", "Ethereum");
+        assert_eq!(fuzzy_score(&eth, "eth"), 1.0);
+        assert!(fuzzy_score(&eth, "etherum") > 0.9);
+    }
+}
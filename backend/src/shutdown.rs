@@ -0,0 +1,211 @@
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+// Owns every background task so shutdown can signal them and wait for in-flight work.
+// Tasks run on the runtime the set was created on, not the spawning worker's: each actix
+// worker has its own runtime, dropped when the server stops, which would cancel a cache
+// write before the grace period even began.
+#[derive(Clone)]
+pub struct BackgroundTasks {
+    token: CancellationToken,
+    tasks: Arc<Mutex<JoinSet<()>>>,
+    runtime: Option<Handle>,
+}
+
+impl Default for BackgroundTasks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BackgroundTasks {
+    pub fn new() -> Self {
+        Self {
+            token: CancellationToken::new(),
+            tasks: Arc::default(),
+            runtime: Handle::try_current().ok(),
+        }
+    }
+
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    // Spawns a tracked task. Long-running tasks should select on the token between
+    // iterations; one-shot tasks (like a cache write) can simply run to completion.
+    // The task stays in the caller's span, so its logs keep the request id.
+    pub fn spawn<F, Fut>(&self, task: F)
+    where
+        F: FnOnce(CancellationToken) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let future = task(self.token.clone());
+        let mut tasks = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+
+        // Reap finished tasks so the set doesn't grow with every request
+        while tasks.try_join_next().is_some() {}
+
+        match &self.runtime {
+            Some(runtime) => tasks.spawn_on(future.in_current_span(), runtime),
+            None => tasks.spawn(future.in_current_span()),
+        };
+    }
+
+    // Signals cancellation and waits up to `grace` for tasks to finish their current work.
+    // Returns false if some tasks had to be aborted.
+    pub async fn shutdown(&self, grace: Duration) -> bool {
+        self.token.cancel();
+
+        let mut tasks = {
+            let mut guard = self.tasks.lock().unwrap_or_else(|e| e.into_inner());
+            std::mem::take(&mut *guard)
+        };
+
+        let pending = tasks.len();
+        let drained = tokio::time::timeout(grace, async {
+            while tasks.join_next().await.is_some() {}
+        })
+        .await
+        .is_ok();
+
+        if drained {
+            tracing::info!("All {} background tasks finished", pending);
+        } else {
+            tracing::warn!(
+                "{} background tasks still running after {:?}, aborting",
+                tasks.len(),
+                grace
+            );
+            tasks.shutdown().await;
+        }
+
+        drained
+    }
+}
+
+// Resolves on Ctrl+C or SIGTERM
+pub async fn wait_for_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => tracing::info!("Received Ctrl+C, shutting down"),
+        _ = terminate => tracing::info!("Received SIGTERM, shutting down"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_shutdown_lets_slow_task_finish_current_iteration() {
+        let tasks = BackgroundTasks::new();
+        let started = Arc::new(AtomicUsize::new(0));
+        let completed = Arc::new(AtomicUsize::new(0));
+
+        let (s, c) = (started.clone(), completed.clone());
+        tasks.spawn(move |token| async move {
+            loop {
+                s.fetch_add(1, Ordering::SeqCst);
+                // Simulated slow write that must not be interrupted
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                c.fetch_add(1, Ordering::SeqCst);
+
+                tokio::select! {
+                    _ = token.cancelled() => break,
+                    _ = tokio::time::sleep(Duration::from_millis(10)) => {}
+                }
+            }
+        });
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(tasks.shutdown(Duration::from_secs(2)).await);
+
+        assert!(started.load(Ordering::SeqCst) >= 1);
+        assert_eq!(started.load(Ordering::SeqCst), completed.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_aborts_tasks_exceeding_grace_period() {
+        let tasks = BackgroundTasks::new();
+        tasks.spawn(|_| async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+        });
+
+        assert!(!tasks.shutdown(Duration::from_millis(50)).await);
+        assert!(tasks.is_shutting_down());
+    }
+
+    #[actix_web::test]
+    async fn test_write_spawned_by_a_handler_outlives_the_server() {
+        use actix_web::{web, App, HttpResponse, HttpServer};
+        use std::sync::atomic::AtomicBool;
+
+        let tasks = BackgroundTasks::new();
+        let written = Arc::new(AtomicBool::new(false));
+        let (server_tasks, server_written) = (tasks.clone(), written.clone());
+        let server = HttpServer::new(move || {
+            let (tasks, written) = (server_tasks.clone(), server_written.clone());
+            App::new().route(
+                "/",
+                web::get().to(move || {
+                    let written = written.clone();
+                    // A slow cache write, still running when the server stops
+                    tasks.spawn(move |_| async move {
+                        tokio::time::sleep(Duration::from_millis(300)).await;
+                        written.store(true, Ordering::SeqCst);
+                    });
+                    async { HttpResponse::Ok().finish() }
+                }),
+            )
+        })
+        .workers(1)
+        .disable_signals()
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let address = server.addrs()[0];
+        let server = server.run();
+        let handle = server.handle();
+        let running = tokio::spawn(server);
+
+        let response = reqwest::get(format!("http://{}/", address)).await.unwrap();
+        assert!(response.status().is_success());
+
+        // Stopping drops the worker's runtime, as it does in main before the grace period
+        handle.stop(true).await;
+        running.await.unwrap().unwrap();
+        assert!(tasks.shutdown(Duration::from_secs(2)).await);
+        assert!(written.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,136 @@
+use futures::future::{BoxFuture, FutureExt, Shared};
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex, PoisonError};
+
+type Flight<V> = Shared<BoxFuture<'static, V>>;
+
+// Coalesces concurrent calls for the same key: the first caller starts the work and
+// callers arriving while it runs wait for its result instead of starting their own.
+// The key is released once the work finishes, so a later call starts afresh.
+pub struct SingleFlight<K, V> {
+    in_flight: Arc<Mutex<HashMap<K, Flight<V>>>>,
+}
+
+impl<K, V> Default for SingleFlight<K, V> {
+    fn default() -> Self {
+        Self { in_flight: Arc::new(Mutex::new(HashMap::new())) }
+    }
+}
+
+impl<K, V> SingleFlight<K, V>
+where
+    K: Hash + Eq + Clone + Send + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Joins the call in flight for `key`, or starts `call` when there is none. One caller
+    // going away doesn't cancel the work for the others; work nobody waits on any more
+    // is picked up again by the next caller for the key.
+    pub async fn run<F, Fut>(&self, key: K, call: F) -> V
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V> + Send + 'static,
+    {
+        let flight = {
+            let mut in_flight = self.lock();
+            match in_flight.get(&key) {
+                Some(flight) => flight.clone(),
+                None => {
+                    let work = call();
+                    let registry = Arc::clone(&self.in_flight);
+                    let done = key.clone();
+                    let flight = async move {
+                        let value = work.await;
+                        registry.lock().unwrap_or_else(PoisonError::into_inner).remove(&done);
+                        value
+                    }
+                    .boxed()
+                    .shared();
+                    in_flight.insert(key, flight.clone());
+                    flight
+                }
+            }
+        };
+        flight.await
+    }
+
+    // Keys with a call still running
+    pub fn in_flight(&self) -> usize {
+        self.lock().len()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<K, Flight<V>>> {
+        self.in_flight.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_concurrent_calls_for_one_key_share_the_work() {
+        let flights: SingleFlight<&str, usize> = SingleFlight::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let call = || {
+            let calls = Arc::clone(&calls);
+            move || async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                calls.fetch_add(1, Ordering::SeqCst) + 1
+            }
+        };
+
+        let results = futures::future::join_all((0..5).map(|_| flights.run("bitcoin", call()))).await;
+        assert_eq!(results, vec![1; 5]);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(flights.in_flight(), 0);
+
+        // Finished work isn't reused
+        assert_eq!(flights.run("bitcoin", call()).await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_different_keys_run_separately() {
+        let flights: SingleFlight<u32, u32> = SingleFlight::new();
+        let (a, b) = futures::join!(
+            flights.run(1, || async { 10 }),
+            flights.run(2, || async { 20 }),
+        );
+        assert_eq!((a, b), (10, 20));
+    }
+
+    #[tokio::test]
+    async fn test_work_survives_the_caller_that_started_it() {
+        let flights: Arc<SingleFlight<&str, &str>> = Arc::new(SingleFlight::new());
+        let (release, released) = tokio::sync::oneshot::channel::<()>();
+
+        let starter = tokio::spawn({
+            let flights = Arc::clone(&flights);
+            async move {
+                flights.run("bitcoin", || async move {
+                    let _ = released.await;
+                    "done"
+                }).await
+            }
+        });
+        while flights.in_flight() == 0 {
+            tokio::task::yield_now().await;
+        }
+        starter.abort();
+
+        let joined = tokio::spawn({
+            let flights = Arc::clone(&flights);
+            async move { flights.run("bitcoin", || async { "started again" }).await }
+        });
+        tokio::task::yield_now().await;
+        release.send(()).unwrap();
+        assert_eq!(joined.await.unwrap(), "done");
+    }
+}
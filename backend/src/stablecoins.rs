@@ -0,0 +1,39 @@
+use mongodb::bson::{doc, Document};
+use crate::models::CryptoToken;
+
+// CoinGecko's category id for pegged assets
+pub const STABLECOIN_CATEGORY: &str = "stablecoins";
+
+// Symbols treated as stablecoins even when we never fetched them through the
+// `stablecoins` category. Lowercase, as CoinGecko returns them. Keep this to
+// well-known pegged assets; a wrong entry hides a real token from the lists.
+pub static STABLECOIN_SYMBOLS: &[&str] = &[
+    "usdt", "usdc", "dai", "busd", "tusd", "usdp", "gusd", "fdusd", "pyusd", "usdd", "usde", "usds",
+    "frax", "lusd", "susd", "crvusd", "gho", "eurc", "eurs", "eurt",
+];
+
+pub fn is_stablecoin(token: &CryptoToken) -> bool {
+    token.category.as_deref() == Some(STABLECOIN_CATEGORY) || STABLECOIN_SYMBOLS.contains(&token.symbol.as_str())
+}
+
+// Same test as `is_stablecoin`, as a MongoDB filter matching everything that isn't one
+pub fn exclusion_filter() -> Document {
+    doc! {
+        "category": { "$ne": STABLECOIN_CATEGORY },
+        "symbol": { "$nin": STABLECOIN_SYMBOLS },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbols_are_lowercase_and_unique() {
+        let mut symbols = STABLECOIN_SYMBOLS.to_vec();
+        assert!(symbols.iter().all(|s| s.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())));
+        symbols.sort();
+        symbols.dedup();
+        assert_eq!(symbols.len(), STABLECOIN_SYMBOLS.len());
+    }
+}
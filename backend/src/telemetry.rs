@@ -0,0 +1,165 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpMessage};
+use std::fmt;
+use std::str::FromStr;
+use tracing::field::Empty;
+use tracing::Span;
+use tracing_actix_web::RootSpanBuilder;
+use tracing_subscriber::EnvFilter;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// Longest incoming X-Request-Id we'll reuse; anything else gets a fresh id
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+// Log line format, picked with LOG_FORMAT
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("expected 'text' or 'json', got '{}'", other)),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LogFormat::Text => "text",
+            LogFormat::Json => "json",
+        })
+    }
+}
+
+// Installs the global subscriber. RUST_LOG filters as before (default `info`), and
+// `log` records from dependencies are forwarded into it.
+pub fn init(format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    let result = match format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().with_current_span(true).with_span_list(true).try_init(),
+    };
+    if let Err(e) = result {
+        eprintln!("Logging was already initialized: {}", e);
+    }
+}
+
+// The id every log line of a request carries, from X-Request-Id when the caller sent a usable one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    fn from_request(req: &ServiceRequest) -> Self {
+        let incoming = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.chars().all(|c| c.is_ascii_graphic()));
+
+        match incoming {
+            Some(id) => RequestId(id.to_string()),
+            None => RequestId(uuid::Uuid::new_v4().to_string()),
+        }
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+// Assigns the request id and echoes it back. Has to wrap outside `TracingLogger`
+// so the id is in the request extensions when the root span is built.
+pub async fn request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let request_id = RequestId::from_request(&req);
+    let header_value = HeaderValue::from_str(&request_id.0).ok();
+    req.extensions_mut().insert(request_id);
+
+    let mut res = next.call(req).await?;
+    if let Some(value) = header_value {
+        res.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+    }
+    Ok(res)
+}
+
+// Root span for every request: our request id plus method, path and the final status
+pub struct RequestSpan;
+
+impl RootSpanBuilder for RequestSpan {
+    fn on_request_start(request: &ServiceRequest) -> Span {
+        let request_id = request
+            .extensions()
+            .get::<RequestId>()
+            .map(|id| id.0.clone())
+            .unwrap_or_default();
+
+        tracing::info_span!(
+            "request",
+            request_id = %request_id,
+            method = %request.method(),
+            path = %request.path(),
+            status = Empty,
+        )
+    }
+
+    fn on_request_end<B: MessageBody>(span: Span, outcome: &Result<ServiceResponse<B>, Error>) {
+        let status = match outcome {
+            Ok(response) => response.status(),
+            Err(error) => error.as_response_error().status_code(),
+        };
+        span.record("status", status.as_u16());
+        span.in_scope(|| {
+            if status.is_server_error() {
+                tracing::warn!(status = status.as_u16(), "request finished");
+            } else {
+                tracing::info!(status = status.as_u16(), "request finished");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn test_log_format_parses_case_insensitively() {
+        assert_eq!("json".parse::<LogFormat>(), Ok(LogFormat::Json));
+        assert_eq!("TEXT".parse::<LogFormat>(), Ok(LogFormat::Text));
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+
+    #[test]
+    fn test_incoming_request_id_reused_only_when_sane() {
+        let req = TestRequest::default().insert_header((REQUEST_ID_HEADER, "abc-123")).to_srv_request();
+        assert_eq!(RequestId::from_request(&req).0, "abc-123");
+
+        let long = "x".repeat(MAX_REQUEST_ID_LEN + 1);
+        for bad in ["", "has space", long.as_str()] {
+            let req = TestRequest::default().insert_header((REQUEST_ID_HEADER, bad)).to_srv_request();
+            let generated = RequestId::from_request(&req).0;
+            assert_ne!(generated, bad);
+            assert!(uuid::Uuid::parse_str(&generated).is_ok());
+        }
+    }
+}
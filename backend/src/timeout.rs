@@ -0,0 +1,95 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpResponse};
+use std::time::Duration;
+use crate::config::Config;
+use crate::models::ErrorResponse;
+
+// Caps how long any request may take (REQUEST_TIMEOUT_SECS). On expiry the handler's
+// future is dropped, which cancels whatever it was awaiting, and the caller gets a 503.
+// Work a handler handed to BackgroundTasks is separate and keeps running.
+pub async fn request_timeout(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let Some(limit) = req
+        .app_data::<web::Data<Config>>()
+        .map(|config| Duration::from_secs(config.request_timeout_secs))
+    else {
+        return next.call(req).await;
+    };
+
+    match tokio::time::timeout(limit, next.call(req)).await {
+        Ok(result) => result,
+        Err(_) => {
+            tracing::warn!(timeout_secs = limit.as_secs(), "Request timed out");
+            // The request went down with the handler, so the 503 travels as an error response
+            let response = HttpResponse::ServiceUnavailable().json(ErrorResponse::new("request timed out"));
+            Err(InternalError::from_response("request timed out", response).into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::middleware::from_fn;
+    use actix_web::body::to_bytes;
+    use actix_web::dev::Service;
+    use actix_web::{test, App};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    // Flags when the handler's future is dropped part-way
+    struct DropGuard(Arc<AtomicBool>);
+
+    impl Drop for DropGuard {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_slow_handler_is_cancelled_with_503() {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let finished = Arc::new(AtomicBool::new(false));
+        let (handler_dropped, handler_finished) = (dropped.clone(), finished.clone());
+
+        let mut config = Config::default_for_tests();
+        config.request_timeout_secs = 1;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(config))
+                .wrap(from_fn(request_timeout))
+                .route("/fast", web::get().to(HttpResponse::Ok))
+                .route(
+                    "/slow",
+                    web::get().to(move || {
+                        let guard = DropGuard(handler_dropped.clone());
+                        let finished = handler_finished.clone();
+                        async move {
+                            tokio::time::sleep(Duration::from_secs(5)).await;
+                            finished.store(true, Ordering::SeqCst);
+                            drop(guard);
+                            HttpResponse::Ok().finish()
+                        }
+                    }),
+                ),
+        )
+        .await;
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/fast").to_request()).await;
+        assert_eq!(resp.status(), 200);
+
+        let err = app.call(test::TestRequest::get().uri("/slow").to_request()).await.err().expect("timed out");
+        let resp = err.error_response();
+        assert_eq!(resp.status(), 503);
+        let body: serde_json::Value = serde_json::from_slice(&to_bytes(resp.into_body()).await.unwrap()).unwrap();
+        assert_eq!(body["error"], "request timed out");
+
+        assert!(dropped.load(Ordering::SeqCst));
+        assert!(!finished.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,223 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::cache_store::{CacheStore, MemoryCacheStore};
+use crate::config::Config;
+use crate::models::CryptoToken;
+
+const VERSION_KEY: &str = "tokens:version";
+
+fn list_key(version: i64) -> String {
+    format!("tokens:{}", version)
+}
+
+struct LocalCopy {
+    version: i64,
+    expires_at: Instant,
+    tokens: Arc<Vec<CryptoToken>>,
+}
+
+// Copy of the cached token list so hot reads can skip MongoDB.
+// The list is stored under a versioned key and `invalidate` bumps the version, so a
+// load that read MongoDB before a write lands under a key nobody reads any more.
+// Replicas sharing the store see each other's invalidations the same way.
+pub struct TokenCache {
+    ttl: Duration,
+    store: Arc<dyn CacheStore>,
+    // Decoded list for the current version, so hits skip the JSON round trip
+    local: Mutex<Option<LocalCopy>>,
+}
+
+impl TokenCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            store: Arc::new(MemoryCacheStore::default()),
+            local: Mutex::new(None),
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(Duration::from_secs(config.memory_cache_ttl_secs))
+    }
+
+    pub fn with_store(mut self, store: Arc<dyn CacheStore>) -> Self {
+        self.store = store;
+        self
+    }
+
+    fn local(&self) -> std::sync::MutexGuard<'_, Option<LocalCopy>> {
+        self.local.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub async fn get(&self) -> Option<Arc<Vec<CryptoToken>>> {
+        if self.ttl.is_zero() {
+            return None;
+        }
+
+        let version = self.generation().await;
+        if let Some(copy) = &*self.local() {
+            if copy.version == version && Instant::now() < copy.expires_at {
+                return Some(copy.tokens.clone());
+            }
+        }
+
+        let raw = self.store.get(&list_key(version)).await?;
+        match serde_json::from_str::<Vec<CryptoToken>>(&raw) {
+            Ok(tokens) => {
+                let tokens = Arc::new(tokens);
+                self.keep_local(version, tokens.clone());
+                Some(tokens)
+            }
+            Err(e) => {
+                tracing::warn!("Discarding unreadable cached token list: {}", e);
+                None
+            }
+        }
+    }
+
+    pub async fn set(&self, tokens: Vec<CryptoToken>) -> Arc<Vec<CryptoToken>> {
+        let generation = self.generation().await;
+        self.set_if_current(generation, tokens).await
+    }
+
+    // Taken before reading MongoDB and handed back to `set_if_current`
+    pub async fn generation(&self) -> i64 {
+        self.store
+            .get(VERSION_KEY)
+            .await
+            .and_then(|raw| raw.parse().ok())
+            .unwrap_or(0)
+    }
+
+    // Stores the list for `generation`; if anything was invalidated since, nobody reads it
+    pub async fn set_if_current(&self, generation: i64, tokens: Vec<CryptoToken>) -> Arc<Vec<CryptoToken>> {
+        let tokens = Arc::new(tokens);
+        if self.ttl.is_zero() {
+            return tokens;
+        }
+
+        match serde_json::to_string(&*tokens) {
+            Ok(json) => self.store.set(&list_key(generation), json, self.ttl).await,
+            Err(e) => tracing::warn!("Failed to serialize token list for the cache: {}", e),
+        }
+        self.keep_local(generation, tokens.clone());
+        tokens
+    }
+
+    fn keep_local(&self, version: i64, tokens: Arc<Vec<CryptoToken>>) {
+        let mut local = self.local();
+        // Never trade a newer copy for one from a load that started earlier
+        if local.as_ref().is_none_or(|copy| copy.version <= version) {
+            *local = Some(LocalCopy {
+                version,
+                expires_at: Instant::now() + self.ttl,
+                tokens,
+            });
+        }
+    }
+
+    pub async fn invalidate(&self) {
+        self.store.incr(VERSION_KEY, 1, None).await;
+        *self.local() = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::test_token;
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+    fn token(token_id: &str) -> CryptoToken {
+        test_token(token_id, 1.0, 1000000.0)
+    }
+
+    #[tokio::test]
+    async fn test_hit_until_invalidated() {
+        let cache = TokenCache::new(Duration::from_secs(60));
+        assert!(cache.get().await.is_none());
+
+        cache.set(vec![token("bitcoin"), token("ethereum")]).await;
+        assert_eq!(cache.get().await.unwrap().len(), 2);
+
+        cache.invalidate().await;
+        assert!(cache.get().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_entry_expires_after_ttl() {
+        let cache = TokenCache::new(Duration::from_millis(20));
+        cache.set(vec![token("bitcoin")]).await;
+        assert!(cache.get().await.is_some());
+
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        assert!(cache.get().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_load_started_before_invalidation_is_not_stored() {
+        let cache = TokenCache::new(Duration::from_secs(60));
+
+        let before = cache.generation().await;
+        cache.invalidate().await;
+        cache.set_if_current(before, vec![token("bitcoin")]).await;
+        assert!(cache.get().await.is_none());
+
+        let current = cache.generation().await;
+        cache.set_if_current(current, vec![token("bitcoin")]).await;
+        assert!(cache.get().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_zero_ttl_disables_cache() {
+        let cache = TokenCache::new(Duration::ZERO);
+        cache.set(vec![token("bitcoin")]).await;
+        assert!(cache.get().await.is_none());
+    }
+
+    // The same miss-then-fill steps as the handlers, against a fake collection whose
+    // single token carries the version of the last write as its price
+    async fn load(cache: &TokenCache, db_version: &AtomicU64) -> u64 {
+        if let Some(tokens) = cache.get().await {
+            return tokens[0].current_price as u64;
+        }
+        let generation = cache.generation().await;
+        let mut stored = token("bitcoin");
+        stored.current_price = db_version.load(Ordering::SeqCst) as f64;
+        // Query latency, so writes land while loads are in flight
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        cache.set_if_current(generation, vec![stored]).await[0].current_price as u64
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_no_stale_reads_after_invalidation_under_contention() {
+        let cache = Arc::new(TokenCache::new(Duration::from_secs(60)));
+        let db_version = Arc::new(AtomicU64::new(0));
+        let done = Arc::new(AtomicBool::new(false));
+
+        let readers: Vec<_> = (0..8)
+            .map(|_| {
+                let (cache, db_version, done) = (cache.clone(), db_version.clone(), done.clone());
+                tokio::spawn(async move {
+                    while !done.load(Ordering::SeqCst) {
+                        load(&cache, &db_version).await;
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+
+        for version in 1..=200 {
+            db_version.store(version, Ordering::SeqCst);
+            cache.invalidate().await;
+            // Give loads that started before the write time to try storing it
+            tokio::time::sleep(Duration::from_millis(2)).await;
+            assert!(load(&cache, &db_version).await >= version, "stale read after write {}", version);
+        }
+
+        done.store(true, Ordering::SeqCst);
+        for reader in readers {
+            reader.await.unwrap();
+        }
+    }
+}
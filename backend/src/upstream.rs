@@ -0,0 +1,99 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use crate::config::Config;
+use crate::crypto_service::CryptoServiceError;
+
+// How long a handler waits for a free permit before giving up on upstream
+const DEFAULT_PERMIT_WAIT: Duration = Duration::from_millis(250);
+
+// Bounds the CoinGecko calls handlers make on a request's behalf: at most
+// MAX_CONCURRENT_UPSTREAM in flight, each cut off after UPSTREAM_TIMEOUT_SECS. A burst
+// beyond that gets `Busy` straight away instead of queueing behind a slow upstream.
+#[derive(Clone)]
+pub struct UpstreamGate {
+    permits: Arc<Semaphore>,
+    max_permits: usize,
+    call_timeout: Duration,
+    permit_wait: Duration,
+}
+
+impl UpstreamGate {
+    pub fn new(max_permits: usize, call_timeout: Duration) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(max_permits)),
+            max_permits,
+            call_timeout,
+            permit_wait: DEFAULT_PERMIT_WAIT,
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(config.max_concurrent_upstream, Duration::from_secs(config.upstream_timeout_secs))
+    }
+
+    pub fn with_permit_wait(mut self, permit_wait: Duration) -> Self {
+        self.permit_wait = permit_wait;
+        self
+    }
+
+    pub fn max_permits(&self) -> usize {
+        self.max_permits
+    }
+
+    // Calls currently holding a permit
+    pub fn in_use(&self) -> usize {
+        self.max_permits - self.permits.available_permits()
+    }
+
+    // Runs `call` under a permit and the per-call timeout. The future is dropped on
+    // timeout, which cancels the request.
+    pub async fn run<T, F>(&self, call: F) -> Result<T, CryptoServiceError>
+    where
+        F: Future<Output = Result<T, CryptoServiceError>>,
+    {
+        let _permit = match tokio::time::timeout(self.permit_wait, self.permits.acquire()).await {
+            Ok(Ok(permit)) => permit,
+            _ => return Err(CryptoServiceError::Busy),
+        };
+        tokio::time::timeout(self.call_timeout, call)
+            .await
+            .unwrap_or(Err(CryptoServiceError::Timeout))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_full_gate_turns_callers_away() {
+        let gate = UpstreamGate::new(1, Duration::from_secs(5)).with_permit_wait(Duration::from_millis(10));
+        let (release, released) = tokio::sync::oneshot::channel::<()>();
+
+        let holder = {
+            let gate = gate.clone();
+            tokio::spawn(async move { gate.run(async { Ok(released.await.is_ok()) }).await })
+        };
+        while gate.in_use() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        assert_eq!(gate.run(async { Ok(()) }).await, Err(CryptoServiceError::Busy));
+        release.send(()).unwrap();
+        assert_eq!(holder.await.unwrap(), Ok(true));
+        assert_eq!(gate.in_use(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_slow_call_times_out() {
+        let gate = UpstreamGate::new(2, Duration::from_millis(20));
+        let result = gate.run(async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(())
+        });
+        assert_eq!(result.await, Err(CryptoServiceError::Timeout));
+        assert_eq!(gate.in_use(), 0);
+    }
+}
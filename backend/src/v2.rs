@@ -0,0 +1,179 @@
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use crate::{
+    auth::MaybeUser,
+    config::Config,
+    crypto_service::CryptoService,
+    db::DbClient,
+    envelope,
+    events::EventRecorder,
+    fallback::FallbackProvider,
+    force_fresh::ForceFreshLimiter,
+    handlers,
+    models::{ApiResponse, CryptoToken, FavoritesQuery, ListQuery, StatsQuery, TokenStats, TokensQuery},
+    rate_limiter::RateLimiter,
+    shutdown::BackgroundTasks,
+    token_cache::TokenCache,
+    upstream::UpstreamGate,
+    webhook::WebhookNotifier,
+};
+
+// /api/v2 versions of endpoints whose response shape changed. Each one is a thin
+// adapter: it runs the /api handler and wraps its answer in `ApiResponse`, so the
+// versions share every DB and CoinGecko call and can't drift apart in behavior.
+// Routes without a v2 adapter exist under /api only.
+
+#[utoipa::path(
+    get,
+    path = "/api/v2/tokens",
+    tag = "v2",
+    params(
+        ("sort_by" = Option<String>, Query,
+            description = "market_cap, current_price, volume_24h, price_change_percentage_24h, price_change_percentage_7d, price_change_percentage_30d, price_change_percentage_1y, name or symbol. Tokens without the 7d, 30d or 1y change sorted by are left out"),
+        ("order" = Option<String>, Query, description = "asc or desc; numbers default to desc, names to asc"),
+        ("page" = Option<u64>, Query, minimum = 1, description = "1-based page number, defaults to 1"),
+        ("per_page" = Option<u64>, Query, minimum = 1, maximum = 250,
+            description = "Page size, defaults to 20. Without page or per_page the whole list is returned"),
+        ("category" = Option<String>, Query, pattern = "^[a-z0-9-]{1,100}$",
+            description = "CoinGecko category id from /api/categories, e.g. decentralized-finance-defi"),
+        ("exclude_stablecoins" = Option<bool>, Query,
+            description = "Leave out tokens in the `stablecoins` category or with a known stablecoin symbol"),
+        ("cursor" = Option<String>, Query,
+            description = "Cursor pagination over the stored tokens: empty for the first page, then the previous page's next cursor. Not combinable with page"),
+        ("include_hidden" = Option<bool>, Query, description = "Also list tokens hidden through /api/admin/tokens/{id}/hide"),
+        ("sparkline" = Option<bool>, Query, description = "Include `sparkline_7d`, hourly prices over the last 7 days, where known"),
+        ("tag" = Option<String>, Query, description = "Only tokens annotated with this tag through /api/tokens/{id}/annotation"),
+        ("direction" = Option<String>, Query,
+            description = "up or down keeps only tokens whose 24h change is positive or negative (unchanged ones are in neither); all, the default, keeps both"),
+        ("fresh" = Option<bool>, Query,
+            description = "Ask upstream even if the cache is young, like `Cache-Control: no-cache`. Once per FORCE_FRESH_INTERVAL_SECS per client, and only when the rate limiter allows"),
+        ("fields" = Option<String>, Query,
+            description = "Comma-separated token fields to return, e.g. `symbol,current_price,ath`; token_id is always included")
+    ),
+    responses(
+        (status = 200, description = "Same list as /api/tokens, enveloped", body = ApiResponse<Vec<CryptoToken>>),
+        (status = 304, description = "If-None-Match matched the current list"),
+        (status = 400, description = "Unknown sort_by/order/direction or field, page out of range, malformed category or invalid/expired cursor",
+            body = ApiResponse<Vec<CryptoToken>>),
+        (status = 503, description = "Rate limited and nothing cached yet", body = ApiResponse<Vec<CryptoToken>>,
+            headers(("Retry-After" = u64, description = "Seconds to wait before retrying")))
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn get_tokens(
+    req: HttpRequest,
+    config: web::Data<Config>,
+    db: web::Data<DbClient>,
+    crypto_service: web::Data<CryptoService>,
+    rate_limiter: web::Data<RateLimiter>,
+    background_tasks: web::Data<BackgroundTasks>,
+    token_cache: web::Data<TokenCache>,
+    notifier: web::Data<WebhookNotifier>,
+    events: web::Data<EventRecorder>,
+    fallback: web::Data<FallbackProvider>,
+    force_limiter: web::Data<ForceFreshLimiter>,
+    user: MaybeUser,
+    query: web::Query<ListQuery>,
+    filter: web::Query<TokensQuery>,
+) -> Result<HttpResponse> {
+    let response = handlers::get_tokens(
+        req, config, db, crypto_service, rate_limiter, background_tasks, token_cache, notifier, events, fallback, force_limiter,
+        user, query, filter,
+    )
+    .await?;
+    Ok(envelope::wrap(response).await)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v2/favorites",
+    tag = "v2",
+    params(
+        ("sort_by" = Option<String>, Query,
+            description = "market_cap, current_price, volume_24h, price_change_percentage_24h, price_change_percentage_7d, price_change_percentage_30d, price_change_percentage_1y, name or symbol. Tokens without the 7d, 30d or 1y change sorted by are left out"),
+        ("order" = Option<String>, Query, description = "asc or desc; numbers default to desc, names to asc"),
+        ("page" = Option<u64>, Query, minimum = 1, description = "1-based page number, defaults to 1"),
+        ("per_page" = Option<u64>, Query, minimum = 1, maximum = 250,
+            description = "Page size, defaults to 20. Without page or per_page every favorite is returned"),
+        ("tag" = Option<String>, Query, description = "Only favorites carrying this tag (case-insensitive)")
+    ),
+    security((), ("api_key" = [])),
+    responses(
+        (status = 200, description = "Same favorites as /api/favorites, enveloped", body = ApiResponse<Vec<CryptoToken>>),
+        (status = 400, description = "Unknown sort_by/order or page out of range", body = ApiResponse<Vec<CryptoToken>>),
+        (status = 401, description = "Unknown API key", body = ApiResponse<Vec<CryptoToken>>),
+        (status = 500, description = "Database error", body = ApiResponse<Vec<CryptoToken>>)
+    )
+)]
+pub async fn get_favorites(
+    config: web::Data<Config>,
+    db: web::Data<DbClient>,
+    user: MaybeUser,
+    query: web::Query<ListQuery>,
+    filter: web::Query<FavoritesQuery>,
+) -> Result<HttpResponse> {
+    let response = handlers::get_favorites(config, db, user, query, filter).await?;
+    Ok(envelope::wrap(response).await)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v2/search",
+    tag = "v2",
+    params(
+        ("q" = Option<String>, Query, description = "Matched against name, symbol and id (case-insensitive)"),
+        ("all" = Option<bool>, Query, description = "With no `q`, return every token by market cap instead of a 400"),
+        ("limit" = Option<usize>, Query, minimum = 1, maximum = 200, description = "Most results to return, defaults to 50"),
+        ("fuzzy" = Option<bool>, Query, description = "Fall back to typo-tolerant matching when nothing matches exactly"),
+        ("min_score" = Option<f64>, Query, minimum = 0.0, maximum = 1.0,
+            description = "Minimum similarity for fuzzy matches, defaults to 0.7"),
+        ("include_hidden" = Option<bool>, Query, description = "Also match tokens hidden through /api/admin/tokens/{id}/hide"),
+        ("debug" = Option<bool>, Query, description = "Add each result's text search `score`; results found another way have none")
+    ),
+    responses(
+        (status = 200, description = "Same matches as /api/search, enveloped", body = ApiResponse<Vec<CryptoToken>>),
+        (status = 400, description = "Missing query without all=true, or invalid limit or min_score", body = ApiResponse<Vec<CryptoToken>>)
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn search_tokens(
+    config: web::Data<Config>,
+    db: web::Data<DbClient>,
+    crypto_service: web::Data<CryptoService>,
+    rate_limiter: web::Data<RateLimiter>,
+    upstream: web::Data<UpstreamGate>,
+    background_tasks: web::Data<BackgroundTasks>,
+    token_cache: web::Data<TokenCache>,
+    notifier: web::Data<WebhookNotifier>,
+    events: web::Data<EventRecorder>,
+    query: web::Query<std::collections::HashMap<String, String>>,
+) -> Result<HttpResponse> {
+    let response = handlers::search_tokens(
+        config, db, crypto_service, rate_limiter, upstream, background_tasks, token_cache, notifier, events, query,
+    )
+    .await?;
+    Ok(envelope::wrap(response).await)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v2/stats",
+    tag = "v2",
+    params(
+        ("exclude_stablecoins" = Option<bool>, Query, description = "Leave pegged assets out of every figure")
+    ),
+    responses(
+        (status = 200, description = "Same statistics as /api/stats, enveloped", body = ApiResponse<TokenStats>,
+            headers(("ETag" = String, description = "Weak validator for If-None-Match"))),
+        (status = 304, description = "If-None-Match matched the current stats")
+    )
+)]
+pub async fn get_stats(
+    req: HttpRequest,
+    config: web::Data<Config>,
+    db: web::Data<DbClient>,
+    token_cache: web::Data<TokenCache>,
+    query: web::Query<StatsQuery>,
+) -> Result<HttpResponse> {
+    let response = handlers::get_stats(req, config, db, token_cache, query).await?;
+    Ok(envelope::wrap(response).await)
+}
@@ -0,0 +1,93 @@
+use futures::TryStreamExt;
+use std::time::Duration;
+use crate::crypto_service::{CryptoService, CryptoServiceError};
+use crate::db::DbClient;
+use crate::envelope::Freshness;
+use crate::events::EventRecorder;
+use crate::handlers::save_tokens_to_cache;
+use crate::models::CryptoToken;
+use crate::rate_limiter::RateLimiter;
+use crate::token_cache::TokenCache;
+use crate::webhook::WebhookNotifier;
+
+// Startup never waits longer than this on CoinGecko
+pub const WARMUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarmUpOutcome {
+    // The stored list is younger than the token TTL, so nothing was fetched
+    AlreadyFresh,
+    // Tokens fetched and stored
+    Fetched(usize),
+    // The rate limiter had no slot
+    RateLimited,
+    Failed,
+    TimedOut,
+}
+
+// Fills the tokens collection before the listener binds, so the first request after a
+// deploy isn't the one paying for the upstream fetch (or getting a 503). Goes through
+// the shared rate limiter like any other call; whatever goes wrong, startup carries on
+// and the first request populates the cache as usual.
+pub struct CacheWarmUp<'a> {
+    pub db: &'a DbClient,
+    pub crypto_service: &'a CryptoService,
+    pub rate_limiter: &'a RateLimiter,
+    pub token_cache: &'a TokenCache,
+    pub notifier: &'a WebhookNotifier,
+    pub events: &'a EventRecorder,
+    // TOKEN_CACHE_TTL_SECS: a stored list younger than this needs no fetch
+    pub max_age_secs: u64,
+    // TOP_TOKENS_LIMIT: how many tokens to fetch, as /api/tokens would
+    pub token_limit: u32,
+}
+
+impl CacheWarmUp<'_> {
+    pub async fn run(&self, limit: Duration) -> WarmUpOutcome {
+        let outcome = tokio::time::timeout(limit, self.warm()).await.unwrap_or(WarmUpOutcome::TimedOut);
+        match outcome {
+            WarmUpOutcome::AlreadyFresh => tracing::info!("Token cache is fresh, skipping warm-up"),
+            WarmUpOutcome::Fetched(count) => tracing::info!(count, "Warmed up the token cache"),
+            WarmUpOutcome::RateLimited => tracing::warn!("Rate limited, leaving the token cache to the first request"),
+            WarmUpOutcome::Failed => tracing::warn!("Token cache warm-up failed, starting anyway"),
+            WarmUpOutcome::TimedOut => tracing::warn!(?limit, "Token cache warm-up timed out, starting anyway"),
+        }
+        outcome
+    }
+
+    async fn warm(&self) -> WarmUpOutcome {
+        let collection = self.db.get_tokens_collection();
+        // An unreadable collection counts as empty: the fetch is still worth trying
+        let stored: Vec<CryptoToken> = match collection.find(None, None).await {
+            Ok(cursor) => cursor.try_collect().await.unwrap_or_default(),
+            Err(e) => {
+                tracing::warn!(error = %e, "Failed to read cached tokens before warm-up");
+                Vec::new()
+            }
+        };
+        if !stored.is_empty() && !Freshness::of(&stored, self.max_age_secs).stale {
+            return WarmUpOutcome::AlreadyFresh;
+        }
+
+        if !self.rate_limiter.try_acquire().await {
+            return WarmUpOutcome::RateLimited;
+        }
+        match self.crypto_service.fetch_top_tokens(self.token_limit).await {
+            Ok(fetched) if !fetched.tokens.is_empty() => {
+                save_tokens_to_cache(&collection, self.token_cache, self.notifier, self.events, &fetched.tokens).await;
+                WarmUpOutcome::Fetched(fetched.tokens.len())
+            }
+            Ok(_) => {
+                tracing::warn!("CoinGecko returned no tokens during warm-up");
+                WarmUpOutcome::Failed
+            }
+            Err(e) => {
+                if e == CryptoServiceError::RateLimited {
+                    self.rate_limiter.record_rate_limit().await;
+                }
+                tracing::warn!(error = %e, "Failed to fetch tokens during warm-up");
+                WarmUpOutcome::Failed
+            }
+        }
+    }
+}
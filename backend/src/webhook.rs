@@ -0,0 +1,209 @@
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use crate::db::DbClient;
+use crate::models::{AlertCondition, AlertTrigger, CryptoToken, DeliveryOutcome, WebhookConfig, WebhookDelivery, WebhookEvent};
+use crate::shutdown::BackgroundTasks;
+
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+
+// Attempts per event, first one included
+pub const MAX_DELIVERY_ATTEMPTS: u32 = 3;
+
+// Events waiting for delivery; past this, new ones are dropped rather than queued
+const QUEUE_CAPACITY: usize = 256;
+
+// Wait before the second attempt, doubling for each one after
+const DEFAULT_BACKOFF: Duration = Duration::from_secs(2);
+
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub fn new_ath_event(token: &CryptoToken, previous_ath: f64) -> WebhookEvent {
+    WebhookEvent {
+        event: "new_ath".to_string(),
+        token_id: token.token_id.clone(),
+        data: serde_json::json!({
+            "price": token.current_price,
+            "previous_ath": previous_ath,
+        }),
+        at: Utc::now(),
+    }
+}
+
+// Names the token that set the alert off, with the move and the threshold it crossed
+pub fn alert_event(trigger: &AlertTrigger, condition: &AlertCondition) -> WebhookEvent {
+    let AlertCondition::PercentMove { percent, window } = condition;
+    WebhookEvent {
+        event: "alert".to_string(),
+        token_id: trigger.token_id.clone(),
+        data: serde_json::json!({
+            "alert_id": trigger.alert_id,
+            "change_percentage": trigger.change_percentage,
+            "percent": percent,
+            "window": window,
+        }),
+        at: trigger.at,
+    }
+}
+
+// `sha256=<hex HMAC of the body>`, sent in X-Webhook-Signature when a secret is set
+pub fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(body);
+    let hex: String = mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256={}", hex)
+}
+
+// Handle request handlers use to queue events. Never blocks: delivery happens in the
+// dispatcher task, off the request path.
+#[derive(Clone, Default)]
+pub struct WebhookNotifier {
+    sender: Option<mpsc::Sender<WebhookEvent>>,
+}
+
+impl WebhookNotifier {
+    // Drops every event; for tests and tools that never deliver
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    // Starts `dispatcher` as a background task fed by the returned notifier
+    pub fn spawn(tasks: &BackgroundTasks, dispatcher: WebhookDispatcher) -> Self {
+        let (sender, mut receiver) = mpsc::channel::<WebhookEvent>(QUEUE_CAPACITY);
+        tasks.spawn(move |token| async move {
+            loop {
+                let event = tokio::select! {
+                    _ = token.cancelled() => break,
+                    event = receiver.recv() => match event {
+                        Some(event) => event,
+                        None => break,
+                    },
+                };
+                dispatcher.dispatch(&event).await;
+            }
+        });
+        Self { sender: Some(sender) }
+    }
+
+    pub fn notify(&self, event: WebhookEvent) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        if let Err(e) = sender.try_send(event) {
+            tracing::warn!(error = %e, "Dropping webhook event");
+        }
+    }
+}
+
+pub struct WebhookDispatcher {
+    db: DbClient,
+    client: reqwest::Client,
+    backoff: Duration,
+}
+
+impl WebhookDispatcher {
+    pub fn new(db: DbClient) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(DELIVERY_TIMEOUT)
+            .build()
+            .expect("Failed to build webhook HTTP client");
+        Self { db, client, backoff: DEFAULT_BACKOFF }
+    }
+
+    pub fn with_backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    // Delivers with the stored config, read per event so PUT /api/admin/webhook
+    // applies without a restart
+    pub async fn dispatch(&self, event: &WebhookEvent) {
+        match self.db.load_webhook_config().await {
+            Ok(Some(config)) if config.enabled => {
+                self.deliver(&config, event).await;
+            }
+            Ok(_) => tracing::debug!(event = %event.event, "No enabled webhook, dropping event"),
+            Err(e) => tracing::warn!(error = %e, "Failed to load webhook config"),
+        }
+    }
+
+    // Tries up to MAX_DELIVERY_ATTEMPTS times, recording every attempt, and returns them
+    pub async fn deliver(&self, config: &WebhookConfig, event: &WebhookEvent) -> Vec<WebhookDelivery> {
+        let body = match serde_json::to_vec(event) {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to encode webhook event");
+                return Vec::new();
+            }
+        };
+
+        let mut attempts = Vec::new();
+        for attempt in 1..=MAX_DELIVERY_ATTEMPTS {
+            if attempt > 1 {
+                tokio::time::sleep(self.backoff * 2u32.pow(attempt - 2)).await;
+            }
+
+            let mut request = self
+                .client
+                .post(&config.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone());
+            if let Some(secret) = config.secret.as_deref() {
+                request = request.header(SIGNATURE_HEADER, signature(secret, &body));
+            }
+
+            let (status, error) = match request.send().await {
+                Ok(response) if response.status().is_success() => (Some(response.status().as_u16()), None),
+                Ok(response) => (Some(response.status().as_u16()), Some(format!("HTTP {}", response.status()))),
+                Err(e) => (None, Some(e.to_string())),
+            };
+            let outcome = match (&error, attempt) {
+                (None, _) => DeliveryOutcome::Delivered,
+                (Some(_), MAX_DELIVERY_ATTEMPTS) => DeliveryOutcome::Failed,
+                (Some(_), _) => DeliveryOutcome::Retrying,
+            };
+
+            let delivery = WebhookDelivery {
+                id: None,
+                event: event.event.clone(),
+                token_id: event.token_id.clone(),
+                url: config.url.clone(),
+                attempt,
+                status,
+                error,
+                outcome,
+                attempted_at: Utc::now(),
+            };
+            if let Err(e) = self.db.record_webhook_delivery(&delivery).await {
+                tracing::warn!(error = %e, "Failed to record webhook delivery");
+            }
+            match outcome {
+                DeliveryOutcome::Delivered => tracing::info!(event = %event.event, token_id = %event.token_id, attempt, "Webhook delivered"),
+                DeliveryOutcome::Retrying => tracing::warn!(event = %event.event, attempt, error = ?delivery.error, "Webhook delivery failed, retrying"),
+                DeliveryOutcome::Failed => tracing::error!(event = %event.event, attempt, error = ?delivery.error, "Webhook delivery failed"),
+            }
+            attempts.push(delivery);
+
+            if outcome == DeliveryOutcome::Delivered {
+                break;
+            }
+        }
+        attempts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature_matches_known_hmac() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            signature("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
@@ -0,0 +1,116 @@
+// Tests for the favorites history backfill against MongoDB and a mocked CoinGecko
+mod common;
+
+use chrono::Utc;
+use crypto_tracker_backend::{
+    backfill::{BackfillJob, BackfillStatus, HistoryBackfill, TickOutcome},
+    crypto_service::CryptoService,
+    db::DbClient,
+    models::{CoinGeckoHistoricalData, HistoryInterval},
+    rate_limiter::RateLimiter,
+};
+use mongodb::bson::{doc, oid::ObjectId};
+use serial_test::serial;
+use std::sync::Arc;
+use wiremock::matchers::{method, path_regex};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn chart() -> serde_json::Value {
+    let now = Utc::now().timestamp_millis() as f64;
+    serde_json::json!({
+        "prices": [[now - 3600000.0, 100.0], [now, 101.0]],
+        "market_caps": [[now - 3600000.0, 1000.0], [now, 1010.0]],
+        "total_volumes": [[now - 3600000.0, 10.0], [now, 11.0]]
+    })
+}
+
+async fn seed_favorites(db: &DbClient) {
+    let mut bitcoin = common::mock_data::create_test_token("bitcoin");
+    bitcoin.is_favorite = true;
+    let solana = common::mock_data::create_test_token("solana");
+    db.get_tokens_collection().insert_many([bitcoin, solana], None).await.unwrap();
+
+    // Only in a user's watchlist
+    db.db
+        .collection("user_favorites")
+        .insert_one(doc! { "user_id": ObjectId::new(), "token_id": "ethereum", "created_at": Utc::now().to_rfc3339() }, None)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_backfill_fetches_only_stale_favorite_charts_in_rotation() {
+    let db = common::setup_test_db().await;
+    let db_client = DbClient { db: db.clone() };
+    seed_favorites(&db_client).await;
+
+    // Already fresh, so never fetched again
+    let fresh: CoinGeckoHistoricalData = serde_json::from_value(chart()).unwrap();
+    db_client.save_history("bitcoin", 1.into(), HistoryInterval::Hourly, &fresh).await.unwrap();
+
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/coins/(bitcoin|ethereum)/market_chart$"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(chart()))
+        .expect(5)
+        .mount(&mock_server)
+        .await;
+
+    let status = BackfillStatus::default();
+    let mut backfill = HistoryBackfill::new(
+        db_client.clone(),
+        CryptoService::new(mock_server.uri(), None),
+        Arc::new(RateLimiter::new(0.0, 60)),
+        status.clone(),
+    );
+
+    let mut fetched = Vec::new();
+    loop {
+        match backfill.tick().await.unwrap() {
+            TickOutcome::Fetched(job) => fetched.push(job),
+            TickOutcome::Idle => break,
+            other => panic!("unexpected tick outcome {:?}", other),
+        }
+    }
+
+    let job = |token_id: &str, days: u32| BackfillJob { token_id: token_id.to_string(), days: days.into() };
+    assert_eq!(
+        fetched,
+        vec![job("bitcoin", 30), job("ethereum", 1), job("bitcoin", 365), job("ethereum", 30), job("ethereum", 365)]
+    );
+    assert_eq!(status.pending(), 0);
+
+    // Everything fetched is now served from the cache
+    let cached = db_client.cached_history("ethereum", 365.into(), HistoryInterval::Daily, Some(Utc::now())).await.unwrap();
+    assert!(cached.is_some());
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_backfill_waits_for_the_rate_limiter() {
+    let db = common::setup_test_db().await;
+    let db_client = DbClient { db: db.clone() };
+    seed_favorites(&db_client).await;
+
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/coins/.+/market_chart$"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(chart()))
+        .expect(0)
+        .mount(&mock_server)
+        .await;
+
+    let rate_limiter = Arc::new(RateLimiter::new(0.0, 60));
+    rate_limiter.record_rate_limit().await;
+    let status = BackfillStatus::default();
+    let mut backfill = HistoryBackfill::new(db_client, CryptoService::new(mock_server.uri(), None), rate_limiter, status.clone());
+
+    assert_eq!(backfill.tick().await.unwrap(), TickOutcome::Waiting);
+    // Two favorites, three ranges each
+    assert_eq!(status.pending(), 6);
+
+    common::cleanup_test_db(&db).await;
+}
@@ -0,0 +1,73 @@
+// Tests for the Binance fallback client with a mock HTTP server
+mod common;
+
+use crypto_tracker_backend::binance::BinanceService;
+use wiremock::matchers::{method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn ticker_json(symbol: &str, price: &str) -> serde_json::Value {
+    serde_json::json!({
+        "symbol": symbol,
+        "priceChange": "1200.00",
+        "priceChangePercent": "2.45",
+        "weightedAvgPrice": "49500.00",
+        "lastPrice": price,
+        "highPrice": "51000.00",
+        "lowPrice": "48000.00",
+        "volume": "25000.5",
+        "quoteVolume": "1237500000.00",
+        "count": 1000000
+    })
+}
+
+#[tokio::test]
+async fn test_fetch_tickers_requests_only_listed_symbols() {
+    common::init_test_logger();
+
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/v3/ticker/24hr"))
+        .and(query_param("symbols", r#"["BTCUSDT","ETHUSDT"]"#))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            ticker_json("BTCUSDT", "50200.00"),
+            ticker_json("ETHUSDT", "3100.00"),
+        ])))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let binance = BinanceService::new(mock_server.uri());
+    let tickers = binance
+        .fetch_tickers(&["BTCUSDT".to_string(), "ETHUSDT".to_string()])
+        .await
+        .unwrap();
+
+    assert_eq!(tickers.len(), 2);
+    assert_eq!(tickers[0].symbol, "BTCUSDT");
+    assert_eq!(tickers[0].last_price, "50200.00");
+    assert_eq!(tickers[1].quote_volume, "1237500000.00");
+}
+
+#[tokio::test]
+async fn test_fetch_tickers_surfaces_errors() {
+    common::init_test_logger();
+
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/v3/ticker/24hr"))
+        .respond_with(ResponseTemplate::new(400).set_body_string(r#"{"code":-1121,"msg":"Invalid symbol."}"#))
+        .mount(&mock_server)
+        .await;
+
+    let binance = BinanceService::new(mock_server.uri());
+    assert!(binance.fetch_tickers(&["NOPEUSDT".to_string()]).await.is_err());
+}
+
+#[tokio::test]
+async fn test_no_symbols_skips_the_request() {
+    // Nothing is mounted, so any request would fail
+    let mock_server = MockServer::start().await;
+    let binance = BinanceService::new(mock_server.uri());
+
+    assert!(binance.fetch_tickers(&[]).await.unwrap().is_empty());
+}
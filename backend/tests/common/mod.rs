@@ -1,4 +1,6 @@
 // Common test utilities
+// Each test crate uses a different subset of these helpers
+#![allow(dead_code)]
 use std::env;
 use mongodb::{Client, Database};
 
@@ -24,56 +26,17 @@ pub async fn cleanup_test_db(db: &Database) {
 }
 
 pub fn init_test_logger() {
-    let _ = env_logger::builder()
-        .is_test(true)
+    let _ = tracing_subscriber::fmt()
+        .with_test_writer()
         .try_init();
 }
 
 // Mock data generators
 pub mod mock_data {
     use chrono::Utc;
-    use serde::{Deserialize, Serialize};
     
     // Re-export models from main crate
-    #[derive(Debug, Serialize, Deserialize, Clone)]
-    pub struct CryptoToken {
-        #[serde(skip_serializing_if = "Option::is_none")]
-        pub id: Option<mongodb::bson::oid::ObjectId>,
-        pub token_id: String,
-        pub symbol: String,
-        pub name: String,
-        pub current_price: f64,
-        pub market_cap: f64,
-        pub volume_24h: f64,
-        pub price_change_24h: f64,
-        pub price_change_percentage_24h: f64,
-        pub high_24h: Option<f64>,
-        pub low_24h: Option<f64>,
-        pub circulating_supply: Option<f64>,
-        pub total_supply: Option<f64>,
-        pub ath: Option<f64>,
-        pub ath_change_percentage: Option<f64>,
-        pub atl: Option<f64>,
-        pub atl_change_percentage: Option<f64>,
-        pub image: Option<String>,
-        pub last_updated: chrono::DateTime<Utc>,
-        pub is_favorite: bool,
-    }
-
-    #[derive(Debug, Serialize, Deserialize, Clone)]
-    pub struct PriceHistoryEntry {
-        pub timestamp: i64,
-        pub price: f64,
-    }
-
-    #[derive(Debug, Serialize, Deserialize, Clone)]
-    pub struct PriceHistory {
-        #[serde(skip_serializing_if = "Option::is_none")]
-        pub id: Option<mongodb::bson::oid::ObjectId>,
-        pub token_id: String,
-        pub prices: Vec<PriceHistoryEntry>,
-        pub last_updated: chrono::DateTime<Utc>,
-    }
+    pub use crypto_tracker_backend::models::{CryptoToken, PriceHistory};
 
     pub fn create_test_token(token_id: &str) -> CryptoToken {
         CryptoToken {
@@ -86,17 +49,29 @@ pub mod mock_data {
             volume_24h: 1000000000.0,
             price_change_24h: 10.0,
             price_change_percentage_24h: 1.0,
+            price_change_percentage_7d: None,
+            price_change_percentage_30d: None,
+            price_change_percentage_1y: None,
+            sparkline_7d: None,
             high_24h: Some(1100.0),
             low_24h: Some(900.0),
             circulating_supply: Some(10000000.0),
             total_supply: Some(21000000.0),
+            max_supply: None,
+            fully_diluted_valuation: None,
             ath: Some(1500.0),
             ath_change_percentage: Some(-33.33),
             atl: Some(100.0),
             atl_change_percentage: Some(900.0),
             image: Some(format!("https://example.com/{}.png", token_id)),
             last_updated: Utc::now(),
+            fetched_at: None,
             is_favorite: false,
+            hidden: false,
+            tags: Vec::new(),
+            note: None,
+            price_source: None,
+            category: None,
         }
     }
 
@@ -107,18 +82,19 @@ pub mod mock_data {
     }
 
     pub fn create_test_price_history(token_id: &str, days: usize) -> PriceHistory {
-        let prices: Vec<PriceHistoryEntry> = (0..days)
-            .map(|i| PriceHistoryEntry {
-                timestamp: (1000 + i * 86400) as i64,
-                price: 1000.0 + (i as f64 * 10.0),
-            })
+        let prices: Vec<(i64, f64)> = (0..days)
+            .map(|i| ((1000 + i * 86400) as i64, 1000.0 + (i as f64 * 10.0)))
             .collect();
 
         PriceHistory {
             id: None,
             token_id: token_id.to_string(),
+            symbol: token_id.to_string(),
             prices,
-            last_updated: Utc::now(),
+            market_caps: Vec::new(),
+            total_volumes: Vec::new(),
+            interval: None,
+            timestamp: Utc::now(),
         }
     }
 }
@@ -0,0 +1,105 @@
+// Response compression as main.rs wires it: Compress outside, the opt-out marker inside
+use actix_web::http::header;
+use actix_web::middleware::{from_fn, Compress};
+use actix_web::{test, web, App, HttpResponse};
+use crypto_tracker_backend::{compression, routes};
+use flate2::read::GzDecoder;
+use futures::stream;
+use std::io::Read;
+
+macro_rules! compressed_app {
+    () => {
+        test::init_service(
+            App::new()
+                .wrap(from_fn(compression::skip_uncompressible))
+                .wrap(Compress::default())
+                .route("/test/events", web::get().to(events))
+                .route("/test/export.ndjson", web::get().to(ndjson_export))
+                .configure(routes::configure),
+        )
+        .await
+    };
+}
+
+fn chunks(prefix: &'static str) -> impl futures::Stream<Item = Result<web::Bytes, actix_web::Error>> {
+    stream::iter((0..200).map(move |i| Ok(web::Bytes::from(format!("{}{{\"n\":{}}}\n", prefix, i)))))
+}
+
+async fn events() -> HttpResponse {
+    HttpResponse::Ok().content_type("text/event-stream").streaming(chunks("data: "))
+}
+
+async fn ndjson_export() -> HttpResponse {
+    HttpResponse::Ok().content_type("application/x-ndjson").streaming(chunks(""))
+}
+
+fn gunzip(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    GzDecoder::new(bytes).read_to_end(&mut out).expect("valid gzip");
+    out
+}
+
+#[actix_web::test]
+async fn test_gzip_body_matches_uncompressed_one() {
+    let app = compressed_app!();
+
+    let req = test::TestRequest::get().uri("/api/openapi.json").to_request();
+    let plain = test::call_and_read_body(&app, req).await;
+    assert!(plain.len() as u64 > compression::MIN_COMPRESS_BYTES);
+
+    let req = test::TestRequest::get()
+        .uri("/api/openapi.json")
+        .insert_header((header::ACCEPT_ENCODING, "gzip"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.headers().get(header::CONTENT_ENCODING).unwrap(), "gzip");
+    let compressed = test::read_body(resp).await;
+
+    assert!(compressed.len() < plain.len());
+    assert_eq!(gunzip(&compressed), plain.to_vec());
+}
+
+#[actix_web::test]
+async fn test_without_accept_encoding_nothing_is_compressed() {
+    let app = compressed_app!();
+
+    let req = test::TestRequest::get().uri("/api/openapi.json").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.headers().get(header::CONTENT_ENCODING).is_none());
+}
+
+#[actix_web::test]
+async fn test_small_responses_and_event_streams_are_not_compressed() {
+    let app = compressed_app!();
+
+    for uri in ["/health/live", "/test/events"] {
+        let req = test::TestRequest::get().uri(uri).insert_header((header::ACCEPT_ENCODING, "gzip")).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get(header::CONTENT_ENCODING).unwrap(), "identity", "{}", uri);
+    }
+
+    let req = test::TestRequest::get()
+        .uri("/test/events")
+        .insert_header((header::ACCEPT_ENCODING, "gzip"))
+        .to_request();
+    let body = test::call_and_read_body(&app, req).await;
+    assert!(body.starts_with(b"data: {\"n\":0}\n"));
+}
+
+#[actix_web::test]
+async fn test_streamed_exports_stay_streamed_when_compressed() {
+    let app = compressed_app!();
+
+    let req = test::TestRequest::get()
+        .uri("/test/export.ndjson")
+        .insert_header((header::ACCEPT_ENCODING, "gzip"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.headers().get(header::CONTENT_ENCODING).unwrap(), "gzip");
+    // Compressed on the fly, so the length isn't known up front
+    assert!(resp.headers().get(header::CONTENT_LENGTH).is_none());
+
+    let lines = String::from_utf8(gunzip(&test::read_body(resp).await)).unwrap();
+    assert_eq!(lines.lines().count(), 200);
+    assert_eq!(lines.lines().last(), Some("{\"n\":199}"));
+}
@@ -1,8 +1,11 @@
 // Tests for CryptoService with mock HTTP server
 mod common;
 
+use crypto_tracker_backend::config::Config;
+use crypto_tracker_backend::crypto_service::{ApiKey, ApiPlan, CryptoService, CryptoServiceError};
+use std::time::{Duration, Instant};
 use wiremock::{MockServer, Mock, ResponseTemplate};
-use wiremock::matchers::{method, path_regex};
+use wiremock::matchers::{header, method, path, path_regex, query_param};
 
 // Mock HTTP client tests
 #[tokio::test]
@@ -29,7 +32,8 @@ async fn test_mock_api_success() {
             "ath": 69000.0,
             "ath_change_percentage": -27.5,
             "atl": 67.81,
-            "atl_change_percentage": 73600.0
+            "atl_change_percentage": 73600.0,
+            "last_updated": "2024-01-01T00:00:00.000Z"
         }
     ]"#;
     
@@ -42,7 +46,7 @@ async fn test_mock_api_success() {
     // Test HTTP request
     let client = reqwest::Client::new();
     let response = client
-        .get(&format!("{}/coins/markets", mock_server.uri()))
+        .get(format!("{}/coins/markets", mock_server.uri()))
         .send()
         .await;
     
@@ -63,7 +67,7 @@ async fn test_mock_api_rate_limit() {
     
     let client = reqwest::Client::new();
     let response = client
-        .get(&format!("{}/test", mock_server.uri()))
+        .get(format!("{}/test", mock_server.uri()))
         .send()
         .await;
     
@@ -92,7 +96,7 @@ async fn test_mock_api_timeout() {
         .unwrap();
     
     let response = client
-        .get(&format!("{}/test", mock_server.uri()))
+        .get(format!("{}/test", mock_server.uri()))
         .send()
         .await;
     
@@ -124,25 +128,33 @@ async fn test_crypto_service_fetch_top_tokens_success() {
             "ath": 69000.0,
             "ath_change_percentage": -27.5,
             "atl": 67.81,
-            "atl_change_percentage": 73600.0
+            "atl_change_percentage": 73600.0,
+            "price_change_percentage_7d_in_currency": -4.5,
+            "price_change_percentage_30d_in_currency": 12.25,
+            "price_change_percentage_1y_in_currency": null,
+            "last_updated": "2024-01-01T00:00:00.000Z"
         }
     ]"#;
     
     Mock::given(method("GET"))
         .and(path("/coins/markets"))
+        .and(query_param("price_change_percentage", "24h,7d,30d,1y"))
         .respond_with(ResponseTemplate::new(200).set_body_string(response_body))
         .mount(&mock_server)
         .await;
     
-    let service = CryptoService::new(mock_server.uri());
+    let service = CryptoService::new(mock_server.uri(), None);
     let result = service.fetch_top_tokens(1).await;
     
     assert!(result.is_ok());
-    let tokens = result.unwrap();
+    let tokens = result.unwrap().tokens;
     assert_eq!(tokens.len(), 1);
     assert_eq!(tokens[0].token_id, "bitcoin");
     assert_eq!(tokens[0].symbol, "btc");
     assert_eq!(tokens[0].current_price, 50000.0);
+    assert_eq!(tokens[0].price_change_percentage_7d, Some(-4.5));
+    assert_eq!(tokens[0].price_change_percentage_30d, Some(12.25));
+    assert_eq!(tokens[0].price_change_percentage_1y, None);
 }
 
 #[tokio::test]
@@ -157,10 +169,14 @@ async fn test_crypto_service_fetch_top_tokens_api_error() {
         .mount(&mock_server)
         .await;
     
-    let service = CryptoService::new(mock_server.uri());
+    let service = CryptoService::new(mock_server.uri(), None);
     let result = service.fetch_top_tokens(1).await;
     
-    assert!(result.is_err());
+    let error = result.unwrap_err();
+    assert_eq!(error, CryptoServiceError::RateLimited);
+    assert_eq!(error.status(), Some(429));
+    // Same text as when errors were plain strings
+    assert_eq!(error.to_string(), "API returned error: 429 Too Many Requests");
 }
 
 #[tokio::test]
@@ -175,10 +191,10 @@ async fn test_crypto_service_fetch_top_tokens_invalid_json() {
         .mount(&mock_server)
         .await;
     
-    let service = CryptoService::new(mock_server.uri());
+    let service = CryptoService::new(mock_server.uri(), None);
     let result = service.fetch_top_tokens(1).await;
     
-    assert!(result.is_err());
+    assert!(matches!(result.unwrap_err(), CryptoServiceError::Parse(_)));
 }
 
 #[tokio::test]
@@ -192,7 +208,9 @@ async fn test_crypto_service_fetch_historical_data_success() {
             [1640000000000, 47000.0],
             [1640086400000, 48000.0],
             [1640172800000, 49000.0]
-        ]
+        ],
+        "market_caps": [],
+        "total_volumes": []
     }"#;
     
     Mock::given(method("GET"))
@@ -200,14 +218,13 @@ async fn test_crypto_service_fetch_historical_data_success() {
         .mount(&mock_server)
         .await;
     
-    let service = CryptoService::new(mock_server.uri());
+    let service = CryptoService::new(mock_server.uri(), None);
     let result = service.fetch_historical_data("bitcoin", 7).await;
     
     assert!(result.is_ok());
     let history = result.unwrap();
-    assert_eq!(history.token_id, "bitcoin");
     assert_eq!(history.prices.len(), 3);
-    assert_eq!(history.prices[0].price, 47000.0);
+    assert_eq!(history.prices[0][1], 47000.0);
 }
 
 #[tokio::test]
@@ -216,14 +233,14 @@ async fn test_crypto_service_fetch_historical_data_empty_prices() {
     
     let mock_server = MockServer::start().await;
     
-    let response_body = r#"{"prices": []}"#;
+    let response_body = r#"{"prices": [], "market_caps": [], "total_volumes": []}"#;
     
     Mock::given(method("GET"))
         .respond_with(ResponseTemplate::new(200).set_body_string(response_body))
         .mount(&mock_server)
         .await;
     
-    let service = CryptoService::new(mock_server.uri());
+    let service = CryptoService::new(mock_server.uri(), None);
     let result = service.fetch_historical_data("bitcoin", 7).await;
     
     assert!(result.is_ok());
@@ -245,11 +262,11 @@ async fn test_crypto_service_timeout() {
         .mount(&mock_server)
         .await;
     
-    let service = CryptoService::new(mock_server.uri());
+    let service = CryptoService::new(mock_server.uri(), None);
     let result = service.fetch_top_tokens(1).await;
     
     // Should timeout and return an error
-    assert!(result.is_err());
+    assert_eq!(result.unwrap_err(), CryptoServiceError::Timeout);
 }
 
 #[tokio::test]
@@ -276,7 +293,8 @@ async fn test_crypto_service_handles_null_values() {
             "ath": null,
             "ath_change_percentage": null,
             "atl": null,
-            "atl_change_percentage": null
+            "atl_change_percentage": null,
+            "last_updated": "2024-01-01T00:00:00.000Z"
         }
     ]"#;
     
@@ -286,12 +304,584 @@ async fn test_crypto_service_handles_null_values() {
         .mount(&mock_server)
         .await;
     
-    let service = CryptoService::new(mock_server.uri());
+    let service = CryptoService::new(mock_server.uri(), None);
     let result = service.fetch_top_tokens(1).await;
     
     assert!(result.is_ok());
-    let tokens = result.unwrap();
+    let tokens = result.unwrap().tokens;
     assert_eq!(tokens.len(), 1);
     assert_eq!(tokens[0].price_change_24h, 0.0); // Should default to 0
     assert!(tokens[0].high_24h.is_none());
 }
+
+#[tokio::test]
+async fn test_malformed_market_entries_are_skipped() {
+    common::init_test_logger();
+
+    let mock_server = MockServer::start().await;
+
+    // An integer price, a string price, no price at all, a non-string id, then a valid one
+    let response_body = r#"[
+        { "id": "bitcoin", "symbol": "btc", "name": "Bitcoin", "image": "", "current_price": 50000, "market_cap": 1000000000000, "total_volume": 50000000000 },
+        { "id": "ethereum", "symbol": "eth", "name": "Ethereum", "image": "", "current_price": "3000.25", "market_cap": null, "total_volume": "1e10" },
+        { "id": "broken", "symbol": "brk", "name": "Broken", "image": "", "current_price": null, "market_cap": 1.0, "total_volume": 1.0 },
+        { "id": 42, "symbol": "num", "name": "Numeric", "image": "", "current_price": 1.0, "market_cap": 1.0, "total_volume": 1.0 },
+        { "id": "solana", "symbol": "sol", "name": "Solana", "image": "https://example.com/sol.png", "current_price": 150.0, "market_cap": 7.0e10, "total_volume": 3.0e9 }
+    ]"#;
+
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(response_body))
+        .mount(&mock_server)
+        .await;
+
+    let service = CryptoService::new(mock_server.uri(), None);
+    let tokens = service.fetch_top_tokens(5).await.unwrap().tokens;
+
+    let ids: Vec<&str> = tokens.iter().map(|t| t.token_id.as_str()).collect();
+    assert_eq!(ids, ["bitcoin", "ethereum", "solana"]);
+    assert_eq!(tokens[0].current_price, 50000.0);
+    assert_eq!((tokens[1].current_price, tokens[1].market_cap, tokens[1].volume_24h), (3000.25, 0.0, 1.0e10));
+    assert!(tokens[0].image.is_none());
+}
+
+#[tokio::test]
+async fn test_upstream_last_updated_kept_with_fallback_to_now() {
+    common::init_test_logger();
+
+    let mock_server = MockServer::start().await;
+    let markets: Vec<serde_json::Value> = [
+        ("dated", serde_json::json!("2024-01-01T00:00:00.000Z")),
+        ("garbled", serde_json::json!("yesterday-ish")),
+        ("missing", serde_json::Value::Null),
+    ]
+    .into_iter()
+    .map(|(id, last_updated)| serde_json::json!({
+        "id": id,
+        "symbol": id,
+        "name": id,
+        "image": "https://example.com/coin.png",
+        "current_price": 1.0,
+        "market_cap": 1000.0,
+        "total_volume": 10.0,
+        "last_updated": last_updated
+    }))
+    .collect();
+
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(markets))
+        .mount(&mock_server)
+        .await;
+
+    let before = chrono::Utc::now();
+    let service = CryptoService::new(mock_server.uri(), None);
+    let tokens = service.fetch_top_tokens(3).await.unwrap().tokens;
+
+    assert_eq!(tokens[0].last_updated.to_rfc3339(), "2024-01-01T00:00:00+00:00");
+    assert!(tokens[1].last_updated >= before);
+    assert!(tokens[2].last_updated >= before);
+    assert!(tokens.iter().all(|t| t.fetched_at.is_some_and(|at| at >= before)));
+}
+
+#[tokio::test]
+async fn test_crypto_service_fetches_sparklines_on_request() {
+    common::init_test_logger();
+
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .and(query_param("sparkline", "true"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([{
+            "id": "bitcoin",
+            "symbol": "btc",
+            "name": "Bitcoin",
+            "image": "https://example.com/btc.png",
+            "current_price": 50000.0,
+            "market_cap": 1000000000000.0,
+            "total_volume": 50000000000.0,
+            "sparkline_in_7d": { "price": [48000.0, 49000.0, 50000.0] }
+        }])))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let service = CryptoService::new(mock_server.uri(), None);
+    let tokens = service.fetch_top_tokens_in(1, None, true).await.unwrap().tokens;
+    assert_eq!(tokens[0].sparkline_7d, Some(vec![48000.0, 49000.0, 50000.0]));
+}
+
+#[tokio::test]
+async fn test_crypto_service_sends_api_key_header() {
+    common::init_test_logger();
+    
+    let mock_server = MockServer::start().await;
+    
+    // Only answers when the demo key header is present
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .and(header("x-cg-demo-api-key", "demo-key"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("[]"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    
+    let api_key = ApiKey { key: "demo-key".to_string(), plan: ApiPlan::Demo };
+    let service = CryptoService::new(mock_server.uri(), Some(api_key));
+    let result = service.fetch_top_tokens(1).await;
+    
+    assert!(result.is_ok());
+}
+
+#[tokio::test]
+async fn test_crypto_service_sends_pro_header_on_every_request() {
+    common::init_test_logger();
+    
+    let mock_server = MockServer::start().await;
+    
+    Mock::given(method("GET"))
+        .and(header("x-cg-pro-api-key", "pro-key"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            r#"{"prices": [], "market_caps": [], "total_volumes": []}"#
+        ))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    
+    let api_key = ApiKey { key: "pro-key".to_string(), plan: ApiPlan::Pro };
+    let service = CryptoService::new(mock_server.uri(), Some(api_key));
+    let result = service.fetch_historical_data("bitcoin", 7).await;
+    
+    assert!(result.is_ok());
+}
+
+#[tokio::test]
+async fn test_crypto_service_omits_key_header_without_key() {
+    common::init_test_logger();
+    
+    let mock_server = MockServer::start().await;
+    
+    Mock::given(method("GET"))
+        .and(header("x-cg-demo-api-key", "demo-key"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("[]"))
+        .expect(0)
+        .mount(&mock_server)
+        .await;
+    
+    let service = CryptoService::new(mock_server.uri(), None);
+    let result = service.fetch_top_tokens(1).await;
+    
+    // No mock matches, so wiremock answers 404
+    let error = result.unwrap_err();
+    assert_eq!(error, CryptoServiceError::Http(reqwest::StatusCode::NOT_FOUND));
+    assert_eq!(error.status(), Some(404));
+}
+
+fn market_page(ids: &[&str]) -> String {
+    let markets: Vec<serde_json::Value> = ids
+        .iter()
+        .map(|id| serde_json::json!({
+            "id": id,
+            "symbol": id,
+            "name": id,
+            "image": "https://example.com/coin.png",
+            "current_price": 1.0,
+            "market_cap": 1000.0,
+            "total_volume": 10.0,
+            "last_updated": "2024-01-01T00:00:00.000Z"
+        }))
+        .collect();
+    serde_json::to_string(&markets).unwrap()
+}
+
+fn coin_ids(prefix: &str, count: usize) -> Vec<String> {
+    (0..count).map(|i| format!("{}-{}", prefix, i)).collect()
+}
+
+async fn mount_page(server: &MockServer, page: &str, response: ResponseTemplate) {
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .and(query_param("per_page", "250"))
+        .and(query_param("page", page))
+        .respond_with(response)
+        .expect(1)
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn test_fetch_top_tokens_pages_past_250_in_order() {
+    common::init_test_logger();
+    
+    let mock_server = MockServer::start().await;
+    let first = coin_ids("first", 250);
+    let second = coin_ids("second", 250);
+    
+    let first_refs: Vec<&str> = first.iter().map(String::as_str).collect();
+    let second_refs: Vec<&str> = second.iter().map(String::as_str).collect();
+    mount_page(&mock_server, "1", ResponseTemplate::new(200).set_body_string(market_page(&first_refs))).await;
+    mount_page(&mock_server, "2", ResponseTemplate::new(200).set_body_string(market_page(&second_refs))).await;
+    
+    let service = CryptoService::new(mock_server.uri(), None)
+        .with_page_delay(std::time::Duration::ZERO);
+    let result = service.fetch_top_tokens(400).await.unwrap();
+    
+    assert!(!result.is_partial());
+    assert_eq!(result.tokens.len(), 400);
+    assert_eq!(result.tokens[0].token_id, "first-0");
+    assert_eq!(result.tokens[249].token_id, "first-249");
+    assert_eq!(result.tokens[250].token_id, "second-0");
+    assert_eq!(result.tokens[399].token_id, "second-149");
+}
+
+#[tokio::test]
+async fn test_fetch_top_tokens_stops_on_short_page() {
+    common::init_test_logger();
+    
+    let mock_server = MockServer::start().await;
+    let first = coin_ids("coin", 120);
+    let first_refs: Vec<&str> = first.iter().map(String::as_str).collect();
+    
+    // Page 2 must never be requested once page 1 comes back short
+    mount_page(&mock_server, "1", ResponseTemplate::new(200).set_body_string(market_page(&first_refs))).await;
+    
+    let service = CryptoService::new(mock_server.uri(), None)
+        .with_page_delay(std::time::Duration::ZERO);
+    let result = service.fetch_top_tokens(500).await.unwrap();
+    
+    assert!(!result.is_partial());
+    assert_eq!(result.tokens.len(), 120);
+}
+
+#[tokio::test]
+async fn test_fetch_top_tokens_keeps_earlier_pages_on_rate_limit() {
+    common::init_test_logger();
+    
+    let mock_server = MockServer::start().await;
+    let first = coin_ids("coin", 250);
+    let first_refs: Vec<&str> = first.iter().map(String::as_str).collect();
+    
+    mount_page(&mock_server, "1", ResponseTemplate::new(200).set_body_string(market_page(&first_refs))).await;
+    mount_page(&mock_server, "2", ResponseTemplate::new(429)).await;
+    
+    let service = CryptoService::new(mock_server.uri(), None)
+        .with_page_delay(std::time::Duration::ZERO);
+    let result = service.fetch_top_tokens(500).await.unwrap();
+    
+    assert_eq!(result.tokens.len(), 250);
+    let error = result.partial.expect("second page failure should be reported");
+    assert_eq!(error.page, 2);
+    assert_eq!(error.error, CryptoServiceError::RateLimited);
+}
+
+#[tokio::test]
+async fn test_fetch_top_tokens_first_page_failure_is_an_error() {
+    common::init_test_logger();
+    
+    let mock_server = MockServer::start().await;
+    mount_page(&mock_server, "1", ResponseTemplate::new(429)).await;
+    
+    let service = CryptoService::new(mock_server.uri(), None)
+        .with_page_delay(std::time::Duration::ZERO);
+    let result = service.fetch_top_tokens(500).await;
+    
+    assert!(result.is_err());
+}
+
+// Two full pages, each answered after `delay`
+async fn mount_delayed_pages(server: &MockServer, delay: Duration) {
+    for (page, prefix) in [("1", "first"), ("2", "second")] {
+        let ids = coin_ids(prefix, 250);
+        let refs: Vec<&str> = ids.iter().map(String::as_str).collect();
+        mount_page(server, page, ResponseTemplate::new(200).set_body_string(market_page(&refs)).set_delay(delay)).await;
+    }
+}
+
+fn keyed_service(base_url: String) -> CryptoService {
+    let api_key = ApiKey { key: "demo-key".to_string(), plan: ApiPlan::Demo };
+    CryptoService::new(base_url, Some(api_key)).with_page_parallelism(2)
+}
+
+#[tokio::test]
+async fn test_fetch_top_tokens_with_a_key_fetches_pages_concurrently_in_order() {
+    common::init_test_logger();
+
+    let mock_server = MockServer::start().await;
+    mount_delayed_pages(&mock_server, Duration::from_millis(500)).await;
+
+    let started = Instant::now();
+    let result = keyed_service(mock_server.uri()).fetch_top_tokens(500).await.unwrap();
+    let elapsed = started.elapsed();
+
+    assert!(elapsed < Duration::from_millis(900), "took {:?}", elapsed);
+    assert!(!result.is_partial());
+    assert_eq!(result.tokens.len(), 500);
+    assert_eq!(result.tokens[0].token_id, "first-0");
+    assert_eq!(result.tokens[249].token_id, "first-249");
+    assert_eq!(result.tokens[250].token_id, "second-0");
+    assert_eq!(result.tokens[499].token_id, "second-249");
+}
+
+#[tokio::test]
+async fn test_fetch_top_tokens_without_a_key_stays_sequential() {
+    common::init_test_logger();
+
+    let mock_server = MockServer::start().await;
+    mount_delayed_pages(&mock_server, Duration::from_millis(500)).await;
+
+    let service = CryptoService::new(mock_server.uri(), None)
+        .with_page_delay(Duration::ZERO)
+        .with_page_parallelism(2);
+    let started = Instant::now();
+    let result = service.fetch_top_tokens(500).await.unwrap();
+
+    assert!(started.elapsed() >= Duration::from_secs(1));
+    assert_eq!(result.tokens[250].token_id, "second-0");
+}
+
+#[tokio::test]
+async fn test_concurrent_page_failure_keeps_earlier_pages_and_skips_later_ones() {
+    common::init_test_logger();
+
+    let mock_server = MockServer::start().await;
+    let first = coin_ids("coin", 250);
+    let first_refs: Vec<&str> = first.iter().map(String::as_str).collect();
+    // Page 2 fails while page 1 is still on its way; page 3 is never worth asking for
+    mount_page(
+        &mock_server,
+        "1",
+        ResponseTemplate::new(200).set_body_string(market_page(&first_refs)).set_delay(Duration::from_millis(300)),
+    )
+    .await;
+    mount_page(&mock_server, "2", ResponseTemplate::new(429)).await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .and(query_param("page", "3"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("[]"))
+        .expect(0)
+        .mount(&mock_server)
+        .await;
+
+    let result = keyed_service(mock_server.uri()).fetch_top_tokens(750).await.unwrap();
+
+    assert_eq!(result.tokens.len(), 250);
+    let error = result.partial.expect("second page failure should be reported");
+    assert_eq!(error.page, 2);
+    assert_eq!(error.error, CryptoServiceError::RateLimited);
+}
+
+#[tokio::test]
+async fn test_unknown_tokens_are_not_found() {
+    common::init_test_logger();
+
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .and(query_param("ids", "no-such-coin"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("[]"))
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/coins/no-such-coin/market_chart"))
+        .respond_with(ResponseTemplate::new(404).set_body_string(r#"{"error":"coin not found"}"#))
+        .mount(&mock_server)
+        .await;
+
+    let service = CryptoService::new(mock_server.uri(), None);
+    assert_eq!(service.fetch_token_details("no-such-coin").await.unwrap_err(), CryptoServiceError::NotFound);
+    assert_eq!(service.fetch_historical_data("no-such-coin", 7).await.unwrap_err(), CryptoServiceError::NotFound);
+}
+
+#[tokio::test]
+async fn test_fetch_coin_profile_skips_market_data() {
+    common::init_test_logger();
+
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/ethereum"))
+        .and(query_param("localization", "false"))
+        .and(query_param("tickers", "false"))
+        .and(query_param("market_data", "false"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "id": "ethereum",
+            "symbol": "eth",
+            "name": "Ethereum",
+            "description": { "en": "Smart contracts &amp; more" },
+            "links": { "homepage": ["https://www.ethereum.org/"], "twitter_screen_name": "ethereum" },
+            "genesis_date": "2015-07-30",
+            "categories": ["Smart Contract Platform"]
+        })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let service = CryptoService::new(mock_server.uri(), None);
+    let profile = service.fetch_coin_profile("ethereum").await.unwrap();
+    assert_eq!(profile.description, "Smart contracts & more");
+    assert_eq!(profile.twitter.as_deref(), Some("https://twitter.com/ethereum"));
+    assert_eq!(profile.categories, vec!["Smart Contract Platform"]);
+
+    assert_eq!(service.fetch_coin_profile("no-such-coin").await.unwrap_err(), CryptoServiceError::NotFound);
+}
+
+#[tokio::test]
+async fn test_unreachable_upstream_is_a_transport_error() {
+    // Nothing listens on port 9
+    let service = CryptoService::new("http://127.0.0.1:9".to_string(), None);
+    let error = service.fetch_categories().await.unwrap_err();
+    assert!(matches!(error, CryptoServiceError::Transport(_)));
+    assert_eq!(error.status(), None);
+}
+
+#[tokio::test]
+async fn test_crypto_service_sends_configured_user_agent() {
+    common::init_test_logger();
+
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .and(header("user-agent", "DexTracker/2.1 (+mailto:ops@example.com)"))
+        .and(header("x-cg-demo-api-key", "demo-key"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("[]"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let mut config = Config::default_for_tests();
+    config.coingecko_api_url = mock_server.uri();
+    config.coingecko_api_key = Some("demo-key".to_string());
+    config.http_user_agent = "DexTracker/2.1 (+mailto:ops@example.com)".to_string();
+    let service = CryptoService::from_config(&config);
+    let result = service.fetch_top_tokens(1).await;
+
+    assert!(result.is_ok());
+}
+
+#[tokio::test]
+async fn test_search_tokens_looks_up_ids_then_their_markets() {
+    common::init_test_logger();
+
+    let mock_server = MockServer::start().await;
+    let market = |id: &str, market_cap: f64| serde_json::json!({
+        "id": id,
+        "symbol": "pepe",
+        "name": id,
+        "image": "https://example.com/pepe.png",
+        "current_price": 0.00001,
+        "market_cap": market_cap,
+        "total_volume": 1000.0,
+        "last_updated": "2024-01-01T00:00:00.000Z"
+    });
+
+    Mock::given(method("GET"))
+        .and(path("/search"))
+        .and(query_param("query", "pepe"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "coins": [
+                { "id": "pepe-classic", "name": "Pepe Classic", "symbol": "PEPEC" },
+                { "id": "pepe", "name": "Pepe", "symbol": "PEPE" }
+            ],
+            "exchanges": []
+        })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    // Markets come back by market cap; the result keeps CoinGecko's search order
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .and(query_param("ids", "pepe-classic,pepe"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            market("pepe", 5e9),
+            market("pepe-classic", 1e6),
+        ])))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/search"))
+        .and(query_param("query", "nothing"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({ "coins": [] })))
+        .mount(&mock_server)
+        .await;
+
+    let service = CryptoService::new(mock_server.uri(), None).with_page_delay(std::time::Duration::ZERO);
+    let tokens = service.search_tokens("pepe").await.unwrap();
+    let ids: Vec<&str> = tokens.iter().map(|t| t.token_id.as_str()).collect();
+    assert_eq!(ids, ["pepe-classic", "pepe"]);
+
+    // No matches means no markets call
+    assert!(service.search_tokens("nothing").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_search_coins_returns_coingeckos_matches_in_order() {
+    common::init_test_logger();
+
+    let mock_server = MockServer::start().await;
+    let coins: Vec<serde_json::Value> = (0..12)
+        .map(|i| serde_json::json!({ "id": format!("doge-{}", i), "name": format!("Doge {}", i), "api_symbol": "doge", "symbol": "DOGE" }))
+        .chain([serde_json::json!({
+            "id": "dogecoin",
+            "name": "Dogecoin",
+            "api_symbol": "dogecoin",
+            "symbol": "DOGE",
+            "market_cap_rank": 9,
+            "thumb": "https://example.com/doge-thumb.png",
+            "large": "https://example.com/doge-large.png"
+        })])
+        .rev()
+        .collect();
+    Mock::given(method("GET"))
+        .and(path("/search"))
+        .and(query_param("query", "doge"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({ "coins": coins, "exchanges": [], "nfts": [] })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let service = CryptoService::new(mock_server.uri(), None);
+    let found = service.search_coins("doge").await.unwrap();
+
+    // Cut to the first ten, unranked coins without rank or logos
+    assert_eq!(found.len(), 10);
+    assert_eq!(found[0].id, "dogecoin");
+    assert_eq!(found[0].symbol, "DOGE");
+    assert_eq!(found[0].market_cap_rank, Some(9));
+    assert_eq!(found[0].thumb.as_deref(), Some("https://example.com/doge-thumb.png"));
+    assert_eq!(found[0].large.as_deref(), Some("https://example.com/doge-large.png"));
+    assert_eq!(found[1].id, "doge-11");
+    assert_eq!(found[1].market_cap_rank, None);
+    assert_eq!(found[1].thumb, None);
+}
+
+#[tokio::test]
+async fn test_fetch_exchange_rates_keyed_by_code() {
+    common::init_test_logger();
+
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/exchange_rates"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            r#"{
+                "rates": {
+                    "btc": { "name": "Bitcoin", "unit": "BTC", "value": 1.0, "type": "crypto" },
+                    "usd": { "name": "US Dollar", "unit": "$", "value": 67187.33, "type": "fiat" },
+                    "eur": { "name": "Euro", "unit": "€", "value": 62140, "type": "fiat" },
+                    "xau": { "name": "Gold - Troy Ounce", "unit": "XAU", "value": 28.8, "type": "commodity" },
+                    "bits": { "name": "Bits", "unit": "μBTC", "value": 1000000.0, "type": "crypto" }
+                }
+            }"#,
+        ))
+        .mount(&mock_server)
+        .await;
+
+    let service = CryptoService::new(mock_server.uri(), None);
+    let rates = service.fetch_exchange_rates().await.unwrap().rates;
+
+    assert_eq!(rates.len(), 5);
+    assert_eq!(rates["usd"].value, 67187.33);
+    assert_eq!(rates["eur"].value, 62140.0);
+    assert_eq!((rates["eur"].unit.as_str(), rates["eur"].rate_type.as_str()), ("€", "fiat"));
+    assert_eq!(rates["xau"].rate_type, "commodity");
+}
@@ -1,8 +1,11 @@
 // Database operation tests
 mod common;
 
-use mongodb::bson::doc;
-use crypto_tracker_backend::{db::DbClient, models::*};
+use chrono::{Duration, Utc};
+use mongodb::bson::{doc, Document};
+use crypto_tracker_backend::db::{history_freshness, DbClient, TOKEN_TEXT_INDEX};
+use crypto_tracker_backend::models::{CryptoToken, RepairPass, TokenDocuments};
+use futures::TryStreamExt;
 use serial_test::serial;
 
 #[tokio::test]
@@ -231,7 +234,7 @@ async fn test_upsert_operation() {
         .build();
     
     let result = collection.update_one(filter.clone(), update, options.clone()).await.unwrap();
-    assert_eq!(result.upserted_id.is_some(), true);
+    assert!(result.upserted_id.is_some());
     
     // Second upsert (update)
     let update2 = doc! {
@@ -246,3 +249,241 @@ async fn test_upsert_operation() {
     
     common::cleanup_test_db(&db).await;
 }
+
+#[tokio::test]
+#[serial]
+async fn test_prune_history_removes_only_expired() {
+    common::init_test_logger();
+    
+    let db = common::setup_test_db().await;
+    let db_client = DbClient { db: db.clone() };
+    let raw = db.collection::<Document>("price_history");
+    let now = Utc::now();
+    
+    // Fetched 2 hours ago: a 1-day chart has expired, a 30-day chart has not
+    let fetched_at = now - Duration::hours(2);
+    raw.insert_many(vec![
+        doc! { "token_id": "expired-1d", "days": 1, "timestamp": fetched_at, "expires_at": fetched_at + history_freshness(1) },
+        doc! { "token_id": "fresh-30d", "days": 30, "timestamp": fetched_at, "expires_at": fetched_at + history_freshness(30) },
+        // Written before expires_at existed
+        doc! { "token_id": "legacy-old", "timestamp": now - Duration::days(3) },
+        doc! { "token_id": "legacy-recent", "timestamp": now - Duration::hours(3) },
+    ], None).await.unwrap();
+    
+    let deleted = db_client.prune_history(now).await.unwrap();
+    assert_eq!(deleted, 2);
+    
+    let mut remaining: Vec<String> = Vec::new();
+    let mut cursor = raw.find(None, None).await.unwrap();
+    while cursor.advance().await.unwrap() {
+        remaining.push(cursor.deserialize_current().unwrap().get_str("token_id").unwrap().to_string());
+    }
+    remaining.sort();
+    assert_eq!(remaining, vec!["fresh-30d", "legacy-recent"]);
+    
+    common::cleanup_test_db(&db).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_prune_history_with_nothing_expired() {
+    common::init_test_logger();
+    
+    let db = common::setup_test_db().await;
+    let db_client = DbClient { db: db.clone() };
+    let now = Utc::now();
+    
+    db.collection::<Document>("price_history")
+        .insert_one(doc! { "token_id": "bitcoin", "days": 365, "timestamp": now, "expires_at": now + history_freshness(365) }, None)
+        .await
+        .unwrap();
+    
+    assert_eq!(db_client.prune_history(now).await.unwrap(), 0);
+    
+    common::cleanup_test_db(&db).await;
+}
+
+async fn seed_token_documents(db: &mongodb::Database) {
+    for token_id in ["dead-coin", "bitcoin"] {
+        let token = common::mock_data::create_test_token(token_id);
+        db.collection::<Document>("tokens")
+            .insert_one(mongodb::bson::to_document(&token).unwrap(), None)
+            .await
+            .unwrap();
+        db.collection::<Document>("price_history")
+            .insert_many(vec![doc! { "token_id": token_id, "days": 1 }, doc! { "token_id": token_id, "days": 30 }], None)
+            .await
+            .unwrap();
+        db.collection::<Document>("symbol_map")
+            .insert_one(doc! { "token_id": token_id, "symbol": "DEADUSDT" }, None)
+            .await
+            .unwrap();
+    }
+    db.collection::<Document>("user_favorites")
+        .insert_one(doc! { "user_id": mongodb::bson::oid::ObjectId::new(), "token_id": "dead-coin" }, None)
+        .await
+        .unwrap();
+    db.collection::<Document>("portfolio_transactions")
+        .insert_one(doc! { "user_id": mongodb::bson::oid::ObjectId::new(), "token_id": "dead-coin" }, None)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn test_purge_token_counts_then_deletes_across_collections() {
+    common::init_test_logger();
+    
+    let db = common::setup_test_db().await;
+    let db_client = DbClient { db: db.clone() };
+    seed_token_documents(&db).await;
+    
+    let expected = TokenDocuments { tokens: 1, price_history: 2, user_favorites: 1, coin_profiles: 0, symbol_map: 1, events: 0, annotations: 0 };
+    
+    // Counting leaves everything in place
+    assert_eq!(db_client.purge_token("dead-coin", false).await.unwrap(), expected);
+    assert_eq!(db_client.purge_token("dead-coin", false).await.unwrap(), expected);
+    
+    assert_eq!(db_client.purge_token("dead-coin", true).await.unwrap(), expected);
+    assert_eq!(db_client.purge_token("dead-coin", false).await.unwrap(), TokenDocuments::default());
+    
+    // Other tokens and users' transactions are untouched
+    let left = db_client.purge_token("bitcoin", false).await.unwrap();
+    assert_eq!((left.tokens, left.price_history, left.symbol_map), (1, 2, 1));
+    let transactions = db.collection::<Document>("portfolio_transactions").count_documents(None, None).await.unwrap();
+    assert_eq!(transactions, 1);
+    
+    common::cleanup_test_db(&db).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_purge_goes_by_fetch_time_and_keeps_hidden_tokens() {
+    common::init_test_logger();
+
+    let db = common::setup_test_db().await;
+    let db_client = DbClient { db: db.clone() };
+    let old = Utc::now() - Duration::days(3);
+    let token_document = |token_id: &str, last_updated, fetched_at: Option<chrono::DateTime<Utc>>| {
+        let mut token = common::mock_data::create_test_token(token_id);
+        token.last_updated = last_updated;
+        token.fetched_at = fetched_at;
+        mongodb::bson::to_document(&token).unwrap()
+    };
+
+    // CoinGecko rarely updates it, but we fetched it just now
+    let slow_updater = token_document("slow-updater", old, Some(Utc::now()));
+    let unfetched = token_document("unfetched", Utc::now(), Some(old));
+    let mut hidden = token_document("hidden-coin", old, Some(old));
+    hidden.insert("hidden", true);
+    // Written before fetched_at existed, one with last_updated still a BSON date
+    let mut legacy_date = token_document("legacy-date", old, None);
+    legacy_date.insert("last_updated", mongodb::bson::DateTime::from_chrono(old));
+    legacy_date.remove("fetched_at");
+    let mut legacy_recent = token_document("legacy-recent", Utc::now(), None);
+    legacy_recent.remove("fetched_at");
+    db.collection::<Document>("tokens")
+        .insert_many(vec![slow_updater, unfetched, hidden, legacy_date, legacy_recent], None)
+        .await
+        .unwrap();
+
+    let deleted = db_client.purge_stale_tokens(Utc::now() - Duration::hours(24)).await.unwrap();
+    assert_eq!(deleted, 2);
+    let mut left: Vec<String> = db_client
+        .get_tokens_collection()
+        .distinct("token_id", None, None)
+        .await
+        .unwrap()
+        .into_iter()
+        .filter_map(|id| id.as_str().map(String::from))
+        .collect();
+    left.sort();
+    assert_eq!(left, ["hidden-coin", "legacy-recent", "slow-updater"]);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_ensure_indexes_is_idempotent() {
+    let db = common::setup_test_db().await;
+    let db_client = DbClient { db: db.clone() };
+
+    db_client.ensure_indexes().await.unwrap();
+    db_client.ensure_indexes().await.unwrap();
+
+    let names = db_client.get_tokens_collection().list_index_names().await.unwrap();
+    assert_eq!(names.iter().filter(|name| *name == TOKEN_TEXT_INDEX).count(), 1, "{:?}", names);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_repair_passes_fix_corrupted_documents_once() {
+    common::init_test_logger();
+
+    let db = common::setup_test_db().await;
+    let db_client = DbClient { db: db.clone() };
+    let tokens = db.collection::<Document>("tokens");
+    let token_document = |token_id: &str, age: Duration| {
+        let mut token = common::mock_data::create_test_token(token_id);
+        token.last_updated = Utc::now() - age;
+        mongodb::bson::to_document(&token).unwrap()
+    };
+
+    // Three copies of bitcoin: the newest isn't the favorited one, and the oldest still
+    // holds last_updated as a BSON date
+    let mut favorited = token_document("bitcoin", Duration::hours(2));
+    favorited.insert("is_favorite", true);
+    favorited.insert("tags", vec!["hodl"]);
+    let mut newest = token_document("bitcoin", Duration::minutes(5));
+    newest.insert("current_price", 61000.0);
+    let mut legacy = token_document("bitcoin", Duration::days(3));
+    legacy.insert("last_updated", mongodb::bson::DateTime::from_chrono(Utc::now() - Duration::days(3)));
+    legacy.insert("note", "cold wallet");
+    // An old-schema ethereum without the fields added since
+    let mut incomplete = token_document("ethereum", Duration::minutes(5));
+    for field in ["is_favorite", "ath", "image", "high_24h"] {
+        incomplete.remove(field);
+    }
+    tokens.insert_many(vec![favorited, newest, legacy, incomplete], None).await.unwrap();
+    db.collection::<Document>("price_history")
+        .insert_many(
+            vec![
+                doc! { "token_id": "bitcoin", "prices": [] },
+                doc! { "token_id": "ethereum", "prices": [[1_i64, 2.0]] },
+            ],
+            None,
+        )
+        .await
+        .unwrap();
+
+    let reports = db_client.repair(&RepairPass::ALL).await.unwrap();
+    let counts: Vec<(RepairPass, u64, u64, u64)> = reports.iter().map(|r| (r.pass, r.examined, r.fixed, r.deleted)).collect();
+    assert_eq!(
+        counts,
+        vec![(RepairPass::Dedupe, 4, 1, 2), (RepairPass::Backfill, 2, 1, 0), (RepairPass::EmptyHistory, 2, 0, 1)]
+    );
+
+    // The newest copy survives, with what users set on the others; every token deserializes again
+    let stored: Vec<CryptoToken> = db_client.get_tokens_collection().find(None, None).await.unwrap().try_collect().await.unwrap();
+    assert_eq!(stored.len(), 2);
+    let bitcoin = stored.iter().find(|t| t.token_id == "bitcoin").unwrap();
+    assert_eq!(bitcoin.current_price, 61000.0);
+    assert!(bitcoin.is_favorite);
+    assert_eq!(bitcoin.tags, vec!["hodl".to_string()]);
+    assert_eq!(bitcoin.note.as_deref(), Some("cold wallet"));
+    let ethereum = tokens.find_one(doc! { "token_id": "ethereum" }, None).await.unwrap().unwrap();
+    assert_eq!(ethereum.get("ath"), Some(&mongodb::bson::Bson::Null));
+    let history: Vec<Document> = db.collection::<Document>("price_history").find(None, None).await.unwrap().try_collect().await.unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].get_str("token_id").unwrap(), "ethereum");
+
+    // Nothing left to fix
+    for report in db_client.repair(&RepairPass::ALL).await.unwrap() {
+        assert_eq!((report.fixed, report.deleted), (0, 0), "{:?}", report.pass);
+    }
+
+    common::cleanup_test_db(&db).await;
+}
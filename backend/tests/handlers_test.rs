@@ -0,0 +1,4720 @@
+// HTTP-level tests for the API handlers
+mod common;
+
+use actix_web::{test, web, App};
+use chrono::{Duration as ChronoDuration, DurationRound, Utc};
+use crypto_tracker_backend::{
+    backfill::BackfillStatus,
+    binance::BinanceService,
+    config::Config,
+    crypto_service::CryptoService,
+    currency::SUPPORTED_CURRENCIES,
+    db::DbClient,
+    events::EventRecorder,
+    fallback::FallbackProvider,
+    force_fresh::ForceFreshLimiter,
+    handlers::{self, HistoryFlights},
+    image_proxy::ImageProxy,
+    models::{
+        BulkFavoriteResponse, CacheDebugInfo, CacheStatus, Category, CoinGeckoHistoricalData, CoinProfile, CoinSearchResult, ConversionResult, ExchangeRate, ExchangeRates, CorrelationMatrix, CryptoToken, DailyReport, DominanceHistory, DominanceSource, FavoritesExport, FavoritesImportMode, FavoritesImportSummary, MarketCapPoint, ErrorResponse, HistoricalChange, HistoryInterval, ImportSummary, NewUser, PortfolioSummary, PriceAt, PriceSource, Readiness,
+        Event, EventKind, ScoredToken, SymbolMapping, TokenBatch, TokenChange, TokenDeletion, TokenPurge, TransactionEntry,
+    },
+    rate_limiter::RateLimiter,
+    routes,
+    shutdown::BackgroundTasks,
+    token_cache::TokenCache,
+    upstream::UpstreamGate,
+    webhook::WebhookNotifier,
+};
+use mongodb::bson::doc;
+use serial_test::serial;
+use std::time::Duration;
+use wiremock::matchers::{method, path, query_param, query_param_is_missing};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+// Everything the handlers pull from app_data, with defaults that never reach the network
+struct TestState {
+    config: Config,
+    db: DbClient,
+    crypto_service: CryptoService,
+    rate_limiter: web::Data<RateLimiter>,
+    background_tasks: BackgroundTasks,
+    token_cache: web::Data<TokenCache>,
+    fallback: web::Data<FallbackProvider>,
+    upstream: web::Data<UpstreamGate>,
+    images: web::Data<ImageProxy>,
+}
+
+impl TestState {
+    fn new(db: DbClient) -> Self {
+        Self {
+            config: Config::default_for_tests(),
+            db,
+            crypto_service: CryptoService::new("http://127.0.0.1:9".to_string(), None),
+            rate_limiter: web::Data::new(RateLimiter::new(0.0, 60)),
+            background_tasks: BackgroundTasks::new(),
+            token_cache: web::Data::new(TokenCache::new(Duration::from_secs(60))),
+            fallback: web::Data::new(FallbackProvider::disabled()),
+            upstream: web::Data::new(UpstreamGate::from_config(&Config::default_for_tests())),
+            images: web::Data::new(ImageProxy::default()),
+        }
+    }
+}
+
+macro_rules! test_app {
+    ($state:expr) => {
+        test::init_service(
+            App::new()
+                .app_data(web::Data::new($state.config.clone()))
+                .app_data(web::Data::new($state.db.clone()))
+                .app_data(web::Data::new($state.crypto_service.clone()))
+                .app_data($state.rate_limiter.clone())
+                .app_data(web::Data::new($state.background_tasks.clone()))
+                .app_data($state.token_cache.clone())
+                .app_data(web::Data::new(WebhookNotifier::disabled()))
+                .app_data(web::Data::new(EventRecorder::new($state.db.clone(), &$state.config)))
+                .app_data($state.upstream.clone())
+                .app_data(web::Data::new(HistoryFlights::new()))
+                .app_data($state.images.clone())
+                .app_data(web::Data::new(ForceFreshLimiter::from_config(&$state.config)))
+                .app_data($state.fallback.clone())
+                .app_data(web::Data::new(BackfillStatus::default()))
+                .configure(routes::configure),
+        )
+        .await
+    };
+}
+
+// Client construction is lazy, so handlers that never need the database run without MongoDB.
+// Lookups fail fast and are treated as cache misses.
+async fn offline_db() -> DbClient {
+    let client = mongodb::Client::with_uri_str("mongodb://127.0.0.1:9/?serverSelectionTimeoutMS=100")
+        .await
+        .expect("valid connection string");
+    DbClient { db: client.database("crypto_tracker_offline") }
+}
+
+fn header_u64(resp: &actix_web::dev::ServiceResponse, name: &str) -> u64 {
+    resp.headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_else(|| panic!("missing or non-numeric {} header", name))
+}
+
+fn cached_token(token_id: &str, price: f64, age: ChronoDuration) -> CryptoToken {
+    CryptoToken {
+        id: None,
+        token_id: token_id.to_string(),
+        symbol: "btc".to_string(),
+        name: "Bitcoin".to_string(),
+        current_price: price,
+        market_cap: 1000000.0,
+        volume_24h: 10000.0,
+        price_change_24h: 0.0,
+        price_change_percentage_24h: 0.0,
+        price_change_percentage_7d: None,
+        price_change_percentage_30d: None,
+        price_change_percentage_1y: None,
+        sparkline_7d: None,
+        high_24h: None,
+        low_24h: None,
+        circulating_supply: None,
+        total_supply: None,
+        max_supply: None,
+        fully_diluted_valuation: None,
+        ath: None,
+        ath_change_percentage: None,
+        atl: None,
+        atl_change_percentage: None,
+        image: None,
+        last_updated: Utc::now() - age,
+        fetched_at: Some(Utc::now() - age),
+        is_favorite: false,
+        hidden: false,
+        tags: Vec::new(),
+        note: None,
+        price_source: None,
+        category: None,
+    }
+}
+
+#[actix_web::test]
+async fn test_debug_cache_hidden_by_default() {
+    let state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/debug/cache").to_request();
+    let resp = test::call_service(&app, req).await;
+
+    assert_eq!(resp.status(), 404);
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_debug_cache_reports_rate_limiter_state() {
+    let db = common::setup_test_db().await;
+    let mut state = TestState::new(DbClient { db: db.clone() });
+    state.config.debug_endpoints = true;
+    state.rate_limiter = web::Data::new(RateLimiter::new(2.0, 60));
+    state.rate_limiter.record_rate_limit().await;
+
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/debug/cache").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+
+    let info: CacheDebugInfo = test::read_body_json(resp).await;
+    assert_eq!(info.cached_tokens, 0);
+    assert!(info.oldest_last_updated.is_none());
+    assert_eq!(info.rate_limited_until, state.rate_limiter.rate_limited_until().await);
+    assert!(info.seconds_until_next_call > 0);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_cache_status_summarizes_each_collection() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    state.rate_limiter.record_rate_limit().await;
+
+    let tokens: Vec<CryptoToken> = [
+        ("bitcoin", ChronoDuration::minutes(1)),
+        ("ethereum", ChronoDuration::hours(2)),
+        ("solana", ChronoDuration::days(3)),
+    ]
+    .into_iter()
+    .map(|(id, age)| cached_token(id, 1.0, age))
+    .collect();
+    state.db.get_tokens_collection().insert_many(&tokens, None).await.unwrap();
+
+    // Stored the way get_historical_data writes them, millisecond BSON dates
+    let fetched = Utc::now().duration_trunc(ChronoDuration::milliseconds(1)).unwrap();
+    let history = db.collection::<mongodb::bson::Document>("price_history");
+    history
+        .insert_many(
+            [
+                doc! { "token_id": "bitcoin", "days": 7_i64, "timestamp": fetched - ChronoDuration::hours(5), "expires_at": fetched },
+                doc! { "token_id": "bitcoin", "days": "max", "timestamp": fetched, "expires_at": fetched + ChronoDuration::days(1) },
+            ],
+            None,
+        )
+        .await
+        .unwrap();
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/cache/status").to_request();
+    let status: CacheStatus = test::call_and_read_body_json(&app, req).await;
+
+    assert_eq!(status.tokens.count, 3);
+    assert_eq!(status.tokens.oldest, Some(tokens[2].last_updated));
+    assert_eq!(status.tokens.newest, Some(tokens[0].last_updated));
+
+    assert_eq!(status.price_history.summary.count, 2);
+    assert_eq!(status.price_history.summary.oldest, Some(fetched - ChronoDuration::hours(5)));
+    assert_eq!(status.price_history.summary.newest, Some(fetched));
+    let days: Vec<Option<&str>> = status.price_history.entries.iter().map(|e| e.days.as_deref()).collect();
+    assert_eq!(days, vec![Some("7"), Some("max")]);
+    assert_eq!(status.price_history.entries[1].expires_at, Some(fetched + ChronoDuration::days(1)));
+
+    assert!(status.rate_limit.backing_off);
+    assert_eq!(status.rate_limit.until, state.rate_limiter.rate_limited_until().await);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+async fn test_liveness_ignores_the_database_but_readiness_does_not() {
+    let state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/health/live").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+    let req = test::TestRequest::get().uri("/health/ready").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 503);
+    let readiness: Readiness = test::read_body_json(resp).await;
+    assert_eq!(readiness, Readiness { ready: false, mongodb: false, tokens_cached: false });
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_ready_once_the_first_tokens_are_stored() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/health/ready").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 503);
+    let readiness: Readiness = test::read_body_json(resp).await;
+    assert!(readiness.mongodb && !readiness.tokens_cached);
+
+    state
+        .db
+        .get_tokens_collection()
+        .insert_one(cached_token("bitcoin", 50000.0, ChronoDuration::zero()), None)
+        .await
+        .unwrap();
+    let req = test::TestRequest::get().uri("/health/ready").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let readiness: Readiness = test::read_body_json(resp).await;
+    assert!(readiness.ready);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+async fn test_cache_status_reports_database_errors() {
+    let state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/cache/status").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 500);
+}
+
+#[actix_web::test]
+async fn test_get_token_uncached_while_rate_limited_returns_503() {
+    let state = TestState::new(offline_db().await);
+    state.rate_limiter.record_rate_limit().await;
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/tokens/bitcoin").to_request();
+    let resp = test::call_service(&app, req).await;
+
+    assert_eq!(resp.status(), 503);
+    let retry_after = header_u64(&resp, "Retry-After");
+    assert!(retry_after > 0);
+
+    let body: ErrorResponse = test::read_body_json(resp).await;
+    assert_eq!(body.retry_after, Some(retry_after));
+}
+
+#[actix_web::test]
+async fn test_get_token_tells_unknown_from_upstream_failure() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .and(query_param("ids", "no-such-coin"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .and(query_param("ids", "bitcoin"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(offline_db().await);
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/tokens/no-such-coin").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+
+    // A CoinGecko outage says nothing about whether the token exists
+    let req = test::TestRequest::get().uri("/api/tokens/bitcoin").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 503);
+    assert!(header_u64(&resp, "Retry-After") > 0);
+    assert!(state.rate_limiter.rate_limited_until().await.is_none());
+}
+
+#[actix_web::test]
+async fn test_admin_bearer_token_is_not_looked_up_as_a_user_key() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([{
+            "id": "bitcoin",
+            "symbol": "btc",
+            "name": "Bitcoin",
+            "current_price": 60000.0,
+            "market_cap": 1000000000000.0,
+            "total_volume": 50000000000.0,
+            "last_updated": Utc::now().to_rfc3339()
+        }])))
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(offline_db().await);
+    state.config.admin_token = Some("s3cret".to_string());
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let app = test_app!(state);
+
+    // /api/tokens is per user when a key is sent; the admin token isn't one
+    let req = test::TestRequest::get()
+        .uri("/api/tokens")
+        .insert_header(("Authorization", "Bearer s3cret"))
+        .to_request();
+    let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(tokens.len(), 1);
+}
+
+#[actix_web::test]
+async fn test_token_list_at_the_limit_is_fetched_in_one_page() {
+    let mock_server = MockServer::start().await;
+    // Market caps keep falling across pages, as CoinGecko ranks them
+    let page = |prefix: &str, rank: usize| {
+        let markets: Vec<serde_json::Value> = (0..250)
+            .map(|i| serde_json::json!({
+                "id": format!("{}-{}", prefix, i),
+                "symbol": "tok",
+                "name": "Token",
+                "current_price": 1.0,
+                "market_cap": 1.0e6 - (rank + i) as f64,
+                "total_volume": 1.0,
+                "last_updated": Utc::now().to_rfc3339()
+            }))
+            .collect();
+        ResponseTemplate::new(200).set_body_json(markets)
+    };
+    // TOP_TOKENS_LIMIT is capped at CoinGecko's per_page, so a second page is never asked for
+    for (number, body, calls) in [("1", page("first", 0), 1), ("2", page("second", 250), 0)] {
+        Mock::given(method("GET"))
+            .and(path("/coins/markets"))
+            .and(query_param("per_page", "250"))
+            .and(query_param("page", number))
+            .respond_with(body)
+            .expect(calls)
+            .mount(&mock_server)
+            .await;
+    }
+
+    let mut state = TestState::new(offline_db().await);
+    state.config.top_tokens_limit = 250;
+    state.crypto_service = CryptoService::new(mock_server.uri(), None).with_page_delay(Duration::ZERO);
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/tokens").to_request();
+    let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(tokens.len(), 250);
+    assert_eq!(tokens[0].token_id, "first-0");
+    assert_eq!(tokens[249].token_id, "first-249");
+}
+
+#[actix_web::test]
+async fn test_slow_upstream_call_is_cut_off() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])).set_delay(Duration::from_secs(5)))
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(offline_db().await);
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    state.upstream = web::Data::new(UpstreamGate::new(4, Duration::from_millis(200)));
+    let app = test_app!(state);
+
+    let started = std::time::Instant::now();
+    let req = test::TestRequest::get().uri("/api/tokens/bitcoin").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 503);
+    assert!(started.elapsed() < Duration::from_secs(2), "took {:?}", started.elapsed());
+    assert_eq!(state.upstream.in_use(), 0);
+}
+
+#[actix_web::test]
+async fn test_upstream_concurrency_is_capped() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/bitcoin/market_chart"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(serde_json::json!({ "prices": [[1, 2.0]], "market_caps": [], "total_volumes": [] }))
+                .set_delay(Duration::from_millis(500)),
+        )
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(offline_db().await);
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    state.upstream =
+        web::Data::new(UpstreamGate::new(2, Duration::from_secs(5)).with_permit_wait(Duration::from_millis(100)));
+    let app = test_app!(state);
+
+    // Six different ranges at once: two get permits, the rest give up after the short wait
+    let requests = (7..13).map(|days| {
+        test::call_service(&app, test::TestRequest::get().uri(&format!("/api/history/bitcoin/{}", days)).to_request())
+    });
+    let statuses: Vec<u16> = futures::future::join_all(requests).await.iter().map(|r| r.status().as_u16()).collect();
+    assert_eq!(statuses.iter().filter(|s| **s == 200).count(), 2, "{:?}", statuses);
+    assert_eq!(statuses.iter().filter(|s| **s == 503).count(), 4, "{:?}", statuses);
+    assert_eq!(mock_server.received_requests().await.unwrap().len(), 2);
+
+    let req = test::TestRequest::get().uri("/metrics").to_request();
+    let body = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+    assert!(body.contains("upstream_permits_in_use 0\n"), "{}", body);
+    assert!(body.contains("upstream_permits_max 2\n"), "{}", body);
+    assert!(body.contains("history_backfill_pending 0\n"), "{}", body);
+}
+
+#[actix_web::test]
+async fn test_identical_history_requests_share_one_upstream_call() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/bitcoin/market_chart"))
+        .and(query_param("days", "7"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(serde_json::json!({ "prices": [[1, 2.0]], "market_caps": [], "total_volumes": [] }))
+                .set_delay(Duration::from_millis(300)),
+        )
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(offline_db().await);
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    // One upstream call a minute, so a second fetch would find no slot
+    state.rate_limiter = web::Data::new(RateLimiter::new(60.0, 1));
+    let app = test_app!(state);
+
+    // Without coalescing the second and third would find no rate-limit slot and nothing cached
+    let requests = (0..3).map(|_| test::call_service(&app, test::TestRequest::get().uri("/api/history/bitcoin/7").to_request()));
+    let mut bodies = Vec::new();
+    for resp in futures::future::join_all(requests).await {
+        assert_eq!(resp.status(), 200);
+        bodies.push(test::read_body_json::<serde_json::Value, _>(resp).await);
+    }
+    assert!(bodies.iter().all(|body| body["prices"][0][1] == 2.0), "{:?}", bodies);
+    assert_eq!(mock_server.received_requests().await.unwrap().len(), 1);
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_get_token_serves_stale_copy_and_refreshes_in_background() {
+    let db = common::setup_test_db().await;
+    let mock_server = MockServer::start().await;
+
+    let fresh = serde_json::json!([{
+        "id": "bitcoin",
+        "symbol": "btc",
+        "name": "Bitcoin",
+        "image": "https://example.com/btc.png",
+        "current_price": 60000.0,
+        "market_cap": 1000000000000.0,
+        "total_volume": 50000000000.0,
+        "last_updated": Utc::now().to_rfc3339()
+    }]);
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .and(query_param("ids", "bitcoin"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(fresh))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(DbClient { db: db.clone() });
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let collection = state.db.get_tokens_collection();
+    collection
+        .insert_one(cached_token("bitcoin", 50000.0, ChronoDuration::minutes(10)), None)
+        .await
+        .unwrap();
+
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/tokens/bitcoin").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+
+    let age = header_u64(&resp, "X-Cache-Age");
+    assert!(age >= 600);
+    let token: CryptoToken = test::read_body_json(resp).await;
+    assert_eq!(token.current_price, 50000.0);
+
+    // Let the refresh land, then the cache should hold the new price
+    assert!(state.background_tasks.shutdown(Duration::from_secs(5)).await);
+    let refreshed = collection
+        .find_one(doc! { "token_id": "bitcoin" }, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(refreshed.current_price, 60000.0);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+async fn test_hiding_requires_admin_token() {
+    let mut state = TestState::new(offline_db().await);
+    state.config.admin_token = Some("s3cret".to_string());
+    let app = test_app!(state);
+
+    for uri in ["/api/admin/tokens/bitcoin/hide", "/api/admin/tokens/bitcoin/unhide"] {
+        let req = test::TestRequest::post().uri(uri).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 401, "{}", uri);
+    }
+}
+
+#[actix_web::test]
+async fn test_writes_require_the_api_token_once_configured() {
+    let mut state = TestState::new(offline_db().await);
+    state.config.api_token = Some("writer".to_string());
+    state.config.admin_token = Some("s3cret".to_string());
+    let app = test_app!(state);
+
+    // An invalid id gets a 400 from the handler itself, so reaching it is easy to tell from a 401
+    let delete = || test::TestRequest::delete().uri("/api/v1/tokens/Not%20A%20Token/annotation");
+
+    for req in [delete(), delete().insert_header(("Authorization", "Bearer wrong")), delete().insert_header(("Authorization", "writer"))] {
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status(), 401);
+        assert_eq!(resp.headers().get("WWW-Authenticate").unwrap(), "Bearer");
+        let body: ErrorResponse = test::read_body_json(resp).await;
+        assert!(body.error.contains("Bearer"), "{}", body.error);
+    }
+
+    let req = delete().insert_header(("Authorization", "Bearer writer")).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    // Legacy paths are guarded alike, and the admin token stands in for the API token
+    let req = test::TestRequest::post().uri("/api/admin/purge?older_than_hours=0").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 401);
+    let req = test::TestRequest::post()
+        .uri("/api/admin/purge?older_than_hours=0")
+        .insert_header(("X-Admin-Token", "s3cret"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    // Reads stay public
+    let req = test::TestRequest::get().uri("/api/currencies").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+}
+
+#[actix_web::test]
+async fn test_writes_are_open_without_an_api_token() {
+    let state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+
+    let req = test::TestRequest::delete().uri("/api/tokens/Not%20A%20Token/annotation").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_hidden_token_stays_out_of_lists_and_stats_after_a_refresh() {
+    let db = common::setup_test_db().await;
+    let mock_server = MockServer::start().await;
+
+    let market = |id: &str, change: f64| serde_json::json!({
+        "id": id,
+        "symbol": id,
+        "name": id,
+        "image": "https://example.com/coin.png",
+        "current_price": 1.0,
+        "market_cap": 1000000.0,
+        "total_volume": 10000.0,
+        "price_change_percentage_24h": change,
+        "last_updated": Utc::now().to_rfc3339()
+    });
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            market("bitcoin", 2.0),
+            market("wrapped-bitcoin", 5.0),
+        ])))
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(DbClient { db: db.clone() });
+    state.config.admin_token = Some("s3cret".to_string());
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let collection = state.db.get_tokens_collection();
+    for token_id in ["bitcoin", "wrapped-bitcoin"] {
+        collection
+            .insert_one(cached_token(token_id, 1.0, ChronoDuration::minutes(10)), None)
+            .await
+            .unwrap();
+    }
+    let app = test_app!(state);
+
+    let req = test::TestRequest::post()
+        .uri("/api/admin/tokens/wrapped-bitcoin/hide")
+        .insert_header(("X-Admin-Token", "s3cret"))
+        .to_request();
+    let token: CryptoToken = test::call_and_read_body_json(&app, req).await;
+    assert!(token.hidden);
+
+    // A refresh from upstream must not unhide it
+    let req = test::TestRequest::get().uri("/api/tokens").to_request();
+    let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(tokens.iter().map(|t| t.token_id.as_str()).collect::<Vec<_>>(), ["bitcoin"]);
+    assert!(state.background_tasks.shutdown(Duration::from_secs(5)).await);
+    let stored = collection.find_one(doc! { "token_id": "wrapped-bitcoin" }, None).await.unwrap().unwrap();
+    assert_eq!(stored.price_change_percentage_24h, 5.0);
+    assert!(stored.hidden);
+    state.rate_limiter.record_rate_limit().await;
+
+    for uri in ["/api/tokens", "/api/tokens?cursor=", "/api/search?q=bitcoin"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(tokens.iter().map(|t| t.token_id.as_str()).collect::<Vec<_>>(), ["bitcoin"], "{}", uri);
+    }
+    let req = test::TestRequest::get().uri("/api/tokens?include_hidden=true").to_request();
+    let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(tokens.len(), 2);
+
+    let req = test::TestRequest::get().uri("/api/stats").to_request();
+    let stats: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(stats["total_tokens"], 1);
+    assert_eq!(stats["total_market_cap"], 1000000.0);
+    let req = test::TestRequest::get().uri("/api/gainers").to_request();
+    let gainers: Vec<TokenChange> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(gainers.iter().map(|t| t.token_id.as_str()).collect::<Vec<_>>(), ["bitcoin"]);
+
+    let req = test::TestRequest::post()
+        .uri("/api/admin/tokens/wrapped-bitcoin/unhide")
+        .insert_header(("X-Admin-Token", "s3cret"))
+        .to_request();
+    let token: CryptoToken = test::call_and_read_body_json(&app, req).await;
+    assert!(!token.hidden);
+    let req = test::TestRequest::get().uri("/api/stats").to_request();
+    let stats: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(stats["total_tokens"], 2);
+
+    let req = test::TestRequest::post()
+        .uri("/api/admin/tokens/no-such-token/hide")
+        .insert_header(("X-Admin-Token", "s3cret"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_sparklines_are_cached_and_survive_plain_refreshes() {
+    let db = common::setup_test_db().await;
+    let mock_server = MockServer::start().await;
+
+    let market = |price: f64, at: chrono::DateTime<Utc>| serde_json::json!({
+        "id": "bitcoin",
+        "symbol": "btc",
+        "name": "Bitcoin",
+        "image": "https://example.com/btc.png",
+        "current_price": price,
+        "market_cap": 1000000000000.0,
+        "total_volume": 50000000000.0,
+        "last_updated": at.to_rfc3339()
+    });
+    let mut with_sparkline = market(50000.0, Utc::now() - ChronoDuration::minutes(5));
+    with_sparkline["sparkline_in_7d"] = serde_json::json!({ "price": [49000.0, 50000.0] });
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .and(query_param("sparkline", "true"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([with_sparkline])))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .and(query_param("sparkline", "false"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([market(51000.0, Utc::now())])))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(DbClient { db: db.clone() });
+    // Every request refreshes, however recent the last fetch
+    state.config.token_cache_ttl_secs = 0;
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let collection = state.db.get_tokens_collection();
+    let app = test_app!(state);
+
+    for uri in ["/api/tokens?sparkline=true", "/api/tokens"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        // Saves run in the background; let each land before the next fetch
+        assert!(state.background_tasks.shutdown(Duration::from_secs(5)).await);
+    }
+
+    let stored = collection.find_one(doc! { "token_id": "bitcoin" }, None).await.unwrap().unwrap();
+    assert_eq!(stored.current_price, 51000.0);
+    assert_eq!(stored.sparkline_7d, Some(vec![49000.0, 50000.0]));
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_refresh_keeps_user_owned_fields() {
+    let db = common::setup_test_db().await;
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([{
+            "id": "bitcoin",
+            "symbol": "btc",
+            "name": "Bitcoin",
+            "image": "https://example.com/btc.png",
+            "current_price": 60000.0,
+            "market_cap": 1000000000000.0,
+            "total_volume": 50000000000.0,
+            "last_updated": Utc::now().to_rfc3339()
+        }])))
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(DbClient { db: db.clone() });
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let collection = state.db.get_tokens_collection();
+    collection
+        .insert_one(cached_token("bitcoin", 50000.0, ChronoDuration::minutes(10)), None)
+        .await
+        .unwrap();
+    let app = test_app!(state);
+
+    let req = test::TestRequest::post()
+        .uri("/api/tokens/favorite")
+        .set_json(serde_json::json!({ "token_id": "bitcoin" }))
+        .to_request();
+    let token: CryptoToken = test::call_and_read_body_json(&app, req).await;
+    assert!(token.is_favorite);
+    let req = test::TestRequest::put()
+        .uri("/api/favorites/bitcoin/meta")
+        .set_json(serde_json::json!({ "tags": ["hodl"], "note": "cold wallet" }))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    // The upstream copy is newer, so the refresh overwrites the market data, and the
+    // list answered from it still carries what the user set
+    let req = test::TestRequest::get().uri("/api/tokens").to_request();
+    let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(tokens[0].current_price, 60000.0);
+    assert!(tokens[0].is_favorite);
+    assert_eq!(tokens[0].tags, vec!["hodl".to_string()]);
+    assert_eq!(tokens[0].note.as_deref(), Some("cold wallet"));
+    assert!(state.background_tasks.shutdown(Duration::from_secs(5)).await);
+
+    let stored = collection.find_one(doc! { "token_id": "bitcoin" }, None).await.unwrap().unwrap();
+    assert_eq!(stored.current_price, 60000.0);
+    assert!(stored.is_favorite);
+    assert_eq!(stored.tags, vec!["hodl".to_string()]);
+    assert_eq!(stored.note.as_deref(), Some("cold wallet"));
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_freshly_fetched_list_keeps_shared_favorites() {
+    let db = common::setup_test_db().await;
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([{
+            "id": "bitcoin",
+            "symbol": "btc",
+            "name": "Bitcoin",
+            "image": "https://example.com/btc.png",
+            "current_price": 60000.0,
+            "market_cap": 1000000000000.0,
+            "total_volume": 50000000000.0,
+            "last_updated": Utc::now().to_rfc3339()
+        }])))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(DbClient { db: db.clone() });
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let mut favorite = cached_token("bitcoin", 50000.0, ChronoDuration::minutes(10));
+    favorite.is_favorite = true;
+    state.db.get_tokens_collection().insert_one(favorite, None).await.unwrap();
+    let app = test_app!(state);
+
+    // The stored copy is stale, so the list is answered from the upstream fetch
+    let req = test::TestRequest::get().uri("/api/tokens").to_request();
+    let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0].current_price, 60000.0);
+    assert!(tokens[0].is_favorite);
+    assert!(state.background_tasks.shutdown(Duration::from_secs(5)).await);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_refresh_skips_write_when_upstream_unchanged() {
+    let db = common::setup_test_db().await;
+    let mock_server = MockServer::start().await;
+
+    // CoinGecko still reports the figures from when we stored them
+    // Stored timestamps have millisecond precision
+    let mut stored = cached_token("bitcoin", 50000.0, ChronoDuration::minutes(10));
+    stored.last_updated = stored.last_updated.duration_trunc(ChronoDuration::milliseconds(1)).unwrap();
+    let upstream = serde_json::json!([{
+        "id": "bitcoin",
+        "symbol": "btc",
+        "name": "Bitcoin",
+        "image": "https://example.com/btc.png",
+        "current_price": 60000.0,
+        "market_cap": 1000000000000.0,
+        "total_volume": 50000000000.0,
+        "last_updated": stored.last_updated.to_rfc3339()
+    }]);
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .and(query_param("ids", "bitcoin"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(upstream))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(DbClient { db: db.clone() });
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let collection = state.db.get_tokens_collection();
+    collection.insert_one(&stored, None).await.unwrap();
+    let app = test_app!(state);
+    let req = test::TestRequest::get().uri("/api/tokens/bitcoin").to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+    assert!(state.background_tasks.shutdown(Duration::from_secs(5)).await);
+
+    // The prices stay as stored, but the copy counts as freshly fetched
+    let after = collection.find_one(doc! { "token_id": "bitcoin" }, None).await.unwrap().unwrap();
+    assert_eq!(after.current_price, 50000.0);
+    assert_eq!(after.last_updated, stored.last_updated);
+    assert!(after.fetched_at.unwrap() > stored.fetched_at.unwrap());
+    assert_eq!(collection.count_documents(doc! { "token_id": "bitcoin" }, None).await.unwrap(), 1);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_saving_the_same_snapshot_twice_only_advances_fetched_at() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    let collection = state.db.get_tokens_collection();
+    let events = EventRecorder::new(state.db.clone(), &state.config);
+
+    // Stored timestamps have millisecond precision
+    let mut snapshot = cached_token("bitcoin", 50000.0, ChronoDuration::minutes(10));
+    snapshot.last_updated = snapshot.last_updated.duration_trunc(ChronoDuration::milliseconds(1)).unwrap();
+    handlers::save_tokens_to_cache(&collection, &state.token_cache, &WebhookNotifier::disabled(), &events, &[snapshot.clone()]).await;
+    let first = collection.find_one(doc! { "token_id": "bitcoin" }, None).await.unwrap().unwrap();
+
+    // Upstream hasn't moved, but we asked again later
+    snapshot.fetched_at = Some(Utc::now());
+    handlers::save_tokens_to_cache(&collection, &state.token_cache, &WebhookNotifier::disabled(), &events, &[snapshot.clone()]).await;
+    let second = collection.find_one(doc! { "token_id": "bitcoin" }, None).await.unwrap().unwrap();
+
+    assert!(second.fetched_at.unwrap() > first.fetched_at.unwrap());
+    assert_eq!(second.current_price, first.current_price);
+    assert_eq!(second.market_cap, first.market_cap);
+    assert_eq!(second.last_updated, first.last_updated);
+    assert_eq!(collection.count_documents(doc! { "token_id": "bitcoin" }, None).await.unwrap(), 1);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_rising_prices_record_one_ath_break() {
+    let db = common::setup_test_db().await;
+    let mock_server = MockServer::start().await;
+    let mut state = TestState::new(DbClient { db: db.clone() });
+    state.config.token_cache_ttl_secs = 0;
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let mut stored = cached_token("bitcoin", 50000.0, ChronoDuration::minutes(10));
+    stored.ath = Some(55000.0);
+    state.db.get_tokens_collection().insert_one(stored, None).await.unwrap();
+    let app = test_app!(state);
+
+    // Each refresh sees a newer quote above the ATH the previous one stored
+    for price in [56000.0, 57000.0, 58000.0] {
+        mock_server.reset().await;
+        Mock::given(method("GET"))
+            .and(path("/coins/markets"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([{
+                "id": "bitcoin",
+                "symbol": "btc",
+                "name": "Bitcoin",
+                "current_price": price,
+                "market_cap": 1000000000000.0,
+                "total_volume": 50000000000.0,
+                "ath": price,
+                "last_updated": Utc::now().to_rfc3339()
+            }])))
+            .mount(&mock_server)
+            .await;
+        let req = test::TestRequest::get().uri("/api/tokens").to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        assert!(state.background_tasks.shutdown(Duration::from_secs(5)).await);
+    }
+
+    let req = test::TestRequest::get().uri("/api/events?kind=ath_break").to_request();
+    let events: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["token_id"], "bitcoin");
+    assert_eq!(events[0]["old_value"], 55000.0);
+    assert_eq!(events[0]["new_value"], 56000.0);
+
+    // 50000 -> 56000 is a 12% move; the later steps are under 2%
+    let req = test::TestRequest::get().uri("/api/events?kind=large_move").to_request();
+    let events: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(events.len(), 1);
+    assert!((events[0]["change_percentage"].as_f64().unwrap() - 12.0).abs() < 1e-9);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+async fn test_events_query_is_validated() {
+    let state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+
+    for uri in ["/api/events?kind=moon", "/api/events?since=yesterday", "/api/events?limit=0"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400, "{}", uri);
+    }
+}
+
+#[actix_web::test]
+async fn test_alert_requests_are_validated() {
+    let state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+
+    for (body, field) in [
+        (serde_json::json!({ "condition": { "type": "percent_move", "percent": 0.05 }, "scope": "bitcoin" }), "condition.percent"),
+        (serde_json::json!({ "condition": { "type": "percent_move", "percent": 2000 }, "scope": "bitcoin" }), "condition.percent"),
+        (serde_json::json!({ "condition": { "type": "percent_move", "percent": 10 }, "scope": "watchlist:core" }), "scope"),
+        (serde_json::json!({ "condition": { "type": "percent_move", "percent": 10 }, "scope": "Not A Token" }), "scope"),
+    ] {
+        let req = test::TestRequest::post().uri("/api/alerts").set_json(&body).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400, "{}", body);
+        let error: ErrorResponse = test::read_body_json(resp).await;
+        assert_eq!(error.fields[0].field, field, "{}", body);
+    }
+
+    let req = test::TestRequest::delete().uri("/api/alerts/not-an-id").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_favorites_alert_covers_new_favorites_and_cools_down() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    let collection = state.db.get_tokens_collection();
+    for token_id in ["solana", "dogecoin"] {
+        collection.insert_one(cached_token(token_id, 100.0, ChronoDuration::minutes(10)), None).await.unwrap();
+    }
+    let app = test_app!(state);
+
+    let req = test::TestRequest::post()
+        .uri("/api/alerts")
+        .set_json(serde_json::json!({ "condition": { "type": "percent_move", "percent": 10, "window": "24h" }, "scope": "favorites" }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 201);
+    let alert: serde_json::Value = test::read_body_json(resp).await;
+    let alert_id = alert["id"].as_str().unwrap().to_string();
+
+    // Favorited after the alert was created, so only a scope expanded at evaluation covers it
+    let req = test::TestRequest::post()
+        .uri("/api/tokens/favorite")
+        .set_json(serde_json::json!({ "token_id": "solana" }))
+        .to_request();
+    assert!(test::call_service(&app, req).await.status().is_success());
+
+    let events = EventRecorder::new(state.db.clone(), &state.config);
+    let moved = |token_id: &str| {
+        let mut token = cached_token(token_id, 112.0, ChronoDuration::zero());
+        token.price_change_percentage_24h = 12.0;
+        token
+    };
+    // The same sustained move, seen by two refreshes
+    for _ in 0..2 {
+        let tokens = vec![moved("solana"), moved("dogecoin")];
+        handlers::save_tokens_to_cache(&collection, &state.token_cache, &WebhookNotifier::disabled(), &events, &tokens).await;
+    }
+
+    let req = test::TestRequest::get().uri(&format!("/api/alerts/{}/triggers", alert_id)).to_request();
+    let triggers: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(triggers.len(), 1);
+    assert_eq!(triggers[0]["token_id"], "solana");
+    assert_eq!(triggers[0]["change_percentage"], 12.0);
+
+    let req = test::TestRequest::delete().uri(&format!("/api/alerts/{}", alert_id)).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 204);
+    let req = test::TestRequest::get().uri("/api/alerts").to_request();
+    let alerts: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+    assert!(alerts.is_empty());
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_get_token_fresh_copy_skips_refresh() {
+    let db = common::setup_test_db().await;
+    let mock_server = MockServer::start().await;
+
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(0)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(DbClient { db: db.clone() });
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    state
+        .db
+        .get_tokens_collection()
+        .insert_one(cached_token("bitcoin", 50000.0, ChronoDuration::seconds(30)), None)
+        .await
+        .unwrap();
+
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/tokens/bitcoin").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+
+    let age = header_u64(&resp, "X-Cache-Age");
+    assert!(age < 300);
+    assert!(state.background_tasks.shutdown(Duration::from_secs(1)).await);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+async fn test_token_supply_reports_nulls_instead_of_guessing() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .and(query_param("ids", "ethereum"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([{
+            "id": "ethereum",
+            "symbol": "eth",
+            "name": "Ethereum",
+            "image": "https://example.com/eth.png",
+            "current_price": 3000.0,
+            "market_cap": 360000000000.0,
+            "total_volume": 10000000000.0,
+            "circulating_supply": 120000000.0,
+            "total_supply": 120000000.0,
+            "max_supply": null,
+            "last_updated": "2024-01-01T00:00:00.000Z"
+        }])))
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(offline_db().await);
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/tokens/ethereum/supply").to_request();
+    let supply: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(supply["circulating_supply"], 120000000.0);
+    assert_eq!(supply["total_supply"], 120000000.0);
+    for field in ["max_supply", "fully_diluted_valuation", "circulating_percent"] {
+        assert!(supply[field].is_null(), "{}", field);
+    }
+
+    let req = test::TestRequest::get().uri("/api/tokens/Not%20An%20Id/supply").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+}
+
+#[actix_web::test]
+async fn test_token_profile_fetched_and_stripped_of_html() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/bitcoin"))
+        .and(query_param("market_data", "false"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "id": "bitcoin",
+            "symbol": "btc",
+            "name": "Bitcoin",
+            "description": { "en": "<p>The first <a href=\"https://en.wikipedia.org/wiki/Cryptocurrency\">cryptocurrency</a>.</p>" },
+            "links": { "homepage": ["https://bitcoin.org", ""], "twitter_screen_name": "bitcoin", "subreddit_url": "https://www.reddit.com/r/Bitcoin/" },
+            "genesis_date": "2009-01-03",
+            "categories": ["Cryptocurrency"]
+        })))
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/coins/no-such-coin"))
+        .respond_with(ResponseTemplate::new(404).set_body_string(r#"{"error":"coin not found"}"#))
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(offline_db().await);
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/tokens/bitcoin/profile").to_request();
+    let profile: CoinProfile = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(profile.description, "The first cryptocurrency.");
+    assert_eq!(profile.homepage.as_deref(), Some("https://bitcoin.org"));
+    assert_eq!(profile.reddit.as_deref(), Some("https://www.reddit.com/r/Bitcoin/"));
+    assert_eq!(profile.genesis_date.as_deref(), Some("2009-01-03"));
+
+    let req = test::TestRequest::get().uri("/api/tokens/no-such-coin/profile").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+    let req = test::TestRequest::get().uri("/api/tokens/Not%20An%20Id/profile").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_stored_profile_served_until_it_expires() {
+    let db = common::setup_test_db().await;
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/bitcoin"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "id": "bitcoin", "symbol": "btc", "name": "Bitcoin", "description": { "en": "Fetched" }
+        })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(DbClient { db: db.clone() });
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let stored = |age: ChronoDuration| CoinProfile {
+        token_id: "bitcoin".to_string(),
+        symbol: "btc".to_string(),
+        name: "Bitcoin".to_string(),
+        description: "Stored".to_string(),
+        homepage: None,
+        twitter: None,
+        reddit: None,
+        genesis_date: None,
+        categories: Vec::new(),
+        fetched_at: Utc::now() - age,
+    };
+    state.db.save_coin_profile(&stored(ChronoDuration::days(1))).await.unwrap();
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/tokens/bitcoin/profile").to_request();
+    let profile: CoinProfile = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(profile.description, "Stored");
+
+    // Past PROFILE_CACHE_TTL_SECS it's fetched again and replaced
+    state.db.save_coin_profile(&stored(ChronoDuration::days(8))).await.unwrap();
+    let req = test::TestRequest::get().uri("/api/tokens/bitcoin/profile").to_request();
+    let profile: CoinProfile = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(profile.description, "Fetched");
+    assert!(state.background_tasks.shutdown(Duration::from_secs(5)).await);
+    assert_eq!(state.db.load_coin_profile("bitcoin").await.unwrap().unwrap().description, "Fetched");
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+async fn test_get_tokens_served_from_memory_without_database() {
+    let state = TestState::new(offline_db().await);
+    state.rate_limiter.record_rate_limit().await;
+    state.token_cache.set(vec![cached_token("bitcoin", 50000.0, ChronoDuration::zero())]).await;
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/tokens").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+
+    let tokens: Vec<CryptoToken> = test::read_body_json(resp).await;
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0].token_id, "bitcoin");
+}
+
+#[actix_web::test]
+async fn test_get_tokens_fields_selects_keys() {
+    let state = TestState::new(offline_db().await);
+    state.rate_limiter.record_rate_limit().await;
+    let mut bitcoin = cached_token("bitcoin", 50000.0, ChronoDuration::zero());
+    bitcoin.ath = Some(69000.0);
+    state.token_cache.set(vec![bitcoin]).await;
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/tokens?fields=symbol,current_price,ath").to_request();
+    let tokens: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        tokens,
+        serde_json::json!([{ "token_id": "bitcoin", "symbol": "btc", "current_price": 50000.0, "ath": 69000.0 }])
+    );
+}
+
+#[actix_web::test]
+async fn test_unknown_fields_rejected_with_the_valid_set() {
+    let state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+
+    for uri in ["/api/tokens?fields=symbol,price", "/api/tokens/bitcoin?fields=symbol,price"] {
+        let resp = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+        assert_eq!(resp.status(), 400, "{}", uri);
+        let body: ErrorResponse = test::read_body_json(resp).await;
+        assert!(body.error.starts_with("unknown fields: price; valid fields are "), "{}: {}", uri, body.error);
+        assert!(body.error.contains("current_price"), "{}", body.error);
+    }
+    // Derived metrics only exist on the single token
+    let resp = test::call_service(&app, test::TestRequest::get().uri("/api/tokens?fields=fdv").to_request()).await;
+    assert_eq!(resp.status(), 400);
+}
+
+#[actix_web::test]
+async fn test_get_token_fields_applies_to_fetched_token() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .and(query_param("ids", "bitcoin"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([{
+            "id": "bitcoin",
+            "symbol": "btc",
+            "name": "Bitcoin",
+            "image": "",
+            "current_price": 50000.0,
+            "market_cap": 1000000000000.0,
+            "total_volume": 50000000000.0,
+            "fully_diluted_valuation": 1050000000000.0
+        }])))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(offline_db().await);
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/tokens/bitcoin?fields=name,fdv").to_request();
+    let token: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(token, serde_json::json!({ "token_id": "bitcoin", "name": "Bitcoin", "fdv": 1050000000000.0 }));
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_cursor_page_fields_are_projected() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    let tokens: Vec<CryptoToken> = (0..3)
+        .map(|i| {
+            let mut token = cached_token(&format!("token-{}", i), 1.0, ChronoDuration::zero());
+            token.market_cap = i as f64;
+            token
+        })
+        .collect();
+    state.db.get_tokens_collection().insert_many(&tokens, None).await.unwrap();
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/tokens?per_page=2&cursor=&fields=name").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let next = resp.headers().get("X-Next-Cursor").unwrap().to_str().unwrap().to_string();
+    let page: serde_json::Value = test::read_body_json(resp).await;
+    // market_cap, _id and the timestamps come back from MongoDB for the cursor but aren't sent
+    assert_eq!(
+        page,
+        serde_json::json!([{ "token_id": "token-2", "name": "Bitcoin" }, { "token_id": "token-1", "name": "Bitcoin" }])
+    );
+
+    let req = test::TestRequest::get().uri(&format!("/api/tokens?per_page=2&cursor={}&fields=name", next)).to_request();
+    let page: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(page, serde_json::json!([{ "token_id": "token-0", "name": "Bitcoin" }]));
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+async fn test_get_tokens_cache_miss_fetches_from_upstream() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([{
+            "id": "bitcoin",
+            "symbol": "btc",
+            "name": "Bitcoin",
+            "image": "https://example.com/btc.png",
+            "current_price": 50000.0,
+            "market_cap": 1000000000000.0,
+            "total_volume": 50000000000.0
+        }])))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(offline_db().await);
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/tokens").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    assert!(resp.headers().get("X-Partial-Result").is_none());
+
+    let tokens: Vec<CryptoToken> = test::read_body_json(resp).await;
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0].token_id, "bitcoin");
+    assert_eq!(tokens[0].current_price, 50000.0);
+}
+
+#[actix_web::test]
+async fn test_get_tokens_rate_limited_and_uncached_returns_503() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+        .expect(0)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(offline_db().await);
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    state.rate_limiter.record_rate_limit().await;
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/tokens").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 503);
+
+    let body: ErrorResponse = test::read_body_json(resp).await;
+    assert_eq!(body.retry_after, Some(60));
+}
+
+#[actix_web::test]
+async fn test_get_tokens_upstream_429_serves_cache_and_backs_off() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .respond_with(ResponseTemplate::new(429))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(offline_db().await);
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    state.token_cache.set(vec![cached_token("bitcoin", 50000.0, ChronoDuration::minutes(10))]).await;
+    let app = test_app!(state);
+
+    // The 429 starts a backoff, so the second request never reaches upstream
+    for _ in 0..2 {
+        let req = test::TestRequest::get().uri("/api/tokens").to_request();
+        let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].current_price, 50000.0);
+    }
+    assert!(state.rate_limiter.rate_limited_until().await.is_some());
+}
+
+#[actix_web::test]
+async fn test_get_tokens_skips_upstream_while_cache_is_fresh() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+        .expect(0)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(offline_db().await);
+    state.config.token_cache_ttl_secs = 300;
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let mut bitcoin = cached_token("bitcoin", 50000.0, ChronoDuration::minutes(2));
+    bitcoin.sparkline_7d = Some(vec![49000.0, 50000.0]);
+    state.token_cache.set(vec![bitcoin]).await;
+    let app = test_app!(state);
+
+    // The rate limiter would allow a call; the cache is young enough not to need one
+    for uri in ["/api/tokens", "/api/tokens?sparkline=true"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(tokens[0].current_price, 50000.0, "{}", uri);
+    }
+    assert_eq!(state.rate_limiter.seconds_until_next_call().await, 0);
+}
+
+fn bitcoin_market(price: f64) -> serde_json::Value {
+    serde_json::json!([{
+        "id": "bitcoin",
+        "symbol": "btc",
+        "name": "Bitcoin",
+        "image": "https://example.com/btc.png",
+        "current_price": price,
+        "market_cap": 1000000000000.0,
+        "total_volume": 50000000000.0,
+        "last_updated": Utc::now().to_rfc3339()
+    }])
+}
+
+fn get_from(uri: &str, ip: &str) -> test::TestRequest {
+    test::TestRequest::get().uri(uri).peer_addr(format!("{}:40000", ip).parse().unwrap())
+}
+
+#[actix_web::test]
+async fn test_forced_fresh_list_goes_upstream_once_per_client() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(bitcoin_market(60000.0)))
+        .expect(2)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(offline_db().await);
+    state.config.token_cache_ttl_secs = 300;
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    state.token_cache.set(vec![cached_token("bitcoin", 50000.0, ChronoDuration::minutes(2))]).await;
+    let app = test_app!(state);
+
+    // Young cache, but the client asked for fresh data
+    let resp = test::call_service(&app, get_from("/api/tokens?fresh=true", "10.0.0.1").to_request()).await;
+    assert!(resp.headers().get("X-Cache").is_none());
+    let tokens: Vec<CryptoToken> = test::read_body_json(resp).await;
+    assert_eq!(tokens[0].current_price, 60000.0);
+
+    // The same client again within the interval is answered from the cache
+    let resp = test::call_service(&app, get_from("/api/tokens?fresh=true", "10.0.0.1").to_request()).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("X-Cache").unwrap(), "STALE-FORCED");
+    let retry_after = header_u64(&resp, "Retry-After");
+    assert!((29..=30).contains(&retry_after), "{}", retry_after);
+
+    // Another client still gets its own forced fetch, through Cache-Control this time
+    let req = get_from("/api/tokens", "10.0.0.2").insert_header(("Cache-Control", "no-cache")).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.headers().get("X-Cache").is_none());
+}
+
+#[actix_web::test]
+async fn test_forced_fresh_list_respects_upstream_backoff() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(bitcoin_market(60000.0)))
+        .expect(0)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(offline_db().await);
+    state.config.token_cache_ttl_secs = 300;
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    state.token_cache.set(vec![cached_token("bitcoin", 50000.0, ChronoDuration::minutes(2))]).await;
+    state.rate_limiter.record_rate_limit().await;
+    let app = test_app!(state);
+
+    let resp = test::call_service(&app, get_from("/api/tokens?fresh=true", "10.0.0.1").to_request()).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("X-Cache").unwrap(), "STALE-FORCED");
+    // It was the backoff, not this client, that stood in the way
+    assert!(resp.headers().get("Retry-After").is_none());
+    let tokens: Vec<CryptoToken> = test::read_body_json(resp).await;
+    assert_eq!(tokens[0].current_price, 50000.0);
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_forced_fresh_token_is_refetched_before_answering() {
+    let db = common::setup_test_db().await;
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .and(query_param("ids", "bitcoin"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(bitcoin_market(60000.0)))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(DbClient { db: db.clone() });
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    state
+        .db
+        .get_tokens_collection()
+        .insert_one(cached_token("bitcoin", 50000.0, ChronoDuration::seconds(30)), None)
+        .await
+        .unwrap();
+    let app = test_app!(state);
+
+    let resp = test::call_service(&app, get_from("/api/tokens/bitcoin?fresh=true", "10.0.0.1").to_request()).await;
+    assert_eq!(header_u64(&resp, "X-Cache-Age"), 0);
+    assert!(resp.headers().get("X-Cache").is_none());
+    let token: CryptoToken = test::read_body_json(resp).await;
+    assert_eq!(token.current_price, 60000.0);
+
+    let req = get_from("/api/tokens/bitcoin", "10.0.0.1").insert_header(("Cache-Control", "no-cache")).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.headers().get("X-Cache").unwrap(), "STALE-FORCED");
+    assert!(resp.headers().contains_key("Retry-After"));
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_forced_fresh_token_keeps_user_owned_fields() {
+    let db = common::setup_test_db().await;
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .and(query_param("ids", "bitcoin"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(bitcoin_market(60000.0)))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(DbClient { db: db.clone() });
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let mut stored = cached_token("bitcoin", 50000.0, ChronoDuration::seconds(30));
+    stored.is_favorite = true;
+    stored.tags = vec!["hodl".to_string()];
+    stored.note = Some("cold wallet".to_string());
+    state.db.get_tokens_collection().insert_one(stored, None).await.unwrap();
+    let app = test_app!(state);
+
+    let req = get_from("/api/tokens/bitcoin?fresh=true", "10.0.0.1").to_request();
+    let token: CryptoToken = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(token.current_price, 60000.0);
+    assert!(token.is_favorite);
+    assert_eq!(token.tags, vec!["hodl".to_string()]);
+    assert_eq!(token.note.as_deref(), Some("cold wallet"));
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+async fn test_get_tokens_refreshes_cache_older_than_ttl() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([{
+            "id": "bitcoin",
+            "symbol": "btc",
+            "name": "Bitcoin",
+            "image": "https://example.com/btc.png",
+            "current_price": 60000.0,
+            "market_cap": 1000000000000.0,
+            "total_volume": 50000000000.0
+        }])))
+        .expect(2)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(offline_db().await);
+    state.config.token_cache_ttl_secs = 60;
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    state.token_cache.set(vec![cached_token("bitcoin", 50000.0, ChronoDuration::minutes(2))]).await;
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/tokens").to_request();
+    let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(tokens[0].current_price, 60000.0);
+
+    // A fresh list without the sparklines asked for still goes upstream
+    state.token_cache.set(vec![cached_token("bitcoin", 50000.0, ChronoDuration::zero())]).await;
+    let req = test::TestRequest::get().uri("/api/tokens?sparkline=true").to_request();
+    let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(tokens[0].current_price, 60000.0);
+}
+
+#[actix_web::test]
+async fn test_hidden_tokens_left_out_of_lists_and_search_by_default() {
+    let state = TestState::new(offline_db().await);
+    state.rate_limiter.record_rate_limit().await;
+    let mut scam = cached_token("bitcoin-scam", 1.0, ChronoDuration::zero());
+    scam.hidden = true;
+    state.token_cache.set(vec![cached_token("bitcoin", 50000.0, ChronoDuration::zero()), scam]).await;
+    let app = test_app!(state);
+
+    for (uri, expected) in [
+        ("/api/tokens", vec!["bitcoin"]),
+        ("/api/tokens?include_hidden=true", vec!["bitcoin", "bitcoin-scam"]),
+        ("/api/search?q=bitcoin", vec!["bitcoin"]),
+        ("/api/search?q=bitcoin&include_hidden=true", vec!["bitcoin", "bitcoin-scam"]),
+    ] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+        let ids: Vec<&str> = tokens.iter().map(|t| t.token_id.as_str()).collect();
+        assert_eq!(ids, expected, "{}", uri);
+    }
+}
+
+#[actix_web::test]
+async fn test_search_ranks_symbol_matches_first_and_caps_limit() {
+    let state = TestState::new(offline_db().await);
+    state.rate_limiter.record_rate_limit().await;
+    let mut tokens = vec![
+        cached_token("wrapped-bitcoin", 60000.0, ChronoDuration::zero()),
+        cached_token("bitcoin", 60000.0, ChronoDuration::zero()),
+        cached_token("bitcoin-cash", 300.0, ChronoDuration::zero()),
+    ];
+    tokens[0].symbol = "wbtc".to_string();
+    tokens[0].market_cap *= 10.0;
+    tokens[1].symbol = "btc".to_string();
+    tokens[2].symbol = "bch".to_string();
+    state.token_cache.set(tokens).await;
+    let app = test_app!(state);
+
+    for (uri, expected) in [
+        ("/api/search?q=btc", vec!["bitcoin", "wrapped-bitcoin"]),
+        ("/api/search?q=bitcoin", vec!["wrapped-bitcoin", "bitcoin", "bitcoin-cash"]),
+        ("/api/search?q=bitcoin&limit=2", vec!["wrapped-bitcoin", "bitcoin"]),
+        ("/api/search?all=true&limit=1", vec!["wrapped-bitcoin"]),
+    ] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+        let ids: Vec<&str> = tokens.iter().map(|t| t.token_id.as_str()).collect();
+        assert_eq!(ids, expected, "{}", uri);
+    }
+
+    for uri in ["/api/search?q=btc&limit=0", "/api/search?q=btc&limit=201", "/api/search?q=btc&limit=x", "/api/search?q="] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400, "{}", uri);
+    }
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_text_search_ranks_by_score_with_symbol_matches_first() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    state.db.ensure_indexes().await.unwrap();
+    state.rate_limiter.record_rate_limit().await;
+    let mut tokens = vec![
+        cached_token("wrapped-bitcoin", 60000.0, ChronoDuration::zero()),
+        cached_token("bitcoin", 60000.0, ChronoDuration::zero()),
+        cached_token("btc-index", 10.0, ChronoDuration::zero()),
+    ];
+    (tokens[0].symbol, tokens[0].name) = ("wbtc".to_string(), "Wrapped Bitcoin".to_string());
+    tokens[0].market_cap *= 10.0;
+    (tokens[1].symbol, tokens[1].name) = ("btc".to_string(), "Bitcoin".to_string());
+    (tokens[2].symbol, tokens[2].name) = ("btcx".to_string(), "BTC Index".to_string());
+    state.db.get_tokens_collection().insert_many(&tokens, None).await.unwrap();
+    let app = test_app!(state);
+
+    // Words match in any order, where a substring search finds nothing
+    let req = test::TestRequest::get().uri("/api/search?q=bitcoin%20wrapped").to_request();
+    let found: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(found[0].token_id, "wrapped-bitcoin");
+
+    // The exact symbol outranks the same word in a name
+    let req = test::TestRequest::get().uri("/api/search?q=btc&debug=true").to_request();
+    let found: Vec<ScoredToken> = test::call_and_read_body_json(&app, req).await;
+    let ids: Vec<&str> = found.iter().map(|t| t.token.token_id.as_str()).collect();
+    assert_eq!(ids, vec!["bitcoin", "btc-index"]);
+    let scores: Vec<f64> = found.iter().map(|t| t.score.expect("text search score")).collect();
+    assert!(scores[0] > scores[1], "{:?}", scores);
+
+    // Without debug the scores stay out
+    let req = test::TestRequest::get().uri("/api/search?q=btc").to_request();
+    let found: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+    assert!(found.iter().all(|t| t.get("score").is_none()));
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_short_and_symbol_queries_fall_back_to_substrings() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    state.db.ensure_indexes().await.unwrap();
+    state.rate_limiter.record_rate_limit().await;
+    let mut tokens = vec![
+        cached_token("bitcoin", 60000.0, ChronoDuration::zero()),
+        cached_token("bitcoin-cash", 300.0, ChronoDuration::zero()),
+    ];
+    tokens[1].symbol = "bch".to_string();
+    state.db.get_tokens_collection().insert_many(&tokens, None).await.unwrap();
+    let app = test_app!(state);
+
+    // Prefixes the text index has no word for: "bi" is short, "bitc" isn't a word
+    for (uri, expected) in [
+        ("/api/search?q=bi&debug=true", vec!["bitcoin", "bitcoin-cash"]),
+        ("/api/search?q=bitc&debug=true", vec!["bitcoin", "bitcoin-cash"]),
+        ("/api/search?q=$btc&debug=true", vec![]),
+        ("/api/search?q=bitcoin-cash&debug=true", vec!["bitcoin-cash"]),
+    ] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let found: Vec<ScoredToken> = test::call_and_read_body_json(&app, req).await;
+        let ids: Vec<&str> = found.iter().map(|t| t.token.token_id.as_str()).collect();
+        assert_eq!(ids, expected, "{}", uri);
+        assert!(found.iter().all(|t| t.score.is_none()), "{}", uri);
+    }
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+async fn test_sparklines_only_sent_when_asked_for() {
+    let state = TestState::new(offline_db().await);
+    state.rate_limiter.record_rate_limit().await;
+    let mut bitcoin = cached_token("bitcoin", 50000.0, ChronoDuration::zero());
+    bitcoin.sparkline_7d = Some(vec![49000.0, 50000.0]);
+    state.token_cache.set(vec![bitcoin]).await;
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/tokens").to_request();
+    let tokens: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+    assert!(tokens[0].get("sparkline_7d").is_none());
+
+    let req = test::TestRequest::get().uri("/api/tokens?sparkline=true").to_request();
+    let tokens: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(tokens[0]["sparkline_7d"], serde_json::json!([49000.0, 50000.0]));
+
+    let req = test::TestRequest::get().uri("/api/search?q=bitcoin").to_request();
+    let tokens: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+    assert!(tokens[0].get("sparkline_7d").is_none());
+}
+
+#[actix_web::test]
+async fn test_envelope_wraps_tokens_only_when_asked_for() {
+    let state = TestState::new(offline_db().await);
+    state.token_cache.set(vec![
+        cached_token("bitcoin", 50000.0, ChronoDuration::zero()),
+        cached_token("ethereum", 3000.0, ChronoDuration::zero()),
+        cached_token("solana", 150.0, ChronoDuration::zero()),
+    ]).await;
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/tokens").to_request();
+    let raw: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(raw.as_array().unwrap().len(), 3);
+
+    for req in [
+        test::TestRequest::get().uri("/api/tokens").insert_header(("X-Response-Envelope", "true")).to_request(),
+        test::TestRequest::get().uri("/api/tokens?envelope=true").to_request(),
+    ] {
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["data"], raw);
+        assert_eq!(body["meta"]["cache"], "hit");
+        assert!(body["meta"]["generated_at"].is_string());
+        assert!(body["meta"]["pagination"].is_null());
+        assert!(body.get("error").is_none());
+    }
+
+    let req = test::TestRequest::get().uri("/api/tokens?page=2&per_page=1&envelope=true").to_request();
+    let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["data"], serde_json::json!([raw[1]]));
+    assert_eq!(body["meta"]["pagination"], serde_json::json!({ "page": 2, "per_page": 1 }));
+
+    let req = test::TestRequest::get().uri("/api/tokens?sort_by=bogus&envelope=true").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["error"]["code"], "bad_request");
+    assert!(body["error"]["message"].as_str().unwrap().starts_with("sort_by must be one of"));
+    assert!(body["error"]["retry_after"].is_null());
+    assert!(body.get("data").is_none() && body.get("meta").is_none());
+}
+
+#[actix_web::test]
+async fn test_envelope_carries_retry_after_on_errors() {
+    let state = TestState::new(offline_db().await);
+    state.rate_limiter.record_rate_limit().await;
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/tokens").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 503);
+    let raw: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(raw["retry_after"], 60);
+
+    let req = test::TestRequest::get().uri("/api/tokens").insert_header(("X-Response-Envelope", "true")).to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 503);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(
+        body,
+        serde_json::json!({
+            "error": { "code": "service_unavailable", "message": raw["error"], "retry_after": 60 }
+        })
+    );
+}
+
+#[actix_web::test]
+async fn test_search_asks_coingecko_only_when_the_cache_has_no_match() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/search"))
+        .and(query_param("query", "pepe"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({ "coins": [{ "id": "pepe", "name": "Pepe", "symbol": "PEPE" }] })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .and(query_param("ids", "pepe"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([{
+            "id": "pepe",
+            "symbol": "pepe",
+            "name": "Pepe",
+            "image": "https://example.com/pepe.png",
+            "current_price": 0.00001,
+            "market_cap": 5e9,
+            "total_volume": 1000.0,
+            "last_updated": "2024-01-01T00:00:00.000Z"
+        }])))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(offline_db().await);
+    state.crypto_service = CryptoService::new(mock_server.uri(), None).with_page_delay(Duration::ZERO);
+    state.token_cache.set(vec![cached_token("bitcoin", 50000.0, ChronoDuration::zero())]).await;
+    let app = test_app!(state);
+
+    // A cache hit and a one-letter miss never reach CoinGecko
+    for (uri, expected) in [("/api/search?q=bit", vec!["bitcoin"]), ("/api/search?q=z", vec![])] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(tokens.iter().map(|t| t.token_id.as_str()).collect::<Vec<_>>(), expected, "{}", uri);
+    }
+
+    let req = test::TestRequest::get().uri("/api/search?q=pepe").to_request();
+    let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(tokens.len(), 1);
+    assert_eq!((tokens[0].token_id.as_str(), tokens[0].name.as_str()), ("pepe", "Pepe"));
+
+    // Backing off, a miss is just an empty result
+    state.rate_limiter.record_rate_limit().await;
+    let req = test::TestRequest::get().uri("/api/search?q=pepe").to_request();
+    let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert!(tokens.is_empty());
+    assert!(state.background_tasks.shutdown(Duration::from_secs(5)).await);
+}
+
+#[actix_web::test]
+async fn test_search_coins_passes_coingeckos_results_through() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/search"))
+        .and(query_param("query", "pepe"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({ "coins": [
+            { "id": "pepe", "name": "Pepe", "symbol": "PEPE", "market_cap_rank": 30, "thumb": "https://example.com/t.png" },
+            { "id": "pepe-classic", "name": "Pepe Classic", "symbol": "PEPEC" }
+        ] })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(offline_db().await);
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let app = test_app!(state);
+
+    for uri in ["/api/search/coins", "/api/search/coins?q=%20p%20"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400, "{}", uri);
+    }
+
+    let req = test::TestRequest::get().uri("/api/search/coins?q=pepe").to_request();
+    let coins: Vec<CoinSearchResult> = test::call_and_read_body_json(&app, req).await;
+    let ids: Vec<&str> = coins.iter().map(|c| c.id.as_str()).collect();
+    assert_eq!(ids, ["pepe", "pepe-classic"]);
+    assert_eq!(coins[0].market_cap_rank, Some(30));
+
+    // Backing off, nothing is asked upstream
+    state.rate_limiter.record_rate_limit().await;
+    let req = test::TestRequest::get().uri("/api/search/coins?q=pepe").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 503);
+    assert!(resp.headers().contains_key("retry-after"));
+}
+
+#[actix_web::test]
+async fn test_near_ath_lists_tokens_within_threshold_closest_first() {
+    let state = TestState::new(offline_db().await);
+    let mut tokens = Vec::new();
+    for (token_id, ath_change) in [
+        ("bitcoin", Some(-3.5)),
+        ("ethereum", Some(-0.5)),
+        ("solana", Some(0.2)),
+        ("cardano", Some(-40.0)),
+        ("dogecoin", None),
+    ] {
+        let mut token = cached_token(token_id, 1.0, ChronoDuration::zero());
+        token.ath_change_percentage = ath_change;
+        token.sparkline_7d = Some(vec![1.0]);
+        tokens.push(token);
+    }
+    state.token_cache.set(tokens).await;
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/near_ath").to_request();
+    let near: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(near.iter().map(|t| t.token_id.as_str()).collect::<Vec<_>>(), ["solana", "ethereum", "bitcoin"]);
+    assert!(near.iter().all(|t| t.sparkline_7d.is_none()));
+
+    let req = test::TestRequest::get().uri("/api/near_ath?threshold=1").to_request();
+    let near: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(near.iter().map(|t| t.token_id.as_str()).collect::<Vec<_>>(), ["solana", "ethereum"]);
+
+    for threshold in ["0", "-1", "NaN"] {
+        let req = test::TestRequest::get().uri(&format!("/api/near_ath?threshold={}", threshold)).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400, "threshold={}", threshold);
+    }
+}
+
+#[actix_web::test]
+async fn test_matching_etag_gets_304() {
+    let state = TestState::new(offline_db().await);
+    state.rate_limiter.record_rate_limit().await;
+    state.token_cache.set(vec![cached_token("bitcoin", 50000.0, ChronoDuration::zero())]).await;
+    let app = test_app!(state);
+
+    for uri in ["/api/tokens", "/api/stats"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+        let etag = resp.headers().get("etag").expect("ETag header").clone();
+        assert!(etag.to_str().unwrap().starts_with("W/\""));
+
+        let req = test::TestRequest::get().uri(uri).insert_header(("If-None-Match", etag.clone())).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 304, "{}", uri);
+        assert_eq!(resp.headers().get("etag"), Some(&etag));
+        assert!(test::read_body(resp).await.is_empty());
+    }
+
+    // A changed list no longer matches
+    let req = test::TestRequest::get().uri("/api/tokens").to_request();
+    let etag = test::call_service(&app, req).await.headers().get("etag").unwrap().clone();
+    state.token_cache.set(vec![cached_token("bitcoin", 51000.0, ChronoDuration::zero())]).await;
+    let req = test::TestRequest::get().uri("/api/tokens").insert_header(("If-None-Match", etag)).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+}
+
+#[actix_web::test]
+async fn test_v2_lists_are_enveloped() {
+    let state = TestState::new(offline_db().await);
+    state.rate_limiter.record_rate_limit().await;
+    state
+        .token_cache
+        .set(vec![
+            cached_token("bitcoin", 50000.0, ChronoDuration::minutes(10)),
+            cached_token("bitcoin-cash", 300.0, ChronoDuration::minutes(20)),
+        ])
+        .await;
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/tokens").to_request();
+    let bare: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let req = test::TestRequest::get().uri("/api/v2/tokens").to_request();
+    let enveloped: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+    assert_eq!(enveloped["data"], bare);
+    assert_eq!(enveloped["meta"]["count"], 2);
+    assert_eq!(enveloped["meta"]["stale"], true);
+    assert!(enveloped["meta"]["cache_age_seconds"].as_u64().unwrap() >= 600);
+    assert!(enveloped["error"].is_null());
+
+    let req = test::TestRequest::get().uri("/api/v2/search?q=cash").to_request();
+    let found: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(found["data"][0]["token_id"], "bitcoin-cash");
+    assert_eq!(found["meta"]["count"], 1);
+}
+
+#[actix_web::test]
+async fn test_v2_stats_share_the_v1_figures_and_etag() {
+    let state = TestState::new(offline_db().await);
+    state.token_cache.set(vec![cached_token("bitcoin", 50000.0, ChronoDuration::zero())]).await;
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/stats").to_request();
+    let bare: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let req = test::TestRequest::get().uri("/api/v2/stats").to_request();
+    let resp = test::call_service(&app, req).await;
+    let etag = resp.headers().get("etag").expect("ETag header").clone();
+    let enveloped: serde_json::Value = test::read_body_json(resp).await;
+
+    assert_eq!(enveloped["data"], bare);
+    assert_eq!(enveloped["meta"]["count"], 1);
+    assert_eq!(enveloped["meta"]["stale"], false);
+
+    let req = test::TestRequest::get().uri("/api/v2/stats").insert_header(("If-None-Match", etag)).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 304);
+}
+
+#[actix_web::test]
+async fn test_v2_errors_use_the_envelope() {
+    let state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+
+    // Rejected by the handler, by the query extractor, and with nothing to serve
+    for (uri, status) in [
+        ("/api/v2/tokens?sort_by=nope", 400),
+        ("/api/v2/tokens?page=abc", 400),
+        ("/api/v2/search", 400),
+    ] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), status, "{}", uri);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body["data"].is_null(), "{}", uri);
+        assert!(body["meta"].is_null(), "{}", uri);
+        assert!(body["error"].as_str().is_some_and(|e| !e.is_empty()), "{}", uri);
+    }
+
+    state.rate_limiter.record_rate_limit().await;
+    let req = test::TestRequest::get().uri("/api/v2/tokens").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 503);
+    assert!(header_u64(&resp, "Retry-After") > 0);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert!(body["error"].is_string());
+}
+
+#[actix_web::test]
+async fn test_bad_cursors_are_rejected_not_restarted() {
+    let state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+
+    for (uri, message) in [
+        ("/api/tokens?cursor=garbage", "invalid"),
+        ("/api/tokens?cursor=&page=2", "page"),
+        // {"s":"name","o":"asc","v":"A","id":"000000000000000000000000","t":0}
+        ("/api/tokens?sort_by=name&cursor=eyJzIjoibmFtZSIsIm8iOiJhc2MiLCJ2IjoiQSIsImlkIjoiMDAwMDAwMDAwMDAwMDAwMDAwMDAwMDAwIiwidCI6MH0", "expired"),
+        ("/api/tokens?cursor=eyJzIjoibmFtZSIsIm8iOiJhc2MiLCJ2IjoiQSIsImlkIjoiMDAwMDAwMDAwMDAwMDAwMDAwMDAwMDAwIiwidCI6MH0", "sort_by=name"),
+    ] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400, "{}", uri);
+        let body: ErrorResponse = test::read_body_json(resp).await;
+        assert!(body.error.contains(message), "{}: {}", uri, body.error);
+    }
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_cursor_walk_has_no_gaps_or_duplicates_across_updates() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    let collection = state.db.get_tokens_collection();
+
+    // Three tokens per market cap, so page boundaries land inside ties
+    let seeded: Vec<CryptoToken> = (0..300)
+        .map(|i| {
+            let mut token = cached_token(&format!("token-{:03}", i), 1.0, ChronoDuration::zero());
+            token.name = format!("Token {:03}", (i * 7) % 300);
+            token.market_cap = (i / 3) as f64;
+            token
+        })
+        .collect();
+    collection.insert_many(&seeded, None).await.unwrap();
+    let app = test_app!(state);
+
+    for (sort, untouched_until_later) in [("market_cap", "token-000"), ("name", "token-299")] {
+        let mut seen = std::collections::HashSet::new();
+        let mut cursor = String::new();
+        let mut pages = 0;
+        loop {
+            let uri = format!("/api/tokens?sort_by={}&per_page=40&cursor={}", sort, cursor);
+            let resp = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+            assert_eq!(resp.status(), 200, "{}", uri);
+            let next = resp.headers().get("X-Next-Cursor").map(|v| v.to_str().unwrap().to_string());
+            let page: Vec<CryptoToken> = test::read_body_json(resp).await;
+            for token in &page {
+                assert!(seen.insert(token.token_id.clone()), "{} returned twice sorting by {}", token.token_id, sort);
+            }
+            pages += 1;
+
+            if pages == 1 {
+                // A refresh lands mid-walk: a token not yet returned is rewritten in
+                // place, and a new one sorts before everything already walked past
+                collection
+                    .update_one(doc! { "token_id": untouched_until_later }, doc! { "$set": { "current_price": 2.0 } }, None)
+                    .await
+                    .unwrap();
+                let mut newcomer = cached_token("newcomer", 1.0, ChronoDuration::zero());
+                newcomer.market_cap = 1e12;
+                newcomer.name = "AAA".to_string();
+                collection.insert_one(&newcomer, None).await.unwrap();
+            }
+            match next {
+                Some(next) => cursor = next,
+                None => break,
+            }
+        }
+        collection.delete_one(doc! { "token_id": "newcomer" }, None).await.unwrap();
+
+        let expected: std::collections::HashSet<String> = seeded.iter().map(|t| t.token_id.clone()).collect();
+        assert_eq!(seen, expected, "sorting by {}", sort);
+        assert_eq!(pages, 8);
+    }
+
+    // The envelope carries the cursor in meta
+    let resp = test::call_service(&app, test::TestRequest::get().uri("/api/v2/tokens?per_page=250&cursor=").to_request()).await;
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert_eq!(body["meta"]["count"], 250);
+    assert!(body["meta"]["next_cursor"].is_string());
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+async fn test_get_tokens_sorts_and_pages_cached_list() {
+    let state = TestState::new(offline_db().await);
+    state.rate_limiter.record_rate_limit().await;
+    state
+        .token_cache
+        .set(vec![
+            cached_token("bitcoin", 50000.0, ChronoDuration::zero()),
+            cached_token("ethereum", 3000.0, ChronoDuration::zero()),
+            cached_token("solana", 150.0, ChronoDuration::zero()),
+        ])
+        .await;
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get()
+        .uri("/api/tokens?sort_by=current_price&order=asc&page=1&per_page=2")
+        .to_request();
+    let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    let ids: Vec<&str> = tokens.iter().map(|t| t.token_id.as_str()).collect();
+    assert_eq!(ids, vec!["solana", "ethereum"]);
+
+    let req = test::TestRequest::get().uri("/api/tokens?page=2&per_page=2").to_request();
+    let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0].token_id, "solana");
+}
+
+#[actix_web::test]
+async fn test_list_params_rejected_before_touching_database() {
+    let state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+
+    for uri in [
+        "/api/favorites?sort_by=popularity",
+        "/api/favorites?order=sideways",
+        "/api/favorites?page=0",
+        "/api/tokens?per_page=251",
+    ] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400, "{}", uri);
+
+        let body: ErrorResponse = test::read_body_json(resp).await;
+        assert!(!body.error.is_empty());
+    }
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_get_favorites_sorted_and_paged_in_database() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    let collection = state.db.get_tokens_collection();
+    for (token_id, market_cap, is_favorite) in [
+        ("bitcoin", 900.0, true),
+        ("ethereum", 400.0, true),
+        ("solana", 100.0, true),
+        ("dogecoin", 50.0, false),
+    ] {
+        let mut token = cached_token(token_id, 1.0, ChronoDuration::zero());
+        token.market_cap = market_cap;
+        token.is_favorite = is_favorite;
+        collection.insert_one(token, None).await.unwrap();
+    }
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get()
+        .uri("/api/favorites?sort_by=market_cap&order=desc&page=2&per_page=2")
+        .to_request();
+    let favorites: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(favorites.len(), 1);
+    assert_eq!(favorites[0].token_id, "solana");
+
+    // No params still returns every favorite
+    let req = test::TestRequest::get().uri("/api/favorites").to_request();
+    let favorites: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(favorites.len(), 3);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_toggle_favorite_invalidates_memory_cache() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    state.rate_limiter.record_rate_limit().await;
+    state
+        .db
+        .get_tokens_collection()
+        .insert_one(cached_token("bitcoin", 50000.0, ChronoDuration::zero()), None)
+        .await
+        .unwrap();
+    let app = test_app!(state);
+
+    // Prime the memory cache with the not-yet-favorite token
+    let req = test::TestRequest::get().uri("/api/tokens").to_request();
+    let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert!(!tokens[0].is_favorite);
+    assert!(state.token_cache.get().await.is_some());
+
+    let req = test::TestRequest::post()
+        .uri("/api/tokens/favorite")
+        .set_json(serde_json::json!({ "token_id": "bitcoin" }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+    assert!(state.token_cache.get().await.is_none());
+
+    let req = test::TestRequest::get().uri("/api/tokens").to_request();
+    let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert!(tokens[0].is_favorite);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_concurrent_toggles_land_on_count_parity() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    state
+        .db
+        .get_tokens_collection()
+        .insert_one(cached_token("bitcoin", 50000.0, ChronoDuration::zero()), None)
+        .await
+        .unwrap();
+    let app = test_app!(state);
+
+    // A read-then-write toggle loses flips here; the atomic one can't
+    let toggles = 20;
+    let requests = (0..toggles).map(|_| {
+        let req = test::TestRequest::post()
+            .uri("/api/tokens/favorite")
+            .set_json(serde_json::json!({ "token_id": "bitcoin" }))
+            .to_request();
+        test::call_service(&app, req)
+    });
+    for resp in futures::future::join_all(requests).await {
+        assert!(resp.status().is_success());
+    }
+
+    let token = state.db.get_tokens_collection().find_one(doc! { "token_id": "bitcoin" }, None).await.unwrap().unwrap();
+    assert_eq!(token.is_favorite, toggles % 2 == 1);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_concurrent_reads_never_see_stale_favorite_after_toggle() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    state.rate_limiter.record_rate_limit().await;
+    state
+        .db
+        .get_tokens_collection()
+        .insert_one(cached_token("bitcoin", 50000.0, ChronoDuration::zero()), None)
+        .await
+        .unwrap();
+    let app = test_app!(state);
+    let done = std::cell::Cell::new(false);
+
+    let reader = || async {
+        while !done.get() {
+            let req = test::TestRequest::get().uri("/api/tokens").to_request();
+            let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(tokens.len(), 1);
+            // Cache hits never suspend, so give the other futures a turn
+            tokio::task::yield_now().await;
+        }
+    };
+
+    let writer = async {
+        for _ in 0..20 {
+            let req = test::TestRequest::post()
+                .uri("/api/tokens/favorite")
+                .set_json(serde_json::json!({ "token_id": "bitcoin" }))
+                .to_request();
+            let toggled: CryptoToken = test::call_and_read_body_json(&app, req).await;
+
+            // The very next read must reflect the write, whatever the readers cached meanwhile
+            let req = test::TestRequest::get().uri("/api/tokens").to_request();
+            let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(tokens[0].is_favorite, toggled.is_favorite);
+        }
+        done.set(true);
+    };
+
+    futures::join!(writer, reader(), reader(), reader(), reader());
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+async fn test_import_requires_admin_token() {
+    let mut state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+    let req = test::TestRequest::post().uri("/api/import/tokens").set_payload("").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+
+    state.config.admin_token = Some("s3cret".to_string());
+    let app = test_app!(state);
+    for token in [None, Some("wrong")] {
+        let mut req = test::TestRequest::post().uri("/api/import/tokens").set_payload("");
+        if let Some(token) = token {
+            req = req.insert_header(("X-Admin-Token", token));
+        }
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status(), 401);
+        let body: ErrorResponse = test::read_body_json(resp).await;
+        assert!(body.error.contains("X-Admin-Token"));
+    }
+}
+
+#[actix_web::test]
+async fn test_webhook_admin_routes_validate_before_the_database() {
+    let mut state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+    let req = test::TestRequest::get().uri("/api/admin/webhook/deliveries").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 403);
+
+    state.config.admin_token = Some("s3cret".to_string());
+    let app = test_app!(state);
+    for body in [
+        serde_json::json!({ "url": "ftp://example.com/hook" }),
+        serde_json::json!({ "url": "not a url" }),
+        serde_json::json!({ "url": "https://example.com/hook", "secret": "  " }),
+    ] {
+        let req = test::TestRequest::put()
+            .uri("/api/admin/webhook")
+            .insert_header(("X-Admin-Token", "s3cret"))
+            .set_json(&body)
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400, "{}", body);
+    }
+
+    let req = test::TestRequest::get()
+        .uri("/api/admin/webhook/deliveries?limit=0")
+        .insert_header(("X-Admin-Token", "s3cret"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+}
+
+#[actix_web::test]
+async fn test_import_reports_unparseable_lines_by_number() {
+    let mut state = TestState::new(offline_db().await);
+    state.config.admin_token = Some("s3cret".to_string());
+    let app = test_app!(state);
+
+    // Only bad lines, so nothing reaches the (offline) database
+    let body = "not json\n\n{\"token_id\":\"bitcoin\"}\r\n{\"token_id\":\"Bad Id\"}";
+    let req = test::TestRequest::post()
+        .uri("/api/import/tokens")
+        .insert_header(("X-Admin-Token", "s3cret"))
+        .set_payload(body)
+        .to_request();
+    let summary: ImportSummary = test::call_and_read_body_json(&app, req).await;
+
+    assert_eq!((summary.inserted, summary.updated), (0, 0));
+    let lines: Vec<usize> = summary.failed.iter().map(|f| f.line).collect();
+    assert_eq!(lines, vec![1, 3, 4]);
+    assert!(summary.failed[1].error.contains("missing field"));
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_json_export_streams_every_token_as_an_attachment() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    for token_id in ["bitcoin", "ethereum", "solana"] {
+        state
+            .db
+            .get_tokens_collection()
+            .insert_one(cached_token(token_id, 1.0, ChronoDuration::hours(1)), None)
+            .await
+            .unwrap();
+    }
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/tokens/export.json").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("content-type").unwrap(), "application/json");
+    assert_eq!(
+        resp.headers().get("content-disposition").unwrap(),
+        &format!("attachment; filename=\"tokens-{}.json\"", Utc::now().format("%Y-%m-%d")),
+    );
+    let tokens: Vec<CryptoToken> = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+    let mut ids: Vec<&str> = tokens.iter().map(|t| t.token_id.as_str()).collect();
+    ids.sort();
+    assert_eq!(ids, ["bitcoin", "ethereum", "solana"]);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_exports_fail_instead_of_skipping_undecodable_tokens() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    state
+        .db
+        .get_tokens_collection()
+        .insert_one(cached_token("bitcoin", 1.0, ChronoDuration::hours(1)), None)
+        .await
+        .unwrap();
+    db.collection::<mongodb::bson::Document>("tokens")
+        .insert_one(doc! { "token_id": "broken", "current_price": "not a number" }, None)
+        .await
+        .unwrap();
+    let app = test_app!(state);
+
+    // The headers are already out, so the body is cut off rather than short
+    for uri in ["/api/export/tokens.ndjson", "/api/tokens/export.json"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(actix_web::body::to_bytes(resp.into_body()).await.is_err(), "{}", uri);
+    }
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_ndjson_export_import_round_trip() {
+    let db = common::setup_test_db().await;
+    let mut state = TestState::new(DbClient { db: db.clone() });
+    state.config.admin_token = Some("s3cret".to_string());
+    let collection = state.db.get_tokens_collection();
+    // More than one import batch
+    for i in 0..150 {
+        let mut token = cached_token(&format!("token-{}", i), i as f64, ChronoDuration::zero());
+        token.is_favorite = i % 3 == 0;
+        if i % 10 == 0 {
+            token.tags = vec!["defi".to_string()];
+            token.note = Some(format!("note {}", i));
+        }
+        collection.insert_one(token, None).await.unwrap();
+    }
+    let app = test_app!(state);
+
+    let snapshot = || async {
+        let mut tokens: Vec<serde_json::Value> = Vec::new();
+        let mut cursor = collection.find(None, None).await.unwrap();
+        while cursor.advance().await.unwrap() {
+            let mut token = cursor.deserialize_current().unwrap();
+            token.id = None;
+            tokens.push(serde_json::to_value(token).unwrap());
+        }
+        tokens.sort_by_key(|t| t["token_id"].as_str().unwrap().to_string());
+        tokens
+    };
+    let before = snapshot().await;
+
+    let req = test::TestRequest::get().uri("/api/export/tokens.ndjson").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.headers().get("content-type").unwrap(), "application/x-ndjson");
+    let export = test::read_body(resp).await;
+    assert_eq!(export.iter().filter(|&&b| b == b'\n').count(), 150);
+
+    db.drop(None).await.unwrap();
+    assert!(snapshot().await.is_empty());
+
+    let req = test::TestRequest::post()
+        .uri("/api/import/tokens")
+        .insert_header(("X-Admin-Token", "s3cret"))
+        .set_payload(export.clone())
+        .to_request();
+    let summary: ImportSummary = test::call_and_read_body_json(&app, req).await;
+    assert_eq!((summary.inserted, summary.updated), (150, 0));
+    assert!(summary.failed.is_empty());
+    assert_eq!(snapshot().await, before);
+
+    // Importing again matches every token instead of duplicating it
+    let req = test::TestRequest::post()
+        .uri("/api/import/tokens")
+        .insert_header(("X-Admin-Token", "s3cret"))
+        .set_payload(export)
+        .to_request();
+    let summary: ImportSummary = test::call_and_read_body_json(&app, req).await;
+    assert_eq!((summary.inserted, summary.updated), (0, 150));
+    assert_eq!(snapshot().await.len(), 150);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+async fn test_malformed_authorization_rejected_before_touching_database() {
+    let state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+
+    for header in ["Basic dXNlcjpwYXNz", "Bearer ", "Bearer a b"] {
+        let req = test::TestRequest::get()
+            .uri("/api/favorites")
+            .insert_header(("Authorization", header))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 401, "{}", header);
+        assert!(resp.headers().contains_key("www-authenticate"));
+        let body: ErrorResponse = test::read_body_json(resp).await;
+        assert!(!body.error.is_empty());
+    }
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_users_have_disjoint_favorites() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    state.db.ensure_indexes().await.unwrap();
+    state.rate_limiter.record_rate_limit().await;
+    for token_id in ["bitcoin", "ethereum", "solana"] {
+        state
+            .db
+            .get_tokens_collection()
+            .insert_one(cached_token(token_id, 1.0, ChronoDuration::zero()), None)
+            .await
+            .unwrap();
+    }
+    let app = test_app!(state);
+
+    let mut keys = Vec::new();
+    for _ in 0..2 {
+        let req = test::TestRequest::post().uri("/api/users").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 201);
+        let user: NewUser = test::read_body_json(resp).await;
+        keys.push(format!("Bearer {}", user.api_key));
+    }
+    let (alice, bob) = (&keys[0], &keys[1]);
+
+    for (key, token_id) in [(alice, "bitcoin"), (bob, "ethereum"), (bob, "solana")] {
+        let req = test::TestRequest::post()
+            .uri("/api/tokens/favorite")
+            .insert_header(("Authorization", key.as_str()))
+            .set_json(serde_json::json!({ "token_id": token_id }))
+            .to_request();
+        let token: CryptoToken = test::call_and_read_body_json(&app, req).await;
+        assert!(token.is_favorite);
+    }
+
+    let favorite_ids = |tokens: Vec<CryptoToken>| {
+        let mut ids: Vec<String> = tokens.into_iter().filter(|t| t.is_favorite).map(|t| t.token_id).collect();
+        ids.sort();
+        ids
+    };
+    for (key, expected) in [(alice, vec!["bitcoin"]), (bob, vec!["ethereum", "solana"])] {
+        let req = test::TestRequest::get()
+            .uri("/api/favorites")
+            .insert_header(("Authorization", key.as_str()))
+            .to_request();
+        let favorites: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(favorite_ids(favorites), expected);
+
+        let req = test::TestRequest::get()
+            .uri("/api/tokens")
+            .insert_header(("Authorization", key.as_str()))
+            .to_request();
+        let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(favorite_ids(tokens), expected);
+    }
+
+    // Toggling again removes it for that user only
+    let req = test::TestRequest::post()
+        .uri("/api/tokens/favorite")
+        .insert_header(("Authorization", alice.as_str()))
+        .set_json(serde_json::json!({ "token_id": "bitcoin" }))
+        .to_request();
+    let token: CryptoToken = test::call_and_read_body_json(&app, req).await;
+    assert!(!token.is_favorite);
+
+    // None of it touched the shared flags
+    let req = test::TestRequest::get().uri("/api/favorites").to_request();
+    let favorites: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert!(favorites.is_empty());
+
+    let req = test::TestRequest::get()
+        .uri("/api/favorites")
+        .insert_header(("Authorization", "Bearer ctk_not-a-real-key"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 401);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_unauthenticated_favorites_stay_global() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    state.rate_limiter.record_rate_limit().await;
+    state
+        .db
+        .get_tokens_collection()
+        .insert_one(cached_token("bitcoin", 50000.0, ChronoDuration::zero()), None)
+        .await
+        .unwrap();
+    let app = test_app!(state);
+
+    let req = test::TestRequest::post().uri("/api/users").to_request();
+    let user: NewUser = test::call_and_read_body_json(&app, req).await;
+
+    let req = test::TestRequest::post()
+        .uri("/api/tokens/favorite")
+        .set_json(serde_json::json!({ "token_id": "bitcoin" }))
+        .to_request();
+    let token: CryptoToken = test::call_and_read_body_json(&app, req).await;
+    assert!(token.is_favorite);
+
+    let req = test::TestRequest::get().uri("/api/favorites").to_request();
+    let favorites: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(favorites.len(), 1);
+
+    // A keyed user starts from their own empty list, not the shared one
+    let req = test::TestRequest::get()
+        .uri("/api/tokens")
+        .insert_header(("Authorization", format!("Bearer {}", user.api_key)))
+        .to_request();
+    let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert!(!tokens[0].is_favorite);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+async fn test_portfolio_requires_an_api_key() {
+    let state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+
+    for req in [
+        test::TestRequest::get().uri("/api/portfolio/summary"),
+        test::TestRequest::get().uri("/api/portfolio/transactions"),
+        test::TestRequest::delete().uri("/api/portfolio/transactions/65f000000000000000000000"),
+    ] {
+        assert_eq!(test::call_service(&app, req.to_request()).await.status(), 401);
+    }
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_portfolio_transactions_and_fifo_summary() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    state
+        .db
+        .get_tokens_collection()
+        .insert_one(cached_token("bitcoin", 300.0, ChronoDuration::zero()), None)
+        .await
+        .unwrap();
+    let app = test_app!(state);
+
+    let req = test::TestRequest::post().uri("/api/users").to_request();
+    let user: NewUser = test::call_and_read_body_json(&app, req).await;
+    let key = format!("Bearer {}", user.api_key);
+    let record = |body: serde_json::Value| {
+        test::TestRequest::post()
+            .uri("/api/portfolio/transactions")
+            .insert_header(("Authorization", key.as_str()))
+            .set_json(body)
+            .to_request()
+    };
+    let trade = |side: &str, day: u32, quantity: f64, price: f64, fee: f64| {
+        serde_json::json!({
+            "token_id": "bitcoin", "side": side, "quantity": quantity, "price_per_unit": price, "fee": fee,
+            "timestamp": format!("2024-01-{:02}T00:00:00Z", day)
+        })
+    };
+
+    // Two lots at 100 and 200 (the first with a fee of 10), then 1.5 sold at 250
+    let mut ids = Vec::new();
+    for body in [trade("buy", 1, 1.0, 100.0, 10.0), trade("buy", 2, 1.0, 200.0, 0.0), trade("sell", 3, 1.5, 250.0, 5.0)] {
+        let resp = test::call_service(&app, record(body)).await;
+        assert_eq!(resp.status(), 201);
+        let entry: TransactionEntry = test::read_body_json(resp).await;
+        ids.push(entry.id);
+    }
+
+    // Only 0.5 is left
+    assert_eq!(test::call_service(&app, record(trade("sell", 4, 0.6, 250.0, 0.0))).await.status(), 422);
+    assert_eq!(test::call_service(&app, record(trade("buy", 4, -1.0, 250.0, 0.0))).await.status(), 400);
+
+    let req = test::TestRequest::get()
+        .uri("/api/portfolio/summary")
+        .insert_header(("Authorization", key.as_str()))
+        .to_request();
+    let summary: PortfolioSummary = test::call_and_read_body_json(&app, req).await;
+    let position = &summary.positions[0];
+    assert_eq!(position.quantity, 0.5);
+    assert_eq!(position.cost_basis, 100.0);
+    assert_eq!(position.average_cost, Some(200.0));
+    // Proceeds 375 - 5 against 110 + 100
+    assert_eq!(position.realized_pnl, 160.0);
+    assert_eq!(position.current_price, Some(300.0));
+    assert_eq!(position.unrealized_pnl, Some(50.0));
+    assert_eq!((summary.total_market_value, summary.total_realized_pnl), (150.0, 160.0));
+
+    // Without the second buy the sell would exceed what's held
+    let req = test::TestRequest::delete()
+        .uri(&format!("/api/portfolio/transactions/{}", ids[1]))
+        .insert_header(("Authorization", key.as_str()))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 422);
+
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/portfolio/transactions/{}", ids[2]))
+        .insert_header(("Authorization", key.as_str()))
+        .set_json(trade("sell", 3, 1.0, 250.0, 0.0))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+    let req = test::TestRequest::delete()
+        .uri(&format!("/api/portfolio/transactions/{}", ids[1]))
+        .insert_header(("Authorization", key.as_str()))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 204);
+
+    let req = test::TestRequest::get()
+        .uri("/api/portfolio/transactions")
+        .insert_header(("Authorization", key.as_str()))
+        .to_request();
+    let entries: Vec<TransactionEntry> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(entries.iter().map(|e| e.id.as_str()).collect::<Vec<_>>(), vec![ids[0].as_str(), ids[2].as_str()]);
+
+    // Another key sees none of it
+    let req = test::TestRequest::post().uri("/api/users").to_request();
+    let other: NewUser = test::call_and_read_body_json(&app, req).await;
+    let req = test::TestRequest::delete()
+        .uri(&format!("/api/portfolio/transactions/{}", ids[0]))
+        .insert_header(("Authorization", format!("Bearer {}", other.api_key)))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+async fn test_bulk_favorites_rejects_empty_and_oversized_lists() {
+    let state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+
+    let too_many: Vec<String> = (0..=handlers::MAX_BULK_FAVORITES).map(|i| format!("token-{}", i)).collect();
+    for token_ids in [Vec::new(), too_many] {
+        let req = test::TestRequest::post()
+            .uri("/api/favorites/bulk")
+            .set_json(serde_json::json!({ "token_ids": token_ids, "favorite": true }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400);
+    }
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_bulk_favorites_sets_then_clears_flag() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    state.rate_limiter.record_rate_limit().await;
+    let ids = ["bitcoin", "ethereum", "solana", "cardano", "polkadot"];
+    for id in ids {
+        state
+            .db
+            .get_tokens_collection()
+            .insert_one(cached_token(id, 1.0, ChronoDuration::zero()), None)
+            .await
+            .unwrap();
+    }
+    let app = test_app!(state);
+
+    for favorite in [true, false] {
+        let req = test::TestRequest::post()
+            .uri("/api/favorites/bulk")
+            .set_json(serde_json::json!({ "token_ids": ids, "favorite": favorite }))
+            .to_request();
+        let result: BulkFavoriteResponse = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(result.matched, 5);
+        assert_eq!(result.modified, 5);
+
+        let req = test::TestRequest::get().uri("/api/favorites").to_request();
+        let favorites: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(favorites.len(), if favorite { 5 } else { 0 });
+    }
+
+    // Unknown ids don't match, and repeating a request changes nothing
+    let req = test::TestRequest::post()
+        .uri("/api/favorites/bulk")
+        .set_json(serde_json::json!({ "token_ids": ["bitcoin", "not-a-token"], "favorite": false }))
+        .to_request();
+    let result: BulkFavoriteResponse = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(result.matched, 1);
+    assert_eq!(result.modified, 0);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_favorites_export_then_import_merge_and_replace() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    state.rate_limiter.record_rate_limit().await;
+    for (id, favorite) in [("bitcoin", true), ("ethereum", true), ("solana", false), ("cardano", false)] {
+        let mut token = cached_token(id, 1.0, ChronoDuration::zero());
+        token.is_favorite = favorite;
+        state.db.get_tokens_collection().insert_one(token, None).await.unwrap();
+    }
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/favorites/export").to_request();
+    let export: FavoritesExport = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(export.token_ids, ["bitcoin", "ethereum"]);
+
+    let favorite_ids = |tokens: Vec<CryptoToken>| {
+        let mut ids: Vec<String> = tokens.into_iter().map(|t| t.token_id).collect();
+        ids.sort();
+        ids
+    };
+
+    // Merge stars the listed tokens and leaves the rest alone; unknown ids are only reported
+    let document = serde_json::json!({
+        "exported_at": export.exported_at,
+        "token_ids": ["solana", "not-a-token"],
+        "mode": "merge",
+    });
+    for expected_favorited in [1, 0] {
+        let req = test::TestRequest::post().uri("/api/favorites/import").set_json(&document).to_request();
+        let summary: FavoritesImportSummary = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(summary.mode, FavoritesImportMode::Merge);
+        assert_eq!((summary.favorited, summary.unfavorited), (expected_favorited, 0));
+        assert_eq!(summary.unknown, ["not-a-token"]);
+    }
+    let req = test::TestRequest::get().uri("/api/favorites").to_request();
+    assert_eq!(favorite_ids(test::call_and_read_body_json(&app, req).await), ["bitcoin", "ethereum", "solana"]);
+
+    // Replace restores exactly the exported set, and again changes nothing the second time
+    let document = serde_json::json!({ "token_ids": export.token_ids, "mode": "replace" });
+    for expected_unfavorited in [1, 0] {
+        let req = test::TestRequest::post().uri("/api/favorites/import").set_json(&document).to_request();
+        let summary: FavoritesImportSummary = test::call_and_read_body_json(&app, req).await;
+        assert_eq!((summary.favorited, summary.unfavorited), (0, expected_unfavorited));
+        assert!(summary.unknown.is_empty());
+    }
+    let req = test::TestRequest::get().uri("/api/favorites").to_request();
+    assert_eq!(favorite_ids(test::call_and_read_body_json(&app, req).await), ["bitcoin", "ethereum"]);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+async fn test_favorites_import_rejects_oversized_document() {
+    let state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+
+    let token_ids: Vec<String> = (0..=handlers::MAX_FAVORITES_IMPORT).map(|i| format!("token-{}", i)).collect();
+    let req = test::TestRequest::post()
+        .uri("/api/favorites/import")
+        .set_json(serde_json::json!({ "token_ids": token_ids, "mode": "replace" }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+}
+
+#[actix_web::test]
+async fn test_favorite_meta_validated_before_touching_database() {
+    let state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+
+    let req = test::TestRequest::put()
+        .uri("/api/favorites/bitcoin/meta")
+        .set_json(serde_json::json!({ "tags": ["defi"], "note": "x".repeat(1001) }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_favorite_meta_updates_and_filters_by_tag() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    for (token_id, is_favorite) in [("bitcoin", true), ("uniswap", true), ("dogecoin", false)] {
+        let mut token = cached_token(token_id, 1.0, ChronoDuration::zero());
+        token.is_favorite = is_favorite;
+        state.db.get_tokens_collection().insert_one(token, None).await.unwrap();
+    }
+    let app = test_app!(state);
+
+    let req = test::TestRequest::put()
+        .uri("/api/favorites/uniswap/meta")
+        .set_json(serde_json::json!({ "tags": ["DeFi", "watch"], "note": "governance vote in May" }))
+        .to_request();
+    let token: CryptoToken = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(token.tags, vec!["defi", "watch"]);
+    assert_eq!(token.note.as_deref(), Some("governance vote in May"));
+
+    let req = test::TestRequest::get().uri("/api/favorites?tag=defi").to_request();
+    let favorites: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(favorites.len(), 1);
+    assert_eq!(favorites[0].token_id, "uniswap");
+
+    // Replacing without a note clears it
+    let req = test::TestRequest::put()
+        .uri("/api/favorites/uniswap/meta")
+        .set_json(serde_json::json!({ "tags": ["long-term"] }))
+        .to_request();
+    let token: CryptoToken = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(token.tags, vec!["long-term"]);
+    assert!(token.note.is_none());
+
+    let req = test::TestRequest::put()
+        .uri("/api/favorites/dogecoin/meta")
+        .set_json(serde_json::json!({ "tags": ["meme"] }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 404);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_get_tokens_falls_back_to_binance_when_coingecko_fails() {
+    let coingecko = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&coingecko)
+        .await;
+
+    let binance = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/v3/ticker/24hr"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([{
+            "symbol": "BTCUSDT",
+            "lastPrice": "61000.00",
+            "priceChange": "1000.00",
+            "priceChangePercent": "1.67",
+            "highPrice": "61500.00",
+            "lowPrice": "59000.00",
+            "quoteVolume": "2500000000.00"
+        }])))
+        .mount(&binance)
+        .await;
+
+    let db = common::setup_test_db().await;
+    let mut state = TestState::new(DbClient { db: db.clone() });
+    state.crypto_service = CryptoService::new(coingecko.uri(), None);
+    state.fallback = web::Data::new(FallbackProvider::new(Some(BinanceService::new(binance.uri()))));
+    for token_id in ["bitcoin", "unlisted-coin"] {
+        state
+            .db
+            .get_tokens_collection()
+            .insert_one(cached_token(token_id, 50000.0, ChronoDuration::hours(1)), None)
+            .await
+            .unwrap();
+    }
+    state
+        .db
+        .get_symbol_map_collection()
+        .insert_one(SymbolMapping { token_id: "bitcoin".to_string(), symbol: "BTCUSDT".to_string() }, None)
+        .await
+        .unwrap();
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/tokens").to_request();
+    let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(tokens.len(), 2);
+
+    let bitcoin = tokens.iter().find(|t| t.token_id == "bitcoin").unwrap();
+    assert_eq!(bitcoin.current_price, 61000.0);
+    assert_eq!(bitcoin.price_change_percentage_24h, 1.67);
+    assert_eq!(bitcoin.price_source, Some(PriceSource::Binance));
+
+    // Binance can't price it, so the cached values stay
+    let unlisted = tokens.iter().find(|t| t.token_id == "unlisted-coin").unwrap();
+    assert_eq!(unlisted.current_price, 50000.0);
+    assert_ne!(unlisted.price_source, Some(PriceSource::Binance));
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+async fn test_tokens_batch_reports_unresolved_ids() {
+    let mock_server = MockServer::start().await;
+    // CoinGecko leaves out the id it doesn't know
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .and(query_param("ids", "ethereum,renamed-coin"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([{
+            "id": "ethereum",
+            "symbol": "eth",
+            "name": "Ethereum",
+            "image": "https://example.com/eth.png",
+            "current_price": 2000.0,
+            "market_cap": 240000000000.0,
+            "total_volume": 10000000000.0
+        }])))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(offline_db().await);
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    state.token_cache.set(vec![cached_token("bitcoin", 50000.0, ChronoDuration::zero())]).await;
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get()
+        .uri("/api/tokens/batch?ids=ethereum,Bad%20Id,bitcoin,renamed-coin,bitcoin")
+        .to_request();
+    let batch: TokenBatch = test::call_and_read_body_json(&app, req).await;
+    let ids: Vec<&str> = batch.tokens.iter().map(|t| t.token_id.as_str()).collect();
+    assert_eq!(ids, ["ethereum", "bitcoin"]);
+    assert_eq!(batch.unresolved_ids, ["Bad Id", "renamed-coin"]);
+
+    for uri in ["/api/tokens/batch", "/api/tokens/batch?ids=,"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400, "{}", uri);
+    }
+}
+
+#[actix_web::test]
+async fn test_tokens_batch_uncached_while_rate_limited_returns_503() {
+    let state = TestState::new(offline_db().await);
+    state.rate_limiter.record_rate_limit().await;
+    state.token_cache.set(vec![cached_token("bitcoin", 50000.0, ChronoDuration::zero())]).await;
+    let app = test_app!(state);
+
+    // Everything cached needs no upstream call
+    let req = test::TestRequest::get().uri("/api/tokens/batch?ids=bitcoin").to_request();
+    let batch: TokenBatch = test::call_and_read_body_json(&app, req).await;
+    assert_eq!((batch.tokens.len(), batch.unresolved_ids.len()), (1, 0));
+
+    let req = test::TestRequest::get().uri("/api/tokens/batch?ids=bitcoin,ethereum").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 503);
+    assert!(header_u64(&resp, "Retry-After") > 0);
+}
+
+#[actix_web::test]
+async fn test_convert_rejects_bad_params() {
+    let state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+
+    for query in ["from=bitcoin", "to=ethereum", "from=bitcoin&to=ethereum&amount=0", "from=bitcoin&to=ethereum&amount=-2"] {
+        let req = test::TestRequest::get().uri(&format!("/api/convert?{}", query)).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400, "{}", query);
+    }
+}
+
+#[actix_web::test]
+async fn test_convert_uses_cached_prices() {
+    let state = TestState::new(offline_db().await);
+    state.rate_limiter.record_rate_limit().await;
+    state
+        .token_cache
+        .set(vec![
+            cached_token("bitcoin", 50000.0, ChronoDuration::zero()),
+            cached_token("ethereum", 2500.0, ChronoDuration::zero()),
+        ])
+        .await;
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get()
+        .uri("/api/convert?from=bitcoin&to=ethereum&amount=1.5")
+        .to_request();
+    let result: ConversionResult = test::call_and_read_body_json(&app, req).await;
+
+    assert_eq!(result.from, "bitcoin");
+    assert_eq!(result.to, "ethereum");
+    assert_eq!(result.amount, 1.5);
+    assert_eq!(result.rate, 20.0);
+    assert_eq!(result.result, 30.0);
+    assert_eq!(result.from_price, 50000.0);
+    assert_eq!(result.to_price, 2500.0);
+    assert!(result.to_updated_at.is_some());
+}
+
+#[actix_web::test]
+async fn test_convert_to_usd() {
+    let state = TestState::new(offline_db().await);
+    state.rate_limiter.record_rate_limit().await;
+    let bitcoin = cached_token("bitcoin", 50000.0, ChronoDuration::minutes(5));
+    let updated_at = bitcoin.last_updated;
+    state.token_cache.set(vec![bitcoin]).await;
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/convert?from=bitcoin&to=usd&amount=0.5").to_request();
+    let result: ConversionResult = test::call_and_read_body_json(&app, req).await;
+
+    assert_eq!(result.result, 25000.0);
+    assert_eq!(result.to_price, 1.0);
+    assert_eq!(result.from_updated_at.timestamp_millis(), updated_at.timestamp_millis());
+    assert!(result.to_updated_at.is_none());
+}
+
+#[actix_web::test]
+async fn test_convert_zero_target_price_is_unprocessable() {
+    let state = TestState::new(offline_db().await);
+    state
+        .token_cache
+        .set(vec![
+            cached_token("bitcoin", 50000.0, ChronoDuration::zero()),
+            cached_token("dead-coin", 0.0, ChronoDuration::zero()),
+        ])
+        .await;
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/convert?from=bitcoin&to=dead-coin").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 422);
+}
+
+#[actix_web::test]
+async fn test_convert_uncached_while_rate_limited_returns_503() {
+    let state = TestState::new(offline_db().await);
+    state.rate_limiter.record_rate_limit().await;
+    state.token_cache.set(vec![cached_token("bitcoin", 50000.0, ChronoDuration::zero())]).await;
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/convert?from=bitcoin&to=ethereum").to_request();
+    let resp = test::call_service(&app, req).await;
+
+    assert_eq!(resp.status(), 503);
+    assert!(header_u64(&resp, "Retry-After") > 0);
+}
+
+#[actix_web::test]
+async fn test_convert_fetches_missing_prices_in_one_request() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .and(query_param("ids", "ethereum"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([{
+            "id": "ethereum",
+            "symbol": "eth",
+            "name": "Ethereum",
+            "image": "https://example.com/eth.png",
+            "current_price": 2000.0,
+            "market_cap": 240000000000.0,
+            "total_volume": 10000000000.0,
+            "last_updated": "2024-01-01T00:00:00.000Z"
+        }])))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .and(query_param("ids", "no-such-coin"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(offline_db().await);
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    state.token_cache.set(vec![cached_token("bitcoin", 50000.0, ChronoDuration::zero())]).await;
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/convert?from=bitcoin&to=ethereum&amount=2").to_request();
+    let result: ConversionResult = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(result.rate, 25.0);
+    assert_eq!(result.result, 50.0);
+
+    let req = test::TestRequest::get().uri("/api/convert?from=no-such-coin&to=bitcoin").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 404);
+    let body: ErrorResponse = test::read_body_json(resp).await;
+    assert!(body.error.contains("no-such-coin (from)"), "{}", body.error);
+}
+
+#[actix_web::test]
+async fn test_convert_names_every_missing_side() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .and(query_param("ids", "ghost-a,ghost-b"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(offline_db().await);
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/convert?from=ghost-a&to=ghost-b").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 404);
+    let body: ErrorResponse = test::read_body_json(resp).await;
+    assert_eq!(body.error, "Unknown token: ghost-a (from), ghost-b (to)");
+}
+
+#[actix_web::test]
+async fn test_movers_limit_out_of_range_rejected() {
+    let state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+
+    for uri in ["/api/gainers?limit=0", "/api/losers?limit=101", "/api/gainers?window=1y", "/api/losers?window=7D"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400, "{}", uri);
+    }
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_gainers_and_losers_ranked_in_database() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    for (token_id, change) in [("up-big", 12.5), ("up-small", 1.5), ("flat", 0.0), ("down-small", -2.0), ("down-big", -9.0)] {
+        let mut token = cached_token(token_id, 1.0, ChronoDuration::zero());
+        token.price_change_percentage_24h = change;
+        state.db.get_tokens_collection().insert_one(token, None).await.unwrap();
+    }
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/gainers?limit=2").to_request();
+    let gainers: Vec<TokenChange> = test::call_and_read_body_json(&app, req).await;
+    let ids: Vec<&str> = gainers.iter().map(|c| c.token_id.as_str()).collect();
+    assert_eq!(ids, vec!["up-big", "up-small"]);
+    assert_eq!(gainers[0].change_percentage, 12.5);
+
+    let req = test::TestRequest::get().uri("/api/losers").to_request();
+    let losers: Vec<TokenChange> = test::call_and_read_body_json(&app, req).await;
+    let ids: Vec<&str> = losers.iter().map(|c| c.token_id.as_str()).collect();
+    assert_eq!(ids, vec!["down-big", "down-small", "up-small", "up-big"]);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_movers_rank_by_the_requested_window() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    for (token_id, change_24h, change_7d) in [("week-up", -1.0, Some(20.0)), ("day-up", 9.0, Some(-3.0)), ("new", 4.0, None)] {
+        let mut token = cached_token(token_id, 1.0, ChronoDuration::zero());
+        token.price_change_percentage_24h = change_24h;
+        token.price_change_percentage_7d = change_7d;
+        state.db.get_tokens_collection().insert_one(token, None).await.unwrap();
+    }
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/gainers?window=7d").to_request();
+    let gainers: Vec<TokenChange> = test::call_and_read_body_json(&app, req).await;
+    let ids: Vec<&str> = gainers.iter().map(|c| c.token_id.as_str()).collect();
+    // No 7d figure is not a 0% change
+    assert_eq!(ids, vec!["week-up", "day-up"]);
+    assert_eq!(gainers[0].change_percentage, 20.0);
+
+    // Nothing stored for 30d yet
+    let req = test::TestRequest::get().uri("/api/losers?window=30d").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 422);
+    let body: ErrorResponse = test::read_body_json(resp).await;
+    assert!(body.error.contains("30d"), "{}", body.error);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+async fn test_history_days_boundaries_reach_upstream_and_max_is_bounded() {
+    let mock_server = MockServer::start().await;
+    let body = serde_json::json!({
+        "prices": [[1_600_000_000_000.0, 1.0]],
+        "market_caps": [[1_600_000_000_000.0, 1.0]],
+        "total_volumes": [[1_600_000_000_000.0, 1.0]],
+    });
+    // `max` asks upstream for the configured limit, never for everything
+    for (days, calls) in [("1", 1), ("365", 2)] {
+        Mock::given(method("GET"))
+            .and(path("/coins/bitcoin/market_chart"))
+            .and(query_param("days", days))
+            .respond_with(ResponseTemplate::new(200).set_body_json(body.clone()))
+            .expect(calls)
+            .mount(&mock_server)
+            .await;
+    }
+
+    let mut state = TestState::new(offline_db().await);
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let app = test_app!(state);
+
+    for days in ["1", "365", "max"] {
+        let req = test::TestRequest::get().uri(&format!("/api/history/bitcoin/{}", days)).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success(), "days={}", days);
+    }
+}
+
+#[actix_web::test]
+async fn test_history_days_bounded_by_configured_limit() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/bitcoin/market_chart"))
+        .and(query_param("days", "30"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "prices": [[1640000000000.0, 100.0]],
+            "market_caps": [[1640000000000.0, 1000.0]],
+            "total_volumes": [[1640000000000.0, 10.0]]
+        })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(offline_db().await);
+    state.config.max_history_days = 30;
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    state.rate_limiter = web::Data::new(RateLimiter::new(60.0, 60));
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/history/bitcoin/31").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    let body: ErrorResponse = test::read_body_json(resp).await;
+    assert!(body.error.contains("30"), "{}", body.error);
+    // Rejected before the rate limiter
+    assert!(state.rate_limiter.can_make_api_call().await);
+
+    // `max` asks CoinGecko for the limit rather than everything
+    let req = test::TestRequest::get().uri("/api/history/bitcoin/max").to_request();
+    let data: CoinGeckoHistoricalData = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(data.prices.len(), 1);
+}
+
+#[actix_web::test]
+async fn test_invalid_history_request_does_not_use_rate_limit_slot() {
+    let mut state = TestState::new(offline_db().await);
+    state.rate_limiter = web::Data::new(RateLimiter::new(60.0, 60));
+    let app = test_app!(state);
+
+    for uri in [
+        "/api/history/bitcoin/0",
+        "/api/history/bitcoin/366",
+        "/api/history/bitcoin/4294967295",
+        "/api/history/bitcoin/week",
+        "/api/history/Bitcoin/7",
+        "/api/history/bit_coin/7",
+        "/api/history/bitcoin/7?currency=doge",
+        "/api/history/bitcoin/7?interval=weekly",
+        "/api/history/bitcoin/7?interval=Daily",
+    ] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400, "{}", uri);
+        let body: ErrorResponse = test::read_body_json(resp).await;
+        assert!(!body.error.is_empty());
+    }
+
+    assert!(state.rate_limiter.can_make_api_call().await);
+}
+
+#[actix_web::test]
+async fn test_malformed_params_get_json_errors() {
+    let state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+
+    let requests = [
+        test::TestRequest::get().uri("/api/history/bitcoin/abc"),
+        test::TestRequest::get().uri("/api/history/bitcoin/7?points=lots"),
+        test::TestRequest::get().uri("/api/gainers?limit=-1"),
+        test::TestRequest::post()
+            .uri("/api/favorites/bulk")
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload("{\"token_ids\": "),
+    ];
+    for req in requests {
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status(), 400);
+        assert_eq!(resp.headers().get("Content-Type").unwrap(), "application/json");
+        let body: ErrorResponse = test::read_body_json(resp).await;
+        assert!(!body.error.is_empty());
+    }
+
+    let req = test::TestRequest::get().uri("/api/history/bitcoin/abc").to_request();
+    let body: ErrorResponse = test::call_and_read_body_json(&app, req).await;
+    assert!(body.error.contains("'max'"), "{}", body.error);
+}
+
+#[actix_web::test]
+async fn test_currencies_lists_the_validation_allowlist() {
+    let state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/currencies").to_request();
+    let currencies: Vec<String> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(currencies, SUPPORTED_CURRENCIES);
+}
+
+#[actix_web::test]
+async fn test_versioned_and_legacy_paths_share_handlers() {
+    let state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/v1/currencies").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    assert!(resp.headers().get("deprecation").is_none());
+    assert!(resp.headers().get("sunset").is_none());
+    let current: Vec<String> = test::read_body_json(resp).await;
+
+    let req = test::TestRequest::get().uri("/api/currencies").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let header = |name: &str| resp.headers().get(name).and_then(|v| v.to_str().ok()).unwrap_or_default().to_string();
+    assert!(header("deprecation").starts_with('@'), "{}", header("deprecation"));
+    assert!(header("sunset").ends_with(" GMT"), "{}", header("sunset"));
+    assert_eq!(header("link"), "</api/v1>; rel=\"successor-version\"");
+    let legacy: Vec<String> = test::read_body_json(resp).await;
+    assert_eq!(legacy, current);
+
+    // Errors come from the same handlers too, and the newer envelope version isn't deprecated
+    let req = test::TestRequest::get().uri("/api/v1/history/bitcoin/abc").to_request();
+    let versioned: ErrorResponse = test::call_and_read_body_json(&app, req).await;
+    let req = test::TestRequest::get().uri("/api/history/bitcoin/abc").to_request();
+    let legacy: ErrorResponse = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(versioned.error, legacy.error);
+
+    let req = test::TestRequest::get().uri("/api/v2/tokens?per_page=0").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.headers().get("deprecation").is_none());
+}
+
+#[actix_web::test]
+async fn test_category_display_name_rejected() {
+    let state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/tokens?category=Layer%201%20(L1)").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    let body: ErrorResponse = test::read_body_json(resp).await;
+    assert!(body.error.contains("/api/categories"));
+}
+
+#[actix_web::test]
+async fn test_category_filter_passes_through_to_markets() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .and(query_param("category", "decentralized-finance-defi"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([{
+            "id": "uniswap",
+            "symbol": "uni",
+            "name": "Uniswap",
+            "image": "https://example.com/uni.png",
+            "current_price": 7.5,
+            "market_cap": 4500000000.0,
+            "total_volume": 100000000.0,
+            "last_updated": "2024-01-01T00:00:00.000Z"
+        }])))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(offline_db().await);
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/tokens?category=decentralized-finance-defi").to_request();
+    let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0].category.as_deref(), Some("decentralized-finance-defi"));
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_category_and_stablecoin_filters_query_database() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    state.rate_limiter.record_rate_limit().await;
+    let seed = [
+        // token_id, symbol, category, 24h change
+        ("bitcoin", "btc", Some("layer-1"), 5.0),
+        ("solana", "sol", Some("layer-1"), -8.0),
+        ("tether", "usdt", None, 0.1),
+        ("dai", "dai", Some("stablecoins"), -0.2),
+        ("new-dollar", "ndol", Some("stablecoins"), 9.0),
+    ];
+    for (token_id, symbol, category, change) in seed {
+        let mut token = cached_token(token_id, 1.0, ChronoDuration::zero());
+        token.symbol = symbol.to_string();
+        token.category = category.map(String::from);
+        token.price_change_percentage_24h = change;
+        state.db.get_tokens_collection().insert_one(token, None).await.unwrap();
+    }
+    // The unfiltered memory cache must not leak into filtered lists
+    state.token_cache.set(vec![cached_token("stale", 1.0, ChronoDuration::zero())]).await;
+    let app = test_app!(state);
+
+    let ids = |tokens: Vec<CryptoToken>| {
+        let mut ids: Vec<String> = tokens.into_iter().map(|t| t.token_id).collect();
+        ids.sort();
+        ids
+    };
+
+    let req = test::TestRequest::get().uri("/api/tokens?category=layer-1").to_request();
+    let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(ids(tokens), vec!["bitcoin", "solana"]);
+
+    let req = test::TestRequest::get().uri("/api/tokens?exclude_stablecoins=true").to_request();
+    let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(ids(tokens), vec!["bitcoin", "solana"]);
+
+    // Nothing cached for the category while rate limited is the usual 503
+    let req = test::TestRequest::get().uri("/api/tokens?category=meme-token").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 503);
+
+    let req = test::TestRequest::get().uri("/api/stats?exclude_stablecoins=true").to_request();
+    let stats: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(stats["total_tokens"], 2);
+    assert_eq!(stats["avg_price_change_24h"], -1.5);
+    assert_eq!(stats["biggest_gainer"]["token_id"], "bitcoin");
+    // With two tokens the median is their average
+    assert_eq!(stats["median_price_change_24h"], -1.5);
+    assert_eq!(stats["gainers"].as_u64().unwrap() + stats["losers"].as_u64().unwrap() + stats["flat"].as_u64().unwrap(), 2);
+
+    let req = test::TestRequest::get().uri("/api/gainers?exclude_stablecoins=true").to_request();
+    let gainers: Vec<TokenChange> = test::call_and_read_body_json(&app, req).await;
+    let gainers: Vec<&str> = gainers.iter().map(|c| c.token_id.as_str()).collect();
+    assert_eq!(gainers, vec!["bitcoin", "solana"]);
+
+    // Without the flag the pegged assets are ranked like anything else
+    let req = test::TestRequest::get().uri("/api/gainers").to_request();
+    let gainers: Vec<TokenChange> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(gainers[0].token_id, "new-dollar");
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_direction_filters_in_database_and_keeps_the_sort() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    state.rate_limiter.record_rate_limit().await;
+    for (token_id, change) in [("bitcoin", 1.5), ("ethereum", -2.0), ("solana", 4.0), ("flat-coin", 0.0), ("cardano", -0.5)] {
+        let mut token = cached_token(token_id, 1.0, ChronoDuration::zero());
+        token.price_change_percentage_24h = change;
+        state.db.get_tokens_collection().insert_one(token, None).await.unwrap();
+    }
+    let app = test_app!(state);
+
+    let ids = |tokens: Vec<CryptoToken>| tokens.into_iter().map(|t| t.token_id).collect::<Vec<_>>();
+    let req = test::TestRequest::get().uri("/api/tokens?direction=up&sort_by=price_change_percentage_24h").to_request();
+    assert_eq!(ids(test::call_and_read_body_json(&app, req).await), ["solana", "bitcoin"]);
+    let req = test::TestRequest::get()
+        .uri("/api/tokens?direction=down&sort_by=price_change_percentage_24h&order=asc")
+        .to_request();
+    assert_eq!(ids(test::call_and_read_body_json(&app, req).await), ["ethereum", "cardano"]);
+    let req = test::TestRequest::get().uri("/api/tokens?direction=all").to_request();
+    assert_eq!(ids(test::call_and_read_body_json(&app, req).await).len(), 5);
+
+    let req = test::TestRequest::get().uri("/api/tokens?direction=sideways").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+async fn test_live_list_excludes_stablecoins() {
+    let mock_server = MockServer::start().await;
+    let market = |id: &str, symbol: &str| serde_json::json!({
+        "id": id,
+        "symbol": symbol,
+        "name": id,
+        "image": "https://example.com/coin.png",
+        "current_price": 1.0,
+        "market_cap": 1000000.0,
+        "total_volume": 1000.0,
+        "last_updated": "2024-01-01T00:00:00.000Z"
+    });
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            market("bitcoin", "btc"),
+            market("tether", "usdt"),
+            market("usd-coin", "usdc"),
+        ])))
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(offline_db().await);
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/tokens?exclude_stablecoins=true").to_request();
+    let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    let ids: Vec<&str> = tokens.iter().map(|t| t.token_id.as_str()).collect();
+    assert_eq!(ids, vec!["bitcoin"]);
+}
+
+#[actix_web::test]
+async fn test_categories_listed_from_coingecko() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/categories/list"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "category_id": "decentralized-finance-defi", "name": "Decentralized Finance (DeFi)" },
+            { "category_id": "layer-1", "name": "Layer 1 (L1)" },
+        ])))
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(offline_db().await);
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/categories").to_request();
+    let categories: Vec<Category> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(categories.len(), 2);
+    assert_eq!(categories[1].category_id, "layer-1");
+}
+
+fn exchange_rates_body() -> serde_json::Value {
+    serde_json::json!({ "rates": {
+        "btc": { "name": "Bitcoin", "unit": "BTC", "value": 1.0, "type": "crypto" },
+        "usd": { "name": "US Dollar", "unit": "$", "value": 67000.0, "type": "fiat" },
+        "eur": { "name": "Euro", "unit": "€", "value": 62000.0, "type": "fiat" },
+        "xau": { "name": "Gold - Troy Ounce", "unit": "XAU", "value": 28.8, "type": "commodity" },
+    }})
+}
+
+#[actix_web::test]
+async fn test_exchange_rates_fiat_by_default() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/exchange_rates"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(exchange_rates_body()))
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(offline_db().await);
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/exchange-rates").to_request();
+    let rates: ExchangeRates = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(rates.rates.keys().collect::<Vec<_>>(), ["eur", "usd"]);
+
+    let req = test::TestRequest::get().uri("/api/exchange-rates?type=all").to_request();
+    let rates: ExchangeRates = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(rates.rates.len(), 4);
+
+    let req = test::TestRequest::get().uri("/api/exchange-rates?type=stocks").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    // A single code is looked up among every type
+    let req = test::TestRequest::get().uri("/api/exchange-rates/XAU").to_request();
+    let gold: ExchangeRate = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(gold.value, 28.8);
+
+    let req = test::TestRequest::get().uri("/api/exchange-rates/zzz").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+}
+
+#[actix_web::test]
+async fn test_exchange_rates_unavailable_while_rate_limited_and_unstored() {
+    let state = TestState::new(offline_db().await);
+    state.rate_limiter.record_rate_limit().await;
+    let app = test_app!(state);
+
+    for uri in ["/api/exchange-rates", "/api/exchange-rates/usd"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 503, "{}", uri);
+        assert!(resp.headers().contains_key("retry-after"));
+    }
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_stored_exchange_rates_served_for_an_hour() {
+    let db = common::setup_test_db().await;
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/exchange_rates"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(exchange_rates_body()))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(DbClient { db: db.clone() });
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let stored = |age: ChronoDuration| ExchangeRates {
+        rates: [("usd".to_string(), ExchangeRate { name: "US Dollar".to_string(), unit: "$".to_string(), value: 50000.0, rate_type: "fiat".to_string() })]
+            .into_iter()
+            .collect(),
+        fetched_at: Utc::now() - age,
+    };
+    state.db.save_exchange_rates(&stored(ChronoDuration::minutes(30))).await.unwrap();
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/exchange-rates/usd").to_request();
+    let usd: ExchangeRate = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(usd.value, 50000.0);
+
+    // An hour old it's fetched again and replaced
+    state.db.save_exchange_rates(&stored(ChronoDuration::minutes(61))).await.unwrap();
+    let req = test::TestRequest::get().uri("/api/exchange-rates/usd").to_request();
+    let usd: ExchangeRate = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(usd.value, 67000.0);
+    assert!(state.background_tasks.shutdown(Duration::from_secs(5)).await);
+    assert_eq!(state.db.load_exchange_rates().await.unwrap().unwrap().rates.len(), 4);
+
+    // Rate limited, an expired copy is still served
+    state.db.save_exchange_rates(&stored(ChronoDuration::hours(5))).await.unwrap();
+    state.rate_limiter.record_rate_limit().await;
+    let req = test::TestRequest::get().uri("/api/exchange-rates").to_request();
+    let rates: ExchangeRates = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(rates.rates["usd"].value, 50000.0);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+async fn test_categories_unavailable_while_rate_limited_and_unstored() {
+    let state = TestState::new(offline_db().await);
+    state.rate_limiter.record_rate_limit().await;
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/categories").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 503);
+}
+
+#[actix_web::test]
+async fn test_history_in_other_currency_passes_vs_currency_upstream() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/bitcoin/market_chart"))
+        .and(query_param("vs_currency", "eur"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "prices": [[1_600_000_000_000.0, 45000.0]],
+            "market_caps": [[1_600_000_000_000.0, 1.0]],
+            "total_volumes": [[1_600_000_000_000.0, 1.0]],
+        })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(offline_db().await);
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/history/bitcoin/7?currency=EUR").to_request();
+    let data: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(data["prices"][0][1], 45000.0);
+}
+
+#[actix_web::test]
+async fn test_history_interval_is_passed_upstream() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/bitcoin/market_chart"))
+        .and(query_param("interval", "daily"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "prices": [[1_600_000_000_000.0, 10000.0]],
+            "market_caps": [],
+            "total_volumes": [],
+        })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(offline_db().await);
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/history/bitcoin/30?interval=daily").to_request();
+    let data: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(data["prices"][0][1], 10000.0);
+}
+
+// Hourly points from a UTC midnight, the value being the hour number
+fn hourly_chart(hours: usize) -> serde_json::Value {
+    let series: Vec<Vec<f64>> = (0..hours).map(|i| vec![1_599_955_200_000.0 + i as f64 * 3_600_000.0, i as f64]).collect();
+    serde_json::json!({ "prices": series, "market_caps": series, "total_volumes": series })
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_hourly_and_daily_history_are_cached_separately() {
+    let db = common::setup_test_db().await;
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/bitcoin/market_chart"))
+        .and(query_param("interval", "daily"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "prices": [[1_599_955_200_000.0, -1.0], [1_600_041_600_000.0, -2.0]],
+            "market_caps": [],
+            "total_volumes": [],
+        })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/coins/bitcoin/market_chart"))
+        .and(query_param_is_missing("interval"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(hourly_chart(48)))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(DbClient { db: db.clone() });
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let app = test_app!(state);
+
+    // Daily first, so a key without the interval would hand it to the hourly request
+    for _ in 0..2 {
+        let req = test::TestRequest::get().uri("/api/history/bitcoin/2?interval=daily").to_request();
+        let daily: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(daily["prices"].as_array().unwrap().len(), 2);
+        assert_eq!(daily["prices"][1][1], -2.0);
+
+        let req = test::TestRequest::get().uri("/api/history/bitcoin/2").to_request();
+        let hourly: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(hourly["prices"].as_array().unwrap().len(), 48);
+    }
+
+    // One document for the token, each granularity in its own series
+    let stored = db.collection::<mongodb::bson::Document>("price_history");
+    assert_eq!(stored.count_documents(doc! { "token_id": "bitcoin" }, None).await.unwrap(), 1);
+    let fetches = DbClient { db: db.clone() }.history_fetches(&["bitcoin".to_string()]).await.unwrap();
+    let mut intervals: Vec<HistoryInterval> = fetches["bitcoin"].iter().map(|fetch| fetch.interval).collect();
+    intervals.sort_by_key(|interval| interval.as_str());
+    assert_eq!(intervals, vec![HistoryInterval::Daily, HistoryInterval::Hourly]);
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_shorter_history_is_sliced_from_a_longer_cached_chart() {
+    let db = common::setup_test_db().await;
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/bitcoin/market_chart"))
+        .and(query_param("days", "30"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(hourly_chart(30 * 24)))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/coins/bitcoin/market_chart"))
+        .and(query_param("days", "7"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(0)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(DbClient { db: db.clone() });
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/history/bitcoin/30").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+    // The last week of the month already cached, counted back from its newest point
+    let req = test::TestRequest::get().uri("/api/history/bitcoin/7").to_request();
+    let week: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let prices = week["prices"].as_array().unwrap();
+    assert_eq!(prices.len(), 7 * 24 + 1);
+    assert_eq!(prices[0][1], (23 * 24 - 1) as f64);
+    assert_eq!(prices[prices.len() - 1][1], (30 * 24 - 1) as f64);
+
+    // Asking for longer than anything cached goes upstream again
+    Mock::given(method("GET"))
+        .and(path("/coins/bitcoin/market_chart"))
+        .and(query_param("days", "90"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(hourly_chart(90 * 24)))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    let req = test::TestRequest::get().uri("/api/history/bitcoin/90").to_request();
+    let quarter: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(quarter["prices"].as_array().unwrap().len(), 90 * 24);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_daily_history_is_rolled_up_from_cached_hourly() {
+    let db = common::setup_test_db().await;
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/bitcoin/market_chart"))
+        .and(query_param_is_missing("interval"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(hourly_chart(72)))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/coins/bitcoin/market_chart"))
+        .and(query_param("interval", "daily"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(0)
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(DbClient { db: db.clone() });
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/history/bitcoin/3").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+    // Last hour of each of the three days, from the hourly chart
+    let req = test::TestRequest::get().uri("/api/history/bitcoin/3?interval=daily").to_request();
+    let daily: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let closes: Vec<f64> = daily["prices"].as_array().unwrap().iter().map(|p| p[1].as_f64().unwrap()).collect();
+    assert_eq!(closes, vec![23.0, 47.0, 71.0]);
+    assert_eq!(daily["market_caps"].as_array().unwrap().len(), 3);
+}
+
+#[actix_web::test]
+async fn test_correlation_params_validated() {
+    let state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+
+    let eleven: Vec<String> = (0..11).map(|i| format!("token-{}", i)).collect();
+    for uri in [
+        "/api/correlation".to_string(),
+        "/api/correlation?tokens=bitcoin".to_string(),
+        "/api/correlation?tokens=bitcoin,bitcoin".to_string(),
+        "/api/correlation?tokens=bitcoin,Ethereum".to_string(),
+        "/api/correlation?tokens=bitcoin,ethereum&days=1".to_string(),
+        "/api/correlation?tokens=bitcoin,ethereum&days=366".to_string(),
+        format!("/api/correlation?tokens={}", eleven.join(",")),
+    ] {
+        let req = test::TestRequest::get().uri(&uri).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400, "{}", uri);
+    }
+
+    // A lowered MAX_HISTORY_DAYS bounds the window as it does /api/history
+    let mut state = TestState::new(offline_db().await);
+    state.config.max_history_days = 30;
+    let app = test_app!(state);
+    let req = test::TestRequest::get().uri("/api/correlation?tokens=bitcoin,ethereum&days=31").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+    // The default window shrinks to fit rather than being rejected
+    let req = test::TestRequest::get().uri("/api/correlation?tokens=bitcoin,ethereum").to_request();
+    assert_ne!(test::call_service(&app, req).await.status(), 400);
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_correlation_from_cached_history() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    let history = db.collection::<mongodb::bson::Document>("price_history");
+    let day_ms = 86_400_000_i64;
+    let start = Utc::now().timestamp_millis() - 30 * day_ms;
+    let walk = |scale: f64| -> Vec<(i64, f64)> {
+        (0..30).map(|d| (start + d * day_ms, 100.0 + scale * ((d * 7 % 5) as f64))).collect()
+    };
+
+    // Stored as [t, p] pairs and as { t, p } documents, like the two writers do
+    let pairs = |points: Vec<(i64, f64)>| points.into_iter().map(|(t, p)| mongodb::bson::bson!([t, p])).collect::<Vec<_>>();
+    let docs = |points: Vec<(i64, f64)>| points.into_iter().map(|(t, p)| doc! { "t": t, "p": p }).collect::<Vec<_>>();
+    history.insert_one(doc! { "token_id": "bitcoin", "prices": pairs(walk(1.0)) }, None).await.unwrap();
+    history.insert_one(doc! { "token_id": "wrapped-bitcoin", "prices": docs(walk(1.0)) }, None).await.unwrap();
+    history.insert_one(doc! { "token_id": "inverse", "prices": docs(walk(-0.5)) }, None).await.unwrap();
+    history.insert_one(doc! { "token_id": "brand-new", "prices": pairs(walk(1.0)[27..].to_vec()) }, None).await.unwrap();
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get()
+        .uri("/api/correlation?tokens=bitcoin,wrapped-bitcoin,inverse,brand-new,uncached&days=60")
+        .to_request();
+    let result: CorrelationMatrix = test::call_and_read_body_json(&app, req).await;
+
+    assert_eq!(result.tokens, vec!["bitcoin", "wrapped-bitcoin", "inverse"]);
+    assert_eq!(result.points, 30);
+    assert!((result.matrix[0][1].unwrap() - 1.0).abs() < 1e-9);
+    // Returns of a mirrored price path aren't exactly mirrored, just close
+    assert!(result.matrix[0][2].unwrap() < -0.9);
+    assert_eq!(result.matrix[2][0], result.matrix[0][2]);
+    let skipped: Vec<&str> = result.skipped.iter().map(|s| s.token_id.as_str()).collect();
+    assert_eq!(skipped, vec!["brand-new", "uncached"]);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+async fn test_historical_change_params_validated() {
+    let state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+
+    for uri in ["/api/tokens/Bitcoin!/change", "/api/tokens/bitcoin/change?days=0", "/api/tokens/bitcoin/change?days=366"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400, "{}", uri);
+    }
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_historical_change_from_cached_history() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    let history = db.collection::<mongodb::bson::Document>("price_history");
+    let day_ms = 86_400_000_i64;
+    let end = Utc::now().timestamp_millis();
+    // Ten days of daily prices, 100 rising by 10 a day
+    let prices: Vec<_> = (0..=10).map(|d| doc! { "t": end - (10 - d) * day_ms, "p": 100.0 + d as f64 * 10.0 }).collect();
+    history.insert_one(doc! { "token_id": "bitcoin", "days": 10_i64, "prices": prices }, None).await.unwrap();
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/tokens/bitcoin/change").to_request();
+    let week: HistoricalChange = test::call_and_read_body_json(&app, req).await;
+    assert_eq!((week.days, week.coverage_days), (7, 7.0));
+    assert_eq!((week.start_price, week.end_price), (130.0, 200.0));
+    assert_eq!(week.change, 70.0);
+    assert!((week.change_percentage - 53.846).abs() < 0.001);
+
+    // Asking past the cached range uses all of it instead of failing
+    let req = test::TestRequest::get().uri("/api/tokens/bitcoin/change?days=30").to_request();
+    let month: HistoricalChange = test::call_and_read_body_json(&app, req).await;
+    assert_eq!((month.days, month.coverage_days), (30, 10.0));
+    assert_eq!(month.change_percentage, 100.0);
+
+    let req = test::TestRequest::get().uri("/api/tokens/ethereum/change").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+async fn test_price_at_params_validated() {
+    let state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+
+    for uri in ["/api/tokens/Bitcoin!/price_at?timestamp=0", "/api/tokens/bitcoin/price_at", "/api/tokens/bitcoin/price_at?timestamp=soon"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400, "{}", uri);
+    }
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_price_at_from_cached_history() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    let history = db.collection::<mongodb::bson::Document>("price_history");
+    let day_ms = 86_400_000_i64;
+    let start = 1_640_000_000_000_i64;
+    // Ten days of daily prices, 100 rising by 10 a day
+    let prices: Vec<_> = (0..=10).map(|d| doc! { "t": start + d * day_ms, "p": 100.0 + d as f64 * 10.0 }).collect();
+    history.insert_one(doc! { "token_id": "bitcoin", "days": 10_i64, "prices": prices }, None).await.unwrap();
+    let app = test_app!(state);
+
+    // Closer to day 3 than day 4
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/tokens/bitcoin/price_at?timestamp={}", start + 3 * day_ms + 5_000))
+        .to_request();
+    let found: PriceAt = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(found.price, 130.0);
+    assert_eq!(found.at.timestamp_millis(), start + 3 * day_ms);
+    assert_eq!(found.delta_ms, -5_000);
+
+    // Up to a day past either end still gets the end point
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/tokens/bitcoin/price_at?timestamp={}", start - day_ms / 2))
+        .to_request();
+    let found: PriceAt = test::call_and_read_body_json(&app, req).await;
+    assert_eq!((found.price, found.delta_ms), (100.0, day_ms / 2));
+
+    let req = test::TestRequest::get()
+        .uri(&format!("/api/tokens/bitcoin/price_at?timestamp={}", start + 12 * day_ms))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 422);
+
+    let req = test::TestRequest::get().uri("/api/tokens/ethereum/price_at?timestamp=1640000000000").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_stored_history_reads_only_the_cache() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    let history = db.collection::<mongodb::bson::Document>("price_history");
+    let day_ms = 86_400_000_i64;
+    let start = 1_640_000_000_000_i64;
+    let daily: Vec<_> = (0..3).map(|d| doc! { "t": start + d * day_ms, "p": 100.0 + d as f64 }).collect();
+    history
+        .insert_many(
+            [
+                doc! { "token_id": "bitcoin", "days": 30_i64, "interval": "daily", "prices": daily },
+                doc! { "token_id": "bitcoin", "days": 1_i64, "interval": "hourly",
+                    "prices": [{ "t": start + 2 * day_ms, "p": 150.0 }, { "t": start + 2 * day_ms + 3_600_000, "p": 151.0 }] },
+            ],
+            None,
+        )
+        .await
+        .unwrap();
+    let app = test_app!(state);
+
+    // Hourly wins the shared timestamp
+    let req = test::TestRequest::get().uri("/api/tokens/bitcoin/history").to_request();
+    let merged: CoinGeckoHistoricalData = test::call_and_read_body_json(&app, req).await;
+    let prices: Vec<f64> = merged.prices.iter().map(|point| point[1]).collect();
+    assert_eq!(prices, vec![100.0, 101.0, 150.0, 151.0]);
+
+    let req = test::TestRequest::get().uri("/api/tokens/bitcoin/history?interval=daily").to_request();
+    let daily: CoinGeckoHistoricalData = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(daily.prices.len(), 3);
+
+    for uri in ["/api/tokens/Bitcoin!/history", "/api/tokens/bitcoin/history?interval=weekly"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400, "{}", uri);
+    }
+
+    // Nothing cached is a 404, never an upstream fetch
+    let req = test::TestRequest::get().uri("/api/tokens/ethereum/history").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+
+    common::cleanup_test_db(&db).await;
+}
+
+fn top_tokens() -> Vec<CryptoToken> {
+    [("bitcoin", 600.0), ("ethereum", 300.0), ("solana", 100.0)]
+        .into_iter()
+        .map(|(token_id, market_cap)| {
+            let mut token = cached_token(token_id, 1.0, ChronoDuration::zero());
+            token.market_cap = market_cap;
+            token
+        })
+        .collect()
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_dominance_from_cached_market_caps() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    state.token_cache.set(top_tokens()).await;
+    let history = db.collection::<mongodb::bson::Document>("price_history");
+    let day_ms = 86_400_000_i64;
+    let start = (Utc::now() - ChronoDuration::days(3)).timestamp_millis();
+    let caps = |values: &[f64]| -> Vec<_> {
+        values.iter().enumerate().map(|(d, cap)| doc! { "t": start + d as i64 * day_ms, "p": cap }).collect()
+    };
+    history
+        .insert_many(
+            [
+                doc! { "token_id": "bitcoin", "days": 30_i64, "prices": [], "market_caps": caps(&[500.0, 600.0, 700.0]) },
+                doc! { "token_id": "ethereum", "days": 30_i64, "prices": [], "market_caps": caps(&[400.0, 300.0, 200.0]) },
+            ],
+            None,
+        )
+        .await
+        .unwrap();
+    let app = test_app!(state);
+
+    // Bitcoin and ethereum are 90% of the top tokens' market cap; solana's missing history is tolerated
+    let req = test::TestRequest::get().uri("/api/dominance?days=7").to_request();
+    let dominance: DominanceHistory = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(dominance.source, DominanceSource::History);
+    assert_eq!(dominance.tokens, vec!["bitcoin", "ethereum", "solana"]);
+    let values: Vec<f64> = dominance.series.iter().map(|p| (p.btc_dominance * 10.0).round() / 10.0).collect();
+    assert_eq!(values, vec![55.6, 66.7, 77.8]);
+    assert_eq!(dominance.series[0].timestamp, start);
+
+    // At most one point falls in a 1-day window, so the current list is used instead
+    let req = test::TestRequest::get().uri("/api/dominance?days=1").to_request();
+    let dominance: DominanceHistory = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(dominance.source, DominanceSource::Snapshot);
+    assert_eq!(dominance.series.len(), 1);
+    assert_eq!(dominance.series[0].btc_dominance, 60.0);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_market_history_sums_tokens_per_day() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    let day_ms = 86_400_000_i64;
+    let today = Utc::now().timestamp_millis().div_euclid(day_ms) * day_ms;
+    let caps = |points: &[(i64, f64)]| -> Vec<_> {
+        points.iter().map(|(day, cap)| doc! { "t": today - day * day_ms + 60_000, "p": cap }).collect()
+    };
+    db.collection::<mongodb::bson::Document>("price_history")
+        .insert_many(
+            [
+                doc! { "token_id": "bitcoin", "days": 30_i64, "prices": [], "market_caps": caps(&[(2, 500.0), (1, 600.0), (0, 700.0)]) },
+                // Only the last day cached
+                doc! { "token_id": "ethereum", "days": 1_i64, "prices": [], "market_caps": caps(&[(0, 300.0)]) },
+                doc! { "token_id": "scam-coin", "days": 30_i64, "prices": [], "market_caps": caps(&[(0, 1e12)]) },
+            ],
+            None,
+        )
+        .await
+        .unwrap();
+    let mut scam = cached_token("scam-coin", 1.0, ChronoDuration::zero());
+    scam.hidden = true;
+    state.db.get_tokens_collection().insert_one(scam, None).await.unwrap();
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/market/history?days=7").to_request();
+    let series: Vec<MarketCapPoint> = test::call_and_read_body_json(&app, req).await;
+    let days: Vec<(i64, f64, usize)> = series.iter().map(|p| (p.timestamp, p.total_market_cap, p.tokens_included)).collect();
+    assert_eq!(
+        days,
+        vec![(today - 2 * day_ms, 500.0, 1), (today - day_ms, 600.0, 1), (today, 1000.0, 2)]
+    );
+
+    let req = test::TestRequest::get().uri("/api/market/history?days=0").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_daily_report_totals_come_from_stored_data() {
+    let db = common::setup_test_db().await;
+    // Nothing may go upstream
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET")).respond_with(ResponseTemplate::new(500)).expect(0).mount(&mock_server).await;
+
+    let mut state = TestState::new(DbClient { db: db.clone() });
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let market = [("bitcoin", 1000.0, 25.0, false), ("ethereum", 500.0, -50.0, true), ("tether", 100.0, 0.0, false)];
+    for (token_id, market_cap, change, favorite) in market {
+        let mut token = cached_token(token_id, 1.0, ChronoDuration::minutes(5));
+        token.market_cap = market_cap;
+        token.price_change_percentage_24h = change;
+        token.is_favorite = favorite;
+        state.db.get_tokens_collection().insert_one(token, None).await.unwrap();
+    }
+    let mut hidden = cached_token("scam-coin", 1.0, ChronoDuration::minutes(5));
+    hidden.market_cap = 1e12;
+    hidden.hidden = true;
+    state.db.get_tokens_collection().insert_one(hidden, None).await.unwrap();
+    for hours_ago in [2, 30] {
+        let event = Event {
+            token_id: "bitcoin".to_string(),
+            kind: EventKind::AthBreak,
+            old_value: 1.0,
+            new_value: 1.25,
+            at: Utc::now() - ChronoDuration::hours(hours_ago),
+        };
+        state.db.record_event(&event, ChronoDuration::zero()).await.unwrap();
+    }
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/report/daily").to_request();
+    let report: DailyReport = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(report.total_market_cap, 1600.0);
+    // 800 + 1000 + 100 a day earlier
+    assert!((report.previous_total_market_cap - 1900.0).abs() < 1e-9);
+    assert!((report.market_cap_change_percentage.unwrap() - (1600.0 / 1900.0 - 1.0) * 100.0).abs() < 1e-9);
+    assert_eq!(report.top_gainers.iter().map(|t| t.token_id.as_str()).collect::<Vec<_>>(), ["bitcoin"]);
+    assert_eq!(report.top_losers.iter().map(|t| t.token_id.as_str()).collect::<Vec<_>>(), ["ethereum"]);
+    assert_eq!(report.favorite_moves.iter().map(|t| t.token_id.as_str()).collect::<Vec<_>>(), ["ethereum"]);
+    assert_eq!(report.events.len(), 1);
+
+    let req = test::TestRequest::get().uri("/api/report/daily?format=markdown").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.headers().get("content-type").unwrap(), "text/markdown; charset=utf-8");
+    let body = test::read_body(resp).await;
+    assert!(std::str::from_utf8(&body).unwrap().starts_with("# Daily report for "));
+
+    common::cleanup_test_db(&db).await;
+}
+
+// A token whose logo lives on the mock server at `logo_path`
+async fn token_with_logo(state: &TestState, mock_server: &MockServer, logo_path: &str) {
+    let mut token = cached_token("bitcoin", 50000.0, ChronoDuration::zero());
+    token.image = Some(format!("{}{}", mock_server.uri(), logo_path));
+    state.token_cache.set(vec![token]).await;
+}
+
+#[actix_web::test]
+async fn test_token_image_is_proxied_and_kept_in_memory() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/logo.png"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(b"\x89PNG".to_vec(), "image/png"))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+    let state = TestState::new(offline_db().await);
+    token_with_logo(&state, &mock_server, "/logo.png").await;
+    let app = test_app!(state);
+
+    for _ in 0..2 {
+        let req = test::TestRequest::get().uri("/api/tokens/bitcoin/image").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers().get("content-type").unwrap(), "image/png");
+        assert_eq!(resp.headers().get("cache-control").unwrap(), "public, max-age=86400");
+        assert_eq!(test::read_body(resp).await, &b"\x89PNG"[..]);
+    }
+
+    let req = test::TestRequest::get().uri("/api/tokens/ethereum/image").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+}
+
+#[actix_web::test]
+async fn test_token_image_redirects_when_the_fetch_fails_or_is_slow() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/missing.png"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/slow.png"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(b"png".to_vec(), "image/png").set_delay(Duration::from_secs(5)))
+        .mount(&mock_server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/page.html"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(b"<html>".to_vec(), "text/html"))
+        .mount(&mock_server)
+        .await;
+    let mut state = TestState::new(offline_db().await);
+    state.images = web::Data::new(ImageProxy::new(Duration::from_millis(200), Duration::from_secs(60)));
+
+    for logo_path in ["/missing.png", "/slow.png", "/page.html"] {
+        token_with_logo(&state, &mock_server, logo_path).await;
+        let app = test_app!(state);
+        let started = std::time::Instant::now();
+        let req = test::TestRequest::get().uri("/api/tokens/bitcoin/image").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 302, "{}", logo_path);
+        assert_eq!(resp.headers().get("location").unwrap().to_str().unwrap(), format!("{}{}", mock_server.uri(), logo_path));
+        assert!(started.elapsed() < Duration::from_secs(2), "{}", logo_path);
+    }
+}
+
+#[actix_web::test]
+async fn test_overview_panels_share_one_snapshot() {
+    let state = TestState::new(offline_db().await);
+    let changes = [("bitcoin", 60.0, 1.0), ("a", 5.0, 8.0), ("b", 5.0, 6.0), ("c", 5.0, 4.0), ("d", 5.0, 2.0), ("e", 5.0, 0.5),
+        ("f", 5.0, -1.0), ("g", 5.0, -3.0), ("flat", 5.0, 0.0)];
+    let tokens = changes
+        .iter()
+        .map(|(token_id, market_cap, change)| {
+            let mut token = cached_token(token_id, 1.0, ChronoDuration::zero());
+            token.market_cap = *market_cap;
+            token.volume_24h = 1.0;
+            token.price_change_percentage_24h = *change;
+            token
+        })
+        .collect();
+    state.token_cache.set(tokens).await;
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/overview").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 200);
+    let etag = resp.headers().get("ETag").unwrap().clone();
+    let overview: serde_json::Value = test::read_body_json(resp).await;
+
+    let ids = |panel: &str| -> Vec<String> {
+        overview[panel].as_array().unwrap().iter().map(|t| t["token_id"].as_str().unwrap().to_string()).collect()
+    };
+    assert_eq!(ids("top_gainers"), ["a", "b", "c", "d", "bitcoin"]);
+    assert_eq!(ids("top_losers"), ["g", "f"]);
+    assert_eq!(overview["stats"]["total_tokens"], 9);
+    assert_eq!(overview["stats"]["total_market_cap"], 100.0);
+    assert_eq!(overview["market"]["total_market_cap"], 100.0);
+    assert_eq!(overview["market"]["total_volume_24h"], 9.0);
+    assert_eq!(overview["market"]["bitcoin_dominance"], 60.0);
+    assert_eq!(overview["market"]["top_gainer"]["token_id"], "a");
+    assert_eq!(overview["market"]["top_loser"]["token_id"], "g");
+
+    let req = test::TestRequest::get().uri("/api/overview").insert_header(("If-None-Match", etag)).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 304);
+}
+
+#[actix_web::test]
+async fn test_screener_rejects_conflicting_bounds() {
+    let state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+
+    for uri in [
+        "/api/screener?min_volume_mcap_ratio=0.5&max_volume_mcap_ratio=0.1",
+        "/api/screener?min_market_cap=100&max_market_cap=10",
+        "/api/screener?min_change_24h=5&max_change_24h=-5",
+        "/api/screener?sort=name",
+        "/api/screener?limit=0",
+    ] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400, "{}", uri);
+    }
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_screener_filters_and_sorts_by_computed_ratio() {
+    let db = common::setup_test_db().await;
+    let state = TestState::new(DbClient { db: db.clone() });
+    // (id, market cap, 24h volume, 24h change)
+    let seeded = [
+        ("busy", 2.0e8, 1.0e8, 4.0),
+        ("quiet", 5.0e8, 1.0e7, 1.0),
+        ("small", 5.0e7, 4.0e7, 9.0),
+        ("dumping", 3.0e8, 1.5e8, -20.0),
+        ("no-cap", 0.0, 1.0e6, 2.0),
+    ];
+    let tokens: Vec<CryptoToken> = seeded
+        .iter()
+        .map(|(token_id, market_cap, volume, change)| {
+            let mut token = cached_token(token_id, 1.0, ChronoDuration::zero());
+            token.market_cap = *market_cap;
+            token.volume_24h = *volume;
+            token.price_change_percentage_24h = *change;
+            token
+        })
+        .collect();
+    state.db.get_tokens_collection().insert_many(tokens, None).await.unwrap();
+    let app = test_app!(state);
+
+    // Ratio first by default; the zero market cap gets a null ratio instead of an error
+    let req = test::TestRequest::get().uri("/api/screener").to_request();
+    let all: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+    let ranked: Vec<(&str, Option<f64>)> = all.iter().map(|t| (t["token_id"].as_str().unwrap(), t["volume_mcap_ratio"].as_f64())).collect();
+    assert_eq!(
+        ranked,
+        vec![("small", Some(0.8)), ("busy", Some(0.5)), ("dumping", Some(0.5)), ("quiet", Some(0.02)), ("no-cap", None)]
+    );
+
+    let req = test::TestRequest::get()
+        .uri("/api/screener?min_volume_mcap_ratio=0.2&min_market_cap=100000000&min_change_24h=-5&sort=volume_24h")
+        .to_request();
+    let screened: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+    let ids: Vec<&str> = screened.iter().map(|t| t["token_id"].as_str().unwrap()).collect();
+    assert_eq!(ids, vec!["busy"]);
+    assert_eq!(screened[0]["volume_mcap_ratio"], 0.5);
+
+    let req = test::TestRequest::get().uri("/api/screener?max_volume_mcap_ratio=1").to_request();
+    let bounded: Vec<serde_json::Value> = test::call_and_read_body_json(&app, req).await;
+    assert!(bounded.iter().all(|t| t["token_id"] != "no-cap"));
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+async fn test_daily_report_rejects_unknown_format() {
+    let state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/report/daily?format=html").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    let body: ErrorResponse = test::read_body_json(resp).await;
+    assert!(body.error.contains("markdown"), "{}", body.error);
+}
+
+#[actix_web::test]
+async fn test_dominance_rejects_bad_days_and_missing_bitcoin() {
+    let state = TestState::new(offline_db().await);
+    state.token_cache.set(top_tokens().split_off(1)).await;
+    let app = test_app!(state);
+
+    for (uri, status) in [("/api/dominance?days=0", 400), ("/api/dominance?days=366", 400), ("/api/dominance", 404)] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), status, "{}", uri);
+    }
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_delete_token_cascades_and_dry_run_only_counts() {
+    let db = common::setup_test_db().await;
+    let mut state = TestState::new(DbClient { db: db.clone() });
+    state.config.admin_token = Some("s3cret".to_string());
+    state.rate_limiter.record_rate_limit().await;
+    let collection = state.db.get_tokens_collection();
+    for token_id in ["bitcoin", "dead-coin"] {
+        collection.insert_one(cached_token(token_id, 1.0, ChronoDuration::minutes(10)), None).await.unwrap();
+    }
+    db.collection::<mongodb::bson::Document>("price_history")
+        .insert_one(doc! { "token_id": "dead-coin", "days": 30_i64, "prices": [] }, None)
+        .await
+        .unwrap();
+    db.collection::<mongodb::bson::Document>("user_favorites")
+        .insert_one(doc! { "user_id": mongodb::bson::oid::ObjectId::new(), "token_id": "dead-coin" }, None)
+        .await
+        .unwrap();
+    let app = test_app!(state);
+
+    // Warm the memory cache so the delete has to drop it
+    let req = test::TestRequest::get().uri("/api/tokens").to_request();
+    let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(tokens.len(), 2);
+
+    let delete = |uri: &str| {
+        test::TestRequest::delete().uri(uri).insert_header(("X-Admin-Token", "s3cret")).to_request()
+    };
+    let dry: TokenDeletion = test::call_and_read_body_json(&app, delete("/api/admin/tokens/dead-coin?dry_run=true")).await;
+    assert!(dry.dry_run);
+    assert_eq!((dry.deleted.tokens, dry.deleted.price_history, dry.deleted.user_favorites), (1, 1, 1));
+    assert_eq!(collection.count_documents(doc! { "token_id": "dead-coin" }, None).await.unwrap(), 1);
+
+    let done: TokenDeletion = test::call_and_read_body_json(&app, delete("/api/admin/tokens/dead-coin")).await;
+    assert!(!done.dry_run);
+    assert_eq!(done.deleted, dry.deleted);
+
+    let req = test::TestRequest::get().uri("/api/tokens").to_request();
+    let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(tokens.iter().map(|t| t.token_id.as_str()).collect::<Vec<_>>(), ["bitcoin"]);
+    let history = db.collection::<mongodb::bson::Document>("price_history").count_documents(None, None).await.unwrap();
+    assert_eq!(history, 0);
+
+    for uri in ["/api/admin/tokens/dead-coin", "/api/admin/tokens/dead-coin?dry_run=true"] {
+        assert_eq!(test::call_service(&app, delete(uri)).await.status(), 404, "{}", uri);
+    }
+    let req = test::TestRequest::delete().uri("/api/admin/tokens/bitcoin").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 401);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+#[serial]
+async fn test_purge_removes_only_stale_tokens_nobody_starred() {
+    let db = common::setup_test_db().await;
+    let mut state = TestState::new(DbClient { db: db.clone() });
+    state.config.admin_token = Some("s3cret".to_string());
+    let collection = state.db.get_tokens_collection();
+    for (token_id, age_hours, shared_favorite) in [
+        ("bitcoin", 1, false),
+        ("dead-coin", 48, false),
+        ("starred-coin", 48, true),
+        ("user-starred-coin", 48, false),
+    ] {
+        let mut token = cached_token(token_id, 1.0, ChronoDuration::hours(age_hours));
+        token.is_favorite = shared_favorite;
+        collection.insert_one(token, None).await.unwrap();
+    }
+    db.collection::<mongodb::bson::Document>("user_favorites")
+        .insert_one(doc! { "user_id": mongodb::bson::oid::ObjectId::new(), "token_id": "user-starred-coin" }, None)
+        .await
+        .unwrap();
+    // Charts are pruned once their freshness window has passed, whoever owns them
+    let now = Utc::now();
+    let history = db.collection::<mongodb::bson::Document>("price_history");
+    history
+        .insert_many(
+            [
+                doc! { "token_id": "bitcoin", "days": 1_i64, "timestamp": now - ChronoDuration::hours(2), "expires_at": now - ChronoDuration::hours(1) },
+                doc! { "token_id": "bitcoin", "days": 30_i64, "timestamp": now, "expires_at": now + ChronoDuration::hours(1) },
+            ],
+            None,
+        )
+        .await
+        .unwrap();
+    let app = test_app!(state);
+
+    let purge = |uri: &str| test::TestRequest::post().uri(uri).insert_header(("X-Admin-Token", "s3cret")).to_request();
+    let purged: TokenPurge = test::call_and_read_body_json(&app, purge("/api/admin/purge?older_than_hours=24")).await;
+    assert_eq!(purged.deleted, 1);
+    assert_eq!(purged.history_deleted, 1);
+    assert_eq!(history.count_documents(doc! { "days": 30_i64 }, None).await.unwrap(), 1);
+
+    let mut left: Vec<String> = collection
+        .distinct("token_id", None, None)
+        .await
+        .unwrap()
+        .into_iter()
+        .filter_map(|id| id.as_str().map(String::from))
+        .collect();
+    left.sort();
+    assert_eq!(left, ["bitcoin", "starred-coin", "user-starred-coin"]);
+
+    let again: TokenPurge = test::call_and_read_body_json(&app, purge("/api/admin/purge")).await;
+    assert_eq!(again.deleted, 0);
+    assert_eq!(again.history_deleted, 0);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_web::test]
+async fn test_purge_requires_admin_token_and_a_positive_age() {
+    let mut state = TestState::new(offline_db().await);
+    state.config.admin_token = Some("s3cret".to_string());
+    let app = test_app!(state);
+
+    let req = test::TestRequest::post().uri("/api/admin/purge").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 401);
+
+    let req = test::TestRequest::post()
+        .uri("/api/admin/purge?older_than_hours=0")
+        .insert_header(("X-Admin-Token", "s3cret"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+    // The admin token works as a bearer token too
+    for (authorization, status) in [("Bearer s3cret", 400), ("Bearer wrong", 401), ("s3cret", 401)] {
+        let req = test::TestRequest::post()
+            .uri("/api/admin/purge?older_than_hours=0")
+            .insert_header(("Authorization", authorization))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), status, "{}", authorization);
+    }
+}
+
+#[actix_web::test]
+async fn test_repair_requires_admin_token_and_known_passes() {
+    let mut state = TestState::new(offline_db().await);
+    state.config.admin_token = Some("s3cret".to_string());
+    let app = test_app!(state);
+
+    let req = test::TestRequest::post().uri("/api/admin/repair?passes=dedupe").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 401);
+
+    let req = test::TestRequest::post()
+        .uri("/api/admin/repair?passes=dedupe,reindex")
+        .insert_header(("X-Admin-Token", "s3cret"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    let error: ErrorResponse = test::read_body_json(resp).await;
+    assert!(error.error.contains("reindex"), "{}", error.error);
+}
+
+#[actix_web::test]
+async fn test_history_points_out_of_range_rejected() {
+    let state = TestState::new(offline_db().await);
+    let app = test_app!(state);
+
+    for points in ["1", "2001"] {
+        let req = test::TestRequest::get()
+            .uri(&format!("/api/history/bitcoin/30?points={}", points))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400);
+    }
+}
+
+#[actix_web::test]
+async fn test_history_downsamples_fresh_data() {
+    let mock_server = MockServer::start().await;
+    let series: Vec<Vec<f64>> = (0..1000)
+        .map(|i| vec![1_600_000_000_000.0 + i as f64 * 3_600_000.0, i as f64])
+        .collect();
+    let body = serde_json::json!({
+        "prices": series,
+        "market_caps": series,
+        "total_volumes": series,
+    });
+    Mock::given(method("GET"))
+        .and(path("/coins/bitcoin/market_chart"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(body))
+        .mount(&mock_server)
+        .await;
+
+    let mut state = TestState::new(offline_db().await);
+    state.crypto_service = CryptoService::new(mock_server.uri(), None);
+    let app = test_app!(state);
+
+    let req = test::TestRequest::get().uri("/api/history/bitcoin/365?points=100").to_request();
+    let data: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+    let prices = data["prices"].as_array().unwrap();
+    assert_eq!(prices.len(), 100);
+    assert_eq!(prices[0][1], 0.0);
+    assert_eq!(prices[99][1], 999.0);
+    assert_eq!(data["total_volumes"].as_array().unwrap().len(), 100);
+}
@@ -1,9 +1,58 @@
-// Integration tests for database operations
+// Integration tests for database operations and the handlers on top of them
 mod common;
 
+use actix_web::{test, web, App};
+use crypto_tracker_backend::{
+    backfill::BackfillStatus,
+    config::Config,
+    crypto_service::CryptoService,
+    db::DbClient,
+    events::EventRecorder,
+    fallback::FallbackProvider,
+    force_fresh::ForceFreshLimiter,
+    handlers::HistoryFlights,
+    models::{CryptoToken, ErrorResponse, FavoriteRequest, TokenAnnotation, MAX_ANNOTATION_NOTE_LENGTH, MAX_ANNOTATION_TAG_LENGTH, MAX_ANNOTATION_TAGS},
+    rate_limiter::RateLimiter,
+    routes,
+    shutdown::BackgroundTasks,
+    token_cache::TokenCache,
+    upstream::UpstreamGate,
+    webhook::WebhookNotifier,
+};
 use mongodb::bson::doc;
 use serial_test::serial;
 use futures::stream::StreamExt;
+use std::time::Duration;
+use wiremock::matchers::{method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+// The full API over `db`, with CoinGecko at `coingecko_url`
+macro_rules! api_app {
+    ($db_client:expr, $coingecko_url:expr) => {{
+        let config = Config::default_for_tests();
+        test::init_service(
+            App::new()
+                .app_data(web::Data::new(config.clone()))
+                .app_data(web::Data::new($db_client.clone()))
+                .app_data(web::Data::new(CryptoService::new($coingecko_url, None)))
+                .app_data(web::Data::new(RateLimiter::new(0.0, 60)))
+                .app_data(web::Data::new(BackgroundTasks::new()))
+                .app_data(web::Data::new(TokenCache::new(Duration::from_secs(60))))
+                .app_data(web::Data::new(WebhookNotifier::disabled()))
+                .app_data(web::Data::new(EventRecorder::new($db_client.clone(), &config)))
+                .app_data(web::Data::new(UpstreamGate::from_config(&config)))
+                .app_data(web::Data::new(HistoryFlights::new()))
+                .app_data(web::Data::new(ForceFreshLimiter::from_config(&config)))
+                .app_data(web::Data::new(FallbackProvider::disabled()))
+                .app_data(web::Data::new(BackfillStatus::default()))
+                .configure(routes::configure),
+        )
+        .await
+    }};
+}
+
+// Nothing listens on the discard port, so any upstream call fails at once
+const UNREACHABLE: &str = "http://127.0.0.1:9";
 
 #[tokio::test]
 #[serial]
@@ -142,24 +191,23 @@ async fn test_bulk_operations() {
 
 #[actix_rt::test]
 #[serial]
-async fn test_get_all_tokens_empty_db() {
+async fn test_get_tokens_empty_db_and_upstream_down() {
     common::init_test_logger();
     
     let db = common::setup_test_db().await;
     let db_client = DbClient { db: db.clone() };
     
-    let app = test::init_service(
-        App::new()
-            .app_data(web::Data::new(db_client.clone()))
-            .route("/api/tokens", web::get().to(get_all_tokens))
-    ).await;
+    let app = api_app!(db_client, UNREACHABLE.to_string());
     
     let req = test::TestRequest::get()
         .uri("/api/tokens")
         .to_request();
     
+    // Nothing cached and nothing fetched
     let resp = test::call_service(&app, req).await;
-    assert!(resp.status().is_success());
+    assert_eq!(resp.status(), 503);
+    let body: ErrorResponse = test::read_body_json(resp).await;
+    assert!(body.retry_after.is_some());
     
     common::cleanup_test_db(&db).await;
 }
@@ -177,11 +225,7 @@ async fn test_toggle_favorite_token() {
     let collection = db_client.get_tokens_collection();
     collection.insert_one(&token, None).await.unwrap();
     
-    let app = test::init_service(
-        App::new()
-            .app_data(web::Data::new(db_client.clone()))
-            .route("/api/tokens/favorite", web::post().to(toggle_favorite))
-    ).await;
+    let app = api_app!(db_client, UNREACHABLE.to_string());
     
     let favorite_req = FavoriteRequest {
         token_id: "bitcoin".to_string(),
@@ -215,11 +259,7 @@ async fn test_get_favorites_empty() {
     let db = common::setup_test_db().await;
     let db_client = DbClient { db: db.clone() };
     
-    let app = test::init_service(
-        App::new()
-            .app_data(web::Data::new(db_client.clone()))
-            .route("/api/favorites", web::get().to(get_favorites))
-    ).await;
+    let app = api_app!(db_client, UNREACHABLE.to_string());
     
     let req = test::TestRequest::get()
         .uri("/api/favorites")
@@ -252,11 +292,7 @@ async fn test_get_favorites_with_data() {
     collection.insert_one(&fav_token, None).await.unwrap();
     collection.insert_one(&non_fav_token, None).await.unwrap();
     
-    let app = test::init_service(
-        App::new()
-            .app_data(web::Data::new(db_client.clone()))
-            .route("/api/favorites", web::get().to(get_favorites))
-    ).await;
+    let app = api_app!(db_client, UNREACHABLE.to_string());
     
     let req = test::TestRequest::get()
         .uri("/api/favorites")
@@ -275,17 +311,21 @@ async fn test_get_favorites_with_data() {
 
 #[actix_rt::test]
 #[serial]
-async fn test_get_token_by_id_not_found() {
+async fn test_get_token_not_found() {
     common::init_test_logger();
     
     let db = common::setup_test_db().await;
     let db_client = DbClient { db: db.clone() };
     
-    let app = test::init_service(
-        App::new()
-            .app_data(web::Data::new(db_client.clone()))
-            .route("/api/tokens/{id}", web::get().to(get_token_by_id))
-    ).await;
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .and(query_param("ids", "nonexistent"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+        .mount(&mock_server)
+        .await;
+    
+    let app = api_app!(db_client, mock_server.uri());
     
     let req = test::TestRequest::get()
         .uri("/api/tokens/nonexistent")
@@ -310,21 +350,22 @@ async fn test_search_tokens_empty_query() {
     let collection = db_client.get_tokens_collection();
     collection.insert_many(&tokens, None).await.unwrap();
     
-    let app = test::init_service(
-        App::new()
-            .app_data(web::Data::new(db_client.clone()))
-            .route("/api/search", web::get().to(search_tokens))
-    ).await;
+    let app = api_app!(db_client, UNREACHABLE.to_string());
     
     let req = test::TestRequest::get()
         .uri("/api/search?q=")
         .to_request();
     
+    // An empty query is rejected rather than matching everything
     let resp = test::call_service(&app, req).await;
-    assert!(resp.status().is_success());
-    
-    let body: Vec<CryptoToken> = test::read_body_json(resp).await;
-    assert_eq!(body.len(), 5); // Returns all tokens when query is empty
+    assert_eq!(resp.status(), 400);
+
+    // unless every token is asked for explicitly
+    let req = test::TestRequest::get()
+        .uri("/api/search?q=&all=true")
+        .to_request();
+    let body: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body.len(), 5);
     
     common::cleanup_test_db(&db).await;
 }
@@ -347,11 +388,7 @@ async fn test_search_tokens_with_query() {
     let collection = db_client.get_tokens_collection();
     collection.insert_many(vec![&bitcoin, &ethereum], None).await.unwrap();
     
-    let app = test::init_service(
-        App::new()
-            .app_data(web::Data::new(db_client.clone()))
-            .route("/api/search", web::get().to(search_tokens))
-    ).await;
+    let app = api_app!(db_client, UNREACHABLE.to_string());
     
     let req = test::TestRequest::get()
         .uri("/api/search?q=bit")
@@ -367,6 +404,51 @@ async fn test_search_tokens_with_query() {
     common::cleanup_test_db(&db).await;
 }
 
+#[actix_rt::test]
+#[serial]
+async fn test_search_tokens_matches_in_mongodb_ranked_and_limited() {
+    common::init_test_logger();
+    
+    let db = common::setup_test_db().await;
+    let db_client = DbClient { db: db.clone() };
+    
+    let mut bitcoin = common::mock_data::create_test_token("bitcoin");
+    bitcoin.symbol = "btc".to_string();
+    bitcoin.market_cap = 1.0;
+    let mut wrapped = common::mock_data::create_test_token("wrapped-bitcoin");
+    wrapped.symbol = "wbtc".to_string();
+    wrapped.name = "Wrapped Bitcoin".to_string();
+    wrapped.market_cap = 100.0;
+    let mut dotted = common::mock_data::create_test_token("dot-btc");
+    dotted.symbol = "b.c".to_string();
+    dotted.name = "Dotted".to_string();
+    dotted.market_cap = 10.0;
+    
+    let collection = db_client.get_tokens_collection();
+    collection.insert_many(vec![&wrapped, &bitcoin, &dotted], None).await.unwrap();
+    
+    // Nothing has filled the memory cache, so every search goes to MongoDB
+    let app = api_app!(db_client, UNREACHABLE.to_string());
+    
+    let req = test::TestRequest::get().uri("/api/search?q=BTC").to_request();
+    let body: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    let ids: Vec<&str> = body.iter().map(|t| t.token_id.as_str()).collect();
+    assert_eq!(ids, vec!["bitcoin", "wrapped-bitcoin", "dot-btc"]);
+    
+    let req = test::TestRequest::get().uri("/api/search?q=btc&limit=1").to_request();
+    let body: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body.len(), 1);
+    assert_eq!(body[0].token_id, "bitcoin");
+    
+    // Regex characters in the query are matched literally
+    let req = test::TestRequest::get().uri("/api/search?q=b.c").to_request();
+    let body: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    let ids: Vec<&str> = body.iter().map(|t| t.token_id.as_str()).collect();
+    assert_eq!(ids, vec!["dot-btc"]);
+    
+    common::cleanup_test_db(&db).await;
+}
+
 #[actix_rt::test]
 #[serial]
 async fn test_invalid_favorite_request() {
@@ -375,11 +457,7 @@ async fn test_invalid_favorite_request() {
     let db = common::setup_test_db().await;
     let db_client = DbClient { db: db.clone() };
     
-    let app = test::init_service(
-        App::new()
-            .app_data(web::Data::new(db_client.clone()))
-            .route("/api/tokens/favorite", web::post().to(toggle_favorite))
-    ).await;
+    let app = api_app!(db_client, UNREACHABLE.to_string());
     
     let req = test::TestRequest::post()
         .uri("/api/tokens/favorite")
@@ -391,3 +469,100 @@ async fn test_invalid_favorite_request() {
     
     common::cleanup_test_db(&db).await;
 }
+
+#[actix_rt::test]
+#[serial]
+async fn test_tag_filter_joins_annotations_and_survives_refresh() {
+    common::init_test_logger();
+    
+    let db = common::setup_test_db().await;
+    let db_client = DbClient { db: db.clone() };
+    let collection = db_client.get_tokens_collection();
+    collection.insert_many(common::mock_data::create_test_tokens(4), None).await.unwrap();
+    
+    let app = api_app!(db_client, UNREACHABLE.to_string());
+    
+    for (token_id, tags) in [("token1", vec!["Long-Term", "core"]), ("token3", vec!["long-term"]), ("token2", vec!["avoid"])] {
+        let req = test::TestRequest::put()
+            .uri(&format!("/api/tokens/{}/annotation", token_id))
+            .set_json(serde_json::json!({ "tags": tags, "note": "why" }))
+            .to_request();
+        let annotation: TokenAnnotation = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(annotation.token_id, token_id);
+    }
+    
+    // A refresh rewrites the token documents; annotations live elsewhere
+    collection.update_many(doc! {}, doc! { "$set": { "current_price": 2.0 } }, None).await.unwrap();
+    
+    let ids = |tokens: Vec<CryptoToken>| {
+        let mut ids: Vec<String> = tokens.into_iter().map(|t| t.token_id).collect();
+        ids.sort();
+        ids
+    };
+    let req = test::TestRequest::get().uri("/api/tokens?tag=LONG-TERM").to_request();
+    let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(ids(tokens), vec!["token1", "token3"]);
+    
+    let req = test::TestRequest::get().uri("/api/tokens?tag=nobody-uses-this").to_request();
+    let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert!(tokens.is_empty());
+    
+    let req = test::TestRequest::get().uri("/api/tokens/token1/annotation").to_request();
+    let annotation: TokenAnnotation = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(annotation.tags, vec!["long-term", "core"]);
+    assert_eq!(annotation.note.as_deref(), Some("why"));
+    
+    let req = test::TestRequest::delete().uri("/api/tokens/token3/annotation").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 204);
+    let req = test::TestRequest::delete().uri("/api/tokens/token3/annotation").to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+    let req = test::TestRequest::get().uri("/api/tokens?tag=long-term").to_request();
+    let tokens: Vec<CryptoToken> = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(ids(tokens), vec!["token1"]);
+    
+    common::cleanup_test_db(&db).await;
+}
+
+#[actix_rt::test]
+#[serial]
+async fn test_annotation_size_limits() {
+    common::init_test_logger();
+    
+    let db = common::setup_test_db().await;
+    let db_client = DbClient { db: db.clone() };
+    db_client.get_tokens_collection().insert_one(common::mock_data::create_test_token("bitcoin"), None).await.unwrap();
+    
+    let app = api_app!(db_client, UNREACHABLE.to_string());
+    let put = |body: serde_json::Value| {
+        test::TestRequest::put().uri("/api/tokens/bitcoin/annotation").set_json(body).to_request()
+    };
+    
+    // Exactly at every limit is fine
+    let tags: Vec<String> = (0..MAX_ANNOTATION_TAGS).map(|i| format!("{:0width$}", i, width = MAX_ANNOTATION_TAG_LENGTH)).collect();
+    let req = put(serde_json::json!({ "tags": tags, "note": "n".repeat(MAX_ANNOTATION_NOTE_LENGTH) }));
+    assert_eq!(test::call_service(&app, req).await.status(), 200);
+    
+    let too_many: Vec<String> = (0..=MAX_ANNOTATION_TAGS).map(|i| i.to_string()).collect();
+    for (body, field) in [
+        (serde_json::json!({ "tags": too_many }), "tags"),
+        (serde_json::json!({ "tags": ["x".repeat(MAX_ANNOTATION_TAG_LENGTH + 1)] }), "tags[0]"),
+        (serde_json::json!({ "note": "n".repeat(MAX_ANNOTATION_NOTE_LENGTH + 1) }), "note"),
+    ] {
+        let resp = test::call_service(&app, put(body)).await;
+        assert_eq!(resp.status(), 400);
+        let error: ErrorResponse = test::read_body_json(resp).await;
+        assert_eq!(error.fields.iter().map(|f| f.field.as_str()).collect::<Vec<_>>(), vec![field]);
+    }
+    
+    // The rejected writes left the saved annotation alone
+    let saved = db_client.load_annotation("bitcoin").await.unwrap().unwrap();
+    assert_eq!(saved.tags.len(), MAX_ANNOTATION_TAGS);
+    
+    let req = test::TestRequest::put()
+        .uri("/api/tokens/not-cached/annotation")
+        .set_json(serde_json::json!({ "tags": ["avoid"] }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), 404);
+    
+    common::cleanup_test_db(&db).await;
+}
@@ -0,0 +1,108 @@
+use actix_web::{test as actix_test, App};
+use crypto_tracker_backend::{openapi::ApiDoc, routes};
+use serde_json::Value;
+use utoipa::OpenApi;
+
+fn spec() -> Value {
+    serde_json::to_value(ApiDoc::openapi()).expect("spec should serialize")
+}
+
+#[test]
+fn test_every_registered_route_is_documented() {
+    let spec = spec();
+    let paths = spec["paths"].as_object().expect("spec should have paths");
+
+    let missing: Vec<String> = routes::registered_routes()
+        .into_iter()
+        .filter(|(method, path)| {
+            paths
+                .get(path)
+                .and_then(|item| item.get(*method))
+                .is_none()
+        })
+        .map(|(method, path)| format!("{} {}", method.to_uppercase(), path))
+        .collect();
+
+    assert!(missing.is_empty(), "Routes missing from the OpenAPI spec: {:?}", missing);
+}
+
+#[test]
+fn test_spec_documents_validation_ranges() {
+    let spec = spec();
+
+    let history_params = spec["paths"]["/api/history/{id}/{days}"]["get"]["parameters"]
+        .as_array()
+        .expect("history should document its parameters");
+    let days = history_params
+        .iter()
+        .find(|p| p["name"] == "days")
+        .expect("days parameter should be documented");
+    assert_eq!(days["in"], "path");
+    assert_eq!(days["schema"]["type"], "string");
+    assert!(days["schema"]["pattern"].as_str().unwrap().contains("max"));
+    let id = history_params
+        .iter()
+        .find(|p| p["name"] == "id")
+        .expect("id parameter should be documented");
+    assert_eq!(id["schema"]["pattern"], "^[a-z0-9-]{1,100}$");
+
+    let search_params = spec["paths"]["/api/search"]["get"]["parameters"]
+        .as_array()
+        .expect("search should document its parameters");
+    let min_score = search_params
+        .iter()
+        .find(|p| p["name"] == "min_score")
+        .expect("min_score parameter should be documented");
+    assert_eq!(min_score["schema"]["minimum"], 0.0);
+    assert_eq!(min_score["schema"]["maximum"], 1.0);
+
+    for list in ["/api/tokens", "/api/favorites"] {
+        let per_page = spec["paths"][list]["get"]["parameters"]
+            .as_array()
+            .and_then(|params| params.iter().find(|p| p["name"] == "per_page"))
+            .unwrap_or_else(|| panic!("{} should document per_page", list));
+        assert_eq!(per_page["schema"]["maximum"], 250);
+    }
+}
+
+#[test]
+fn test_spec_lists_versioned_paths_and_deprecates_legacy_ones() {
+    let spec = spec();
+    let paths = &spec["paths"];
+
+    let versioned = &paths["/api/v1/history/{id}/{days}"]["get"];
+    let legacy = &paths["/api/history/{id}/{days}"]["get"];
+    assert_eq!(versioned["parameters"], legacy["parameters"]);
+    assert_eq!(legacy["deprecated"], true);
+    assert!(versioned.get("deprecated").is_none());
+    assert_ne!(versioned["operationId"], legacy["operationId"]);
+
+    assert_eq!(paths["/api/tokens"]["get"]["deprecated"], true);
+    assert!(paths["/api/v1/tokens"]["get"].get("deprecated").is_none());
+    assert!(paths["/api/v2/tokens"]["get"].get("deprecated").is_none());
+}
+
+#[test]
+fn test_error_body_and_models_are_in_components() {
+    let spec = spec();
+    let schemas = spec["components"]["schemas"].as_object().expect("spec should have schemas");
+
+    for name in ["CryptoToken", "TokenStats", "PriceHistory", "FavoriteRequest", "MarketStats", "ErrorResponse"] {
+        assert!(schemas.contains_key(name), "{} missing from components", name);
+    }
+}
+
+#[actix_web::test]
+async fn test_spec_and_swagger_ui_are_served() {
+    let app = actix_test::init_service(App::new().configure(routes::configure)).await;
+
+    let req = actix_test::TestRequest::get().uri("/api/openapi.json").to_request();
+    let resp = actix_test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+    let body: Value = actix_test::read_body_json(resp).await;
+    assert_eq!(body["info"]["title"], "Crypto Tracker API");
+
+    let req = actix_test::TestRequest::get().uri("/api/docs/").to_request();
+    let resp = actix_test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+}
@@ -18,17 +18,29 @@ proptest! {
             volume_24h: price * 100000.0,
             price_change_24h: 0.0,
             price_change_percentage_24h: 0.0,
+            price_change_percentage_7d: None,
+            price_change_percentage_30d: None,
+            price_change_percentage_1y: None,
+            sparkline_7d: None,
             high_24h: Some(price * 1.1),
             low_24h: Some(price * 0.9),
             circulating_supply: None,
             total_supply: None,
+            max_supply: None,
+            fully_diluted_valuation: None,
             ath: None,
             ath_change_percentage: None,
             atl: None,
             atl_change_percentage: None,
             image: None,
             last_updated: Utc::now(),
+            fetched_at: None,
             is_favorite: false,
+            hidden: false,
+            tags: Vec::new(),
+            note: None,
+            price_source: None,
+            category: None,
         };
         
         prop_assert!(token.current_price >= 0.0);
@@ -48,17 +60,29 @@ proptest! {
             volume_24h: 10000.0,
             price_change_24h: change,
             price_change_percentage_24h: change,
+            price_change_percentage_7d: None,
+            price_change_percentage_30d: None,
+            price_change_percentage_1y: None,
+            sparkline_7d: None,
             high_24h: None,
             low_24h: None,
             circulating_supply: None,
             total_supply: None,
+            max_supply: None,
+            fully_diluted_valuation: None,
             ath: None,
             ath_change_percentage: None,
             atl: None,
             atl_change_percentage: None,
             image: None,
             last_updated: Utc::now(),
+            fetched_at: None,
             is_favorite: false,
+            hidden: false,
+            tags: Vec::new(),
+            note: None,
+            price_source: None,
+            category: None,
         };
         
         // Price change percentage can be any real number in reality
@@ -83,17 +107,29 @@ proptest! {
             volume_24h: 10000.0,
             price_change_24h: 0.0,
             price_change_percentage_24h: 0.0,
+            price_change_percentage_7d: None,
+            price_change_percentage_30d: None,
+            price_change_percentage_1y: None,
+            sparkline_7d: None,
             high_24h: Some(high),
             low_24h: Some(low),
             circulating_supply: None,
             total_supply: None,
+            max_supply: None,
+            fully_diluted_valuation: None,
             ath: None,
             ath_change_percentage: None,
             atl: None,
             atl_change_percentage: None,
             image: None,
             last_updated: Utc::now(),
+            fetched_at: None,
             is_favorite: false,
+            hidden: false,
+            tags: Vec::new(),
+            note: None,
+            price_source: None,
+            category: None,
         };
         
         prop_assert!(token.high_24h.unwrap() >= token.low_24h.unwrap());
@@ -116,17 +152,29 @@ proptest! {
             volume_24h: 10000.0,
             price_change_24h: 0.0,
             price_change_percentage_24h: 0.0,
+            price_change_percentage_7d: None,
+            price_change_percentage_30d: None,
+            price_change_percentage_1y: None,
+            sparkline_7d: None,
             high_24h: None,
             low_24h: None,
             circulating_supply: Some(supply),
             total_supply: Some(supply * 1.5),
+            max_supply: None,
+            fully_diluted_valuation: None,
             ath: None,
             ath_change_percentage: None,
             atl: None,
             atl_change_percentage: None,
             image: None,
             last_updated: Utc::now(),
+            fetched_at: None,
             is_favorite: false,
+            hidden: false,
+            tags: Vec::new(),
+            note: None,
+            price_source: None,
+            category: None,
         };
         
         // Market cap should be close to price * circulating_supply
@@ -167,17 +215,29 @@ proptest! {
             volume_24h: 10000.0,
             price_change_24h: 0.0,
             price_change_percentage_24h: 0.0,
+            price_change_percentage_7d: None,
+            price_change_percentage_30d: None,
+            price_change_percentage_1y: None,
+            sparkline_7d: None,
             high_24h: None,
             low_24h: None,
             circulating_supply: None,
             total_supply: None,
+            max_supply: None,
+            fully_diluted_valuation: None,
             ath: None,
             ath_change_percentage: None,
             atl: None,
             atl_change_percentage: None,
             image: None,
             last_updated: Utc::now(),
+            fetched_at: None,
             is_favorite: false,
+            hidden: false,
+            tags: Vec::new(),
+            note: None,
+            price_source: None,
+            category: None,
         };
         
         prop_assert!(!token.token_id.is_empty());
@@ -200,17 +260,29 @@ proptest! {
             volume_24h: 10000.0,
             price_change_24h: 0.0,
             price_change_percentage_24h: 0.0,
+            price_change_percentage_7d: None,
+            price_change_percentage_30d: None,
+            price_change_percentage_1y: None,
+            sparkline_7d: None,
             high_24h: None,
             low_24h: None,
             circulating_supply: None,
             total_supply: None,
+            max_supply: None,
+            fully_diluted_valuation: None,
             ath: None,
             ath_change_percentage: None,
             atl: None,
             atl_change_percentage: None,
             image: None,
             last_updated: Utc::now(),
+            fetched_at: None,
             is_favorite: false,
+            hidden: false,
+            tags: Vec::new(),
+            note: None,
+            price_source: None,
+            category: None,
         };
         
         let json = serde_json::to_string(&token).unwrap();
@@ -0,0 +1,117 @@
+// Tests for the Redis-backed shared state. Needs a running Redis at REDIS_TEST_URL
+// (e.g. redis://127.0.0.1:6379); skipped when that isn't set.
+#![cfg(feature = "redis")]
+
+use chrono::{Duration as ChronoDuration, Utc};
+use crypto_tracker_backend::cache_store::CacheStore;
+use crypto_tracker_backend::rate_limiter::RateLimiter;
+use crypto_tracker_backend::redis_store::RedisStore;
+use crypto_tracker_backend::token_cache::TokenCache;
+use std::sync::Arc;
+use std::time::Duration;
+
+async fn test_store(name: &str) -> Option<RedisStore> {
+    let url = std::env::var("REDIS_TEST_URL").ok()?;
+    let store = RedisStore::connect(&url)
+        .await
+        .expect("Failed to connect to test Redis");
+    let stamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    Some(store.with_prefix(format!("test_crypto_tracker_{}_{}", name, stamp)))
+}
+
+#[tokio::test]
+async fn test_rate_limit_state_shared_between_instances() {
+    let Some(store) = test_store("rate_limit").await else {
+        eprintln!("REDIS_TEST_URL not set, skipping");
+        return;
+    };
+    let store: Arc<dyn CacheStore> = Arc::new(store);
+
+    let first = RateLimiter::new(60.0, 60).with_store(store.clone());
+    let second = RateLimiter::new(60.0, 60).with_store(store.clone());
+
+    assert!(second.can_make_api_call().await);
+    first.record_api_call().await;
+    assert!(!second.can_make_api_call().await);
+
+    first.record_rate_limit().await;
+    let until = second.rate_limited_until().await.expect("backoff should be visible");
+    assert!(until > Utc::now() + ChronoDuration::seconds(50));
+}
+
+#[tokio::test]
+async fn test_only_one_instance_claims_a_call_slot() {
+    let Some(store) = test_store("acquire").await else {
+        eprintln!("REDIS_TEST_URL not set, skipping");
+        return;
+    };
+    let store: Arc<dyn CacheStore> = Arc::new(store);
+
+    let attempts: Vec<_> = (0..8)
+        .map(|_| {
+            let limiter = RateLimiter::new(60.0, 60).with_store(store.clone());
+            tokio::spawn(async move { limiter.try_acquire().await })
+        })
+        .collect();
+
+    let mut granted = 0;
+    for attempt in attempts {
+        if attempt.await.unwrap() {
+            granted += 1;
+        }
+    }
+    assert_eq!(granted, 1);
+}
+
+#[tokio::test]
+async fn test_values_expire_with_their_lifetime() {
+    let Some(store) = test_store("expiry").await else {
+        eprintln!("REDIS_TEST_URL not set, skipping");
+        return;
+    };
+
+    store.set("key", "value".to_string(), Duration::from_millis(50)).await;
+    assert_eq!(store.get("key").await.as_deref(), Some("value"));
+
+    tokio::time::sleep(Duration::from_millis(120)).await;
+    assert!(store.get("key").await.is_none());
+}
+
+#[tokio::test]
+async fn test_incr_and_compare_and_set_match_memory_semantics() {
+    let Some(store) = test_store("primitives").await else {
+        eprintln!("REDIS_TEST_URL not set, skipping");
+        return;
+    };
+    let minute = Duration::from_secs(60);
+
+    assert_eq!(store.incr("hits", 1, None).await, Some(1));
+    assert_eq!(store.incr("hits", 5, Some(minute)).await, Some(6));
+
+    assert!(store.compare_and_set("lock", None, "a".to_string(), minute).await);
+    assert!(!store.compare_and_set("lock", None, "b".to_string(), minute).await);
+    assert!(!store.compare_and_set("lock", Some("b"), "c".to_string(), minute).await);
+    assert!(store.compare_and_set("lock", Some("a"), "c".to_string(), minute).await);
+    assert_eq!(store.get("lock").await.as_deref(), Some("c"));
+}
+
+#[tokio::test]
+async fn test_token_cache_invalidation_reaches_other_instances() {
+    let Some(store) = test_store("tokens").await else {
+        eprintln!("REDIS_TEST_URL not set, skipping");
+        return;
+    };
+    let store: Arc<dyn CacheStore> = Arc::new(store);
+
+    let first = TokenCache::new(Duration::from_secs(60)).with_store(store.clone());
+    let second = TokenCache::new(Duration::from_secs(60)).with_store(store);
+
+    first.set(Vec::new()).await;
+    assert!(second.get().await.is_some());
+
+    second.invalidate().await;
+    assert!(first.get().await.is_none());
+}
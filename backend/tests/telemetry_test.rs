@@ -0,0 +1,156 @@
+// Request ids and the spans they ride on, checked against captured JSON log output
+use actix_web::middleware::from_fn;
+use actix_web::{test, web, App};
+use crypto_tracker_backend::{
+    config::Config,
+    crypto_service::CryptoService,
+    db::DbClient,
+    events::EventRecorder,
+    fallback::FallbackProvider,
+    force_fresh::ForceFreshLimiter,
+    handlers::HistoryFlights,
+    rate_limiter::RateLimiter,
+    routes,
+    shutdown::BackgroundTasks,
+    telemetry::{self, RequestSpan, REQUEST_ID_HEADER},
+    token_cache::TokenCache,
+    upstream::UpstreamGate,
+    webhook::WebhookNotifier,
+};
+use serde_json::Value;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing_actix_web::TracingLogger;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+// Collects everything the subscriber writes
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Captured {
+    fn lines(&self) -> Vec<Value> {
+        let bytes = self.0.lock().unwrap().clone();
+        String::from_utf8(bytes)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("JSON log line"))
+            .collect()
+    }
+}
+
+// Same wrapping order as main.rs: the request id is assigned before the root span is built
+macro_rules! traced_app {
+    ($crypto_service:expr) => {{
+        let client = mongodb::Client::with_uri_str("mongodb://127.0.0.1:9/?serverSelectionTimeoutMS=100")
+            .await
+            .expect("valid connection string");
+        test::init_service(
+            App::new()
+                .app_data(web::Data::new(Config::default_for_tests()))
+                .app_data(web::Data::new(DbClient { db: client.database("crypto_tracker_offline") }))
+                .app_data(web::Data::new($crypto_service))
+                .app_data(web::Data::new(RateLimiter::new(0.0, 60)))
+                .app_data(web::Data::new(BackgroundTasks::new()))
+                .app_data(web::Data::new(TokenCache::new(Duration::from_secs(60))))
+                .app_data(web::Data::new(FallbackProvider::disabled()))
+                .app_data(web::Data::new(WebhookNotifier::disabled()))
+                .app_data(web::Data::new(EventRecorder::disabled()))
+                .app_data(web::Data::new(UpstreamGate::from_config(&Config::default_for_tests())))
+                .app_data(web::Data::new(HistoryFlights::new()))
+                .app_data(web::Data::new(ForceFreshLimiter::from_config(&Config::default_for_tests())))
+                .wrap(TracingLogger::<RequestSpan>::new())
+                .wrap(from_fn(telemetry::request_id))
+                .configure(routes::configure),
+        )
+        .await
+    }};
+}
+
+fn span_names(line: &Value) -> Vec<&str> {
+    line["spans"]
+        .as_array()
+        .map(|spans| spans.iter().filter_map(|s| s["name"].as_str()).collect())
+        .unwrap_or_default()
+}
+
+#[actix_web::test]
+async fn test_request_id_reaches_handler_events_and_upstream_span() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([{
+            "id": "bitcoin",
+            "symbol": "btc",
+            "name": "Bitcoin",
+            "image": "https://example.com/btc.png",
+            "current_price": 50000.0,
+            "market_cap": 1000000000000.0,
+            "total_volume": 20000000000.0,
+            "last_updated": "2024-01-01T00:00:00.000Z"
+        }])))
+        .mount(&mock_server)
+        .await;
+
+    let captured = Captured::default();
+    let writer = captured.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .json()
+        .with_current_span(true)
+        .with_span_list(true)
+        .with_writer(move || writer.clone())
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let app = traced_app!(CryptoService::new(mock_server.uri(), None));
+    let req = test::TestRequest::get()
+        .uri("/api/tokens/bitcoin")
+        .insert_header((REQUEST_ID_HEADER, "req-42"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+
+    assert!(resp.status().is_success());
+    assert_eq!(resp.headers().get(REQUEST_ID_HEADER).unwrap(), "req-42");
+
+    let lines = captured.lines();
+    let in_request = |line: &&Value| line["spans"][0]["request_id"] == "req-42";
+
+    let handler_event = lines
+        .iter()
+        .filter(in_request)
+        .find(|line| line["fields"]["token_id"] == "bitcoin")
+        .expect("handler event carrying the request id");
+    assert_eq!(span_names(handler_event), vec!["request"]);
+
+    let upstream_event = lines
+        .iter()
+        .filter(in_request)
+        .find(|line| span_names(line) == vec!["request", "coingecko"])
+        .expect("event inside the upstream span");
+    assert_eq!(upstream_event["span"]["path"], "/coins/markets");
+    assert_eq!(upstream_event["fields"]["status"], 200);
+    assert!(upstream_event["fields"]["latency_ms"].is_u64());
+}
+
+#[actix_web::test]
+async fn test_request_id_generated_when_missing() {
+    let app = traced_app!(CryptoService::new("http://127.0.0.1:9".to_string(), None));
+
+    let req = test::TestRequest::get().uri("/api/openapi.json").to_request();
+    let resp = test::call_service(&app, req).await;
+
+    let id = resp.headers().get(REQUEST_ID_HEADER).expect("generated request id");
+    assert!(uuid::Uuid::parse_str(id.to_str().unwrap()).is_ok());
+}
@@ -0,0 +1,140 @@
+// Tests for the startup token cache warm-up against a mocked CoinGecko
+mod common;
+
+use crypto_tracker_backend::{
+    config::Config,
+    crypto_service::CryptoService,
+    db::DbClient,
+    events::EventRecorder,
+    rate_limiter::RateLimiter,
+    token_cache::TokenCache,
+    warmup::{CacheWarmUp, WarmUpOutcome},
+    webhook::WebhookNotifier,
+};
+use serial_test::serial;
+use std::time::Duration;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+// Fails fast, like a database that isn't up
+async fn offline_db() -> DbClient {
+    let client = mongodb::Client::with_uri_str("mongodb://127.0.0.1:9/?serverSelectionTimeoutMS=100")
+        .await
+        .expect("valid connection string");
+    DbClient { db: client.database("crypto_tracker_offline") }
+}
+
+fn markets() -> serde_json::Value {
+    serde_json::json!([
+        { "id": "bitcoin", "symbol": "btc", "name": "Bitcoin", "image": "", "current_price": 50000.0, "market_cap": 1.0e12, "total_volume": 5.0e10 },
+        { "id": "ethereum", "symbol": "eth", "name": "Ethereum", "image": "", "current_price": 3000.0, "market_cap": 3.6e11, "total_volume": 2.0e10 },
+    ])
+}
+
+async fn run_warm_up(db: &DbClient, mock_server: &MockServer, rate_limiter: &RateLimiter, limit: Duration) -> WarmUpOutcome {
+    let config = Config::default_for_tests();
+    CacheWarmUp {
+        db,
+        crypto_service: &CryptoService::new(mock_server.uri(), None),
+        rate_limiter,
+        token_cache: &TokenCache::new(Duration::from_secs(60)),
+        notifier: &WebhookNotifier::disabled(),
+        events: &EventRecorder::new(db.clone(), &config),
+        max_age_secs: config.token_cache_ttl_secs,
+        token_limit: config.top_tokens_limit,
+    }
+    .run(limit)
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn test_warm_up_fills_an_empty_collection() {
+    let db = common::setup_test_db().await;
+    let db_client = DbClient { db: db.clone() };
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(markets()))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let rate_limiter = RateLimiter::new(60.0, 60);
+    let outcome = run_warm_up(&db_client, &mock_server, &rate_limiter, Duration::from_secs(10)).await;
+
+    assert_eq!(outcome, WarmUpOutcome::Fetched(2));
+    assert_eq!(db_client.get_tokens_collection().count_documents(None, None).await.unwrap(), 2);
+    // It was the first upstream call, so the next has to wait
+    assert!(!rate_limiter.try_acquire().await);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_warm_up_skips_a_fresh_collection() {
+    let db = common::setup_test_db().await;
+    let db_client = DbClient { db: db.clone() };
+    let mut bitcoin = common::mock_data::create_test_token("bitcoin");
+    bitcoin.fetched_at = Some(chrono::Utc::now());
+    db_client.get_tokens_collection().insert_one(bitcoin, None).await.unwrap();
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(markets()))
+        .expect(0)
+        .mount(&mock_server)
+        .await;
+
+    let rate_limiter = RateLimiter::new(60.0, 60);
+    let outcome = run_warm_up(&db_client, &mock_server, &rate_limiter, Duration::from_secs(10)).await;
+
+    assert_eq!(outcome, WarmUpOutcome::AlreadyFresh);
+    assert!(rate_limiter.try_acquire().await);
+
+    common::cleanup_test_db(&db).await;
+}
+
+#[tokio::test]
+async fn test_warm_up_gives_up_on_upstream_errors() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&mock_server)
+        .await;
+
+    let outcome = run_warm_up(&offline_db().await, &mock_server, &RateLimiter::new(0.0, 60), Duration::from_secs(10)).await;
+    assert_eq!(outcome, WarmUpOutcome::Failed);
+}
+
+#[tokio::test]
+async fn test_warm_up_is_capped_by_its_timeout() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/coins/markets"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(markets()).set_delay(Duration::from_secs(5)))
+        .mount(&mock_server)
+        .await;
+
+    let started = std::time::Instant::now();
+    let outcome = run_warm_up(&offline_db().await, &mock_server, &RateLimiter::new(0.0, 60), Duration::from_secs(1)).await;
+    assert_eq!(outcome, WarmUpOutcome::TimedOut);
+    assert!(started.elapsed() < Duration::from_secs(3));
+}
+
+#[tokio::test]
+async fn test_warm_up_respects_the_rate_limiter() {
+    let mock_server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(markets()))
+        .expect(0)
+        .mount(&mock_server)
+        .await;
+
+    let rate_limiter = RateLimiter::new(0.0, 60);
+    rate_limiter.record_rate_limit().await;
+    let outcome = run_warm_up(&offline_db().await, &mock_server, &rate_limiter, Duration::from_secs(10)).await;
+    assert_eq!(outcome, WarmUpOutcome::RateLimited);
+}
@@ -0,0 +1,107 @@
+// Webhook delivery against a mock receiver. The database is unreachable, so recording
+// attempts fails (and is logged); the attempts are checked from deliver()'s return value.
+mod common;
+
+use chrono::Utc;
+use crypto_tracker_backend::db::DbClient;
+use crypto_tracker_backend::models::{DeliveryOutcome, WebhookConfig, WebhookEvent};
+use crypto_tracker_backend::webhook::{signature, WebhookDispatcher, MAX_DELIVERY_ATTEMPTS, SIGNATURE_HEADER};
+use std::time::Duration;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+async fn dispatcher() -> WebhookDispatcher {
+    let client = mongodb::Client::with_uri_str("mongodb://127.0.0.1:9/?serverSelectionTimeoutMS=100")
+        .await
+        .expect("valid connection string");
+    WebhookDispatcher::new(DbClient { db: client.database("crypto_tracker_offline") })
+        .with_backoff(Duration::from_millis(10))
+}
+
+fn event() -> WebhookEvent {
+    WebhookEvent {
+        event: "new_ath".to_string(),
+        token_id: "bitcoin".to_string(),
+        data: serde_json::json!({ "price": 70000.0, "previous_ath": 69000.0 }),
+        at: Utc::now(),
+    }
+}
+
+fn config(server: &MockServer, secret: Option<&str>) -> WebhookConfig {
+    WebhookConfig {
+        url: format!("{}/hook", server.uri()),
+        secret: secret.map(String::from),
+        enabled: true,
+    }
+}
+
+#[tokio::test]
+async fn test_delivery_is_signed_with_the_secret() {
+    common::init_test_logger();
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/hook"))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let attempts = dispatcher().await.deliver(&config(&server, Some("hush")), &event()).await;
+
+    assert_eq!(attempts.len(), 1);
+    assert_eq!(attempts[0].outcome, DeliveryOutcome::Delivered);
+    assert_eq!(attempts[0].status, Some(204));
+
+    let received = &server.received_requests().await.unwrap()[0];
+    let sent = received.headers.get(SIGNATURE_HEADER).expect("signature header");
+    assert_eq!(sent.to_str().unwrap(), signature("hush", &received.body));
+    let body: serde_json::Value = serde_json::from_slice(&received.body).unwrap();
+    assert_eq!(body["event"], "new_ath");
+    assert_eq!(body["token_id"], "bitcoin");
+}
+
+#[tokio::test]
+async fn test_server_error_is_retried_until_delivered() {
+    common::init_test_logger();
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let attempts = dispatcher().await.deliver(&config(&server, None), &event()).await;
+
+    let outcomes: Vec<_> = attempts.iter().map(|a| (a.attempt, a.status, a.outcome)).collect();
+    assert_eq!(
+        outcomes,
+        vec![(1, Some(500), DeliveryOutcome::Retrying), (2, Some(200), DeliveryOutcome::Delivered)]
+    );
+    let received = server.received_requests().await.unwrap();
+    assert!(received.iter().all(|r| !r.headers.contains_key(SIGNATURE_HEADER)));
+}
+
+#[tokio::test]
+async fn test_permanent_failure_stops_after_max_attempts() {
+    common::init_test_logger();
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(u64::from(MAX_DELIVERY_ATTEMPTS))
+        .mount(&server)
+        .await;
+
+    let attempts = dispatcher().await.deliver(&config(&server, None), &event()).await;
+
+    assert_eq!(attempts.len(), MAX_DELIVERY_ATTEMPTS as usize);
+    assert!(attempts[..2].iter().all(|a| a.outcome == DeliveryOutcome::Retrying));
+    let last = attempts.last().unwrap();
+    assert_eq!(last.outcome, DeliveryOutcome::Failed);
+    assert_eq!(last.error.as_deref(), Some("HTTP 503 Service Unavailable"));
+}